@@ -0,0 +1,90 @@
+//! Measures the throughput of yield / resume round trips, both between Lua coroutines and between
+//! a Lua coroutine and the host.
+//!
+//! Run with `cargo bench --bench coroutine`.
+
+use std::time::{Duration, Instant};
+
+use allocator_api2::vec;
+use gc_arena::allocator_api::MetricsAlloc;
+use piccolo::{Closure, Executor, Fuel, Lua, Stack, StaticError, Value};
+
+const ROUND_TRIPS: i64 = 1_000_000;
+
+fn lua_ping_pong() -> Result<Duration, StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("ping_pong"),
+            &br#"
+                local n = ...
+                local pong = coroutine.wrap(function(v)
+                    while true do
+                        v = coroutine.yield(v + 1)
+                    end
+                end)
+                local v = 0
+                for _ = 1, n do
+                    v = pong(v)
+                end
+                assert(v == n)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ROUND_TRIPS)))
+    })?;
+
+    let start = Instant::now();
+    lua.execute::<()>(&executor)?;
+    Ok(start.elapsed())
+}
+
+fn host_ping_pong() -> Result<Duration, StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("ping_pong"),
+            &br#"
+                local v = 0
+                while true do
+                    v = coroutine.yield(v + 1)
+                end
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let start = Instant::now();
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        let mut stack = Stack::new(&mut values, 0);
+        let mut fuel = Fuel::with(i32::MAX);
+
+        executor.step(ctx, &mut fuel);
+        for _ in 0..ROUND_TRIPS {
+            executor.take_result_into(ctx, &mut stack).unwrap().unwrap();
+            executor.resume_from(ctx, &mut stack).unwrap();
+            executor.step(ctx, &mut fuel);
+        }
+        executor.take_result_into(ctx, &mut stack).unwrap().unwrap();
+        assert_eq!(stack.get(0), Value::Integer(ROUND_TRIPS + 1));
+    });
+    Ok(start.elapsed())
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name}: {ROUND_TRIPS} round trips in {elapsed:?} ({:.0} ns per round trip)",
+        elapsed.as_nanos() as f64 / ROUND_TRIPS as f64
+    );
+}
+
+fn main() -> Result<(), StaticError> {
+    report("lua ping pong", lua_ping_pong()?);
+    report("host ping pong", host_ping_pong()?);
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! Measures the overhead saved by calling intrinsic callbacks directly from the VM, by comparing
+//! calls to `math.floor` and `string.len` against calls to the same functions through a normal
+//! callback frame.
+//!
+//! Run with `cargo bench --bench intrinsic`.
+
+use std::time::{Duration, Instant};
+
+use piccolo::{Callback, CallbackReturn, Closure, Executor, Function, Lua, StaticError, Value};
+
+const CALLS: i64 = 10_000_000;
+
+fn time_calls(
+    lib: &'static str,
+    name: &'static str,
+    intrinsic: bool,
+) -> Result<Duration, StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let Value::Table(table) = ctx.get_global(lib) else {
+            panic!("{lib} is not loaded");
+        };
+        let Value::Function(Function::Callback(callback)) = table.get(ctx, name) else {
+            panic!("{name} is not a callback");
+        };
+        assert!(callback.intrinsic().is_some());
+
+        let function: Function = if intrinsic {
+            callback.into()
+        } else {
+            // Calls the same intrinsic from inside a normal callback, so that the VM has to push a
+            // callback frame and return through the executor.
+            Callback::from_fn_with(&ctx, callback, |callback, ctx, mut exec, stack| {
+                (callback.intrinsic().unwrap())(ctx, exec.fuel(), stack)?;
+                Ok(CallbackReturn::Return)
+            })
+            .into()
+        };
+
+        let arg: Value = if lib == "math" {
+            Value::Number(2.5)
+        } else {
+            ctx.intern_static(b"hello").into()
+        };
+
+        let closure = Closure::load(
+            ctx,
+            Some("calls"),
+            &br#"
+                local f, arg, n = ...
+                for _ = 1, n do
+                    f(arg)
+                end
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), (function, arg, CALLS))))
+    })?;
+
+    let start = Instant::now();
+    lua.execute::<()>(&executor)?;
+    Ok(start.elapsed())
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name}: {CALLS} calls in {elapsed:?} ({:.1} ns per call)",
+        elapsed.as_nanos() as f64 / CALLS as f64
+    );
+}
+
+fn main() -> Result<(), StaticError> {
+    for (lib, name) in [("math", "floor"), ("string", "len")] {
+        report(
+            &format!("{lib}.{name} intrinsic"),
+            time_calls(lib, name, true)?,
+        );
+        report(
+            &format!("{lib}.{name} callback frame"),
+            time_calls(lib, name, false)?,
+        );
+    }
+    Ok(())
+}
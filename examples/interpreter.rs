@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::error::Error as StdError;
 use std::fs::File;
 
@@ -101,6 +102,22 @@ fn main() -> Result<(), Box<dyn StdError>> {
 
     let mut lua = Lua::full();
 
+    // Matches the warning behavior of the standalone `lua` interpreter, except that warnings start
+    // out enabled.
+    lua.enter(|ctx| {
+        let enabled = Cell::new(true);
+        ctx.set_warning_handler(move |message| match message {
+            "@on" => enabled.set(true),
+            "@off" => enabled.set(false),
+            _ if message.starts_with('@') => {}
+            _ => {
+                if enabled.get() {
+                    eprintln!("Lua warning: {message}");
+                }
+            }
+        });
+    });
+
     if !matches.contains_id("file") {
         run_repl(&mut lua)?;
         return Ok(());
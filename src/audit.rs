@@ -0,0 +1,107 @@
+//! Static analysis of how a compiled chunk uses its environment.
+//!
+//! Every global variable access in Lua is an index into the `_ENV` upvalue with a constant string
+//! key, so the set of global names a chunk can touch is known once it is compiled, as long as the
+//! chunk never uses `_ENV` as an ordinary value. A sandboxing host can use an
+//! [`EnvironmentAudit`] to reject scripts that reference disallowed names before running them.
+
+use std::{collections::BTreeSet, string::String as StdString};
+
+use crate::{
+    opcode::{Operation, RCIndex},
+    types::{ConstantIndex8, UpValueDescriptor, UpValueIndex},
+    Constant, FunctionPrototype,
+};
+
+/// The global names and upvalues used by a [`FunctionPrototype`] and every prototype nested
+/// within it, returned by [`FunctionPrototype::audit`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvironmentAudit {
+    /// Global names which are read, such as `print` in `print("hello")`.
+    pub global_reads: BTreeSet<StdString>,
+    /// Global names which are assigned to, such as `x` in `x = 1`.
+    pub global_writes: BTreeSet<StdString>,
+    /// True if `_ENV` is indexed with a key that is not a constant, is used as a value, or is
+    /// itself assigned to.
+    ///
+    /// If this is set, the chunk may access globals which are not listed in `global_reads` or
+    /// `global_writes`.
+    pub dynamic_access: bool,
+    /// The upvalues captured by the audited prototype itself.
+    ///
+    /// For a chunk loaded from source this is only ever `_ENV`.
+    pub upvalues: Vec<UpValueDescriptor>,
+}
+
+impl EnvironmentAudit {
+    /// Returns every global name which is either read or written.
+    pub fn globals(&self) -> BTreeSet<&str> {
+        self.global_reads
+            .iter()
+            .chain(&self.global_writes)
+            .map(|s| s.as_str())
+            .collect()
+    }
+}
+
+pub(crate) fn audit_prototype(proto: &FunctionPrototype<'_>) -> EnvironmentAudit {
+    let mut audit = EnvironmentAudit {
+        upvalues: proto.upvalues.to_vec(),
+        ..Default::default()
+    };
+    let env = proto
+        .upvalues
+        .iter()
+        .map(|&desc| desc == UpValueDescriptor::Environment)
+        .collect::<Vec<_>>();
+    audit_nested(proto, &env, &mut audit);
+    audit
+}
+
+// `env[i]` is true if upvalue `i` of `proto` is the `_ENV` of the chunk.
+fn audit_nested(proto: &FunctionPrototype<'_>, env: &[bool], audit: &mut EnvironmentAudit) {
+    let is_env = |up: UpValueIndex| env.get(up.0 as usize).copied().unwrap_or(false);
+    let name = |key: ConstantIndex8| match proto.constants.get(key.0 as usize) {
+        Some(Constant::String(s)) => Some(s.to_str_lossy().into_owned()),
+        _ => None,
+    };
+
+    for opcode in proto.opcodes.iter() {
+        match opcode.decode() {
+            Operation::GetUpTable { table, key, .. } if is_env(table) => match key {
+                RCIndex::Constant(key) => {
+                    if let Some(name) = name(key) {
+                        audit.global_reads.insert(name);
+                    }
+                }
+                RCIndex::Register(_) => audit.dynamic_access = true,
+            },
+            Operation::SetUpTable { table, key, .. } if is_env(table) => match key {
+                RCIndex::Constant(key) => {
+                    if let Some(name) = name(key) {
+                        audit.global_writes.insert(name);
+                    }
+                }
+                RCIndex::Register(_) => audit.dynamic_access = true,
+            },
+            Operation::GetUpValue { source, .. } if is_env(source) => audit.dynamic_access = true,
+            Operation::SetUpValue { dest, .. } if is_env(dest) => audit.dynamic_access = true,
+            _ => {}
+        }
+    }
+
+    for nested in proto.prototypes.iter() {
+        // A nested function can only see the chunk's `_ENV` through its parent's upvalues. Copying
+        // `_ENV` into a local is already counted as dynamic access.
+        let nested_env = nested
+            .upvalues
+            .iter()
+            .map(|&desc| match desc {
+                UpValueDescriptor::Environment => true,
+                UpValueDescriptor::ParentLocal(_) => false,
+                UpValueDescriptor::Outer(up) => is_env(up),
+            })
+            .collect::<Vec<_>>();
+        audit_nested(nested, &nested_env, audit);
+    }
+}
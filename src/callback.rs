@@ -7,7 +7,7 @@ use std::{
 use allocator_api2::boxed;
 use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
 
-use crate::{Context, Error, Execution, Function, Stack, Thread};
+use crate::{Context, Error, Execution, Fuel, Function, Stack, Thread};
 
 /// Describes the next action for an [`Executor`](crate::Executor) to take after a callback has
 /// returned.
@@ -76,6 +76,13 @@ pub trait CallbackFn<'gc>: Collect {
     ) -> Result<CallbackReturn<'gc>, Error<'gc>>;
 }
 
+/// A plain Rust function that can be called directly by the VM.
+///
+/// Intrinsic functions operate directly on a window of the calling thread's stack containing their
+/// arguments, and must leave their return values in the same stack window. Since they are called
+/// without pushing a callback frame, they cannot call other functions, yield, or resume threads.
+pub type IntrinsicFn<'gc> = fn(Context<'gc>, &mut Fuel, Stack<'gc, '_>) -> Result<(), Error<'gc>>;
+
 /// A garbage collected instance of an object that impelments [`CallbackFn`].
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -89,10 +96,52 @@ pub struct CallbackInner<'gc> {
         Execution<'gc, '_>,
         Stack<'gc, '_>,
     ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    intrinsic: Option<IntrinsicFn<'gc>>,
 }
 
 impl<'gc> Callback<'gc> {
     pub fn new<C: CallbackFn<'gc> + 'gc>(mc: &Mutation<'gc>, callback: C) -> Self {
+        Self::new_inner(mc, callback, None)
+    }
+
+    /// Create a callback from an [`IntrinsicFn`].
+    ///
+    /// When called from Lua, the VM will call the intrinsic function directly without creating a
+    /// callback frame, avoiding a round trip through the `Executor`. When called any other way, it
+    /// acts as a normal callback which returns the values left in the stack.
+    pub fn new_intrinsic(mc: &Mutation<'gc>, intrinsic: IntrinsicFn<'gc>) -> Self {
+        struct IntrinsicCallback<'gc>(IntrinsicFn<'gc>);
+
+        // SAFETY: Function pointers can't hold any data.
+        unsafe impl<'gc> Collect for IntrinsicCallback<'gc> {
+            fn needs_trace() -> bool
+            where
+                Self: Sized,
+            {
+                false
+            }
+        }
+
+        impl<'gc> CallbackFn<'gc> for IntrinsicCallback<'gc> {
+            fn call(
+                &self,
+                ctx: Context<'gc>,
+                mut exec: Execution<'gc, '_>,
+                stack: Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>> {
+                (self.0)(ctx, exec.fuel(), stack)?;
+                Ok(CallbackReturn::Return)
+            }
+        }
+
+        Self::new_inner(mc, IntrinsicCallback(intrinsic), Some(intrinsic))
+    }
+
+    fn new_inner<C: CallbackFn<'gc> + 'gc>(
+        mc: &Mutation<'gc>,
+        callback: C,
+        intrinsic: Option<IntrinsicFn<'gc>>,
+    ) -> Self {
         #[repr(C)]
         struct HeaderCallback<'gc, C> {
             header: CallbackInner<'gc>,
@@ -122,6 +171,7 @@ impl<'gc> Callback<'gc> {
                         let hc = ptr as *const HeaderCallback<C>;
                         ((*hc).callback).call(ctx, exec, stack)
                     },
+                    intrinsic,
                 },
                 callback,
             },
@@ -201,6 +251,12 @@ impl<'gc> Callback<'gc> {
     ) -> Result<CallbackReturn<'gc>, Error<'gc>> {
         unsafe { (self.0.call)(Gc::as_ptr(self.0), ctx, exec, stack) }
     }
+
+    /// If this callback was created with [`Callback::new_intrinsic`], returns the intrinsic
+    /// function.
+    pub fn intrinsic(self) -> Option<IntrinsicFn<'gc>> {
+        self.0.intrinsic
+    }
 }
 
 impl<'gc> fmt::Debug for Callback<'gc> {
@@ -302,6 +358,13 @@ pub trait Sequence<'gc>: Collect {
     ) -> Result<SequencePoll<'gc>, Error<'gc>> {
         Err(error)
     }
+
+    /// Called if the thread running this `Sequence` is reset before the `Sequence` finishes, for
+    /// example because the `Executor` running it was stopped. The `Sequence` will not be polled
+    /// again.
+    ///
+    /// By default, this method does nothing.
+    fn cancel(&mut self, _mc: &Mutation<'gc>) {}
 }
 
 /// A boxed value that implements [`Sequence`].
@@ -1,6 +1,7 @@
 use std::{
+    cell::{Cell, OnceCell},
     hash::{Hash, Hasher},
-    io::Read,
+    io::{self, Read, Write},
 };
 
 use allocator_api2::{boxed, vec, SliceExt};
@@ -8,11 +9,19 @@ use gc_arena::{allocator_api::MetricsAlloc, lock::Lock, Collect, Gc, Mutation};
 use thiserror::Error;
 
 use crate::{
-    compiler::{self, CompiledPrototype, FunctionRef, LineNumber},
+    audit::{audit_prototype, EnvironmentAudit},
+    compiler::{
+        self, CompiledPrototype, CompilerOptions, FunctionRef, LineNumber, Lint, LocalVariable,
+    },
+    dump::{self, UndumpError},
+    luac::{self, LuacError},
     opcode::OpCode,
+    sanitizer::{self, ArenaTag},
     thread::OpenUpValue,
-    types::UpValueDescriptor,
-    Constant, Context, String, Table, Value,
+    types::{RegisterIndex, UpValueDescriptor, UpValueIndex},
+    usage::FunctionId,
+    verify::{verify_prototype, VerifyError},
+    Constant, Context, SourceMap, String, Table, Value,
 };
 
 #[derive(Debug, Error)]
@@ -21,6 +30,97 @@ pub enum PrototypeError {
     Parser(#[from] compiler::ParseError),
     #[error(transparent)]
     Compiler(#[from] compiler::CompileError),
+    #[error(transparent)]
+    Verify(#[from] VerifyError),
+    #[error(transparent)]
+    Undump(#[from] UndumpError),
+    #[error(transparent)]
+    Luac(#[from] LuacError),
+}
+
+/// Whether the VM bounds checks the registers used by the opcodes of loaded chunks, set with
+/// `Context::set_opcode_checks`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum OpCodeChecks {
+    /// Every register access is bounds checked.
+    #[default]
+    Checked,
+    /// Chunks are checked once by the verifier when they are loaded, and are then run without
+    /// bounds checking register access. Loading fails with `PrototypeError::Verify` if a chunk
+    /// does not pass verification.
+    Trusted,
+}
+
+/// Singleton holding the `OpCodeChecks` used when loading chunks.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct OpCodeChecksSetting(pub(crate) Cell<OpCodeChecks>);
+
+/// Singleton holding the heat at which prototypes tier up, see `Context::set_tier_up_threshold`.
+#[cfg(feature = "tier-up")]
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct TierUpThreshold(pub(crate) Cell<u64>);
+
+#[cfg(feature = "tier-up")]
+impl Default for TierUpThreshold {
+    fn default() -> Self {
+        Self(Cell::new(1000))
+    }
+}
+
+/// Singleton holding the `CompilerOptions` used when compiling chunks.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct CompilerOptionsSetting(pub(crate) Cell<CompilerOptions>);
+
+/// Execution counters kept for every [`FunctionPrototype`].
+///
+/// These are updated by the VM as it runs and are cheap enough to always be enabled. They are
+/// shared by every closure created from the same prototype.
+#[derive(Debug, Default)]
+pub struct ProtoCounters {
+    invocations: Cell<u64>,
+    back_edges: Cell<u64>,
+}
+
+impl ProtoCounters {
+    /// The number of times a closure of this prototype has been called, including tail calls.
+    pub fn invocations(&self) -> u64 {
+        self.invocations.get()
+    }
+
+    /// The number of backwards jumps taken within this prototype, which is roughly the number of
+    /// loop iterations it has run.
+    pub fn back_edges(&self) -> u64 {
+        self.back_edges.get()
+    }
+
+    /// A single measure of how hot this prototype is, the sum of its invocations and back edges.
+    pub fn heat(&self) -> u64 {
+        self.invocations().saturating_add(self.back_edges())
+    }
+
+    pub fn reset(&self) {
+        self.invocations.set(0);
+        self.back_edges.set(0);
+    }
+
+    // Adds counters recorded in an earlier run, see `Context::set_profile`.
+    pub(crate) fn seed(&self, invocations: u64, back_edges: u64) {
+        self.invocations
+            .set(self.invocations.get().saturating_add(invocations));
+        self.back_edges
+            .set(self.back_edges.get().saturating_add(back_edges));
+    }
+
+    pub(crate) fn record_invocation(&self) {
+        self.invocations.set(self.invocations.get().wrapping_add(1));
+    }
+
+    pub(crate) fn record_back_edge(&self) {
+        self.back_edges.set(self.back_edges.get().wrapping_add(1));
+    }
 }
 
 #[derive(Debug, Collect)]
@@ -35,7 +135,23 @@ pub struct FunctionPrototype<'gc> {
     pub opcodes: boxed::Box<[OpCode], MetricsAlloc<'gc>>,
     pub opcode_line_numbers: boxed::Box<[(usize, LineNumber)], MetricsAlloc<'gc>>,
     pub upvalues: boxed::Box<[UpValueDescriptor], MetricsAlloc<'gc>>,
+    /// Debug information for every local variable declared in this prototype, empty if the
+    /// prototype was loaded from a stripped binary chunk.
+    pub local_variables: boxed::Box<[LocalVariable<String<'gc>>], MetricsAlloc<'gc>>,
+    /// The name of each upvalue, empty if the prototype was loaded from a stripped binary chunk.
+    pub upvalue_names: boxed::Box<[String<'gc>], MetricsAlloc<'gc>>,
     pub prototypes: boxed::Box<[Gc<'gc, FunctionPrototype<'gc>>], MetricsAlloc<'gc>>,
+    /// Maps the lines of the chunk this prototype was compiled from back to original source files,
+    /// shared by every prototype in the chunk.
+    pub source_map: Option<Gc<'gc, SourceMap<'gc>>>,
+    #[collect(require_static)]
+    pub counters: ProtoCounters,
+    #[collect(require_static)]
+    id: OnceCell<FunctionId>,
+    // Only ever set by `FunctionPrototype::trust`, once the prototype is behind a `Gc` and can no
+    // longer be changed.
+    #[collect(require_static)]
+    trusted: Cell<bool>,
 }
 
 impl<'gc> FunctionPrototype<'gc> {
@@ -52,12 +168,29 @@ impl<'gc> FunctionPrototype<'gc> {
         chunk_name: String<'gc>,
         compiled_function: &CompiledPrototype<S>,
         map_string: impl Fn(&S) -> String<'gc>,
+    ) -> Self {
+        Self::from_compiled_map_strings_with_source_map(
+            mc,
+            chunk_name,
+            compiled_function,
+            map_string,
+            None,
+        )
+    }
+
+    pub fn from_compiled_map_strings_with_source_map<S>(
+        mc: &Mutation<'gc>,
+        chunk_name: String<'gc>,
+        compiled_function: &CompiledPrototype<S>,
+        map_string: impl Fn(&S) -> String<'gc>,
+        source_map: Option<Gc<'gc, SourceMap<'gc>>>,
     ) -> Self {
         fn new<'gc, S>(
             mc: &Mutation<'gc>,
             chunk_name: String<'gc>,
             compiled_function: &CompiledPrototype<S>,
             map_string: impl Fn(&S) -> String<'gc> + Copy,
+            source_map: Option<Gc<'gc, SourceMap<'gc>>>,
         ) -> FunctionPrototype<'gc> {
             let alloc = MetricsAlloc::new(mc);
 
@@ -77,12 +210,25 @@ impl<'gc> FunctionPrototype<'gc> {
             let upvalues =
                 SliceExt::to_vec_in(compiled_function.upvalues.as_slice(), alloc.clone());
 
+            let mut local_variables = vec::Vec::new_in(alloc.clone());
+            local_variables.extend(compiled_function.local_variables.iter().map(|l| {
+                LocalVariable {
+                    name: map_string(&l.name),
+                    register: l.register,
+                    start_pc: l.start_pc,
+                    end_pc: l.end_pc,
+                }
+            }));
+
+            let mut upvalue_names = vec::Vec::new_in(alloc.clone());
+            upvalue_names.extend(compiled_function.upvalue_names.iter().map(map_string));
+
             let mut prototypes = vec::Vec::new_in(alloc);
             prototypes.extend(
                 compiled_function
                     .prototypes
                     .iter()
-                    .map(|cf| Gc::new(mc, new(mc, chunk_name, cf, map_string))),
+                    .map(|cf| Gc::new(mc, new(mc, chunk_name, cf, map_string, source_map))),
             );
 
             FunctionPrototype {
@@ -98,18 +244,60 @@ impl<'gc> FunctionPrototype<'gc> {
                 opcodes: opcodes.into_boxed_slice(),
                 opcode_line_numbers: opcode_line_numbers.into_boxed_slice(),
                 upvalues: upvalues.into_boxed_slice(),
+                local_variables: local_variables.into_boxed_slice(),
+                upvalue_names: upvalue_names.into_boxed_slice(),
                 prototypes: prototypes.into_boxed_slice(),
+                source_map,
+                counters: ProtoCounters::default(),
+                id: OnceCell::new(),
+                trusted: Cell::new(false),
             }
         }
 
-        new(mc, chunk_name, compiled_function, &map_string)
+        new(mc, chunk_name, compiled_function, &map_string, source_map)
     }
 
+    /// Compile a chunk from source.
+    ///
+    /// The source is lexed as it is read, a few kilobytes at a time, so it never needs to be read
+    /// into memory in full beforehand. It does not need to be buffered either.
     pub fn compile(
         ctx: Context<'gc>,
         source_name: &str,
         source: impl Read,
     ) -> Result<FunctionPrototype<'gc>, PrototypeError> {
+        Self::compile_with_source_map(ctx, source_name, source, None)
+    }
+
+    /// Compile a chunk which has been preprocessed or assembled from several original source
+    /// files, attaching a source map which relates its lines back to those files.
+    pub fn compile_with_source_map(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+        source_map: Option<Gc<'gc, SourceMap<'gc>>>,
+    ) -> Result<FunctionPrototype<'gc>, PrototypeError> {
+        Ok(Self::compile_inner(ctx, source_name, source, source_map, false)?.0)
+    }
+
+    /// Compile a chunk, also returning warnings about likely mistakes in it such as unused locals.
+    ///
+    /// See [`compiler::compile_chunk_with_lints`] for the lints which are checked.
+    pub fn compile_with_lints(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+    ) -> Result<(FunctionPrototype<'gc>, Vec<Lint<String<'gc>>>), PrototypeError> {
+        Self::compile_inner(ctx, source_name, source, None, true)
+    }
+
+    fn compile_inner(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+        source_map: Option<Gc<'gc, SourceMap<'gc>>>,
+        lint: bool,
+    ) -> Result<(FunctionPrototype<'gc>, Vec<Lint<String<'gc>>>), PrototypeError> {
         #[derive(Copy, Clone)]
         struct Interner<'gc>(Context<'gc>);
 
@@ -122,18 +310,167 @@ impl<'gc> FunctionPrototype<'gc> {
         }
 
         let interner = Interner(ctx);
-
-        let chunk = compiler::parse_chunk(source, interner)?;
-        let compiled_function = compiler::compile_chunk(&chunk, interner)?;
-
-        Ok(FunctionPrototype::from_compiled(
+        let ext_opcodes = ctx.ext_opcodes().names();
+
+        let options = ctx.compiler_options();
+        let chunk = compiler::parse_chunk_with_options(source, interner, options)?;
+        let (compiled_function, lints) = if lint {
+            compiler::compile_chunk_with_lints(&chunk, interner, &ext_opcodes)?
+        } else {
+            let compiled_function =
+                compiler::compile_chunk_with_ext_opcodes(&chunk, interner, &ext_opcodes)?;
+            (compiled_function, Vec::new())
+        };
+
+        let proto = FunctionPrototype::from_compiled_map_strings_with_source_map(
             &ctx,
             ctx.intern(source_name.as_bytes()),
             &compiled_function,
-        ))
+            |s| *s,
+            source_map,
+        );
+        Ok((proto, lints))
+    }
+
+    /// Returns the source file and line which the given line of the chunk was compiled from.
+    ///
+    /// If the chunk was loaded with a source map which covers the line, the original file and line
+    /// are returned, otherwise this is the chunk name and the line unchanged.
+    pub fn source_location(&self, line: LineNumber) -> (String<'gc>, LineNumber) {
+        self.source_map
+            .and_then(|map| map.map(line))
+            .unwrap_or((self.chunk_name, line))
+    }
+
+    /// Serialize this prototype as a binary chunk which can be loaded with `Closure::load_binary`,
+    /// see the [`dump`](crate::dump) module.
+    pub fn dump(&self, strip: bool) -> Vec<u8> {
+        dump::dump(self, strip)
+    }
+
+    /// A version of [`FunctionPrototype::dump`] which writes the binary chunk to `writer`.
+    pub fn dump_to(&self, mut writer: impl Write, strip: bool) -> io::Result<()> {
+        writer.write_all(&dump::dump(self, strip))
+    }
+
+    /// Run the verifier over this prototype and every prototype nested within it, marking them as
+    /// trusted if they all pass.
+    ///
+    /// The VM does not bounds check register access while running a trusted prototype. This can
+    /// only be done once a prototype is behind a `Gc`, since it can no longer be changed after it
+    /// has been verified.
+    pub fn trust(this: Gc<'gc, Self>) -> Result<(), VerifyError> {
+        let mut protos = Vec::new();
+        let mut to_visit = vec![this];
+        while let Some(proto) = to_visit.pop() {
+            if !proto.is_trusted() {
+                verify_prototype(&proto)?;
+                protos.push(proto);
+            }
+            to_visit.extend(proto.prototypes.iter().copied());
+        }
+        for proto in protos {
+            proto.trusted.set(true);
+        }
+        Ok(())
+    }
+
+    // Trusts this prototype alone once its heat reaches `threshold`, if it passes the verifier.
+    // Nested prototypes tier up on their own once they are hot.
+    #[cfg(feature = "tier-up")]
+    pub(crate) fn tier_up(this: Gc<'gc, Self>, threshold: u64) {
+        if !this.is_trusted()
+            && this.counters.heat() >= threshold
+            && verify_prototype(&this).is_ok()
+        {
+            this.trusted.set(true);
+        }
+    }
+
+    /// Returns true if this prototype has passed the verifier, see [`FunctionPrototype::trust`].
+    pub fn is_trusted(&self) -> bool {
+        self.trusted.get()
+    }
+
+    /// Returns the stable identifier of this prototype, computed the first time it is requested.
+    pub fn id(&self) -> &FunctionId {
+        self.id.get_or_init(|| FunctionId::of(self))
+    }
+
+    /// Returns the name of the local variable held in `register` while running the opcode at `pc`,
+    /// if there is one.
+    pub fn local_name(&self, register: RegisterIndex, pc: usize) -> Option<String<'gc>> {
+        self.local_variables
+            .iter()
+            .find(|l| l.register == register && l.start_pc <= pc && pc < l.end_pc)
+            .map(|l| l.name)
+    }
+
+    /// Returns the name of the given upvalue, if it is known.
+    pub fn upvalue_name(&self, upvalue: UpValueIndex) -> Option<String<'gc>> {
+        self.upvalue_names.get(upvalue.0 as usize).copied()
+    }
+
+    /// Returns the global names used by this prototype and every prototype nested within it, along
+    /// with the upvalues it captures, without running it. See the [`crate::audit`] module.
+    pub fn audit(&self) -> EnvironmentAudit {
+        audit_prototype(self)
+    }
+
+    /// Returns this prototype and every prototype nested within it, ordered from the hottest to
+    /// the coldest according to [`ProtoCounters::heat`].
+    ///
+    /// At most `limit` prototypes are returned, and prototypes which have never run are skipped.
+    pub fn hottest(this: Gc<'gc, Self>, limit: usize) -> Vec<Gc<'gc, FunctionPrototype<'gc>>> {
+        let mut found = Vec::new();
+        let mut to_visit = vec![this];
+        while let Some(proto) = to_visit.pop() {
+            if proto.counters.heat() > 0 {
+                found.push(proto);
+            }
+            to_visit.extend(proto.prototypes.iter().copied());
+        }
+        found.sort_by_key(|p| std::cmp::Reverse(p.counters.heat()));
+        found.truncate(limit);
+        found
+    }
+
+    /// Reset the counters of this prototype and every prototype nested within it.
+    pub fn reset_counters(&self) {
+        self.counters.reset();
+        for proto in self.prototypes.iter() {
+            proto.reset_counters();
+        }
     }
 }
 
+// Seeds the counters of a freshly loaded prototype and every prototype nested within it from the
+// profile set with `Context::set_profile`, tiering up the ones which are already hot.
+fn warm_up<'gc>(ctx: Context<'gc>, proto: Gc<'gc, FunctionPrototype<'gc>>) {
+    let Some(profile) = ctx.profile() else {
+        return;
+    };
+    let mut to_visit = vec![proto];
+    while let Some(proto) = to_visit.pop() {
+        if let Some(function) = profile.get(proto.id()) {
+            proto
+                .counters
+                .seed(function.invocations, function.back_edges);
+            #[cfg(feature = "tier-up")]
+            FunctionPrototype::tier_up(proto, ctx.tier_up_threshold());
+        }
+        to_visit.extend(proto.prototypes.iter().copied());
+    }
+}
+
+// Chunk names starting with '=' or '@' are displayed without their first character, as in PUC-Rio
+// Lua.
+pub(crate) fn display_chunk_name(chunk_name: &str) -> &str {
+    chunk_name
+        .strip_prefix(&['=', '@'][..])
+        .unwrap_or(chunk_name)
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub enum UpValueState<'gc> {
@@ -165,6 +502,9 @@ impl<'gc> UpValue<'gc> {
     }
 
     pub fn set(self, mc: &Mutation<'gc>, state: UpValueState<'gc>) {
+        if let UpValueState::Closed(v) = state {
+            sanitizer::check_value(mc, v, "an upvalue");
+        }
         self.0.set(mc, state)
     }
 }
@@ -174,6 +514,7 @@ impl<'gc> UpValue<'gc> {
 pub struct ClosureInner<'gc> {
     proto: Gc<'gc, FunctionPrototype<'gc>>,
     upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    arena: ArenaTag,
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -216,6 +557,7 @@ impl<'gc> Closure<'gc> {
             if proto.upvalues.len() > 1 || proto.upvalues[0] != UpValueDescriptor::Environment {
                 return Err(ClosureError::HasUpValues);
             } else if let Some(environment) = environment {
+                sanitizer::check_value(mc, environment.into(), "a closure environment");
                 upvalues.push(UpValue(Gc::new(
                     mc,
                     Lock::new(UpValueState::Closed(Value::Table(environment))),
@@ -225,7 +567,14 @@ impl<'gc> Closure<'gc> {
             }
         }
 
-        Ok(Closure(Gc::new(mc, ClosureInner { proto, upvalues })))
+        Ok(Closure(Gc::new(
+            mc,
+            ClosureInner {
+                proto,
+                upvalues,
+                arena: ArenaTag::new(mc),
+            },
+        )))
     }
 
     pub fn from_parts(
@@ -233,7 +582,14 @@ impl<'gc> Closure<'gc> {
         proto: Gc<'gc, FunctionPrototype<'gc>>,
         upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
     ) -> Self {
-        Self(Gc::new(mc, ClosureInner { proto, upvalues }))
+        Self(Gc::new(
+            mc,
+            ClosureInner {
+                proto,
+                upvalues,
+                arena: ArenaTag::new(mc),
+            },
+        ))
     }
 
     pub fn from_inner(inner: Gc<'gc, ClosureInner<'gc>>) -> Self {
@@ -261,7 +617,90 @@ impl<'gc> Closure<'gc> {
         env: Table<'gc>,
     ) -> Result<Closure<'gc>, PrototypeError> {
         let proto = FunctionPrototype::compile(ctx, name.unwrap_or("<anonymous>"), source)?;
-        Ok(Closure::new(&ctx, proto, Some(env)).unwrap())
+        Self::from_loaded(ctx, proto, env)
+    }
+
+    /// Compile a top-level closure from a preprocessed or concatenated chunk, using the given
+    /// table as the `_ENV` table.
+    ///
+    /// The source map relates lines of the chunk back to the original files, and is used to
+    /// report original locations in debugging information.
+    pub fn load_with_source_map(
+        ctx: Context<'gc>,
+        name: Option<&str>,
+        source: impl Read,
+        env: Table<'gc>,
+        source_map: SourceMap<'gc>,
+    ) -> Result<Closure<'gc>, PrototypeError> {
+        let proto = FunctionPrototype::compile_with_source_map(
+            ctx,
+            name.unwrap_or("<anonymous>"),
+            source,
+            Some(Gc::new(&ctx, source_map)),
+        )?;
+        Self::from_loaded(ctx, proto, env)
+    }
+
+    /// Load a top-level closure from a binary chunk written by `FunctionPrototype::dump`.
+    ///
+    /// A dumped function can have any number of upvalues. As in PUC-Rio Lua, the first upvalue is
+    /// set to `env` and the rest start as nil. Binary chunks are always run through the verifier,
+    /// since nothing stops them from being crafted by hand.
+    ///
+    /// Chunks written by PUC-Rio Lua 5.4 are also accepted, and are translated into piccolo
+    /// opcodes by the [`luac`](crate::luac) module.
+    pub fn load_binary(
+        ctx: Context<'gc>,
+        chunk: &[u8],
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, PrototypeError> {
+        let proto = if chunk.starts_with(luac::SIGNATURE) {
+            luac::translate(ctx, chunk)?
+        } else {
+            dump::undump(ctx, chunk)?
+        };
+        let proto = Gc::new(&ctx, proto);
+        if ctx.opcode_checks() == OpCodeChecks::Trusted {
+            FunctionPrototype::trust(proto)?;
+        } else {
+            let mut to_verify = vec![proto];
+            while let Some(proto) = to_verify.pop() {
+                verify_prototype(&proto)?;
+                to_verify.extend(proto.prototypes.iter().copied());
+            }
+        }
+        warm_up(ctx, proto);
+
+        sanitizer::check_value(&ctx, env.into(), "a closure environment");
+        let mut upvalues = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        for i in 0..proto.upvalues.len() {
+            let value = if i == 0 {
+                Value::Table(env)
+            } else {
+                Value::Nil
+            };
+            upvalues.push(UpValue(Gc::new(
+                &ctx,
+                Lock::new(UpValueState::Closed(value)),
+            )));
+        }
+        let closure = Closure::from_parts(&ctx, proto, upvalues);
+        Ok(closure)
+    }
+
+    // Creates the closure for a freshly compiled chunk, verifying it if the context is set to
+    // trust loaded chunks.
+    pub(crate) fn from_loaded(
+        ctx: Context<'gc>,
+        proto: FunctionPrototype<'gc>,
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, PrototypeError> {
+        let closure = Closure::new(&ctx, proto, Some(env)).unwrap();
+        if ctx.opcode_checks() == OpCodeChecks::Trusted {
+            FunctionPrototype::trust(closure.prototype())?;
+        }
+        warm_up(ctx, closure.prototype());
+        Ok(closure)
     }
 
     pub fn prototype(self) -> Gc<'gc, FunctionPrototype<'gc>> {
@@ -271,4 +710,9 @@ impl<'gc> Closure<'gc> {
     pub fn upvalues(self) -> &'gc [UpValue<'gc>] {
         &Gc::as_ref(self.0).upvalues
     }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn arena_tag(self) -> ArenaTag {
+        self.0.arena
+    }
 }
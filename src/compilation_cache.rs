@@ -0,0 +1,129 @@
+use std::{collections::hash_map::Entry, rc::Rc};
+
+use ahash::HashMap;
+
+use crate::{
+    compiler::{
+        self, interning::BasicInterner, CompiledPrototype, CompilerOptions, StringInterner,
+    },
+    Closure, Context, FunctionPrototype, PrototypeError, Table,
+};
+
+/// A cache of compiled chunks keyed by their source, which can be shared between `Lua` instances.
+///
+/// Parsing and compiling a chunk produces a [`CompiledPrototype`] which does not depend on any
+/// `Lua` instance, and only the final step of turning it into a [`FunctionPrototype`] has to be
+/// repeated for each instance. Reloading many unchanged scripts, such as on hot-reload, then skips
+/// the parser and compiler entirely.
+///
+/// Entries are keyed by the exact source bytes along with everything else that affects the
+/// compiled output, the [`CompilerOptions`] and the ext opcodes registered in the loading
+/// instance, so a cached chunk is always identical to compiling it again. Chunks which fail to
+/// compile are not cached.
+#[derive(Default)]
+pub struct CompilationCache {
+    entries: HashMap<CacheKey, CompiledPrototype<Rc<[u8]>>>,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CacheKey {
+    source: Box<[u8]>,
+    options: CompilerOptions,
+    ext_opcodes: Vec<Box<[u8]>>,
+}
+
+impl CompilationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile a chunk like [`FunctionPrototype::compile`], reusing the result of any previous
+    /// compilation of the same source.
+    pub fn compile<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl AsRef<[u8]>,
+    ) -> Result<FunctionPrototype<'gc>, PrototypeError> {
+        let key = CacheKey {
+            source: source.as_ref().into(),
+            options: ctx.compiler_options(),
+            ext_opcodes: ctx
+                .ext_opcodes()
+                .names()
+                .into_iter()
+                .map(|name| name.as_bytes().into())
+                .collect(),
+        };
+
+        let compiled = match self.entries.entry(key) {
+            Entry::Occupied(entry) => {
+                self.hits += 1;
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => {
+                self.misses += 1;
+                let key = entry.key();
+                let mut interner = BasicInterner::default();
+                let ext_opcodes = key
+                    .ext_opcodes
+                    .iter()
+                    .map(|name| interner.intern(name))
+                    .collect::<Vec<_>>();
+                let chunk =
+                    compiler::parse_chunk_with_options(&*key.source, &mut interner, key.options)?;
+                entry.insert(compiler::compile_chunk_with_ext_opcodes(
+                    &chunk,
+                    &mut interner,
+                    &ext_opcodes,
+                )?)
+            }
+        };
+
+        Ok(FunctionPrototype::from_compiled_map_strings(
+            &ctx,
+            ctx.intern(source_name.as_bytes()),
+            compiled,
+            |s| ctx.intern(s),
+        ))
+    }
+
+    /// Load a top-level closure like [`Closure::load_with_env`], reusing the result of any
+    /// previous compilation of the same source.
+    pub fn load_with_env<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        name: Option<&str>,
+        source: impl AsRef<[u8]>,
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, PrototypeError> {
+        let proto = self.compile(ctx, name.unwrap_or("<anonymous>"), source)?;
+        Closure::from_loaded(ctx, proto, env)
+    }
+
+    /// The number of distinct chunks in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The number of compilations which were served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// The number of compilations which had to parse and compile their chunk.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Remove every cached chunk.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     collections::{hash_map, VecDeque},
     fmt, iter, mem,
+    string::String as StdString,
 };
 
 use ahash::HashMap;
@@ -27,10 +28,10 @@ use super::{
     parser::{
         AssignmentStatement, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk,
         ConstructorField, Expression, FieldSuffix, ForStatement, FunctionCallStatement,
-        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalFunctionStatement,
-        LocalStatement, PrimaryExpression, RecordKey, RepeatStatement, ReturnStatement,
-        SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor,
-        UnaryOperator, WhileStatement,
+        FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalAttribute,
+        LocalFunctionStatement, LocalStatement, PrimaryExpression, RecordKey, RepeatStatement,
+        ReturnStatement, SimpleExpression, Statement, SuffixPart, SuffixedExpression,
+        TableConstructor, UnaryOperator, WhileStatement,
     },
     register_allocator::RegisterAllocator,
     StringInterner,
@@ -38,7 +39,7 @@ use super::{
 
 #[derive(Debug, Copy, Clone, Error)]
 pub enum CompileErrorKind {
-    #[error("insufficient available registers")]
+    #[error("function or expression too complex")]
     Registers,
     #[error("too many upvalues")]
     UpValues,
@@ -56,16 +57,64 @@ pub enum CompileErrorKind {
     JumpLocal,
     #[error("jump offset overflow")]
     JumpOverflow,
+    #[error("attempt to assign to const variable")]
+    AssignToConst,
 }
 
-#[derive(Debug, Copy, Clone, Error)]
-#[error("compiler error at line {line_number}: {kind}")]
+#[derive(Debug, Clone, Error)]
+#[error("compiler error in {function} at line {line_number}: {kind}")]
 pub struct CompileError {
     pub kind: CompileErrorKind,
+    /// The function that was being compiled when the error occurred.
+    pub function: FunctionRef<StdString>,
+    pub line_number: LineNumber,
+}
+
+/// A warning about code which compiles but is likely to be a mistake, produced by
+/// [`compile_chunk_with_lints`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintKind<S> {
+    /// A local variable which is never read or assigned after it is declared.
+    UnusedLocal(S),
+    /// A local variable declared with the same name as another local which is already in scope.
+    ShadowedLocal(S),
+    /// An assignment to a global variable from inside a function, usually a missing `local`.
+    GlobalAssignment(S),
+    /// The length operator applied to a table constructor which is not a sequence.
+    NonSequenceLength,
+}
+
+impl<S: AsRef<[u8]>> fmt::Display for LintKind<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |s: &S| StdString::from_utf8_lossy(s.as_ref()).into_owned();
+        match self {
+            LintKind::UnusedLocal(n) => write!(f, "unused local variable '{}'", name(n)),
+            LintKind::ShadowedLocal(n) => write!(f, "local variable '{}' shadows another", name(n)),
+            LintKind::GlobalAssignment(n) => {
+                write!(
+                    f,
+                    "assignment to global variable '{}' in a function",
+                    name(n)
+                )
+            }
+            LintKind::NonSequenceLength => write!(f, "length of a table which is not a sequence"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint<S> {
+    pub kind: LintKind<S>,
     pub line_number: LineNumber,
 }
 
-#[derive(Debug, Copy, Clone, Collect)]
+impl<S: AsRef<[u8]>> fmt::Display for Lint<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning at line {}: {}", self.line_number, self.kind)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Collect)]
 #[collect(no_drop)]
 pub enum FunctionRef<S> {
     Named(S, LineNumber),
@@ -101,6 +150,29 @@ impl<S> FunctionRef<S> {
     }
 }
 
+/// Debug information for a local variable, recording where in its function it is in scope.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
+#[collect(no_drop)]
+pub struct LocalVariable<S> {
+    pub name: S,
+    pub register: RegisterIndex,
+    /// The first opcode at which the local is in scope.
+    pub start_pc: usize,
+    /// The opcode after the last one at which the local is in scope.
+    pub end_pc: usize,
+}
+
+impl<S> LocalVariable<S> {
+    pub fn map_string<S2>(self, f: impl Fn(S) -> S2) -> LocalVariable<S2> {
+        LocalVariable {
+            name: f(self.name),
+            register: self.register,
+            start_pc: self.start_pc,
+            end_pc: self.end_pc,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Collect)]
 #[collect(no_drop)]
 pub struct CompiledPrototype<S> {
@@ -115,6 +187,12 @@ pub struct CompiledPrototype<S> {
     /// Stored in sorted opcode index order with redundant entries removed.
     pub opcode_line_numbers: Vec<(usize, LineNumber)>,
     pub upvalues: Vec<UpValueDescriptor>,
+    /// Every local variable declared in this function, in declaration order.
+    ///
+    /// This, along with `upvalue_names`, is only debug information and may be empty.
+    pub local_variables: Vec<LocalVariable<S>>,
+    /// The name of each upvalue, in the same order as `upvalues`.
+    pub upvalue_names: Vec<S>,
     pub prototypes: Vec<Box<CompiledPrototype<S>>>,
 }
 
@@ -137,6 +215,12 @@ impl<S> CompiledPrototype<S> {
                 opcodes: this.opcodes,
                 opcode_line_numbers: this.opcode_line_numbers,
                 upvalues: this.upvalues,
+                local_variables: this
+                    .local_variables
+                    .into_iter()
+                    .map(|l| l.map_string(f))
+                    .collect(),
+                upvalue_names: this.upvalue_names.into_iter().map(f).collect(),
                 prototypes: this
                     .prototypes
                     .into_iter()
@@ -151,26 +235,80 @@ impl<S> CompiledPrototype<S> {
 pub fn compile_chunk<S: StringInterner>(
     chunk: &Chunk<S::String>,
     create_string: S,
+) -> Result<CompiledPrototype<S::String>, CompileError> {
+    compile_chunk_with_ext_opcodes(chunk, create_string, &[])
+}
+
+/// Compile a chunk, turning calls to any of the given global names into calls to the ext opcode
+/// at the same index, see [`crate::ext`].
+pub fn compile_chunk_with_ext_opcodes<S: StringInterner>(
+    chunk: &Chunk<S::String>,
+    create_string: S,
+    ext_opcodes: &[S::String],
+) -> Result<CompiledPrototype<S::String>, CompileError> {
+    compile(chunk, create_string, ext_opcodes, None)
+}
+
+/// Compile a chunk like [`compile_chunk_with_ext_opcodes`], also returning warnings about likely
+/// mistakes in it.
+///
+/// Locals whose names start with an underscore are never reported as unused or shadowing.
+pub fn compile_chunk_with_lints<S: StringInterner>(
+    chunk: &Chunk<S::String>,
+    create_string: S,
+    ext_opcodes: &[S::String],
+) -> Result<(CompiledPrototype<S::String>, Vec<Lint<S::String>>), CompileError> {
+    let mut lints = Vec::new();
+    let proto = compile(chunk, create_string, ext_opcodes, Some(&mut lints))?;
+    Ok((proto, lints))
+}
+
+fn compile<S: StringInterner>(
+    chunk: &Chunk<S::String>,
+    create_string: S,
+    ext_opcodes: &[S::String],
+    lints: Option<&mut Vec<Lint<S::String>>>,
 ) -> Result<CompiledPrototype<S::String>, CompileError> {
     let mut compiler = Compiler {
         string_interner: create_string,
+        ext_opcodes: ext_opcodes.to_vec(),
+        lints,
         current_function: CompilerFunction::start(FunctionRef::Chunk, &[], true).unwrap(),
         upper_functions: Vec::new(),
     };
-    compiler.block(&chunk.block).map_err(|kind| CompileError {
+    // If compiling an inner function fails, it is left as the current function, so errors always
+    // name the innermost function being compiled.
+    let error = |reference: &FunctionRef<S::String>, line_number, kind| CompileError {
         kind,
-        line_number: compiler.current_function.current_line_number,
-    })?;
+        function: reference
+            .as_string_ref()
+            .map_strings(|s| StdString::from_utf8_lossy(s.as_ref()).into_owned()),
+        line_number,
+    };
+
+    if let Err(kind) = compiler.block(&chunk.block) {
+        let function = &compiler.current_function;
+        return Err(error(
+            &function.reference,
+            function.current_line_number,
+            kind,
+        ));
+    }
 
+    let reference = compiler.current_function.reference.clone();
     let line_number = compiler.current_function.current_line_number;
     compiler
         .current_function
         .finish()
-        .map_err(|kind| CompileError { kind, line_number })
+        .map_err(|kind| error(&reference, line_number, kind))
 }
 
-struct Compiler<S: StringInterner> {
+struct Compiler<'a, S: StringInterner> {
     string_interner: S,
+    // Global names whose calls are compiled to `LoadExt` of the ext opcode at the same index.
+    ext_opcodes: Vec<S::String>,
+    // Only set if lints were requested.
+    lints: Option<&'a mut Vec<Lint<S::String>>>,
     current_function: CompilerFunction<S::String>,
     upper_functions: Vec<CompilerFunction<S::String>>,
 }
@@ -189,6 +327,13 @@ struct CompilerFunction<S> {
     has_varargs: bool,
     fixed_params: u8,
     locals: Vec<(S, RegisterIndex)>,
+    // The index in `local_variables` of each entry in `locals`.
+    local_indexes: Vec<usize>,
+    // The line each entry in `locals` was declared on, and whether it has been referenced since.
+    local_usage: Vec<(LineNumber, bool)>,
+    local_variables: Vec<LocalVariable<S>>,
+    // Registers of the locals in scope which were declared `<const>` or `<close>`.
+    const_locals: Vec<RegisterIndex>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
@@ -305,8 +450,11 @@ struct BlockDescriptor {
     // The index of the first jump target in this block. All jump targets above this will go out of
     // scope when the block ends.
     bottom_jump_target: usize,
-    // True if any lower function has an upvalue reference to variables in this block
+    // True if any lower function has an upvalue reference to variables in this block, or if this
+    // block has any to-be-closed variables. Either way, variables must be closed when leaving it.
     owns_upvalues: bool,
+    // True if there are any to-be-closed variables declared in this block.
+    has_to_be_closed: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -339,7 +487,7 @@ enum CallMode {
     TailCall,
 }
 
-impl<S: StringInterner> Compiler<S> {
+impl<'a, S: StringInterner> Compiler<'a, S> {
     fn block(&mut self, block: &Block<S::String>) -> Result<(), CompileErrorKind> {
         self.enter_block();
         self.block_statements(block)?;
@@ -351,20 +499,29 @@ impl<S: StringInterner> Compiler<S> {
             stack_bottom: self.current_function.register_allocator.stack_top(),
             bottom_jump_target: self.current_function.jump_targets.len(),
             owns_upvalues: false,
+            has_to_be_closed: false,
         });
     }
 
     fn exit_block(&mut self) -> Result<(), CompileErrorKind> {
         let last_block = self.current_function.blocks.pop().unwrap();
 
-        while let Some((_, last)) = self.current_function.locals.last() {
+        while let Some((name, last)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
-                self.current_function.register_allocator.free(*last);
-                self.current_function.locals.pop();
+                let (name, last) = (name.clone(), *last);
+                let &(line_number, used) = self.current_function.local_usage.last().unwrap();
+                if !used && !name.as_ref().starts_with(b"_") {
+                    self.lint_at(LintKind::UnusedLocal(name), line_number);
+                }
+                self.current_function.register_allocator.free(last);
+                self.current_function.pop_local();
             } else {
                 break;
             }
         }
+        self.current_function
+            .const_locals
+            .retain(|r| (r.0 as u16) < last_block.stack_bottom);
         self.current_function
             .jump_targets
             .drain(last_block.bottom_jump_target..);
@@ -406,11 +563,11 @@ impl<S: StringInterner> Compiler<S> {
     fn block_statements(&mut self, block: &Block<S::String>) -> Result<(), CompileErrorKind> {
         if let Some(return_statement) = &block.return_statement {
             for statement in &block.statements {
-                self.current_function.set_line_number(statement.line_number);
+                self.current_function.set_line_number(statement.span.start);
                 self.statement(statement)?;
             }
             self.current_function
-                .set_line_number(return_statement.line_number);
+                .set_line_number(return_statement.span.start);
             self.return_statement(return_statement)?;
         } else {
             let mut last = block.statements.len();
@@ -426,14 +583,14 @@ impl<S: StringInterner> Compiler<S> {
             self.enter_block();
             for i in 0..block.statements.len() - trailing_labels.len() {
                 self.current_function
-                    .set_line_number(block.statements[i].line_number);
+                    .set_line_number(block.statements[i].span.start);
                 self.statement(&block.statements[i])?;
             }
             self.exit_block()?;
 
             for label_statement in trailing_labels {
                 self.current_function
-                    .set_line_number(label_statement.line_number);
+                    .set_line_number(label_statement.span.start);
                 self.statement(label_statement)?;
             }
         }
@@ -477,8 +634,14 @@ impl<S: StringInterner> Compiler<S> {
             .collect::<Result<Vec<_>, CompileErrorKind>>()?;
 
         // A return of a single function call is a tail call, and this is the only thing
-        // in Lua that is considered a tail call
-        if returns.len() == 1 {
+        // in Lua that is considered a tail call. If there are to-be-closed variables in scope, they
+        // must be closed after the call returns, so it cannot be a tail call.
+        let has_to_be_closed = self
+            .current_function
+            .blocks
+            .iter()
+            .any(|b| b.has_to_be_closed);
+        if returns.len() == 1 && !has_to_be_closed {
             match returns.pop().unwrap() {
                 ExprDescriptor::FunctionCall { func, args } => {
                     self.call_function(*func, args, CallMode::TailCall)?;
@@ -520,14 +683,28 @@ impl<S: StringInterner> Compiler<S> {
         let end_label = self.unique_jump_label();
         let mut next_label = self.unique_jump_label();
 
-        for (i, (if_expr, block)) in iter::once(&if_statement.if_part)
+        let mut parts = iter::once(&if_statement.if_part)
             .chain(&if_statement.else_if_parts)
-            .enumerate()
-        {
+            .enumerate();
+        let mut always_taken = false;
+        for (i, (if_expr, block)) in parts.by_ref() {
             self.jump_target(next_label.clone())?;
             next_label = self.unique_jump_label();
 
             let if_expr = self.expression(if_expr)?;
+
+            // Branches with constant conditions are resolved at compile time.
+            if let ExprDescriptor::Constant(cons) = &if_expr {
+                if cons.to_bool() {
+                    self.block(block)?;
+                    always_taken = true;
+                    break;
+                } else {
+                    self.dead_code(|this| this.block(block))?;
+                    continue;
+                }
+            }
+
             self.expr_test(if_expr, true)?;
             self.jump(next_label.clone())?;
 
@@ -539,9 +716,24 @@ impl<S: StringInterner> Compiler<S> {
             self.exit_block()?;
         }
 
-        self.jump_target(next_label)?;
-        if let Some(else_block) = &if_statement.else_part {
-            self.block(else_block)?;
+        if always_taken {
+            // Every branch after one which is always taken is dead.
+            self.dead_code(|this| {
+                for (_, (if_expr, block)) in parts {
+                    let if_expr = this.expression(if_expr)?;
+                    this.expr_test(if_expr, true)?;
+                    this.block(block)?;
+                }
+                if let Some(else_block) = &if_statement.else_part {
+                    this.block(else_block)?;
+                }
+                Ok(())
+            })?;
+        } else {
+            self.jump_target(next_label)?;
+            if let Some(else_block) = &if_statement.else_part {
+                self.block(else_block)?;
+            }
         }
 
         self.jump_target(end_label)?;
@@ -587,7 +779,7 @@ impl<S: StringInterner> Compiler<S> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompileErrorKind::Registers)?;
-                self.current_function.locals.push((name.clone(), loop_var));
+                self.declare_local(name.clone(), loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
@@ -668,9 +860,7 @@ impl<S: StringInterner> Compiler<S> {
                     .push(name_count)
                     .ok_or(CompileErrorKind::Registers)?;
                 for i in 0..name_count {
-                    self.current_function
-                        .locals
-                        .push((names[i as usize].clone(), RegisterIndex(names_reg.0 + i)));
+                    self.declare_local(names[i as usize].clone(), RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label.clone())?;
@@ -831,6 +1021,9 @@ impl<S: StringInterner> Compiler<S> {
                     self.current_function.register_allocator.free(source);
                 }
                 VariableDescriptor::Global(_) => {
+                    if !self.upper_functions.is_empty() {
+                        self.lint(LintKind::GlobalAssignment(name.clone()));
+                    }
                     let env = self.get_environment()?;
                     self.set_table(
                         env,
@@ -864,10 +1057,10 @@ impl<S: StringInterner> Compiler<S> {
                 .operations
                 .push(Operation::LoadNil { dest, count });
             for i in 0..name_len {
-                self.current_function.locals.push((
+                self.declare_local(
                     local_statement.names[i].clone(),
                     RegisterIndex(dest.0 + i as u8),
-                ));
+                );
             }
         } else {
             for i in 0..val_len {
@@ -883,20 +1076,38 @@ impl<S: StringInterner> Compiler<S> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.declare_local(
                             local_statement.names[val_len - 1 + j as usize].clone(),
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
-                    self.current_function
-                        .locals
-                        .push((local_statement.names[i].clone(), reg));
+                    self.declare_local(local_statement.names[i].clone(), reg);
                 }
             }
         }
 
+        // All of the names in this statement are now the topmost locals, in order.
+        let first_local = self.current_function.locals.len() - name_len;
+        for (i, attribute) in local_statement.attributes.iter().enumerate() {
+            let Some(attribute) = attribute else {
+                continue;
+            };
+
+            let reg = self.current_function.locals[first_local + i].1;
+            self.current_function.const_locals.push(reg);
+
+            if *attribute == LocalAttribute::Close {
+                self.current_function
+                    .operations
+                    .push(Operation::ToBeClosed { value: reg });
+                let block = self.current_function.blocks.last_mut().unwrap();
+                block.owns_upvalues = true;
+                block.has_to_be_closed = true;
+            }
+        }
+
         Ok(())
     }
 
@@ -951,25 +1162,31 @@ impl<S: StringInterner> Compiler<S> {
             expr: ExprDescriptor<S::String>,
         ) -> Result<(), CompileErrorKind> {
             match target {
-                AssignmentTarget::Name(name) => match this.find_variable(name.clone())? {
-                    VariableDescriptor::Local(dest) => {
-                        this.expr_discharge(expr, ExprDestination::Register(dest))?;
-                    }
-                    VariableDescriptor::UpValue(dest) => {
-                        let (source, source_is_temp) = this.expr_any_register(expr)?;
-                        this.current_function
-                            .operations
-                            .push(Operation::SetUpValue { source, dest });
-                        if source_is_temp {
-                            this.current_function.register_allocator.free(source);
+                AssignmentTarget::Name(name) => {
+                    this.check_assignable(name)?;
+                    match this.find_variable(name.clone())? {
+                        VariableDescriptor::Local(dest) => {
+                            this.expr_discharge(expr, ExprDestination::Register(dest))?;
+                        }
+                        VariableDescriptor::UpValue(dest) => {
+                            let (source, source_is_temp) = this.expr_any_register(expr)?;
+                            this.current_function
+                                .operations
+                                .push(Operation::SetUpValue { source, dest });
+                            if source_is_temp {
+                                this.current_function.register_allocator.free(source);
+                            }
+                        }
+                        VariableDescriptor::Global(name) => {
+                            if !this.upper_functions.is_empty() {
+                                this.lint(LintKind::GlobalAssignment(name.clone()));
+                            }
+                            let env = this.get_environment()?;
+                            let key = ExprDescriptor::Constant(Constant::String(name));
+                            this.set_table(env, key, expr)?;
                         }
                     }
-                    VariableDescriptor::Global(name) => {
-                        let env = this.get_environment()?;
-                        let key = ExprDescriptor::Constant(Constant::String(name));
-                        this.set_table(env, key, expr)?;
-                    }
-                },
+                }
 
                 AssignmentTarget::Field(table, field) => {
                     let table = this.suffixed_expression(table)?;
@@ -1026,9 +1243,7 @@ impl<S: StringInterner> Compiler<S> {
             .register_allocator
             .push(1)
             .ok_or(CompileErrorKind::Registers)?;
-        self.current_function
-            .locals
-            .push((local_function.name.clone(), dest));
+        self.declare_local(local_function.name.clone(), dest);
 
         let proto = self.new_prototype(
             FunctionRef::Named(
@@ -1192,9 +1407,11 @@ impl<S: StringInterner> Compiler<S> {
             PrimaryExpression::Name(name) => {
                 Ok(ExprDescriptor::Variable(self.find_variable(name.clone())?))
             }
-            PrimaryExpression::GroupedExpression(expr) => {
-                Ok(ExprDescriptor::Group(Box::new(self.expression(expr)?)))
-            }
+            PrimaryExpression::GroupedExpression(expr) => Ok(match self.expression(expr)? {
+                // Constants are always a single value, so they can still be folded.
+                ExprDescriptor::Constant(cons) => ExprDescriptor::Constant(cons),
+                expr => ExprDescriptor::Group(Box::new(expr)),
+            }),
         }
     }
 
@@ -1209,6 +1426,22 @@ impl<S: StringInterner> Compiler<S> {
             }
         }
 
+        if let (
+            UnaryOperator::Len,
+            ExprDescriptor::TableConstructor {
+                array_fields,
+                record_fields,
+            },
+        ) = (unop, &expr)
+        {
+            let has_nil = array_fields
+                .iter()
+                .any(|f| matches!(f, ExprDescriptor::Constant(Constant::Nil)));
+            if !record_fields.is_empty() || has_nil {
+                self.lint(LintKind::NonSequenceLength);
+            }
+        }
+
         Ok(ExprDescriptor::UnaryOperator {
             op: unop,
             expr: Box::new(expr),
@@ -1250,11 +1483,34 @@ impl<S: StringInterner> Compiler<S> {
                 })
             }
 
-            BinOpCategory::ShortCircuit(op) => Ok(ExprDescriptor::ShortCircuitBinOp {
-                left: Box::new(left),
-                op,
-                right: Box::new(right),
-            }),
+            BinOpCategory::ShortCircuit(op) => {
+                // A constant left side decides which side is the result, and evaluating it has no
+                // side effects.
+                if let ExprDescriptor::Constant(a) = &left {
+                    let short_circuits = match op {
+                        ShortCircuitBinOp::And => !a.to_bool(),
+                        ShortCircuitBinOp::Or => a.to_bool(),
+                    };
+                    return Ok(if short_circuits {
+                        left
+                    } else {
+                        match right {
+                            // The result of a short-circuit operator is always a single value.
+                            right @ (ExprDescriptor::VarArgs
+                            | ExprDescriptor::FunctionCall { .. }
+                            | ExprDescriptor::MethodCall { .. }) => {
+                                ExprDescriptor::Group(Box::new(right))
+                            }
+                            right => right,
+                        }
+                    });
+                }
+                Ok(ExprDescriptor::ShortCircuitBinOp {
+                    left: Box::new(left),
+                    op,
+                    right: Box::new(right),
+                })
+            }
 
             BinOpCategory::Concat => Ok(match (left, right) {
                 (ExprDescriptor::Concat(mut left), ExprDescriptor::Concat(right)) => {
@@ -1305,6 +1561,52 @@ impl<S: StringInterner> Compiler<S> {
         ))
     }
 
+    // Bring a new local into scope in the current function, linting it if it shadows another.
+    fn declare_local(&mut self, name: S::String, register: RegisterIndex) {
+        if self.lints.is_some() && !name.as_ref().starts_with(b"_") {
+            let shadows = iter::once(&self.current_function)
+                .chain(self.upper_functions.iter())
+                .flat_map(|function| &function.locals)
+                .any(|(local_name, _)| local_name.as_ref() == name.as_ref());
+            if shadows {
+                self.lint(LintKind::ShadowedLocal(name.clone()));
+            }
+        }
+        self.current_function.push_local(name, register);
+    }
+
+    fn lint(&mut self, kind: LintKind<S::String>) {
+        let line_number = self.current_function.current_line_number;
+        self.lint_at(kind, line_number);
+    }
+
+    fn lint_at(&mut self, kind: LintKind<S::String>, line_number: LineNumber) {
+        if let Some(lints) = &mut self.lints {
+            lints.push(Lint { kind, line_number });
+        }
+    }
+
+    // Returns an error if the given name refers to a local declared `<const>` or `<close>`, either
+    // in the current function or in any enclosing function.
+    fn check_assignable(&self, name: &S::String) -> Result<(), CompileErrorKind> {
+        for function in iter::once(&self.current_function).chain(self.upper_functions.iter().rev())
+        {
+            if let Some((_, register)) = function
+                .locals
+                .iter()
+                .rev()
+                .find(|(local_name, _)| local_name.as_ref() == name.as_ref())
+            {
+                return if function.const_locals.contains(register) {
+                    Err(CompileErrorKind::AssignToConst)
+                } else {
+                    Ok(())
+                };
+            }
+        }
+        Ok(())
+    }
+
     fn find_variable(
         &mut self,
         name: S::String,
@@ -1327,6 +1629,7 @@ impl<S: StringInterner> Compiler<S> {
             for j in (0..get_function(self, i).locals.len()).rev() {
                 let (local_name, register) = get_function(self, i).locals[j].clone();
                 if name.as_ref() == local_name.as_ref() {
+                    get_function(self, i).local_usage[j].1 = true;
                     if i == current_function {
                         return Ok(VariableDescriptor::Local(register));
                     } else {
@@ -1412,6 +1715,34 @@ impl<S: StringInterner> Compiler<S> {
         jl
     }
 
+    // Compile code which can never run, such as the body of `if false then ... end`. It is checked
+    // for errors like any other code, but none of the opcodes it generates are kept.
+    fn dead_code(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), CompileErrorKind>,
+    ) -> Result<(), CompileErrorKind> {
+        let operations = self.current_function.operations.len();
+        let operation_lines = self.current_function.operation_lines.len();
+        let current_line_number = self.current_function.current_line_number;
+        let functions = self.current_function.functions.len();
+        let local_variables = self.current_function.local_variables.len();
+
+        f(self)?;
+
+        let function = &mut self.current_function;
+        function.operations.truncate(operations);
+        function.operation_lines.truncate(operation_lines);
+        function.current_line_number = current_line_number;
+        function.functions.truncate(functions);
+        function.local_variables.truncate(local_variables);
+        // Jumps out of dead code, such as a `break`, are dropped along with it.
+        function
+            .pending_jumps
+            .retain(|pending_jump| pending_jump.instruction < operations);
+
+        Ok(())
+    }
+
     fn jump(&mut self, target: JumpLabel<S::String>) -> Result<(), CompileErrorKind> {
         let jmp_inst = self.current_function.operations.len();
         let current_stack_top = self.current_function.register_allocator.stack_top();
@@ -1626,7 +1957,20 @@ impl<S: StringInterner> Compiler<S> {
         args: Vec<ExprDescriptor<S::String>>,
         mode: CallMode,
     ) -> Result<RegisterIndex, CompileErrorKind> {
-        let func = self.expr_discharge(func, ExprDestination::PushNew)?;
+        let func = match self.ext_opcode(&func) {
+            Some(ext) => {
+                let dest = self
+                    .current_function
+                    .register_allocator
+                    .push(1)
+                    .ok_or(CompileErrorKind::Registers)?;
+                self.current_function
+                    .operations
+                    .push(Operation::LoadExt { dest, ext });
+                dest
+            }
+            None => self.expr_discharge(func, ExprDestination::PushNew)?,
+        };
         let args = self.push_arguments(args)?;
 
         match mode {
@@ -1649,6 +1993,19 @@ impl<S: StringInterner> Compiler<S> {
         Ok(func)
     }
 
+    // If the called expression is a global which is registered as an ext opcode, returns the index
+    // of the ext opcode.
+    fn ext_opcode(&self, func: &ExprDescriptor<S::String>) -> Option<u8> {
+        let ExprDescriptor::Variable(VariableDescriptor::Global(name)) = func else {
+            return None;
+        };
+        let index = self
+            .ext_opcodes
+            .iter()
+            .position(|ext| ext.as_ref() == name.as_ref())?;
+        index.try_into().ok()
+    }
+
     // Performs a method call similarly to how `call_function` works. Method calls have a special
     // opcode that make them more efficient than executing them in a naive way.
     fn call_method(
@@ -2432,6 +2789,10 @@ impl<S: Clone> CompilerFunction<S> {
             has_varargs: false,
             fixed_params: 0,
             locals: Vec::new(),
+            local_indexes: Vec::new(),
+            local_usage: Vec::new(),
+            local_variables: Vec::new(),
+            const_locals: Vec::new(),
             blocks: Vec::new(),
             unique_jump_id: 0,
             jump_targets: Vec::new(),
@@ -2451,9 +2812,11 @@ impl<S: Clone> CompilerFunction<S> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize].clone(), RegisterIndex(i)));
+            function.push_local(parameters[i as usize].clone(), RegisterIndex(i));
+        }
+        // Unused parameters are common in callbacks, so they are never linted.
+        for (_, used) in &mut function.local_usage {
+            *used = true;
         }
         Ok(function)
     }
@@ -2464,7 +2827,7 @@ impl<S: Clone> CompilerFunction<S> {
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
+        while let Some(r) = self.pop_local() {
             self.register_allocator.free(r);
         }
         assert_eq!(
@@ -2503,10 +2866,34 @@ impl<S: Clone> CompilerFunction<S> {
                 .collect(),
             opcode_line_numbers: operation_lines,
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
+            local_variables: self.local_variables,
+            upvalue_names: self.upvalues.into_iter().map(|(n, _)| n).collect(),
             prototypes: self.functions.into_iter().map(|f| Box::new(f)).collect(),
         })
     }
 
+    // Bring a new local into scope starting at the next opcode.
+    fn push_local(&mut self, name: S, register: RegisterIndex) {
+        self.local_indexes.push(self.local_variables.len());
+        self.local_variables.push(LocalVariable {
+            name: name.clone(),
+            register,
+            start_pc: self.operations.len(),
+            end_pc: self.operations.len(),
+        });
+        self.locals.push((name, register));
+        self.local_usage.push((self.current_line_number, false));
+    }
+
+    // Take the most recently declared local out of scope, returning its register.
+    fn pop_local(&mut self) -> Option<RegisterIndex> {
+        let (_, register) = self.locals.pop()?;
+        self.local_usage.pop();
+        let index = self.local_indexes.pop().unwrap();
+        self.local_variables[index].end_pc = self.operations.len();
+        Some(register)
+    }
+
     pub fn set_line_number(&mut self, line_number: LineNumber) {
         self.current_line_number = line_number;
         self.operation_lines
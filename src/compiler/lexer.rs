@@ -1,5 +1,7 @@
 use std::{
-    char, fmt, i32, i64,
+    char,
+    collections::VecDeque,
+    fmt, i32, i64,
     io::{self, Read},
     str,
 };
@@ -250,12 +252,17 @@ impl fmt::Display for LineNumber {
     }
 }
 
+// The source is read this many bytes at a time, so it is lexed as it is read without ever being
+// held in memory all at once, and without needing to be wrapped in a `BufReader`.
+const READ_CHUNK_SIZE: usize = 4096;
+
 pub struct Lexer<R, S> {
     source: Option<R>,
     interner: S,
-    peek_buffer: Vec<u8>,
+    peek_buffer: VecDeque<u8>,
     string_buffer: Vec<u8>,
     line_number: u64,
+    column: u64,
 }
 
 impl<R, S> Lexer<R, S>
@@ -267,9 +274,10 @@ where
         Lexer {
             source: Some(source),
             interner,
-            peek_buffer: Vec::new(),
+            peek_buffer: VecDeque::new(),
             string_buffer: Vec::new(),
             line_number: 0,
+            column: 0,
         }
     }
 
@@ -278,6 +286,11 @@ where
         LineNumber(self.line_number)
     }
 
+    /// Current column of the source file, as a 0-indexed byte offset into the current line.
+    pub fn column(&self) -> u64 {
+        self.column
+    }
+
     pub fn skip_whitespace(&mut self) -> Result<(), LexError> {
         let mut do_skip_whitespace = || {
             while let Some(c) = self.peek(0)? {
@@ -523,6 +536,7 @@ where
         }
 
         self.line_number += 1;
+        self.column = 0;
         Ok(())
     }
 
@@ -850,14 +864,14 @@ where
     fn peek(&mut self, n: usize) -> Result<Option<u8>, LexError> {
         if let Some(source) = self.source.as_mut() {
             while self.peek_buffer.len() <= n {
-                let mut c = [0];
-                match source.read(&mut c) {
+                let mut chunk = [0; READ_CHUNK_SIZE];
+                match source.read(&mut chunk) {
                     Ok(0) => {
                         self.source = None;
                         break;
                     }
-                    Ok(_) => {
-                        self.peek_buffer.push(c[0]);
+                    Ok(len) => {
+                        self.peek_buffer.extend(&chunk[..len]);
                     }
                     Err(e) => {
                         if e.kind() != io::ErrorKind::Interrupted {
@@ -877,7 +891,8 @@ where
             n <= self.peek_buffer.len(),
             "cannot advance over un-peeked characters"
         );
-        self.peek_buffer.drain(0..n);
+        self.peek_buffer.drain(..n);
+        self.column += n as u64;
     }
 
     fn take_string(&mut self) -> S::String {
@@ -926,30 +941,19 @@ pub fn read_dec_integer(s: &[u8]) -> Option<i64> {
     }
 }
 
+// Hex integers wrap around on overflow, unlike decimal integers which become floats.
 pub fn read_hex_integer(s: &[u8]) -> Option<i64> {
     let (is_neg, s) = read_neg(s);
-
     if s.len() < 3 || s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
         return None;
     }
-
     let mut i: u64 = 0;
     for &c in &s[2..] {
         let d = from_hex_digit(c)? as u64;
-        i = i.checked_mul(16)?.checked_add(d)?;
-    }
-
-    if is_neg {
-        if i <= i64::MAX as u64 {
-            Some(-(i as i64))
-        } else if i == i64::MAX as u64 + 1 {
-            Some(i64::MIN)
-        } else {
-            None
-        }
-    } else {
-        i.try_into().ok()
+        i = i.wrapping_mul(16).wrapping_add(d);
     }
+    let i = i as i64;
+    Some(if is_neg { i.wrapping_neg() } else { i })
 }
 
 pub fn read_float(s: &[u8]) -> Option<f64> {
@@ -957,26 +961,34 @@ pub fn read_float(s: &[u8]) -> Option<f64> {
 }
 
 pub fn read_dec_float(s: &[u8]) -> Option<f64> {
+    // Rust's float parsing is locale independent, but it also accepts words like "inf" and "NaN"
+    // which are not valid Lua numerals.
+    if !s
+        .iter()
+        .all(|&c| is_digit(c) || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'))
+    {
+        return None;
+    }
     let s = str::from_utf8(s).ok()?;
     str::parse(s).ok()
 }
 
+// Hex floats are correctly rounded to the nearest `f64`, with ties going to even, like the
+// `strtod` used by PUC-Rio Lua.
 pub fn read_hex_float(s: &[u8]) -> Option<f64> {
-    const MAX_SIGNIFICANT_DIGITS: u32 = 30;
-
     let (is_neg, s) = read_neg(s);
-
     if s.len() < 3 || s[0] != b'0' || (s[1] != b'x' && s[1] != b'X') {
         return None;
     }
 
-    let mut significant_digits: u32 = 0;
-    let mut non_significant_digits: u32 = 0;
+    // The value is `mantissa * 2^exp`, plus some amount less than one unit of `mantissa` if
+    // `sticky` is set.
+    let mut mantissa: u64 = 0;
+    let mut exp: i64 = 0;
+    let mut sticky = false;
+    let mut digits = 0;
     let mut found_dot = false;
-    let mut base: f64 = 0.0;
-    let mut exp: i32 = 0;
     let mut i = 2;
-
     while i < s.len() {
         let c = s[i];
         if c == b'.' {
@@ -985,18 +997,18 @@ pub fn read_hex_float(s: &[u8]) -> Option<f64> {
             }
             found_dot = true;
         } else if let Some(d) = from_hex_digit(c) {
-            if significant_digits == 0 && d == 0 {
-                non_significant_digits += 1;
-            } else if significant_digits < MAX_SIGNIFICANT_DIGITS {
-                significant_digits += 1;
-                base = (base * 16.0) + d as f64;
+            digits += 1;
+            if mantissa >> 60 == 0 {
+                mantissa = mantissa * 16 + d as u64;
+                if found_dot {
+                    exp -= 4;
+                }
             } else {
-                // ignore the digit, but count it towards the expontent
-                exp = exp.checked_add(4)?;
-            }
-            if found_dot {
-                // Correct exponent for the fractional part
-                exp = exp.checked_sub(4)?;
+                // The mantissa is full, so the digit only affects rounding.
+                sticky |= d != 0;
+                if !found_dot {
+                    exp += 4;
+                }
             }
         } else {
             break;
@@ -1004,30 +1016,82 @@ pub fn read_hex_float(s: &[u8]) -> Option<f64> {
         i += 1;
     }
 
-    if non_significant_digits + significant_digits == 0 {
+    if digits == 0 {
         return None;
     }
 
     if i + 1 < s.len() && (s[i] == b'p' || s[i] == b'P') {
         let (exp_neg, exp_s) = read_neg(&s[i + 1..]);
-        let mut exp1: i32 = 0;
+        if exp_s.is_empty() {
+            return None;
+        }
+        let mut exp1: i64 = 0;
         for &c in exp_s {
             let d = from_digit(c)?;
-            exp1 = exp1.saturating_mul(10).saturating_add(d as i32);
-        }
-        if exp_neg {
-            exp1 = -exp1;
+            exp1 = exp1.saturating_mul(10).saturating_add(d as i64);
         }
-        exp = exp.saturating_add(exp1);
+        exp = exp.saturating_add(if exp_neg { -exp1 } else { exp1 });
     } else if i != s.len() {
         return None;
     }
 
-    if is_neg {
-        base = -base;
+    let f = round_to_f64(mantissa, exp, sticky);
+    Some(if is_neg { -f } else { f })
+}
+
+// Rounds `mantissa * 2^exp` (plus a little more if `sticky` is set) to the nearest `f64`.
+fn round_to_f64(mantissa: u64, exp: i64, sticky: bool) -> f64 {
+    const MANTISSA_BITS: i64 = 53;
+    const MIN_EXP: i64 = -1022;
+    const MAX_EXP: i64 = 1023;
+
+    if mantissa == 0 {
+        return 0.0;
+    }
+
+    let bits = 64 - mantissa.leading_zeros() as i64;
+    let top_exp = exp.saturating_add(bits - 1);
+    if top_exp > MAX_EXP {
+        return f64::INFINITY;
+    }
+
+    // Subnormal results have fewer bits of precision.
+    let keep = MANTISSA_BITS - (MIN_EXP - top_exp).max(0);
+    let shift = bits - keep;
+    let (mut m, exp) = if shift <= 0 {
+        (mantissa << -shift, exp + shift)
+    } else if shift > 64 {
+        // The value is less than half of the smallest subnormal.
+        return 0.0;
+    } else {
+        let rem = if shift == 64 {
+            mantissa
+        } else {
+            mantissa & ((1 << shift) - 1)
+        };
+        let m = mantissa.checked_shr(shift as u32).unwrap_or(0);
+        let half = 1 << (shift - 1);
+        let round_up = rem > half || (rem == half && (sticky || m & 1 == 1));
+        (m + round_up as u64, exp + shift)
+    };
+
+    // Multiplying by a power of two is exact, so build `m * 2^exp` out of two exact factors,
+    // keeping each factor's exponent within range.
+    let mut exp = exp;
+    if m >> MANTISSA_BITS != 0 {
+        m >>= 1;
+        exp += 1;
     }
+    m as f64 * pow2(exp)
+}
 
-    Some(base * (exp as f64).exp2())
+// Returns 2^exp for any exponent between the smallest subnormal and the largest normal `f64`.
+fn pow2(exp: i64) -> f64 {
+    if exp >= -1022 {
+        f64::from_bits(((exp + 1023) as u64) << 52)
+    } else {
+        f64::from_bits(1 << (exp + 1074))
+    }
 }
 
 pub fn read_neg(s: &[u8]) -> (bool, &[u8]) {
@@ -1265,6 +1329,18 @@ mod tests {
                 0x99999999999999999999999999999999p999999999999999999999999999999
                 9223372036854775807
                 9223372036854775808
+                0x7fffffffffffffff
+                0xffffffffffffffff
+                0x10000000000000001
+                0x1p-1074
+                0x1p-1075
+                0x1.8p-1074
+                0x1.fffffffffffffp1023
+                0x1.fffffffffffff8p1023
+                0x1.00000000000008p0
+                0x1.00000000000018p0
+                0x1.000000000000080000000001p0
+                0x.0001P16
             "#,
             &[
                 Token::Integer(0xdeadbeef),
@@ -1278,6 +1354,18 @@ mod tests {
                 Token::Float(f64::INFINITY),
                 Token::Integer(9223372036854775807),
                 Token::Float(9223372036854775808.0),
+                Token::Integer(i64::MAX),
+                Token::Integer(-1),
+                Token::Integer(1),
+                Token::Float(f64::from_bits(1)),
+                Token::Float(0.0),
+                Token::Float(f64::from_bits(2)),
+                Token::Float(f64::MAX),
+                Token::Float(f64::INFINITY),
+                Token::Float(1.0),
+                Token::Float(1.0 + f64::EPSILON * 2.0),
+                Token::Float(1.0 + f64::EPSILON),
+                Token::Float(1.0),
             ],
         );
     }
@@ -1370,4 +1458,46 @@ mod tests {
         assert_eq!(trim_whitespace(b""), b"");
         assert_eq!(trim_whitespace(b" . "), b".");
     }
+
+    #[test]
+    fn chunked_reads() {
+        // Reads at most `piece` bytes of the source at a time, counting every read.
+        struct Pieces<'a> {
+            source: &'a [u8],
+            piece: usize,
+            reads: usize,
+        }
+
+        impl Read for Pieces<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.reads += 1;
+                let len = buf.len().min(self.piece).min(self.source.len());
+                buf[..len].copy_from_slice(&self.source[..len]);
+                self.source = &self.source[len..];
+                Ok(len)
+            }
+        }
+
+        let source = "local name = 'value'\n".repeat(1000);
+        for piece in [3, usize::MAX] {
+            let mut pieces = Pieces {
+                source: source.as_bytes(),
+                piece,
+                reads: 0,
+            };
+            let mut lexer = Lexer::new(&mut pieces, BasicInterner::default());
+            let mut tokens = 0;
+            while let Some(token) = lexer.read_token().unwrap() {
+                if tokens % 4 == 3 {
+                    assert_eq!(token, str_token("value"));
+                }
+                tokens += 1;
+            }
+            assert_eq!(tokens, 4000);
+
+            // Tokens may be split across reads, but the source is never read a byte at a time.
+            let expected_reads = source.len() / piece.min(READ_CHUNK_SIZE) + 2;
+            assert!(pieces.reads <= expected_reads);
+        }
+    }
 }
@@ -0,0 +1,619 @@
+//! A source to source transform which makes Lua chunks smaller for shipping.
+//!
+//! The minifier prints a parsed [`Chunk`] back out without comments or unnecessary whitespace,
+//! and can optionally rename every local variable to a short generated name. Every statement is
+//! printed on the same line as it appeared in the original source, so a minified chunk compiles
+//! to the same bytecode with the same line numbers, and error messages still point at the
+//! original lines. The only observable difference after renaming locals is in debug information,
+//! such as local variable names and the names of local functions.
+//!
+//! Minified sources can be compiled and then stored with [`crate::dump`] like any other chunk.
+
+use std::{collections::HashSet, io::Read, rc::Rc};
+
+use super::{
+    interning::BasicInterner,
+    parser::{
+        parse_chunk, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk, ConstructorField,
+        Expression, FieldSuffix, ForStatement, FunctionDefinition, HeadExpression, LocalAttribute,
+        ParseError, PrimaryExpression, RecordKey, SimpleExpression, Statement, SuffixPart,
+        SuffixedExpression, UnaryOperator,
+    },
+    LineNumber,
+};
+
+/// Parse a chunk from `source` and minify it, see [`minify_chunk`].
+pub fn minify(source: impl Read, rename_locals: bool) -> Result<Vec<u8>, ParseError> {
+    let chunk = parse_chunk(source, BasicInterner::default())?;
+    Ok(minify_chunk(&chunk, rename_locals))
+}
+
+/// Print a parsed chunk as compact Lua source.
+///
+/// If `rename_locals` is true, every local variable, parameter and local function is given a
+/// short generated name. Generated names never collide with each other or with any global name
+/// used in the chunk, so every variable still refers to the same declaration. Fields, methods,
+/// labels, `_ENV` and the implicit `self` parameter of methods keep their original names.
+pub fn minify_chunk<S: AsRef<[u8]>>(chunk: &Chunk<S>, rename_locals: bool) -> Vec<u8> {
+    let mut minifier = Minifier::new(None);
+    minifier.block_statements(&chunk.block);
+
+    if rename_locals {
+        // The first pass finds every global name, which generated names must avoid so that a
+        // renamed local never captures a global access.
+        let mut reserved = minifier.globals;
+        for keyword in KEYWORDS {
+            reserved.insert(keyword.to_vec());
+        }
+        reserved.insert(b"self".to_vec());
+        reserved.insert(b"_ENV".to_vec());
+
+        minifier = Minifier::new(Some(NameGenerator { reserved, next: 0 }));
+        minifier.block_statements(&chunk.block);
+    }
+
+    minifier.output
+}
+
+const KEYWORDS: [&[u8]; 22] = [
+    b"and",
+    b"break",
+    b"do",
+    b"else",
+    b"elseif",
+    b"end",
+    b"false",
+    b"for",
+    b"function",
+    b"goto",
+    b"if",
+    b"in",
+    b"local",
+    b"nil",
+    b"not",
+    b"or",
+    b"repeat",
+    b"return",
+    b"then",
+    b"true",
+    b"until",
+    b"while",
+];
+
+struct NameGenerator {
+    reserved: HashSet<Vec<u8>>,
+    next: usize,
+}
+
+impl NameGenerator {
+    fn generate(&mut self) -> Rc<[u8]> {
+        const FIRST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+        const REST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_0123456789";
+
+        loop {
+            let mut n = self.next;
+            self.next += 1;
+
+            let mut name = vec![FIRST[n % FIRST.len()]];
+            n /= FIRST.len();
+            while n > 0 {
+                n -= 1;
+                name.push(REST[n % REST.len()]);
+                n /= REST.len();
+            }
+
+            if !self.reserved.contains(&name) {
+                return name.into();
+            }
+        }
+    }
+}
+
+struct Minifier<'a> {
+    output: Vec<u8>,
+    line: u64,
+    last: u8,
+    // Every local variable in scope, innermost last, along with the name it is printed as.
+    scope: Vec<(&'a [u8], Rc<[u8]>)>,
+    globals: HashSet<Vec<u8>>,
+    renamer: Option<NameGenerator>,
+}
+
+impl<'a> Minifier<'a> {
+    fn new(renamer: Option<NameGenerator>) -> Self {
+        Minifier {
+            output: Vec::new(),
+            line: 0,
+            last: b'\n',
+            scope: Vec::new(),
+            globals: HashSet::new(),
+            renamer,
+        }
+    }
+
+    fn block<S: AsRef<[u8]>>(&mut self, block: &'a Block<S>) {
+        let scope = self.scope.len();
+        self.block_statements(block);
+        self.scope.truncate(scope);
+        self.line(block.closed_on);
+    }
+
+    // Prints the statements of a block without closing its scope, so that the condition of a
+    // `repeat` loop can still see the locals of its body.
+    fn block_statements<S: AsRef<[u8]>>(&mut self, block: &'a Block<S>) {
+        for statement in &block.statements {
+            self.line(statement.span.start);
+            self.statement(&statement.inner);
+        }
+
+        if let Some(return_statement) = &block.return_statement {
+            self.line(return_statement.span.start);
+            self.token(b"return");
+            self.expression_list(&return_statement.inner.returns);
+        }
+    }
+
+    fn statement<S: AsRef<[u8]>>(&mut self, statement: &'a Statement<S>) {
+        match statement {
+            Statement::If(if_statement) => {
+                self.token(b"if");
+                self.expression(&if_statement.if_part.0);
+                self.token(b"then");
+                self.block(&if_statement.if_part.1);
+                for (condition, block) in &if_statement.else_if_parts {
+                    self.token(b"elseif");
+                    self.expression(condition);
+                    self.token(b"then");
+                    self.block(block);
+                }
+                if let Some(block) = &if_statement.else_part {
+                    self.token(b"else");
+                    self.block(block);
+                }
+                self.token(b"end");
+            }
+            Statement::While(while_statement) => {
+                self.token(b"while");
+                self.expression(&while_statement.condition);
+                self.token(b"do");
+                self.block(&while_statement.block);
+                self.token(b"end");
+            }
+            Statement::Do(block) => {
+                self.token(b"do");
+                self.block(block);
+                self.token(b"end");
+            }
+            Statement::For(ForStatement::Numeric {
+                name,
+                initial,
+                limit,
+                step,
+                body,
+            }) => {
+                self.token(b"for");
+                let name = self.declare(name);
+                self.token(b"=");
+                self.expression(initial);
+                self.token(b",");
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.token(b",");
+                    self.expression(step);
+                }
+                self.token(b"do");
+                let scope = self.scope.len();
+                self.scope.push(name);
+                self.block(body);
+                self.scope.truncate(scope);
+                self.token(b"end");
+            }
+            Statement::For(ForStatement::Generic {
+                names,
+                arguments,
+                body,
+            }) => {
+                self.token(b"for");
+                let names = self.declare_list(names);
+                self.token(b"in");
+                self.expression_list(arguments);
+                self.token(b"do");
+                let scope = self.scope.len();
+                self.scope.extend(names);
+                self.block(body);
+                self.scope.truncate(scope);
+                self.token(b"end");
+            }
+            Statement::Repeat(repeat_statement) => {
+                self.token(b"repeat");
+                let scope = self.scope.len();
+                self.block_statements(&repeat_statement.body);
+                self.line(repeat_statement.body.closed_on);
+                self.token(b"until");
+                self.expression(&repeat_statement.until);
+                self.scope.truncate(scope);
+            }
+            Statement::Function(function_statement) => {
+                self.token(b"function");
+                self.variable(&function_statement.name);
+                for field in &function_statement.fields {
+                    self.token(b".");
+                    self.token(field.as_ref());
+                }
+                if let Some(method) = &function_statement.method {
+                    self.token(b":");
+                    self.token(method.as_ref());
+                }
+                self.function_body(
+                    &function_statement.definition,
+                    function_statement.method.is_some(),
+                );
+            }
+            Statement::LocalFunction(local_function) => {
+                self.token(b"local");
+                self.token(b"function");
+                let name = self.declare(&local_function.name);
+                // The function can refer to itself, so it is in scope within its own body.
+                self.scope.push(name);
+                self.function_body(&local_function.definition, false);
+            }
+            Statement::LocalStatement(local_statement) => {
+                self.token(b"local");
+                let mut names = Vec::new();
+                for (i, name) in local_statement.names.iter().enumerate() {
+                    if i != 0 {
+                        self.token(b",");
+                    }
+                    names.push(self.declare(name));
+                    match local_statement.attributes.get(i).copied().flatten() {
+                        Some(LocalAttribute::Const) => self.tokens(&[b"<", b"const", b">"]),
+                        Some(LocalAttribute::Close) => self.tokens(&[b"<", b"close", b">"]),
+                        None => {}
+                    }
+                }
+                // The values are evaluated before the new locals come into scope.
+                if !local_statement.values.is_empty() {
+                    self.token(b"=");
+                    self.expression_list(&local_statement.values);
+                }
+                self.scope.extend(names);
+            }
+            Statement::Label(label_statement) => {
+                self.token(b"::");
+                self.token(label_statement.name.as_ref());
+                self.token(b"::");
+            }
+            Statement::Break => self.token(b"break"),
+            Statement::Goto(goto_statement) => {
+                self.token(b"goto");
+                self.token(goto_statement.name.as_ref());
+            }
+            Statement::FunctionCall(function_call) => {
+                self.statement_separator(&function_call.head);
+                self.suffixed_expression(&function_call.head);
+                self.call_suffix(&function_call.call);
+            }
+            Statement::Assignment(assignment) => {
+                for (i, target) in assignment.targets.iter().enumerate() {
+                    if i != 0 {
+                        self.token(b",");
+                    }
+                    match target {
+                        AssignmentTarget::Name(name) => self.variable(name),
+                        AssignmentTarget::Field(head, field) => {
+                            if i == 0 {
+                                self.statement_separator(head);
+                            }
+                            self.suffixed_expression(head);
+                            self.field_suffix(field);
+                        }
+                    }
+                }
+                self.token(b"=");
+                self.expression_list(&assignment.values);
+            }
+        }
+    }
+
+    // A statement starting with a parenthesis would otherwise continue the previous statement as
+    // a function call.
+    fn statement_separator<S: AsRef<[u8]>>(&mut self, head: &SuffixedExpression<S>) {
+        if matches!(head.primary, PrimaryExpression::GroupedExpression(_)) {
+            self.token(b";");
+        }
+    }
+
+    fn function_body<S: AsRef<[u8]>>(
+        &mut self,
+        definition: &'a FunctionDefinition<S>,
+        is_method: bool,
+    ) {
+        let scope = self.scope.len();
+        if is_method {
+            self.scope.push((b"self", b"self"[..].into()));
+        }
+        self.token(b"(");
+        let parameters = self.declare_list(&definition.parameters);
+        if definition.has_varargs {
+            if !parameters.is_empty() {
+                self.token(b",");
+            }
+            self.token(b"...");
+        }
+        self.token(b")");
+        self.scope.extend(parameters);
+        self.block(&definition.body);
+        self.scope.truncate(scope);
+        self.token(b"end");
+    }
+
+    fn expression_list<S: AsRef<[u8]>>(&mut self, expressions: &'a [Expression<S>]) {
+        for (i, expression) in expressions.iter().enumerate() {
+            if i != 0 {
+                self.token(b",");
+            }
+            self.expression(expression);
+        }
+    }
+
+    fn expression<S: AsRef<[u8]>>(&mut self, expression: &'a Expression<S>) {
+        match &*expression.head {
+            HeadExpression::Simple(simple) => self.simple_expression(simple),
+            HeadExpression::UnaryOperator(op, operand) => {
+                self.token(match op {
+                    UnaryOperator::Not => b"not",
+                    UnaryOperator::Minus => b"-",
+                    UnaryOperator::BitNot => b"~",
+                    UnaryOperator::Len => b"#",
+                });
+                self.expression(operand);
+            }
+        }
+
+        for (op, operand) in &expression.tail {
+            self.token(binary_operator(*op));
+            self.expression(operand);
+        }
+    }
+
+    fn simple_expression<S: AsRef<[u8]>>(&mut self, simple: &'a SimpleExpression<S>) {
+        match simple {
+            SimpleExpression::Float(f) => self.token(&float_literal(*f)),
+            SimpleExpression::Integer(i) => {
+                // Negative integer literals only come from hex literals which wrap around, and
+                // printing them with a minus sign would turn them into an expression.
+                if *i < 0 {
+                    self.token(format!("{:#x}", *i as u64).as_bytes())
+                } else {
+                    self.token(i.to_string().as_bytes())
+                }
+            }
+            SimpleExpression::String(s) => self.token(&string_literal(s.as_ref())),
+            SimpleExpression::Nil => self.token(b"nil"),
+            SimpleExpression::True => self.token(b"true"),
+            SimpleExpression::False => self.token(b"false"),
+            SimpleExpression::VarArgs => self.token(b"..."),
+            SimpleExpression::TableConstructor(table) => {
+                self.token(b"{");
+                for (i, field) in table.fields.iter().enumerate() {
+                    if i != 0 {
+                        self.token(b",");
+                    }
+                    match field {
+                        ConstructorField::Array(value) => self.expression(value),
+                        ConstructorField::Record(key, value) => {
+                            match key {
+                                RecordKey::Named(name) => self.token(name.as_ref()),
+                                RecordKey::Indexed(key) => {
+                                    self.token(b"[");
+                                    self.expression(key);
+                                    self.token(b"]");
+                                }
+                            }
+                            self.token(b"=");
+                            self.expression(value);
+                        }
+                    }
+                }
+                self.token(b"}");
+            }
+            SimpleExpression::Function(definition) => {
+                self.token(b"function");
+                self.function_body(definition, false);
+            }
+            SimpleExpression::Suffixed(suffixed) => self.suffixed_expression(suffixed),
+        }
+    }
+
+    fn suffixed_expression<S: AsRef<[u8]>>(&mut self, suffixed: &'a SuffixedExpression<S>) {
+        match &suffixed.primary {
+            PrimaryExpression::Name(name) => self.variable(name),
+            PrimaryExpression::GroupedExpression(expression) => {
+                self.token(b"(");
+                self.expression(expression);
+                self.token(b")");
+            }
+        }
+
+        for suffix in &suffixed.suffixes {
+            match suffix {
+                SuffixPart::Field(field) => self.field_suffix(field),
+                SuffixPart::Call(call) => self.call_suffix(call),
+            }
+        }
+    }
+
+    fn field_suffix<S: AsRef<[u8]>>(&mut self, field: &'a FieldSuffix<S>) {
+        match field {
+            FieldSuffix::Named(name) => {
+                self.token(b".");
+                self.token(name.as_ref());
+            }
+            FieldSuffix::Indexed(key) => {
+                self.token(b"[");
+                self.expression(key);
+                self.token(b"]");
+            }
+        }
+    }
+
+    fn call_suffix<S: AsRef<[u8]>>(&mut self, call: &'a CallSuffix<S>) {
+        let args = match call {
+            CallSuffix::Method(name, args) => {
+                self.token(b":");
+                self.token(name.as_ref());
+                args
+            }
+            CallSuffix::Function(args) => args,
+        };
+        self.token(b"(");
+        self.expression_list(args);
+        self.token(b")");
+    }
+
+    // Prints a new local variable name, returning the scope entry for it. The caller decides when
+    // the variable comes into scope.
+    fn declare<S: AsRef<[u8]>>(&mut self, name: &'a S) -> (&'a [u8], Rc<[u8]>) {
+        let name = name.as_ref();
+        let printed: Rc<[u8]> = match &mut self.renamer {
+            Some(renamer) if name != b"_ENV" => renamer.generate(),
+            _ => name.into(),
+        };
+        self.token(&printed);
+        (name, printed)
+    }
+
+    fn declare_list<S: AsRef<[u8]>>(&mut self, names: &'a [S]) -> Vec<(&'a [u8], Rc<[u8]>)> {
+        let mut declared = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            if i != 0 {
+                self.token(b",");
+            }
+            declared.push(self.declare(name));
+        }
+        declared
+    }
+
+    // Prints a reference to a variable, which is either a local in scope or a global.
+    fn variable<S: AsRef<[u8]>>(&mut self, name: &'a S) {
+        let name = name.as_ref();
+        match self.scope.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, printed)) => {
+                let printed = printed.clone();
+                self.token(&printed);
+            }
+            None => {
+                self.globals.insert(name.to_vec());
+                self.token(name);
+            }
+        }
+    }
+
+    // Moves the output to the given source line. Output never moves backwards, statements which
+    // shared a line in the source share it in the output as well.
+    fn line(&mut self, line: LineNumber) {
+        while self.line < line.0 {
+            self.output.push(b'\n');
+            self.last = b'\n';
+            self.line += 1;
+        }
+    }
+
+    fn tokens(&mut self, tokens: &[&[u8]]) {
+        for token in tokens {
+            self.token(token);
+        }
+    }
+
+    fn token(&mut self, token: &[u8]) {
+        if let Some(&first) = token.first() {
+            if needs_space(self.last, first) {
+                self.output.push(b' ');
+            }
+            self.output.extend_from_slice(token);
+            self.last = *token.last().unwrap();
+        }
+    }
+}
+
+// Returns true if two tokens ending and starting with the given bytes would lex differently
+// without a space between them.
+fn needs_space(last: u8, first: u8) -> bool {
+    let is_word = |c: u8| c.is_ascii_alphanumeric() || c == b'_';
+    (is_word(last) && is_word(first))
+        || (last.is_ascii_digit() && first == b'.')
+        || (last == b'.' && (first == b'.' || first.is_ascii_digit()))
+        || matches!(
+            (last, first),
+            (b'=' | b'~' | b'<' | b'>', b'=')
+                | (b'/', b'/')
+                | (b':', b':')
+                | (b'<', b'<')
+                | (b'>', b'>')
+                | (b'-', b'-')
+                | (b'[', b'[' | b'=')
+        )
+}
+
+fn binary_operator(op: BinaryOperator) -> &'static [u8] {
+    match op {
+        BinaryOperator::Add => b"+",
+        BinaryOperator::Sub => b"-",
+        BinaryOperator::Mul => b"*",
+        BinaryOperator::Mod => b"%",
+        BinaryOperator::Pow => b"^",
+        BinaryOperator::Div => b"/",
+        BinaryOperator::IDiv => b"//",
+        BinaryOperator::BitAnd => b"&",
+        BinaryOperator::BitOr => b"|",
+        BinaryOperator::BitXor => b"~",
+        BinaryOperator::ShiftLeft => b"<<",
+        BinaryOperator::ShiftRight => b">>",
+        BinaryOperator::Concat => b"..",
+        BinaryOperator::NotEqual => b"~=",
+        BinaryOperator::Equal => b"==",
+        BinaryOperator::LessThan => b"<",
+        BinaryOperator::LessEqual => b"<=",
+        BinaryOperator::GreaterThan => b">",
+        BinaryOperator::GreaterEqual => b">=",
+        BinaryOperator::And => b"and",
+        BinaryOperator::Or => b"or",
+    }
+}
+
+fn float_literal(f: f64) -> Vec<u8> {
+    if f.is_infinite() {
+        // Float literals are never negative, and the only way to write an infinite one is with an
+        // exponent that overflows.
+        b"1e999".to_vec()
+    } else {
+        // Rust always prints floats with a decimal point or exponent, so they can't be mistaken
+        // for integers, and with enough digits to round trip.
+        format!("{f:?}").into_bytes()
+    }
+}
+
+fn string_literal(s: &[u8]) -> Vec<u8> {
+    let mut literal = Vec::with_capacity(s.len() + 2);
+    literal.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' => literal.extend_from_slice(b"\\\""),
+            b'\\' => literal.extend_from_slice(b"\\\\"),
+            b'\n' => literal.extend_from_slice(b"\\n"),
+            b'\r' => literal.extend_from_slice(b"\\r"),
+            b'\t' => literal.extend_from_slice(b"\\t"),
+            c if c.is_ascii_control() => {
+                // Decimal escapes are always three digits when the next byte is a digit.
+                if s.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                    literal.extend_from_slice(format!("\\{c:03}").as_bytes());
+                } else {
+                    literal.extend_from_slice(format!("\\{c}").as_bytes());
+                }
+            }
+            c => literal.push(c),
+        }
+    }
+    literal.push(b'"');
+    literal
+}
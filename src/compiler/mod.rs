@@ -1,14 +1,19 @@
 mod compiler;
 pub mod interning;
 pub mod lexer;
+pub mod minify;
 mod operators;
 pub mod parser;
 mod register_allocator;
 
 pub use self::{
-    compiler::{compile_chunk, CompileError, CompileErrorKind, CompiledPrototype, FunctionRef},
+    compiler::{
+        compile_chunk, compile_chunk_with_ext_opcodes, compile_chunk_with_lints, CompileError,
+        CompileErrorKind, CompiledPrototype, FunctionRef, Lint, LintKind, LocalVariable,
+    },
     interning::StringInterner,
     lexer::LineNumber,
-    parser::parse_chunk,
-    parser::{ParseError, ParseErrorKind},
+    minify::{minify, minify_chunk},
+    parser::{parse_chunk, parse_chunk_with_options, parse_chunk_with_recovery},
+    parser::{CompilerOptions, ParseError, ParseErrorKind},
 };
@@ -104,6 +104,11 @@ pub fn simple_binop_const_fold<S: AsRef<[u8]>>(
     left: &Constant<S>,
     right: &Constant<S>,
 ) -> Option<Constant<S>> {
+    // Whether strings are coerced to numbers is only known at runtime.
+    if matches!(left, Constant::String(_)) || matches!(right, Constant::String(_)) {
+        return None;
+    }
+
     match simple_binop {
         SimpleBinOp::Add => left.add(right),
         SimpleBinOp::Sub => left.subtract(right),
@@ -189,9 +194,11 @@ pub fn unop_const_fold<S: AsRef<[u8]>>(
     cons: &Constant<S>,
 ) -> Option<Constant<S>> {
     match unop {
+        // Whether strings are coerced to numbers is only known at runtime.
+        UnaryOperator::Minus | UnaryOperator::BitNot if matches!(cons, Constant::String(_)) => None,
         UnaryOperator::Minus => cons.negate(),
         UnaryOperator::Not => Some(cons.not()),
         UnaryOperator::BitNot => cons.bitwise_not(),
-        UnaryOperator::Len => None,
+        UnaryOperator::Len => cons.length(),
     }
 }
@@ -1,3 +1,25 @@
+//! The Lua parser and the abstract syntax tree it produces.
+//!
+//! [`parse_chunk`] turns Lua source into a [`Chunk`] without compiling it, so linters, formatters
+//! and other static analysis tools can be built on the same parser that piccolo itself uses. The
+//! tree is generic over the string type `S` produced by the given [`StringInterner`], which is
+//! used for every name and string literal.
+//!
+//! The tree follows the source closely. Binary operator chains are kept in the order they were
+//! written, parenthesized expressions are kept as [`PrimaryExpression::GroupedExpression`], and
+//! printing every node in order reproduces an equivalent chunk. The only information which is
+//! lost is comments, whitespace, the exact spelling of literals, and whether call arguments were
+//! written as a lone string or table constructor instead of in parentheses.
+//!
+//! Statements and expressions carry a [`Span`] of the source lines they were parsed from.
+//!
+//! ```
+//! # use piccolo::compiler::{interning::BasicInterner, parse_chunk, parser::Statement};
+//! let chunk = parse_chunk(&b"local x = 1\nprint(x)"[..], BasicInterner::default()).unwrap();
+//! assert!(matches!(chunk.block.statements[0].inner, Statement::LocalStatement(_)));
+//! assert_eq!(chunk.block.statements[1].span.start.0, 1);
+//! ```
+
 use std::{io::Read, ops, rc::Rc};
 
 use thiserror::Error;
@@ -11,6 +33,8 @@ use super::{
 pub struct LineAnnotated<T> {
     pub inner: T,
     pub line_number: LineNumber,
+    /// The 0-indexed byte offset of the start of `inner` within its line.
+    pub column: u64,
 }
 
 impl<T> ops::Deref for LineAnnotated<T> {
@@ -28,14 +52,20 @@ impl<T> AsRef<T> for LineAnnotated<T> {
 }
 
 impl<T> LineAnnotated<T> {
-    pub fn new(line_number: LineNumber, inner: T) -> Self {
-        Self { inner, line_number }
+    pub fn new(line_number: LineNumber, column: u64, inner: T) -> Self {
+        Self {
+            inner,
+            line_number,
+            column,
+        }
     }
 
     pub fn map<R>(self, f: impl FnOnce(T) -> R) -> LineAnnotated<R> {
         LineAnnotated {
             inner: f(self.inner),
             line_number: self.line_number,
+            column: self.column,
+            column: self.column,
         }
     }
 
@@ -43,19 +73,60 @@ impl<T> LineAnnotated<T> {
         Ok(LineAnnotated {
             inner: f(self.inner)?,
             line_number: self.line_number,
+            column: self.column,
+            column: self.column,
         })
     }
 }
 
+/// The source lines covered by a syntax node, from the line of its first token to the line of
+/// its last token.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub start: LineNumber,
+    pub end: LineNumber,
+}
+
+/// A syntax node along with its [`Span`].
+#[derive(Debug, Clone)]
+pub struct Spanned<T> {
+    pub inner: T,
+    pub span: Span,
+}
+
+impl<T> ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> AsRef<T> for Spanned<T> {
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> Spanned<T> {
+    pub fn new(span: Span, inner: T) -> Self {
+        Self { inner, span }
+    }
+}
+
+/// A parsed source file.
 #[derive(Debug, Clone)]
 pub struct Chunk<S> {
     pub block: Block<S>,
 }
 
+/// A sequence of statements, optionally ending with a `return`.
 #[derive(Debug, Clone)]
 pub struct Block<S> {
-    pub statements: Vec<LineAnnotated<Statement<S>>>,
-    pub return_statement: Option<LineAnnotated<ReturnStatement<S>>>,
+    pub statements: Vec<Spanned<Statement<S>>>,
+    pub return_statement: Option<Spanned<ReturnStatement<S>>>,
+    /// The line of the token which ended the block, such as its `end` keyword, or the last line of
+    /// the source for the top level block.
     pub closed_on: LineNumber,
 }
 
@@ -66,9 +137,13 @@ pub enum Statement<S> {
     Do(Block<S>),
     For(ForStatement<S>),
     Repeat(RepeatStatement<S>),
+    /// `function a.b:c() end`, which assigns to a global, local or field.
     Function(FunctionStatement<S>),
+    /// `local function f() end`
     LocalFunction(LocalFunctionStatement<S>),
+    /// `local a, b <const> = 1, 2`
     LocalStatement(LocalStatement<S>),
+    /// `::name::`
     Label(LabelStatement<S>),
     Break,
     Goto(GotoStatement<S>),
@@ -96,6 +171,7 @@ pub struct WhileStatement<S> {
 
 #[derive(Debug, Clone)]
 pub enum ForStatement<S> {
+    /// `for name = initial, limit, step do body end`
     Numeric {
         name: S,
         initial: Expression<S>,
@@ -103,6 +179,7 @@ pub enum ForStatement<S> {
         step: Option<Expression<S>>,
         body: Block<S>,
     },
+    /// `for names in arguments do body end`
     Generic {
         names: Vec<S>,
         arguments: Vec<Expression<S>>,
@@ -112,6 +189,7 @@ pub enum ForStatement<S> {
 
 #[derive(Debug, Clone)]
 pub struct RepeatStatement<S> {
+    /// The loop body. Locals declared in the body are in scope in the `until` condition.
     pub body: Block<S>,
     pub until: Expression<S>,
 }
@@ -128,8 +206,10 @@ pub struct GotoStatement<S> {
 
 #[derive(Debug, Clone)]
 pub struct FunctionStatement<S> {
+    /// The variable being assigned to, or indexed if there are fields.
     pub name: S,
     pub fields: Vec<S>,
+    /// The method name after a `:`, in which case the function has an implicit `self` parameter.
     pub method: Option<S>,
     pub definition: FunctionDefinition<S>,
 }
@@ -140,9 +220,20 @@ pub struct LocalFunctionStatement<S> {
     pub definition: FunctionDefinition<S>,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LocalAttribute {
+    /// `<const>`, the variable may not be assigned to after it is declared.
+    Const,
+    /// `<close>`, the variable is constant and its `__close` metamethod is called when it goes out
+    /// of scope.
+    Close,
+}
+
 #[derive(Debug, Clone)]
 pub struct LocalStatement<S> {
     pub names: Vec<S>,
+    /// The attribute of each name in `names`, if it has one.
+    pub attributes: Vec<Option<LocalAttribute>>,
     pub values: Vec<Expression<S>>,
 }
 
@@ -179,21 +270,31 @@ pub enum UnaryOperator {
     Len,
 }
 
+/// An expression made of a head followed by any number of binary operators and their right hand
+/// sides.
+///
+/// Operator precedence is already resolved: an operator in `tail` binds less tightly than
+/// everything inside the right hand side it is paired with, so `1 + 2 * 3` has a tail of
+/// `[(Add, 2 * 3)]`, while `1 * 2 + 3` has a tail of `[(Mul, 2), (Add, 3)]` which is evaluated
+/// left to right.
 #[derive(Debug, Clone)]
 pub struct Expression<S> {
     pub head: Box<HeadExpression<S>>,
     pub tail: Vec<(BinaryOperator, Expression<S>)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub enum HeadExpression<S> {
     Simple(SimpleExpression<S>),
+    /// A unary operator, which applies to the whole expression it is paired with.
     UnaryOperator(UnaryOperator, Expression<S>),
 }
 
 #[derive(Debug, Clone)]
 pub enum SimpleExpression<S> {
     Float(f64),
+    /// An integer literal. This is only negative if a hex literal wrapped around.
     Integer(i64),
     String(S),
     Nil,
@@ -208,18 +309,23 @@ pub enum SimpleExpression<S> {
 #[derive(Debug, Clone)]
 pub enum PrimaryExpression<S> {
     Name(S),
+    /// An expression in parentheses, which truncates multiple results to one.
     GroupedExpression(Expression<S>),
 }
 
 #[derive(Debug, Clone)]
 pub enum FieldSuffix<S> {
+    /// `.name`
     Named(S),
+    /// `[key]`
     Indexed(Expression<S>),
 }
 
 #[derive(Debug, Clone)]
 pub enum CallSuffix<S> {
+    /// `:name(args)`
     Method(S, Vec<Expression<S>>),
+    /// `(args)`
     Function(Vec<Expression<S>>),
 }
 
@@ -229,6 +335,8 @@ pub enum SuffixPart<S> {
     Call(CallSuffix<S>),
 }
 
+/// A name or parenthesized expression followed by any number of field accesses and calls, such
+/// as `a.b[c]:d(e)`.
 #[derive(Debug, Clone)]
 pub struct SuffixedExpression<S> {
     pub primary: PrimaryExpression<S>,
@@ -237,6 +345,7 @@ pub struct SuffixedExpression<S> {
 
 #[derive(Debug, Clone)]
 pub struct FunctionDefinition<S> {
+    /// The named parameters, not including the implicit `self` of methods.
     pub parameters: Vec<S>,
     pub has_varargs: bool,
     pub body: Block<S>,
@@ -244,6 +353,7 @@ pub struct FunctionDefinition<S> {
 
 #[derive(Debug, Clone)]
 pub struct FunctionCallStatement<S> {
+    /// Everything before the final call.
     pub head: SuffixedExpression<S>,
     pub call: CallSuffix<S>,
 }
@@ -256,7 +366,9 @@ pub struct AssignmentStatement<S> {
 
 #[derive(Debug, Clone)]
 pub enum AssignmentTarget<S> {
+    /// A local or global variable.
     Name(S),
+    /// A field of the value of the given expression.
     Field(SuffixedExpression<S>, FieldSuffix<S>),
 }
 
@@ -267,13 +379,16 @@ pub struct TableConstructor<S> {
 
 #[derive(Debug, Clone)]
 pub enum ConstructorField<S> {
+    /// A positional field, assigned to the next array index.
     Array(Expression<S>),
     Record(RecordKey<S>, Expression<S>),
 }
 
 #[derive(Debug, Clone)]
 pub enum RecordKey<S> {
+    /// `name = value`
     Named(S),
+    /// `[key] = value`
     Indexed(Expression<S>),
 }
 
@@ -295,33 +410,98 @@ pub enum ParseErrorKind {
     ExpressionNotStatement,
     #[error("recursion limit reached")]
     RecursionLimit,
+    #[error("unknown attribute {0:?}")]
+    UnknownAttribute(String),
+    #[error("multiple to-be-closed variables in local list")]
+    MultipleToBeClosed,
     #[error(transparent)]
     LexError(#[from] LexError),
 }
 
 #[derive(Debug, Error)]
-#[error("parse error at line {line_number}: {kind}")]
+#[error("parse error at line {line_number}, column {}: {kind}", column + 1)]
 pub struct ParseError {
     pub kind: ParseErrorKind,
     pub line_number: LineNumber,
+    /// The 0-indexed byte offset within the line of the token which caused the error.
+    pub column: u64,
+}
+
+/// Language extensions which the parser can be asked to accept, see [`parse_chunk_with_options`].
+///
+/// Every extension is disabled by default, which parses strictly standard Lua. Extensions are
+/// desugared into standard syntax while parsing, so they need no support from the compiler.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct CompilerOptions {
+    /// Accept `|a, b| a + b` as a short form of `function(a, b) return a + b end`.
+    ///
+    /// The parameter list may end with `...` like any other, and the body is a single expression
+    /// which extends as far to the right as possible, so `|x| x + 1` returns `x + 1`.
+    pub short_lambdas: bool,
 }
 
+/// Parse a complete chunk of Lua source, interning every name and string literal with `interner`.
 pub fn parse_chunk<R, S>(source: R, interner: S) -> Result<Chunk<S::String>, ParseError>
 where
     R: Read,
     S: StringInterner,
 {
-    Parser {
-        lexer: Lexer::new(source, interner),
-        read_buffer: Vec::new(),
-        recursion_guard: Rc::new(()),
+    parse_chunk_with_options(source, interner, CompilerOptions::default())
+}
+
+/// A version of [`parse_chunk`] which accepts the language extensions enabled in `options`.
+pub fn parse_chunk_with_options<R, S>(
+    source: R,
+    interner: S,
+    options: CompilerOptions,
+) -> Result<Chunk<S::String>, ParseError>
+where
+    R: Read,
+    S: StringInterner,
+{
+    Parser::new(source, interner, options, false).parse_chunk()
+}
+
+/// A version of [`parse_chunk`] which recovers from syntax errors to report as many of them as
+/// possible in one pass, which is useful for editors and REPLs.
+///
+/// When a statement fails to parse, the error is recorded and tokens are skipped until one which
+/// can start or end a statement, such as `local` or `end`, and parsing continues from there.
+/// Recovery is best-effort, so an error can cause more errors to be reported after it. Errors
+/// from the lexer and hitting the recursion limit stop parsing immediately.
+///
+/// Returns every error found in source order, or the chunk if there were none.
+pub fn parse_chunk_with_recovery<R, S>(
+    source: R,
+    interner: S,
+) -> Result<Chunk<S::String>, Vec<ParseError>>
+where
+    R: Read,
+    S: StringInterner,
+{
+    let mut parser = Parser::new(source, interner, CompilerOptions::default(), true);
+    let result = parser.parse_chunk();
+    let mut errors = parser.errors.unwrap_or_default();
+    match result {
+        Ok(chunk) if errors.is_empty() => Ok(chunk),
+        Ok(_) => Err(errors),
+        Err(err) => {
+            errors.push(err);
+            Err(errors)
+        }
     }
-    .parse_chunk()
 }
 
 struct Parser<R, S: StringInterner> {
     lexer: Lexer<R, S>,
+    options: CompilerOptions,
     read_buffer: Vec<LineAnnotated<Token<S::String>>>,
+    // The line of the most recently consumed token, which is where the node being parsed ends.
+    last_line: LineNumber,
+    // The number of tokens consumed so far.
+    consumed: usize,
+    // Errors which have been recovered from, if recovery is enabled.
+    errors: Option<Vec<ParseError>>,
     recursion_guard: Rc<()>,
 }
 
@@ -329,16 +509,39 @@ impl<R, S: StringInterner> Parser<R, S>
 where
     R: Read,
 {
+    fn new(source: R, interner: S, options: CompilerOptions, recover: bool) -> Self {
+        Parser {
+            lexer: Lexer::new(source, interner),
+            options,
+            read_buffer: Vec::new(),
+            last_line: LineNumber(0),
+            consumed: 0,
+            errors: recover.then(Vec::new),
+            recursion_guard: Rc::new(()),
+        }
+    }
+
     fn parse_chunk(&mut self) -> Result<Chunk<S::String>, ParseError> {
-        let block = self.parse_block()?;
-        if !self.look_ahead(0)?.is_none() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream { expected: None },
-                line_number: self.lexer.line_number(),
-            })
-        } else {
-            Ok(Chunk { block })
+        let mut block = self.parse_block()?;
+        // A block only stops early on a token which closes a block, such as a stray `end`.
+        while let Some(next) = self.look_ahead(0)? {
+            let error = ParseError {
+                kind: ParseErrorKind::Unexpected {
+                    unexpected: format!("{:?}", next.inner),
+                    expected: "statement".to_owned(),
+                },
+                line_number: next.line_number,
+                column: next.column,
+            };
+            let consumed = self.consumed;
+            self.recover(error, consumed)?;
+
+            let rest = self.parse_block()?;
+            block.statements.extend(rest.statements);
+            block.return_statement = rest.return_statement.or(block.return_statement);
+            block.closed_on = rest.closed_on;
         }
+        Ok(Chunk { block })
     }
 
     fn parse_block(&mut self) -> Result<Block<S::String>, ParseError> {
@@ -356,17 +559,25 @@ where
                     self.take_next()?;
                 }
                 Token::Return => {
-                    return_statement = Some(LineAnnotated::new(
-                        next.line_number,
-                        self.parse_return_statement()?,
-                    ));
-                    break;
+                    let start = next.line_number;
+                    let consumed = self.consumed;
+                    match self.parse_return_statement() {
+                        Ok(statement) => {
+                            return_statement = Some(Spanned::new(self.span_from(start), statement));
+                            break;
+                        }
+                        Err(err) => self.recover(err, consumed)?,
+                    }
                 }
                 _ => {
-                    statements.push(LineAnnotated::new(
-                        next.line_number,
-                        self.parse_statement()?,
-                    ));
+                    let start = next.line_number;
+                    let consumed = self.consumed;
+                    match self.parse_statement() {
+                        Ok(statement) => {
+                            statements.push(Spanned::new(self.span_from(start), statement))
+                        }
+                        Err(err) => self.recover(err, consumed)?,
+                    }
                 }
             }
         }
@@ -535,6 +746,7 @@ where
                     expected: "'=' or 'in'".to_owned(),
                 },
                 line_number: next.line_number,
+                column: next.column,
             }),
         }
     }
@@ -592,10 +804,26 @@ where
     fn parse_local_statement(&mut self) -> Result<LocalStatement<S::String>, ParseError> {
         self.expect_next(Token::Local)?;
         let mut names = Vec::new();
+        let mut attributes = Vec::new();
         names.push(self.expect_name()?.inner);
+        attributes.push(self.parse_local_attribute()?);
         while self.check_ahead(0, Token::Comma)? {
             self.take_next()?;
             names.push(self.expect_name()?.inner);
+            attributes.push(self.parse_local_attribute()?);
+        }
+
+        if attributes
+            .iter()
+            .filter(|&&a| a == Some(LocalAttribute::Close))
+            .count()
+            > 1
+        {
+            return Err(ParseError {
+                kind: ParseErrorKind::MultipleToBeClosed,
+                line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
+            });
         }
 
         let values = if self.check_ahead(0, Token::Assign)? {
@@ -605,7 +833,36 @@ where
             Vec::new()
         };
 
-        Ok(LocalStatement { names, values })
+        Ok(LocalStatement {
+            names,
+            attributes,
+            values,
+        })
+    }
+
+    fn parse_local_attribute(&mut self) -> Result<Option<LocalAttribute>, ParseError> {
+        if !self.check_ahead(0, Token::LessThan)? {
+            return Ok(None);
+        }
+        self.take_next()?;
+
+        let name = self.expect_name()?;
+        let attribute = match name.inner.as_ref() {
+            b"const" => LocalAttribute::Const,
+            b"close" => LocalAttribute::Close,
+            other => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::UnknownAttribute(
+                        String::from_utf8_lossy(other).into_owned(),
+                    ),
+                    line_number: name.line_number,
+                    column: name.column,
+                });
+            }
+        };
+
+        self.expect_next(Token::GreaterThan)?;
+        Ok(Some(attribute))
     }
 
     fn parse_label_statement(&mut self) -> Result<LabelStatement<S::String>, ParseError> {
@@ -623,10 +880,10 @@ where
 
     fn parse_expression_statement(&mut self) -> Result<Statement<S::String>, ParseError> {
         let mut suffixed_expression = self.parse_suffixed_expression()?;
-        let line_number = self
+        let (line_number, column) = self
             .look_ahead(0)?
-            .map(|t| t.line_number)
-            .unwrap_or_else(|| self.lexer.line_number());
+            .map(|t| (t.line_number, t.column))
+            .unwrap_or_else(|| (self.lexer.line_number(), self.lexer.column()));
         if self.check_ahead(0, Token::Assign)? || self.check_ahead(0, Token::Comma)? {
             let mut targets = Vec::new();
             loop {
@@ -639,6 +896,7 @@ where
                             return Err(ParseError {
                                 kind: ParseErrorKind::AssignToExpression,
                                 line_number,
+                                column,
                             });
                         }
                     }
@@ -649,6 +907,7 @@ where
                             return Err(ParseError {
                                 kind: ParseErrorKind::AssignToExpression,
                                 line_number,
+                                column,
                             })
                         }
                     }
@@ -681,12 +940,14 @@ where
                 SuffixPart::Field(_) => Err(ParseError {
                     kind: ParseErrorKind::ExpressionNotStatement,
                     line_number,
+                    column,
                 }),
             }
         } else {
             Err(ParseError {
                 kind: ParseErrorKind::ExpressionNotStatement,
                 line_number,
+                column,
             })
         }
     }
@@ -711,6 +972,7 @@ where
     ) -> Result<Expression<S::String>, ParseError> {
         let _recursion_guard = self.recursion_guard()?;
 
+        let start = self.get_next()?.line_number;
         let head = if let Some(unary_op) = get_unary_operator(self.get_next()?) {
             self.take_next()?;
             HeadExpression::UnaryOperator(unary_op, self.parse_sub_expression(UNARY_PRIORITY)?)
@@ -733,6 +995,7 @@ where
         Ok(Expression {
             head: Box::new(head),
             tail,
+            span: self.span_from(start),
         })
     }
 
@@ -768,6 +1031,9 @@ where
                 self.take_next()?;
                 SimpleExpression::Function(self.parse_function_definition()?)
             }
+            Token::BitOr if self.options.short_lambdas => {
+                SimpleExpression::Function(self.parse_short_lambda()?)
+            }
             _ => SimpleExpression::Suffixed(self.parse_suffixed_expression()?),
         })
     }
@@ -787,6 +1053,7 @@ where
                     expected: "grouped expression or name".to_owned(),
                 },
                 line_number: next.line_number,
+                column: next.column,
             }),
         }
     }
@@ -810,6 +1077,7 @@ where
                     expected: "field or suffix".to_owned(),
                 },
                 line_number: next.line_number,
+                column: next.column,
             }),
         }
     }
@@ -835,18 +1103,30 @@ where
                 self.expect_next(Token::RightParen)?;
                 args
             }
-            Token::LeftBrace => vec![Expression {
-                head: Box::new(HeadExpression::Simple(SimpleExpression::TableConstructor(
-                    self.parse_table_constructor()?,
-                ))),
-                tail: vec![],
-            }],
-            Token::String(_) => vec![Expression {
-                head: Box::new(HeadExpression::Simple(SimpleExpression::String(
-                    self.expect_string()?.inner,
-                ))),
-                tail: vec![],
-            }],
+            Token::LeftBrace => {
+                let start = next.line_number;
+                let table = self.parse_table_constructor()?;
+                vec![Expression {
+                    head: Box::new(HeadExpression::Simple(SimpleExpression::TableConstructor(
+                        table,
+                    ))),
+                    tail: vec![],
+                    span: self.span_from(start),
+                }]
+            }
+            Token::String(_) => {
+                let string = self.expect_string()?;
+                vec![Expression {
+                    head: Box::new(HeadExpression::Simple(SimpleExpression::String(
+                        string.inner,
+                    ))),
+                    tail: vec![],
+                    span: Span {
+                        start: string.line_number,
+                        end: string.line_number,
+                    },
+                }]
+            }
             token => {
                 return Err(ParseError {
                     kind: ParseErrorKind::Unexpected {
@@ -854,6 +1134,7 @@ where
                         expected: "function arguments".to_owned(),
                     },
                     line_number: next.line_number,
+                    column: next.column,
                 });
             }
         };
@@ -878,6 +1159,7 @@ where
                     expected: "expression suffix".to_owned(),
                 },
                 line_number: next.line_number,
+                column: next.column,
             }),
         }
     }
@@ -904,10 +1186,49 @@ where
 
     fn parse_function_definition(&mut self) -> Result<FunctionDefinition<S::String>, ParseError> {
         self.expect_next(Token::LeftParen)?;
+        let (parameters, has_varargs) = self.parse_parameters(Token::RightParen)?;
+        self.expect_next(Token::RightParen)?;
+
+        let body = self.parse_block()?;
+        self.expect_next(Token::End)?;
+
+        Ok(FunctionDefinition {
+            parameters,
+            has_varargs,
+            body,
+        })
+    }
+
+    // Parses `|a, b| expr` into the equivalent of `function(a, b) return expr end`.
+    fn parse_short_lambda(&mut self) -> Result<FunctionDefinition<S::String>, ParseError> {
+        self.expect_next(Token::BitOr)?;
+        let (parameters, has_varargs) = self.parse_parameters(Token::BitOr)?;
+        self.expect_next(Token::BitOr)?;
+
+        let start = self.get_next()?.line_number;
+        let returns = vec![self.parse_expression()?];
+        let span = self.span_from(start);
+
+        Ok(FunctionDefinition {
+            parameters,
+            has_varargs,
+            body: Block {
+                statements: Vec::new(),
+                return_statement: Some(Spanned::new(span, ReturnStatement { returns })),
+                closed_on: span.end,
+            },
+        })
+    }
 
+    // Parses a possibly empty list of parameter names, optionally ending with `...`, up to but not
+    // including the token `close`.
+    fn parse_parameters(
+        &mut self,
+        close: Token<S::String>,
+    ) -> Result<(Vec<S::String>, bool), ParseError> {
         let mut parameters = Vec::new();
         let mut has_varargs = false;
-        if !self.check_ahead(0, Token::RightParen)? {
+        if !self.check_ahead(0, close)? {
             loop {
                 let next = self.take_next()?;
                 match next.inner {
@@ -923,6 +1244,7 @@ where
                                 expected: "parameter name or '...'".to_owned(),
                             },
                             line_number: next.line_number,
+                            column: next.column,
                         });
                     }
                 }
@@ -933,16 +1255,7 @@ where
                 }
             }
         }
-        self.expect_next(Token::RightParen)?;
-
-        let body = self.parse_block()?;
-        self.expect_next(Token::End)?;
-
-        Ok(FunctionDefinition {
-            parameters,
-            has_varargs,
-            body,
-        })
+        Ok((parameters, has_varargs))
     }
 
     fn parse_table_constructor(&mut self) -> Result<TableConstructor<S::String>, ParseError> {
@@ -997,6 +1310,7 @@ where
             Err(ParseError {
                 kind: ParseErrorKind::RecursionLimit,
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         }
     }
@@ -1010,6 +1324,7 @@ where
             Err(ParseError {
                 kind: ParseErrorKind::EndOfStream { expected: None },
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         }
     }
@@ -1023,9 +1338,10 @@ where
                     expected: Some(format!("{:?}", token)),
                 },
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         } else {
-            let next_token = self.read_buffer.remove(0);
+            let next_token = self.consume();
             if *next_token == token {
                 Ok(next_token.line_number)
             } else {
@@ -1035,6 +1351,7 @@ where
                         expected: format!("{:?}", token),
                     },
                     line_number: next_token.line_number,
+                    column: next_token.column,
                 })
             }
         }
@@ -1049,16 +1366,20 @@ where
                     expected: Some("name".to_owned()),
                 },
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         } else {
-            self.read_buffer.remove(0).try_map(|t| match t {
+            let next = self.consume();
+            let (line_number, column) = (next.line_number, next.column);
+            next.try_map(|t| match t {
                 Token::Name(name) => Ok(name),
                 token => Err(ParseError {
                     kind: ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", token),
                         expected: "name".to_owned(),
                     },
-                    line_number: self.lexer.line_number(),
+                    line_number,
+                    column,
                 }),
             })
         }
@@ -1073,16 +1394,20 @@ where
                     expected: Some("string".to_owned()),
                 },
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         } else {
-            self.read_buffer.remove(0).try_map(|t| match t {
+            let next = self.consume();
+            let (line_number, column) = (next.line_number, next.column);
+            next.try_map(|t| match t {
                 Token::String(string) => Ok(string),
                 token => Err(ParseError {
                     kind: ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", token),
                         expected: "string".to_owned(),
                     },
-                    line_number: self.lexer.line_number(),
+                    line_number,
+                    column,
                 }),
             })
         }
@@ -1095,9 +1420,71 @@ where
             Err(ParseError {
                 kind: ParseErrorKind::EndOfStream { expected: None },
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })
         } else {
-            Ok(self.read_buffer.remove(0))
+            Ok(self.consume())
+        }
+    }
+
+    // Removes the next token from the read buffer, which must not be empty.
+    fn consume(&mut self) -> LineAnnotated<Token<S::String>> {
+        let token = self.read_buffer.remove(0);
+        self.last_line = token.line_number;
+        self.consumed += 1;
+        token
+    }
+
+    // If recovery is enabled, records the error and skips ahead to the next token which can start
+    // or end a statement, otherwise returns the error. `consumed` is the number of tokens which
+    // had been consumed before the failed statement started, so that at least one token is always
+    // skipped.
+    fn recover(&mut self, error: ParseError, consumed: usize) -> Result<(), ParseError> {
+        let Some(errors) = &mut self.errors else {
+            return Err(error);
+        };
+        if matches!(
+            error.kind,
+            ParseErrorKind::LexError(_) | ParseErrorKind::RecursionLimit
+        ) {
+            return Err(error);
+        }
+        errors.push(error);
+
+        if self.consumed == consumed && !self.read_buffer.is_empty() {
+            self.consume();
+        }
+        while let Some(next) = self.look_ahead(0)? {
+            match next.inner {
+                Token::Local
+                | Token::Function
+                | Token::If
+                | Token::While
+                | Token::For
+                | Token::Repeat
+                | Token::Return
+                | Token::Do
+                | Token::End
+                | Token::Else
+                | Token::ElseIf
+                | Token::Until
+                | Token::DoubleColon
+                | Token::Goto
+                | Token::Break
+                | Token::SemiColon => break,
+                _ => {
+                    self.consume();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The span from the given line to the end of the most recently consumed token.
+    fn span_from(&self, start: LineNumber) -> Span {
+        Span {
+            start,
+            end: self.last_line,
         }
     }
 
@@ -1128,14 +1515,17 @@ where
             self.lexer.skip_whitespace().map_err(|e| ParseError {
                 kind: ParseErrorKind::LexError(e),
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })?;
             let line_number = self.lexer.line_number();
+            let column = self.lexer.column();
             if let Some(token) = self.lexer.read_token().map_err(|e| ParseError {
                 kind: ParseErrorKind::LexError(e),
                 line_number: self.lexer.line_number(),
+                column: self.lexer.column(),
             })? {
                 self.read_buffer
-                    .push(LineAnnotated::new(line_number, token));
+                    .push(LineAnnotated::new(line_number, column, token));
             } else {
                 break;
             }
@@ -83,7 +83,7 @@ impl RegisterAllocator {
             None
         } else if size as u16 <= 256 - self.stack_top {
             let rbegin = self.stack_top as u8;
-            for i in rbegin..rbegin + size {
+            for i in self.stack_top..self.stack_top + size as u16 {
                 self.registers[i as usize] = true;
             }
             if self.first_free == self.stack_top {
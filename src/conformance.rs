@@ -0,0 +1,95 @@
+//! The Lua semantics test corpus used by piccolo's own test suite, enabled with the
+//! `conformance` feature.
+//!
+//! Each script exercises one area of the language or stdlib, such as operator coercions,
+//! metamethod dispatch or error messages, and succeeds only if it runs to completion without
+//! raising an error. Every check is a plain `assert`, so the scripts can be used to validate forks
+//! of piccolo or other Lua implementations against the same expectations.
+
+use crate::{Closure, Executor, Lua, StaticError};
+
+/// A single script from the conformance corpus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Script {
+    /// The file name of the script, which is also used as its chunk name by [`Script::run`].
+    pub name: &'static str,
+    pub source: &'static str,
+}
+
+impl Script {
+    /// Find a script by its file name.
+    pub fn find(name: &str) -> Option<Script> {
+        SCRIPTS.iter().copied().find(|s| s.name == name)
+    }
+
+    /// Run the script in the given `Lua` instance, which should have the full stdlib loaded.
+    ///
+    /// String coercion is enabled in the instance first, since the corpus checks the arithmetic
+    /// coercions of PUC-Rio Lua.
+    pub fn run(self, lua: &mut Lua) -> Result<(), StaticError> {
+        let executor = lua.try_enter(|ctx| {
+            ctx.set_string_coercion(true);
+            let closure = Closure::load(ctx, Some(self.name), self.source.as_bytes())?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        lua.execute::<()>(&executor)
+    }
+}
+
+macro_rules! script {
+    ($name:literal) => {
+        Script {
+            name: $name,
+            source: include_str!(concat!("../tests/scripts/", $name)),
+        }
+    };
+}
+
+/// Every script in the conformance corpus, sorted by name.
+pub const SCRIPTS: &[Script] = &[
+    script!("arithmetic.lua"),
+    script!("basic.lua"),
+    script!("bit.lua"),
+    script!("close.lua"),
+    script!("coroutine.lua"),
+    script!("environment.lua"),
+    script!("error.lua"),
+    script!("expression_order.lua"),
+    script!("for.lua"),
+    script!("function.lua"),
+    script!("function_assign.lua"),
+    script!("gc.lua"),
+    script!("goto.lua"),
+    script!("if.lua"),
+    script!("jumps_close_upvalues.lua"),
+    script!("load.lua"),
+    script!("math.lua"),
+    script!("metacall.lua"),
+    script!("metaeq.lua"),
+    script!("metaindex.lua"),
+    script!("metaop_add.lua"),
+    script!("metaops.lua"),
+    script!("metatable.lua"),
+    script!("methods.lua"),
+    script!("multi.lua"),
+    script!("next.lua"),
+    script!("numerals.lua"),
+    script!("operators.lua"),
+    script!("pairs.lua"),
+    script!("pcall.lua"),
+    script!("raw.lua"),
+    script!("recursion.lua"),
+    script!("repeat.lua"),
+    script!("scope.lua"),
+    script!("select.lua"),
+    script!("string.lua"),
+    script!("table.lua"),
+    script!("tailcall.lua"),
+    script!("tonumber.lua"),
+    script!("type.lua"),
+    script!("unpack.lua"),
+    script!("upvalues.lua"),
+    script!("utf8.lua"),
+    script!("varargs.lua"),
+    script!("while.lua"),
+];
@@ -81,13 +81,7 @@ impl<S: AsRef<[u8]>> Constant<S> {
     pub fn to_integer(&self) -> Option<i64> {
         match self.to_numeric() {
             Some(Self::Integer(a)) => Some(a),
-            Some(Self::Number(a)) => {
-                if ((a as i64) as f64) == a {
-                    Some(a as i64)
-                } else {
-                    None
-                }
-            }
+            Some(Self::Number(a)) => f64_to_i64(a),
             _ => None,
         }
     }
@@ -151,7 +145,10 @@ impl<S: AsRef<[u8]>> Constant<S> {
                 if b == 0 {
                     None
                 } else {
-                    Some(Self::Integer(((a % b) + b) % b))
+                    // The result has the sign of the divisor. `wrapping_rem` avoids panicking on
+                    // `i64::MIN % -1`.
+                    let r = a.wrapping_rem(b);
+                    Some(Self::Integer(if r != 0 && (r ^ b) < 0 { r + b } else { r }))
                 }
             }
             (a, b) => {
@@ -174,6 +171,15 @@ impl<S: AsRef<[u8]>> Constant<S> {
         }
     }
 
+    /// Only string constants have a length, the length of any other value is either an error or
+    /// depends on its metatable.
+    pub fn length(&self) -> Option<Self> {
+        match self {
+            Self::String(s) => Some(Self::Integer(s.as_ref().len() as i64)),
+            _ => None,
+        }
+    }
+
     // Bitwise operators
 
     pub fn bitwise_not(&self) -> Option<Self> {
@@ -319,3 +325,19 @@ impl<S: AsRef<[u8]>> Hash for IdenticalConstant<S> {
         }
     }
 }
+
+/// Returns the integer with exactly the same value as the given float, if one exists.
+///
+/// This is the conversion used everywhere a float must be interpreted as an integer, such as when
+/// normalizing table keys (so that `t[1.0]` and `t[1]` are the same entry).
+pub(crate) fn f64_to_i64(n: f64) -> Option<i64> {
+    // `as` casts saturate, and `i64::MAX as f64` rounds up to 2^63, so floats outside of the range
+    // of an i64 must be rejected before casting.
+    if n >= i64::MIN as f64 && n < -(i64::MIN as f64) {
+        let i = n as i64;
+        if i as f64 == n {
+            return Some(i);
+        }
+    }
+    None
+}
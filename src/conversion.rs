@@ -1,4 +1,13 @@
-use std::{array, iter, ops, string::String as StdString, vec};
+use std::{
+    array,
+    borrow::Cow,
+    ffi::OsStr,
+    iter, ops,
+    path::{Path, PathBuf},
+    string::String as StdString,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec,
+};
 
 use crate::{
     Callback, Closure, Context, Function, String, Table, Thread, TypeError, UserData, Value,
@@ -46,6 +55,13 @@ macro_rules! impl_int_into {
 }
 impl_int_into!(i8, u8, i16, u16, i32, u32);
 
+// `isize` is never wider than 64 bits.
+impl<'gc> IntoValue<'gc> for isize {
+    fn into_value(self, _: Context<'gc>) -> Value<'gc> {
+        Value::Integer(self as i64)
+    }
+}
+
 impl<'gc> IntoValue<'gc> for f32 {
     fn into_value(self, _: Context<'gc>) -> Value<'gc> {
         Value::Number(self.into())
@@ -72,8 +88,10 @@ impl_copy_into!(
     u8,
     u16,
     u32,
+    isize,
     f32,
     f64,
+    char,
     String<'gc>,
     Table<'gc>,
     Function<'gc>,
@@ -96,6 +114,62 @@ impl<'gc> IntoValue<'gc> for StdString {
     }
 }
 
+impl<'a, 'gc> IntoValue<'gc> for Cow<'a, str> {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        Value::String(ctx.intern(self.as_bytes()))
+    }
+}
+
+impl<'a, 'gc> IntoValue<'gc> for &'a StdString {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        Value::String(ctx.intern(self.as_bytes()))
+    }
+}
+
+/// A `char` becomes a string containing its UTF-8 encoding.
+impl<'gc> IntoValue<'gc> for char {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        Value::String(ctx.intern(self.encode_utf8(&mut [0; 4]).as_bytes()))
+    }
+}
+
+/// Paths become strings of their platform encoded bytes, which are not necessarily UTF-8.
+impl<'a, 'gc> IntoValue<'gc> for &'a Path {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        Value::String(ctx.intern(self.as_os_str().as_encoded_bytes()))
+    }
+}
+
+impl<'gc> IntoValue<'gc> for PathBuf {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        self.as_path().into_value(ctx)
+    }
+}
+
+impl<'a, 'gc> IntoValue<'gc> for &'a PathBuf {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        self.as_path().into_value(ctx)
+    }
+}
+
+/// A `Duration` becomes a number of seconds.
+impl<'gc> IntoValue<'gc> for Duration {
+    fn into_value(self, _: Context<'gc>) -> Value<'gc> {
+        Value::Number(self.as_secs_f64())
+    }
+}
+
+/// A `SystemTime` becomes the number of seconds since the Unix epoch, like the result of
+/// `os.time`, which is negative for times before the epoch.
+impl<'gc> IntoValue<'gc> for SystemTime {
+    fn into_value(self, _: Context<'gc>) -> Value<'gc> {
+        Value::Number(match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        })
+    }
+}
+
 impl<'gc, T: IntoValue<'gc>> IntoValue<'gc> for Option<T> {
     fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
         match self {
@@ -237,7 +311,7 @@ macro_rules! impl_int_from {
         )*
     };
 }
-impl_int_from!(i64, u64, i32, u32, i16, u16, i8, u8);
+impl_int_from!(i64, u64, i32, u32, i16, u16, i8, u8, isize, usize);
 
 macro_rules! impl_float_from {
     ($($f:ty),* $(,)?) => {
@@ -344,6 +418,77 @@ impl<'gc> FromValue<'gc> for StdString {
     }
 }
 
+/// Accepts strings that contain exactly one UTF-8 encoded `char`.
+impl<'gc> FromValue<'gc> for char {
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        let s = String::from_value(ctx, value)?;
+        let mut chars = s.to_str().ok().into_iter().flat_map(|s| s.chars());
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(TypeError {
+                expected: "char",
+                found: "string which is not a single character",
+            }),
+        }
+    }
+}
+
+/// On Unix platforms any string is accepted as a path, elsewhere the string must be valid UTF-8.
+impl<'gc> FromValue<'gc> for PathBuf {
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        let s = String::from_value(ctx, value)?;
+
+        #[cfg(unix)]
+        let path = {
+            use std::os::unix::ffi::OsStrExt;
+            OsStr::from_bytes(s.as_bytes())
+        };
+
+        #[cfg(not(unix))]
+        let path = OsStr::new(s.to_str().map_err(|_| TypeError {
+            expected: "UTF-8 path",
+            found: "non-UTF-8 string",
+        })?);
+
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// Accepts a non-negative number of seconds.
+impl<'gc> FromValue<'gc> for Duration {
+    fn from_value(_: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        let secs = value.to_number().ok_or(TypeError {
+            expected: "duration",
+            found: value.type_name(),
+        })?;
+        Duration::try_from_secs_f64(secs).map_err(|_| TypeError {
+            expected: "duration",
+            found: "negative or out of range number",
+        })
+    }
+}
+
+/// Accepts a number of seconds since the Unix epoch, see the `IntoValue` implementation.
+impl<'gc> FromValue<'gc> for SystemTime {
+    fn from_value(_: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        let secs = value.to_number().ok_or(TypeError {
+            expected: "time",
+            found: value.type_name(),
+        })?;
+        let time = Duration::try_from_secs_f64(secs.abs()).ok().and_then(|d| {
+            if secs >= 0.0 {
+                UNIX_EPOCH.checked_add(d)
+            } else {
+                UNIX_EPOCH.checked_sub(d)
+            }
+        });
+        time.ok_or(TypeError {
+            expected: "time",
+            found: "out of range number",
+        })
+    }
+}
+
 pub trait IntoMultiValue<'gc> {
     type Iter: Iterator<Item = Value<'gc>>;
 
@@ -486,6 +631,93 @@ impl<'gc, I: FromValue<'gc>, const N: usize> FromMultiValue<'gc> for Variadic<[I
     }
 }
 
+/// A result which follows the common Lua convention for fallible functions.
+///
+/// When converted into multiple values, `Ok(v)` becomes the values of `v`, and `Err(e)` becomes
+/// the two values `nil, e`.
+///
+/// When converted from multiple values, a first value of `nil` followed by a non-nil value is
+/// interpreted as `Err`, anything else is interpreted as `Ok`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct LuaResult<T, E>(pub Result<T, E>);
+
+impl<T, E> LuaResult<T, E> {
+    pub fn ok(value: T) -> Self {
+        Self(Ok(value))
+    }
+
+    pub fn err(error: E) -> Self {
+        Self(Err(error))
+    }
+
+    pub fn into_result(self) -> Result<T, E> {
+        self.0
+    }
+}
+
+impl<T, E> From<Result<T, E>> for LuaResult<T, E> {
+    fn from(result: Result<T, E>) -> Self {
+        Self(result)
+    }
+}
+
+impl<T, E> From<LuaResult<T, E>> for Result<T, E> {
+    fn from(result: LuaResult<T, E>) -> Self {
+        result.0
+    }
+}
+
+pub enum LuaResultIter<'gc, I> {
+    Ok(I),
+    Err(array::IntoIter<Value<'gc>, 2>),
+}
+
+impl<'gc, I: Iterator<Item = Value<'gc>>> Iterator for LuaResultIter<'gc, I> {
+    type Item = Value<'gc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LuaResultIter::Ok(iter) => iter.next(),
+            LuaResultIter::Err(iter) => iter.next(),
+        }
+    }
+}
+
+impl<'gc, T, E> IntoMultiValue<'gc> for LuaResult<T, E>
+where
+    T: IntoMultiValue<'gc>,
+    E: IntoValue<'gc>,
+{
+    type Iter = LuaResultIter<'gc, T::Iter>;
+
+    fn into_multi_value(self, ctx: Context<'gc>) -> Self::Iter {
+        match self.0 {
+            Ok(v) => LuaResultIter::Ok(v.into_multi_value(ctx)),
+            Err(e) => LuaResultIter::Err([Value::Nil, e.into_value(ctx)].into_iter()),
+        }
+    }
+}
+
+impl<'gc, T, E> FromMultiValue<'gc> for LuaResult<T, E>
+where
+    T: FromMultiValue<'gc>,
+    E: FromValue<'gc>,
+{
+    fn from_multi_value(
+        ctx: Context<'gc>,
+        mut values: impl Iterator<Item = Value<'gc>>,
+    ) -> Result<Self, TypeError> {
+        let first = values.next().unwrap_or_default();
+        let second = values.next().unwrap_or_default();
+        if first.is_nil() && !second.is_nil() {
+            Ok(Self(Err(E::from_value(ctx, second)?)))
+        } else {
+            let values = [first, second].into_iter().chain(values);
+            Ok(Self(Ok(T::from_multi_value(ctx, values)?)))
+        }
+    }
+}
+
 macro_rules! impl_tuple {
     ($($name:ident),* $(,)?) => (
         impl<'gc, $($name,)*> IntoMultiValue<'gc> for ($($name,)*)
@@ -0,0 +1,554 @@
+//! The binary chunk format written by `string.dump` and read back by `load`.
+//!
+//! A binary chunk holds a serialized [`FunctionPrototype`] and everything nested within it. The
+//! format is specific to piccolo and may change between versions, in which case older chunks fail
+//! to load with [`UndumpError::BadVersion`]. Source maps are not included.
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{CompiledPrototype, FunctionRef, LineNumber, LocalVariable},
+    opcode::{OpCode, Operation, RCIndex},
+    types::{
+        ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor,
+        UpValueIndex, VarCount,
+    },
+    Constant, Context, FunctionPrototype, String,
+};
+
+/// Every binary chunk starts with these bytes. Like PUC-Rio Lua binary chunks the first byte is
+/// ESC, which can never start a text chunk.
+pub const SIGNATURE: &[u8] = b"\x1bPiccolo";
+
+const FORMAT_VERSION: u8 = 2;
+
+// Chunks nesting prototypes deeper than this are rejected, so that loading a hostile chunk cannot
+// overflow the stack.
+const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum UndumpError {
+    #[error("not a piccolo binary chunk")]
+    BadSignature,
+    #[error("binary chunk format version {0} is not supported")]
+    BadVersion(u8),
+    #[error("truncated binary chunk")]
+    Truncated,
+    #[error("malformed binary chunk")]
+    Malformed,
+}
+
+/// Serialize a prototype and every prototype nested within it.
+///
+/// If `strip` is true, the chunk name, function names, line information and the names of locals
+/// and upvalues are left out.
+pub fn dump(proto: &FunctionPrototype<'_>, strip: bool) -> Vec<u8> {
+    let mut writer = Writer {
+        out: SIGNATURE.to_vec(),
+        strip,
+    };
+    writer.u8(FORMAT_VERSION);
+    if strip {
+        writer.bytes(b"=?");
+    } else {
+        writer.bytes(proto.chunk_name.as_bytes());
+    }
+    writer.proto(proto);
+    writer.out
+}
+
+/// Deserialize a chunk written by [`dump`].
+///
+/// The prototype is not verified, so it must be passed through [`FunctionPrototype::trust`] or run
+/// with bounds checked opcodes.
+pub fn undump<'gc>(ctx: Context<'gc>, chunk: &[u8]) -> Result<FunctionPrototype<'gc>, UndumpError> {
+    let input = chunk
+        .strip_prefix(SIGNATURE)
+        .ok_or(UndumpError::BadSignature)?;
+    let mut reader = Reader { ctx, input };
+    match reader.u8()? {
+        FORMAT_VERSION => {}
+        version => return Err(UndumpError::BadVersion(version)),
+    }
+    let chunk_name = reader.string()?;
+    let compiled = reader.proto(0)?;
+    if !reader.input.is_empty() {
+        return Err(UndumpError::Malformed);
+    }
+    Ok(FunctionPrototype::from_compiled(
+        &ctx, chunk_name, &compiled,
+    ))
+}
+
+struct Writer {
+    out: Vec<u8>,
+    strip: bool,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.out.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.out.extend(v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.out.extend(v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.out.extend(v.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(u32::try_from(len).expect("binary chunk section is too large"));
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.out.extend(bytes);
+    }
+
+    fn proto(&mut self, proto: &FunctionPrototype<'_>) {
+        match proto.reference {
+            FunctionRef::Chunk => self.u8(0),
+            FunctionRef::Expression(line) => {
+                self.u8(1);
+                self.u64(line.0);
+            }
+            FunctionRef::Named(_, line) if self.strip => {
+                self.u8(1);
+                self.u64(line.0);
+            }
+            FunctionRef::Named(name, line) => {
+                self.u8(2);
+                self.bytes(name.as_bytes());
+                self.u64(line.0);
+            }
+        }
+        self.u8(proto.fixed_params);
+        self.u8(proto.has_varargs as u8);
+        self.u16(proto.stack_size);
+
+        self.len(proto.constants.len());
+        for constant in proto.constants.iter() {
+            match constant {
+                Constant::Nil => self.u8(0),
+                Constant::Boolean(b) => {
+                    self.u8(1);
+                    self.u8(*b as u8);
+                }
+                Constant::Integer(i) => {
+                    self.u8(2);
+                    self.u64(*i as u64);
+                }
+                Constant::Number(n) => {
+                    self.u8(3);
+                    self.u64(n.to_bits());
+                }
+                Constant::String(s) => {
+                    self.u8(4);
+                    self.bytes(s.as_bytes());
+                }
+            }
+        }
+
+        self.len(proto.opcodes.len());
+        for opcode in proto.opcodes.iter() {
+            write_operation(self, opcode.decode());
+        }
+
+        if self.strip {
+            self.len(0);
+        } else {
+            self.len(proto.opcode_line_numbers.len());
+            for &(index, line) in proto.opcode_line_numbers.iter() {
+                self.len(index);
+                self.u64(line.0);
+            }
+        }
+
+        self.len(proto.upvalues.len());
+        for upvalue in proto.upvalues.iter() {
+            match *upvalue {
+                UpValueDescriptor::Environment => self.u8(0),
+                UpValueDescriptor::ParentLocal(register) => {
+                    self.u8(1);
+                    self.u8(register.0);
+                }
+                UpValueDescriptor::Outer(index) => {
+                    self.u8(2);
+                    self.u8(index.0);
+                }
+            }
+        }
+
+        if self.strip {
+            self.len(0);
+            self.len(0);
+        } else {
+            self.len(proto.local_variables.len());
+            for local in proto.local_variables.iter() {
+                self.bytes(local.name.as_bytes());
+                self.u8(local.register.0);
+                self.len(local.start_pc);
+                self.len(local.end_pc);
+            }
+
+            self.len(proto.upvalue_names.len());
+            for name in proto.upvalue_names.iter() {
+                self.bytes(name.as_bytes());
+            }
+        }
+
+        self.len(proto.prototypes.len());
+        for proto in proto.prototypes.iter() {
+            self.proto(proto);
+        }
+    }
+}
+
+struct Reader<'gc, 'a> {
+    ctx: Context<'gc>,
+    input: &'a [u8],
+}
+
+impl<'gc, 'a> Reader<'gc, 'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], UndumpError> {
+        if self.input.len() < N {
+            return Err(UndumpError::Truncated);
+        }
+        let (bytes, rest) = self.input.split_at(N);
+        self.input = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, UndumpError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, UndumpError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, UndumpError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn bool(&mut self) -> Result<bool, UndumpError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(UndumpError::Malformed),
+        }
+    }
+
+    fn len(&mut self) -> Result<usize, UndumpError> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String<'gc>, UndumpError> {
+        let len = self.len()?;
+        if self.input.len() < len {
+            return Err(UndumpError::Truncated);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(self.ctx.intern(bytes))
+    }
+
+    fn proto(&mut self, depth: usize) -> Result<CompiledPrototype<String<'gc>>, UndumpError> {
+        if depth > MAX_DEPTH {
+            return Err(UndumpError::Malformed);
+        }
+
+        let reference = match self.u8()? {
+            0 => FunctionRef::Chunk,
+            1 => FunctionRef::Expression(LineNumber(self.u64()?)),
+            2 => {
+                let name = self.string()?;
+                FunctionRef::Named(name, LineNumber(self.u64()?))
+            }
+            _ => return Err(UndumpError::Malformed),
+        };
+        let fixed_params = self.u8()?;
+        let has_varargs = self.bool()?;
+        let stack_size = self.u16()?;
+
+        // Counts are never used to reserve space up front, so a hostile chunk cannot make us
+        // allocate more than its own size.
+        let mut constants = Vec::new();
+        for _ in 0..self.len()? {
+            constants.push(match self.u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Boolean(self.bool()?),
+                2 => Constant::Integer(self.u64()? as i64),
+                3 => Constant::Number(f64::from_bits(self.u64()?)),
+                4 => Constant::String(self.string()?),
+                _ => return Err(UndumpError::Malformed),
+            });
+        }
+
+        let mut opcodes = Vec::new();
+        for _ in 0..self.len()? {
+            opcodes.push(OpCode::encode(read_operation(self)?));
+        }
+
+        let mut opcode_line_numbers = Vec::new();
+        for _ in 0..self.len()? {
+            let index = self.len()?;
+            opcode_line_numbers.push((index, LineNumber(self.u64()?)));
+        }
+        if !opcode_line_numbers.windows(2).all(|w| w[0].0 < w[1].0) {
+            return Err(UndumpError::Malformed);
+        }
+        // Stripped chunks have no line information, but every prototype needs at least one line.
+        if opcode_line_numbers.is_empty() {
+            opcode_line_numbers.push((0, LineNumber(0)));
+        }
+
+        let mut upvalues = Vec::new();
+        for _ in 0..self.len()? {
+            upvalues.push(match self.u8()? {
+                0 => UpValueDescriptor::Environment,
+                1 => UpValueDescriptor::ParentLocal(RegisterIndex(self.u8()?)),
+                2 => UpValueDescriptor::Outer(UpValueIndex(self.u8()?)),
+                _ => return Err(UndumpError::Malformed),
+            });
+        }
+
+        let mut local_variables = Vec::new();
+        for _ in 0..self.len()? {
+            let name = self.string()?;
+            let register = RegisterIndex(self.u8()?);
+            let start_pc = self.len()?;
+            let end_pc = self.len()?;
+            if start_pc > end_pc {
+                return Err(UndumpError::Malformed);
+            }
+            local_variables.push(LocalVariable {
+                name,
+                register,
+                start_pc,
+                end_pc,
+            });
+        }
+
+        let mut upvalue_names = Vec::new();
+        for _ in 0..self.len()? {
+            upvalue_names.push(self.string()?);
+        }
+        if !upvalue_names.is_empty() && upvalue_names.len() != upvalues.len() {
+            return Err(UndumpError::Malformed);
+        }
+
+        let mut prototypes = Vec::new();
+        for _ in 0..self.len()? {
+            prototypes.push(Box::new(self.proto(depth + 1)?));
+        }
+
+        Ok(CompiledPrototype {
+            reference,
+            fixed_params,
+            has_varargs,
+            stack_size,
+            constants,
+            opcodes,
+            opcode_line_numbers,
+            upvalues,
+            local_variables,
+            upvalue_names,
+            prototypes,
+        })
+    }
+}
+
+// A value stored in the fields of an `Operation`.
+trait Field: Sized {
+    fn write(self, writer: &mut Writer);
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError>;
+}
+
+impl Field for u8 {
+    fn write(self, writer: &mut Writer) {
+        writer.u8(self);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        reader.u8()
+    }
+}
+
+impl Field for bool {
+    fn write(self, writer: &mut Writer) {
+        writer.u8(self as u8);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        reader.bool()
+    }
+}
+
+impl Field for i16 {
+    fn write(self, writer: &mut Writer) {
+        writer.u16(self as u16);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        Ok(reader.u16()? as i16)
+    }
+}
+
+macro_rules! index_fields {
+    ($($ty:ident($inner:ident)),* $(,)?) => {
+        $(
+            impl Field for $ty {
+                fn write(self, writer: &mut Writer) {
+                    self.0.write(writer);
+                }
+
+                fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+                    Ok($ty($inner::read(reader)?))
+                }
+            }
+        )*
+    };
+}
+
+index_fields!(
+    RegisterIndex(u8),
+    ConstantIndex8(u8),
+    UpValueIndex(u8),
+    PrototypeIndex(u8),
+);
+
+impl Field for ConstantIndex16 {
+    fn write(self, writer: &mut Writer) {
+        writer.u16(self.0);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        Ok(ConstantIndex16(reader.u16()?))
+    }
+}
+
+impl Field for Opt254 {
+    fn write(self, writer: &mut Writer) {
+        writer.u8(self.to_u8().unwrap_or(255));
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        Ok(match reader.u8()? {
+            255 => Opt254::none(),
+            v => Opt254::some(v),
+        })
+    }
+}
+
+impl Field for VarCount {
+    fn write(self, writer: &mut Writer) {
+        writer.u8(self.to_constant().unwrap_or(255));
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        Ok(match reader.u8()? {
+            255 => VarCount::variable(),
+            v => VarCount::constant(v),
+        })
+    }
+}
+
+impl Field for RCIndex {
+    fn write(self, writer: &mut Writer) {
+        match self {
+            RCIndex::Register(register) => {
+                writer.u8(0);
+                register.write(writer);
+            }
+            RCIndex::Constant(constant) => {
+                writer.u8(1);
+                constant.write(writer);
+            }
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, UndumpError> {
+        match reader.u8()? {
+            0 => Ok(RCIndex::Register(Field::read(reader)?)),
+            1 => Ok(RCIndex::Constant(Field::read(reader)?)),
+            _ => Err(UndumpError::Malformed),
+        }
+    }
+}
+
+macro_rules! operations {
+    ($($tag:literal => $name:ident { $($field:ident),* $(,)? }),* $(,)?) => {
+        fn write_operation(writer: &mut Writer, operation: Operation) {
+            match operation {
+                $(Operation::$name { $($field),* } => {
+                    writer.u8($tag);
+                    $(Field::write($field, writer);)*
+                })*
+            }
+        }
+
+        fn read_operation(reader: &mut Reader) -> Result<Operation, UndumpError> {
+            Ok(match reader.u8()? {
+                $($tag => Operation::$name { $($field: Field::read(reader)?),* },)*
+                _ => return Err(UndumpError::Malformed),
+            })
+        }
+    };
+}
+
+operations! {
+    0 => Move { dest, source },
+    1 => LoadConstant { dest, constant },
+    2 => LoadBool { dest, value, skip_next },
+    3 => LoadNil { dest, count },
+    4 => NewTable { dest, array_size, map_size },
+    5 => GetTable { dest, table, key },
+    6 => SetTable { table, key, value },
+    7 => GetUpTable { dest, table, key },
+    8 => SetUpTable { table, key, value },
+    9 => SetList { base, count },
+    10 => Call { func, args, returns },
+    11 => TailCall { func, args },
+    12 => Return { start, count },
+    13 => VarArgs { dest, count },
+    14 => Jump { offset, close_upvalues },
+    15 => ToBeClosed { value },
+    16 => Test { value, is_true },
+    17 => TestSet { dest, value, is_true },
+    18 => Closure { dest, proto },
+    19 => NumericForPrep { base, jump },
+    20 => NumericForLoop { base, jump },
+    21 => GenericForCall { base, var_count },
+    22 => GenericForLoop { base, jump },
+    23 => Method { base, table, key },
+    24 => Concat { dest, source, count },
+    25 => GetUpValue { dest, source },
+    26 => SetUpValue { dest, source },
+    27 => Length { dest, source },
+    28 => Eq { skip_if, left, right },
+    29 => Less { skip_if, left, right },
+    30 => LessEq { skip_if, left, right },
+    31 => Not { dest, source },
+    32 => Minus { dest, source },
+    33 => Add { dest, left, right },
+    34 => Sub { dest, left, right },
+    35 => Mul { dest, left, right },
+    36 => Div { dest, left, right },
+    37 => IDiv { dest, left, right },
+    38 => Mod { dest, left, right },
+    39 => Pow { dest, left, right },
+    40 => BitAnd { dest, left, right },
+    41 => BitOr { dest, left, right },
+    42 => BitXor { dest, left, right },
+    43 => ShiftLeft { dest, left, right },
+    44 => ShiftRight { dest, left, right },
+    45 => BitNot { dest, source },
+    46 => LoadExt { dest, ext },
+}
@@ -0,0 +1,83 @@
+//! Host-defined "ext" opcodes.
+//!
+//! Intrinsic callbacks (see [`Callback::new_intrinsic`]) already skip creating a callback frame
+//! when called from Lua, but calling one still means looking up the function by name, usually in
+//! the globals table, on every call. For the hottest paths of an embedding, such as reading
+//! components in an ECS, an intrinsic can instead be registered as an ext opcode under a global
+//! name. Chunks compiled after registration turn every call to that global name into a
+//! `LoadExt` instruction, which loads the intrinsic directly from this table, followed by the
+//! usual call instruction.
+//!
+//! Because the lookup happens at compile time, a chunk keeps calling the registered intrinsic even
+//! if the global of the same name is later reassigned, and calls are only rewritten when the name
+//! is not shadowed by a local variable. Ext opcodes are meant for hosts that control both the
+//! registered names and the scripts using them.
+
+use std::string::String as StdString;
+
+use gc_arena::{lock::RefLock, Collect, Gc, Mutation};
+use thiserror::Error;
+
+use crate::{Callback, Context, IntrinsicFn, String};
+
+/// The maximum number of ext opcodes which can be registered with a single `Lua` instance.
+pub const MAX_EXT_OPCODES: usize = 16;
+
+#[derive(Debug, Clone, Error)]
+pub enum ExtOpcodeError {
+    #[error("ext opcode {0:?} is already registered")]
+    AlreadyRegistered(StdString),
+    #[error("no more than {MAX_EXT_OPCODES} ext opcodes can be registered")]
+    TooMany,
+}
+
+/// The ext opcodes registered with a `Lua` instance, see the [module docs](self).
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ExtOpcodes<'gc>(Gc<'gc, RefLock<Vec<(String<'gc>, Callback<'gc>)>>>);
+
+impl<'gc> ExtOpcodes<'gc> {
+    pub fn new(mc: &Mutation<'gc>) -> Self {
+        Self(Gc::new(mc, RefLock::new(Vec::new())))
+    }
+
+    /// Register an intrinsic to be called directly in place of calls to the global `name`, and
+    /// return its ext opcode index.
+    ///
+    /// Only chunks compiled after this call are affected.
+    pub fn register(
+        self,
+        ctx: Context<'gc>,
+        name: &str,
+        intrinsic: IntrinsicFn<'gc>,
+    ) -> Result<u8, ExtOpcodeError> {
+        let mut ext_opcodes = self.0.borrow_mut(&ctx);
+        if ext_opcodes
+            .iter()
+            .any(|(n, _)| n.as_bytes() == name.as_bytes())
+        {
+            return Err(ExtOpcodeError::AlreadyRegistered(name.to_owned()));
+        }
+        if ext_opcodes.len() >= MAX_EXT_OPCODES {
+            return Err(ExtOpcodeError::TooMany);
+        }
+        ext_opcodes.push((
+            ctx.intern(name.as_bytes()),
+            Callback::new_intrinsic(&ctx, intrinsic),
+        ));
+        Ok((ext_opcodes.len() - 1) as u8)
+    }
+
+    /// Returns the intrinsic callback registered as the given ext opcode.
+    pub fn get(self, ext: u8) -> Option<Callback<'gc>> {
+        self.0
+            .borrow()
+            .get(ext as usize)
+            .map(|&(_, callback)| callback)
+    }
+
+    /// Returns the names of every registered ext opcode, in ext opcode order.
+    pub fn names(self) -> Vec<String<'gc>> {
+        self.0.borrow().iter().map(|&(name, _)| name).collect()
+    }
+}
@@ -1,6 +1,8 @@
 use gc_arena::{lock::RefLock, Collect, Finalization, Gc, GcWeak, Mutation};
 
-use crate::{thread::ThreadInner, Thread};
+use crate::{
+    table::TableInner, thread::ThreadInner, userdata::UserDataInner, Table, Thread, UserData, Value,
+};
 
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -8,6 +10,7 @@ pub struct Finalizers<'gc>(Gc<'gc, RefLock<FinalizersState<'gc>>>);
 
 impl<'gc> Finalizers<'gc> {
     const THREAD_ERR: &'static str = "thread finalization was missed";
+    const FINALIZER_ERR: &'static str = "object finalization was missed";
 
     pub(crate) fn new(mc: &Mutation<'gc>) -> Self {
         Finalizers(Gc::new(mc, RefLock::default()))
@@ -17,16 +20,82 @@ impl<'gc> Finalizers<'gc> {
         self.0.borrow_mut(mc).threads.push(Gc::downgrade(ptr));
     }
 
+    pub(crate) fn register_weak_table(&self, mc: &Mutation<'gc>, ptr: Gc<'gc, TableInner<'gc>>) {
+        self.0.borrow_mut(mc).weak_tables.push(Gc::downgrade(ptr));
+    }
+
+    /// Register a table whose metatable has a `__gc` metamethod.
+    pub(crate) fn register_table(&self, mc: &Mutation<'gc>, ptr: Gc<'gc, TableInner<'gc>>) {
+        self.0.borrow_mut(mc).tables.push(Gc::downgrade(ptr));
+    }
+
+    /// Register a userdata whose metatable has a `__gc` metamethod.
+    pub(crate) fn register_userdata(&self, mc: &Mutation<'gc>, ptr: Gc<'gc, UserDataInner<'gc>>) {
+        self.0.borrow_mut(mc).userdata.push(Gc::downgrade(ptr));
+    }
+
+    /// Returns true if there are any unreachable objects waiting for their `__gc` metamethod to
+    /// be called.
+    pub fn has_pending(&self) -> bool {
+        !self.0.borrow().pending.is_empty()
+    }
+
+    /// Take the next unreachable object waiting for its `__gc` metamethod to be called.
+    ///
+    /// The object is no longer marked for finalization, it will only be finalized again if a
+    /// metatable with a `__gc` field is set on it again.
+    pub fn pop_pending(&self, mc: &Mutation<'gc>) -> Option<Value<'gc>> {
+        let value = self.0.borrow_mut(mc).pending.pop()?;
+        match value {
+            Value::Table(t) => t.into_inner().borrow_mut(mc).has_finalizer = false,
+            Value::UserData(u) => u.set_has_finalizer(mc, false),
+            _ => unreachable!(),
+        }
+        Some(value)
+    }
+
     /// First stage of two-stage finalization.
     ///
     /// This stage can cause resurrection, so the arena must be *fully re-marked* before stage two
     /// (`Finalizers::finalize`).
+    ///
+    /// Every unreachable table or userdata marked for finalization is resurrected here and moved
+    /// to the pending list, where it is kept alive until its `__gc` metamethod has been called.
     pub(crate) fn prepare(&self, fc: &Finalization<'gc>) {
-        let state = self.0.borrow();
+        let mut state = self.0.borrow_mut(fc);
         for &ptr in &state.threads {
             let thread = Thread::from_inner(ptr.upgrade(fc).expect(Self::THREAD_ERR));
             thread.resurrect_live_upvalues(fc).unwrap();
         }
+
+        let FinalizersState {
+            tables,
+            userdata,
+            pending,
+            ..
+        } = &mut *state;
+
+        tables.retain(|&ptr| {
+            let ptr = ptr.upgrade(fc).expect(Self::FINALIZER_ERR);
+            if Gc::is_dead(fc, ptr) {
+                Gc::resurrect(fc, ptr);
+                pending.push(Table::from_inner(ptr).into());
+                false
+            } else {
+                true
+            }
+        });
+
+        userdata.retain(|&ptr| {
+            let ptr = ptr.upgrade(fc).expect(Self::FINALIZER_ERR);
+            if Gc::is_dead(fc, ptr) {
+                Gc::resurrect(fc, ptr);
+                pending.push(UserData::from_inner(ptr).into());
+                false
+            } else {
+                true
+            }
+        });
     }
 
     /// Second stage of two-stage finalization.
@@ -47,6 +116,28 @@ impl<'gc> Finalizers<'gc> {
                 true
             }
         });
+
+        // Weak tables only need their entries cleared if they themselves are going to survive.
+        // Tables which are no longer weak are dropped from the list, they will be registered again
+        // if they become weak.
+        state.weak_tables.retain(|&ptr| {
+            let Some(ptr) = ptr.upgrade(fc) else {
+                return false;
+            };
+            if Gc::is_dead(fc, ptr) {
+                return false;
+            }
+            let mut table_state = ptr.borrow_mut(fc);
+            let weak_mode = table_state.weak_mode();
+            if weak_mode.is_weak() {
+                table_state
+                    .raw_table
+                    .clear_dead(fc, weak_mode.keys, weak_mode.values);
+                true
+            } else {
+                false
+            }
+        });
     }
 }
 
@@ -54,4 +145,8 @@ impl<'gc> Finalizers<'gc> {
 #[collect(no_drop)]
 struct FinalizersState<'gc> {
     threads: Vec<GcWeak<'gc, ThreadInner<'gc>>>,
+    weak_tables: Vec<GcWeak<'gc, TableInner<'gc>>>,
+    tables: Vec<GcWeak<'gc, TableInner<'gc>>>,
+    userdata: Vec<GcWeak<'gc, UserDataInner<'gc>>>,
+    pending: Vec<Value<'gc>>,
 }
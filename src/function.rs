@@ -1,10 +1,103 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
 use gc_arena::{Collect, Gc, Mutation};
+use thiserror::Error;
 
 use crate::{
-    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, IntoMultiValue,
-    Sequence, SequencePoll, Stack,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor,
+    ExecutorMode, Fuel, IntoMultiValue, Sequence, SequencePoll, Stack, Value, Variadic,
 };
 
+/// An error returned by [`Function::call_with_fuel`].
+#[derive(Debug, Clone, Collect, Error)]
+#[collect(no_drop)]
+pub enum CallError<'gc> {
+    /// The function raised an error.
+    #[error("{0}")]
+    Error(Error<'gc>),
+    /// The function tried to yield from the thread it was called on, which has nothing to yield
+    /// to.
+    #[error("attempt to yield from a function called with `call_with_fuel`")]
+    Yielded,
+    /// The function did not finish before its fuel ran out or the fuel was interrupted.
+    #[error("function ran out of fuel")]
+    OutOfFuel,
+}
+
+impl<'gc> From<Error<'gc>> for CallError<'gc> {
+    fn from(error: Error<'gc>) -> Self {
+        Self::Error(error)
+    }
+}
+
+/// A host function which decides how to continue after a yield, see [`YieldPolicy::Hook`].
+///
+/// It is called with the yielded values, and returns the values to resume with, or an error to
+/// raise where the yield happened.
+pub type YieldHook =
+    Rc<dyn for<'gc> Fn(Context<'gc>, &[Value<'gc>]) -> Result<Vec<Value<'gc>>, Error<'gc>>>;
+
+/// What happens when Lua code yields from a call made by Rust code which cannot be suspended, set
+/// with `Context::set_yield_policy`.
+///
+/// This applies to functions called with [`Function::call_with_fuel`] and to `__gc` finalizers
+/// run by `Lua::run_finalizers`. Yields from Lua code run by an `Executor` are never affected,
+/// they always suspend the executor.
+#[derive(Clone, Default)]
+pub enum YieldPolicy {
+    /// The call fails. `Function::call_with_fuel` returns `CallError::Yielded`, and a finalizer
+    /// is abandoned.
+    #[default]
+    Error,
+    /// The yield returns immediately with no values, as if the call had been made in a new
+    /// coroutine which is resumed until it finishes.
+    Resume,
+    /// The [`YieldHook`] is called with the yielded values to decide how to continue.
+    Hook(YieldHook),
+}
+
+impl fmt::Debug for YieldPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YieldPolicy::Error => f.write_str("Error"),
+            YieldPolicy::Resume => f.write_str("Resume"),
+            YieldPolicy::Hook(_) => f.write_str("Hook"),
+        }
+    }
+}
+
+/// Singleton holding the policy set with `Context::set_yield_policy`.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct YieldPolicySetting(pub(crate) RefCell<YieldPolicy>);
+
+impl YieldPolicy {
+    // Takes the result of an executor which has finished stepping. If its main thread yielded
+    // rather than returned, the executor is continued according to this policy and `None` is
+    // returned.
+    pub(crate) fn take_result<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        executor: Executor<'gc>,
+    ) -> Result<Option<Vec<Value<'gc>>>, CallError<'gc>> {
+        let Variadic(values) = executor
+            .take_result::<Variadic<Vec<Value<'gc>>>>(ctx)
+            .expect("finished executor must have a result")?;
+        if executor.mode() != ExecutorMode::Suspended {
+            return Ok(Some(values));
+        }
+        match self {
+            YieldPolicy::Error => return Err(CallError::Yielded),
+            YieldPolicy::Resume => executor.resume(ctx, ()).unwrap(),
+            YieldPolicy::Hook(hook) => match hook(ctx, &values) {
+                Ok(values) => executor.resume(ctx, Variadic(values)).unwrap(),
+                Err(err) => executor.resume_err(&ctx, err).unwrap(),
+            },
+        }
+        Ok(None)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Collect)]
 #[collect(no_drop)]
 pub enum Function<'gc> {
@@ -92,4 +185,42 @@ impl<'gc> Function<'gc> {
             },
         ))
     }
+
+    /// Calls this function to completion on a new thread, without the caller needing to set up
+    /// and drive an `Executor`.
+    ///
+    /// This is meant for small synchronous hooks which are not expected to yield, such as
+    /// callbacks stored by host code. The function may call other functions and resume coroutines
+    /// of its own. If it yields from the thread it was called on, the `YieldPolicy` set with
+    /// `Context::set_yield_policy` decides what happens, and by default this returns
+    /// `CallError::Yielded`. If it does not finish within the given fuel this returns
+    /// `CallError::OutOfFuel`. In either case the call is abandoned and cannot be continued.
+    ///
+    /// # Panics
+    ///
+    /// This runs a separate `Executor`, so like `Executor::step` it must not be called from a
+    /// callback which is being run by an `Executor`.
+    pub fn call_with_fuel(
+        self,
+        ctx: Context<'gc>,
+        args: impl IntoMultiValue<'gc>,
+        fuel: &mut Fuel,
+    ) -> Result<Vec<Value<'gc>>, CallError<'gc>> {
+        let executor = Executor::start(ctx, self, args);
+        let policy = ctx.yield_policy();
+        loop {
+            if !executor.step(ctx, fuel) {
+                executor.stop(&ctx);
+                return Err(CallError::OutOfFuel);
+            }
+            match policy.take_result(ctx, executor) {
+                Ok(Some(results)) => return Ok(results),
+                Ok(None) => {}
+                Err(err) => {
+                    executor.stop(&ctx);
+                    return Err(err);
+                }
+            }
+        }
+    }
 }
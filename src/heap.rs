@@ -0,0 +1,396 @@
+use std::{
+    collections::{hash_map, VecDeque},
+    fmt, mem, ops,
+    string::String as StdString,
+};
+
+use ahash::{AHashMap, AHashSet};
+use gc_arena::Gc;
+
+use crate::{
+    closure::{ClosureInner, UpValue, UpValueState},
+    string::StringInner,
+    table::TableInner,
+    userdata::UserDataInner,
+    Closure, Constant, Function, FunctionPrototype, String, Table, Thread, UserData, Value,
+};
+
+/// The number of objects of a single kind and the approximate number of bytes they use.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct KindStats {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl ops::Add for KindStats {
+    type Output = KindStats;
+
+    fn add(self, rhs: KindStats) -> KindStats {
+        KindStats {
+            count: self.count + rhs.count,
+            bytes: self.bytes + rhs.bytes,
+        }
+    }
+}
+
+impl KindStats {
+    fn record(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Heap statistics broken down by object kind, returned by `Lua::heap_stats`.
+///
+/// Byte counts are estimates: they include the object itself and any storage it owns (such as the
+/// array and map parts of a table, or the stack of a thread), but not the allocator's own
+/// overhead. The size of the value held by a userdata is not known, so only its header is counted.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub tables: KindStats,
+    pub strings: KindStats,
+    pub closures: KindStats,
+    pub threads: KindStats,
+    pub userdata: KindStats,
+}
+
+impl HeapStats {
+    /// Walk every object reachable from the given roots and tally them by kind.
+    ///
+    /// Each object is counted once, no matter how many times it is referenced. Callbacks are
+    /// opaque, so values held only by a callback are not found, and neither are values held
+    /// only by a thread which is currently running.
+    pub fn reachable_from<'gc>(roots: impl IntoIterator<Item = Value<'gc>>) -> HeapStats {
+        let mut walker = Walker::default();
+        walker.pending.extend(roots);
+        walker.run();
+        walker.stats
+    }
+
+    /// The combined statistics of every kind of object.
+    pub fn total(&self) -> KindStats {
+        self.tables + self.strings + self.closures + self.threads + self.userdata
+    }
+}
+
+#[derive(Default)]
+struct Walker<'gc> {
+    stats: HeapStats,
+    seen: AHashSet<*const ()>,
+    pending: Vec<Value<'gc>>,
+}
+
+impl<'gc> Walker<'gc> {
+    fn run(&mut self) {
+        while let Some(value) = self.pending.pop() {
+            match value {
+                Value::String(s) => self.visit_string(s),
+                Value::Table(t) => self.visit_table(t),
+                Value::Function(Function::Closure(c)) => self.visit_closure(c),
+                Value::Thread(t) => self.visit_thread(t),
+                Value::UserData(u) => self.visit_userdata(u),
+                _ => {}
+            }
+        }
+    }
+
+    // Returns true the first time it is called with a given pointer.
+    fn first_visit<T>(&mut self, ptr: *const T) -> bool {
+        self.seen.insert(ptr as *const ())
+    }
+
+    fn visit_string(&mut self, s: String<'gc>) {
+        if self.first_visit(Gc::as_ptr(s.into_inner())) {
+            self.stats
+                .strings
+                .record(mem::size_of::<StringInner>() + s.as_bytes().len());
+        }
+    }
+
+    fn visit_table(&mut self, t: Table<'gc>) {
+        if !self.first_visit(Gc::as_ptr(t.into_inner())) {
+            return;
+        }
+
+        let inner = t.into_inner();
+        let state = inner.borrow();
+        self.stats
+            .tables
+            .record(mem::size_of::<TableInner<'gc>>() + state.raw_table.allocated_bytes());
+        if let Some(metatable) = state.metatable {
+            self.pending.push(metatable.into());
+        }
+        for (key, value) in t {
+            self.pending.push(key);
+            self.pending.push(value);
+        }
+    }
+
+    fn visit_closure(&mut self, c: Closure<'gc>) {
+        if !self.first_visit(Gc::as_ptr(c.into_inner())) {
+            return;
+        }
+
+        self.stats.closures.record(
+            mem::size_of::<ClosureInner<'gc>>()
+                + c.upvalues().len() * mem::size_of::<UpValue<'gc>>(),
+        );
+        for upvalue in c.upvalues() {
+            // Open upvalues point into the stack of a thread, which is walked with the thread.
+            if let UpValueState::Closed(value) = upvalue.get() {
+                self.pending.push(value);
+            }
+        }
+        self.visit_prototype(c.prototype());
+    }
+
+    // Prototypes are not counted themselves, but the strings in their constants are.
+    fn visit_prototype(&mut self, proto: Gc<'gc, FunctionPrototype<'gc>>) {
+        if !self.first_visit(Gc::as_ptr(proto)) {
+            return;
+        }
+
+        for constant in proto.constants.iter() {
+            if let Constant::String(s) = *constant {
+                self.pending.push(s.into());
+            }
+        }
+        for &child in proto.prototypes.iter() {
+            self.visit_prototype(child);
+        }
+    }
+
+    fn visit_thread(&mut self, t: Thread<'gc>) {
+        if !self.first_visit(Gc::as_ptr(t.into_inner())) {
+            return;
+        }
+
+        let pending = &mut self.pending;
+        let bytes = t.visit_heap(|_, value| pending.push(value));
+        self.stats.threads.record(bytes);
+    }
+
+    fn visit_userdata(&mut self, u: UserData<'gc>) {
+        if !self.first_visit(Gc::as_ptr(u.into_inner())) {
+            return;
+        }
+
+        self.stats
+            .userdata
+            .record(mem::size_of::<UserDataInner<'gc>>());
+        if let Some(metatable) = u.metatable() {
+            self.pending.push(metatable.into());
+        }
+    }
+}
+
+/// One step along a `ReferencePath`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStep {
+    /// The value of a table entry. The key is written as a Lua literal if it is a string, number
+    /// or boolean, and with `Value::display` otherwise.
+    Field(StdString),
+    /// The key of a table entry, written like the key of a `PathStep::Field`.
+    Key(StdString),
+    /// The metatable of a table or userdata.
+    Metatable,
+    /// A closed upvalue of a closure, by index.
+    UpValue(usize),
+    /// The function of a call frame of a thread, by index.
+    Frame(usize),
+    /// A value on the stack of a thread, by index.
+    Stack(usize),
+}
+
+impl fmt::Display for PathStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathStep::Field(key) => match as_name(key) {
+                Some(name) => write!(f, ".{}", name),
+                None => write!(f, "[{}]", key),
+            },
+            PathStep::Key(key) => write!(f, ".<key {}>", key),
+            PathStep::Metatable => write!(f, ".<metatable>"),
+            PathStep::UpValue(i) => write!(f, ".<upvalue {}>", i),
+            PathStep::Frame(i) => write!(f, ".<frame {}>", i),
+            PathStep::Stack(i) => write!(f, ".<stack {}>", i),
+        }
+    }
+}
+
+/// A chain of references from a root to a value, found by `Lua::reference_paths`.
+///
+/// Displays as the root followed by each step, such as `_G.cache[3].<metatable>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferencePath {
+    /// The name of the root the path starts from.
+    pub root: StdString,
+    pub steps: Vec<PathStep>,
+}
+
+impl fmt::Display for ReferencePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root)?;
+        for step in &self.steps {
+            write!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find up to `limit` example paths of references from the given named roots to `target`.
+///
+/// Each path ends with a different reference to `target`, and reaches the object holding that
+/// reference by one of the shortest possible paths. Like `HeapStats::reachable_from`, references
+/// held by callbacks and by running threads cannot be followed, and neither can references held by
+/// function prototypes. Values which are not garbage collected objects have no paths.
+pub fn reference_paths<'gc>(
+    roots: impl IntoIterator<Item = (StdString, Value<'gc>)>,
+    target: Value<'gc>,
+    limit: usize,
+) -> Vec<ReferencePath> {
+    enum Origin {
+        Root(usize),
+        Edge(*const (), PathStep),
+    }
+
+    fn build_path(
+        roots: &[(StdString, Value<'_>)],
+        origins: &AHashMap<*const (), Origin>,
+        mut ptr: *const (),
+        last: PathStep,
+    ) -> ReferencePath {
+        let mut steps = vec![last];
+        loop {
+            match &origins[&ptr] {
+                Origin::Root(i) => {
+                    steps.reverse();
+                    return ReferencePath {
+                        root: roots[*i].0.clone(),
+                        steps,
+                    };
+                }
+                Origin::Edge(parent, step) => {
+                    steps.push(step.clone());
+                    ptr = *parent;
+                }
+            }
+        }
+    }
+
+    let Some(target_ptr) = object_ptr(target) else {
+        return Vec::new();
+    };
+    let roots: Vec<_> = roots.into_iter().collect();
+
+    let mut paths = Vec::new();
+    let mut origins = AHashMap::new();
+    let mut queue = VecDeque::new();
+    for (i, (name, value)) in roots.iter().enumerate() {
+        let Some(ptr) = object_ptr(*value) else {
+            continue;
+        };
+        if ptr == target_ptr {
+            if paths.len() < limit {
+                paths.push(ReferencePath {
+                    root: name.clone(),
+                    steps: Vec::new(),
+                });
+            }
+        } else if let hash_map::Entry::Vacant(vacant) = origins.entry(ptr) {
+            vacant.insert(Origin::Root(i));
+            queue.push_back(*value);
+        }
+    }
+
+    while let Some(value) = queue.pop_front() {
+        if paths.len() >= limit {
+            break;
+        }
+
+        let parent = object_ptr(value).unwrap();
+        visit_references(value, |step, child| {
+            let Some(ptr) = object_ptr(child) else {
+                return;
+            };
+            if ptr == target_ptr {
+                if paths.len() < limit {
+                    paths.push(build_path(&roots, &origins, parent, step));
+                }
+            } else if let hash_map::Entry::Vacant(vacant) = origins.entry(ptr) {
+                vacant.insert(Origin::Edge(parent, step));
+                queue.push_back(child);
+            }
+        });
+    }
+
+    paths
+}
+
+// Returns the address of the object a value points to, or `None` if it is not a garbage collected
+// object.
+fn object_ptr(value: Value<'_>) -> Option<*const ()> {
+    Some(match value {
+        Value::String(s) => Gc::as_ptr(s.into_inner()) as *const (),
+        Value::Table(t) => Gc::as_ptr(t.into_inner()) as *const (),
+        Value::Function(Function::Closure(c)) => Gc::as_ptr(c.into_inner()) as *const (),
+        Value::Function(Function::Callback(c)) => Gc::as_ptr(c.into_inner()) as *const (),
+        Value::Thread(t) => Gc::as_ptr(t.into_inner()) as *const (),
+        Value::UserData(u) => Gc::as_ptr(u.into_inner()) as *const (),
+        _ => return None,
+    })
+}
+
+// Calls `visit` with every object directly referenced by a value, and the step which leads to it.
+fn visit_references<'gc>(value: Value<'gc>, mut visit: impl FnMut(PathStep, Value<'gc>)) {
+    match value {
+        Value::Table(t) => {
+            if let Some(metatable) = t.metatable() {
+                visit(PathStep::Metatable, metatable.into());
+            }
+            for (key, value) in t {
+                if object_ptr(key).is_none() && object_ptr(value).is_none() {
+                    continue;
+                }
+                let literal = key_literal(key);
+                if object_ptr(key).is_some() {
+                    visit(PathStep::Key(literal.clone()), key);
+                }
+                visit(PathStep::Field(literal), value);
+            }
+        }
+        Value::Function(Function::Closure(c)) => {
+            for (i, upvalue) in c.upvalues().iter().enumerate() {
+                if let UpValueState::Closed(value) = upvalue.get() {
+                    visit(PathStep::UpValue(i), value);
+                }
+            }
+        }
+        Value::Thread(t) => {
+            t.visit_heap(visit);
+        }
+        Value::UserData(u) => {
+            if let Some(metatable) = u.metatable() {
+                visit(PathStep::Metatable, metatable.into());
+            }
+        }
+        _ => {}
+    }
+}
+
+fn key_literal(key: Value<'_>) -> StdString {
+    match key {
+        Value::String(s) => format!("{:?}", s.to_str_lossy()),
+        key => key.display().to_string(),
+    }
+}
+
+// If a key literal is a string which is a valid Lua name, returns the name without quotes.
+fn as_name(literal: &str) -> Option<&str> {
+    let name = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let valid = (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
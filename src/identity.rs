@@ -0,0 +1,109 @@
+use std::{cell::Cell, fmt};
+
+use ahash::random_state::RandomState;
+use gc_arena::{Collect, Gc};
+
+use crate::{Function, Value};
+
+/// How the identity of tables, functions, threads and userdata is exposed to scripts, through the
+/// default `tostring` output and the `rawid` builtin.
+///
+/// Set with `Context::set_identity_policy`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum IdentityPolicy {
+    /// Every object gets an identity derived from its address by a keyed permutation, with a
+    /// random key chosen for each `Lua` instance.
+    ///
+    /// Identities are unique among live objects and stay the same for the lifetime of an object,
+    /// but reveal nothing about the memory layout of the host, so they are safe to show to
+    /// sandboxed scripts. An identity may be reused after its object is collected.
+    #[default]
+    Opaque,
+    /// Every object is identified by its address, as in PUC-Rio Lua.
+    ///
+    /// This makes identities match addresses seen from Rust while debugging, but reveals memory
+    /// addresses to scripts, which defeats address space layout randomization.
+    Address,
+    /// Objects have no visible identity: `tostring` only shows their type, and `rawid` returns
+    /// `nil`.
+    Hidden,
+}
+
+/// The identity of an object, as exposed by the current `IdentityPolicy`.
+///
+/// Displays as hexadecimal, which is how it appears in `tostring` output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectId(pub u64);
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x}", self.0)
+    }
+}
+
+/// Singleton holding the identity policy and the key used for opaque identities.
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct Identities {
+    policy: Cell<IdentityPolicy>,
+    key: RandomState,
+}
+
+impl Default for Identities {
+    fn default() -> Self {
+        Self {
+            policy: Cell::new(IdentityPolicy::default()),
+            key: RandomState::new(),
+        }
+    }
+}
+
+impl Identities {
+    // The number of Feistel rounds used to permute addresses into opaque identities.
+    const ROUNDS: u32 = 4;
+
+    pub(crate) fn policy(&self) -> IdentityPolicy {
+        self.policy.get()
+    }
+
+    pub(crate) fn set_policy(&self, policy: IdentityPolicy) {
+        self.policy.set(policy);
+    }
+
+    /// Returns the identity of a value under the current policy, or `None` if it is not an object
+    /// with an identity or identities are hidden.
+    ///
+    /// Strings are compared by value in Lua, so they never have an identity.
+    pub(crate) fn id(&self, value: Value<'_>) -> Option<ObjectId> {
+        let addr = object_address(value)?;
+        match self.policy.get() {
+            IdentityPolicy::Opaque => Some(ObjectId(self.permute(addr))),
+            IdentityPolicy::Address => Some(ObjectId(addr)),
+            IdentityPolicy::Hidden => None,
+        }
+    }
+
+    // A keyed Feistel network, which is a bijection on `u64`, so distinct addresses always get
+    // distinct identities.
+    fn permute(&self, addr: u64) -> u64 {
+        let mut left = (addr >> 32) as u32;
+        let mut right = addr as u32;
+        for round in 0..Self::ROUNDS {
+            let f = self.key.hash_one((round, right)) as u32;
+            (left, right) = (right, left ^ f);
+        }
+        ((left as u64) << 32) | right as u64
+    }
+}
+
+fn object_address(value: Value<'_>) -> Option<u64> {
+    let ptr = match value {
+        Value::Table(t) => Gc::as_ptr(t.into_inner()) as *const (),
+        Value::Function(Function::Closure(c)) => Gc::as_ptr(c.into_inner()) as *const (),
+        Value::Function(Function::Callback(c)) => Gc::as_ptr(c.into_inner()) as *const (),
+        Value::Thread(t) => Gc::as_ptr(t.into_inner()) as *const (),
+        Value::UserData(u) => Gc::as_ptr(u.into_inner()) as *const (),
+        _ => return None,
+    };
+    Some(ptr as usize as u64)
+}
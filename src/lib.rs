@@ -1,20 +1,36 @@
 pub mod any;
 pub mod async_callback;
+pub mod audit;
 pub mod callback;
 pub mod closure;
+pub mod compilation_cache;
 pub mod compiler;
+#[cfg(feature = "conformance")]
+pub mod conformance;
 pub mod constant;
 pub mod conversion;
+pub mod dump;
 pub mod error;
+pub mod ext;
 pub mod finalizers;
 pub mod fuel;
 pub mod function;
+pub mod heap;
+pub mod identity;
 pub mod io;
 pub mod lua;
+pub mod luac;
 pub mod meta_ops;
+pub mod module;
 pub mod opcode;
+pub mod owned;
+pub mod plugin;
 pub mod raw_ops;
 pub mod registry;
+mod sanitizer;
+pub mod schema;
+pub mod sequence;
+pub mod source_map;
 pub mod stack;
 pub mod stash;
 pub mod stdlib;
@@ -22,34 +38,55 @@ pub mod string;
 pub mod table;
 pub mod thread;
 pub mod types;
+pub mod usage;
 pub mod userdata;
 pub mod value;
+pub mod verify;
+pub mod versioning;
 
 #[doc(inline)]
 pub use self::{
     async_callback::{AsyncSequence, SequenceReturn},
-    callback::{BoxSequence, Callback, CallbackFn, CallbackReturn, Sequence, SequencePoll},
-    closure::{Closure, ClosureError, FunctionPrototype, PrototypeError},
+    audit::EnvironmentAudit,
+    callback::{
+        BoxSequence, Callback, CallbackFn, CallbackReturn, IntrinsicFn, Sequence, SequencePoll,
+    },
+    closure::{
+        Closure, ClosureError, FunctionPrototype, OpCodeChecks, ProtoCounters, PrototypeError,
+    },
+    compilation_cache::CompilationCache,
     constant::Constant,
-    conversion::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Variadic},
+    conversion::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, LuaResult, Variadic},
     error::{Error, RuntimeError, StaticError, TypeError},
     finalizers::Finalizers,
     fuel::Fuel,
-    function::Function,
-    lua::{Context, Lua},
+    function::{CallError, Function, YieldHook, YieldPolicy},
+    heap::{HeapStats, KindStats, PathStep, ReferencePath},
+    identity::{IdentityPolicy, ObjectId},
+    lua::{Context, Lua, MemoryPressureEvent},
     meta_ops::MetaMethod,
+    module::ModuleBuilder,
+    owned::{OwnedValue, OwnedValueError},
+    plugin::{PluginError, PluginManager, PluginQuota},
     registry::{Registry, Singleton},
-    stack::Stack,
+    schema::{Schema, SchemaError},
+    sequence::SequenceExt,
+    source_map::SourceMap,
+    stack::{Stack, StackLimitError, StackLimits},
     stash::{
         StashedCallback, StashedClosure, StashedError, StashedExecutor, StashedFunction,
         StashedString, StashedTable, StashedThread, StashedUserData, StashedValue,
     },
     string::{BadConcatType, String},
-    table::{InvalidTableKey, Table},
+    table::{FieldError, InvalidTableKey, SnapshotValue, Table, TableSnapshot},
     thread::{
-        BadExecutorMode, BadThreadMode, Execution, Executor, ExecutorMode, Thread, ThreadMode,
-        VMError,
+        BadExecutorMode, BadThreadMode, Execution, Executor, ExecutorMode, HookInfo,
+        InstructionHook, TaskScope, TaskScopeClosed, Thread, ThreadMode, ThreadPool, Traceback,
+        TracebackEntry, TracebackFrame, VMError,
     },
+    usage::{FunctionId, FunctionProfile, FunctionUsage, Profile, UsageReport},
     userdata::{BadUserDataType, UserData},
     value::Value,
+    verify::VerifyError,
+    versioning::{ApiVersions, UnknownApiVersion},
 };
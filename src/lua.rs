@@ -1,14 +1,38 @@
-use std::ops;
+use std::{
+    cell::{Cell, RefCell},
+    mem, ops,
+    rc::Rc,
+    string::String as StdString,
+};
 
 use gc_arena::{metrics::Metrics, Arena, Collect, CollectionPhase, Mutation, Root, Rootable};
+use rand::{rngs::SmallRng, RngCore, SeedableRng};
 
+#[cfg(feature = "tier-up")]
+use crate::closure::TierUpThreshold;
 use crate::{
+    closure::{CompilerOptionsSetting, OpCodeChecksSetting},
+    compiler::CompilerOptions,
+    ext::ExtOpcodes,
     finalizers::Finalizers,
+    function::{YieldPolicy, YieldPolicySetting},
+    heap::{self, HeapStats, ReferencePath},
+    identity::{Identities, IdentityPolicy, ObjectId},
+    meta_ops::{self, MetaMethod, MetaResult},
+    stack::StackLimitsSetting,
     stash::{Fetchable, Stashable},
-    stdlib::{load_base, load_coroutine, load_io, load_math, load_string, load_table},
+    stdlib::{
+        load_base, load_coroutine, load_io, load_math, load_os, load_package, load_string,
+        load_table, load_utf8, Clock, FileSystem, FileSystemSetting, MathRng, ModuleResolver,
+        ModuleResolverSetting, OsClock, StdLib,
+    },
     string::InternedStringSet,
-    Error, FromMultiValue, Fuel, IntoValue, InvalidTableKey, Registry, Singleton, StashedExecutor,
-    StaticError, String, Table, Value,
+    table::ObservedTables,
+    usage::{Profile, ProfileSetting, UsageReport, UsageTracker},
+    BadThreadMode, BoxSequence, Callback, CallbackReturn, Error, Execution, Executor,
+    FromMultiValue, Fuel, IntoValue, InvalidTableKey, OpCodeChecks, Registry, Sequence,
+    SequencePoll, Singleton, Stack, StackLimits, StashedExecutor, StashedValue, StaticError,
+    String, Table, Thread, ThreadPool, Value,
 };
 
 #[derive(Copy, Clone)]
@@ -34,6 +58,25 @@ impl<'gc> Context<'gc> {
         self.state.finalizers
     }
 
+    /// The host-defined ext opcodes, see [`crate::ext`].
+    pub fn ext_opcodes(self) -> ExtOpcodes<'gc> {
+        self.state.ext_opcodes
+    }
+
+    pub fn thread_pool(self) -> ThreadPool<'gc> {
+        self.state.thread_pool
+    }
+
+    /// Calls `ctx.thread_pool().take(ctx)`.
+    pub fn new_thread(self) -> Thread<'gc> {
+        self.state.thread_pool.take(self)
+    }
+
+    /// Calls `ctx.thread_pool().recycle(&ctx, thread)`.
+    pub fn recycle_thread(self, thread: Thread<'gc>) -> Result<(), BadThreadMode> {
+        self.state.thread_pool.recycle(&self, thread)
+    }
+
     /// Calls `ctx.globals().set(ctx, key, value)`.
     pub fn set_global<K: IntoValue<'gc>, V: IntoValue<'gc>>(
         self,
@@ -48,6 +91,410 @@ impl<'gc> Context<'gc> {
         self.state.globals.get(self, key)
     }
 
+    /// Creates a temporary overlay environment on top of the globals table.
+    ///
+    /// Reads of keys missing from the overlay fall through to the real globals, while assignments
+    /// to globals are written to the overlay itself, leaving the real globals untouched. Use the
+    /// returned table as the environment for code with `Closure::load_with_env`. Afterwards, the
+    /// overlay can be discarded, inspected as a table of everything the code assigned, or applied
+    /// to the real globals with `Context::commit_fork`.
+    ///
+    /// The overlay is shallow: mutating a table that is reachable from the globals (such as
+    /// `string.foo = 1`) still mutates the shared table. Assigning `nil` to a global which is only
+    /// present in the real globals has no visible effect.
+    pub fn fork(self) -> Table<'gc> {
+        let metatable = Table::new(&self);
+        metatable
+            .set(self, MetaMethod::Index, self.state.globals)
+            .unwrap();
+        let overlay = Table::new(&self);
+        overlay.set_metatable(&self, Some(metatable));
+        overlay
+    }
+
+    /// Copies every entry of an overlay created by `Context::fork` into the real globals.
+    pub fn commit_fork(self, overlay: Table<'gc>) {
+        for (key, value) in overlay {
+            self.state.globals.set(self, key, value).unwrap();
+        }
+    }
+
+    /// Creates a shadow globals table, for finding out which globals a script defines.
+    ///
+    /// This is an overlay like `Context::fork`. Within the shadow, `_G` refers to the shadow
+    /// itself, so `_G.name = value` and `rawset(_G, name, value)` are captured like any other
+    /// global assignment. The metatable of the shadow is also protected, so it cannot be used to
+    /// reach the real globals.
+    ///
+    /// The shadow is not a sandbox, and code can still reach the real globals. In particular,
+    /// chunks loaded by `load` or `dofile` without an explicit environment use the real globals,
+    /// so `load("x = 1")()` assigns `x` outside of the shadow.
+    ///
+    /// Use the returned table as the environment for code with `Closure::load_with_env`. Functions
+    /// defined by that code keep the shadow as their environment. After execution, call
+    /// `Context::captured_globals` to get the definitions.
+    pub fn shadow_globals(self) -> Table<'gc> {
+        let shadow = self.fork();
+        shadow
+            .metatable()
+            .unwrap()
+            .set(self, "__metatable", false)
+            .unwrap();
+        shadow.set(self, "_G", shadow).unwrap();
+        shadow
+    }
+
+    /// Returns a new table of every global assigned in a shadow created by
+    /// `Context::shadow_globals`.
+    pub fn captured_globals(self, shadow: Table<'gc>) -> Table<'gc> {
+        let captured = Table::new(&self);
+        for (key, value) in shadow {
+            // Skip the `_G` self reference, unless the script replaced it.
+            let is_self_ref = match (key, value) {
+                (Value::String(k), Value::Table(v)) => k == "_G" && v == shadow,
+                _ => false,
+            };
+            if is_self_ref {
+                continue;
+            }
+            captured.set(self, key, value).unwrap();
+        }
+        captured
+    }
+
+    /// Makes reading a global which is not defined in `env` an error which names the global, to
+    /// catch misspelled names.
+    ///
+    /// This sets the `__index` metamethod of `env`, creating a metatable for it if it has none, so
+    /// it applies to every script using `env` as its environment and to no other. If `env` already
+    /// had an `__index` metamethod, such as an overlay created by `Context::fork`, a global is only
+    /// an error if it is missing there as well. Assigning to undefined globals is still allowed,
+    /// and `rawget` can be used to check whether a global is defined.
+    pub fn enable_strict_globals(self, env: Table<'gc>) {
+        #[derive(Collect)]
+        #[collect(require_static)]
+        struct CheckDefined(StdString);
+
+        impl<'gc> Sequence<'gc> for CheckDefined {
+            fn poll(
+                &mut self,
+                ctx: Context<'gc>,
+                _exec: Execution<'gc, '_>,
+                mut stack: Stack<'gc, '_>,
+            ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                if stack.get(0).is_nil() {
+                    return Err(undefined_global(ctx, &self.0));
+                }
+                stack.resize(1);
+                Ok(SequencePoll::Return)
+            }
+        }
+
+        fn undefined_global<'gc>(ctx: Context<'gc>, name: &str) -> Error<'gc> {
+            format!("variable '{name}' is not declared")
+                .into_value(ctx)
+                .into()
+        }
+
+        let metatable = env.metatable().unwrap_or_else(|| {
+            let metatable = Table::new(&self);
+            env.set_metatable(&self, Some(metatable));
+            metatable
+        });
+        let fallback = metatable.get(self, MetaMethod::Index);
+
+        let index = Callback::from_fn_with(&self, fallback, |&fallback, ctx, _, mut stack| {
+            let key = stack.get(1);
+            let name = key.display().to_string();
+            if fallback.is_nil() {
+                return Err(undefined_global(ctx, &name));
+            }
+            match meta_ops::index(ctx, fallback, key)? {
+                MetaResult::Value(v) if v.is_nil() => Err(undefined_global(ctx, &name)),
+                MetaResult::Value(v) => {
+                    stack.replace(ctx, v);
+                    Ok(CallbackReturn::Return)
+                }
+                MetaResult::Call(call) => Ok(call.into_callback_return(
+                    &mut stack,
+                    Some(BoxSequence::new(&ctx, CheckDefined(name))),
+                )),
+            }
+        });
+        metatable.set(self, MetaMethod::Index, index).unwrap();
+    }
+
+    /// Set the function which receives every warning emitted with `Context::warn`, including
+    /// warnings from the `warn` builtin.
+    ///
+    /// Like in PUC-Rio Lua, control messages such as `"@on"` and `"@off"` are passed to the
+    /// handler unchanged, and it is up to the handler to interpret them. If no handler is set,
+    /// warnings are discarded.
+    pub fn set_warning_handler(self, handler: impl Fn(&str) + 'static) {
+        *self.singleton::<Rootable![WarningHandler]>().0.borrow_mut() = Some(Rc::new(handler));
+    }
+
+    /// Emit a warning through the current warning handler.
+    ///
+    /// The handler may itself set a new warning handler or emit further warnings.
+    pub fn warn(self, message: &str) {
+        let handler = self
+            .singleton::<Rootable![WarningHandler]>()
+            .0
+            .borrow()
+            .clone();
+        if let Some(handler) = handler {
+            handler(message);
+        }
+    }
+
+    /// Set the amount of fuel each `__gc` finalizer may consume when run by
+    /// `Lua::run_finalizers`.
+    ///
+    /// A finalizer which runs out of fuel is abandoned, and a warning is emitted through
+    /// `Context::warn`. The default is `1 << 20`.
+    pub fn set_finalizer_fuel(self, fuel: i32) {
+        self.singleton::<Rootable![FinalizerFuel]>().0.set(fuel);
+    }
+
+    /// Returns the fuel budget of each `__gc` finalizer, see `Context::set_finalizer_fuel`.
+    pub fn finalizer_fuel(self) -> i32 {
+        self.singleton::<Rootable![FinalizerFuel]>().0.get()
+    }
+
+    /// Set what happens when Lua code yields from a function called by Rust code which cannot be
+    /// suspended, see [`YieldPolicy`].
+    pub fn set_yield_policy(self, policy: YieldPolicy) {
+        *self
+            .singleton::<Rootable![YieldPolicySetting]>()
+            .0
+            .borrow_mut() = policy;
+    }
+
+    /// Returns the policy set with `Context::set_yield_policy`.
+    pub fn yield_policy(self) -> YieldPolicy {
+        self.singleton::<Rootable![YieldPolicySetting]>()
+            .0
+            .borrow()
+            .clone()
+    }
+
+    /// Enable or disable coercion of strings to numbers in arithmetic and bitwise operations.
+    ///
+    /// By default, `"10" + 1` is an error. With string coercion enabled, strings which can be
+    /// parsed as numbers are converted before arithmetic, as in PUC-Rio Lua, so `"10" + 1` is `11`.
+    /// Strings which cannot be converted still fall back to metamethods or raise an error.
+    /// Numbers are always converted to strings for concatenation, regardless of this setting.
+    ///
+    /// This setting applies to every thread in this `Lua` instance.
+    pub fn set_string_coercion(self, enabled: bool) {
+        self.singleton::<Rootable![StringCoercion]>().0.set(enabled);
+    }
+
+    /// Returns whether strings are coerced to numbers in arithmetic, see
+    /// `Context::set_string_coercion`.
+    pub fn string_coercion(self) -> bool {
+        self.singleton::<Rootable![StringCoercion]>().0.get()
+    }
+
+    /// Allow or forbid loading binary chunks written by `string.dump` with the `load` function.
+    ///
+    /// Binary chunks are allowed by default. When forbidden, `load` fails on binary chunks as if
+    /// its mode were `"t"`. `Closure::load_binary` can still be called from Rust.
+    pub fn set_binary_chunks(self, enabled: bool) {
+        self.singleton::<Rootable![ForbidBinaryChunks]>()
+            .0
+            .set(!enabled);
+    }
+
+    /// Returns whether `load` accepts binary chunks, see `Context::set_binary_chunks`.
+    pub fn binary_chunks(self) -> bool {
+        !self.singleton::<Rootable![ForbidBinaryChunks]>().0.get()
+    }
+
+    /// Set whether chunks loaded with `Closure::load` and its variants are verified and then run
+    /// without register bounds checks, see `OpCodeChecks`.
+    ///
+    /// By default every register access is checked. Prototypes which were not loaded while this
+    /// is set can still be verified with `FunctionPrototype::trust`.
+    pub fn set_opcode_checks(self, checks: OpCodeChecks) {
+        self.singleton::<Rootable![OpCodeChecksSetting]>()
+            .0
+            .set(checks);
+    }
+
+    /// Returns whether loaded chunks are verified and trusted, see `Context::set_opcode_checks`.
+    pub fn opcode_checks(self) -> OpCodeChecks {
+        self.singleton::<Rootable![OpCodeChecksSetting]>().0.get()
+    }
+
+    /// Set how hot a prototype must get, as measured by `ProtoCounters::heat`, before the VM
+    /// verifies it and runs it without register bounds checks, as if it had been trusted with
+    /// `FunctionPrototype::trust`.
+    ///
+    /// The check is made whenever the VM starts running a prototype, so a prototype which gets hot
+    /// inside a single long loop tiers up the next time it is entered. Only the hot prototype
+    /// itself is verified, and one which fails verification keeps running with bounds checks. The
+    /// default threshold is 1000, and `u64::MAX` turns tiering up off.
+    #[cfg(feature = "tier-up")]
+    pub fn set_tier_up_threshold(self, threshold: u64) {
+        self.singleton::<Rootable![TierUpThreshold]>()
+            .0
+            .set(threshold);
+    }
+
+    /// Returns the heat at which prototypes tier up, see `Context::set_tier_up_threshold`.
+    #[cfg(feature = "tier-up")]
+    pub fn tier_up_threshold(self) -> u64 {
+        self.singleton::<Rootable![TierUpThreshold]>().0.get()
+    }
+
+    /// Set the language extensions accepted when compiling chunks with `Closure::load` and its
+    /// variants, including chunks loaded by the `load` builtin. See `CompilerOptions`.
+    ///
+    /// By default every extension is disabled, and only standard Lua is accepted.
+    pub fn set_compiler_options(self, options: CompilerOptions) {
+        self.singleton::<Rootable![CompilerOptionsSetting]>()
+            .0
+            .set(options);
+    }
+
+    /// Returns the language extensions accepted when compiling chunks, see
+    /// `Context::set_compiler_options`.
+    pub fn compiler_options(self) -> CompilerOptions {
+        self.singleton::<Rootable![CompilerOptionsSetting]>()
+            .0
+            .get()
+    }
+
+    /// Enable or disable tracking of the fuel consumed by each Lua function.
+    ///
+    /// While enabled, fuel consumed by VM instructions is charged to the function being run, and
+    /// fuel consumed by a callback is charged to the Lua function which called it. The accumulated
+    /// usage is returned by `Lua::usage_report`. Tracking is disabled by default.
+    pub fn set_usage_tracking(self, enabled: bool) {
+        self.singleton::<Rootable![UsageTracker]>()
+            .set_enabled(enabled);
+    }
+
+    /// Set the profile used to warm up chunks loaded from now on, or `None` to stop warming them
+    /// up.
+    ///
+    /// Every function loaded with `Closure::load` and its variants, including binary chunks,
+    /// starts out with the counters recorded for it in the profile added to its
+    /// `ProtoCounters`, so that it is as hot as it was when the profile was recorded. With the
+    /// `tier-up` feature, a function which was hot enough to tier up is verified as soon as it is
+    /// loaded, and runs without register bounds checks from its first call.
+    pub fn set_profile(self, profile: Option<Profile>) {
+        *self.singleton::<Rootable![ProfileSetting]>().0.borrow_mut() = profile.map(Rc::new);
+    }
+
+    /// Returns the profile used to warm up loaded chunks, see `Context::set_profile`.
+    pub fn profile(self) -> Option<Rc<Profile>> {
+        self.singleton::<Rootable![ProfileSetting]>()
+            .0
+            .borrow()
+            .clone()
+    }
+
+    /// Returns whether fuel usage is being tracked, see `Context::set_usage_tracking`.
+    pub fn usage_tracking(self) -> bool {
+        self.singleton::<Rootable![UsageTracker]>().is_enabled()
+    }
+
+    /// Set how the identity of objects is exposed to scripts, through the default `tostring`
+    /// output and the `rawid` builtin. See `IdentityPolicy` for the options.
+    ///
+    /// By default, objects have opaque identities which do not reveal their addresses.
+    pub fn set_identity_policy(self, policy: IdentityPolicy) {
+        self.singleton::<Rootable![Identities]>().set_policy(policy);
+    }
+
+    /// Returns the current identity policy, see `Context::set_identity_policy`.
+    pub fn identity_policy(self) -> IdentityPolicy {
+        self.singleton::<Rootable![Identities]>().policy()
+    }
+
+    /// Seed the random number generator used by `math.random`, the same as calling
+    /// `math.randomseed(seed)` from Lua.
+    ///
+    /// With the same seed, scripts see the same sequence of random numbers, which makes it possible
+    /// to replay a run deterministically. The generator is shared by every thread in this `Lua`
+    /// instance.
+    pub fn seed_random(self, seed: u64) {
+        self.set_random_generator(SmallRng::seed_from_u64(seed));
+    }
+
+    /// Replace the random number generator used by `math.random`.
+    ///
+    /// The generator stays in use until a script calls `math.randomseed`. Calling it with a seed
+    /// replaces the generator with the default one, while calling it with no arguments seeds the
+    /// default generator from this one.
+    pub fn set_random_generator(self, rng: impl RngCore + 'static) {
+        *self.singleton::<Rootable![MathRng]>().0.borrow_mut() = Box::new(rng);
+    }
+
+    /// Set limits on the values callbacks and sequences may leave on the stack, see `StackLimits`.
+    ///
+    /// These limits apply to every thread in this `Lua` instance.
+    pub fn set_stack_limits(self, limits: StackLimits) {
+        self.singleton::<Rootable![StackLimitsSetting]>()
+            .0
+            .set(limits);
+    }
+
+    /// Returns the current stack limits, see `Context::set_stack_limits`.
+    pub fn stack_limits(self) -> StackLimits {
+        self.singleton::<Rootable![StackLimitsSetting]>().0.get()
+    }
+
+    /// Replace the clock used by the `os` library, see `Clock`.
+    ///
+    /// By default, the `os` library reads the system clock.
+    pub fn set_clock(self, clock: impl Clock + 'static) {
+        *self.singleton::<Rootable![OsClock]>().0.borrow_mut() = Box::new(clock);
+    }
+
+    /// Set the file system used by `io.open` and `io.lines`, see `FileSystem`.
+    ///
+    /// By default there is no file system and opening any file fails, use `StdFileSystem` to give
+    /// scripts access to the real disk.
+    pub fn set_file_system(self, file_system: impl FileSystem + 'static) {
+        *self
+            .singleton::<Rootable![FileSystemSetting]>()
+            .0
+            .borrow_mut() = Some(Rc::new(file_system));
+    }
+
+    /// Set the resolver used by the default searcher of `require`, see `ModuleResolver`.
+    ///
+    /// By default there is no resolver and only modules in `package.preload` can be required, use
+    /// `StdModuleResolver` to load modules from the real disk.
+    pub fn set_module_resolver(self, resolver: impl ModuleResolver + 'static) {
+        *self
+            .singleton::<Rootable![ModuleResolverSetting]>()
+            .0
+            .borrow_mut() = Some(Rc::new(resolver));
+    }
+
+    /// Returns the identity of a table, function, thread or userdata under the current identity
+    /// policy.
+    ///
+    /// Returns `None` for any other value, and for every value if identities are hidden.
+    pub fn object_id(self, value: Value<'gc>) -> Option<ObjectId> {
+        self.singleton::<Rootable![Identities]>().id(value)
+    }
+
+    /// Deliver every change made to an observed table since the last flush to the table's
+    /// observer, see `Table::set_observer`.
+    ///
+    /// This is called automatically whenever `Executor::step` returns, and only needs to be called
+    /// directly to deliver changes made from Rust outside of an executor.
+    pub fn flush_table_changes(self) {
+        self.singleton::<Rootable![ObservedTables<'_>]>()
+            .flush(self);
+    }
+
     /// Calls `ctx.registry().singleton::<S>(ctx)`.
     pub fn singleton<S>(self) -> &'gc Root<'gc, S>
     where
@@ -88,6 +535,31 @@ impl<'gc> ops::Deref for Context<'gc> {
 
 pub struct Lua {
     arena: Arena<Rootable![State<'_>]>,
+    memory_pressure: MemoryPressure,
+}
+
+/// Passed to the memory pressure handler when the memory used by a `Lua` instance rises past one
+/// of its memory thresholds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryPressureEvent {
+    /// The highest threshold that has been crossed.
+    pub threshold: usize,
+    /// The index of the crossed threshold, in ascending order of all thresholds.
+    pub level: usize,
+    /// The total memory used at the time the threshold was crossed.
+    pub total_memory: usize,
+}
+
+#[derive(Default)]
+struct MemoryPressure {
+    thresholds: Vec<usize>,
+    // The number of thresholds that the total memory was above at the last check.
+    level: usize,
+    handler: Option<Box<dyn FnMut(&mut Lua, MemoryPressureEvent)>>,
+    // Incremented whenever the handler is set or cleared, so that changes made by the handler
+    // while it runs are not undone when it returns.
+    generation: u64,
+    running: bool,
 }
 
 impl Default for Lua {
@@ -101,20 +573,57 @@ impl Lua {
     pub fn empty() -> Self {
         Lua {
             arena: Arena::<Rootable![State<'_>]>::new(|mc| State::new(mc)),
+            memory_pressure: MemoryPressure::default(),
         }
     }
 
     /// Create a new `Lua` instance with the core stdlib loaded.
     pub fn core() -> Self {
-        let mut lua = Self::empty();
-        lua.load_core();
-        lua
+        Lua::with_stdlib(StdLib::CORE)
     }
 
     /// Create a new `Lua` instance with all of the stdlib loaded.
     pub fn full() -> Self {
-        let mut lua = Lua::core();
-        lua.load_io();
+        Lua::with_stdlib(StdLib::ALL)
+    }
+
+    /// Create a new `Lua` instance with only the given parts of the stdlib loaded.
+    ///
+    /// If `StdLib::BINARY_CHUNKS` is not set, `load` refuses binary chunks, see
+    /// `Context::set_binary_chunks`. The `package` library is loaded last, so that every other
+    /// selected library is recorded in `package.loaded`.
+    pub fn with_stdlib(stdlib: StdLib) -> Self {
+        let mut lua = Self::empty();
+        lua.enter(|ctx| {
+            ctx.set_binary_chunks(stdlib.contains(StdLib::BINARY_CHUNKS));
+            if stdlib.contains(StdLib::BASE) {
+                load_base(ctx);
+            }
+            if stdlib.contains(StdLib::COROUTINE) {
+                load_coroutine(ctx);
+            }
+            if stdlib.contains(StdLib::MATH) {
+                load_math(ctx);
+            }
+            if stdlib.contains(StdLib::STRING) {
+                load_string(ctx);
+            }
+            if stdlib.contains(StdLib::TABLE) {
+                load_table(ctx);
+            }
+            if stdlib.contains(StdLib::UTF8) {
+                load_utf8(ctx);
+            }
+            if stdlib.contains(StdLib::IO) {
+                load_io(ctx);
+            }
+            if stdlib.contains(StdLib::OS) {
+                load_os(ctx);
+            }
+            if stdlib.contains(StdLib::PACKAGE) {
+                load_package(ctx);
+            }
+        });
         lua
     }
 
@@ -126,6 +635,7 @@ impl Lua {
     ///   - `load_math`
     ///   - `load_string`
     ///   - `load_table`
+    ///   - `load_utf8`
     pub fn load_core(&mut self) {
         self.enter(|ctx| {
             load_base(ctx);
@@ -133,16 +643,38 @@ impl Lua {
             load_math(ctx);
             load_string(ctx);
             load_table(ctx);
+            load_utf8(ctx);
         })
     }
 
     /// Load the parts of the stdlib that allow I/O.
+    ///
+    /// Files are opened through the file system set with `Context::set_file_system`.
     pub fn load_io(&mut self) {
         self.enter(|ctx| {
             load_io(ctx);
         })
     }
 
+    /// Load the time functions of the `os` library, which read the clock set with
+    /// `Context::set_clock`.
+    pub fn load_os(&mut self) {
+        self.enter(|ctx| {
+            load_os(ctx);
+        })
+    }
+
+    /// Load the `package` library and `require`, which loads modules through the resolver set
+    /// with `Context::set_module_resolver`.
+    ///
+    /// Standard libraries which are already loaded are recorded in `package.loaded`, so this
+    /// should be called after loading the rest of the stdlib.
+    pub fn load_package(&mut self) {
+        self.enter(|ctx| {
+            load_package(ctx);
+        })
+    }
+
     /// Size of all memory used by this Lua context.
     ///
     /// This is equivalent to `self.gc_metrics().total_allocation()`. This counts all `Gc` allocated
@@ -165,12 +697,106 @@ impl Lua {
 
         self.arena.collect_all();
         assert!(self.arena.collection_phase() == CollectionPhase::Sleeping);
+        self.check_memory_pressure();
     }
 
     pub fn gc_metrics(&self) -> &Metrics {
         self.arena.metrics()
     }
 
+    /// Count the tables, strings, closures, threads and userdata reachable from the globals table,
+    /// along with the approximate number of bytes each kind uses.
+    ///
+    /// This walks the whole reachable heap, so it is meant for diagnosing leaks in script code
+    /// (such as an ever growing registry table), not for calling every frame. Values which are only
+    /// reachable from a `Stashed*` handle or a `Registry` singleton are not counted. To inspect
+    /// other values, use `HeapStats::reachable_from` directly.
+    pub fn heap_stats(&mut self) -> HeapStats {
+        self.enter(|ctx| HeapStats::reachable_from([ctx.globals().into()]))
+    }
+
+    /// Find up to `limit` example paths of references which keep `target` alive, to answer
+    /// questions like "why is this table still alive".
+    ///
+    /// Paths start from the globals table, named `_G`, and from the threads of the given
+    /// executors, named `executor 0` for the main thread of the first executor and `executor 0
+    /// thread 1` for a thread it is waiting on. The contents of `Registry` singletons and of other
+    /// stashed values cannot be enumerated, so references held only there are not found. See
+    /// `heap::reference_paths` to search from other roots.
+    pub fn reference_paths(
+        &mut self,
+        target: &StashedValue,
+        executors: &[&StashedExecutor],
+        limit: usize,
+    ) -> Vec<ReferencePath> {
+        self.enter(|ctx| {
+            let mut roots = vec![("_G".to_owned(), Value::Table(ctx.globals()))];
+            for (i, executor) in executors.iter().enumerate() {
+                for (j, thread) in ctx.fetch(*executor).threads().into_iter().enumerate() {
+                    let name = if j == 0 {
+                        format!("executor {}", i)
+                    } else {
+                        format!("executor {} thread {}", i, j)
+                    };
+                    roots.push((name, Value::Thread(thread)));
+                }
+            }
+            heap::reference_paths(roots, ctx.fetch(target), limit)
+        })
+    }
+
+    /// Set the memory thresholds (in bytes) at which the memory pressure handler is called.
+    ///
+    /// These are soft limits: nothing stops memory from growing past them, but the handler gets a
+    /// chance to react first, such as by running a full collection with `Lua::gc_collect`,
+    /// dropping host caches, or warning the script author.
+    ///
+    /// Memory usage is checked at the end of every call to `Lua::enter`. The handler is called once
+    /// each time usage rises past one or more thresholds, and a threshold can only fire again after
+    /// usage has fallen back below it.
+    pub fn set_memory_thresholds(&mut self, mut thresholds: Vec<usize>) {
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        let total = self.total_memory();
+        self.memory_pressure.level = thresholds.partition_point(|&t| t <= total);
+        self.memory_pressure.thresholds = thresholds;
+    }
+
+    /// Set the function which is called when memory usage rises past one of the thresholds set
+    /// with `Lua::set_memory_thresholds`.
+    ///
+    /// The handler is never called recursively. Memory usage is checked again once the handler
+    /// returns, and if it has risen past another threshold in the meantime, the handler is called
+    /// again. The handler may replace or clear itself, which takes effect once it returns.
+    pub fn set_memory_pressure_handler(
+        &mut self,
+        handler: impl FnMut(&mut Lua, MemoryPressureEvent) + 'static,
+    ) {
+        self.memory_pressure.handler = Some(Box::new(handler));
+        self.memory_pressure.generation += 1;
+    }
+
+    /// Remove the memory pressure handler, if one is set.
+    pub fn clear_memory_pressure_handler(&mut self) {
+        self.memory_pressure.handler = None;
+        self.memory_pressure.generation += 1;
+    }
+
+    /// Returns the fuel consumed by each Lua function since usage tracking was enabled with
+    /// `Context::set_usage_tracking`, or since the last call to `Lua::reset_usage`.
+    ///
+    /// Functions are identified by a `FunctionId`, which stays the same when a chunk is reloaded.
+    /// To find out which functions consumed the fuel of a single frame, call `Lua::reset_usage` at
+    /// the start of each frame.
+    pub fn usage_report(&mut self) -> UsageReport {
+        self.enter(|ctx| ctx.singleton::<Rootable![UsageTracker]>().report())
+    }
+
+    /// Clear all fuel usage accumulated so far.
+    pub fn reset_usage(&mut self) {
+        self.enter(|ctx| ctx.singleton::<Rootable![UsageTracker]>().reset())
+    }
+
     /// Enter the garbage collection arena and perform some operation.
     ///
     /// In order to interact with Lua or do any useful work with Lua values, you must do so from
@@ -181,15 +807,29 @@ impl Lua {
     /// collected cocurrently with accessing the arena.
     ///
     /// Automatically triggers garbage collection before returning if the allocation debt is larger
-    /// than a small constant.
+    /// than a small constant, unless collection has been stopped with `collectgarbage("stop")`. A
+    /// full collection cycle is run instead if one was requested with `collectgarbage("collect")`.
     pub fn enter<F, T>(&mut self, f: F) -> T
     where
         F: for<'gc> FnOnce(Context<'gc>) -> T,
     {
-        const COLLECTOR_GRANULARITY: f64 = 1024.0;
+        let (r, stopped, collect_requested, granularity) = self.arena.mutate(move |mc, state| {
+            let ctx = state.ctx(mc);
+            let r = f(ctx);
+            let control = ctx.singleton::<Rootable![GcControl]>();
+            (
+                r,
+                control.stopped.get(),
+                control.collect_requested.replace(false),
+                control.granularity.get(),
+            )
+        });
+        if collect_requested {
+            self.gc_collect();
+            return r;
+        }
 
-        let r = self.arena.mutate(move |mc, state| f(state.ctx(mc)));
-        if self.arena.metrics().allocation_debt() > COLLECTOR_GRANULARITY {
+        if !stopped && self.arena.metrics().allocation_debt() > granularity {
             if self.arena.collection_phase() == CollectionPhase::Collecting {
                 self.arena.collect_debt();
             } else {
@@ -205,6 +845,7 @@ impl Lua {
                 }
             }
         }
+        self.check_memory_pressure();
         r
     }
 
@@ -217,14 +858,114 @@ impl Lua {
         self.enter(move |ctx| f(ctx).map_err(Error::into_static))
     }
 
+    /// Call the `__gc` metamethod of every unreachable object which has been queued for
+    /// finalization.
+    ///
+    /// Each finalizer is run on its own thread until it completes or exhausts the budget set with
+    /// `Context::set_finalizer_fuel`, in which case it is abandoned and a warning is emitted. A
+    /// finalizer which yields is continued or abandoned according to `Context::set_yield_policy`,
+    /// and yielded values are otherwise ignored. Errors raised by finalizers are ignored, as are
+    /// `__gc` fields which are not callable at the time the finalizer is run.
+    ///
+    /// This is called automatically by `Lua::finish`.
+    pub fn run_finalizers(&mut self) {
+        const FUEL_PER_GC: i32 = 4096;
+
+        loop {
+            let next = self.enter(|ctx| {
+                let object = ctx.finalizers().pop_pending(&ctx)?;
+                let metatable = match object {
+                    Value::Table(t) => t.metatable(),
+                    Value::UserData(u) => u.metatable(),
+                    _ => None,
+                };
+                let function =
+                    metatable.and_then(|mt| meta_ops::call(ctx, mt.get(ctx, MetaMethod::Gc)).ok());
+                Some(function.map(|f| ctx.stash(Executor::start(ctx, f, object))))
+            });
+
+            match next {
+                None => break,
+                Some(None) => {}
+                Some(Some(executor)) => {
+                    let mut budget = self.enter(|ctx| ctx.finalizer_fuel());
+                    loop {
+                        let step_fuel = budget.min(FUEL_PER_GC);
+                        let mut fuel = Fuel::with(step_fuel);
+
+                        let finished = self.enter(|ctx| {
+                            let executor = ctx.fetch(&executor);
+                            executor.step(ctx, &mut fuel)
+                                && !matches!(
+                                    ctx.yield_policy().take_result(ctx, executor),
+                                    Ok(None)
+                                )
+                        });
+                        if finished {
+                            break;
+                        }
+
+                        budget -= step_fuel - fuel.remaining();
+                        if budget <= 0 {
+                            self.enter(|ctx| ctx.warn("__gc metamethod ran out of fuel"));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_memory_pressure(&mut self) {
+        // Checks made by the handler itself are skipped, any change in usage is found by the check
+        // after it returns instead.
+        if self.memory_pressure.running {
+            return;
+        }
+
+        loop {
+            let total_memory = self.total_memory();
+            let level = self
+                .memory_pressure
+                .thresholds
+                .partition_point(|&t| t <= total_memory);
+            let prev_level = mem::replace(&mut self.memory_pressure.level, level);
+            if level <= prev_level {
+                return;
+            }
+
+            let Some(mut handler) = self.memory_pressure.handler.take() else {
+                return;
+            };
+            let generation = self.memory_pressure.generation;
+            let event = MemoryPressureEvent {
+                threshold: self.memory_pressure.thresholds[level - 1],
+                level: level - 1,
+                total_memory,
+            };
+
+            self.memory_pressure.running = true;
+            handler(self, event);
+            self.memory_pressure.running = false;
+
+            if self.memory_pressure.generation == generation {
+                self.memory_pressure.handler = Some(handler);
+            }
+        }
+    }
+
     /// Run the given executor to completion.
     ///
     /// This will periodically exit the arena in order to collect garbage concurrently with running
-    /// Lua code.
+    /// Lua code, and will run any pending `__gc` finalizers in-between steps of the executor.
     pub fn finish(&mut self, executor: &StashedExecutor) {
         const FUEL_PER_GC: i32 = 4096;
 
         loop {
+            if self.enter(|ctx| ctx.finalizers().has_pending()) {
+                self.run_finalizers();
+            }
+
             let mut fuel = Fuel::with(FUEL_PER_GC);
 
             if self.enter(|ctx| ctx.fetch(executor).step(ctx, &mut fuel)) {
@@ -246,6 +987,52 @@ impl Lua {
     }
 }
 
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct WarningHandler(RefCell<Option<Rc<dyn Fn(&str)>>>);
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct FinalizerFuel(Cell<i32>);
+
+impl Default for FinalizerFuel {
+    fn default() -> Self {
+        Self(Cell::new(1 << 20))
+    }
+}
+
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct StringCoercion(Cell<bool>);
+
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct ForbidBinaryChunks(Cell<bool>);
+
+/// Requests made to the garbage collector from inside the arena by `collectgarbage`, which are
+/// carried out by `Lua::enter` once the arena is exited.
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct GcControl {
+    /// Automatic collection is paused until `collectgarbage("restart")`.
+    pub(crate) stopped: Cell<bool>,
+    /// The next time the arena is exited, a full collection cycle is run.
+    pub(crate) collect_requested: Cell<bool>,
+    /// The allocation debt in bytes which is allowed to build up before `Lua::enter` does any
+    /// collector work.
+    pub(crate) granularity: Cell<f64>,
+}
+
+impl Default for GcControl {
+    fn default() -> Self {
+        Self {
+            stopped: Cell::new(false),
+            collect_requested: Cell::new(false),
+            granularity: Cell::new(1024.0),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 struct State<'gc> {
@@ -253,6 +1040,8 @@ struct State<'gc> {
     registry: Registry<'gc>,
     strings: InternedStringSet<'gc>,
     finalizers: Finalizers<'gc>,
+    thread_pool: ThreadPool<'gc>,
+    ext_opcodes: ExtOpcodes<'gc>,
 }
 
 impl<'gc> State<'gc> {
@@ -262,6 +1051,8 @@ impl<'gc> State<'gc> {
             registry: Registry::new(mc),
             strings: InternedStringSet::new(mc),
             finalizers: Finalizers::new(mc),
+            thread_pool: ThreadPool::new(mc),
+            ext_opcodes: ExtOpcodes::new(mc),
         }
     }
 
@@ -0,0 +1,1027 @@
+//! Loading of binary chunks written by PUC-Rio Lua 5.4, either by `luac` or by `string.dump`.
+//!
+//! Lua 5.4 bytecode is translated into piccolo opcodes when it is loaded, so precompiled chunks run
+//! on the same VM as chunks compiled from source. Most Lua instructions map onto a single opcode,
+//! the rest are expanded into short sequences of opcodes. Only chunks written by a little endian
+//! build of Lua with the default 64 bit integer and float types can be loaded, anything else fails
+//! with [`LuacError::BadFormat`].
+//!
+//! The translated prototype is not verified, `Closure::load_binary` always runs it through the
+//! verifier.
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{CompiledPrototype, FunctionRef, LineNumber, LocalVariable},
+    opcode::{OpCode, Operation, RCIndex},
+    types::{
+        ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueDescriptor,
+        UpValueIndex, VarCount,
+    },
+    Constant, Context, FunctionPrototype, String,
+};
+
+/// Every Lua binary chunk starts with these bytes.
+pub const SIGNATURE: &[u8] = b"\x1bLua";
+
+const VERSION: u8 = 0x54;
+const FORMAT: u8 = 0;
+const LUAC_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const LUAC_INT: i64 = 0x5678;
+const LUAC_NUM: f64 = 370.5;
+
+// Chunks nesting prototypes deeper than this are rejected, so that loading a hostile chunk cannot
+// overflow the stack.
+const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum LuacError {
+    #[error("not a Lua binary chunk")]
+    BadSignature,
+    #[error("Lua binary chunk version {0:#x} is not supported")]
+    BadVersion(u8),
+    #[error("Lua binary chunk was written for an incompatible platform")]
+    BadFormat,
+    #[error("truncated Lua binary chunk")]
+    Truncated,
+    #[error("malformed Lua binary chunk")]
+    Malformed,
+    #[error("Lua binary chunk uses {0}, which cannot be translated")]
+    Unsupported(&'static str),
+    #[error("Lua function is too large to be translated")]
+    TooLarge,
+}
+
+/// Read a Lua 5.4 binary chunk and translate it into a prototype.
+pub fn translate<'gc>(
+    ctx: Context<'gc>,
+    chunk: &[u8],
+) -> Result<FunctionPrototype<'gc>, LuacError> {
+    let input = chunk
+        .strip_prefix(SIGNATURE)
+        .ok_or(LuacError::BadSignature)?;
+    let mut reader = Reader { ctx, input };
+    match reader.u8()? {
+        VERSION => {}
+        version => return Err(LuacError::BadVersion(version)),
+    }
+    if reader.u8()? != FORMAT
+        || reader.take(LUAC_DATA.len())? != LUAC_DATA
+        || reader.take(3)? != [4, 8, 8]
+        || reader.integer()? != LUAC_INT
+        || reader.number()? != LUAC_NUM
+    {
+        return Err(LuacError::BadFormat);
+    }
+    // The number of upvalues of the main function, which is repeated in the function itself.
+    reader.u8()?;
+    let function = reader.function(0)?;
+    if !reader.input.is_empty() {
+        return Err(LuacError::Malformed);
+    }
+
+    let chunk_name = function.source.unwrap_or_else(|| ctx.intern_static(b"=?"));
+    let compiled = translate_function(&function, true)?;
+    Ok(FunctionPrototype::from_compiled(
+        &ctx, chunk_name, &compiled,
+    ))
+}
+
+// A function as it is stored in a Lua 5.4 binary chunk.
+struct LuaFunction<'gc> {
+    source: Option<String<'gc>>,
+    line_defined: usize,
+    num_params: u8,
+    is_vararg: bool,
+    max_stack_size: u8,
+    code: Vec<u32>,
+    constants: Vec<Constant<String<'gc>>>,
+    // Whether each upvalue is a local of the enclosing function, and its index there.
+    upvalues: Vec<(bool, u8)>,
+    prototypes: Vec<LuaFunction<'gc>>,
+    line_info: Vec<i8>,
+    abs_line_info: Vec<(usize, usize)>,
+    local_variables: Vec<(String<'gc>, usize, usize)>,
+    upvalue_names: Vec<String<'gc>>,
+}
+
+struct Reader<'gc, 'a> {
+    ctx: Context<'gc>,
+    input: &'a [u8],
+}
+
+impl<'gc, 'a> Reader<'gc, 'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LuacError> {
+        if self.input.len() < len {
+            return Err(LuacError::Truncated);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LuacError> {
+        Ok(self.take(1)?[0])
+    }
+
+    // Sizes are written most significant group first, seven bits at a time, with the high bit set
+    // on the last byte.
+    fn size(&mut self) -> Result<usize, LuacError> {
+        let mut size: usize = 0;
+        loop {
+            let byte = self.u8()?;
+            if size > usize::MAX >> 7 {
+                return Err(LuacError::Malformed);
+            }
+            size = (size << 7) | (byte & 0x7f) as usize;
+            if byte & 0x80 != 0 {
+                return Ok(size);
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, LuacError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn number(&mut self) -> Result<f64, LuacError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<Option<String<'gc>>, LuacError> {
+        match self.size()? {
+            0 => Ok(None),
+            size => {
+                let bytes = self.take(size - 1)?;
+                Ok(Some(self.ctx.intern(bytes)))
+            }
+        }
+    }
+
+    fn function(&mut self, depth: usize) -> Result<LuaFunction<'gc>, LuacError> {
+        if depth > MAX_DEPTH {
+            return Err(LuacError::Malformed);
+        }
+
+        let source = self.string()?;
+        let line_defined = self.size()?;
+        let _last_line_defined = self.size()?;
+        let num_params = self.u8()?;
+        let is_vararg = self.u8()? != 0;
+        let max_stack_size = self.u8()?;
+
+        // Counts are never used to reserve space up front, so a hostile chunk cannot make us
+        // allocate more than its own size.
+        let mut code = Vec::new();
+        for _ in 0..self.size()? {
+            code.push(u32::from_le_bytes(self.take(4)?.try_into().unwrap()));
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0..self.size()? {
+            constants.push(match self.u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Boolean(false),
+                17 => Constant::Boolean(true),
+                3 => Constant::Integer(self.integer()?),
+                19 => Constant::Number(self.number()?),
+                4 | 20 => Constant::String(self.string()?.ok_or(LuacError::Malformed)?),
+                _ => return Err(LuacError::Malformed),
+            });
+        }
+
+        let mut upvalues = Vec::new();
+        for _ in 0..self.size()? {
+            let in_stack = self.u8()? != 0;
+            let index = self.u8()?;
+            let _kind = self.u8()?;
+            upvalues.push((in_stack, index));
+        }
+
+        let mut prototypes = Vec::new();
+        for _ in 0..self.size()? {
+            prototypes.push(self.function(depth + 1)?);
+        }
+
+        let mut line_info = Vec::new();
+        for _ in 0..self.size()? {
+            line_info.push(self.u8()? as i8);
+        }
+        let mut abs_line_info = Vec::new();
+        for _ in 0..self.size()? {
+            let pc = self.size()?;
+            abs_line_info.push((pc, self.size()?));
+        }
+
+        let mut local_variables = Vec::new();
+        for _ in 0..self.size()? {
+            let name = self.string()?.ok_or(LuacError::Malformed)?;
+            let start_pc = self.size()?;
+            let end_pc = self.size()?;
+            if start_pc > end_pc || end_pc > code.len() {
+                return Err(LuacError::Malformed);
+            }
+            local_variables.push((name, start_pc, end_pc));
+        }
+
+        let mut upvalue_names = Vec::new();
+        for _ in 0..self.size()? {
+            upvalue_names.push(self.string()?.ok_or(LuacError::Malformed)?);
+        }
+        if !upvalue_names.is_empty() && upvalue_names.len() != upvalues.len() {
+            return Err(LuacError::Malformed);
+        }
+
+        Ok(LuaFunction {
+            source,
+            line_defined,
+            num_params,
+            is_vararg,
+            max_stack_size,
+            code,
+            constants,
+            upvalues,
+            prototypes,
+            line_info,
+            abs_line_info,
+            local_variables,
+            upvalue_names,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Instruction(u32);
+
+impl Instruction {
+    fn op(self) -> u8 {
+        (self.0 & 0x7f) as u8
+    }
+
+    fn a(self) -> u8 {
+        (self.0 >> 7) as u8
+    }
+
+    fn k(self) -> bool {
+        (self.0 >> 15) & 1 != 0
+    }
+
+    fn b(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    fn c(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    fn sb(self) -> i64 {
+        self.b() as i64 - 127
+    }
+
+    fn sc(self) -> i64 {
+        self.c() as i64 - 127
+    }
+
+    fn bx(self) -> usize {
+        (self.0 >> 15) as usize
+    }
+
+    fn sbx(self) -> i64 {
+        self.bx() as i64 - 65535
+    }
+
+    fn ax(self) -> usize {
+        (self.0 >> 7) as usize
+    }
+
+    fn sj(self) -> isize {
+        (self.0 >> 7) as isize - 16777215
+    }
+}
+
+// Lua 5.4 opcode numbers, in the order of `lopcodes.h`.
+mod op {
+    pub const MOVE: u8 = 0;
+    pub const LOADI: u8 = 1;
+    pub const LOADF: u8 = 2;
+    pub const LOADK: u8 = 3;
+    pub const LOADKX: u8 = 4;
+    pub const LOADFALSE: u8 = 5;
+    pub const LFALSESKIP: u8 = 6;
+    pub const LOADTRUE: u8 = 7;
+    pub const LOADNIL: u8 = 8;
+    pub const GETUPVAL: u8 = 9;
+    pub const SETUPVAL: u8 = 10;
+    pub const GETTABUP: u8 = 11;
+    pub const GETTABLE: u8 = 12;
+    pub const GETI: u8 = 13;
+    pub const GETFIELD: u8 = 14;
+    pub const SETTABUP: u8 = 15;
+    pub const SETTABLE: u8 = 16;
+    pub const SETI: u8 = 17;
+    pub const SETFIELD: u8 = 18;
+    pub const NEWTABLE: u8 = 19;
+    pub const SELF: u8 = 20;
+    pub const ADDI: u8 = 21;
+    pub const ADDK: u8 = 22;
+    pub const SUBK: u8 = 23;
+    pub const MULK: u8 = 24;
+    pub const MODK: u8 = 25;
+    pub const POWK: u8 = 26;
+    pub const DIVK: u8 = 27;
+    pub const IDIVK: u8 = 28;
+    pub const BANDK: u8 = 29;
+    pub const BORK: u8 = 30;
+    pub const BXORK: u8 = 31;
+    pub const SHRI: u8 = 32;
+    pub const SHLI: u8 = 33;
+    pub const ADD: u8 = 34;
+    pub const SUB: u8 = 35;
+    pub const MUL: u8 = 36;
+    pub const MOD: u8 = 37;
+    pub const POW: u8 = 38;
+    pub const DIV: u8 = 39;
+    pub const IDIV: u8 = 40;
+    pub const BAND: u8 = 41;
+    pub const BOR: u8 = 42;
+    pub const BXOR: u8 = 43;
+    pub const SHL: u8 = 44;
+    pub const SHR: u8 = 45;
+    pub const MMBIN: u8 = 46;
+    pub const MMBINI: u8 = 47;
+    pub const MMBINK: u8 = 48;
+    pub const UNM: u8 = 49;
+    pub const BNOT: u8 = 50;
+    pub const NOT: u8 = 51;
+    pub const LEN: u8 = 52;
+    pub const CONCAT: u8 = 53;
+    pub const CLOSE: u8 = 54;
+    pub const TBC: u8 = 55;
+    pub const JMP: u8 = 56;
+    pub const EQ: u8 = 57;
+    pub const LT: u8 = 58;
+    pub const LE: u8 = 59;
+    pub const EQK: u8 = 60;
+    pub const EQI: u8 = 61;
+    pub const LTI: u8 = 62;
+    pub const LEI: u8 = 63;
+    pub const GTI: u8 = 64;
+    pub const GEI: u8 = 65;
+    pub const TEST: u8 = 66;
+    pub const TESTSET: u8 = 67;
+    pub const CALL: u8 = 68;
+    pub const TAILCALL: u8 = 69;
+    pub const RETURN: u8 = 70;
+    pub const RETURN0: u8 = 71;
+    pub const RETURN1: u8 = 72;
+    pub const FORLOOP: u8 = 73;
+    pub const FORPREP: u8 = 74;
+    pub const TFORPREP: u8 = 75;
+    pub const TFORCALL: u8 = 76;
+    pub const TFORLOOP: u8 = 77;
+    pub const SETLIST: u8 = 78;
+    pub const CLOSURE: u8 = 79;
+    pub const VARARG: u8 = 80;
+    pub const VARARGPREP: u8 = 81;
+    pub const EXTRAARG: u8 = 82;
+}
+
+// A translated opcode, with jumps still pointing at Lua instructions rather than opcodes.
+enum Pending {
+    Op(Operation),
+    Jump {
+        target: usize,
+        close_upvalues: Opt254,
+    },
+    ForPrep {
+        base: RegisterIndex,
+        target: usize,
+    },
+    ForLoop {
+        base: RegisterIndex,
+        target: usize,
+    },
+}
+
+struct Translator<'a, 'gc> {
+    function: &'a LuaFunction<'gc>,
+    constants: Vec<Constant<String<'gc>>>,
+    stack_size: u16,
+    pending: Vec<Pending>,
+}
+
+fn translate_function<'gc>(
+    function: &LuaFunction<'gc>,
+    is_main: bool,
+) -> Result<CompiledPrototype<String<'gc>>, LuacError> {
+    let mut translator = Translator {
+        function,
+        constants: function.constants.clone(),
+        stack_size: function.max_stack_size as u16,
+        pending: Vec::new(),
+    };
+
+    // The index of the first opcode translated from each Lua instruction, followed by the total
+    // number of opcodes.
+    let mut starts = Vec::with_capacity(function.code.len() + 1);
+    for pc in 0..function.code.len() {
+        starts.push(translator.pending.len());
+        translator.instruction(pc)?;
+    }
+    starts.push(translator.pending.len());
+
+    let code = &function.code;
+    for pc in 0..code.len() {
+        // Lua instructions which skip the next instruction must skip exactly one opcode.
+        let skips = match Instruction(code[pc]).op() {
+            op::EQ..=op::TESTSET => true,
+            op::LFALSESKIP => true,
+            _ => false,
+        };
+        if skips && (pc + 2 >= starts.len() || starts[pc + 2] != starts[pc + 1] + 1) {
+            return Err(LuacError::Malformed);
+        }
+    }
+
+    let jump = |from: usize, target: usize| -> Result<i16, LuacError> {
+        let target = *starts.get(target).ok_or(LuacError::Malformed)?;
+        i16::try_from(target as isize - from as isize - 1).map_err(|_| LuacError::TooLarge)
+    };
+    let mut opcodes = Vec::with_capacity(translator.pending.len());
+    for (index, pending) in translator.pending.iter().enumerate() {
+        opcodes.push(OpCode::encode(match *pending {
+            Pending::Op(operation) => operation,
+            Pending::Jump {
+                target,
+                close_upvalues,
+            } => Operation::Jump {
+                offset: jump(index, target)?,
+                close_upvalues,
+            },
+            Pending::ForPrep { base, target } => Operation::NumericForPrep {
+                base,
+                jump: jump(index, target)?,
+            },
+            Pending::ForLoop { base, target } => Operation::NumericForLoop {
+                base,
+                jump: jump(index, target)?,
+            },
+        }));
+    }
+
+    let mut opcode_line_numbers = Vec::new();
+    let mut line = function.line_defined;
+    let mut abs_line_info = function.abs_line_info.iter();
+    for (pc, &delta) in function.line_info.iter().enumerate().take(code.len()) {
+        if delta == -128 {
+            line = abs_line_info
+                .find(|&&(abs_pc, _)| abs_pc == pc)
+                .ok_or(LuacError::Malformed)?
+                .1;
+        } else {
+            line = line.wrapping_add_signed(delta as isize);
+        }
+        // Lua lines start at 1, piccolo lines start at 0.
+        let line = LineNumber(line.saturating_sub(1) as u64);
+        if starts[pc] < starts[pc + 1] && opcode_line_numbers.last().map(|&(_, l)| l) != Some(line)
+        {
+            opcode_line_numbers.push((starts[pc], line));
+        }
+    }
+    if opcode_line_numbers.is_empty() {
+        opcode_line_numbers.push((0, LineNumber(0)));
+    }
+
+    let mut upvalues = Vec::new();
+    for &(in_stack, index) in &function.upvalues {
+        upvalues.push(match (is_main, in_stack) {
+            // The only upvalue of a main chunk is `_ENV`.
+            (true, true) => UpValueDescriptor::Environment,
+            (false, true) => UpValueDescriptor::ParentLocal(RegisterIndex(index)),
+            (_, false) => UpValueDescriptor::Outer(UpValueIndex(index)),
+        });
+    }
+
+    // Lua does not store the register of each local, it is the number of locals which are still
+    // in scope when it is declared.
+    let mut local_variables = Vec::new();
+    for (i, &(name, start_pc, end_pc)) in function.local_variables.iter().enumerate() {
+        let register = function.local_variables[..i]
+            .iter()
+            .filter(|&&(_, s, e)| s <= start_pc && start_pc < e)
+            .count();
+        local_variables.push(LocalVariable {
+            name,
+            register: RegisterIndex(u8::try_from(register).map_err(|_| LuacError::Malformed)?),
+            start_pc: starts[start_pc],
+            end_pc: starts[end_pc],
+        });
+    }
+
+    if function.prototypes.len() > 256 {
+        return Err(LuacError::TooLarge);
+    }
+    let mut prototypes = Vec::new();
+    for proto in &function.prototypes {
+        prototypes.push(Box::new(translate_function(proto, false)?));
+    }
+
+    Ok(CompiledPrototype {
+        reference: if is_main {
+            FunctionRef::Chunk
+        } else {
+            FunctionRef::Expression(LineNumber(function.line_defined.saturating_sub(1) as u64))
+        },
+        fixed_params: function.num_params,
+        has_varargs: function.is_vararg,
+        stack_size: translator.stack_size,
+        constants: translator.constants,
+        opcodes,
+        opcode_line_numbers,
+        upvalues,
+        local_variables,
+        upvalue_names: function.upvalue_names.clone(),
+        prototypes,
+    })
+}
+
+impl<'a, 'gc> Translator<'a, 'gc> {
+    fn instruction(&mut self, pc: usize) -> Result<(), LuacError> {
+        let i = Instruction(self.function.code[pc]);
+        let a = RegisterIndex(i.a());
+        let b = RegisterIndex(i.b());
+        let c = RegisterIndex(i.c());
+        let rk = || {
+            if i.k() {
+                RCIndex::Constant(ConstantIndex8(i.c()))
+            } else {
+                RCIndex::Register(c)
+            }
+        };
+
+        let operation = match i.op() {
+            op::MOVE => Operation::Move { dest: a, source: b },
+            op::LOADI => self.load_constant(a, Constant::Integer(i.sbx()))?,
+            op::LOADF => self.load_constant(a, Constant::Number(i.sbx() as f64))?,
+            op::LOADK => Operation::LoadConstant {
+                dest: a,
+                constant: constant16(i.bx())?,
+            },
+            op::LOADKX => Operation::LoadConstant {
+                dest: a,
+                constant: constant16(self.extra_arg(pc)?)?,
+            },
+            op::LOADFALSE | op::LFALSESKIP | op::LOADTRUE => Operation::LoadBool {
+                dest: a,
+                value: i.op() == op::LOADTRUE,
+                skip_next: i.op() == op::LFALSESKIP,
+            },
+            op::LOADNIL => Operation::LoadNil {
+                dest: a,
+                count: i.b().checked_add(1).ok_or(LuacError::TooLarge)?,
+            },
+            op::GETUPVAL => Operation::GetUpValue {
+                dest: a,
+                source: UpValueIndex(i.b()),
+            },
+            op::SETUPVAL => Operation::SetUpValue {
+                dest: UpValueIndex(i.b()),
+                source: a,
+            },
+            op::GETTABUP => Operation::GetUpTable {
+                dest: a,
+                table: UpValueIndex(i.b()),
+                key: RCIndex::Constant(ConstantIndex8(i.c())),
+            },
+            op::GETTABLE => Operation::GetTable {
+                dest: a,
+                table: b,
+                key: RCIndex::Register(c),
+            },
+            op::GETI => Operation::GetTable {
+                dest: a,
+                table: b,
+                key: self.constant8(Constant::Integer(i.c() as i64))?,
+            },
+            op::GETFIELD => Operation::GetTable {
+                dest: a,
+                table: b,
+                key: RCIndex::Constant(ConstantIndex8(i.c())),
+            },
+            op::SETTABUP => Operation::SetUpTable {
+                table: UpValueIndex(i.a()),
+                key: RCIndex::Constant(ConstantIndex8(i.b())),
+                value: rk(),
+            },
+            op::SETTABLE => Operation::SetTable {
+                table: a,
+                key: RCIndex::Register(b),
+                value: rk(),
+            },
+            op::SETI => Operation::SetTable {
+                table: a,
+                key: self.constant8(Constant::Integer(i.b() as i64))?,
+                value: rk(),
+            },
+            op::SETFIELD => Operation::SetTable {
+                table: a,
+                key: RCIndex::Constant(ConstantIndex8(i.b())),
+                value: rk(),
+            },
+            op::NEWTABLE => {
+                let mut array_size = i.c() as usize;
+                if i.k() {
+                    array_size += self.extra_arg(pc)? * 256;
+                }
+                let map_size = match i.b() {
+                    0 => 0,
+                    b => 1usize.checked_shl(b as u32 - 1).unwrap_or(usize::MAX),
+                };
+                Operation::NewTable {
+                    dest: a,
+                    array_size: array_size.min(u8::MAX as usize) as u8,
+                    map_size: map_size.min(u8::MAX as usize) as u8,
+                }
+            }
+            op::SELF => Operation::Method {
+                base: a,
+                table: b,
+                key: rk(),
+            },
+            op::ADDI => Operation::Add {
+                dest: a,
+                left: RCIndex::Register(b),
+                right: self.constant8(Constant::Integer(i.sc()))?,
+            },
+            op::ADDK..=op::BXORK => {
+                let left = RCIndex::Register(b);
+                let right = RCIndex::Constant(ConstantIndex8(i.c()));
+                arithmetic(i.op() - op::ADDK + op::ADD, a, left, right)
+            }
+            op::SHRI => Operation::ShiftRight {
+                dest: a,
+                left: RCIndex::Register(b),
+                right: self.constant8(Constant::Integer(i.sc()))?,
+            },
+            op::SHLI => Operation::ShiftLeft {
+                dest: a,
+                left: self.constant8(Constant::Integer(i.sc()))?,
+                right: RCIndex::Register(b),
+            },
+            op::ADD..=op::SHR => arithmetic(i.op(), a, RCIndex::Register(b), RCIndex::Register(c)),
+            // Arithmetic opcodes call metamethods themselves.
+            op::MMBIN | op::MMBINI | op::MMBINK => return Ok(()),
+            op::UNM => Operation::Minus { dest: a, source: b },
+            op::BNOT => Operation::BitNot { dest: a, source: b },
+            op::NOT => Operation::Not { dest: a, source: b },
+            op::LEN => Operation::Length { dest: a, source: b },
+            op::CONCAT => Operation::Concat {
+                dest: a,
+                source: a,
+                count: i.b(),
+            },
+            op::CLOSE => Operation::Jump {
+                offset: 0,
+                close_upvalues: Opt254::try_some(i.a()).ok_or(LuacError::TooLarge)?,
+            },
+            op::TBC => Operation::ToBeClosed { value: a },
+            op::JMP => {
+                let target = (pc as isize + 1 + i.sj()) as usize;
+                self.pending.push(Pending::Jump {
+                    target,
+                    close_upvalues: Opt254::none(),
+                });
+                return Ok(());
+            }
+            op::EQ => Operation::Eq {
+                skip_if: !i.k(),
+                left: RCIndex::Register(a),
+                right: RCIndex::Register(b),
+            },
+            op::LT => Operation::Less {
+                skip_if: !i.k(),
+                left: RCIndex::Register(a),
+                right: RCIndex::Register(b),
+            },
+            op::LE => Operation::LessEq {
+                skip_if: !i.k(),
+                left: RCIndex::Register(a),
+                right: RCIndex::Register(b),
+            },
+            op::EQK => Operation::Eq {
+                skip_if: !i.k(),
+                left: RCIndex::Register(a),
+                right: RCIndex::Constant(ConstantIndex8(i.b())),
+            },
+            op::EQI..=op::GEI => {
+                // A non-zero C means the immediate was written as a float.
+                let immediate = self.constant8(if i.c() != 0 {
+                    Constant::Number(i.sb() as f64)
+                } else {
+                    Constant::Integer(i.sb())
+                })?;
+                let register = RCIndex::Register(a);
+                let skip_if = !i.k();
+                match i.op() {
+                    op::EQI => Operation::Eq {
+                        skip_if,
+                        left: register,
+                        right: immediate,
+                    },
+                    op::LTI => Operation::Less {
+                        skip_if,
+                        left: register,
+                        right: immediate,
+                    },
+                    op::LEI => Operation::LessEq {
+                        skip_if,
+                        left: register,
+                        right: immediate,
+                    },
+                    op::GTI => Operation::Less {
+                        skip_if,
+                        left: immediate,
+                        right: register,
+                    },
+                    _ => Operation::LessEq {
+                        skip_if,
+                        left: immediate,
+                        right: register,
+                    },
+                }
+            }
+            op::TEST => Operation::Test {
+                value: a,
+                is_true: !i.k(),
+            },
+            op::TESTSET => Operation::TestSet {
+                dest: a,
+                value: b,
+                is_true: !i.k(),
+            },
+            op::CALL => {
+                let args = var_count(i.b())?;
+                let returns = var_count(i.c())?;
+                match self.variable_set_list(pc)? {
+                    Some((table, start)) => {
+                        let args = args
+                            .to_constant()
+                            .ok_or(LuacError::Unsupported("a nested variable argument call"))?;
+                        self.shift_set_list(table, i.a() as usize + args as usize, start)?;
+                        Operation::Call {
+                            func: self.register(i.a(), 1)?,
+                            args: VarCount::constant(args),
+                            returns,
+                        }
+                    }
+                    None => Operation::Call {
+                        func: a,
+                        args,
+                        returns,
+                    },
+                }
+            }
+            op::TAILCALL => Operation::TailCall {
+                func: a,
+                args: var_count(i.b())?,
+            },
+            op::RETURN => Operation::Return {
+                start: a,
+                count: var_count(i.b())?,
+            },
+            op::RETURN0 => Operation::Return {
+                start: a,
+                count: VarCount::constant(0),
+            },
+            op::RETURN1 => Operation::Return {
+                start: a,
+                count: VarCount::constant(1),
+            },
+            op::FORPREP => {
+                // Lua skips the loop by jumping past its `FORLOOP`, piccolo jumps to it.
+                self.pending.push(Pending::ForPrep {
+                    base: a,
+                    target: pc + i.bx() + 1,
+                });
+                return Ok(());
+            }
+            op::FORLOOP => {
+                let target = (pc + 1).checked_sub(i.bx()).ok_or(LuacError::Malformed)?;
+                self.pending.push(Pending::ForLoop { base: a, target });
+                return Ok(());
+            }
+            op::TFORPREP => {
+                self.push(Operation::ToBeClosed {
+                    value: self.register(i.a(), 3)?,
+                });
+                self.pending.push(Pending::Jump {
+                    target: pc + i.bx() + 1,
+                    close_upvalues: Opt254::none(),
+                });
+                return Ok(());
+            }
+            op::TFORCALL => {
+                // Lua calls a copy of the iterator, state and control variable, placing the
+                // results above the to-be-closed variable.
+                for n in 0..3 {
+                    self.push(Operation::Move {
+                        dest: self.register(i.a(), 4 + n)?,
+                        source: self.register(i.a(), n)?,
+                    });
+                }
+                Operation::Call {
+                    func: self.register(i.a(), 4)?,
+                    args: VarCount::constant(2),
+                    returns: VarCount::try_constant(i.c()).ok_or(LuacError::TooLarge)?,
+                }
+            }
+            op::TFORLOOP => {
+                // If the first result is not nil, it becomes the new control variable and the
+                // loop continues.
+                let first = self.register(i.a(), 4)?;
+                let target = (pc + 1).checked_sub(i.bx()).ok_or(LuacError::Malformed)?;
+                let nil = self.constant8(Constant::Nil)?;
+                self.push(Operation::Eq {
+                    skip_if: false,
+                    left: RCIndex::Register(first),
+                    right: nil,
+                });
+                self.push(Operation::Jump {
+                    offset: 2,
+                    close_upvalues: Opt254::none(),
+                });
+                self.push(Operation::Move {
+                    dest: self.register(i.a(), 2)?,
+                    source: first,
+                });
+                self.pending.push(Pending::Jump {
+                    target,
+                    close_upvalues: Opt254::none(),
+                });
+                return Ok(());
+            }
+            op::SETLIST => {
+                if i.b() == 0 {
+                    // The values were already moved into place by the preceding `CALL` or
+                    // `VARARG`.
+                    Operation::SetList {
+                        base: a,
+                        count: VarCount::variable(),
+                    }
+                } else {
+                    let start = self.set_list_start(pc)?;
+                    self.shift_set_list(i.a(), i.a() as usize + i.b() as usize, start)?;
+                    Operation::SetList {
+                        base: a,
+                        count: VarCount::try_constant(i.b()).ok_or(LuacError::TooLarge)?,
+                    }
+                }
+            }
+            op::CLOSURE => Operation::Closure {
+                dest: a,
+                proto: PrototypeIndex(u8::try_from(i.bx()).map_err(|_| LuacError::TooLarge)?),
+            },
+            op::VARARG => {
+                let count = var_count(i.c())?;
+                match self.variable_set_list(pc)? {
+                    Some((table, start)) => {
+                        self.shift_set_list(table, i.a() as usize - 1, start)?;
+                        Operation::VarArgs {
+                            dest: self.register(i.a(), 1)?,
+                            count,
+                        }
+                    }
+                    None => Operation::VarArgs { dest: a, count },
+                }
+            }
+            // Varargs are collected when a function is called, and extra arguments are only read
+            // by the instructions they belong to.
+            op::VARARGPREP | op::EXTRAARG => return Ok(()),
+            _ => return Err(LuacError::Malformed),
+        };
+        self.push(operation);
+        Ok(())
+    }
+
+    fn push(&mut self, operation: Operation) {
+        self.pending.push(Pending::Op(operation));
+    }
+
+    fn register(&self, base: u8, offset: u8) -> Result<RegisterIndex, LuacError> {
+        base.checked_add(offset)
+            .map(RegisterIndex)
+            .ok_or(LuacError::TooLarge)
+    }
+
+    fn extra_arg(&self, pc: usize) -> Result<usize, LuacError> {
+        match self.function.code.get(pc + 1).copied().map(Instruction) {
+            Some(i) if i.op() == op::EXTRAARG => Ok(i.ax()),
+            _ => Err(LuacError::Malformed),
+        }
+    }
+
+    // Returns the index of a constant, adding it if the function does not already have it.
+    fn constant(&mut self, constant: Constant<String<'gc>>) -> usize {
+        let found = self.constants.iter().position(|c| match (c, &constant) {
+            (Constant::Nil, Constant::Nil) => true,
+            (Constant::Integer(a), Constant::Integer(b)) => a == b,
+            (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        });
+        found.unwrap_or_else(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        })
+    }
+
+    fn constant8(&mut self, constant: Constant<String<'gc>>) -> Result<RCIndex, LuacError> {
+        let index = u8::try_from(self.constant(constant)).map_err(|_| LuacError::TooLarge)?;
+        Ok(RCIndex::Constant(ConstantIndex8(index)))
+    }
+
+    fn load_constant(
+        &mut self,
+        dest: RegisterIndex,
+        constant: Constant<String<'gc>>,
+    ) -> Result<Operation, LuacError> {
+        Ok(Operation::LoadConstant {
+            dest,
+            constant: constant16(self.constant(constant))?,
+        })
+    }
+
+    // The number of values already stored by earlier `SETLIST` instructions of the same table.
+    fn set_list_start(&self, pc: usize) -> Result<usize, LuacError> {
+        let i = Instruction(self.function.code[pc]);
+        let mut start = i.c() as usize;
+        if i.k() {
+            start += self.extra_arg(pc)? * 256;
+        }
+        Ok(start)
+    }
+
+    // If the instruction at `pc` produces a variable number of values which are stored into a
+    // table by the next instruction, returns the table register and the list start index.
+    fn variable_set_list(&self, pc: usize) -> Result<Option<(u8, usize)>, LuacError> {
+        let i = Instruction(self.function.code[pc]);
+        match self.function.code.get(pc + 1).copied().map(Instruction) {
+            Some(next) if i.c() == 0 && next.op() == op::SETLIST && next.b() == 0 => {
+                if next.a() >= i.a() {
+                    return Err(LuacError::Malformed);
+                }
+                Ok(Some((next.a(), self.set_list_start(pc + 1)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Lua stores list values directly above the table, while piccolo keeps the list index there.
+    // Moves the registers `table + 1..=last` up by one and loads the list index below them.
+    fn shift_set_list(&mut self, table: u8, last: usize, start: usize) -> Result<(), LuacError> {
+        // The register above `last` must also exist, since a variable number of values may be
+        // placed there.
+        let top = u8::try_from(last + 2).map_err(|_| LuacError::TooLarge)?;
+        self.stack_size = self.stack_size.max(top as u16);
+        for r in (table as usize + 1..=last).rev() {
+            self.push(Operation::Move {
+                dest: RegisterIndex(r as u8 + 1),
+                source: RegisterIndex(r as u8),
+            });
+        }
+        let index = self.register(table, 1)?;
+        let load = self.load_constant(index, Constant::Integer(start as i64))?;
+        self.push(load);
+        Ok(())
+    }
+}
+
+fn arithmetic(opcode: u8, dest: RegisterIndex, left: RCIndex, right: RCIndex) -> Operation {
+    match opcode {
+        op::ADD => Operation::Add { dest, left, right },
+        op::SUB => Operation::Sub { dest, left, right },
+        op::MUL => Operation::Mul { dest, left, right },
+        op::MOD => Operation::Mod { dest, left, right },
+        op::POW => Operation::Pow { dest, left, right },
+        op::DIV => Operation::Div { dest, left, right },
+        op::IDIV => Operation::IDiv { dest, left, right },
+        op::BAND => Operation::BitAnd { dest, left, right },
+        op::BOR => Operation::BitOr { dest, left, right },
+        op::BXOR => Operation::BitXor { dest, left, right },
+        op::SHL => Operation::ShiftLeft { dest, left, right },
+        _ => Operation::ShiftRight { dest, left, right },
+    }
+}
+
+// Lua counts are one more than the real count, with zero meaning a variable count.
+fn var_count(count: u8) -> Result<VarCount, LuacError> {
+    match count {
+        0 => Ok(VarCount::variable()),
+        count => VarCount::try_constant(count - 1).ok_or(LuacError::TooLarge),
+    }
+}
+
+fn constant16(index: usize) -> Result<ConstantIndex16, LuacError> {
+    Ok(ConstantIndex16(
+        u16::try_from(index).map_err(|_| LuacError::TooLarge)?,
+    ))
+}
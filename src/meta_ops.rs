@@ -1,15 +1,19 @@
-use gc_arena::Collect;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    string::String as StdString,
+};
+
+use ahash::HashSet;
+use gc_arena::{Collect, Rootable};
 use thiserror::Error;
 
 use crate::{
-    Callback, CallbackReturn, Context, Function, IntoValue, InvalidTableKey, Table, Value,
+    table::NextValue, BoxSequence, Callback, CallbackReturn, Constant, Context, Error, Execution,
+    Function, IntoValue, InvalidTableKey, Sequence, SequencePoll, Singleton, Stack, String, Table,
+    Value,
 };
 
-// TODO: Remaining metamethods to implement:
-// - Lt
-// - Le
-// - Concat
-
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Collect)]
 #[collect(require_static)]
 pub enum MetaMethod {
@@ -37,9 +41,45 @@ pub enum MetaMethod {
     Concat,
     Lt,
     Le,
+    Close,
+    Gc,
 }
 
 impl MetaMethod {
+    pub const ALL: [MetaMethod; 26] = [
+        MetaMethod::Len,
+        MetaMethod::Index,
+        MetaMethod::NewIndex,
+        MetaMethod::Call,
+        MetaMethod::Pairs,
+        MetaMethod::ToString,
+        MetaMethod::Eq,
+        MetaMethod::Add,
+        MetaMethod::Sub,
+        MetaMethod::Mul,
+        MetaMethod::Div,
+        MetaMethod::Mod,
+        MetaMethod::Pow,
+        MetaMethod::Unm,
+        MetaMethod::IDiv,
+        MetaMethod::BAnd,
+        MetaMethod::BOr,
+        MetaMethod::BXor,
+        MetaMethod::BNot,
+        MetaMethod::Shl,
+        MetaMethod::Shr,
+        MetaMethod::Concat,
+        MetaMethod::Lt,
+        MetaMethod::Le,
+        MetaMethod::Close,
+        MetaMethod::Gc,
+    ];
+
+    /// Returns the built-in metamethod with the given name, such as `"__index"`.
+    pub fn from_name(name: &str) -> Option<MetaMethod> {
+        MetaMethod::ALL.into_iter().find(|m| m.name() == name)
+    }
+
     pub const fn name(self) -> &'static str {
         match self {
             MetaMethod::Len => "__len",
@@ -66,6 +106,8 @@ impl MetaMethod {
             MetaMethod::Concat => "__concat",
             MetaMethod::Lt => "__lt",
             MetaMethod::Le => "__le",
+            MetaMethod::Close => "__close",
+            MetaMethod::Gc => "__gc",
         }
     }
 
@@ -100,6 +142,8 @@ impl MetaMethod {
             MetaMethod::Concat => "concatenate",
             MetaMethod::Lt => "compare less than", // ???
             MetaMethod::Le => "compare less than or equal", // ???
+            MetaMethod::Close => "close",
+            MetaMethod::Gc => "finalize",
         }
     }
 }
@@ -124,6 +168,58 @@ pub enum MetaResult<'gc, const N: usize> {
     Call(MetaCall<'gc, N>),
 }
 
+impl<'gc, const N: usize> MetaCall<'gc, N> {
+    /// Push the metamethod arguments onto the top of the stack and return a `SequencePoll` which
+    /// calls the metamethod with them.
+    ///
+    /// The results of the metamethod will be placed on the stack where its arguments were, so they
+    /// will begin at the current top of the stack the next time the sequence is polled.
+    pub fn into_sequence_poll(self, stack: &mut Stack<'gc, '_>) -> SequencePoll<'gc> {
+        let bottom = stack.len();
+        stack.extend(self.args);
+        SequencePoll::Call {
+            function: self.function,
+            bottom,
+        }
+    }
+
+    /// Replace the contents of the stack with the metamethod arguments and return a
+    /// `CallbackReturn` which calls the metamethod, optionally followed by a sequence.
+    pub fn into_callback_return(
+        self,
+        stack: &mut Stack<'gc, '_>,
+        then: Option<BoxSequence<'gc>>,
+    ) -> CallbackReturn<'gc> {
+        stack.clear();
+        stack.extend(self.args);
+        CallbackReturn::Call {
+            function: self.function,
+            then,
+        }
+    }
+}
+
+impl<'gc, const N: usize> MetaResult<'gc, N> {
+    /// Return the result of a metamethod operation from a callback.
+    ///
+    /// If the result is a value, the stack is replaced with it. Otherwise, the stack is replaced
+    /// with the metamethod arguments and the metamethod is called, so the callback returns whatever
+    /// the metamethod returns.
+    pub fn into_callback_return(
+        self,
+        ctx: Context<'gc>,
+        stack: &mut Stack<'gc, '_>,
+    ) -> CallbackReturn<'gc> {
+        match self {
+            MetaResult::Value(v) => {
+                stack.replace(ctx, v);
+                CallbackReturn::Return
+            }
+            MetaResult::Call(call) => call.into_callback_return(stack, None),
+        }
+    }
+}
+
 impl<'gc, const N: usize> From<Value<'gc>> for MetaResult<'gc, N> {
     fn from(value: Value<'gc>) -> Self {
         Self::Value(value)
@@ -141,22 +237,102 @@ pub enum MetaOperatorError {
     #[error("could not call metamethod {}: {}", .0.name(), .1)]
     Call(MetaMethod, #[source] MetaCallError),
     #[error("could not {} a {} value", .0.verb(), .1)]
-    Unary(MetaMethod, &'static str),
+    Unary(MetaMethod, Cow<'static, str>),
     #[error("could not {} values of type {} and {}", .0.verb(), .1, .2)]
-    Binary(MetaMethod, &'static str, &'static str),
+    Binary(MetaMethod, Cow<'static, str>, Cow<'static, str>),
+    #[error("'{}' chain too long; possible loop", .0.name())]
+    ChainTooLong(MetaMethod),
     #[error(transparent)]
     IndexKeyError(#[from] InvalidTableKey),
 }
 
-#[derive(Debug, Copy, Clone, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("could not call a {} value", .0)]
-pub struct MetaCallError(&'static str);
+pub struct MetaCallError(Cow<'static, str>);
 
-fn get_metatable<'gc>(val: Value<'gc>) -> Option<Table<'gc>> {
+/// Returns the name of the type of a value, for use in error messages.
+///
+/// This is the `__name` field of the value's metatable if it is a string, and otherwise the plain
+/// Lua type name as returned by `Value::type_name`.
+pub fn type_name<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Cow<'static, str> {
+    match metatable_name(ctx, v) {
+        Some(name) => Cow::Owned(name.to_str_lossy().into_owned()),
+        None => Cow::Borrowed(v.type_name()),
+    }
+}
+
+fn metatable_name<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Option<String<'gc>> {
+    match get_metatable(ctx, v)?.get(ctx, "__name") {
+        Value::String(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the metatable of any value.
+///
+/// Tables and userdata have their own individual metatables, while every other value shares a
+/// single metatable with all other values of the same type (see [`set_metatable`]).
+pub fn get_metatable<'gc>(ctx: Context<'gc>, val: Value<'gc>) -> Option<Table<'gc>> {
     match val {
         Value::Table(t) => t.metatable(),
         Value::UserData(u) => u.metatable(),
-        _ => None,
+        _ => match ctx
+            .singleton::<Rootable![TypeMetatables<'_>]>()
+            .0
+            .get_value(TypeMetatables::slot(val))
+        {
+            Value::Table(mt) => Some(mt),
+            _ => None,
+        },
+    }
+}
+
+/// Sets the metatable of any value, with the semantics of `debug.setmetatable`.
+///
+/// For tables and userdata, this sets the metatable of that individual value. For any other type
+/// of value, this sets the metatable shared by every value of the same type, so for example
+/// setting the metatable of a string sets the metatable of all strings. Integers and floats share
+/// a single metatable.
+pub fn set_metatable<'gc>(ctx: Context<'gc>, val: Value<'gc>, metatable: Option<Table<'gc>>) {
+    match val {
+        Value::Table(t) => {
+            t.apply_metatable(ctx, metatable);
+        }
+        Value::UserData(u) => {
+            u.apply_metatable(ctx, metatable);
+        }
+        _ => {
+            ctx.singleton::<Rootable![TypeMetatables<'_>]>()
+                .0
+                .set_value(&ctx, TypeMetatables::slot(val), metatable.into_value(ctx))
+                .unwrap();
+        }
+    }
+}
+
+// Metatables shared by all values of a type other than table and userdata, keyed by a small
+// integer for each type.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct TypeMetatables<'gc>(Table<'gc>);
+
+impl<'gc> Singleton<'gc> for TypeMetatables<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        TypeMetatables(Table::new(&ctx))
+    }
+}
+
+impl<'gc> TypeMetatables<'gc> {
+    fn slot(val: Value<'gc>) -> Value<'gc> {
+        Value::Integer(match val {
+            Value::Nil => 1,
+            Value::Boolean(_) => 2,
+            Value::Integer(_) | Value::Number(_) => 3,
+            Value::String(_) => 4,
+            Value::Function(_) => 5,
+            Value::Thread(_) => 6,
+            Value::Table(_) | Value::UserData(_) => unreachable!(),
+        })
     }
 }
 
@@ -165,109 +341,161 @@ fn get_metamethod<'gc>(
     val: Value<'gc>,
     method: MetaMethod,
 ) -> Option<Value<'gc>> {
-    get_metatable(val)
+    get_metatable(ctx, val)
         .map(|mt| mt.get(ctx, method))
         .filter(|v| !v.is_nil())
 }
 
-pub fn index<'gc>(
+/// Metatable fields which have a meaning to the runtime but are not metamethods.
+const RESERVED_METAFIELDS: [&str; 3] = ["__name", "__mode", "__metatable"];
+
+#[derive(Debug, Clone, Error)]
+pub enum CustomMetaMethodError {
+    #[error("custom metamethod name {0:?} must start with \"__\"")]
+    InvalidName(StdString),
+    #[error("{0:?} is a built-in metamethod or metafield")]
+    Reserved(StdString),
+    #[error("custom metamethod {0:?} is not registered")]
+    NotRegistered(StdString),
+}
+
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct CustomMetaMethods(RefCell<HashSet<StdString>>);
+
+/// Register an additional metamethod name, such as `"__serialize"` or `"__clone"`.
+///
+/// Custom metamethods are never called by the VM itself, they are a way for hosts to establish
+/// their own metatable conventions which their callbacks can look up with [`custom`]. The name
+/// must start with `__` and must not be the name of a built-in metamethod or metafield.
+/// Registering the same name more than once is allowed.
+pub fn register_custom<'gc>(ctx: Context<'gc>, name: &str) -> Result<(), CustomMetaMethodError> {
+    if !name.starts_with("__") || name.len() == 2 {
+        return Err(CustomMetaMethodError::InvalidName(name.to_owned()));
+    }
+    if MetaMethod::from_name(name).is_some() || RESERVED_METAFIELDS.contains(&name) {
+        return Err(CustomMetaMethodError::Reserved(name.to_owned()));
+    }
+
+    ctx.singleton::<Rootable![CustomMetaMethods]>()
+        .0
+        .borrow_mut()
+        .insert(name.to_owned());
+    Ok(())
+}
+
+/// Returns whether a custom metamethod name has been registered with [`register_custom`].
+pub fn is_custom<'gc>(ctx: Context<'gc>, name: &str) -> bool {
+    ctx.singleton::<Rootable![CustomMetaMethods]>()
+        .0
+        .borrow()
+        .contains(name)
+}
+
+/// Look up a custom metamethod registered with [`register_custom`] on a value.
+///
+/// The lookup is the same as for built-in metamethods: the field is read from the metatable of
+/// a table or userdata without triggering any metamethods, and a `nil` field is treated as
+/// missing. The field can hold any value, use [`call`] to call it if it should be a function.
+pub fn custom<'gc>(
     ctx: Context<'gc>,
-    table: Value<'gc>,
-    key: Value<'gc>,
-) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
-    let idx = match table {
-        Value::Table(table) => {
-            let v = table.get(ctx, key);
-            if !v.is_nil() {
-                return Ok(MetaResult::Value(v));
-            }
+    v: Value<'gc>,
+    name: &str,
+) -> Result<Option<Value<'gc>>, CustomMetaMethodError> {
+    if !is_custom(ctx, name) {
+        return Err(CustomMetaMethodError::NotRegistered(name.to_owned()));
+    }
 
-            let idx = if let Some(mt) = table.metatable() {
-                mt.get(ctx, MetaMethod::Index)
-            } else {
-                Value::Nil
-            };
+    Ok(get_metatable(ctx, v)
+        .map(|mt| mt.get_value(ctx.intern(name.as_bytes()).into()))
+        .filter(|v| !v.is_nil()))
+}
 
-            if idx.is_nil() {
-                return Ok(MetaResult::Value(Value::Nil));
-            }
+/// The default maximum length of a chain of `__index` or `__newindex` tables, matching
+/// `MAXTAGLOOP` in PUC-Rio Lua.
+pub const DEFAULT_META_CHAIN_LIMIT: usize = 2000;
 
-            idx
-        }
-        Value::UserData(u) if u.metatable().is_some() => {
-            let idx = if let Some(mt) = u.metatable() {
-                mt.get(ctx, MetaMethod::Index)
-            } else {
-                Value::Nil
-            };
+#[derive(Collect)]
+#[collect(require_static)]
+struct MetaChainLimit(Cell<usize>);
 
-            if idx.is_nil() {
-                return Err(MetaOperatorError::Unary(
-                    MetaMethod::Index,
-                    table.type_name(),
-                ));
-            }
+impl Default for MetaChainLimit {
+    fn default() -> Self {
+        MetaChainLimit(Cell::new(DEFAULT_META_CHAIN_LIMIT))
+    }
+}
 
-            idx
-        }
-        _ => {
-            return Err(MetaOperatorError::Unary(
-                MetaMethod::Index,
-                table.type_name(),
-            ))
-        }
-    };
+/// Returns the maximum number of `__index` or `__newindex` tables that will be followed by a
+/// single index operation before raising an error.
+pub fn meta_chain_limit<'gc>(ctx: Context<'gc>) -> usize {
+    ctx.singleton::<Rootable![MetaChainLimit]>().0.get()
+}
 
-    // NOTE: The __index metamethod (and others) can easily infinite loop or enter arbitrarily long
-    // chains:
-    //
-    // `t = {}; setmetatable(t, { __index = t }); t.a`
-    //
+/// Sets the maximum number of `__index` or `__newindex` tables that will be followed by a single
+/// index operation before raising an error, defaults to `DEFAULT_META_CHAIN_LIMIT`.
+pub fn set_meta_chain_limit<'gc>(ctx: Context<'gc>, limit: usize) {
+    ctx.singleton::<Rootable![MetaChainLimit]>().0.set(limit);
+}
+
+pub fn index<'gc>(
+    ctx: Context<'gc>,
+    table: Value<'gc>,
+    key: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     // PUC-Rio Lua guards the maximum length of metamethod chains to `MAXTAGLOOP` in cases where no
     // Lua code is invoked. It must do this, because otherwise Lua code could cause the interpreter
-    // to infinite loop without triggering hook functions. We don't HAVE to mimic this behavior here
-    // due to piccolo's flexibility: the `Executor` design allows us to ensure that control is still
-    // periodically returned by performing the access through a separate callback.
+    // to infinite loop without triggering hook functions, as in:
     //
-    // We could introduce a maximum chain depth, or try to detect infinite chains in simple cases,
-    // or just follow chains of metamethods in blocks to reduce the number of separate callback
-    // calls. Right now, it works in the absolute *simplest* possible way.
+    // `t = {}; setmetatable(t, { __index = t }); t.a`
     //
-    // We could also make it a little nicer to deal with arbitrary long metamethod chains by
-    // replacing the `MetaCall` machinery with a `Sequence` and allowing `Sequence` impls to
-    // participate in custom backtrace printing. If done generically, every metamethod chain call
-    // could print its current chain depth as part of the backtrace, helping to debug infinite
-    // loops due to metamethod chains. Changing `MetaCall` to use sequences also has a potential
-    // performance benefit because a `BoxSequence` can avoid allocation when the sequence is a ZST.
-    Ok(MetaResult::Call(match idx {
-        table @ (Value::Table(_) | Value::UserData(_)) => MetaCall {
-            function: Callback::from_fn(&ctx, |ctx, _, mut stack| {
-                let table = stack.get(0);
-                let key = stack.get(1);
-                stack.clear();
-
-                match index(ctx, table, key)? {
-                    MetaResult::Value(v) => {
-                        stack.push_back(v);
-                        Ok(CallbackReturn::Return)
-                    }
-                    MetaResult::Call(call) => {
-                        stack.extend(call.args);
-                        Ok(CallbackReturn::Call {
-                            function: call.function,
-                            then: None,
-                        })
-                    }
+    // We follow chains of `__index` tables directly here, up to a configurable limit, and only
+    // return a call once we reach an `__index` function.
+    let mut table = table;
+    let mut limit = None;
+    let mut depth = 0;
+
+    loop {
+        let idx = match table {
+            Value::Table(table) => {
+                let v = table.get(ctx, key);
+                if !v.is_nil() {
+                    return Ok(MetaResult::Value(v));
                 }
-            })
-            .into(),
-            args: [table, key],
-        },
-        _ => MetaCall {
-            function: call(ctx, idx).map_err(|e| MetaOperatorError::Call(MetaMethod::Index, e))?,
-            args: [table, key],
-        },
-    }))
+
+                let idx = if let Some(mt) = table.metatable() {
+                    mt.get(ctx, MetaMethod::Index)
+                } else {
+                    Value::Nil
+                };
+
+                if idx.is_nil() {
+                    return Ok(MetaResult::Value(Value::Nil));
+                }
+
+                idx
+            }
+            _ => get_metamethod(ctx, table, MetaMethod::Index).ok_or_else(|| {
+                MetaOperatorError::Unary(MetaMethod::Index, type_name(ctx, table))
+            })?,
+        };
+
+        match idx {
+            Value::Table(_) | Value::UserData(_) => {
+                depth += 1;
+                if depth >= *limit.get_or_insert_with(|| meta_chain_limit(ctx)) {
+                    return Err(MetaOperatorError::ChainTooLong(MetaMethod::Index));
+                }
+                table = idx;
+            }
+            _ => {
+                return Ok(MetaResult::Call(MetaCall {
+                    function: call(ctx, idx)
+                        .map_err(|e| MetaOperatorError::Call(MetaMethod::Index, e))?,
+                    args: [table, key],
+                }));
+            }
+        }
+    }
 }
 
 pub fn new_index<'gc>(
@@ -276,84 +504,65 @@ pub fn new_index<'gc>(
     key: Value<'gc>,
     value: Value<'gc>,
 ) -> Result<Option<MetaCall<'gc, 3>>, MetaOperatorError> {
-    let idx = match table {
-        Value::Table(table) => {
-            let v = table.get(ctx, key);
-            if !v.is_nil() {
-                // If the value is present in the table, then we do not invoke the metamethod.
-                table.set_value(&ctx, key, value)?;
-                return Ok(None);
-            }
+    // Chains of `__newindex` tables are followed directly, see the note in `index`.
+    let mut table = table;
+    let mut limit = None;
+    let mut depth = 0;
 
-            let idx = if let Some(mt) = table.metatable() {
-                mt.get(ctx, MetaMethod::NewIndex)
-            } else {
-                Value::Nil
-            };
+    loop {
+        let idx = match table {
+            Value::Table(table) => {
+                let v = table.get(ctx, key);
+                if !v.is_nil() {
+                    // If the value is present in the table, then we do not invoke the metamethod.
+                    table.set_value(&ctx, key, value)?;
+                    return Ok(None);
+                }
 
-            if idx.is_nil() {
-                // If we do not have a __newindex metamethod, then just set the table value
-                // directly.
-                table.set_value(&ctx, key, value)?;
-                return Ok(None);
-            }
+                let idx = if let Some(mt) = table.metatable() {
+                    mt.get(ctx, MetaMethod::NewIndex)
+                } else {
+                    Value::Nil
+                };
 
-            idx
-        }
-        Value::UserData(u) if u.metatable().is_some() => {
-            let idx = if let Some(mt) = u.metatable() {
-                mt.get(ctx, MetaMethod::NewIndex)
-            } else {
-                Value::Nil
-            };
+                if idx.is_nil() {
+                    // If we do not have a __newindex metamethod, then just set the table value
+                    // directly.
+                    table.set_value(&ctx, key, value)?;
+                    return Ok(None);
+                }
 
-            if idx.is_nil() {
-                return Err(
-                    MetaOperatorError::Unary(MetaMethod::NewIndex, table.type_name()).into(),
-                );
+                idx
             }
+            _ => get_metamethod(ctx, table, MetaMethod::NewIndex).ok_or_else(|| {
+                MetaOperatorError::Unary(MetaMethod::NewIndex, type_name(ctx, table))
+            })?,
+        };
 
-            idx
-        }
-        _ => {
-            return Err(MetaOperatorError::Unary(MetaMethod::NewIndex, table.type_name()).into());
-        }
-    };
-
-    Ok(Some(match idx {
-        table @ (Value::Table(_) | Value::UserData(_)) => MetaCall {
-            function: Callback::from_fn(&ctx, |ctx, _, mut stack| {
-                // NOTE: Potential for indexing loop here, see note in __index.
-                let (table, key, value): (Value, Value, Value) = stack.consume(ctx)?;
-                if let Some(call) = new_index(ctx, table, key, value)? {
-                    stack.extend(call.args);
-                    Ok(CallbackReturn::Call {
-                        function: call.function,
-                        then: None,
-                    })
-                } else {
-                    Ok(CallbackReturn::Return)
+        match idx {
+            Value::Table(_) | Value::UserData(_) => {
+                depth += 1;
+                if depth >= *limit.get_or_insert_with(|| meta_chain_limit(ctx)) {
+                    return Err(MetaOperatorError::ChainTooLong(MetaMethod::NewIndex));
                 }
-            })
-            .into(),
-            args: [table, key, value],
-        },
-        _ => MetaCall {
-            function: call(ctx, idx)
-                .map_err(|e| MetaOperatorError::Call(MetaMethod::NewIndex, e))?,
-            args: [table, key, value],
-        },
-    }))
+                table = idx;
+            }
+            _ => {
+                return Ok(Some(MetaCall {
+                    function: call(ctx, idx)
+                        .map_err(|e| MetaOperatorError::Call(MetaMethod::NewIndex, e))?,
+                    args: [table, key, value],
+                }));
+            }
+        }
+    }
 }
 
 pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, MetaCallError> {
-    let metatable = match v {
-        Value::Function(f) => return Ok(f),
-        Value::Table(t) => t.metatable(),
-        Value::UserData(ud) => ud.metatable(),
-        _ => None,
+    if let Value::Function(f) = v {
+        return Ok(f);
     }
-    .ok_or(MetaCallError(v.type_name()))?;
+    let metatable = get_metatable(ctx, v).ok_or_else(|| MetaCallError(type_name(ctx, v)))?;
 
     match metatable.get(ctx, MetaMethod::Call) {
         f @ (Value::Function(_) | Value::Table(_) | Value::UserData(_)) => Ok(
@@ -369,16 +578,12 @@ pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, Meta
             })
             .into(),
         ),
-        f => Err(MetaCallError(f.type_name())),
+        f => Err(MetaCallError(type_name(ctx, f))),
     }
 }
 
 pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, MetaOperatorError> {
-    if let Some(metatable) = match v {
-        Value::Table(t) => t.metatable(),
-        Value::UserData(u) => u.metatable(),
-        _ => None,
-    } {
+    if let Some(metatable) = get_metatable(ctx, v) {
         let len = metatable.get(ctx, MetaMethod::Len);
         if !len.is_nil() {
             return Ok(MetaResult::Call(MetaCall {
@@ -392,19 +597,221 @@ pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>,
     match v {
         Value::String(s) => Ok(MetaResult::Value(s.len().into())),
         Value::Table(t) => Ok(MetaResult::Value(t.length().into())),
-        f => Err(MetaOperatorError::Unary(MetaMethod::Len, f.type_name())),
+        f => Err(MetaOperatorError::Unary(MetaMethod::Len, type_name(ctx, f))),
+    }
+}
+
+/// The result of [`pairs`].
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub enum PairsResult<'gc> {
+    /// The iterator function, state and initial control value for a generic `for` loop.
+    Iter([Value<'gc>; 3]),
+    /// The `__pairs` metamethod must be called, its first three results are the iterator triple.
+    Call(MetaCall<'gc, 1>),
+}
+
+/// Get the iterator triple used to iterate over a value with `pairs`.
+///
+/// If the value has a `__pairs` metamethod, then it is called with the value. Otherwise, tables
+/// are iterated over with the `next` function (see [`next_callback`]), and any other value is an
+/// error.
+pub fn pairs<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<PairsResult<'gc>, MetaOperatorError> {
+    if let Some(m) = get_metamethod(ctx, v, MetaMethod::Pairs) {
+        return Ok(PairsResult::Call(MetaCall {
+            function: call(ctx, m).map_err(|e| MetaOperatorError::Call(MetaMethod::Pairs, e))?,
+            args: [v],
+        }));
+    }
+
+    match v {
+        Value::Table(_) => Ok(PairsResult::Iter([
+            next_callback(ctx).into(),
+            v,
+            Value::Nil,
+        ])),
+        v => Err(MetaOperatorError::Unary(
+            MetaMethod::Pairs,
+            type_name(ctx, v),
+        )),
     }
 }
 
+/// Returns the callback which implements the `next` function, and which [`pairs`] returns as the
+/// iterator for tables.
+pub fn next_callback<'gc>(ctx: Context<'gc>) -> Callback<'gc> {
+    #[derive(Copy, Clone, Collect)]
+    #[collect(no_drop)]
+    struct NextCallback<'gc>(Callback<'gc>);
+
+    impl<'gc> Singleton<'gc> for NextCallback<'gc> {
+        fn create(ctx: Context<'gc>) -> Self {
+            NextCallback(Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (table, index): (Table, Value) = stack.consume(ctx)?;
+                match table.next(index) {
+                    NextValue::Found { key, value } => stack.replace(ctx, (key, value)),
+                    NextValue::Last => stack.replace(ctx, (Value::Nil, Value::Nil)),
+                    NextValue::NotFound => {
+                        return Err("invalid table key".into_value(ctx).into());
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }))
+        }
+    }
+
+    ctx.singleton::<Rootable![NextCallback<'_>]>().0
+}
+
+/// The result of [`concat_many`].
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub enum ConcatResult<'gc> {
+    /// Every value was concatenated, this is the final result.
+    Value(Value<'gc>),
+    /// A `__concat` metamethod must be called to make progress.
+    ///
+    /// The values in `values[..remaining]` have not been touched yet. Once the call completes,
+    /// the concatenation should continue by calling `concat_many` again with those values followed
+    /// by the first result of the call.
+    Call {
+        call: MetaCall<'gc, 2>,
+        remaining: usize,
+    },
+}
+
+/// Concatenate two values with the `..` operator, calling the `__concat` metamethod if either
+/// value is not a string or a number.
+pub fn concat<'gc>(
+    ctx: Context<'gc>,
+    lhs: Value<'gc>,
+    rhs: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+    meta_metaop(ctx, lhs, rhs, MetaMethod::Concat, |a, b| {
+        if is_concat_primitive(a) && is_concat_primitive(b) {
+            Some(String::concat(ctx, &[a, b]).ok()?.into())
+        } else {
+            None
+        }
+    })
+}
+
+/// Concatenate any number of values with the `..` operator.
+///
+/// Concatenation in Lua is right associative, so `a .. b .. c` is evaluated as `a .. (b .. c)`.
+/// Runs of strings and numbers are joined in a single step without creating any intermediate
+/// strings, and the values are only folded pairwise where a `__concat` metamethod is needed.
+///
+/// Concatenating an empty slice of values produces the empty string.
+pub fn concat_many<'gc>(
+    ctx: Context<'gc>,
+    values: &[Value<'gc>],
+) -> Result<ConcatResult<'gc>, MetaOperatorError> {
+    if values.iter().all(|&v| is_concat_primitive(v)) {
+        return Ok(ConcatResult::Value(
+            String::concat(ctx, values)
+                .expect("strings and numbers can always be concatenated")
+                .into(),
+        ));
+    }
+
+    let (&last, _) = values.split_last().unwrap();
+    let mut acc = last;
+    let mut end = values.len() - 1;
+    while end > 0 {
+        if is_concat_primitive(acc) && is_concat_primitive(values[end - 1]) {
+            let mut start = end - 1;
+            while start > 0 && is_concat_primitive(values[start - 1]) {
+                start -= 1;
+            }
+            let mut run = values[start..end].to_vec();
+            run.push(acc);
+            acc = String::concat(ctx, &run)
+                .expect("strings and numbers can always be concatenated")
+                .into();
+            end = start;
+        } else {
+            match concat(ctx, values[end - 1], acc)? {
+                MetaResult::Value(v) => {
+                    acc = v;
+                    end -= 1;
+                }
+                MetaResult::Call(call) => {
+                    return Ok(ConcatResult::Call {
+                        call,
+                        remaining: end - 1,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(ConcatResult::Value(acc))
+}
+
+/// Returns a callback which concatenates all of its arguments with [`concat_many`], calling any
+/// necessary `__concat` metamethods, and returns the result.
+pub fn concat_callback<'gc>(ctx: Context<'gc>) -> Callback<'gc> {
+    #[derive(Copy, Clone, Collect)]
+    #[collect(no_drop)]
+    struct ConcatCallback<'gc>(Callback<'gc>);
+
+    impl<'gc> Singleton<'gc> for ConcatCallback<'gc> {
+        fn create(ctx: Context<'gc>) -> Self {
+            ConcatCallback(Callback::from_fn(&ctx, |ctx, _, _| {
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    ConcatSeq { call_bottom: None },
+                )))
+            }))
+        }
+    }
+
+    ctx.singleton::<Rootable![ConcatCallback<'_>]>().0
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct ConcatSeq {
+    call_bottom: Option<usize>,
+}
+
+impl<'gc> Sequence<'gc> for ConcatSeq {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(bottom) = self.call_bottom.take() {
+            // Only the first result of the metamethod is used, and it takes the place of both of
+            // its operands.
+            stack.resize(bottom + 1);
+        }
+
+        match concat_many(ctx, &stack[..])? {
+            ConcatResult::Value(v) => {
+                stack.replace(ctx, v);
+                Ok(SequencePoll::Return)
+            }
+            ConcatResult::Call { call, remaining } => {
+                stack.resize(remaining);
+                self.call_bottom = Some(remaining);
+                Ok(call.into_sequence_poll(&mut stack))
+            }
+        }
+    }
+}
+
+fn is_concat_primitive(v: Value<'_>) -> bool {
+    matches!(v, Value::String(_) | Value::Integer(_) | Value::Number(_))
+}
+
 pub fn tostring<'gc>(
     ctx: Context<'gc>,
     v: Value<'gc>,
 ) -> Result<MetaResult<'gc, 1>, MetaOperatorError> {
-    if let Some(metatable) = match v {
-        Value::Table(t) => t.metatable(),
-        Value::UserData(u) => u.metatable(),
-        _ => None,
-    } {
+    if let Some(metatable) = get_metatable(ctx, v) {
         let tostring = metatable.get(ctx, MetaMethod::ToString);
         if !tostring.is_nil() {
             return Ok(MetaResult::Call(MetaCall {
@@ -417,41 +824,64 @@ pub fn tostring<'gc>(
 
     Ok(match v {
         v @ Value::String(_) => MetaResult::Value(v),
-        v => MetaResult::Value(ctx.intern(v.display().to_string().as_bytes()).into()),
+        v @ (Value::Nil | Value::Boolean(_) | Value::Integer(_) | Value::Number(_)) => {
+            MetaResult::Value(ctx.intern(v.display().to_string().as_bytes()).into())
+        }
+        v => {
+            let id = ctx.object_id(v);
+            let s = match (metatable_name(ctx, v), v) {
+                (Some(name), Value::Table(_) | Value::UserData(_)) => match id {
+                    Some(id) => format!("{}: {}", name.to_str_lossy(), id),
+                    None => name.to_str_lossy().into_owned(),
+                },
+                _ => match id {
+                    Some(id) => format!("<{} {}>", v.type_name(), id),
+                    None => format!("<{}>", v.type_name()),
+                },
+            };
+            MetaResult::Value(ctx.intern(s.as_bytes()).into())
+        }
     })
 }
 
+/// Returns the call to make to close a to-be-closed variable with the given value.
+///
+/// The `__close` metamethod is called with the value and the error that caused the variable to go
+/// out of scope, or nil if it went out of scope normally. Returns `None` if the value is nil or
+/// false, which are ignored.
+pub fn close<'gc>(
+    ctx: Context<'gc>,
+    v: Value<'gc>,
+    error: Value<'gc>,
+) -> Result<Option<MetaCall<'gc, 2>>, MetaOperatorError> {
+    if !v.to_bool() {
+        return Ok(None);
+    }
+
+    if let Some(m) = get_metamethod(ctx, v, MetaMethod::Close) {
+        Ok(Some(MetaCall {
+            function: call(ctx, m).map_err(|e| MetaOperatorError::Call(MetaMethod::Close, e))?,
+            args: [v, error],
+        }))
+    } else {
+        Err(MetaOperatorError::Unary(
+            MetaMethod::Close,
+            type_name(ctx, v),
+        ))
+    }
+}
+
 pub fn equal<'gc>(
     ctx: Context<'gc>,
     lhs: Value<'gc>,
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
-    Ok(match (lhs, rhs) {
-        (Value::Nil, Value::Nil) => Value::Boolean(true).into(),
-        (Value::Nil, _) => Value::Boolean(false).into(),
-
-        (Value::Boolean(a), Value::Boolean(b)) => Value::Boolean(a == b).into(),
-        (Value::Boolean(_), _) => Value::Boolean(false).into(),
-
-        (Value::Integer(a), Value::Integer(b)) => Value::Boolean(a == b).into(),
-        (Value::Integer(a), Value::Number(b)) => Value::Boolean(a as f64 == b).into(),
-        (Value::Integer(_), _) => Value::Boolean(false).into(),
-
-        (Value::Number(a), Value::Number(b)) => Value::Boolean(a == b).into(),
-        (Value::Number(a), Value::Integer(b)) => Value::Boolean(b as f64 == a).into(),
-        (Value::Number(_), _) => Value::Boolean(false).into(),
-
-        (Value::String(a), Value::String(b)) => Value::Boolean(a == b).into(),
-        (Value::String(_), _) => Value::Boolean(false).into(),
-
-        (Value::Function(a), Value::Function(b)) => Value::Boolean(a == b).into(),
-        (Value::Function(_), _) => Value::Boolean(false).into(),
-
-        (Value::Thread(a), Value::Thread(b)) => Value::Boolean(a == b).into(),
-        (Value::Thread(_), _) => Value::Boolean(false).into(),
+    if lhs.raw_equal(rhs) {
+        return Ok(Value::Boolean(true).into());
+    }
 
-        (Value::Table(a), Value::Table(b)) if a == b => Value::Boolean(true).into(),
-        (Value::Table(_), Value::Table(_)) => {
+    Ok(match (lhs, rhs) {
+        (Value::Table(_), Value::Table(_)) | (Value::UserData(_), Value::UserData(_)) => {
             if let Some(m) = get_metamethod(ctx, lhs, MetaMethod::Eq) {
                 MetaResult::Call(MetaCall {
                     function: call(ctx, m)
@@ -468,27 +898,41 @@ pub fn equal<'gc>(
                 Value::Boolean(false).into()
             }
         }
-        (Value::Table(_), _) => Value::Boolean(false).into(),
+        _ => Value::Boolean(false).into(),
+    })
+}
 
-        (Value::UserData(a), Value::UserData(b)) if a == b => Value::Boolean(true).into(),
-        (Value::UserData(_), Value::UserData(_)) => {
-            if let Some(m) = get_metamethod(ctx, lhs, MetaMethod::Eq) {
-                MetaResult::Call(MetaCall {
-                    function: call(ctx, m)
-                        .map_err(|e| MetaOperatorError::Call(MetaMethod::Eq, e))?,
-                    args: [lhs, rhs],
-                })
-            } else if let Some(m) = get_metamethod(ctx, rhs, MetaMethod::Eq) {
-                MetaResult::Call(MetaCall {
-                    function: call(ctx, m)
-                        .map_err(|e| MetaOperatorError::Call(MetaMethod::Eq, e))?,
-                    args: [lhs, rhs],
-                })
-            } else {
-                Value::Boolean(false).into()
-            }
-        }
-        (Value::UserData(_), _) => Value::Boolean(false).into(),
+pub fn less_than<'gc>(
+    ctx: Context<'gc>,
+    lhs: Value<'gc>,
+    rhs: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+    meta_metaop(ctx, lhs, rhs, MetaMethod::Lt, |a, b| {
+        Some(Value::Boolean(match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => a < b,
+            (Value::Integer(a), Value::Number(b)) => (a as f64) < b,
+            (Value::Number(a), Value::Integer(b)) => a < b as f64,
+            (Value::Number(a), Value::Number(b)) => a < b,
+            (Value::String(a), Value::String(b)) => a.as_bytes() < b.as_bytes(),
+            _ => return None,
+        }))
+    })
+}
+
+pub fn less_equal<'gc>(
+    ctx: Context<'gc>,
+    lhs: Value<'gc>,
+    rhs: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+    meta_metaop(ctx, lhs, rhs, MetaMethod::Le, |a, b| {
+        Some(Value::Boolean(match (a, b) {
+            (Value::Integer(a), Value::Integer(b)) => a <= b,
+            (Value::Integer(a), Value::Number(b)) => (a as f64) <= b,
+            (Value::Number(a), Value::Integer(b)) => a <= b as f64,
+            (Value::Number(a), Value::Number(b)) => a <= b,
+            (Value::String(a), Value::String(b)) => a.as_bytes() <= b.as_bytes(),
+            _ => return None,
+        }))
     })
 }
 
@@ -514,8 +958,8 @@ fn meta_metaop<'gc>(
             } else {
                 return Err(MetaOperatorError::Binary(
                     method,
-                    lhs.type_name(),
-                    rhs.type_name(),
+                    type_name(ctx, lhs),
+                    type_name(ctx, rhs),
                 ));
             }
         }
@@ -528,8 +972,8 @@ fn meta_metaop<'gc>(
             } else {
                 return Err(MetaOperatorError::Binary(
                     method,
-                    lhs.type_name(),
-                    rhs.type_name(),
+                    type_name(ctx, lhs),
+                    type_name(ctx, rhs),
                 ));
             }
         }
@@ -542,14 +986,31 @@ fn meta_metaop<'gc>(
             } else {
                 return Err(MetaOperatorError::Binary(
                     method,
-                    lhs.type_name(),
-                    rhs.type_name(),
+                    type_name(ctx, lhs),
+                    type_name(ctx, rhs),
+                ));
+            }
+        }
+        (a, b) => {
+            if let Some(v) = const_op(a, b) {
+                v.into()
+            } else if let Some(m) =
+                get_metamethod(ctx, lhs, method).or_else(|| get_metamethod(ctx, rhs, method))
+            {
+                // Values other than tables and userdata may have metamethods through their shared
+                // type metatable.
+                MetaResult::Call(MetaCall {
+                    function: call(ctx, m).map_err(|e| MetaOperatorError::Call(method, e))?,
+                    args: [lhs, rhs],
+                })
+            } else {
+                return Err(MetaOperatorError::Binary(
+                    method,
+                    type_name(ctx, lhs),
+                    type_name(ctx, rhs),
                 ));
             }
         }
-        (a, b) => const_op(a, b)
-            .ok_or_else(|| MetaOperatorError::Binary(method, lhs.type_name(), rhs.type_name()))?
-            .into(),
     })
 }
 
@@ -567,22 +1028,44 @@ fn meta_unary_metaop<'gc>(
                     args: [arg],
                 })
             } else {
-                return Err(MetaOperatorError::Unary(method, arg.type_name()));
+                return Err(MetaOperatorError::Unary(method, type_name(ctx, arg)));
+            }
+        }
+        val => {
+            if let Some(v) = const_op(val) {
+                v.into()
+            } else if let Some(m) = get_metamethod(ctx, arg, method) {
+                MetaResult::Call(MetaCall {
+                    function: call(ctx, m).map_err(|e| MetaOperatorError::Call(method, e))?,
+                    args: [arg],
+                })
+            } else {
+                return Err(MetaOperatorError::Unary(method, type_name(ctx, arg)));
             }
         }
-        val => const_op(val)
-            .ok_or_else(|| MetaOperatorError::Unary(method, arg.type_name()))?
-            .into(),
     })
 }
 
+// Converts an arithmetic operand to a constant, coercing strings to numbers only if string
+// coercion is enabled with `Context::set_string_coercion`.
+//
+// The arithmetic methods of `Constant` always parse strings, so strings must be rejected here
+// when coercion is disabled.
+fn arith_operand<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Option<Constant<String<'gc>>> {
+    match v {
+        Value::String(_) if ctx.string_coercion() => v.to_numeric()?.to_constant(),
+        Value::String(_) => None,
+        v => v.to_constant(),
+    }
+}
+
 pub fn add<'gc>(
     ctx: Context<'gc>,
     lhs: Value<'gc>,
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Add, |a, b| {
-        Some(a.to_constant()?.add(&b.to_constant()?)?.into())
+        Some(arith_operand(ctx, a)?.add(&arith_operand(ctx, b)?)?.into())
     })
 }
 
@@ -592,7 +1075,11 @@ pub fn subtract<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Sub, |a, b| {
-        Some(a.to_constant()?.subtract(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .subtract(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -602,7 +1089,11 @@ pub fn multiply<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Mul, |a, b| {
-        Some(a.to_constant()?.multiply(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .multiply(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -612,7 +1103,11 @@ pub fn float_divide<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Div, |a, b| {
-        Some(a.to_constant()?.float_divide(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .float_divide(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -622,7 +1117,11 @@ pub fn floor_divide<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::IDiv, |a, b| {
-        Some(a.to_constant()?.floor_divide(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .floor_divide(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -632,7 +1131,11 @@ pub fn modulo<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Mod, |a, b| {
-        Some(a.to_constant()?.modulo(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .modulo(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -642,7 +1145,11 @@ pub fn exponentiate<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Pow, |a, b| {
-        Some(a.to_constant()?.exponentiate(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .exponentiate(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -651,7 +1158,7 @@ pub fn negate<'gc>(
     lhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 1>, MetaOperatorError> {
     meta_unary_metaop(ctx, lhs, MetaMethod::Unm, |val| {
-        Some(val.to_constant()?.negate()?.into())
+        Some(arith_operand(ctx, val)?.negate()?.into())
     })
 }
 
@@ -660,7 +1167,7 @@ pub fn bitwise_not<'gc>(
     lhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 1>, MetaOperatorError> {
     meta_unary_metaop(ctx, lhs, MetaMethod::BNot, |val| {
-        Some(val.to_constant()?.bitwise_not()?.into())
+        Some(arith_operand(ctx, val)?.bitwise_not()?.into())
     })
 }
 
@@ -670,7 +1177,11 @@ pub fn bitwise_and<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::BAnd, |a, b| {
-        Some(a.to_constant()?.bitwise_and(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .bitwise_and(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -680,7 +1191,11 @@ pub fn bitwise_or<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::BOr, |a, b| {
-        Some(a.to_constant()?.bitwise_or(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .bitwise_or(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -690,7 +1205,11 @@ pub fn bitwise_xor<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::BXor, |a, b| {
-        Some(a.to_constant()?.bitwise_xor(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .bitwise_xor(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -700,7 +1219,11 @@ pub fn shift_left<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Shl, |a, b| {
-        Some(a.to_constant()?.shift_left(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .shift_left(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
 
@@ -710,6 +1233,10 @@ pub fn shift_right<'gc>(
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
     meta_metaop(ctx, lhs, rhs, MetaMethod::Shr, |a, b| {
-        Some(a.to_constant()?.shift_right(&b.to_constant()?)?.into())
+        Some(
+            arith_operand(ctx, a)?
+                .shift_right(&arith_operand(ctx, b)?)?
+                .into(),
+        )
     })
 }
@@ -0,0 +1,101 @@
+use crate::{Callback, CallbackReturn, Context, Error, Execution, IntoValue, Stack, Table};
+
+/// A function which wraps a callback, given the context, the name the callback is being registered
+/// under, and the callback to wrap.
+pub type Middleware<'gc> = Box<dyn Fn(Context<'gc>, &str, Callback<'gc>) -> Callback<'gc> + 'gc>;
+
+/// A helper for building tables of callbacks, such as library modules.
+///
+/// Middleware added with [`ModuleBuilder::with_middleware`] is applied to every callback that is
+/// added *afterwards*, which makes it possible to add cross-cutting behavior like timing, argument
+/// logging or permission checks to a whole module without changing each callback.
+///
+/// Middleware is applied in the order it was added, so the most recently added middleware is the
+/// outermost wrapper and is the first to see each call. A middleware will usually create a new
+/// callback that does some work and then calls the wrapped callback with
+/// [`CallbackReturn::Call`], see [`ModuleBuilder::wrap`].
+pub struct ModuleBuilder<'gc> {
+    ctx: Context<'gc>,
+    table: Table<'gc>,
+    middleware: Vec<Middleware<'gc>>,
+}
+
+impl<'gc> ModuleBuilder<'gc> {
+    /// Start building a new, empty module table.
+    pub fn new(ctx: Context<'gc>) -> Self {
+        Self::from_table(ctx, Table::new(&ctx))
+    }
+
+    /// Add entries to an existing table.
+    pub fn from_table(ctx: Context<'gc>, table: Table<'gc>) -> Self {
+        Self {
+            ctx,
+            table,
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Wrap every callback added after this point with the given middleware.
+    pub fn with_middleware(
+        mut self,
+        middleware: impl Fn(Context<'gc>, &str, Callback<'gc>) -> Callback<'gc> + 'gc,
+    ) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Add a callback under the given name, wrapped by all of the current middleware.
+    pub fn callback(self, name: &'static str, callback: Callback<'gc>) -> Self {
+        let callback = self
+            .middleware
+            .iter()
+            .fold(callback, |callback, middleware| {
+                middleware(self.ctx, name, callback)
+            });
+        self.table.set(self.ctx, name, callback).unwrap();
+        self
+    }
+
+    /// Add a callback created from the given function, wrapped by all of the current middleware.
+    pub fn function<F>(self, name: &'static str, f: F) -> Self
+    where
+        F: 'static
+            + Fn(
+                Context<'gc>,
+                Execution<'gc, '_>,
+                Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        let callback = Callback::from_fn(&self.ctx, f);
+        self.callback(name, callback)
+    }
+
+    /// Add a plain value under the given name, middleware is not applied.
+    pub fn value(self, name: &'static str, value: impl IntoValue<'gc>) -> Self {
+        self.table.set(self.ctx, name, value).unwrap();
+        self
+    }
+
+    /// Finish building and return the module table.
+    pub fn build(self) -> Table<'gc> {
+        self.table
+    }
+
+    /// A convenience for writing middleware.
+    ///
+    /// Returns a callback which calls `before` with the arguments in the stack and then, if
+    /// `before` does not return an error, calls `callback` with the same (possibly modified)
+    /// stack.
+    pub fn wrap<F>(ctx: Context<'gc>, callback: Callback<'gc>, before: F) -> Callback<'gc>
+    where
+        F: 'static + Fn(Context<'gc>, &mut Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+    {
+        Callback::from_fn_with(&ctx, callback, move |&callback, ctx, _, mut stack| {
+            before(ctx, &mut stack)?;
+            Ok(CallbackReturn::Call {
+                function: callback.into(),
+                then: None,
+            })
+        })
+    }
+}
@@ -110,9 +110,15 @@ pub enum Operation {
     },
     Jump {
         offset: i16,
-        // If set, close upvalues >= `close_upvalues`
+        // If set, close upvalues and to-be-closed variables >= `close_upvalues`
         close_upvalues: Opt254,
     },
+    /// Mark the given register as a to-be-closed variable. When it goes out of scope, the `__close`
+    /// metamethod of its value will be called. The value must be nil, false, or have a `__close`
+    /// metamethod.
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     /// Test the register as a boolean, if its boolean value matches `is_true`, skip the next
     /// instruction.
     Test {
@@ -283,6 +289,12 @@ pub enum Operation {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    /// Load the host-defined ext opcode with the given index into `dest`, or nil if there is no
+    /// such ext opcode, see [`crate::ext`].
+    LoadExt {
+        dest: RegisterIndex,
+        ext: u8,
+    },
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -371,6 +383,7 @@ impl OpCode {
                 offset,
                 close_upvalues,
             },
+            Operation::ToBeClosed { value } => OpCodeRepr::ToBeClosed { value },
             Operation::Test { value, is_true } => OpCodeRepr::Test { value, is_true },
             Operation::TestSet {
                 dest,
@@ -653,6 +666,7 @@ impl OpCode {
                 }
             },
             Operation::BitNot { dest, source } => OpCodeRepr::BitNot { dest, source },
+            Operation::LoadExt { dest, ext } => OpCodeRepr::LoadExt { dest, ext },
         })
     }
 
@@ -761,6 +775,7 @@ impl OpCode {
                 offset,
                 close_upvalues,
             },
+            OpCodeRepr::ToBeClosed { value } => Operation::ToBeClosed { value },
             OpCodeRepr::Test { value, is_true } => Operation::Test { value, is_true },
             OpCodeRepr::TestSet {
                 dest,
@@ -1151,6 +1166,7 @@ impl OpCode {
                 right: right.into(),
             },
             OpCodeRepr::BitNot { dest, source } => Operation::BitNot { dest, source },
+            OpCodeRepr::LoadExt { dest, ext } => Operation::LoadExt { dest, ext },
         }
     }
 }
@@ -1265,6 +1281,9 @@ enum OpCodeRepr {
         offset: i16,
         close_upvalues: Opt254,
     },
+    ToBeClosed {
+        value: RegisterIndex,
+    },
     Test {
         value: RegisterIndex,
         is_true: bool,
@@ -1633,4 +1652,8 @@ enum OpCodeRepr {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    LoadExt {
+        dest: RegisterIndex,
+        ext: u8,
+    },
 }
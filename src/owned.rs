@@ -0,0 +1,153 @@
+use thiserror::Error;
+
+use crate::{Context, FromValue, IntoValue, Table, TypeError, Value};
+
+/// A deep copy of Lua data which is independent of any `Lua` instance.
+///
+/// An `OwnedValue` has no arena lifetime, so it can be sent to other threads, serialized, stored
+/// or compared in tests, and converted back into a `Value` in any `Lua` instance with
+/// [`IntoValue`].
+///
+/// Tables whose keys are exactly the integers `1` to `n` become an `Array`, and every other table,
+/// including an empty one, becomes a `Map` in table iteration order. Metatables are not copied.
+/// A table reachable in several places is copied once for each, and tables which contain
+/// themselves can't be converted at all.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Array(Vec<OwnedValue>),
+    Map(Vec<(OwnedValue, OwnedValue)>),
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum OwnedValueError {
+    /// Functions, threads and userdata have no owned representation.
+    #[error("cannot convert a {0} to an owned value")]
+    Unsupported(&'static str),
+    #[error("cannot convert a table which contains itself to an owned value")]
+    Cycle,
+}
+
+impl OwnedValue {
+    /// Make a deep copy of a value, see [`OwnedValue`].
+    pub fn from_value(value: Value<'_>) -> Result<OwnedValue, OwnedValueError> {
+        convert(value, &mut Vec::new())
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, OwnedValue::Nil)
+    }
+}
+
+fn convert<'gc>(
+    value: Value<'gc>,
+    in_progress: &mut Vec<Table<'gc>>,
+) -> Result<OwnedValue, OwnedValueError> {
+    Ok(match value {
+        Value::Nil => OwnedValue::Nil,
+        Value::Boolean(b) => OwnedValue::Boolean(b),
+        Value::Integer(i) => OwnedValue::Integer(i),
+        Value::Number(n) => OwnedValue::Number(n),
+        Value::String(s) => OwnedValue::String(s.as_bytes().to_vec()),
+        Value::Table(table) => {
+            if in_progress.contains(&table) {
+                return Err(OwnedValueError::Cycle);
+            }
+            in_progress.push(table);
+            let entries = table
+                .iter()
+                .map(|(key, value)| Ok((convert(key, in_progress)?, convert(value, in_progress)?)))
+                .collect::<Result<Vec<_>, _>>();
+            in_progress.pop();
+            into_array_or_map(entries?)
+        }
+        _ => return Err(OwnedValueError::Unsupported(value.type_name())),
+    })
+}
+
+fn into_array_or_map(mut entries: Vec<(OwnedValue, OwnedValue)>) -> OwnedValue {
+    let len = entries.len() as i64;
+    let is_sequence = entries
+        .iter()
+        .all(|(key, _)| matches!(key, &OwnedValue::Integer(i) if (1..=len).contains(&i)));
+    if entries.is_empty() || !is_sequence {
+        return OwnedValue::Map(entries);
+    }
+
+    // Table keys are unique, so `len` keys in the range `1..=len` are each of them exactly once.
+    entries.sort_by_key(|(key, _)| match key {
+        &OwnedValue::Integer(i) => i,
+        _ => unreachable!(),
+    });
+    OwnedValue::Array(entries.into_iter().map(|(_, value)| value).collect())
+}
+
+/// Entries of a `Map` with a key that can't be stored in a table, `Nil` or NaN, are skipped.
+impl<'gc> IntoValue<'gc> for OwnedValue {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        match self {
+            OwnedValue::Nil => Value::Nil,
+            OwnedValue::Boolean(b) => Value::Boolean(b),
+            OwnedValue::Integer(i) => Value::Integer(i),
+            OwnedValue::Number(n) => Value::Number(n),
+            OwnedValue::String(s) => Value::String(ctx.intern(&s)),
+            OwnedValue::Array(values) => {
+                let table = Table::new(&ctx);
+                for (i, value) in values.into_iter().enumerate() {
+                    table.set(ctx, i as i64 + 1, value).unwrap();
+                }
+                table.into()
+            }
+            OwnedValue::Map(entries) => {
+                let table = Table::new(&ctx);
+                for (key, value) in entries {
+                    let _ = table.set(ctx, key, value);
+                }
+                table.into()
+            }
+        }
+    }
+}
+
+impl<'gc> FromValue<'gc> for OwnedValue {
+    fn from_value(_: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        OwnedValue::from_value(value).map_err(|err| match err {
+            OwnedValueError::Unsupported(found) => TypeError {
+                expected: "nil, boolean, number, string or table",
+                found,
+            },
+            OwnedValueError::Cycle => TypeError {
+                expected: "table without cycles",
+                found: "table",
+            },
+        })
+    }
+}
+
+impl From<bool> for OwnedValue {
+    fn from(b: bool) -> Self {
+        OwnedValue::Boolean(b)
+    }
+}
+
+impl From<i64> for OwnedValue {
+    fn from(i: i64) -> Self {
+        OwnedValue::Integer(i)
+    }
+}
+
+impl From<f64> for OwnedValue {
+    fn from(n: f64) -> Self {
+        OwnedValue::Number(n)
+    }
+}
+
+impl From<&str> for OwnedValue {
+    fn from(s: &str) -> Self {
+        OwnedValue::String(s.as_bytes().to_vec())
+    }
+}
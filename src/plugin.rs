@@ -0,0 +1,460 @@
+use std::{io::Read, string::String as StdString};
+
+use thiserror::Error;
+
+use crate::{
+    Closure, Executor, ExecutorMode, Fuel, Lua, StashedExecutor, StashedTable, StaticError, Table,
+    Value,
+};
+
+#[derive(Debug, Clone, Error)]
+pub enum PluginError {
+    #[error("plugin {0:?} is already loaded")]
+    AlreadyLoaded(StdString),
+    #[error("plugin {0:?} is not loaded")]
+    NotLoaded(StdString),
+    #[error("plugin {0:?} is disabled")]
+    Disabled(StdString),
+    #[error("plugin {plugin:?} does not define a function named {name:?}")]
+    NoSuchFunction { plugin: StdString, name: StdString },
+    #[error("plugin {plugin:?} already has the maximum of {max_tasks} tasks")]
+    TaskQuotaExceeded { plugin: StdString, max_tasks: usize },
+    #[error("plugin {plugin:?} already has the maximum of {max_timers} timers")]
+    TimerQuotaExceeded {
+        plugin: StdString,
+        max_timers: usize,
+    },
+    #[error("shutdown handler of plugin {0:?} did not finish within its fuel")]
+    ShutdownTimedOut(StdString),
+    #[error(transparent)]
+    Lua(#[from] StaticError),
+}
+
+/// Manages the lifecycle of a set of plugins which share a single `Lua` instance.
+///
+/// Each plugin is loaded into its own environment created by `Context::shadow_globals`, so the
+/// globals it defines are kept separate from the real globals and from every other plugin. A
+/// plugin also owns a set of tasks, which are executors that are stepped by
+/// `PluginManager::step` for as long as the plugin is enabled, and a set of timers, which start
+/// new tasks after a number of steps.
+///
+/// A plugin moves through the following states:
+///
+///   - `PluginManager::load` runs the plugin's main chunk, and the plugin starts out enabled.
+///   - `PluginManager::disable` stops all of the plugin's tasks, and no new tasks can be spawned
+///     until it is enabled again with `PluginManager::enable`. The environment is kept.
+///   - `PluginManager::unload` disables the plugin and drops its environment, then collects
+///     garbage and runs any `__gc` finalizers of objects which are no longer reachable.
+///
+/// When the host is shutting down, `PluginManager::shutdown` gives every plugin which defines an
+/// `on_shutdown` function a bounded amount of fuel to clean up, and then disables every plugin.
+///
+/// Each plugin can also be given a [`PluginQuota`], so that a single misbehaving plugin cannot
+/// starve the others.
+#[derive(Default)]
+pub struct PluginManager {
+    plugins: Vec<Plugin>,
+}
+
+/// Limits on the resources used by a single plugin, see `PluginManager::set_quota`.
+///
+/// A limit of `None` means unlimited, which is the default.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PluginQuota {
+    /// The maximum number of tasks the plugin may have at once. Adding a task past this limit
+    /// fails with `PluginError::TaskQuotaExceeded`.
+    pub max_tasks: Option<usize>,
+    /// The maximum total fuel the plugin's tasks may use during a single `PluginManager::step`.
+    ///
+    /// Once the plugin has used up this budget, the rest of its tasks wait until the next step,
+    /// where they are stepped before the tasks which already ran.
+    pub max_fuel_per_step: Option<i32>,
+    /// The maximum number of timers the plugin may have waiting at once. Setting a timer past
+    /// this limit fails with `PluginError::TimerQuotaExceeded`.
+    pub max_timers: Option<usize>,
+}
+
+struct Plugin {
+    name: StdString,
+    env: StashedTable,
+    tasks: Vec<StashedExecutor>,
+    timers: Vec<Timer>,
+    enabled: bool,
+    quota: PluginQuota,
+}
+
+struct Timer {
+    // The number of steps left to skip before the timer fires.
+    delay: u32,
+    executor: StashedExecutor,
+}
+
+impl PluginManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a plugin under the given name, running its main chunk to completion.
+    ///
+    /// If the main chunk raises an error, the plugin is not loaded.
+    pub fn load(
+        &mut self,
+        lua: &mut Lua,
+        name: &str,
+        source: impl Read,
+    ) -> Result<(), PluginError> {
+        if self.find(name).is_some() {
+            return Err(PluginError::AlreadyLoaded(name.to_owned()));
+        }
+
+        let (env, executor) = lua.try_enter(|ctx| {
+            let env = ctx.shadow_globals();
+            let closure = Closure::load_with_env(ctx, Some(name), source, env)?;
+            Ok((
+                ctx.stash(env),
+                ctx.stash(Executor::start(ctx, closure.into(), ())),
+            ))
+        })?;
+        lua.execute::<()>(&executor)?;
+
+        self.plugins.push(Plugin {
+            name: name.to_owned(),
+            env,
+            tasks: Vec::new(),
+            timers: Vec::new(),
+            enabled: true,
+            quota: PluginQuota::default(),
+        });
+        Ok(())
+    }
+
+    /// Returns the environment of a loaded plugin.
+    ///
+    /// Pass the environment to `Context::captured_globals` to get every global the plugin has
+    /// defined.
+    pub fn env(&self, name: &str) -> Option<&StashedTable> {
+        Some(&self.find(name)?.env)
+    }
+
+    /// Returns whether the named plugin is enabled, or `None` if it is not loaded.
+    pub fn is_enabled(&self, name: &str) -> Option<bool> {
+        Some(self.find(name)?.enabled)
+    }
+
+    /// Returns the names of all loaded plugins, in the order they were loaded.
+    pub fn plugins(&self) -> impl Iterator<Item = &str> + '_ {
+        self.plugins.iter().map(|p| p.name.as_str())
+    }
+
+    /// Returns the number of running tasks owned by the named plugin, or `None` if it is not
+    /// loaded.
+    pub fn task_count(&self, name: &str) -> Option<usize> {
+        Some(self.find(name)?.tasks.len())
+    }
+
+    /// Returns the number of timers of the named plugin which have not fired yet, or `None` if it
+    /// is not loaded.
+    pub fn timer_count(&self, name: &str) -> Option<usize> {
+        Some(self.find(name)?.timers.len())
+    }
+
+    /// Returns the quota of the named plugin, or `None` if it is not loaded.
+    pub fn quota(&self, name: &str) -> Option<PluginQuota> {
+        Some(self.find(name)?.quota)
+    }
+
+    /// Set the resource limits of a loaded plugin.
+    ///
+    /// Lowering `max_tasks` or `max_timers` below the plugin's current number of tasks or timers
+    /// does not stop any of them, but no new ones can be added until enough of them have
+    /// finished.
+    pub fn set_quota(&mut self, name: &str, quota: PluginQuota) -> Result<(), PluginError> {
+        self.find_mut(name)?.quota = quota;
+        Ok(())
+    }
+
+    /// Add an executor to the task set of an enabled plugin.
+    pub fn add_task(&mut self, name: &str, executor: StashedExecutor) -> Result<(), PluginError> {
+        let plugin = self.find_task_slot(name)?;
+        plugin.tasks.push(executor);
+        Ok(())
+    }
+
+    /// Start a new task which calls the function with the given name from the plugin's
+    /// environment.
+    pub fn spawn(&mut self, lua: &mut Lua, name: &str, function: &str) -> Result<(), PluginError> {
+        let plugin = self.find_task_slot(name)?;
+        let executor = plugin.start(lua, function)?;
+        plugin.tasks.push(executor);
+        Ok(())
+    }
+
+    /// Set a timer which starts a new task calling the function with the given name from the
+    /// plugin's environment, once `delay` calls to `PluginManager::step` have passed.
+    ///
+    /// With a `delay` of 0, the task starts during the next step. A timer which fires while the
+    /// plugin is at its `max_tasks` quota waits until one of the plugin's tasks has finished.
+    pub fn set_timer(
+        &mut self,
+        lua: &mut Lua,
+        name: &str,
+        function: &str,
+        delay: u32,
+    ) -> Result<(), PluginError> {
+        let plugin = self.find_enabled(name)?;
+        if let Some(max_timers) = plugin.quota.max_timers {
+            if plugin.timers.len() >= max_timers {
+                return Err(PluginError::TimerQuotaExceeded {
+                    plugin: name.to_owned(),
+                    max_timers,
+                });
+            }
+        }
+        let executor = plugin.start(lua, function)?;
+        plugin.timers.push(Timer { delay, executor });
+        Ok(())
+    }
+
+    /// Step every task of every enabled plugin once, giving each task `fuel_per_task` fuel.
+    ///
+    /// A task which yields is resumed on the next call to `PluginManager::step`, so a task can
+    /// yield once per step to wait for the next one. Tasks which return are removed from their
+    /// plugin. Tasks which finish with an error are also removed, and their errors are returned
+    /// along with the name of the owning plugin.
+    ///
+    /// A plugin with a `max_fuel_per_step` quota gives each task at most its remaining budget, and
+    /// tasks which are left without any budget are not stepped.
+    ///
+    /// Timers which are due start their tasks before any tasks are stepped, so a new task is first
+    /// stepped in the same step its timer fires.
+    pub fn step(&mut self, lua: &mut Lua, fuel_per_task: i32) -> Vec<(StdString, StaticError)> {
+        let mut errors = Vec::new();
+
+        for plugin in self.plugins.iter_mut().filter(|p| p.enabled) {
+            let mut timers = Vec::new();
+            for mut timer in plugin.timers.drain(..) {
+                let has_slot = plugin
+                    .quota
+                    .max_tasks
+                    .map_or(true, |max_tasks| plugin.tasks.len() < max_tasks);
+                if timer.delay > 0 {
+                    timer.delay -= 1;
+                    timers.push(timer);
+                } else if has_slot {
+                    plugin.tasks.push(timer.executor);
+                } else {
+                    timers.push(timer);
+                }
+            }
+            plugin.timers = timers;
+
+            let mut budget = plugin.quota.max_fuel_per_step;
+            let mut waiting = Vec::new();
+            let mut stepped = Vec::new();
+
+            for task in plugin.tasks.drain(..) {
+                let task_fuel = match budget {
+                    Some(budget) if budget <= 0 => {
+                        waiting.push(task);
+                        continue;
+                    }
+                    Some(budget) => fuel_per_task.min(budget),
+                    None => fuel_per_task,
+                };
+
+                let mut fuel = Fuel::with(task_fuel);
+                let running = lua.enter(|ctx| {
+                    let executor = ctx.fetch(&task);
+                    if !executor.step(ctx, &mut fuel) {
+                        return true;
+                    }
+
+                    match executor.mode() {
+                        ExecutorMode::Suspended => {
+                            executor.resume(ctx, ()).unwrap();
+                            true
+                        }
+                        ExecutorMode::Result => {
+                            if let Err(err) = executor.take_result::<()>(ctx).unwrap() {
+                                errors.push((plugin.name.clone(), err.into_static()));
+                            }
+                            false
+                        }
+                        _ => false,
+                    }
+                });
+
+                if let Some(budget) = &mut budget {
+                    *budget -= task_fuel - fuel.remaining();
+                }
+                if running {
+                    stepped.push(task);
+                }
+            }
+
+            // Tasks which had to wait go first on the next step.
+            waiting.extend(stepped);
+            plugin.tasks = waiting;
+        }
+
+        if lua.enter(|ctx| ctx.finalizers().has_pending()) {
+            lua.run_finalizers();
+        }
+
+        errors
+    }
+
+    /// Enable a disabled plugin, allowing new tasks to be spawned.
+    pub fn enable(&mut self, name: &str) -> Result<(), PluginError> {
+        let plugin = self.find_mut(name)?;
+        plugin.enabled = true;
+        Ok(())
+    }
+
+    /// Disable a plugin, stopping all of its tasks and cancelling all of its timers.
+    pub fn disable(&mut self, lua: &mut Lua, name: &str) -> Result<(), PluginError> {
+        let plugin = self.find_mut(name)?;
+        plugin.enabled = false;
+        lua.enter(|ctx| {
+            for task in plugin.tasks.drain(..) {
+                ctx.fetch(&task).stop(&ctx);
+            }
+            for timer in plugin.timers.drain(..) {
+                ctx.fetch(&timer.executor).stop(&ctx);
+            }
+        });
+        Ok(())
+    }
+
+    /// Shut down every loaded plugin.
+    ///
+    /// Each plugin which defines a global `on_shutdown` function has it called with no arguments
+    /// and given `handler_fuel` fuel to finish, during which it may yield and is resumed
+    /// immediately. A handler which raises an error or runs out of fuel is stopped, and its error
+    /// is returned along with the name of the owning plugin. Handlers are run in the order plugins
+    /// were loaded, and no other tasks are stepped while they run.
+    ///
+    /// Afterwards every task of every plugin is stopped and every plugin is disabled. Plugins stay
+    /// loaded, so their environments can still be inspected before they are unloaded.
+    pub fn shutdown(&mut self, lua: &mut Lua, handler_fuel: i32) -> Vec<(StdString, PluginError)> {
+        let mut errors = Vec::new();
+
+        for plugin in &self.plugins {
+            let result = lua.enter(|ctx| {
+                let env: Table = ctx.fetch(&plugin.env);
+                let Value::Function(handler) = env.get(ctx, "on_shutdown") else {
+                    return Ok(());
+                };
+
+                let executor = Executor::start(ctx, handler, ());
+                let mut fuel = Fuel::with(handler_fuel);
+                loop {
+                    if !executor.step(ctx, &mut fuel) {
+                        executor.stop(&ctx);
+                        return Err(PluginError::ShutdownTimedOut(plugin.name.clone()));
+                    }
+
+                    match executor.mode() {
+                        ExecutorMode::Suspended if fuel.should_continue() => {
+                            executor.resume(ctx, ()).unwrap();
+                        }
+                        ExecutorMode::Suspended => {
+                            executor.stop(&ctx);
+                            return Err(PluginError::ShutdownTimedOut(plugin.name.clone()));
+                        }
+                        ExecutorMode::Result => {
+                            return executor
+                                .take_result::<()>(ctx)
+                                .unwrap()
+                                .map_err(|err| PluginError::Lua(err.into_static()));
+                        }
+                        _ => return Ok(()),
+                    }
+                }
+            });
+            if let Err(err) = result {
+                errors.push((plugin.name.clone(), err));
+            }
+        }
+
+        let names = self
+            .plugins
+            .iter()
+            .map(|p| p.name.clone())
+            .collect::<Vec<_>>();
+        for name in names {
+            self.disable(lua, &name).expect("plugin is loaded");
+        }
+
+        errors
+    }
+
+    /// Disable and then unload a plugin, dropping its environment.
+    ///
+    /// This performs a full garbage collection and runs finalizers, so any `__gc` metamethods of
+    /// objects which were only reachable from the plugin are called before this returns.
+    pub fn unload(&mut self, lua: &mut Lua, name: &str) -> Result<(), PluginError> {
+        self.disable(lua, name)?;
+        let index = self
+            .plugins
+            .iter()
+            .position(|p| p.name == name)
+            .expect("plugin was just disabled");
+        self.plugins.remove(index);
+
+        lua.gc_collect();
+        lua.run_finalizers();
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&Plugin> {
+        self.plugins.iter().find(|p| p.name == name)
+    }
+
+    fn find_mut(&mut self, name: &str) -> Result<&mut Plugin, PluginError> {
+        self.plugins
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| PluginError::NotLoaded(name.to_owned()))
+    }
+
+    fn find_enabled(&mut self, name: &str) -> Result<&mut Plugin, PluginError> {
+        let plugin = self.find_mut(name)?;
+        if plugin.enabled {
+            Ok(plugin)
+        } else {
+            Err(PluginError::Disabled(name.to_owned()))
+        }
+    }
+
+    // Find an enabled plugin which is allowed to start another task.
+    fn find_task_slot(&mut self, name: &str) -> Result<&mut Plugin, PluginError> {
+        let plugin = self.find_enabled(name)?;
+        match plugin.quota.max_tasks {
+            Some(max_tasks) if plugin.tasks.len() >= max_tasks => {
+                Err(PluginError::TaskQuotaExceeded {
+                    plugin: name.to_owned(),
+                    max_tasks,
+                })
+            }
+            _ => Ok(plugin),
+        }
+    }
+}
+
+impl Plugin {
+    // Start an executor which calls the named function from the plugin's environment.
+    fn start(&self, lua: &mut Lua, function: &str) -> Result<StashedExecutor, PluginError> {
+        let executor = lua.enter(|ctx| {
+            let env: Table = ctx.fetch(&self.env);
+            match env.get(ctx, ctx.intern(function.as_bytes())) {
+                Value::Function(f) => Some(ctx.stash(Executor::start(ctx, f, ()))),
+                _ => None,
+            }
+        });
+        executor.ok_or_else(|| PluginError::NoSuchFunction {
+            plugin: self.name.clone(),
+            name: function.to_owned(),
+        })
+    }
+}
@@ -0,0 +1,66 @@
+//! Debug build checks which catch values from one `Lua` instance being stored in another.
+//!
+//! The `'gc` branding of gc-arena normally makes this impossible, but it can be defeated by
+//! unsafe host code (for example by transmuting lifetimes), and a value which ends up reachable
+//! from the wrong arena is never traced by it, which silently corrupts both instances. In debug
+//! builds every table, closure, thread and userdata remembers the arena it was allocated in, and
+//! storing one of them in an object of another arena panics immediately. In release builds these
+//! checks compile to nothing.
+
+use gc_arena::{Collect, Mutation};
+
+use crate::Value;
+
+/// Identifies the arena an object was allocated in.
+///
+/// This is zero sized in release builds. The default tag is unknown and never fails a check.
+#[derive(Debug, Copy, Clone, Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct ArenaTag {
+    #[cfg(debug_assertions)]
+    arena: Option<usize>,
+}
+
+impl ArenaTag {
+    #[inline]
+    pub(crate) fn new(mc: &Mutation<'_>) -> Self {
+        #[cfg(not(debug_assertions))]
+        let _ = mc;
+        Self {
+            // Each arena hands out a single `Mutation` for its whole lifetime, so its address
+            // identifies the arena.
+            #[cfg(debug_assertions)]
+            arena: Some(mc as *const Mutation<'_> as usize),
+        }
+    }
+}
+
+/// Panics if `value` was allocated in a different arena than the one `mc` belongs to.
+///
+/// `target` describes where the value was being stored, for the panic message.
+#[inline]
+pub(crate) fn check_value<'gc>(mc: &Mutation<'gc>, value: Value<'gc>, target: &str) {
+    #[cfg(debug_assertions)]
+    {
+        use crate::Function;
+
+        let tag = match value {
+            Value::Table(t) => t.arena_tag(),
+            Value::Function(Function::Closure(c)) => c.arena_tag(),
+            Value::Thread(t) => t.arena_tag(),
+            Value::UserData(u) => u.arena_tag(),
+            _ => ArenaTag::default(),
+        };
+        if let Some(arena) = tag.arena {
+            if arena != mc as *const Mutation<'gc> as usize {
+                panic!(
+                    "a {} from a different `Lua` instance was stored in {}",
+                    value.type_name(),
+                    target
+                );
+            }
+        }
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = (mc, value, target);
+}
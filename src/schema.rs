@@ -0,0 +1,317 @@
+//! Validation of option tables passed from scripts.
+//!
+//! A [`Schema`] describes the fields a table may contain, their types, the range of numeric fields
+//! and the default values of missing fields. [`Schema::validate`] checks a table against it in one
+//! call and reports every problem it finds at once, each qualified with the path to the offending
+//! field:
+//!
+//! ```text
+//! invalid options: opts.width: 0 is out of range 1..=4096; opts.title: expected string, got table
+//! ```
+
+use std::{fmt, ops::RangeInclusive, string::String as StdString};
+
+use thiserror::Error;
+
+use crate::{Context, IntoValue, OwnedValue, Table, Value};
+
+/// The type a field must have to pass validation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    /// Any value other than nil.
+    Any,
+    Boolean,
+    /// An integer. Floats are not accepted, even if they have an integral value.
+    Integer,
+    /// An integer or a float.
+    Number,
+    String,
+    Table,
+    Function,
+    /// A table which is itself validated against a schema.
+    Record(Schema),
+    /// A sequence, every element of which must have the given type.
+    List(Box<FieldType>),
+}
+
+impl FieldType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldType::Any => "any value",
+            FieldType::Boolean => "boolean",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::String => "string",
+            FieldType::Table | FieldType::Record(_) => "table",
+            FieldType::Function => "function",
+            FieldType::List(_) => "list",
+        }
+    }
+
+    fn matches(&self, value: Value<'_>) -> bool {
+        match (self, value) {
+            (_, Value::Nil) => false,
+            (FieldType::Any, _) => true,
+            (FieldType::Boolean, Value::Boolean(_)) => true,
+            (FieldType::Integer, Value::Integer(_)) => true,
+            (FieldType::Number, Value::Integer(_) | Value::Number(_)) => true,
+            (FieldType::String, Value::String(_)) => true,
+            (FieldType::Table | FieldType::Record(_) | FieldType::List(_), Value::Table(_)) => true,
+            (FieldType::Function, Value::Function(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A single named field of a [`Schema`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    name: StdString,
+    ty: FieldType,
+    required: bool,
+    default: Option<OwnedValue>,
+    range: Option<RangeInclusive<f64>>,
+}
+
+impl Field {
+    /// An optional field of the given type.
+    pub fn new(name: impl Into<StdString>, ty: FieldType) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            required: false,
+            default: None,
+            range: None,
+        }
+    }
+
+    /// Make the field required, it is an error for it to be missing.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Store the given value in the table if the field is missing.
+    ///
+    /// The default is not validated, and a field with a default is never reported as missing.
+    pub fn default(mut self, value: impl Into<OwnedValue>) -> Self {
+        self.default = Some(value.into());
+        self
+    }
+
+    /// Require the value of a numeric field to be within the given range. The range is ignored for
+    /// fields of any other type.
+    pub fn range(mut self, range: RangeInclusive<f64>) -> Self {
+        self.range = Some(range);
+        self
+    }
+}
+
+/// A description of the fields of an option table, see the [module documentation](self).
+///
+/// Tables may not contain string keys which are not fields of the schema, since these are usually
+/// misspellings, unless [`Schema::allow_unknown`] is set. Keys which are not strings are always
+/// ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    fields: Vec<Field>,
+    allow_unknown: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Accept string keys which are not fields of the schema.
+    pub fn allow_unknown(mut self) -> Self {
+        self.allow_unknown = true;
+        self
+    }
+
+    /// Validate a table against this schema, storing the defaults of missing fields into it.
+    ///
+    /// A nil value is treated as an empty table, and a new table is returned in its place. The
+    /// `name` is the start of the path given for every issue, such as the name of the function
+    /// argument being validated.
+    pub fn validate<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        name: &str,
+        value: Value<'gc>,
+    ) -> Result<Table<'gc>, SchemaError> {
+        let mut issues = Vec::new();
+        let table = match value {
+            Value::Nil => Table::new(&ctx),
+            Value::Table(table) => table,
+            value => {
+                issues.push(SchemaIssue {
+                    path: name.to_owned(),
+                    kind: SchemaIssueKind::WrongType {
+                        expected: "table",
+                        found: value.type_name(),
+                    },
+                });
+                return Err(SchemaError { issues });
+            }
+        };
+
+        self.validate_table(ctx, name, table, &mut issues);
+        if issues.is_empty() {
+            Ok(table)
+        } else {
+            Err(SchemaError { issues })
+        }
+    }
+
+    fn validate_table<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        path: &str,
+        table: Table<'gc>,
+        issues: &mut Vec<SchemaIssue>,
+    ) {
+        for field in &self.fields {
+            let path = format!("{path}.{}", field.name);
+            let value = table.get(ctx, field.name.as_str());
+            if value.is_nil() {
+                if let Some(default) = &field.default {
+                    // Keys which are strings are always valid.
+                    table
+                        .set(ctx, field.name.as_str(), default.clone().into_value(ctx))
+                        .unwrap();
+                } else if field.required {
+                    issues.push(SchemaIssue {
+                        path,
+                        kind: SchemaIssueKind::Missing,
+                    });
+                }
+                continue;
+            }
+
+            validate_value(ctx, path, &field.ty, field.range.as_ref(), value, issues);
+        }
+
+        if !self.allow_unknown {
+            for (key, _) in table.iter() {
+                if let Value::String(key) = key {
+                    let key = key.to_str_lossy();
+                    if !self.fields.iter().any(|f| f.name == key) {
+                        issues.push(SchemaIssue {
+                            path: format!("{path}.{key}"),
+                            kind: SchemaIssueKind::Unknown,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn validate_value<'gc>(
+    ctx: Context<'gc>,
+    path: StdString,
+    ty: &FieldType,
+    range: Option<&RangeInclusive<f64>>,
+    value: Value<'gc>,
+    issues: &mut Vec<SchemaIssue>,
+) {
+    if !ty.matches(value) {
+        issues.push(SchemaIssue {
+            path,
+            kind: SchemaIssueKind::WrongType {
+                expected: ty.name(),
+                found: value.type_name(),
+            },
+        });
+        return;
+    }
+
+    let number = match value {
+        Value::Integer(i) => Some(i as f64),
+        Value::Number(n) => Some(n),
+        _ => None,
+    };
+    if let (Some(range), Some(n)) = (range, number) {
+        if !range.contains(&n) {
+            issues.push(SchemaIssue {
+                path,
+                kind: SchemaIssueKind::OutOfRange {
+                    value: n,
+                    min: *range.start(),
+                    max: *range.end(),
+                },
+            });
+        }
+        return;
+    }
+
+    match (ty, value) {
+        (FieldType::Record(schema), Value::Table(table)) => {
+            schema.validate_table(ctx, &path, table, issues);
+        }
+        (FieldType::List(element), Value::Table(table)) => {
+            for i in 1..=table.length() {
+                let path = format!("{path}[{i}]");
+                match table.get(ctx, i) {
+                    Value::Nil => issues.push(SchemaIssue {
+                        path,
+                        kind: SchemaIssueKind::Missing,
+                    }),
+                    value => validate_value(ctx, path, element, None, value, issues),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A single problem found by [`Schema::validate`].
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{path}: {kind}")]
+pub struct SchemaIssue {
+    /// The path to the field, starting with the name given to `Schema::validate`, such as
+    /// `options.window.size` or `options.items[2]`.
+    pub path: StdString,
+    pub kind: SchemaIssueKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SchemaIssueKind {
+    #[error("missing required field")]
+    Missing,
+    #[error("expected {expected}, got {found}")]
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("{value} is out of range {min}..={max}")]
+    OutOfRange { value: f64, min: f64, max: f64 },
+    #[error("unknown field")]
+    Unknown,
+}
+
+/// Every problem found by [`Schema::validate`], in the order the fields were declared, followed by
+/// any unknown fields.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub struct SchemaError {
+    pub issues: Vec<SchemaIssue>,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid options: ")?;
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i != 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{issue}")?;
+        }
+        Ok(())
+    }
+}
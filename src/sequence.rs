@@ -0,0 +1,558 @@
+//! Combinators for building [`Sequence`]s out of smaller steps.
+//!
+//! Writing a `Sequence` by hand means writing a state machine, which is a lot of ceremony for
+//! callbacks which only need to do a few things in order, like calling a function and then
+//! adjusting its results. The combinators in [`SequenceExt`] chain sequences together instead:
+//!
+//! ```
+//! # use piccolo::{Callback, CallbackReturn, Function, sequence::{Call, SequenceExt}};
+//! # piccolo::Lua::core().enter(|ctx| {
+//! let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+//!     let f: Function = stack.from_front(ctx)?;
+//!     let g: Function = stack.from_front(ctx)?;
+//!     // Calls `f` with the rest of the arguments, adds one to its result and passes that to `g`.
+//!     let seq = Call(f)
+//!         .map(|ctx, mut stack| {
+//!             let n: i64 = stack.consume(ctx)?;
+//!             stack.replace(ctx, n + 1);
+//!             Ok(())
+//!         })
+//!         .then_call(g);
+//!     Ok(CallbackReturn::Sequence(seq.boxed(&ctx)))
+//! });
+//! # });
+//! ```
+//!
+//! Closures passed to combinators must be `'static`, so any values they need from the arena must
+//! be passed along on the stack or held by the sequences themselves.
+//!
+//! This module also has ready-made continuations for `CallbackReturn::Call { then, .. }`, which
+//! are run with the results of the call on the stack: [`CollectTable`] packs the results into a
+//! table, [`Adjust`] truncates or pads them to a fixed count, and [`with_results`] converts them
+//! and hands them to a Rust closure.
+
+use gc_arena::{Collect, Mutation};
+
+use crate::{
+    BoxSequence, Context, Error, Execution, FromMultiValue, Function, IntoMultiValue, Sequence,
+    SequencePoll, Stack, Table,
+};
+
+/// A `Sequence` which calls a function with its arguments and returns the function's results.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Call<'gc>(pub Function<'gc>);
+
+impl<'gc> Sequence<'gc> for Call<'gc> {
+    fn poll(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        Ok(SequencePoll::TailCall(self.0))
+    }
+}
+
+/// A `Sequence` which packs the values on the stack into a new table and returns it.
+///
+/// Values are stored at the keys `1..=n` and `n` is stored under the key `"n"`, like
+/// `table.pack`.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(require_static)]
+pub struct CollectTable {
+    count: Option<usize>,
+}
+
+impl CollectTable {
+    /// Collect every value on the stack.
+    pub fn all() -> Self {
+        Self { count: None }
+    }
+
+    /// Collect only the first `count` values, padding with `nil` if there are fewer.
+    pub fn first(count: usize) -> Self {
+        Self { count: Some(count) }
+    }
+}
+
+impl<'gc> Sequence<'gc> for CollectTable {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(count) = self.count {
+            stack.resize(count);
+        }
+        let len = stack.len();
+        let table = Table::new(&ctx);
+        for (i, value) in stack.drain(..).enumerate() {
+            table.set(ctx, i as i64 + 1, value)?;
+        }
+        table.set(ctx, "n", len as i64)?;
+        stack.replace(ctx, table);
+        Ok(SequencePoll::Return)
+    }
+}
+
+/// A `Sequence` which returns exactly the given number of values from the stack, dropping extra
+/// values and padding with `nil`.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(require_static)]
+pub struct Adjust(pub usize);
+
+impl<'gc> Sequence<'gc> for Adjust {
+    fn poll(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.resize(self.0);
+        Ok(SequencePoll::Return)
+    }
+}
+
+/// Create a `Sequence` which converts the values on the stack to `A`, calls `f` with them and
+/// returns the values `f` returns.
+///
+/// ```
+/// # use piccolo::{Callback, CallbackReturn, Function, sequence::with_results};
+/// # piccolo::Lua::core().enter(|ctx| {
+/// let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+///     let function: Function = stack.from_front(ctx)?;
+///     Ok(CallbackReturn::Call {
+///         function,
+///         then: Some(with_results(&ctx, |_, _, (a, b): (i64, i64)| Ok(a + b))),
+///     })
+/// });
+/// # });
+/// ```
+pub fn with_results<'gc, A, R, F>(mc: &Mutation<'gc>, f: F) -> BoxSequence<'gc>
+where
+    A: FromMultiValue<'gc>,
+    R: IntoMultiValue<'gc>,
+    F: 'static + FnOnce(Context<'gc>, Execution<'gc, '_>, A) -> Result<R, Error<'gc>>,
+{
+    BoxSequence::new(
+        mc,
+        WithResults {
+            f: Some(
+                move |ctx: Context<'gc>, exec: Execution<'gc, '_>, mut stack: Stack<'gc, '_>| {
+                    let args = stack.consume(ctx)?;
+                    let results = f(ctx, exec, args)?;
+                    stack.replace(ctx, results);
+                    Ok(())
+                },
+            ),
+        },
+    )
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct WithResults<F> {
+    f: Option<F>,
+}
+
+impl<'gc, F> Sequence<'gc> for WithResults<F>
+where
+    F: 'static + FnOnce(Context<'gc>, Execution<'gc, '_>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+{
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let f = self
+            .f
+            .take()
+            .expect("`with_results` sequence polled after returning");
+        f(ctx, exec, stack)?;
+        Ok(SequencePoll::Return)
+    }
+}
+
+/// Combinators available on every [`Sequence`].
+pub trait SequenceExt<'gc>: Sequence<'gc> + Sized {
+    /// Once this sequence returns, run `next` with the returned values as its arguments.
+    fn then<N: Sequence<'gc>>(self, next: N) -> Then<Self, N> {
+        Then {
+            first: Front::new(self),
+            second: next,
+            started: false,
+        }
+    }
+
+    /// Once this sequence returns, call `function` with the returned values and return its
+    /// results.
+    fn then_call(self, function: Function<'gc>) -> Then<Self, Call<'gc>> {
+        self.then(Call(function))
+    }
+
+    /// Once this sequence returns, call `f` with the returned values on the stack and return
+    /// whatever `f` leaves there.
+    fn map<F>(self, f: F) -> Map<Self, F>
+    where
+        F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+    {
+        Map {
+            front: Front::new(self),
+            f: Some(f),
+        }
+    }
+
+    /// Once this sequence returns, call `f` with the returned values on the stack to pick the
+    /// sequence to run next, which is started with whatever `f` leaves on the stack.
+    fn and_then<F, N>(self, f: F) -> AndThen<Self, F, N>
+    where
+        F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<N, Error<'gc>>,
+        N: Sequence<'gc>,
+    {
+        AndThen {
+            front: Front::new(self),
+            f: Some(f),
+            next: None,
+        }
+    }
+
+    /// Replace any error raised by this sequence, or by an action it triggered and did not handle,
+    /// with the result of `f`.
+    fn map_err<F>(self, f: F) -> MapErr<Self, F>
+    where
+        F: 'static + FnMut(Context<'gc>, Error<'gc>) -> Error<'gc>,
+    {
+        MapErr { seq: self, f }
+    }
+
+    /// Box this sequence, ready to be returned from a callback as `CallbackReturn::Sequence`.
+    fn boxed(self, mc: &Mutation<'gc>) -> BoxSequence<'gc>
+    where
+        Self: 'gc,
+    {
+        BoxSequence::new(mc, self)
+    }
+}
+
+impl<'gc, S: Sequence<'gc>> SequenceExt<'gc> for S {}
+
+/// Returned by [`SequenceExt::then`].
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct Then<A, B> {
+    first: Front<A>,
+    second: B,
+    #[collect(require_static)]
+    started: bool,
+}
+
+impl<'gc, A: Sequence<'gc>, B: Sequence<'gc>> Sequence<'gc> for Then<A, B> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if !self.started {
+            match self.first.poll(ctx, exec.reborrow(), stack.reborrow())? {
+                FrontPoll::Action(poll) => return Ok(poll),
+                FrontPoll::Finished => self.started = true,
+            }
+        }
+        self.second.poll(ctx, exec, stack)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if self.started {
+            return self.second.error(ctx, exec, error, stack);
+        }
+        match self
+            .first
+            .error(ctx, exec.reborrow(), error, stack.reborrow())?
+        {
+            FrontPoll::Action(poll) => Ok(poll),
+            FrontPoll::Finished => {
+                self.started = true;
+                self.second.poll(ctx, exec, stack)
+            }
+        }
+    }
+
+    fn cancel(&mut self, mc: &Mutation<'gc>) {
+        if self.started {
+            self.second.cancel(mc);
+        } else {
+            self.first.seq.cancel(mc);
+        }
+    }
+}
+
+/// Returned by [`SequenceExt::map`].
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct Map<S, F> {
+    front: Front<S>,
+    #[collect(require_static)]
+    f: Option<F>,
+}
+
+impl<'gc, S, F> Map<S, F>
+where
+    F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+{
+    fn finish(
+        &mut self,
+        ctx: Context<'gc>,
+        front: FrontPoll<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        match front {
+            FrontPoll::Action(poll) => Ok(poll),
+            FrontPoll::Finished => {
+                let f = self
+                    .f
+                    .take()
+                    .expect("`Map` sequence polled after returning");
+                f(ctx, stack)?;
+                Ok(SequencePoll::Return)
+            }
+        }
+    }
+}
+
+impl<'gc, S, F> Sequence<'gc> for Map<S, F>
+where
+    S: Sequence<'gc>,
+    F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<(), Error<'gc>>,
+{
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let front = self.front.poll(ctx, exec, stack.reborrow())?;
+        self.finish(ctx, front, stack)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let front = self.front.error(ctx, exec, error, stack.reborrow())?;
+        self.finish(ctx, front, stack)
+    }
+
+    fn cancel(&mut self, mc: &Mutation<'gc>) {
+        self.front.seq.cancel(mc);
+    }
+}
+
+/// Returned by [`SequenceExt::and_then`].
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct AndThen<S, F, N> {
+    front: Front<S>,
+    #[collect(require_static)]
+    f: Option<F>,
+    next: Option<N>,
+}
+
+impl<'gc, S, F, N> AndThen<S, F, N>
+where
+    F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<N, Error<'gc>>,
+    N: Sequence<'gc>,
+{
+    fn finish(
+        &mut self,
+        ctx: Context<'gc>,
+        front: FrontPoll<'gc>,
+        exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        match front {
+            FrontPoll::Action(poll) => Ok(poll),
+            FrontPoll::Finished => {
+                let f = self
+                    .f
+                    .take()
+                    .expect("`AndThen` sequence polled after returning");
+                let next = self.next.insert(f(ctx, stack.reborrow())?);
+                next.poll(ctx, exec, stack)
+            }
+        }
+    }
+}
+
+impl<'gc, S, F, N> Sequence<'gc> for AndThen<S, F, N>
+where
+    S: Sequence<'gc>,
+    F: 'static + FnOnce(Context<'gc>, Stack<'gc, '_>) -> Result<N, Error<'gc>>,
+    N: Sequence<'gc>,
+{
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(next) = &mut self.next {
+            return next.poll(ctx, exec, stack);
+        }
+        let front = self.front.poll(ctx, exec.reborrow(), stack.reborrow())?;
+        self.finish(ctx, front, exec, stack)
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(next) = &mut self.next {
+            return next.error(ctx, exec, error, stack);
+        }
+        let front = self
+            .front
+            .error(ctx, exec.reborrow(), error, stack.reborrow())?;
+        self.finish(ctx, front, exec, stack)
+    }
+
+    fn cancel(&mut self, mc: &Mutation<'gc>) {
+        match &mut self.next {
+            Some(next) => next.cancel(mc),
+            None => self.front.seq.cancel(mc),
+        }
+    }
+}
+
+/// Returned by [`SequenceExt::map_err`].
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct MapErr<S, F> {
+    seq: S,
+    #[collect(require_static)]
+    f: F,
+}
+
+impl<'gc, S, F> Sequence<'gc> for MapErr<S, F>
+where
+    S: Sequence<'gc>,
+    F: 'static + FnMut(Context<'gc>, Error<'gc>) -> Error<'gc>,
+{
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.seq
+            .poll(ctx, exec, stack)
+            .map_err(|err| (self.f)(ctx, err))
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.seq
+            .error(ctx, exec, error, stack)
+            .map_err(|err| (self.f)(ctx, err))
+    }
+
+    fn cancel(&mut self, mc: &Mutation<'gc>) {
+        self.seq.cancel(mc);
+    }
+}
+
+// A sequence which has more work queued after it, so its tail actions are turned into regular
+// actions which come back to the combinator once they finish.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Front<S> {
+    seq: S,
+    // Set once `seq` has issued its final action, after which it is never polled again.
+    #[collect(require_static)]
+    done: bool,
+}
+
+enum FrontPoll<'gc> {
+    Action(SequencePoll<'gc>),
+    // The sequence has returned and its return values are on the stack.
+    Finished,
+}
+
+impl<S> Front<S> {
+    fn new(seq: S) -> Self {
+        Self { seq, done: false }
+    }
+
+    fn untail<'gc>(&mut self, poll: SequencePoll<'gc>) -> FrontPoll<'gc> {
+        let action = match poll {
+            SequencePoll::Return => return FrontPoll::Finished,
+            SequencePoll::TailCall(function) => SequencePoll::Call {
+                bottom: 0,
+                function,
+            },
+            SequencePoll::TailYield(to_thread) => SequencePoll::Yield {
+                bottom: 0,
+                to_thread,
+            },
+            SequencePoll::TailResume(thread) => SequencePoll::Resume { bottom: 0, thread },
+            poll => return FrontPoll::Action(poll),
+        };
+        self.done = true;
+        FrontPoll::Action(action)
+    }
+
+    fn poll<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<FrontPoll<'gc>, Error<'gc>>
+    where
+        S: Sequence<'gc>,
+    {
+        if self.done {
+            return Ok(FrontPoll::Finished);
+        }
+        let poll = self.seq.poll(ctx, exec, stack)?;
+        Ok(self.untail(poll))
+    }
+
+    fn error<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> Result<FrontPoll<'gc>, Error<'gc>>
+    where
+        S: Sequence<'gc>,
+    {
+        // The final action of the sequence failed, which the sequence would never have seen if it
+        // had been run as a tail action.
+        if self.done {
+            return Err(error);
+        }
+        let poll = self.seq.error(ctx, exec, error, stack)?;
+        Ok(self.untail(poll))
+    }
+}
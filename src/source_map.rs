@@ -0,0 +1,69 @@
+use gc_arena::Collect;
+
+use crate::{compiler::LineNumber, String};
+
+/// Maps lines of a chunk back to the original files it was built from.
+///
+/// Scripts are sometimes preprocessed or bundled together into a single chunk before being
+/// loaded. A `SourceMap` supplied with `Closure::load_with_source_map` records which file and
+/// line each part of the bundled chunk came from, so that debugging information such as
+/// [`HookInfo`](crate::HookInfo) can report locations in the original files.
+///
+/// The map is made of segments. Each segment starts at a line of the chunk, and every line from
+/// there up to the start of the next segment is assumed to come from consecutive lines of a single
+/// original file. Lines before the first segment are not mapped.
+#[derive(Debug, Clone, Default, Collect)]
+#[collect(no_drop)]
+pub struct SourceMap<'gc> {
+    segments: Vec<Segment<'gc>>,
+}
+
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct Segment<'gc> {
+    chunk_line: LineNumber,
+    file: String<'gc>,
+    line: LineNumber,
+}
+
+impl<'gc> SourceMap<'gc> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a segment which maps `chunk_line` and the lines after it to `file`, starting at
+    /// `line`.
+    ///
+    /// If a segment already starts at `chunk_line`, it is replaced.
+    pub fn add_segment(&mut self, chunk_line: LineNumber, file: String<'gc>, line: LineNumber) {
+        let segment = Segment {
+            chunk_line,
+            file,
+            line,
+        };
+        match self
+            .segments
+            .binary_search_by_key(&chunk_line, |s| s.chunk_line)
+        {
+            Ok(i) => self.segments[i] = segment,
+            Err(i) => self.segments.insert(i, segment),
+        }
+    }
+
+    /// Returns the original file and line of a line in the chunk, if it is mapped.
+    pub fn map(&self, chunk_line: LineNumber) -> Option<(String<'gc>, LineNumber)> {
+        let i = match self
+            .segments
+            .binary_search_by_key(&chunk_line, |s| s.chunk_line)
+        {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let segment = &self.segments[i];
+        Some((
+            segment.file,
+            LineNumber(segment.line.0 + (chunk_line.0 - segment.chunk_line.0)),
+        ))
+    }
+}
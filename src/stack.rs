@@ -1,29 +1,112 @@
 use std::{
+    cell::Cell,
     iter,
     ops::{Bound, Index, IndexMut, RangeBounds},
     slice::{self, SliceIndex},
 };
 
 use allocator_api2::vec;
-use gc_arena::allocator_api::MetricsAlloc;
+use gc_arena::{allocator_api::MetricsAlloc, Collect};
+use thiserror::Error;
 
 use crate::{Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, TypeError, Value};
 
+/// Limits on the values left on the stack by callbacks and sequences, set with
+/// `Context::set_stack_limits`.
+///
+/// Limits are checked whenever a callback or sequence finishes a step, and exceeding one is an
+/// error in the calling thread. They guard against bindings which push an unbounded number of
+/// values, and do not limit the stack used by Lua functions themselves. By default, there are no
+/// limits.
+///
+/// The stack size limit is also enforced while a callback or sequence grows the stack, so that
+/// memory use stays bounded until the step finishes. The stack grows to at most one value past the
+/// limit, and any further values are discarded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StackLimits {
+    /// The most values a callback or sequence may return.
+    pub max_returns: usize,
+    /// The most values the stack of a single thread may hold.
+    pub max_stack_size: usize,
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        Self {
+            max_returns: usize::MAX,
+            max_stack_size: usize::MAX,
+        }
+    }
+}
+
+impl StackLimits {
+    /// Checks the stack after a callback or sequence step, which left `len` values on its part of
+    /// a thread stack holding `total` values.
+    pub(crate) fn check(
+        &self,
+        len: usize,
+        total: usize,
+        returning: bool,
+    ) -> Result<(), StackLimitError> {
+        if returning && len > self.max_returns {
+            Err(StackLimitError::TooManyReturns {
+                count: len,
+                max: self.max_returns,
+            })
+        } else if total > self.max_stack_size {
+            Err(StackLimitError::StackOverflow {
+                size: total,
+                max: self.max_stack_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum StackLimitError {
+    #[error("callback returned too many values ({count}, the limit is {max})")]
+    TooManyReturns { count: usize, max: usize },
+    #[error("stack overflow ({size} values, the limit is {max})")]
+    StackOverflow { size: usize, max: usize },
+}
+
+/// Singleton holding the current `StackLimits`.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct StackLimitsSetting(pub(crate) Cell<StackLimits>);
+
 pub struct Stack<'gc, 'a> {
     values: &'a mut vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     bottom: usize,
+    limits: StackLimits,
 }
 
 impl<'gc, 'a> Stack<'gc, 'a> {
     pub fn new(values: &'a mut vec::Vec<Value<'gc>, MetricsAlloc<'gc>>, bottom: usize) -> Self {
         assert!(values.len() >= bottom);
-        Self { values, bottom }
+        Self {
+            values,
+            bottom,
+            limits: StackLimits::default(),
+        }
+    }
+
+    /// Set the limits reported by `Stack::remaining_capacity`.
+    ///
+    /// The `Executor` sets the limits configured with `Context::set_stack_limits` on the stacks it
+    /// gives to callbacks and sequences.
+    pub fn with_limits(mut self, limits: StackLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
     pub fn reborrow(&mut self) -> Stack<'gc, '_> {
         Stack {
             values: self.values,
             bottom: self.bottom,
+            limits: self.limits,
         }
     }
 
@@ -31,9 +114,18 @@ impl<'gc, 'a> Stack<'gc, 'a> {
         Stack {
             values: self.values,
             bottom: self.bottom + bottom,
+            limits: self.limits,
         }
     }
 
+    /// Returns how many more values can be pushed onto this stack before it exceeds its
+    /// `StackLimits`, and returning it would be an error.
+    pub fn remaining_capacity(&self) -> usize {
+        let returns = self.limits.max_returns.saturating_sub(self.len());
+        let size = self.limits.max_stack_size.saturating_sub(self.values.len());
+        returns.min(size)
+    }
+
     pub fn get(&self, i: usize) -> Value<'gc> {
         self.values
             .get(self.bottom + i)
@@ -42,11 +134,15 @@ impl<'gc, 'a> Stack<'gc, 'a> {
     }
 
     pub fn push_back(&mut self, value: Value<'gc>) {
-        self.values.push(value);
+        if self.values.len() < self.size_limit() {
+            self.values.push(value);
+        }
     }
 
     pub fn push_front(&mut self, value: Value<'gc>) {
-        self.values.insert(self.bottom, value);
+        if self.values.len() < self.size_limit() {
+            self.values.insert(self.bottom, value);
+        }
     }
 
     pub fn pop_back(&mut self) -> Option<Value<'gc>> {
@@ -78,11 +174,14 @@ impl<'gc, 'a> Stack<'gc, 'a> {
     }
 
     pub fn resize(&mut self, size: usize) {
-        self.values.resize(self.bottom + size, Value::Nil);
+        let size = self.bottom.saturating_add(size);
+        let limit = self.size_limit().max(self.values.len());
+        self.values.resize(size.min(limit), Value::Nil);
     }
 
     pub fn reserve(&mut self, additional: usize) {
-        self.values.reserve(additional);
+        let room = self.size_limit().saturating_sub(self.values.len());
+        self.values.reserve(additional.min(room));
     }
 
     pub fn capacity(&self) -> usize {
@@ -107,17 +206,13 @@ impl<'gc, 'a> Stack<'gc, 'a> {
     }
 
     pub fn into_back(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
-        for v in v.into_multi_value(ctx) {
-            self.values.push(v.into_value(ctx));
-        }
+        self.extend_limited(v.into_multi_value(ctx));
     }
 
     pub fn into_front(&mut self, ctx: Context<'gc>, v: impl IntoMultiValue<'gc>) {
-        let mut c = 0;
-        for v in v.into_multi_value(ctx) {
-            c += 1;
-            self.values.push(v.into_value(ctx));
-        }
+        let len = self.values.len();
+        self.extend_limited(v.into_multi_value(ctx));
+        let c = self.values.len() - len;
         self.values[self.bottom..].rotate_right(c);
     }
 
@@ -137,6 +232,19 @@ impl<'gc, 'a> Stack<'gc, 'a> {
     pub fn consume<V: FromMultiValue<'gc>>(&mut self, ctx: Context<'gc>) -> Result<V, TypeError> {
         V::from_multi_value(ctx, self.drain(..))
     }
+
+    // The stack may hold one value more than `StackLimits::max_stack_size`, so that the check made
+    // once a callback or sequence step finishes still finds that the limit was exceeded.
+    fn size_limit(&self) -> usize {
+        self.limits.max_stack_size.saturating_add(1)
+    }
+
+    // Pushes values until the stack reaches its size limit, without consuming the rest of the
+    // iterator.
+    fn extend_limited(&mut self, iter: impl IntoIterator<Item = Value<'gc>>) {
+        let room = self.size_limit().saturating_sub(self.values.len());
+        self.values.extend(iter.into_iter().take(room));
+    }
 }
 
 impl<'gc: 'b, 'a, 'b> IntoIterator for &'b Stack<'gc, 'a> {
@@ -150,25 +258,25 @@ impl<'gc: 'b, 'a, 'b> IntoIterator for &'b Stack<'gc, 'a> {
 
 impl<'gc, 'a> Extend<Value<'gc>> for Stack<'gc, 'a> {
     fn extend<T: IntoIterator<Item = Value<'gc>>>(&mut self, iter: T) {
-        self.values.extend(iter);
+        self.extend_limited(iter);
     }
 }
 
 impl<'gc, 'a, 'b> Extend<Value<'gc>> for &'b mut Stack<'gc, 'a> {
     fn extend<T: IntoIterator<Item = Value<'gc>>>(&mut self, iter: T) {
-        self.values.extend(iter);
+        self.extend_limited(iter);
     }
 }
 
 impl<'gc: 'b, 'a, 'b> Extend<&'a Value<'gc>> for Stack<'gc, 'a> {
     fn extend<T: IntoIterator<Item = &'a Value<'gc>>>(&mut self, iter: T) {
-        self.values.extend(iter);
+        self.extend_limited(iter.into_iter().copied());
     }
 }
 
 impl<'gc: 'b, 'a, 'b, 'c> Extend<&'b Value<'gc>> for &'c mut Stack<'gc, 'a> {
     fn extend<T: IntoIterator<Item = &'b Value<'gc>>>(&mut self, iter: T) {
-        self.values.extend(iter);
+        self.extend_limited(iter.into_iter().copied());
     }
 }
 
@@ -1,12 +1,18 @@
-use gc_arena::Collect;
+use std::string::String as StdString;
+
+use gc_arena::{metrics::Pacing, Collect, Rootable};
 
 use crate::{
-    meta_ops::{self, MetaResult},
-    table::NextValue,
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, MetaMethod,
-    Sequence, SequencePoll, Stack, String, Table, TypeError, Value, Variadic,
+    closure::{display_chunk_name, UpValueState},
+    lua::GcControl,
+    meta_ops::{self, MetaResult, PairsResult},
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, FromValue, Fuel,
+    Function, IntoValue, Sequence, SequencePoll, Stack, String, Table, TypeError, Value,
 };
 
+// The first byte of a precompiled PUC-Rio Lua chunk.
+const BINARY_CHUNK_SIGNATURE: u8 = 0x1b;
+
 pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "tonumber",
@@ -72,20 +78,7 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
             if stack.is_empty() {
                 Err("Bad argument to tostring".into_value(ctx).into())
             } else {
-                match meta_ops::tostring(ctx, stack.get(0))? {
-                    MetaResult::Value(v) => {
-                        stack[0] = v;
-                        stack.drain(1..);
-                        Ok(CallbackReturn::Return)
-                    }
-                    MetaResult::Call(call) => {
-                        stack.replace(ctx, Variadic(call.args));
-                        Ok(CallbackReturn::Call {
-                            function: call.function,
-                            then: None,
-                        })
-                    }
-                }
+                Ok(meta_ops::tostring(ctx, stack.get(0))?.into_callback_return(ctx, &mut stack))
             }
         }),
     )
@@ -93,14 +86,59 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
 
     ctx.set_global(
         "error",
-        Callback::from_fn(&ctx, |_, _, stack| Err(stack.get(0).into())),
+        Callback::from_fn(&ctx, |ctx, exec, stack| {
+            let error = stack.get(0);
+            let level: Option<i64> = FromValue::from_value(ctx, stack.get(1))?;
+            // String messages are prefixed with the position of the function at the given level,
+            // which by default is the function that called `error`. Level 0 adds no position.
+            if let Value::String(message) = error {
+                let frame = usize::try_from(level.unwrap_or(1))
+                    .ok()
+                    .and_then(|level| exec.lua_frame(level));
+                if let Some(frame) = frame {
+                    let mut prefixed = format!(
+                        "{}:{}: ",
+                        display_chunk_name(&frame.source_file.to_str_lossy()),
+                        frame.source_line
+                    )
+                    .into_bytes();
+                    prefixed.extend(message.as_bytes());
+                    return Err(String::from_slice(&ctx, prefixed).into_value(ctx).into());
+                }
+            }
+            Err(error.into())
+        }),
+    )
+    .unwrap();
+
+    ctx.set_global(
+        "warn",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            if stack.is_empty() {
+                return Err("Missing argument to warn".into_value(ctx).into());
+            }
+
+            let mut message = Vec::new();
+            for value in stack.drain(..) {
+                let Some(s) = value.into_string(ctx) else {
+                    return Err("Bad argument to warn".into_value(ctx).into());
+                };
+                message.extend(s.as_bytes());
+            }
+            ctx.warn(&StdString::from_utf8_lossy(&message));
+            Ok(CallbackReturn::Return)
+        }),
     )
     .unwrap();
 
     ctx.set_global(
         "assert",
         Callback::from_fn(&ctx, |ctx, _, stack| {
-            if stack.get(0).to_bool() {
+            if stack.is_empty() {
+                Err("bad argument #1 to 'assert' (value expected)"
+                    .into_value(ctx)
+                    .into())
+            } else if stack.get(0).to_bool() {
                 Ok(CallbackReturn::Return)
             } else if stack.get(1).is_nil() {
                 Err("assertion failed!".into_value(ctx).into())
@@ -153,47 +191,167 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     .unwrap();
 
     ctx.set_global(
-        "type",
+        "load",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            if stack.is_empty() {
-                Err("Missing argument to type".into_value(ctx).into())
-            } else {
-                stack.replace(ctx, stack.get(0).type_name());
-                Ok(CallbackReturn::Return)
+            #[derive(Collect)]
+            #[collect(no_drop)]
+            struct LoadReader<'gc> {
+                reader: Function<'gc>,
+                options: LoadOptions<'gc>,
+                #[collect(require_static)]
+                chunk: Vec<u8>,
+                started: bool,
             }
-        }),
-    )
-    .unwrap();
 
-    ctx.set_global(
-        "select",
-        Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let ind = stack.get(0);
-            if let Some(n) = ind.to_integer() {
-                if n >= 1 {
-                    let last = (n as usize).min(stack.len());
-                    stack.drain(0..last);
-                    return Ok(CallbackReturn::Return);
-                } else if n < 0 {
-                    let inverse_index = n.unsigned_abs() as usize;
-                    let len = stack.len();
-                    if inverse_index < len {
-                        stack.drain(0..len - inverse_index);
-                        return Ok(CallbackReturn::Return);
+            impl<'gc> Sequence<'gc> for LoadReader<'gc> {
+                fn poll(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    if self.started {
+                        // The chunk ends when the reader returns `nil` or an empty string.
+                        let piece = match stack.get(0) {
+                            Value::Nil => None,
+                            piece => match piece.into_string(ctx) {
+                                Some(piece) => Some(piece).filter(|p| !p.as_bytes().is_empty()),
+                                None => {
+                                    stack.replace(
+                                        ctx,
+                                        (Value::Nil, "reader function must return a string"),
+                                    );
+                                    return Ok(SequencePoll::Return);
+                                }
+                            },
+                        };
+                        match piece {
+                            Some(piece) => self.chunk.extend(piece.as_bytes()),
+                            None => {
+                                self.options.load(ctx, &self.chunk, &mut stack);
+                                return Ok(SequencePoll::Return);
+                            }
+                        }
                     }
+
+                    self.started = true;
+                    stack.clear();
+                    Ok(SequencePoll::Call {
+                        bottom: 0,
+                        function: self.reader,
+                    })
                 }
-            }
 
-            if matches!(ind, Value::String(s) if s == b"#") {
-                stack.replace(ctx, stack.len() as i64 - 1);
-                return Ok(CallbackReturn::Return);
+                // Errors raised by the reader are returned as the error message, as with errors
+                // in the chunk itself.
+                fn error(
+                    &mut self,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    error: Error<'gc>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    stack.replace(ctx, (Value::Nil, error.to_value(ctx)));
+                    Ok(SequencePoll::Return)
+                }
             }
 
-            Err("Bad argument to 'select'".into_value(ctx).into())
+            let chunk = stack.get(0);
+            let options = LoadOptions {
+                chunk_name: FromValue::from_value(ctx, stack.get(1))?,
+                mode: FromValue::from_value(ctx, stack.get(2))?,
+                // An explicit `nil` environment is still used as the environment.
+                env: if stack.len() > 3 {
+                    Some(stack.get(3))
+                } else {
+                    None
+                },
+            };
+
+            match chunk {
+                Value::String(s) => {
+                    options.load(ctx, s.as_bytes(), &mut stack);
+                    Ok(CallbackReturn::Return)
+                }
+                Value::Function(reader) => Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    LoadReader {
+                        reader,
+                        options,
+                        chunk: Vec::new(),
+                        started: false,
+                    },
+                ))),
+                chunk => Err(format!(
+                    "bad argument #1 to 'load' (string expected, got {})",
+                    chunk.type_name()
+                )
+                .into_value(ctx)
+                .into()),
+            }
         }),
     )
     .unwrap();
 
+    // `type`, `select` and `rawequal` are among the most frequently called builtins, so they are
+    // intrinsics which run without pushing a callback frame.
+    fn type_<'gc>(
+        ctx: Context<'gc>,
+        _: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        if stack.is_empty() {
+            Err("Missing argument to type".into_value(ctx).into())
+        } else {
+            stack.replace(ctx, stack.get(0).type_name());
+            Ok(())
+        }
+    }
+
+    ctx.set_global("type", Callback::new_intrinsic(&ctx, type_))
+        .unwrap();
+
+    fn select<'gc>(
+        ctx: Context<'gc>,
+        _: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        let ind = stack.get(0);
+        if matches!(ind, Value::String(s) if s == b"#") {
+            stack.replace(ctx, stack.len() as i64 - 1);
+            return Ok(());
+        }
+
+        let Some(n) = ind.to_integer() else {
+            return Err(format!(
+                "bad argument #1 to 'select' (number expected, got {})",
+                ind.type_name()
+            )
+            .into_value(ctx)
+            .into());
+        };
+        if n >= 1 {
+            let last = usize::try_from(n).unwrap_or(usize::MAX).min(stack.len());
+            stack.drain(0..last);
+            return Ok(());
+        } else if n < 0 {
+            // Negative indices count back from the last argument.
+            let inverse_index = usize::try_from(n.unsigned_abs()).unwrap_or(usize::MAX);
+            let len = stack.len();
+            if inverse_index < len {
+                stack.drain(0..len - inverse_index);
+                return Ok(());
+            }
+        }
+
+        Err("bad argument #1 to 'select' (index out of range)"
+            .into_value(ctx)
+            .into())
+    }
+
+    ctx.set_global("select", Callback::new_intrinsic(&ctx, select))
+        .unwrap();
+
     ctx.set_global(
         "rawget",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
@@ -204,11 +362,38 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
+    fn rawequal<'gc>(
+        ctx: Context<'gc>,
+        _: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        let (a, b): (Value, Value) = stack.consume(ctx)?;
+        stack.replace(ctx, a.raw_equal(b));
+        Ok(())
+    }
+
+    ctx.set_global("rawequal", Callback::new_intrinsic(&ctx, rawequal))
+        .unwrap();
+
+    ctx.set_global(
+        "rawid",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let v: Value = stack.consume(ctx)?;
+            let id = ctx.object_id(v).map(|id| id.0 as i64);
+            stack.replace(ctx, id);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
     ctx.set_global(
         "rawlen",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let table: Table = stack.consume(ctx)?;
-            stack.replace(ctx, table.length());
+            let v: Value = stack.consume(ctx)?;
+            let len = v
+                .raw_len()
+                .ok_or_else(|| "table or string expected".into_value(ctx))?;
+            stack.replace(ctx, len);
             Ok(CallbackReturn::Return)
         }),
     )
@@ -228,14 +413,21 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "getmetatable",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            if let Value::Table(t) = stack.get(0) {
-                stack.replace(ctx, t.metatable());
-                Ok(CallbackReturn::Return)
-            } else {
-                Err("'getmetatable' can only be used on table types"
-                    .into_value(ctx)
-                    .into())
+            if stack.is_empty() {
+                return Err("Missing argument to getmetatable".into_value(ctx).into());
             }
+            let metatable = meta_ops::get_metatable(ctx, stack.get(0));
+
+            // A `__metatable` field hides the real metatable.
+            let result = match metatable {
+                Some(mt) => match mt.get(ctx, "__metatable") {
+                    Value::Nil => mt.into(),
+                    protected => protected,
+                },
+                None => Value::Nil,
+            };
+            stack.replace(ctx, result);
+            Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
@@ -244,55 +436,54 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         "setmetatable",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let (t, mt): (Table, Option<Table>) = stack.consume(ctx)?;
-            t.set_metatable(&ctx, mt);
+            if t.metatable()
+                .is_some_and(|old| !old.get(ctx, "__metatable").is_nil())
+            {
+                return Err("cannot change a protected metatable".into_value(ctx).into());
+            }
+            t.apply_metatable(ctx, mt);
             stack.replace(ctx, t);
             Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
 
-    fn next<'gc>(
-        ctx: Context<'gc>,
-        table: Table<'gc>,
-        index: Value<'gc>,
-    ) -> Result<(Value<'gc>, Value<'gc>), Value<'gc>> {
-        match table.next(index) {
-            NextValue::Found { key, value } => Ok((key, value)),
-            NextValue::Last => Ok((Value::Nil, Value::Nil)),
-            NextValue::NotFound => Err("invalid table key".into_value(ctx)),
-        }
-    }
-
-    let next = Callback::from_fn(&ctx, |ctx, _, mut stack| {
-        let (table, index): (Table, Value) = stack.consume(ctx)?;
-        stack.replace(ctx, next(ctx, table, index)?);
-        Ok(CallbackReturn::Return)
-    });
-
+    let next = meta_ops::next_callback(ctx);
     ctx.set_global("next", next).unwrap();
 
     ctx.set_global(
         "pairs",
-        Callback::from_fn_with(&ctx, next, move |next, ctx, _, mut stack| {
-            let table = stack.get(0);
-            if let Some(mt) = match table {
-                Value::Table(t) => t.metatable(),
-                Value::UserData(u) => u.metatable(),
-                _ => None,
-            } {
-                let pairs = mt.get(ctx, MetaMethod::Pairs);
-                if !pairs.is_nil() {
-                    let function = meta_ops::call(ctx, pairs)?;
-                    stack.replace(ctx, (table, Value::Nil));
-                    return Ok(CallbackReturn::Call {
-                        function,
-                        then: None,
-                    });
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            // Only the first three results of a `__pairs` metamethod are returned.
+            #[derive(Collect)]
+            #[collect(require_static)]
+            struct PairsResults;
+
+            impl<'gc> Sequence<'gc> for PairsResults {
+                fn poll(
+                    &mut self,
+                    _ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    stack.resize(3);
+                    Ok(SequencePoll::Return)
                 }
             }
 
-            stack.replace(ctx, (*next, table));
-            Ok(CallbackReturn::Return)
+            if stack.is_empty() {
+                return Err("bad argument #1 to 'pairs' (table expected, got no value)"
+                    .into_value(ctx)
+                    .into());
+            }
+            match meta_ops::pairs(ctx, stack.get(0))? {
+                PairsResult::Iter([iter, state, control]) => {
+                    stack.replace(ctx, (iter, state, control));
+                    Ok(CallbackReturn::Return)
+                }
+                PairsResult::Call(call) => Ok(call
+                    .into_callback_return(&mut stack, Some(BoxSequence::new(&ctx, PairsResults)))),
+            }
         }),
     )
     .unwrap();
@@ -326,11 +517,10 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
                     }
                 }
 
-                stack.extend(call.args);
-                CallbackReturn::Call {
-                    function: call.function,
-                    then: Some(BoxSequence::new(&ctx, INext(next_index))),
-                }
+                call.into_callback_return(
+                    &mut stack,
+                    Some(BoxSequence::new(&ctx, INext(next_index))),
+                )
             }
         })
     });
@@ -338,7 +528,14 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "ipairs",
         Callback::from_fn_with(&ctx, inext, move |inext, ctx, _, mut stack| {
-            stack.into_front(ctx, *inext);
+            // Any value may be iterated, since indexing it may call an `__index` metamethod.
+            if stack.is_empty() {
+                return Err("bad argument #1 to 'ipairs' (table expected, got no value)"
+                    .into_value(ctx)
+                    .into());
+            }
+            let value = stack.get(0);
+            stack.replace(ctx, (*inext, value, 0));
             Ok(CallbackReturn::Return)
         }),
     )
@@ -346,15 +543,67 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
 
     ctx.set_global(
         "collectgarbage",
-        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
-            match stack.consume::<Option<String>>(ctx)? {
-                Some(arg) if arg == "count" => {
-                    stack.into_back(ctx, ctx.metrics().total_allocation() as f64 / 1024.0);
+        Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let (option, pause, _stepmul, stepsize): (
+                Option<String>,
+                Option<i64>,
+                Option<i64>,
+                Option<i64>,
+            ) = stack.consume(ctx)?;
+            let control = ctx.singleton::<Rootable![GcControl]>();
+            match option
+                .as_ref()
+                .map(|o| o.as_bytes())
+                .unwrap_or(&b"collect"[..])
+            {
+                b"collect" => {
+                    // Collection cannot happen inside the arena, so stop running Lua until the
+                    // arena is exited and the collection has been done.
+                    control.collect_requested.set(true);
+                    exec.fuel().interrupt();
+                    stack.replace(ctx, 0);
+                }
+                b"step" => {
+                    // Every step finishes a whole collection cycle.
+                    control.collect_requested.set(true);
+                    exec.fuel().interrupt();
+                    stack.replace(ctx, true);
+                }
+                b"count" => {
+                    stack.replace(ctx, ctx.metrics().total_allocation() as f64 / 1024.0);
                 }
-                Some(_) => {
-                    return Err("bad argument to 'collectgarbage'".into_value(ctx).into());
+                b"stop" => {
+                    control.stopped.set(true);
+                    stack.replace(ctx, 0);
+                }
+                b"restart" => {
+                    control.stopped.set(false);
+                    stack.replace(ctx, 0);
+                }
+                b"isrunning" => {
+                    stack.replace(ctx, !control.stopped.get());
+                }
+                b"incremental" => {
+                    if let Some(pause) = pause.filter(|&p| p > 0) {
+                        let mut pacing = Pacing::default();
+                        pacing.sleep_factor = pause as f64 / 100.0;
+                        ctx.metrics().set_pacing(pacing);
+                    }
+                    if let Some(stepsize) = stepsize.filter(|&s| s > 0) {
+                        control
+                            .granularity
+                            .set(2.0f64.powi(stepsize.min(62) as i32));
+                    }
+                    stack.replace(ctx, "incremental");
+                }
+                option => {
+                    return Err(format!(
+                        "bad argument #1 to 'collectgarbage' (invalid option '{}')",
+                        StdString::from_utf8_lossy(option)
+                    )
+                    .into_value(ctx)
+                    .into());
                 }
-                None => {}
             }
             Ok(CallbackReturn::Return)
         }),
@@ -363,3 +612,59 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
 
     ctx.set_global("_VERSION", "piccolo").unwrap();
 }
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct LoadOptions<'gc> {
+    chunk_name: Option<String<'gc>>,
+    mode: Option<String<'gc>>,
+    env: Option<Value<'gc>>,
+}
+
+impl<'gc> LoadOptions<'gc> {
+    // Compiles the chunk and places the resulting function on the stack, or `nil` and an error
+    // message if it could not be loaded.
+    fn load(&self, ctx: Context<'gc>, chunk: &[u8], stack: &mut Stack<'gc, '_>) {
+        match self.compile(ctx, chunk) {
+            Ok(closure) => stack.replace(ctx, closure),
+            Err(message) => stack.replace(ctx, (Value::Nil, message)),
+        }
+    }
+
+    fn compile(&self, ctx: Context<'gc>, chunk: &[u8]) -> Result<Closure<'gc>, StdString> {
+        let chunk_name = self
+            .chunk_name
+            .map(|n| n.to_str_lossy().into_owned())
+            .unwrap_or_else(|| "=(load)".to_owned());
+        let mode = self.mode.map(|m| m.as_bytes()).unwrap_or(b"bt");
+
+        let closure = if chunk.first() == Some(&BINARY_CHUNK_SIGNATURE) {
+            if !ctx.binary_chunks() {
+                return Err(
+                    "attempt to load a binary chunk (binary chunks are disabled)".to_owned(),
+                );
+            }
+            if !mode.contains(&b'b') {
+                return Err(format!(
+                    "attempt to load a binary chunk (mode is '{}')",
+                    StdString::from_utf8_lossy(mode)
+                ));
+            }
+            Closure::load_binary(ctx, chunk, ctx.globals())
+        } else {
+            if !mode.contains(&b't') {
+                return Err(format!(
+                    "attempt to load a text chunk (mode is '{}')",
+                    StdString::from_utf8_lossy(mode)
+                ));
+            }
+            Closure::load_with_env(ctx, Some(&*chunk_name), chunk, ctx.globals())
+        }
+        .map_err(|err| format!("{}: {}", display_chunk_name(&chunk_name), err))?;
+        if let (Some(env), Some(upvalue)) = (self.env, closure.upvalues().first()) {
+            // `env` replaces the first upvalue, which is `_ENV` unless this is a dumped function.
+            upvalue.set(&ctx, UpValueState::Closed(env));
+        }
+        Ok(closure)
+    }
+}
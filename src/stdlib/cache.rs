@@ -0,0 +1,107 @@
+use std::{collections::VecDeque, rc::Rc};
+
+use gc_arena::{lock::RefLock, Collect, Collection, Gc, Mutation};
+
+use crate::String;
+
+/// A small cache of values parsed from Lua strings, keyed by string identity.
+///
+/// String library functions like `string.gsub` or `string.format` must parse their pattern or
+/// format arguments before they can do any work. Constant strings in Lua code are interned, so when
+/// the same constant string is passed to such a function repeatedly (such as inside a loop), the
+/// previously parsed value can be found here instead of parsing the string again.
+///
+/// The cache holds at most `capacity` entries and evicts the oldest entry once full.
+pub struct StringCache<'gc, T: 'static>(Gc<'gc, RefLock<StringCacheState<'gc, T>>>);
+
+struct StringCacheState<'gc, T> {
+    capacity: usize,
+    entries: VecDeque<(String<'gc>, Rc<T>)>,
+}
+
+// SAFETY: The only held `Gc` values are the key strings, which we trace.
+unsafe impl<'gc, T: 'static> Collect for StringCacheState<'gc, T> {
+    fn trace(&self, cc: &Collection) {
+        for (string, _) in &self.entries {
+            string.trace(cc);
+        }
+    }
+}
+
+// SAFETY: We just trace the inner `Gc` pointer.
+unsafe impl<'gc, T: 'static> Collect for StringCache<'gc, T> {
+    fn trace(&self, cc: &Collection) {
+        self.0.trace(cc);
+    }
+}
+
+impl<'gc, T: 'static> Copy for StringCache<'gc, T> {}
+
+impl<'gc, T: 'static> Clone for StringCache<'gc, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'gc, T: 'static> StringCache<'gc, T> {
+    pub fn new(mc: &Mutation<'gc>, capacity: usize) -> Self {
+        Self(Gc::new(
+            mc,
+            RefLock::new(StringCacheState {
+                capacity,
+                entries: VecDeque::with_capacity(capacity),
+            }),
+        ))
+    }
+
+    pub fn get(self, key: String<'gc>) -> Option<Rc<T>> {
+        self.0
+            .borrow()
+            .entries
+            .iter()
+            .find(|(s, _)| Gc::ptr_eq(s.into_inner(), key.into_inner()))
+            .map(|(_, v)| v.clone())
+    }
+
+    /// Insert a new parsed value for the given string, evicting the oldest entry if the cache is
+    /// full.
+    pub fn insert(self, mc: &Mutation<'gc>, key: String<'gc>, value: T) -> Rc<T> {
+        let value = Rc::new(value);
+        let mut state = self.0.borrow_mut(mc);
+        if state.capacity == 0 {
+            return value;
+        }
+        if state.entries.len() >= state.capacity {
+            state.entries.pop_front();
+        }
+        state.entries.push_back((key, value.clone()));
+        value
+    }
+
+    /// Return the cached value for the given string, or parse it with the given function and
+    /// insert the result.
+    pub fn get_or_try_insert_with<E>(
+        self,
+        mc: &Mutation<'gc>,
+        key: String<'gc>,
+        parse: impl FnOnce(&[u8]) -> Result<T, E>,
+    ) -> Result<Rc<T>, E> {
+        if let Some(value) = self.get(key) {
+            Ok(value)
+        } else {
+            Ok(self.insert(mc, key, parse(key.as_bytes())?))
+        }
+    }
+
+    pub fn len(self) -> usize {
+        self.0.borrow().entries.len()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0.borrow().entries.is_empty()
+    }
+
+    pub fn clear(self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).entries.clear();
+    }
+}
@@ -13,7 +13,7 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
             ctx,
             "create",
             Callback::from_fn(&ctx, |ctx, _, mut stack| {
-                let thread = Thread::new(ctx);
+                let thread = ctx.new_thread();
                 thread
                     .start_suspended(&ctx, meta_ops::call(ctx, stack.get(0))?)
                     .unwrap();
@@ -80,15 +80,20 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         .set(
             ctx,
             "status",
-            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
                 let thread: Thread = stack.consume(ctx)?;
                 stack.replace(
                     ctx,
-                    match thread.mode() {
-                        ThreadMode::Stopped => "dead",
-                        ThreadMode::Running | ThreadMode::Waiting => "running",
-                        ThreadMode::Normal => "normal",
-                        ThreadMode::Result | ThreadMode::Suspended => "suspended",
+                    if thread == exec.current_thread().thread {
+                        "running"
+                    } else {
+                        match thread.mode() {
+                            ThreadMode::Stopped => "dead",
+                            ThreadMode::Running => "running",
+                            // A thread which resumed another thread is waiting on it to finish.
+                            ThreadMode::Normal | ThreadMode::Waiting => "normal",
+                            ThreadMode::Result | ThreadMode::Suspended => "suspended",
+                        }
                     },
                 );
                 Ok(CallbackReturn::Return)
@@ -96,6 +101,41 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    coroutine
+        .set(
+            ctx,
+            "isyieldable",
+            Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+                let thread: Option<Thread> = stack.consume(ctx)?;
+                let thread = thread.unwrap_or(exec.current_thread().thread);
+                // Yielding from the main thread suspends the whole executor, which is not
+                // something Lua code can do on its own.
+                stack.replace(ctx, thread != exec.threads()[0]);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    coroutine
+        .set(
+            ctx,
+            "wrap",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let thread = ctx.new_thread();
+                thread
+                    .start_suspended(&ctx, meta_ops::call(ctx, stack.get(0))?)
+                    .unwrap();
+                stack.replace(
+                    ctx,
+                    Callback::from_fn_with(&ctx, thread, |&thread, _, _, _| {
+                        Ok(CallbackReturn::Resume { thread, then: None })
+                    }),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     coroutine
         .set(
             ctx,
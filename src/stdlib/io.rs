@@ -1,13 +1,127 @@
-use std::io::{self, Write};
+use std::{
+    cell::RefCell,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
 
-use gc_arena::Collect;
+use gc_arena::{Collect, Rootable, StaticCollect};
 
 use crate::{
+    constant::Constant,
     meta_ops::{self, MetaResult},
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Sequence, SequencePoll,
-    Stack,
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, MetaMethod,
+    Sequence, SequencePoll, Stack, String, Table, UserData, Value, Variadic,
 };
 
+// Reading and writing files consumes one fuel for every `IO_BYTES_PER_FUEL` bytes.
+const IO_BYTES_PER_FUEL: usize = 64;
+
+// The longest numeral read by the "n" format, as in PUC-Rio Lua.
+const MAX_NUMERAL_LEN: usize = 200;
+
+/// A file opened by a `FileSystem`.
+///
+/// This is implemented for every type which is `Read + Write + Seek`. Files which do not support
+/// an operation, such as writing to a read-only archive, should return an error from it.
+pub trait FileStream: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> FileStream for T {}
+
+/// How a file is to be opened, parsed from the mode argument of `io.open`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OpenMode {
+    pub read: bool,
+    pub write: bool,
+    /// Every write goes to the end of the file.
+    pub append: bool,
+    /// The file is created if it does not exist.
+    pub create: bool,
+    /// The file is emptied when it is opened.
+    pub truncate: bool,
+}
+
+impl OpenMode {
+    /// Parses a C `fopen` style mode, such as `"r"`, `"w+"` or `"ab"`.
+    pub fn parse(mode: &[u8]) -> Option<OpenMode> {
+        let (&kind, rest) = mode.split_first()?;
+        let (update, rest) = match rest.split_first() {
+            Some((b'+', rest)) => (true, rest),
+            _ => (false, rest),
+        };
+        if !rest.iter().all(|&b| b == b'b') {
+            return None;
+        }
+        Some(match kind {
+            b'r' => OpenMode {
+                read: true,
+                write: update,
+                append: false,
+                create: false,
+                truncate: false,
+            },
+            b'w' => OpenMode {
+                read: update,
+                write: true,
+                append: false,
+                create: true,
+                truncate: true,
+            },
+            b'a' => OpenMode {
+                read: update,
+                write: true,
+                append: true,
+                create: true,
+                truncate: false,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// The backend of the `io` library, set with `Context::set_file_system`.
+///
+/// Embedders can implement this to expose something other than the real disk to scripts, such as
+/// a packed asset archive or an in-memory file system. Until a file system is set, `io.open`
+/// fails for every path.
+pub trait FileSystem {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn FileStream>>;
+}
+
+/// A `FileSystem` which opens files on the real disk, relative to the current directory.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StdFileSystem;
+
+impl FileSystem for StdFileSystem {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn FileStream>> {
+        let file = OpenOptions::new()
+            .read(mode.read)
+            .write(mode.write && !mode.append)
+            .append(mode.append)
+            .create(mode.create)
+            .truncate(mode.truncate)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Singleton holding the `FileSystem` used by the `io` library.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct FileSystemSetting(pub(crate) RefCell<Option<Rc<dyn FileSystem>>>);
+
+// The userdata behind Lua file handles, which is `None` once the file is closed.
+struct FileHandle(RefCell<Option<BufReader<Box<dyn FileStream>>>>);
+
+#[derive(Debug, Copy, Clone)]
+enum ReadFormat {
+    Number,
+    Line,
+    LineWithNewline,
+    All,
+    Count(usize),
+}
+
 pub fn load_io<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "print",
@@ -38,12 +152,7 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
                                 v.write(&mut stdout)?
                             }
                             MetaResult::Call(call) => {
-                                let bottom = stack.len();
-                                stack.extend(call.args);
-                                return Ok(SequencePoll::Call {
-                                    function: call.function,
-                                    bottom,
-                                });
+                                return Ok(call.into_sequence_poll(&mut stack));
                             }
                         }
                     }
@@ -63,4 +172,481 @@ pub fn load_io<'gc>(ctx: Context<'gc>) {
         }),
     )
     .unwrap();
+
+    let methods = Table::new(&ctx);
+
+    methods
+        .set(
+            ctx,
+            "read",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let handle = file_handle(ctx, "read", stack.get(0))?;
+                let formats = parse_formats(ctx, "read", stack.drain(1..), 2)?;
+                let mut file = handle.0.borrow_mut();
+                let file = file.as_mut().ok_or_else(|| closed_file(ctx))?;
+                let (values, bytes) = read_formats(ctx, file, &formats);
+                exec.fuel().consume(bytes_fuel(bytes));
+                match values {
+                    Ok(values) => stack.replace(ctx, Variadic(values)),
+                    Err(err) => stack.replace(ctx, (Value::Nil, err.to_string())),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "write",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let file_value = stack.get(0);
+                let handle = file_handle(ctx, "write", file_value)?;
+                let mut file = handle.0.borrow_mut();
+                let file = file.as_mut().ok_or_else(|| closed_file(ctx))?;
+
+                let mut bytes = Vec::new();
+                for i in 1..stack.len() {
+                    let value = stack.get(i);
+                    if !matches!(
+                        value,
+                        Value::String(_) | Value::Integer(_) | Value::Number(_)
+                    ) {
+                        return Err(bad_argument(
+                            ctx,
+                            "write",
+                            i + 1,
+                            &format!("string expected, got {}", value.type_name()),
+                        ));
+                    }
+                    value.write(&mut bytes).unwrap();
+                }
+                exec.fuel().consume(bytes_fuel(bytes.len()));
+
+                match write_bytes(file, &bytes) {
+                    Ok(()) => stack.replace(ctx, file_value),
+                    Err(err) => stack.replace(ctx, (Value::Nil, err.to_string())),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "seek",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (file_value, whence, offset): (Value, Option<String>, Option<i64>) =
+                    stack.consume(ctx)?;
+                let handle = file_handle(ctx, "seek", file_value)?;
+                let mut file = handle.0.borrow_mut();
+                let file = file.as_mut().ok_or_else(|| closed_file(ctx))?;
+
+                let offset = offset.unwrap_or(0);
+                let pos = match whence.as_ref().map(|w| w.as_bytes()).unwrap_or(b"cur") {
+                    b"set" => match u64::try_from(offset) {
+                        Ok(offset) => SeekFrom::Start(offset),
+                        Err(_) => {
+                            stack.replace(ctx, (Value::Nil, "invalid argument"));
+                            return Ok(CallbackReturn::Return);
+                        }
+                    },
+                    b"cur" => SeekFrom::Current(offset),
+                    b"end" => SeekFrom::End(offset),
+                    _ => {
+                        return Err(bad_argument(ctx, "seek", 2, "invalid option"));
+                    }
+                };
+                match file.seek(pos) {
+                    Ok(pos) => stack.replace(ctx, pos as i64),
+                    Err(err) => stack.replace(ctx, (Value::Nil, err.to_string())),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "flush",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let file_value = stack.get(0);
+                let handle = file_handle(ctx, "flush", file_value)?;
+                let mut file = handle.0.borrow_mut();
+                let file = file.as_mut().ok_or_else(|| closed_file(ctx))?;
+                match file.get_mut().flush() {
+                    Ok(()) => stack.replace(ctx, file_value),
+                    Err(err) => stack.replace(ctx, (Value::Nil, err.to_string())),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let close = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        let handle = file_handle(ctx, "close", stack.get(0))?;
+        let file = handle.0.borrow_mut().take();
+        let mut file = file.ok_or_else(|| closed_file(ctx))?;
+        match file.get_mut().flush() {
+            Ok(()) => stack.replace(ctx, true),
+            Err(err) => stack.replace(ctx, (Value::Nil, err.to_string())),
+        }
+        Ok(CallbackReturn::Return)
+    });
+    methods.set(ctx, "close", close).unwrap();
+
+    methods
+        .set(
+            ctx,
+            "lines",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let file_value = stack.get(0);
+                file_handle(ctx, "lines", file_value)?;
+                let formats = parse_formats(ctx, "lines", stack.drain(1..), 2)?;
+                stack.replace(ctx, lines_iterator(ctx, file_value, formats, false));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let metatable = Table::new(&ctx);
+    metatable.set(ctx, MetaMethod::Index, methods).unwrap();
+    metatable.set(ctx, "__name", "FILE*").unwrap();
+    metatable.set(ctx, MetaMethod::Close, close).unwrap();
+    metatable
+        .set(
+            ctx,
+            MetaMethod::ToString,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let file_value = stack.get(0);
+                let handle = file_handle(ctx, "tostring", file_value)?;
+                let name = if handle.0.borrow().is_none() {
+                    "file (closed)".to_owned()
+                } else if let Some(id) = ctx.object_id(file_value) {
+                    format!("file ({})", id)
+                } else {
+                    "file".to_owned()
+                };
+                stack.replace(ctx, name);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let io = Table::new(&ctx);
+
+    io.set(
+        ctx,
+        "open",
+        Callback::from_fn_with(&ctx, metatable, |&metatable, ctx, _, mut stack| {
+            let (path, mode): (String, Option<String>) = stack.consume(ctx)?;
+            let mode = mode.as_ref().map(|m| m.as_bytes()).unwrap_or(b"r");
+            let mode = OpenMode::parse(mode)
+                .ok_or_else(|| bad_argument(ctx, "open", 2, "invalid mode"))?;
+            match open_file(ctx, metatable, path, mode) {
+                Ok(file) => stack.replace(ctx, file),
+                Err(err) => stack.replace(ctx, (Value::Nil, err)),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    io.set(
+        ctx,
+        "lines",
+        Callback::from_fn_with(&ctx, metatable, |&metatable, ctx, _, mut stack| {
+            let path: String = stack.from_front(ctx)?;
+            let formats = parse_formats(ctx, "lines", stack.drain(..), 2)?;
+            let mode = OpenMode::parse(b"r").unwrap();
+            let file = open_file(ctx, metatable, path, mode).map_err(|e| e.into_value(ctx))?;
+            stack.replace(ctx, lines_iterator(ctx, file.into(), formats, true));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    io.set(
+        ctx,
+        "type",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let handle = match stack.get(0) {
+                Value::UserData(ud) => ud.downcast_static::<FileHandle>().ok(),
+                _ => None,
+            };
+            match handle {
+                Some(handle) if handle.0.borrow().is_none() => stack.replace(ctx, "closed file"),
+                Some(_) => stack.replace(ctx, "file"),
+                None => stack.replace(ctx, Value::Nil),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    io.set(ctx, "close", close).unwrap();
+
+    ctx.set_global("io", io).unwrap();
+}
+
+// Opens a file through the current `FileSystem`, returning the error message on failure.
+fn open_file<'gc>(
+    ctx: Context<'gc>,
+    metatable: Table<'gc>,
+    path: String<'gc>,
+    mode: OpenMode,
+) -> Result<UserData<'gc>, std::string::String> {
+    let fail = |err: &dyn std::fmt::Display| format!("{}: {}", path.to_str_lossy(), err);
+    let file_system = ctx
+        .singleton::<Rootable![FileSystemSetting]>()
+        .0
+        .borrow()
+        .clone()
+        .ok_or_else(|| fail(&"no file system available"))?;
+    let path_str = path.as_str().map_err(|_| fail(&"invalid path"))?;
+    let stream = file_system.open(path_str, mode).map_err(|err| fail(&err))?;
+
+    let file = UserData::new_static(&ctx, FileHandle(RefCell::new(Some(BufReader::new(stream)))));
+    file.set_metatable(&ctx, Some(metatable));
+    Ok(file)
+}
+
+// Returns an iterator function which reads the given formats from a file each time it is called.
+// If `close` is set, the file is closed once the iterator reaches the end of the file.
+fn lines_iterator<'gc>(
+    ctx: Context<'gc>,
+    file: Value<'gc>,
+    formats: Vec<ReadFormat>,
+    close: bool,
+) -> Callback<'gc> {
+    Callback::from_fn_with(
+        &ctx,
+        (file, StaticCollect(formats)),
+        move |(file, formats), ctx, mut exec, mut stack| {
+            let handle = file_handle(ctx, "lines", *file)?;
+            let mut guard = handle.0.borrow_mut();
+            let reader = guard
+                .as_mut()
+                .ok_or_else(|| "file is already closed".into_value(ctx))?;
+            let (values, bytes) = read_formats(ctx, reader, &formats.0);
+            exec.fuel().consume(bytes_fuel(bytes));
+            let values = values.map_err(|err| err.to_string().into_value(ctx))?;
+            if close && values.first().map_or(true, |v| v.is_nil()) {
+                *guard = None;
+            }
+            stack.replace(ctx, Variadic(values));
+            Ok(CallbackReturn::Return)
+        },
+    )
+}
+
+fn file_handle<'gc>(
+    ctx: Context<'gc>,
+    function: &str,
+    value: Value<'gc>,
+) -> Result<&'gc FileHandle, Error<'gc>> {
+    match value {
+        Value::UserData(ud) => ud.downcast_static::<FileHandle>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        bad_argument(
+            ctx,
+            function,
+            1,
+            &format!("FILE* expected, got {}", value.type_name()),
+        )
+    })
+}
+
+fn closed_file<'gc>(ctx: Context<'gc>) -> Value<'gc> {
+    "attempt to use a closed file".into_value(ctx)
+}
+
+fn bad_argument<'gc>(ctx: Context<'gc>, function: &str, arg: usize, message: &str) -> Error<'gc> {
+    format!("bad argument #{} to '{}' ({})", arg, function, message)
+        .into_value(ctx)
+        .into()
+}
+
+fn bytes_fuel(bytes: usize) -> i32 {
+    (bytes / IO_BYTES_PER_FUEL).try_into().unwrap_or(i32::MAX)
+}
+
+// Parses the formats given to `read` or `lines`, the first of which is argument number `first`.
+// With no formats, a single line is read.
+fn parse_formats<'gc>(
+    ctx: Context<'gc>,
+    function: &str,
+    values: impl Iterator<Item = Value<'gc>>,
+    first: usize,
+) -> Result<Vec<ReadFormat>, Error<'gc>> {
+    let mut formats = Vec::new();
+    for (i, value) in values.enumerate() {
+        let format = match value {
+            Value::String(s) => {
+                // Formats may start with a '*' for compatibility with Lua 5.2.
+                let s = s.as_bytes();
+                let s = s.strip_prefix(b"*").unwrap_or(s);
+                match s.first() {
+                    Some(b'n') => ReadFormat::Number,
+                    Some(b'l') => ReadFormat::Line,
+                    Some(b'L') => ReadFormat::LineWithNewline,
+                    Some(b'a') => ReadFormat::All,
+                    _ => return Err(bad_argument(ctx, function, first + i, "invalid format")),
+                }
+            }
+            value => match value.to_integer() {
+                Some(n) => ReadFormat::Count(n.max(0).try_into().unwrap_or(usize::MAX)),
+                None => return Err(bad_argument(ctx, function, first + i, "invalid format")),
+            },
+        };
+        formats.push(format);
+    }
+    if formats.is_empty() {
+        formats.push(ReadFormat::Line);
+    }
+    Ok(formats)
+}
+
+// Reads each format in turn, stopping after the first one which fails and returns `nil`. Also
+// returns the number of bytes read, even if there was an error.
+fn read_formats<'gc>(
+    ctx: Context<'gc>,
+    reader: &mut BufReader<Box<dyn FileStream>>,
+    formats: &[ReadFormat],
+) -> (io::Result<Vec<Value<'gc>>>, usize) {
+    let mut values = Vec::new();
+    let mut total = 0;
+    for &format in formats {
+        let mut buf = Vec::new();
+        let res = read_format(ctx, reader, format, &mut buf);
+        total += buf.len();
+        match res {
+            Ok(value) => {
+                values.push(value);
+                if value.is_nil() {
+                    break;
+                }
+            }
+            Err(err) => return (Err(err), total),
+        }
+    }
+    (Ok(values), total)
+}
+
+// Reads a single format, using `buf` to hold the bytes read. Returns `nil` if nothing could be
+// read.
+fn read_format<'gc>(
+    ctx: Context<'gc>,
+    reader: &mut BufReader<Box<dyn FileStream>>,
+    format: ReadFormat,
+    buf: &mut Vec<u8>,
+) -> io::Result<Value<'gc>> {
+    let found = match format {
+        ReadFormat::Number => {
+            read_numeral(reader, buf)?;
+            return Ok(match Constant::String(&buf[..]).to_numeric() {
+                Some(Constant::Integer(i)) => Value::Integer(i),
+                Some(Constant::Number(n)) => Value::Number(n),
+                _ => Value::Nil,
+            });
+        }
+        ReadFormat::Line | ReadFormat::LineWithNewline => {
+            let found = reader.read_until(b'\n', buf)? != 0;
+            if matches!(format, ReadFormat::Line) && buf.last() == Some(&b'\n') {
+                buf.pop();
+            }
+            found
+        }
+        ReadFormat::All => {
+            reader.read_to_end(buf)?;
+            true
+        }
+        ReadFormat::Count(0) => !reader.fill_buf()?.is_empty(),
+        ReadFormat::Count(n) => {
+            reader.take(n as u64).read_to_end(buf)?;
+            !buf.is_empty()
+        }
+    };
+    Ok(if found {
+        Value::String(ctx.intern(buf))
+    } else {
+        Value::Nil
+    })
+}
+
+// Reads the longest prefix of the input which looks like a numeral, following PUC-Rio Lua.
+fn read_numeral(reader: &mut BufReader<Box<dyn FileStream>>, buf: &mut Vec<u8>) -> io::Result<()> {
+    fn peek(reader: &mut BufReader<Box<dyn FileStream>>) -> io::Result<Option<u8>> {
+        Ok(reader.fill_buf()?.first().copied())
+    }
+
+    // Consumes the next byte if it is one of `set`.
+    let accept = |reader: &mut BufReader<Box<dyn FileStream>>,
+                  buf: &mut Vec<u8>,
+                  set: &[u8]|
+     -> io::Result<bool> {
+        match peek(reader)? {
+            Some(b) if buf.len() < MAX_NUMERAL_LEN && set.contains(&b) => {
+                buf.push(b);
+                reader.consume(1);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    };
+
+    let read_digits = |reader: &mut BufReader<Box<dyn FileStream>>,
+                       buf: &mut Vec<u8>,
+                       hex: bool|
+     -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(b) = peek(reader)? {
+            let is_digit = if hex {
+                b.is_ascii_hexdigit()
+            } else {
+                b.is_ascii_digit()
+            };
+            if !is_digit || buf.len() >= MAX_NUMERAL_LEN {
+                break;
+            }
+            buf.push(b);
+            reader.consume(1);
+            count += 1;
+        }
+        Ok(count)
+    };
+
+    while peek(reader)?.map_or(false, |b| b.is_ascii_whitespace()) {
+        reader.consume(1);
+    }
+    accept(reader, buf, b"+-")?;
+    let mut count = 0;
+    let hex = if accept(reader, buf, b"0")? {
+        count += 1;
+        accept(reader, buf, b"xX")?
+    } else {
+        false
+    };
+    count += read_digits(reader, buf, hex)?;
+    if accept(reader, buf, b".")? {
+        count += read_digits(reader, buf, hex)?;
+    }
+    if count > 0 && accept(reader, buf, if hex { b"pP" } else { b"eE" })? {
+        accept(reader, buf, b"+-")?;
+        read_digits(reader, buf, false)?;
+    }
+    Ok(())
+}
+
+// Writes to a file, first discarding anything buffered for reading so that the write happens at
+// the position seen by Lua.
+fn write_bytes(file: &mut BufReader<Box<dyn FileStream>>, bytes: &[u8]) -> io::Result<()> {
+    if !file.buffer().is_empty() {
+        file.seek(SeekFrom::Current(0))?;
+    }
+    file.get_mut().write_all(bytes)
 }
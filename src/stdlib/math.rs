@@ -1,11 +1,11 @@
-use std::{cell::RefCell, f64, rc::Rc};
+use std::{cell::RefCell, f64};
 
-use gc_arena::Mutation;
-use rand::{rngs::SmallRng, Rng, SeedableRng};
+use gc_arena::{Collect, Mutation, Rootable};
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
 
 use crate::{
-    raw_ops, Callback, CallbackReturn, Context, FromMultiValue, IntoMultiValue, IntoValue, Table,
-    Value, Variadic,
+    raw_ops, Callback, CallbackReturn, Context, Error, FromMultiValue, Fuel, IntoMultiValue,
+    IntoValue, Stack, Table, Value, Variadic,
 };
 
 pub fn load_math<'gc>(ctx: Context<'gc>) {
@@ -34,14 +34,13 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     }
 
     let math = Table::new(&ctx);
-    let seeded_rng: Rc<RefCell<SmallRng>> = Rc::new(RefCell::new(SmallRng::from_entropy()));
 
     math.set(
         ctx,
         "abs",
         callback("abs", &ctx, |_, v: Value| {
             Some(if let Value::Integer(i) = v {
-                Value::Integer(i.abs())
+                Value::Integer(i.wrapping_abs())
             } else {
                 v.to_number()?.abs().into()
             })
@@ -79,7 +78,12 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "ceil",
-        callback("ceil", &ctx, |_, v: f64| Some(to_int(v.ceil().into()))),
+        callback("ceil", &ctx, |_, v: Value| {
+            Some(match v {
+                Value::Integer(i) => Value::Integer(i),
+                v => to_int(v.to_number()?.ceil().into()),
+            })
+        }),
     )
     .unwrap();
 
@@ -100,12 +104,27 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
-    math.set(
-        ctx,
-        "floor",
-        callback("floor", &ctx, |_, v: f64| Some(to_int(v.floor().into()))),
-    )
-    .unwrap();
+    fn floor<'gc>(
+        ctx: Context<'gc>,
+        _: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        let v: Value = stack.consume(ctx)?;
+        let res = match v {
+            Value::Integer(i) => Value::Integer(i),
+            v => to_int(
+                v.to_number()
+                    .ok_or_else(|| "Bad argument to floor".into_value(ctx))?
+                    .floor()
+                    .into(),
+            ),
+        };
+        stack.replace(ctx, res);
+        Ok(())
+    }
+
+    math.set(ctx, "floor", Callback::new_intrinsic(&ctx, floor))
+        .unwrap();
 
     math.set(
         ctx,
@@ -178,7 +197,18 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     math.set(
         ctx,
         "modf",
-        callback("modf", &ctx, |_, f: f64| Some((f as i64, f % 1.0))),
+        callback("modf", &ctx, |_, v: Value| {
+            Some(match v {
+                Value::Integer(i) => (Value::Number(i as f64), 0.0),
+                v => {
+                    let f = v.to_number()?;
+                    let int = f.trunc();
+                    // Infinities have an integral part of themselves and no fractional part.
+                    let frac = if f.is_infinite() { 0.0 } else { f - int };
+                    (Value::Number(int), frac)
+                }
+            })
+        }),
     )
     .unwrap();
 
@@ -191,15 +221,14 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
-    let random_rng = seeded_rng.clone();
     math.set(
         ctx,
         "random",
         callback(
             "random",
             &ctx,
-            move |_, (a, b): (Option<i64>, Option<i64>)| -> Option<Value> {
-                let rng = &random_rng;
+            |ctx, (a, b): (Option<i64>, Option<i64>)| -> Option<Value> {
+                let rng = &ctx.singleton::<Rootable![MathRng]>().0;
                 match (a, b) {
                     (None, None) => Some(rng.borrow_mut().gen::<f64>().into()),
                     (Some(0), None) => Some(rng.borrow_mut().gen::<i64>().into()),
@@ -213,22 +242,24 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
-    let randomseed_rng = seeded_rng.clone();
     math.set(
         ctx,
         "randomseed",
         callback(
             "randomseed",
             &ctx,
-            move |_, (u, l): (Option<u64>, Option<u64>)| {
-                let rng = &randomseed_rng;
+            |ctx, (u, l): (Option<u64>, Option<u64>)| {
+                let rng = &ctx.singleton::<Rootable![MathRng]>().0;
                 match (u, l) {
                     (None, None) => {
-                        *rng.borrow_mut() = SmallRng::from_entropy();
+                        // Seed from the current generator rather than from entropy, so that
+                        // scripts stay deterministic once the embedder has seeded the generator.
+                        let new = SmallRng::from_rng(&mut *rng.borrow_mut()).ok()?;
+                        *rng.borrow_mut() = Box::new(new);
                         Some(())
                     }
                     (Some(seed), None) | (Some(seed), Some(0)) => {
-                        *rng.borrow_mut() = SmallRng::seed_from_u64(seed);
+                        *rng.borrow_mut() = Box::new(SmallRng::seed_from_u64(seed));
                         Some(())
                     }
                     (Some(high), Some(low)) => {
@@ -242,7 +273,7 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
                                 low_bytes[idx_mod_16]
                             }
                         });
-                        *rng.borrow_mut() = SmallRng::from_seed(seed);
+                        *rng.borrow_mut() = Box::new(SmallRng::from_seed(seed));
                         Some(())
                     }
                     _ => None,
@@ -300,5 +331,44 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     )
     .unwrap();
 
+    math.set(
+        ctx,
+        "wrapadd",
+        callback("wrapadd", &ctx, |_, (a, b): (i64, i64)| {
+            Some(a.wrapping_add(b))
+        }),
+    )
+    .unwrap();
+
+    math.set(
+        ctx,
+        "wrapmul",
+        callback("wrapmul", &ctx, |_, (a, b): (i64, i64)| {
+            Some(a.wrapping_mul(b))
+        }),
+    )
+    .unwrap();
+
+    math.set(
+        ctx,
+        "wrapsub",
+        callback("wrapsub", &ctx, |_, (a, b): (i64, i64)| {
+            Some(a.wrapping_sub(b))
+        }),
+    )
+    .unwrap();
+
     ctx.set_global("math", math).unwrap();
 }
+
+/// Singleton holding the random number generator used by `math.random`, shared by every thread in
+/// a `Lua` instance. It is seeded from entropy until seeded by a script or the embedder.
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct MathRng(pub(crate) RefCell<Box<dyn RngCore>>);
+
+impl Default for MathRng {
+    fn default() -> Self {
+        Self(RefCell::new(Box::new(SmallRng::from_entropy())))
+    }
+}
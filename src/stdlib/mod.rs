@@ -1,11 +1,29 @@
 mod base;
+mod cache;
 mod coroutine;
 mod io;
 mod math;
+mod os;
+mod package;
+mod profile;
 mod string;
 mod table;
+mod utf8;
 
 pub use self::{
-    base::load_base, coroutine::load_coroutine, io::load_io, math::load_math, string::load_string,
+    base::load_base,
+    cache::StringCache,
+    coroutine::load_coroutine,
+    io::{load_io, FileStream, FileSystem, OpenMode, StdFileSystem},
+    math::load_math,
+    os::{load_os, Clock, SystemClock},
+    package::{load_package, ModuleResolver, ModuleSource, StdModuleResolver},
+    profile::StdLib,
+    string::load_string,
     table::load_table,
+    utf8::load_utf8,
+};
+
+pub(crate) use self::{
+    io::FileSystemSetting, math::MathRng, os::OsClock, package::ModuleResolverSetting,
 };
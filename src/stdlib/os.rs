@@ -0,0 +1,410 @@
+use std::{
+    cell::RefCell,
+    io::Write,
+    string::String as StdString,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use gc_arena::{Collect, Rootable};
+
+use crate::{Callback, CallbackReturn, Context, Error, IntoValue, String, Table, Value};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// The source of time for the `os` library, set with `Context::set_clock`.
+///
+/// The default is `SystemClock`. Sandboxed or deterministic environments can supply a virtual
+/// clock instead, so that scripts never observe the real time.
+pub trait Clock {
+    /// The current time in whole seconds since the Unix epoch, returned by `os.time()`.
+    fn time(&self) -> i64;
+
+    /// The processor time used by the program in seconds, returned by `os.clock()`.
+    fn clock(&self) -> f64;
+
+    /// The offset of local time from UTC at the given time, in seconds east of UTC.
+    ///
+    /// This is used to convert between times and dates in local time, in `os.date` and
+    /// `os.time`. By default, local time is UTC.
+    fn utc_offset(&self, _time: i64) -> i64 {
+        0
+    }
+}
+
+/// A `Clock` which reads the system clock.
+///
+/// The Rust standard library has no portable way to read processor time, so `os.clock()` returns
+/// the wall time elapsed since the clock was created instead. Local time is UTC.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn time(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs().try_into().unwrap_or(i64::MAX),
+            Err(err) => -i64::try_from(err.duration().as_secs()).unwrap_or(i64::MAX),
+        }
+    }
+
+    fn clock(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+}
+
+/// Singleton holding the clock used by the `os` library.
+#[derive(Collect)]
+#[collect(require_static)]
+pub(crate) struct OsClock(pub(crate) RefCell<Box<dyn Clock>>);
+
+impl Default for OsClock {
+    fn default() -> Self {
+        Self(RefCell::new(Box::new(SystemClock::default())))
+    }
+}
+
+pub fn load_os<'gc>(ctx: Context<'gc>) {
+    let os = Table::new(&ctx);
+
+    os.set(
+        ctx,
+        "clock",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let clock = ctx.singleton::<Rootable![OsClock]>().0.borrow().clock();
+            stack.replace(ctx, clock);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "time",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let table: Option<Table> = stack.consume(ctx)?;
+            let clock = ctx.singleton::<Rootable![OsClock]>().0.borrow();
+            let time = match table {
+                None => clock.time(),
+                Some(table) => {
+                    let year = get_field(ctx, table, "year", None)?;
+                    let month = get_field(ctx, table, "month", None)?;
+                    let day = get_field(ctx, table, "day", None)?;
+                    let hour = get_field(ctx, table, "hour", Some(12))?;
+                    let min = get_field(ctx, table, "min", Some(0))?;
+                    let sec = get_field(ctx, table, "sec", Some(0))?;
+
+                    let local = local_seconds(year, month, day, hour, min, sec)
+                        .ok_or_else(|| "time result cannot be represented".into_value(ctx))?;
+                    let time = local.saturating_sub(clock.utc_offset(local));
+
+                    // Update the table with the normalized date, like `mktime` does.
+                    let date = Date::new(time, clock.utc_offset(time));
+                    set_fields(ctx, table, &date);
+                    time
+                }
+            };
+            stack.replace(ctx, time);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "difftime",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (t2, t1): (i64, i64) = stack.consume(ctx)?;
+            stack.replace(ctx, t2 as f64 - t1 as f64);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    os.set(
+        ctx,
+        "date",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (format, time): (Option<String>, Option<i64>) = stack.consume(ctx)?;
+            let mut format = format.map(|f| f.as_bytes()).unwrap_or(b"%c");
+            let clock = ctx.singleton::<Rootable![OsClock]>().0.borrow();
+            let time = time.unwrap_or_else(|| clock.time());
+
+            let utc = format.first() == Some(&b'!');
+            if utc {
+                format = &format[1..];
+            }
+            let date = Date::new(time, if utc { 0 } else { clock.utc_offset(time) });
+
+            if format.starts_with(b"*t") {
+                let table = Table::new(&ctx);
+                set_fields(ctx, table, &date);
+                stack.replace(ctx, table);
+            } else {
+                let mut out = Vec::new();
+                write_date(&mut out, format, &date, utc).map_err(|spec| {
+                    format!(
+                        "bad argument #1 to 'date' (invalid conversion specifier '%{}')",
+                        StdString::from_utf8_lossy(spec)
+                    )
+                    .into_value(ctx)
+                })?;
+                stack.replace(ctx, ctx.intern(&out));
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    ctx.set_global("os", os).unwrap();
+}
+
+// Reads an integer field of a date table, which must be present unless it has a default.
+fn get_field<'gc>(
+    ctx: Context<'gc>,
+    table: Table<'gc>,
+    key: &'static str,
+    default: Option<i64>,
+) -> Result<i64, Error<'gc>> {
+    let value = table.get(ctx, key);
+    let n = match (value, default) {
+        (Value::Nil, Some(default)) => return Ok(default),
+        (Value::Nil, None) => {
+            return Err(format!("field '{}' missing in date table", key)
+                .into_value(ctx)
+                .into());
+        }
+        (value, _) => value
+            .to_integer()
+            .ok_or_else(|| format!("field '{}' is not an integer", key).into_value(ctx))?,
+    };
+    if i32::try_from(n).is_err() {
+        return Err(format!("field '{}' is out-of-bound", key)
+            .into_value(ctx)
+            .into());
+    }
+    Ok(n)
+}
+
+fn set_fields<'gc>(ctx: Context<'gc>, table: Table<'gc>, date: &Date) {
+    table.set(ctx, "year", date.year).unwrap();
+    table.set(ctx, "month", date.month).unwrap();
+    table.set(ctx, "day", date.day).unwrap();
+    table.set(ctx, "hour", date.hour).unwrap();
+    table.set(ctx, "min", date.min).unwrap();
+    table.set(ctx, "sec", date.sec).unwrap();
+    table.set(ctx, "wday", date.wday + 1).unwrap();
+    table.set(ctx, "yday", date.yday + 1).unwrap();
+    table.set(ctx, "isdst", false).unwrap();
+}
+
+// Converts a date which may have out of range fields, like the 32nd of a month, into seconds since
+// the epoch, as if the date were in UTC.
+fn local_seconds(year: i64, month: i64, day: i64, hour: i64, min: i64, sec: i64) -> Option<i64> {
+    let month = month - 1;
+    let year = year + month.div_euclid(12);
+    let days = days_from_civil(year, month.rem_euclid(12) + 1, 1) + day - 1;
+    days.checked_mul(SECONDS_PER_DAY)?
+        .checked_add(hour * 3600 + min * 60 + sec)
+}
+
+// The number of days from 1970-01-01 to the given date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// The date of the given number of days since 1970-01-01, as year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// A broken down time, with the same meaning as the fields of the C `struct tm`, except that
+// `year` and `month` are not offset.
+struct Date {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    // Days since Sunday.
+    wday: i64,
+    // Days since the 1st of January.
+    yday: i64,
+    // Seconds east of UTC.
+    offset: i64,
+}
+
+impl Date {
+    fn new(time: i64, offset: i64) -> Self {
+        let local = time.saturating_add(offset);
+        let days = local.div_euclid(SECONDS_PER_DAY);
+        let secs = local.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            min: secs / 60 % 60,
+            sec: secs % 60,
+            // The epoch was a Thursday.
+            wday: (days + 4).rem_euclid(7),
+            yday: days - days_from_civil(year, 1, 1),
+            offset,
+        }
+    }
+
+    // The ISO 8601 week-based year and week number.
+    fn iso_week(&self) -> (i64, i64) {
+        fn weeks_in_year(year: i64) -> i64 {
+            let p = |y: i64| (y + y.div_euclid(4) - y.div_euclid(100) + y.div_euclid(400)) % 7;
+            if p(year) == 4 || p(year - 1) == 3 {
+                53
+            } else {
+                52
+            }
+        }
+
+        let monday_based = (self.wday + 6) % 7;
+        let week = (self.yday - monday_based + 10) / 7;
+        if week < 1 {
+            (self.year - 1, weeks_in_year(self.year - 1))
+        } else if week > weeks_in_year(self.year) {
+            (self.year + 1, 1)
+        } else {
+            (self.year, week)
+        }
+    }
+}
+
+// Formats a date like C `strftime` in the "C" locale, returning the invalid conversion specifier
+// on error.
+fn write_date<'a>(
+    out: &mut Vec<u8>,
+    format: &'a [u8],
+    date: &Date,
+    utc: bool,
+) -> Result<(), &'a [u8]> {
+    let mut i = 0;
+    while i < format.len() {
+        if format[i] != b'%' {
+            out.push(format[i]);
+            i += 1;
+            continue;
+        }
+
+        let start = i + 1;
+        let mut spec = *format.get(start).ok_or(&format[start..])?;
+        i = start + 1;
+        // The `E` and `O` modifiers select alternative representations, which are the same as the
+        // plain ones in the "C" locale.
+        let allowed: &[u8] = match spec {
+            b'E' => b"cCxXyY",
+            b'O' => b"deHImMSuUVwWy",
+            _ => b"",
+        };
+        if !allowed.is_empty() {
+            match format.get(i) {
+                Some(c) if allowed.contains(c) => spec = *c,
+                _ => return Err(&format[start..(i + 1).min(format.len())]),
+            }
+            i += 1;
+        }
+
+        match spec {
+            b'a' => out.extend_from_slice(&WEEKDAYS[date.wday as usize].as_bytes()[..3]),
+            b'A' => out.extend_from_slice(WEEKDAYS[date.wday as usize].as_bytes()),
+            b'b' | b'h' => out.extend_from_slice(&MONTHS[date.month as usize - 1].as_bytes()[..3]),
+            b'B' => out.extend_from_slice(MONTHS[date.month as usize - 1].as_bytes()),
+            b'c' => write_date(out, b"%a %b %e %H:%M:%S %Y", date, utc)?,
+            b'C' => write!(out, "{:02}", date.year.div_euclid(100)).unwrap(),
+            b'd' => write!(out, "{:02}", date.day).unwrap(),
+            b'D' | b'x' => write_date(out, b"%m/%d/%y", date, utc)?,
+            b'e' => write!(out, "{:2}", date.day).unwrap(),
+            b'F' => write_date(out, b"%Y-%m-%d", date, utc)?,
+            b'g' => write!(out, "{:02}", date.iso_week().0.rem_euclid(100)).unwrap(),
+            b'G' => write!(out, "{}", date.iso_week().0).unwrap(),
+            b'H' => write!(out, "{:02}", date.hour).unwrap(),
+            b'I' => write!(out, "{:02}", (date.hour + 11) % 12 + 1).unwrap(),
+            b'j' => write!(out, "{:03}", date.yday + 1).unwrap(),
+            b'm' => write!(out, "{:02}", date.month).unwrap(),
+            b'M' => write!(out, "{:02}", date.min).unwrap(),
+            b'n' => out.push(b'\n'),
+            b'p' => out.extend_from_slice(if date.hour < 12 { b"AM" } else { b"PM" }),
+            b'r' => write_date(out, b"%I:%M:%S %p", date, utc)?,
+            b'R' => write_date(out, b"%H:%M", date, utc)?,
+            b'S' => write!(out, "{:02}", date.sec).unwrap(),
+            b't' => out.push(b'\t'),
+            b'T' | b'X' => write_date(out, b"%H:%M:%S", date, utc)?,
+            b'u' => write!(out, "{}", (date.wday + 6) % 7 + 1).unwrap(),
+            b'U' => write!(out, "{:02}", (date.yday + 7 - date.wday) / 7).unwrap(),
+            b'V' => write!(out, "{:02}", date.iso_week().1).unwrap(),
+            b'w' => write!(out, "{}", date.wday).unwrap(),
+            b'W' => write!(out, "{:02}", (date.yday + 7 - (date.wday + 6) % 7) / 7).unwrap(),
+            b'y' => write!(out, "{:02}", date.year.rem_euclid(100)).unwrap(),
+            b'Y' => write!(out, "{}", date.year).unwrap(),
+            b'z' => {
+                let sign = if date.offset < 0 { '-' } else { '+' };
+                let minutes = date.offset.abs() / 60;
+                write!(out, "{}{:02}{:02}", sign, minutes / 60, minutes % 60).unwrap();
+            }
+            b'Z' => {
+                if utc {
+                    out.extend_from_slice(b"UTC");
+                } else {
+                    write_date(out, b"%z", date, utc)?;
+                }
+            }
+            b'%' => out.push(b'%'),
+            _ => return Err(&format[start..i]),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, ErrorKind},
+    rc::Rc,
+    string::String as StdString,
+};
+
+use gc_arena::{Collect, Rootable};
+
+use crate::{
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, IntoValue, Sequence,
+    SequencePoll, Stack, String, Table, Value,
+};
+
+// The standard libraries which are recorded in `package.loaded` if they are loaded before the
+// `package` library.
+const STANDARD_LIBRARIES: &[&str] = &["coroutine", "io", "math", "os", "string", "table", "utf8"];
+
+/// The source of a module found by a `ModuleResolver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleSource {
+    /// Where the module was found, which is used as the chunk name of the module and passed to it
+    /// as its second argument.
+    pub path: StdString,
+    pub source: Vec<u8>,
+}
+
+/// The backend of the default searcher of `require`, set with `Context::set_module_resolver`.
+///
+/// Embedders implement this to decide where Lua modules come from, such as a directory on disk,
+/// a packed asset archive or sources compiled into the host. Until a resolver is set, only
+/// modules in `package.preload` can be required.
+pub trait ModuleResolver {
+    /// Find the source of the module with the given name, as passed to `require`.
+    ///
+    /// Returns `None` if there is no such module, in which case `require` moves on to the next
+    /// searcher.
+    fn resolve(&self, name: &str) -> io::Result<Option<ModuleSource>>;
+}
+
+/// A `ModuleResolver` which reads modules from the real disk.
+///
+/// Like `package.path` in PUC-Rio Lua, `path` is a list of templates separated by `;`. Every `?`
+/// in a template is replaced by the module name with each `.` replaced by `/`, and the first file
+/// which exists is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StdModuleResolver {
+    pub path: StdString,
+}
+
+impl Default for StdModuleResolver {
+    fn default() -> Self {
+        Self {
+            path: "./?.lua;./?/init.lua".to_owned(),
+        }
+    }
+}
+
+impl ModuleResolver for StdModuleResolver {
+    fn resolve(&self, name: &str) -> io::Result<Option<ModuleSource>> {
+        let name = name.replace('.', "/");
+        for template in self.path.split(';').filter(|t| !t.is_empty()) {
+            let path = template.replace('?', &name);
+            match fs::read(&path) {
+                Ok(source) => return Ok(Some(ModuleSource { path, source })),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Singleton holding the `ModuleResolver` used by `require`.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct ModuleResolverSetting(pub(crate) RefCell<Option<Rc<dyn ModuleResolver>>>);
+
+pub fn load_package<'gc>(ctx: Context<'gc>) {
+    let package = Table::new(&ctx);
+
+    let loaded = Table::new(&ctx);
+    loaded.set(ctx, "_G", ctx.globals()).unwrap();
+    for &name in STANDARD_LIBRARIES {
+        if let Value::Table(lib) = ctx.globals().get(ctx, name) {
+            loaded.set(ctx, name, lib).unwrap();
+        }
+    }
+    loaded.set(ctx, "package", package).unwrap();
+    package.set(ctx, "loaded", loaded).unwrap();
+    package.set(ctx, "preload", Table::new(&ctx)).unwrap();
+
+    let searchers = Table::new(&ctx);
+    searchers
+        .set(
+            ctx,
+            1,
+            Callback::from_fn_with(&ctx, package, |&package, ctx, _, mut stack| {
+                let name: String = stack.consume(ctx)?;
+                let Value::Table(preload) = package.get(ctx, "preload") else {
+                    return Err("'package.preload' must be a table".into_value(ctx).into());
+                };
+                match preload.get(ctx, name) {
+                    Value::Nil => stack.replace(
+                        ctx,
+                        format!("no field package.preload['{}']", name.to_str_lossy()),
+                    ),
+                    loader => stack.replace(ctx, (loader, ":preload:")),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+    searchers
+        .set(
+            ctx,
+            2,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let name: String = stack.consume(ctx)?;
+                let name = name.to_str_lossy();
+                let resolver = ctx
+                    .singleton::<Rootable![ModuleResolverSetting]>()
+                    .0
+                    .borrow()
+                    .clone();
+                let Some(resolver) = resolver else {
+                    stack.replace(ctx, "no module resolver available");
+                    return Ok(CallbackReturn::Return);
+                };
+                let module = resolver.resolve(&name).map_err(|err| {
+                    format!("error loading module '{}': {}", name, err).into_value(ctx)
+                })?;
+                match module {
+                    Some(module) => {
+                        let closure = Closure::load(ctx, Some(&*module.path), &module.source[..])
+                            .map_err(|err| {
+                            format!(
+                                "error loading module '{}' from '{}':\n\t{}",
+                                name, module.path, err
+                            )
+                            .into_value(ctx)
+                        })?;
+                        stack.replace(ctx, (closure, module.path));
+                    }
+                    None => stack.replace(ctx, format!("no module '{}' in the resolver", name)),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+    package.set(ctx, "searchers", searchers).unwrap();
+
+    ctx.set_global("package", package);
+
+    ctx.set_global(
+        "require",
+        Callback::from_fn_with(&ctx, package, |&package, ctx, _, mut stack| {
+            let name: String = stack.consume(ctx)?;
+            let Value::Table(loaded) = package.get(ctx, "loaded") else {
+                return Err("'package.loaded' must be a table".into_value(ctx).into());
+            };
+            let module = loaded.get(ctx, name);
+            if module.to_bool() {
+                stack.replace(ctx, module);
+                return Ok(CallbackReturn::Return);
+            }
+
+            let Value::Table(searchers) = package.get(ctx, "searchers") else {
+                return Err("'package.searchers' must be a table".into_value(ctx).into());
+            };
+            Ok(CallbackReturn::Sequence(BoxSequence::new(
+                &ctx,
+                Require {
+                    loaded,
+                    searchers,
+                    name,
+                    next_searcher: 1,
+                    messages: Vec::new(),
+                    loading: None,
+                },
+            )))
+        }),
+    );
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Require<'gc> {
+    loaded: Table<'gc>,
+    searchers: Table<'gc>,
+    name: String<'gc>,
+    #[collect(require_static)]
+    next_searcher: i64,
+    // The messages returned by searchers which did not find the module.
+    #[collect(require_static)]
+    messages: Vec<u8>,
+    // The extra value returned by the searcher alongside the loader, once the loader is called.
+    loading: Option<Value<'gc>>,
+}
+
+impl<'gc> Sequence<'gc> for Require<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(extra) = self.loading {
+            let module = stack.get(0);
+            if !module.is_nil() {
+                self.loaded.set(ctx, self.name, module)?;
+            }
+            if self.loaded.get(ctx, self.name).is_nil() {
+                self.loaded.set(ctx, self.name, true)?;
+            }
+            stack.replace(ctx, (self.loaded.get(ctx, self.name), extra));
+            return Ok(SequencePoll::Return);
+        }
+
+        // Every poll after the first has the results of the last searcher.
+        if self.next_searcher > 1 {
+            match stack.get(0) {
+                Value::Function(loader) => {
+                    let extra = stack.get(1);
+                    self.loading = Some(extra);
+                    stack.replace(ctx, (self.name, extra));
+                    return Ok(SequencePoll::Call {
+                        bottom: 0,
+                        function: loader,
+                    });
+                }
+                Value::String(message) => {
+                    self.messages.extend(b"\n\t");
+                    self.messages.extend(message.as_bytes());
+                }
+                _ => {}
+            }
+        }
+
+        match self.searchers.get(ctx, self.next_searcher) {
+            Value::Nil => {
+                let mut message = b"module '".to_vec();
+                message.extend(self.name.as_bytes());
+                message.extend(b"' not found:");
+                message.extend(&self.messages);
+                Err(String::from_slice(&ctx, message).into_value(ctx).into())
+            }
+            Value::Function(searcher) => {
+                self.next_searcher += 1;
+                stack.replace(ctx, self.name);
+                Ok(SequencePoll::Call {
+                    bottom: 0,
+                    function: searcher,
+                })
+            }
+            _ => Err("'package.searchers' must contain functions"
+                .into_value(ctx)
+                .into()),
+        }
+    }
+}
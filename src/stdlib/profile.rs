@@ -0,0 +1,93 @@
+use std::ops;
+
+/// A set of stdlib libraries and capabilities, used to choose which parts of the stdlib are
+/// loaded by `Lua::with_stdlib`.
+///
+/// Flags are combined with `|`, so a sandbox which only allows pure computation might be created
+/// with `Lua::with_stdlib(StdLib::BASE | StdLib::STRING | StdLib::TABLE | StdLib::MATH)`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct StdLib(u32);
+
+impl StdLib {
+    /// The base library, see `load_base`.
+    pub const BASE: StdLib = StdLib(1 << 0);
+    /// The `coroutine` library, see `load_coroutine`.
+    pub const COROUTINE: StdLib = StdLib(1 << 1);
+    /// The `math` library, see `load_math`.
+    pub const MATH: StdLib = StdLib(1 << 2);
+    /// The `string` library, see `load_string`.
+    pub const STRING: StdLib = StdLib(1 << 3);
+    /// The `table` library, see `load_table`.
+    pub const TABLE: StdLib = StdLib(1 << 4);
+    /// The `utf8` library, see `load_utf8`.
+    pub const UTF8: StdLib = StdLib(1 << 5);
+    /// The `io` library, which can read and write files through the configured `FileSystem`.
+    pub const IO: StdLib = StdLib(1 << 6);
+    /// The `os` library, which can read the configured `Clock`.
+    pub const OS: StdLib = StdLib(1 << 7);
+    /// The `package` library and `require`, which can load modules through the configured
+    /// `ModuleResolver`.
+    pub const PACKAGE: StdLib = StdLib(1 << 8);
+    /// Allow `load` to accept binary chunks written by `string.dump`.
+    ///
+    /// Binary chunks are always verified before they are run, but they can still express
+    /// programs which the compiler would never produce, so sandboxes may wish to only accept
+    /// source text. This has no effect unless `BASE` is also set.
+    pub const BINARY_CHUNKS: StdLib = StdLib(1 << 9);
+
+    /// No libraries at all.
+    pub const NONE: StdLib = StdLib(0);
+    /// The libraries loaded by `Lua::core`, which do not allow performing any I/O.
+    pub const CORE: StdLib = StdLib(
+        Self::BASE.0
+            | Self::COROUTINE.0
+            | Self::MATH.0
+            | Self::STRING.0
+            | Self::TABLE.0
+            | Self::UTF8.0
+            | Self::BINARY_CHUNKS.0,
+    );
+    /// Every library, as loaded by `Lua::full`.
+    pub const ALL: StdLib = StdLib(Self::CORE.0 | Self::IO.0 | Self::OS.0 | Self::PACKAGE.0);
+
+    /// Returns true if every flag set in `other` is also set in `self`.
+    pub const fn contains(self, other: StdLib) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl ops::BitOr for StdLib {
+    type Output = StdLib;
+
+    fn bitor(self, rhs: StdLib) -> StdLib {
+        StdLib(self.0 | rhs.0)
+    }
+}
+
+impl ops::BitOrAssign for StdLib {
+    fn bitor_assign(&mut self, rhs: StdLib) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl ops::BitAnd for StdLib {
+    type Output = StdLib;
+
+    fn bitand(self, rhs: StdLib) -> StdLib {
+        StdLib(self.0 & rhs.0)
+    }
+}
+
+impl ops::Sub for StdLib {
+    type Output = StdLib;
+
+    /// Removes every flag set in `rhs`, so `StdLib::CORE - StdLib::BINARY_CHUNKS` is the core
+    /// stdlib without binary chunk loading.
+    fn sub(self, rhs: StdLib) -> StdLib {
+        StdLib(self.0 & !rhs.0)
+    }
+}
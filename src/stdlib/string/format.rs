@@ -0,0 +1,431 @@
+use std::{fmt::Write as _, io::Write as _, string::String as StdString};
+
+use thiserror::Error;
+
+use crate::value::format_float_g;
+
+// The longest width or precision allowed in a conversion specifier.
+const MAX_SPEC_DIGITS: usize = 2;
+
+#[derive(Debug, Clone, Error)]
+pub enum FormatError {
+    #[error("invalid conversion '{0}' to 'format'")]
+    InvalidConversion(StdString),
+    #[error("specifier '%q' cannot have modifiers")]
+    QuoteModifiers,
+    #[error("bad argument #{0} to 'format' (no value)")]
+    MissingArgument(usize),
+    #[error("bad argument #{arg} to 'format' (number expected, got {found})")]
+    ExpectedNumber { arg: usize, found: &'static str },
+    #[error("bad argument #{0} to 'format' (number has no integer representation)")]
+    NoIntegerRepresentation(usize),
+    #[error("bad argument #{0} to 'format' (value has no literal form)")]
+    NoLiteralForm(usize),
+    #[error("'__tostring' must return a string")]
+    ToStringNotString,
+}
+
+/// A parsed `string.format` format string.
+#[derive(Debug)]
+pub struct FormatString {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug)]
+pub enum Piece {
+    Literal(Vec<u8>),
+    Spec(Spec),
+}
+
+/// A single conversion specifier, such as `%-5d`.
+#[derive(Debug)]
+pub struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: u8,
+}
+
+impl FormatString {
+    pub fn parse(format: &[u8]) -> Result<FormatString, FormatError> {
+        let mut pieces = Vec::new();
+        let mut literal = Vec::new();
+        let mut i = 0;
+        while i < format.len() {
+            let c = format[i];
+            i += 1;
+            if c != b'%' {
+                literal.push(c);
+            } else if format.get(i) == Some(&b'%') {
+                literal.push(b'%');
+                i += 1;
+            } else {
+                let start = i - 1;
+                let (spec, end) = Spec::parse(format, i).map_err(|end| {
+                    FormatError::InvalidConversion(
+                        StdString::from_utf8_lossy(&format[start..end]).into_owned(),
+                    )
+                })?;
+                if spec.conversion == b'q' && end - start > 2 {
+                    return Err(FormatError::QuoteModifiers);
+                }
+                i = end;
+
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(Piece::Spec(spec));
+            }
+        }
+
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        Ok(FormatString { pieces })
+    }
+
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+}
+
+impl Spec {
+    // Parses the specifier following a `%` at `format[start..]`, returning it along with the index
+    // just past its conversion character. On failure, returns the end of the invalid specifier.
+    fn parse(format: &[u8], start: usize) -> Result<(Spec, usize), usize> {
+        let mut spec = Spec {
+            left: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            conversion: 0,
+        };
+
+        let mut i = start;
+        let mut flags = Vec::new();
+        while let Some(&c) = format.get(i) {
+            match c {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            flags.push(c);
+            i += 1;
+        }
+
+        let read_digits = |i: &mut usize| -> Result<usize, usize> {
+            let begin = *i;
+            let mut n = 0;
+            while let Some(d) = format.get(*i).filter(|c| c.is_ascii_digit()) {
+                n = n * 10 + (d - b'0') as usize;
+                *i += 1;
+            }
+            if *i - begin > MAX_SPEC_DIGITS {
+                Err(*i)
+            } else {
+                Ok(n)
+            }
+        };
+
+        spec.width = read_digits(&mut i)?;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(read_digits(&mut i)?);
+        }
+
+        let Some(&conversion) = format.get(i) else {
+            return Err(i);
+        };
+        i += 1;
+        spec.conversion = conversion;
+
+        let (allowed_flags, allows_precision) = match conversion {
+            b'c' => ("-", false),
+            b'd' | b'i' => ("-+ 0", true),
+            b'u' => ("-0", true),
+            b'o' | b'x' | b'X' => ("-#0", true),
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => ("-+ #0", true),
+            b's' => ("-", true),
+            b'q' => ("", false),
+            _ => return Err(i),
+        };
+
+        if !flags.iter().all(|f| allowed_flags.as_bytes().contains(f))
+            || (!allows_precision && spec.precision.is_some())
+        {
+            return Err(i);
+        }
+
+        Ok((spec, i))
+    }
+
+    pub fn conversion(&self) -> u8 {
+        self.conversion
+    }
+
+    /// Format an integer with one of the integer conversions `c`, `d`, `i`, `u`, `o`, `x`, or `X`.
+    pub fn format_integer(&self, out: &mut Vec<u8>, n: i64) {
+        let (sign, prefix, mut digits) = match self.conversion {
+            b'c' => {
+                self.pad(out, &[n as u8]);
+                return;
+            }
+            b'd' | b'i' => (self.sign(n < 0), "", n.unsigned_abs().to_string()),
+            b'u' => ("", "", (n as u64).to_string()),
+            b'o' => ("", "", format!("{:o}", n as u64)),
+            b'x' => (
+                "",
+                if self.alternate && n != 0 { "0x" } else { "" },
+                format!("{:x}", n as u64),
+            ),
+            b'X' => (
+                "",
+                if self.alternate && n != 0 { "0X" } else { "" },
+                format!("{:X}", n as u64),
+            ),
+            c => panic!("'{}' is not an integer conversion", c as char),
+        };
+
+        if let Some(precision) = self.precision {
+            if precision == 0 && n == 0 {
+                digits.clear();
+            }
+            if digits.len() < precision {
+                digits.insert_str(0, &"0".repeat(precision - digits.len()));
+            }
+        }
+        if self.conversion == b'o' && self.alternate && !digits.starts_with('0') {
+            digits.insert(0, '0');
+        }
+
+        // Like in C, the `0` flag is ignored when a precision is given.
+        let zero = self.precision.is_none();
+        self.pad_number(out, sign, prefix, &digits, zero);
+    }
+
+    /// Format a float with one of the float conversions `a`, `A`, `e`, `E`, `f`, `F`, `g`, or `G`.
+    pub fn format_float(&self, out: &mut Vec<u8>, n: f64) {
+        let upper = self.conversion.is_ascii_uppercase();
+        let sign = self.sign(n.is_sign_negative() && !n.is_nan());
+        let abs = n.abs();
+
+        let (prefix, body) = if n.is_nan() {
+            ("", "nan".to_owned())
+        } else if n.is_infinite() {
+            ("", "inf".to_owned())
+        } else {
+            match self.conversion.to_ascii_lowercase() {
+                b'a' => ("0x", format_hex_float(abs, self.precision, self.alternate)),
+                b'e' => (
+                    "",
+                    format_float_e(abs, self.precision.unwrap_or(6), self.alternate),
+                ),
+                b'f' => {
+                    let precision = self.precision.unwrap_or(6);
+                    let mut s = format!("{:.*}", precision, abs);
+                    if self.alternate && precision == 0 {
+                        s.push('.');
+                    }
+                    ("", s)
+                }
+                b'g' => (
+                    "",
+                    format_float_g(abs, self.precision.unwrap_or(6), self.alternate),
+                ),
+                c => panic!("'{}' is not a float conversion", c as char),
+            }
+        };
+
+        let (prefix, body) = if upper {
+            (prefix.to_ascii_uppercase(), body.to_ascii_uppercase())
+        } else {
+            (prefix.to_owned(), body)
+        };
+
+        // Infinities and NaN are never padded with zeros.
+        self.pad_number(out, sign, &prefix, &body, n.is_finite());
+    }
+
+    /// Format a string with the `s` conversion.
+    pub fn format_string(&self, out: &mut Vec<u8>, s: &[u8]) {
+        match self.precision {
+            Some(precision) if precision < s.len() => self.pad(out, &s[..precision]),
+            _ => self.pad(out, s),
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    fn pad(&self, out: &mut Vec<u8>, s: &[u8]) {
+        let fill = self.width.saturating_sub(s.len());
+        if !self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+        out.extend_from_slice(s);
+        if self.left {
+            out.resize(out.len() + fill, b' ');
+        }
+    }
+
+    // Pads a formatted number to the width of this spec. If zero padding is allowed and requested,
+    // the zeros are placed between the sign and prefix and the digits.
+    fn pad_number(&self, out: &mut Vec<u8>, sign: &str, prefix: &str, digits: &str, zero: bool) {
+        let len = sign.len() + prefix.len() + digits.len();
+        let fill = self.width.saturating_sub(len);
+        if self.zero && zero && !self.left {
+            out.extend_from_slice(sign.as_bytes());
+            out.extend_from_slice(prefix.as_bytes());
+            out.resize(out.len() + fill, b'0');
+            out.extend_from_slice(digits.as_bytes());
+        } else {
+            let mut s = Vec::with_capacity(len);
+            s.extend_from_slice(sign.as_bytes());
+            s.extend_from_slice(prefix.as_bytes());
+            s.extend_from_slice(digits.as_bytes());
+            self.pad(out, &s);
+        }
+    }
+}
+
+/// Write a string as a quoted Lua string literal which reads back as the same string, as the `%q`
+/// conversion does.
+pub fn quote_string(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' | b'\n' => {
+                out.push(b'\\');
+                out.push(c);
+            }
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\0' | 0x01..=0x1f | 0x7f => {
+                // A decimal escape must be padded to three digits if a digit follows it.
+                if s.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    write!(out, "\\{:03}", c).unwrap();
+                } else {
+                    write!(out, "\\{}", c).unwrap();
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Write an integer as a Lua literal which reads back as the same integer, as the `%q` conversion
+/// does.
+pub fn quote_integer(out: &mut Vec<u8>, n: i64) {
+    if n == i64::MIN {
+        // `-9223372036854775808` would be read as the negation of a float.
+        out.extend_from_slice(b"0x8000000000000000");
+    } else {
+        write!(out, "{}", n).unwrap();
+    }
+}
+
+/// Write a float as a Lua expression which evaluates to exactly the same float, as the `%q`
+/// conversion does.
+pub fn quote_float(out: &mut Vec<u8>, n: f64) {
+    if n.is_nan() {
+        out.extend_from_slice(b"(0/0)");
+    } else if n.is_infinite() {
+        out.extend_from_slice(if n > 0.0 {
+            &b"1e9999"[..]
+        } else {
+            &b"-1e9999"[..]
+        });
+    } else {
+        if n.is_sign_negative() {
+            out.push(b'-');
+        }
+        out.extend_from_slice(b"0x");
+        out.extend_from_slice(format_hex_float(n.abs(), None, false).as_bytes());
+    }
+}
+
+// Format a finite, non-negative float like the C format specifier `"%.{precision}e"`.
+fn format_float_e(n: f64, precision: usize, alternate: bool) -> StdString {
+    let s = format!("{:.*e}", precision, n);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    format!(
+        "{}{}e{}{:02}",
+        mantissa,
+        if alternate && precision == 0 { "." } else { "" },
+        if exp < 0 { '-' } else { '+' },
+        exp.unsigned_abs()
+    )
+}
+
+// Format a finite, non-negative float like the C format specifier `"%a"`, without the leading
+// `0x`. If a precision is given, the fraction is rounded to that many hex digits, otherwise it is
+// written exactly with no trailing zeros.
+fn format_hex_float(n: f64, precision: Option<usize>, alternate: bool) -> StdString {
+    const FRACTION_BITS: u32 = 52;
+    const FRACTION_DIGITS: usize = 13;
+
+    let bits = n.to_bits();
+    let biased_exp = ((bits >> FRACTION_BITS) & 0x7ff) as i32;
+    let fraction = bits & ((1 << FRACTION_BITS) - 1);
+    let (mut lead, exp) = match (biased_exp, fraction) {
+        (0, 0) => (0, 0),
+        (0, _) => (0, -1022),
+        (e, _) => (1, e - 1023),
+    };
+
+    let mut digits = format!("{:013x}", fraction);
+    match precision {
+        None => {
+            let len = digits.trim_end_matches('0').len();
+            digits.truncate(len);
+        }
+        Some(precision) if precision < FRACTION_DIGITS => {
+            // Round the fraction to the requested number of digits, with ties to even.
+            let shift = (FRACTION_DIGITS - precision) * 4;
+            let full = (lead << FRACTION_BITS) | fraction;
+            let half = 1 << (shift - 1);
+            let rem = full & ((1 << shift) - 1);
+            let mut rounded = full >> shift;
+            if rem > half || (rem == half && rounded & 1 == 1) {
+                rounded += 1;
+            }
+            lead = rounded >> (precision * 4);
+            digits.clear();
+            if precision > 0 {
+                let mask = (1 << (precision * 4)) - 1;
+                write!(digits, "{:0width$x}", rounded & mask, width = precision).unwrap();
+            }
+        }
+        Some(precision) => {
+            digits.push_str(&"0".repeat(precision - FRACTION_DIGITS));
+        }
+    }
+
+    let mut s = lead.to_string();
+    if !digits.is_empty() || alternate {
+        s.push('.');
+    }
+    s.push_str(&digits);
+    write!(s, "p{:+}", exp).unwrap();
+    s
+}
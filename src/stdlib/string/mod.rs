@@ -0,0 +1,1120 @@
+mod format;
+mod pack;
+mod pattern;
+
+use std::{cell::Cell, rc::Rc};
+
+use gc_arena::Collect;
+
+use crate::{
+    meta_ops::{self, MetaCall, MetaResult},
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Fuel, Function, IntoValue,
+    MetaMethod, Sequence, SequencePoll, Stack, String, Table, Value, Variadic,
+};
+
+use self::{
+    format::{quote_float, quote_integer, quote_string, FormatError, FormatString, Piece},
+    pack::{int_fits, len_fits, pack_int, unpack_int, Kind, PackError, MAX_SIZE},
+    pattern::{is_plain, Capture, Match, Pattern},
+};
+
+use super::StringCache;
+
+// The number of parsed patterns cached by each pattern matching function.
+const PATTERN_CACHE_SIZE: usize = 32;
+
+// The number of parsed format strings cached by `string.format`.
+const FORMAT_CACHE_SIZE: usize = 32;
+
+// The number of parsed format strings shared by `string.pack`, `string.packsize` and
+// `string.unpack`.
+const PACK_FORMAT_CACHE_SIZE: usize = 32;
+
+// Formatting consumes one fuel for every `FORMAT_BYTES_PER_FUEL` bytes of output.
+const FORMAT_BYTES_PER_FUEL: usize = 64;
+
+// Packing and unpacking consume one fuel for every `PACK_BYTES_PER_FUEL` bytes of packed data.
+const PACK_BYTES_PER_FUEL: usize = 64;
+
+// Repeating a string consumes one fuel for every `REP_BYTES_PER_FUEL` bytes of output.
+const REP_BYTES_PER_FUEL: usize = 64;
+
+// Pattern matching consumes one fuel for every `PATTERN_STEPS_PER_FUEL` steps of the matcher.
+const PATTERN_STEPS_PER_FUEL: usize = 64;
+
+pub fn load_string<'gc>(ctx: Context<'gc>) {
+    let string = Table::new(&ctx);
+
+    fn len<'gc>(
+        ctx: Context<'gc>,
+        _: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        let string = stack.consume::<String>(ctx)?;
+        let len = string.len();
+        stack.replace(ctx, len);
+        Ok(())
+    }
+
+    string
+        .set(ctx, "len", Callback::new_intrinsic(&ctx, len))
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "sub",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (string, i, j) = stack.consume::<(String, i64, Option<i64>)>(ctx)?;
+                let substr = ctx.intern(operate_sub(string.as_bytes(), i, j)?);
+                stack.replace(ctx, substr);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "byte",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (string, i, j) = stack.consume::<(String, Option<i64>, Option<i64>)>(ctx)?;
+                let i = i.unwrap_or(1);
+                let bytes = operate_sub(string.as_bytes(), i, Some(j.unwrap_or(i)))?;
+                stack.extend(bytes.iter().map(|&b| Value::Integer(b.into())));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "dump",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (function, strip) = stack.consume::<(Function, Option<bool>)>(ctx)?;
+                let Function::Closure(closure) = function else {
+                    return Err("unable to dump given function".into_value(ctx).into());
+                };
+                let chunk = closure.prototype().dump(strip.unwrap_or(false));
+                stack.replace(ctx, ctx.intern(&chunk));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "char",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let codes = stack.consume::<Variadic<Vec<i64>>>(ctx)?;
+                let mut bytes = Vec::with_capacity(codes.len());
+                for (i, &c) in codes.iter().enumerate() {
+                    let b = u8::try_from(c).map_err(|_| {
+                        format!("bad argument #{} to 'char' (value out of range)", i + 1)
+                            .into_value(ctx)
+                    })?;
+                    bytes.push(b);
+                }
+                stack.replace(ctx, ctx.intern(&bytes));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "rep",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let (string, n, sep) = stack.consume::<(String, i64, Option<String>)>(ctx)?;
+                let string = string.as_bytes();
+                let sep = sep.map(|s| s.as_bytes()).unwrap_or_default();
+                if n <= 0 {
+                    stack.replace(ctx, ctx.intern_static(b""));
+                    return Ok(CallbackReturn::Return);
+                }
+
+                let too_large = || "resulting string too large".into_value(ctx);
+                let n = usize::try_from(n).map_err(|_| too_large())?;
+                let len = (string.len() + sep.len())
+                    .checked_mul(n)
+                    .map(|len| len - sep.len())
+                    .filter(|&len| len <= isize::MAX as usize)
+                    .ok_or_else(too_large)?;
+
+                let mut result = Vec::new();
+                result.try_reserve_exact(len).map_err(|_| too_large())?;
+                exec.fuel()
+                    .consume((len / REP_BYTES_PER_FUEL).try_into().unwrap_or(i32::MAX));
+                for i in 0..n {
+                    if i != 0 {
+                        result.extend_from_slice(sep);
+                    }
+                    result.extend_from_slice(string);
+                }
+                stack.replace(ctx, ctx.intern(&result));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "lower",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let string = stack.consume::<String>(ctx)?;
+                let lowered = ctx.intern(
+                    &string
+                        .as_bytes()
+                        .iter()
+                        .map(u8::to_ascii_lowercase)
+                        .collect::<Vec<_>>(),
+                );
+                stack.replace(ctx, lowered);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "reverse",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let string = stack.consume::<String>(ctx)?;
+                let reversed =
+                    ctx.intern(&string.as_bytes().iter().copied().rev().collect::<Vec<_>>());
+                stack.replace(ctx, reversed);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "upper",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let string = stack.consume::<String>(ctx)?;
+                let uppered = ctx.intern(
+                    &string
+                        .as_bytes()
+                        .iter()
+                        .map(u8::to_ascii_uppercase)
+                        .collect::<Vec<_>>(),
+                );
+                stack.replace(ctx, uppered);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    let patterns = StringCache::<Pattern>::new(&ctx, PATTERN_CACHE_SIZE);
+
+    string
+        .set(
+            ctx,
+            "find",
+            Callback::from_fn_with(&ctx, patterns, |&patterns, ctx, mut exec, mut stack| {
+                let (string, pattern, init, plain): (String, String, Option<i64>, Option<Value>) =
+                    stack.consume(ctx)?;
+                let subject = string.as_bytes();
+                let Some(init) = start_index(init, subject.len()) else {
+                    stack.replace(ctx, Value::Nil);
+                    return Ok(CallbackReturn::Return);
+                };
+
+                if plain.is_some_and(|p| p.to_bool()) || is_plain(pattern.as_bytes()) {
+                    let needle = pattern.as_bytes();
+                    let found = if needle.is_empty() {
+                        Some(init)
+                    } else {
+                        subject[init..]
+                            .windows(needle.len())
+                            .position(|w| w == needle)
+                            .map(|i| init + i)
+                    };
+                    match found {
+                        Some(start) => {
+                            stack.replace(ctx, ((start + 1) as i64, (start + needle.len()) as i64))
+                        }
+                        None => stack.replace(ctx, Value::Nil),
+                    }
+                    return Ok(CallbackReturn::Return);
+                }
+
+                let pattern = patterns.get_or_try_insert_with(&ctx, pattern, Pattern::parse)?;
+                match find_match(&pattern, subject, init, exec.fuel())? {
+                    Some(m) => {
+                        stack.replace(ctx, ((m.start + 1) as i64, m.end as i64));
+                        push_captures(ctx, &mut stack, string, &m, false);
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "match",
+            Callback::from_fn_with(&ctx, patterns, |&patterns, ctx, mut exec, mut stack| {
+                let (string, pattern, init): (String, String, Option<i64>) = stack.consume(ctx)?;
+                let subject = string.as_bytes();
+                let Some(init) = start_index(init, subject.len()) else {
+                    stack.replace(ctx, Value::Nil);
+                    return Ok(CallbackReturn::Return);
+                };
+
+                let pattern = patterns.get_or_try_insert_with(&ctx, pattern, Pattern::parse)?;
+                match find_match(&pattern, subject, init, exec.fuel())? {
+                    Some(m) => push_captures(ctx, &mut stack, string, &m, true),
+                    None => stack.replace(ctx, Value::Nil),
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // `string.gmatch` treats a leading `^` literally, so it needs its own cache of patterns.
+    let unanchored_patterns = StringCache::<Pattern>::new(&ctx, PATTERN_CACHE_SIZE);
+
+    string
+        .set(
+            ctx,
+            "gmatch",
+            Callback::from_fn_with(&ctx, unanchored_patterns, |&patterns, ctx, _, mut stack| {
+                let (string, pattern, init): (String, String, Option<i64>) = stack.consume(ctx)?;
+                let pattern =
+                    patterns.get_or_try_insert_with(&ctx, pattern, Pattern::parse_unanchored)?;
+                let position = Cell::new(start_index(init, string.len() as usize));
+                let last_match = Cell::new(None);
+
+                let iter = Callback::from_fn_with(
+                    &ctx,
+                    string,
+                    move |&string, ctx, mut exec, mut stack| {
+                        stack.clear();
+                        let subject = string.as_bytes();
+                        let Some(mut pos) = position.get() else {
+                            return Ok(CallbackReturn::Return);
+                        };
+
+                        let mut steps = 0;
+                        let mut found = None;
+                        while pos <= subject.len() {
+                            if let Some(m) = pattern.match_at(subject, pos, &mut steps)? {
+                                if last_match.get() != Some(m.end) {
+                                    found = Some(m);
+                                    break;
+                                }
+                            }
+                            pos += 1;
+                        }
+                        exec.fuel().consume((steps / PATTERN_STEPS_PER_FUEL) as i32);
+
+                        match found {
+                            Some(m) => {
+                                position.set(Some(m.end));
+                                last_match.set(Some(m.end));
+                                push_captures(ctx, &mut stack, string, &m, true);
+                            }
+                            None => position.set(None),
+                        }
+                        Ok(CallbackReturn::Return)
+                    },
+                );
+
+                stack.replace(ctx, iter);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "gsub",
+            Callback::from_fn_with(&ctx, patterns, |&patterns, ctx, _, mut stack| {
+                let (string, pattern, repl, max): (String, String, Value, Option<i64>) =
+                    stack.consume(ctx)?;
+                match repl {
+                    Value::String(_)
+                    | Value::Integer(_)
+                    | Value::Number(_)
+                    | Value::Table(_)
+                    | Value::Function(_) => {}
+                    v => {
+                        return Err(format!(
+                            "bad argument #3 to 'gsub' (string/function/table expected, got {})",
+                            v.type_name()
+                        )
+                        .into_value(ctx)
+                        .into())
+                    }
+                }
+                let pattern = patterns.get_or_try_insert_with(&ctx, pattern, Pattern::parse)?;
+
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    GSub {
+                        string,
+                        pattern,
+                        repl,
+                        max: max.map(|n| n.max(0) as usize).unwrap_or(usize::MAX),
+                        pos: 0,
+                        last_match: None,
+                        count: 0,
+                        done: false,
+                        pending: None,
+                        result: Vec::new(),
+                    },
+                )))
+            }),
+        )
+        .unwrap();
+
+    let formats = StringCache::<FormatString>::new(&ctx, FORMAT_CACHE_SIZE);
+
+    string
+        .set(
+            ctx,
+            "format",
+            Callback::from_fn_with(&ctx, formats, |&formats, ctx, mut exec, mut stack| {
+                let format: String = stack.from_front(ctx)?;
+                let format = formats.get_or_try_insert_with(&ctx, format, FormatString::parse)?;
+                let mut seq = Format {
+                    format,
+                    args: stack.drain(..).collect(),
+                    piece: 0,
+                    arg: 0,
+                    result: Vec::new(),
+                };
+
+                match seq.run(ctx)? {
+                    Some(call) => Ok(
+                        call.into_callback_return(&mut stack, Some(BoxSequence::new(&ctx, seq)))
+                    ),
+                    None => {
+                        exec.fuel()
+                            .consume((seq.result.len() / FORMAT_BYTES_PER_FUEL) as i32);
+                        stack.replace(ctx, ctx.intern(&seq.result));
+                        Ok(CallbackReturn::Return)
+                    }
+                }
+            }),
+        )
+        .unwrap();
+
+    let pack_formats = StringCache::<pack::Format>::new(&ctx, PACK_FORMAT_CACHE_SIZE);
+
+    string
+        .set(
+            ctx,
+            "pack",
+            Callback::from_fn_with(&ctx, pack_formats, |&formats, ctx, mut exec, mut stack| {
+                let format = check_string(ctx, "pack", &stack, 1)?;
+                let format = formats
+                    .get_or_try_insert_with(&ctx, format, |f| pack::Format::parse("pack", f))?;
+                let mut items = format.items();
+                let mut out = Vec::new();
+                let mut arg = 1;
+                while let Some(item) = items.next_item(out.len()) {
+                    out.resize(out.len() + item.padding, 0);
+                    match item.kind {
+                        Kind::Padding => out.push(0),
+                        Kind::PadAlign | Kind::Nop => {}
+                        _ => {
+                            arg += 1;
+                            pack_item(ctx, &mut out, &stack, arg, item)?;
+                        }
+                    }
+                }
+
+                exec.fuel()
+                    .consume((out.len() / PACK_BYTES_PER_FUEL) as i32);
+                stack.replace(ctx, ctx.intern(&out));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "packsize",
+            Callback::from_fn_with(&ctx, pack_formats, |&formats, ctx, _, mut stack| {
+                let format = check_string(ctx, "packsize", &stack, 1)?;
+                let format = formats
+                    .get_or_try_insert_with(&ctx, format, |f| pack::Format::parse("packsize", f))?;
+                let mut items = format.items();
+                let mut total: usize = 0;
+                while let Some(item) = items.next_item(total) {
+                    if matches!(item.kind, Kind::String | Kind::ZString) {
+                        return Err(PackError::bad_argument(
+                            "packsize",
+                            1,
+                            "variable-length format",
+                        )
+                        .into());
+                    }
+                    total = item
+                        .size
+                        .checked_add(item.padding)
+                        .and_then(|size| total.checked_add(size))
+                        .filter(|&total| total <= MAX_SIZE)
+                        .ok_or_else(|| {
+                            PackError::bad_argument("packsize", 1, "format result too large")
+                        })?;
+                }
+
+                stack.replace(ctx, total as i64);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    string
+        .set(
+            ctx,
+            "unpack",
+            Callback::from_fn_with(&ctx, pack_formats, |&formats, ctx, mut exec, mut stack| {
+                let format = check_string(ctx, "unpack", &stack, 1)?;
+                let data = check_string(ctx, "unpack", &stack, 2)?;
+                let init = if stack.get(2).is_nil() {
+                    1
+                } else {
+                    check_integer("unpack", &stack, 3)?
+                };
+
+                let data = data.as_bytes();
+                let len = data.len();
+                let mut pos = match init {
+                    i if i > 0 => i as u64 as usize - 1,
+                    0 => 0,
+                    i if i.unsigned_abs() as usize > len => 0,
+                    i => len - i.unsigned_abs() as usize,
+                };
+                if pos > len {
+                    return Err(PackError::bad_argument(
+                        "unpack",
+                        3,
+                        "initial position out of string",
+                    )
+                    .into());
+                }
+
+                let too_short = || PackError::bad_argument("unpack", 2, "data string too short");
+                let mut results = Vec::new();
+                let format = formats
+                    .get_or_try_insert_with(&ctx, format, |f| pack::Format::parse("unpack", f))?;
+                let mut items = format.items();
+                while let Some(item) = items.next_item(pos) {
+                    if item
+                        .padding
+                        .checked_add(item.size)
+                        .map_or(true, |size| size > len - pos)
+                    {
+                        return Err(too_short().into());
+                    }
+                    pos += item.padding;
+
+                    let bytes = &data[pos..pos + item.size];
+                    match item.kind {
+                        Kind::Int | Kind::Uint => {
+                            let n = unpack_int(
+                                bytes,
+                                item.little_endian,
+                                item.size,
+                                item.kind == Kind::Int,
+                            )?;
+                            results.push(Value::Integer(n));
+                        }
+                        Kind::Float => {
+                            let bytes = bytes.try_into().unwrap();
+                            let n = if item.little_endian {
+                                f32::from_le_bytes(bytes)
+                            } else {
+                                f32::from_be_bytes(bytes)
+                            };
+                            results.push(Value::Number(n as f64));
+                        }
+                        Kind::Double => {
+                            let bytes = bytes.try_into().unwrap();
+                            let n = if item.little_endian {
+                                f64::from_le_bytes(bytes)
+                            } else {
+                                f64::from_be_bytes(bytes)
+                            };
+                            results.push(Value::Number(n));
+                        }
+                        Kind::Char => results.push(ctx.intern(bytes).into()),
+                        Kind::String => {
+                            let str_len =
+                                unpack_int(bytes, item.little_endian, item.size, false)? as u64;
+                            let start = pos + item.size;
+                            if str_len > (len - start) as u64 {
+                                return Err(too_short().into());
+                            }
+                            let str_len = str_len as usize;
+                            results.push(ctx.intern(&data[start..start + str_len]).into());
+                            pos += str_len;
+                        }
+                        Kind::ZString => {
+                            let str_len =
+                                data[pos..].iter().position(|&b| b == 0).ok_or_else(|| {
+                                    PackError::bad_argument(
+                                        "unpack",
+                                        2,
+                                        "unfinished string for format 'z'",
+                                    )
+                                })?;
+                            results.push(ctx.intern(&data[pos..pos + str_len]).into());
+                            pos += str_len + 1;
+                        }
+                        Kind::Padding | Kind::PadAlign | Kind::Nop => {}
+                    }
+                    pos += item.size;
+                }
+
+                exec.fuel().consume((pos / PACK_BYTES_PER_FUEL) as i32);
+                stack.clear();
+                stack.extend(results);
+                stack.push_back(Value::Integer(pos as i64 + 1));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // All strings share a metatable which allows calling string functions as methods, as in
+    // `("x"):upper()`.
+    let metatable = Table::new(&ctx);
+    metatable.set(ctx, MetaMethod::Index, string).unwrap();
+    meta_ops::set_metatable(ctx, ctx.intern(b"").into(), Some(metatable));
+
+    ctx.set_global("string", string).unwrap();
+}
+
+// Converts a 1-based, possibly negative Lua start index to a byte index into a string of length
+// `len`. Returns `None` if the index is past the end of the string, where nothing can match.
+fn start_index(init: Option<i64>, len: usize) -> Option<usize> {
+    let len = len as i64;
+    let init = match init.unwrap_or(1) {
+        i if i > 0 => i - 1,
+        0 => 0,
+        i if i < -len => 0,
+        i => len + i,
+    };
+    (init <= len).then_some(init as usize)
+}
+
+// Finds the first match of `pattern` at or after `init`, consuming fuel for the work done.
+fn find_match(
+    pattern: &Pattern,
+    subject: &[u8],
+    init: usize,
+    fuel: &mut Fuel,
+) -> Result<Option<Match>, pattern::PatternError> {
+    let mut steps = 0;
+    let mut found = None;
+    for start in init..=subject.len() {
+        found = pattern.match_at(subject, start, &mut steps)?;
+        if found.is_some() || pattern.is_anchored() {
+            break;
+        }
+    }
+    fuel.consume((steps / PATTERN_STEPS_PER_FUEL) as i32);
+    Ok(found)
+}
+
+fn capture_value<'gc>(ctx: Context<'gc>, string: String<'gc>, capture: Capture) -> Value<'gc> {
+    match capture {
+        Capture::Range(start, end) => ctx.intern(&string.as_bytes()[start..end]).into(),
+        Capture::Position(pos) => Value::Integer(pos as i64 + 1),
+    }
+}
+
+// Pushes the captures of a match onto the stack. If the pattern has no captures and `whole` is
+// true, the whole match is pushed instead.
+fn push_captures<'gc>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    string: String<'gc>,
+    m: &Match,
+    whole: bool,
+) {
+    if m.captures.is_empty() {
+        if whole {
+            stack.push_back(capture_value(ctx, string, Capture::Range(m.start, m.end)));
+        }
+    } else {
+        for &capture in &m.captures {
+            stack.push_back(capture_value(ctx, string, capture));
+        }
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct GSub<'gc> {
+    string: String<'gc>,
+    #[collect(require_static)]
+    pattern: Rc<Pattern>,
+    repl: Value<'gc>,
+    #[collect(require_static)]
+    max: usize,
+    #[collect(require_static)]
+    pos: usize,
+    #[collect(require_static)]
+    last_match: Option<usize>,
+    #[collect(require_static)]
+    count: usize,
+    #[collect(require_static)]
+    done: bool,
+    // The match whose replacement is being computed by a call to a function or `__index`
+    // metamethod.
+    #[collect(require_static)]
+    pending: Option<Match>,
+    #[collect(require_static)]
+    result: Vec<u8>,
+}
+
+impl<'gc> GSub<'gc> {
+    // Appends the replacement value for a match, keeping the original text if the value is false
+    // or nil.
+    fn add_value(
+        &mut self,
+        ctx: Context<'gc>,
+        m: &Match,
+        value: Value<'gc>,
+    ) -> Result<(), Error<'gc>> {
+        match value {
+            Value::Nil | Value::Boolean(false) => self
+                .result
+                .extend_from_slice(&self.string.as_bytes()[m.start..m.end]),
+            v => {
+                let s = v.into_string(ctx).ok_or_else(|| {
+                    format!("invalid replacement value (a {})", v.type_name()).into_value(ctx)
+                })?;
+                self.result.extend_from_slice(s.as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    // Appends the expansion of a replacement string, where `%0` to `%9` are replaced by captures.
+    fn add_string(&mut self, ctx: Context<'gc>, m: &Match, repl: &[u8]) -> Result<(), Error<'gc>> {
+        let subject = self.string.as_bytes();
+        let mut i = 0;
+        while i < repl.len() {
+            let c = repl[i];
+            i += 1;
+            if c != b'%' {
+                self.result.push(c);
+                continue;
+            }
+
+            let escaped = repl.get(i).copied();
+            i += 1;
+            match escaped {
+                Some(b'%') => self.result.push(b'%'),
+                Some(d @ b'0'..=b'9') => {
+                    let index = (d - b'0') as usize;
+                    let capture = if index == 0 || (index == 1 && m.captures.is_empty()) {
+                        Capture::Range(m.start, m.end)
+                    } else {
+                        *m.captures.get(index - 1).ok_or_else(|| {
+                            format!("invalid capture index %{index} in replacement string")
+                                .into_value(ctx)
+                        })?
+                    };
+                    match capture {
+                        Capture::Range(start, end) => {
+                            self.result.extend_from_slice(&subject[start..end])
+                        }
+                        Capture::Position(pos) => self
+                            .result
+                            .extend_from_slice((pos + 1).to_string().as_bytes()),
+                    }
+                }
+                _ => {
+                    return Err("invalid use of '%' in replacement string"
+                        .into_value(ctx)
+                        .into())
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'gc> Sequence<'gc> for GSub<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        if let Some(m) = self.pending.take() {
+            let value = stack.get(0);
+            stack.clear();
+            self.add_value(ctx, &m, value)?;
+        }
+
+        let pattern = self.pattern.clone();
+        let subject = self.string.as_bytes();
+        let fuel = exec.fuel();
+        while !self.done && self.count < self.max {
+            let mut steps = 0;
+            let found = pattern.match_at(subject, self.pos, &mut steps)?;
+            fuel.consume((steps / PATTERN_STEPS_PER_FUEL) as i32);
+            self.done = pattern.is_anchored();
+
+            match found {
+                Some(m) if self.last_match != Some(m.end) => {
+                    self.count += 1;
+                    self.pos = m.end;
+                    self.last_match = Some(m.end);
+
+                    match self.repl {
+                        Value::Function(function) => {
+                            push_captures(ctx, &mut stack, self.string, &m, true);
+                            self.pending = Some(m);
+                            return Ok(SequencePoll::Call {
+                                function,
+                                bottom: 0,
+                            });
+                        }
+                        Value::Table(_) => {
+                            let key = match m.captures.first() {
+                                Some(&capture) => capture_value(ctx, self.string, capture),
+                                None => {
+                                    capture_value(ctx, self.string, Capture::Range(m.start, m.end))
+                                }
+                            };
+                            match meta_ops::index(ctx, self.repl, key)? {
+                                MetaResult::Value(v) => self.add_value(ctx, &m, v)?,
+                                MetaResult::Call(call) => {
+                                    self.pending = Some(m);
+                                    return Ok(call.into_sequence_poll(&mut stack));
+                                }
+                            }
+                        }
+                        repl => {
+                            let repl = repl.into_string(ctx).expect("checked in gsub");
+                            self.add_string(ctx, &m, repl.as_bytes())?;
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(&c) = subject.get(self.pos) {
+                        self.result.push(c);
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+            }
+
+            if !fuel.should_continue() {
+                return Ok(SequencePoll::Pending);
+            }
+        }
+
+        self.result.extend_from_slice(&subject[self.pos..]);
+        let result = ctx.intern(&self.result);
+        stack.replace(ctx, (result, self.count as i64));
+        Ok(SequencePoll::Return)
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Format<'gc> {
+    #[collect(require_static)]
+    format: Rc<FormatString>,
+    args: Vec<Value<'gc>>,
+    // The index of the next piece of the format string to format.
+    #[collect(require_static)]
+    piece: usize,
+    // The index of the next argument to be consumed.
+    #[collect(require_static)]
+    arg: usize,
+    #[collect(require_static)]
+    result: Vec<u8>,
+}
+
+impl<'gc> Format<'gc> {
+    // Formats pieces of the format string until the end is reached, or until the argument of a `%s`
+    // specifier needs its `__tostring` metamethod called. In that case, the call is returned and
+    // the result must be passed to `Format::finish_tostring` before continuing.
+    fn run(&mut self, ctx: Context<'gc>) -> Result<Option<MetaCall<'gc, 1>>, Error<'gc>> {
+        let format = self.format.clone();
+        while let Some(piece) = format.pieces().get(self.piece) {
+            match piece {
+                Piece::Literal(literal) => self.result.extend_from_slice(literal),
+                Piece::Spec(spec) => {
+                    // The format string is argument #1.
+                    let arg_num = self.arg + 2;
+                    let value = *self
+                        .args
+                        .get(self.arg)
+                        .ok_or(FormatError::MissingArgument(arg_num))?;
+                    self.arg += 1;
+
+                    match spec.conversion() {
+                        b'c' | b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+                            spec.format_integer(&mut self.result, integer_arg(value, arg_num)?);
+                        }
+                        b's' => match meta_ops::tostring(ctx, value)? {
+                            MetaResult::Value(v) => self.finish_tostring(v)?,
+                            MetaResult::Call(call) => return Ok(Some(call)),
+                        },
+                        b'q' => match value {
+                            Value::Nil | Value::Boolean(_) => self
+                                .result
+                                .extend_from_slice(value.display().to_string().as_bytes()),
+                            Value::Integer(i) => quote_integer(&mut self.result, i),
+                            Value::Number(n) => quote_float(&mut self.result, n),
+                            Value::String(s) => quote_string(&mut self.result, s.as_bytes()),
+                            _ => return Err(FormatError::NoLiteralForm(arg_num).into()),
+                        },
+                        _ => {
+                            let n = value.to_number().ok_or(FormatError::ExpectedNumber {
+                                arg: arg_num,
+                                found: value.type_name(),
+                            })?;
+                            spec.format_float(&mut self.result, n);
+                        }
+                    }
+                }
+            }
+            self.piece += 1;
+        }
+        Ok(None)
+    }
+
+    // Formats the string returned by `tostring` for the current `%s` specifier.
+    fn finish_tostring(&mut self, value: Value<'gc>) -> Result<(), FormatError> {
+        let Value::String(s) = value else {
+            return Err(FormatError::ToStringNotString);
+        };
+        let Piece::Spec(spec) = &self.format.pieces()[self.piece] else {
+            panic!("current piece is not a specifier");
+        };
+        spec.format_string(&mut self.result, s.as_bytes());
+        Ok(())
+    }
+}
+
+impl<'gc> Sequence<'gc> for Format<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        // We are only ever polled after a `__tostring` call, whose result is at the bottom of the
+        // stack.
+        let value = stack.get(0);
+        stack.clear();
+        self.finish_tostring(value)?;
+        self.piece += 1;
+
+        match self.run(ctx)? {
+            Some(call) => Ok(call.into_sequence_poll(&mut stack)),
+            None => {
+                exec.fuel()
+                    .consume((self.result.len() / FORMAT_BYTES_PER_FUEL) as i32);
+                stack.replace(ctx, ctx.intern(&self.result));
+                Ok(SequencePoll::Return)
+            }
+        }
+    }
+}
+
+// Converts an argument of an integer conversion to an integer, which must be a number or a string
+// with an exact integer representation.
+fn integer_arg(value: Value<'_>, arg_num: usize) -> Result<i64, FormatError> {
+    match value.to_integer() {
+        Some(i) => Ok(i),
+        None if value.to_number().is_some() => Err(FormatError::NoIntegerRepresentation(arg_num)),
+        None => Err(FormatError::ExpectedNumber {
+            arg: arg_num,
+            found: value.type_name(),
+        }),
+    }
+}
+
+// Returns the bytes of `string` from `i` to `j` inclusive, where negative indexes count back from
+// the end of the string, as in `string.sub`.
+fn operate_sub(string: &[u8], i: i64, j: Option<i64>) -> Result<&[u8], std::num::TryFromIntError> {
+    let i = match i {
+        i if i > 0 => i.saturating_sub(1).try_into()?,
+        0 => 0,
+        i => string.len().saturating_sub(i.unsigned_abs().try_into()?),
+    };
+    let j = if let Some(j) = j {
+        if j >= 0 {
+            j.try_into()?
+        } else {
+            let j: usize = j.unsigned_abs().try_into()?;
+            string.len().saturating_sub(j.saturating_sub(1))
+        }
+    } else {
+        string.len()
+    }
+    .clamp(0, string.len());
+
+    Ok(if i >= j || i >= string.len() {
+        &[]
+    } else {
+        &string[i..j]
+    })
+}
+
+// Packs the value of argument number `arg` as the given item.
+fn pack_item<'gc>(
+    ctx: Context<'gc>,
+    out: &mut Vec<u8>,
+    stack: &Stack<'gc, '_>,
+    arg: usize,
+    item: pack::Item,
+) -> Result<(), PackError> {
+    match item.kind {
+        Kind::Int | Kind::Uint => {
+            let n = check_integer("pack", stack, arg)?;
+            if !int_fits(n, item.kind, item.size) {
+                let message = if item.kind == Kind::Int {
+                    "integer overflow"
+                } else {
+                    "unsigned overflow"
+                };
+                return Err(PackError::bad_argument("pack", arg, message));
+            }
+            let negative = item.kind == Kind::Int && n < 0;
+            pack_int(out, n as u64, item.little_endian, item.size, negative);
+        }
+        Kind::Float => {
+            let n = check_number("pack", stack, arg)? as f32;
+            if item.little_endian {
+                out.extend_from_slice(&n.to_le_bytes());
+            } else {
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+        Kind::Double => {
+            let n = check_number("pack", stack, arg)?;
+            if item.little_endian {
+                out.extend_from_slice(&n.to_le_bytes());
+            } else {
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+        Kind::Char => {
+            let s = check_string(ctx, "pack", stack, arg)?.as_bytes();
+            if s.len() > item.size {
+                return Err(PackError::bad_argument(
+                    "pack",
+                    arg,
+                    "string longer than given size",
+                ));
+            }
+            out.extend_from_slice(s);
+            out.resize(out.len() + item.size - s.len(), 0);
+        }
+        Kind::String => {
+            let s = check_string(ctx, "pack", stack, arg)?.as_bytes();
+            if !len_fits(s.len(), item.size) {
+                return Err(PackError::bad_argument(
+                    "pack",
+                    arg,
+                    "string length does not fit in given size",
+                ));
+            }
+            pack_int(out, s.len() as u64, item.little_endian, item.size, false);
+            out.extend_from_slice(s);
+        }
+        Kind::ZString => {
+            let s = check_string(ctx, "pack", stack, arg)?.as_bytes();
+            if s.contains(&0) {
+                return Err(PackError::bad_argument(
+                    "pack",
+                    arg,
+                    "string contains zeros",
+                ));
+            }
+            out.extend_from_slice(s);
+            out.push(0);
+        }
+        Kind::Padding | Kind::PadAlign | Kind::Nop => {}
+    }
+    Ok(())
+}
+
+// Returns the type name of argument number `arg` (counting from 1) for use in error messages.
+fn arg_type_name(stack: &Stack<'_, '_>, arg: usize) -> &'static str {
+    if arg > stack.len() {
+        "no value"
+    } else {
+        stack.get(arg - 1).type_name()
+    }
+}
+
+// Returns argument number `arg` (counting from 1) as an integer.
+fn check_integer(
+    function: &'static str,
+    stack: &Stack<'_, '_>,
+    arg: usize,
+) -> Result<i64, PackError> {
+    let value = stack.get(arg - 1);
+    match value.to_integer() {
+        Some(i) => Ok(i),
+        None if value.to_number().is_some() => Err(PackError::bad_argument(
+            function,
+            arg,
+            "number has no integer representation",
+        )),
+        None => Err(PackError::bad_argument(
+            function,
+            arg,
+            format!("number expected, got {}", arg_type_name(stack, arg)),
+        )),
+    }
+}
+
+// Returns argument number `arg` (counting from 1) as a float.
+fn check_number(
+    function: &'static str,
+    stack: &Stack<'_, '_>,
+    arg: usize,
+) -> Result<f64, PackError> {
+    stack.get(arg - 1).to_number().ok_or_else(|| {
+        PackError::bad_argument(
+            function,
+            arg,
+            format!("number expected, got {}", arg_type_name(stack, arg)),
+        )
+    })
+}
+
+// Returns argument number `arg` (counting from 1) as a string, converting numbers to strings.
+fn check_string<'gc>(
+    ctx: Context<'gc>,
+    function: &'static str,
+    stack: &Stack<'gc, '_>,
+    arg: usize,
+) -> Result<String<'gc>, PackError> {
+    stack.get(arg - 1).into_string(ctx).ok_or_else(|| {
+        PackError::bad_argument(
+            function,
+            arg,
+            format!("string expected, got {}", arg_type_name(stack, arg)),
+        )
+    })
+}
@@ -0,0 +1,341 @@
+use std::{borrow::Cow, slice};
+
+use thiserror::Error;
+
+// The size in bytes of a Lua integer, and of the native `size_t`.
+const INTEGER_SIZE: usize = 8;
+
+// The largest integer size allowed in a format string.
+const MAX_INTEGER_SIZE: usize = 16;
+
+// The largest alignment allowed by `!` with no size, matching the alignment of the largest native
+// type in PUC-Rio Lua on common 64-bit platforms.
+const NATIVE_MAX_ALIGN: usize = 8;
+
+/// The maximum total size of packed data.
+pub const MAX_SIZE: usize = i64::MAX as usize;
+
+#[derive(Debug, Clone, Error)]
+pub enum PackError {
+    #[error("invalid format option '{0}'")]
+    InvalidOption(char),
+    #[error("integral size ({0}) out of limits [1,16]")]
+    SizeOutOfLimits(usize),
+    #[error("missing size for format option 'c'")]
+    MissingSize,
+    #[error("{0}-byte integer does not fit into Lua Integer")]
+    IntegerDoesNotFit(usize),
+    #[error("bad argument #{arg} to '{function}' ({message})")]
+    BadArgument {
+        function: &'static str,
+        arg: usize,
+        message: Cow<'static, str>,
+    },
+}
+
+impl PackError {
+    pub fn bad_argument(
+        function: &'static str,
+        arg: usize,
+        message: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        PackError::BadArgument {
+            function,
+            arg,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// A signed integer.
+    Int,
+    /// An unsigned integer.
+    Uint,
+    /// A 4-byte float.
+    Float,
+    /// An 8-byte float.
+    Double,
+    /// A fixed size string.
+    Char,
+    /// A string preceded by its length, as an unsigned integer of the item size.
+    String,
+    /// A zero-terminated string.
+    ZString,
+    /// A single byte of padding.
+    Padding,
+    /// Padding to the alignment of the following option, which is otherwise ignored.
+    PadAlign,
+    /// An option with no data, such as an endianness or alignment setting.
+    Nop,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Item {
+    pub kind: Kind,
+    pub size: usize,
+    /// The number of padding bytes which must be placed before this item to align it.
+    pub padding: usize,
+    pub little_endian: bool,
+}
+
+/// A parsed `string.pack` format string, which can be cached and reused.
+#[derive(Debug, Clone)]
+pub struct Format {
+    options: Vec<FormatOption>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FormatOption {
+    kind: Kind,
+    size: usize,
+    // The alignment of the option, limited by the maximum alignment at that point in the format
+    // string. Options which are never aligned have an alignment of 1.
+    align: usize,
+    little_endian: bool,
+}
+
+impl Format {
+    /// Parse a format string. The function name is used in error messages.
+    pub fn parse(function: &'static str, format: &[u8]) -> Result<Self, PackError> {
+        let mut parser = Parser {
+            function,
+            format,
+            pos: 0,
+            little_endian: cfg!(target_endian = "little"),
+            max_align: 1,
+        };
+        let mut options = Vec::new();
+        while let Some(option) = parser.next_option()? {
+            options.push(option);
+        }
+        Ok(Self { options })
+    }
+
+    /// Iterate over the items of the format.
+    pub fn items(&self) -> Items<'_> {
+        Items {
+            options: self.options.iter(),
+        }
+    }
+}
+
+/// The items of a `Format`, read one at a time.
+pub struct Items<'a> {
+    options: slice::Iter<'a, FormatOption>,
+}
+
+impl<'a> Items<'a> {
+    /// Read the next item, which is placed after `total_size` bytes of packed data.
+    pub fn next_item(&mut self, total_size: usize) -> Option<Item> {
+        let option = self.options.next()?;
+        let align = option.align;
+        Some(Item {
+            kind: option.kind,
+            size: option.size,
+            padding: (align - (total_size & (align - 1))) & (align - 1),
+            little_endian: option.little_endian,
+        })
+    }
+}
+
+struct Parser<'a> {
+    function: &'static str,
+    format: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+    max_align: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn next_option(&mut self) -> Result<Option<FormatOption>, PackError> {
+        if self.pos >= self.format.len() {
+            return Ok(None);
+        }
+
+        let (kind, size) = self.read_option()?;
+        let mut align = size;
+        if kind == Kind::PadAlign {
+            let next = if self.pos < self.format.len() {
+                let (next, next_size) = self.read_option()?;
+                align = next_size;
+                Some(next)
+            } else {
+                None
+            };
+            if next.is_none() || next == Some(Kind::Char) || align == 0 {
+                return Err(PackError::bad_argument(
+                    self.function,
+                    1,
+                    "invalid next option for option 'X'",
+                ));
+            }
+        }
+
+        let align = if align <= 1 || kind == Kind::Char {
+            1
+        } else {
+            let align = align.min(self.max_align);
+            if !align.is_power_of_two() {
+                return Err(PackError::bad_argument(
+                    self.function,
+                    1,
+                    "format asks for alignment not power of 2",
+                ));
+            }
+            align
+        };
+
+        Ok(Some(FormatOption {
+            kind,
+            size,
+            align,
+            little_endian: self.little_endian,
+        }))
+    }
+
+    fn read_option(&mut self) -> Result<(Kind, usize), PackError> {
+        let opt = self.format[self.pos];
+        self.pos += 1;
+        Ok(match opt {
+            b'b' => (Kind::Int, 1),
+            b'B' => (Kind::Uint, 1),
+            b'h' => (Kind::Int, 2),
+            b'H' => (Kind::Uint, 2),
+            b'l' | b'j' => (Kind::Int, INTEGER_SIZE),
+            b'L' | b'J' | b'T' => (Kind::Uint, INTEGER_SIZE),
+            b'f' => (Kind::Float, 4),
+            b'd' | b'n' => (Kind::Double, 8),
+            b'i' => (Kind::Int, self.read_size_limit(4)?),
+            b'I' => (Kind::Uint, self.read_size_limit(4)?),
+            b's' => (Kind::String, self.read_size_limit(INTEGER_SIZE)?),
+            b'c' => (Kind::Char, self.read_size().ok_or(PackError::MissingSize)?),
+            b'z' => (Kind::ZString, 0),
+            b'x' => (Kind::Padding, 1),
+            b'X' => (Kind::PadAlign, 0),
+            b' ' => (Kind::Nop, 0),
+            b'<' => {
+                self.little_endian = true;
+                (Kind::Nop, 0)
+            }
+            b'>' => {
+                self.little_endian = false;
+                (Kind::Nop, 0)
+            }
+            b'=' => {
+                self.little_endian = cfg!(target_endian = "little");
+                (Kind::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.read_size_limit(NATIVE_MAX_ALIGN)?;
+                (Kind::Nop, 0)
+            }
+            opt => return Err(PackError::InvalidOption(opt as char)),
+        })
+    }
+
+    // Reads an optional size following an option.
+    fn read_size(&mut self) -> Option<usize> {
+        let mut size: Option<usize> = None;
+        while let Some(d) = self.format.get(self.pos).filter(|c| c.is_ascii_digit()) {
+            let n = size.unwrap_or(0);
+            if n > (MAX_SIZE - 9) / 10 {
+                break;
+            }
+            size = Some(n * 10 + (d - b'0') as usize);
+            self.pos += 1;
+        }
+        size
+    }
+
+    // Reads an optional integer size following an option, which must be within the allowed
+    // integer sizes.
+    fn read_size_limit(&mut self, default: usize) -> Result<usize, PackError> {
+        let size = self.read_size().unwrap_or(default);
+        if size == 0 || size > MAX_INTEGER_SIZE {
+            return Err(PackError::SizeOutOfLimits(size));
+        }
+        Ok(size)
+    }
+}
+
+/// Append an integer of the given size in bytes. If the size is larger than a Lua integer, the
+/// extra bytes are filled with the sign of the integer.
+pub fn pack_int(out: &mut Vec<u8>, n: u64, little_endian: bool, size: usize, negative: bool) {
+    let start = out.len();
+    for i in 0..size {
+        out.push(if i < INTEGER_SIZE {
+            (n >> (i * 8)) as u8
+        } else if negative {
+            0xff
+        } else {
+            0
+        });
+    }
+    if !little_endian {
+        out[start..].reverse();
+    }
+}
+
+/// Read an integer of the given size in bytes, sign extending it if it is signed.
+///
+/// Integers larger than a Lua integer must have only sign bytes in their extra bytes.
+pub fn unpack_int(
+    bytes: &[u8],
+    little_endian: bool,
+    size: usize,
+    signed: bool,
+) -> Result<i64, PackError> {
+    let byte = |i: usize| {
+        if little_endian {
+            bytes[i]
+        } else {
+            bytes[size - 1 - i]
+        }
+    };
+
+    let limit = size.min(INTEGER_SIZE);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | byte(i) as u64;
+    }
+
+    if size < INTEGER_SIZE {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > INTEGER_SIZE {
+        let sign = if !signed || (res as i64) >= 0 {
+            0
+        } else {
+            0xff
+        };
+        if (limit..size).any(|i| byte(i) != sign) {
+            return Err(PackError::IntegerDoesNotFit(size));
+        }
+    }
+
+    Ok(res as i64)
+}
+
+/// Returns whether a Lua integer fits in an integer item of the given kind and size.
+pub fn int_fits(n: i64, kind: Kind, size: usize) -> bool {
+    if size >= INTEGER_SIZE {
+        return true;
+    }
+    let bits = size * 8;
+    match kind {
+        Kind::Int => {
+            let lim = 1i64 << (bits - 1);
+            -lim <= n && n < lim
+        }
+        _ => (n as u64) < (1u64 << bits),
+    }
+}
+
+/// Returns whether a string length can be stored in a length prefix of the given size.
+pub fn len_fits(len: usize, size: usize) -> bool {
+    size >= INTEGER_SIZE || (len as u64) < (1u64 << (size * 8))
+}
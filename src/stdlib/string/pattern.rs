@@ -0,0 +1,543 @@
+use thiserror::Error;
+
+/// The maximum number of captures in a single pattern.
+const MAX_CAPTURES: usize = 32;
+
+/// The maximum recursion depth of the matcher, patterns which need more than this raise
+/// `PatternError::TooComplex` rather than overflowing the Rust stack.
+const MAX_MATCH_DEPTH: usize = 200;
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum PatternError {
+    #[error("malformed pattern (ends with '%')")]
+    EndsWithEscape,
+    #[error("malformed pattern (missing ']')")]
+    MissingBracket,
+    #[error("malformed pattern (missing arguments to '%b')")]
+    MissingBalanceArguments,
+    #[error("missing '[' after '%f' in pattern")]
+    MissingFrontierSet,
+    #[error("invalid capture index %{0} in pattern")]
+    InvalidCaptureIndex(u8),
+    #[error("invalid pattern capture")]
+    InvalidCapture,
+    #[error("unfinished capture")]
+    UnfinishedCapture,
+    #[error("too many captures")]
+    TooManyCaptures,
+    #[error("pattern too complex")]
+    TooComplex,
+}
+
+/// A parsed Lua pattern.
+///
+/// Patterns are parsed up front, so a malformed pattern is always an error, even if matching would
+/// have failed before reaching the malformed part.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    anchored: bool,
+    items: Vec<Item>,
+}
+
+/// A single capture from a successful match.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Capture {
+    /// A captured substring, as a range of byte indexes into the subject.
+    Range(usize, usize),
+    /// A position capture `()`, as a byte index into the subject.
+    Position(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    /// The captures of the pattern, in order. This is empty if the pattern has no captures.
+    pub captures: Vec<Capture>,
+}
+
+/// Returns true if the pattern contains no special characters, and so can only match itself.
+pub fn is_plain(pattern: &[u8]) -> bool {
+    !pattern.iter().any(|b| b"^$*+?.([%-".contains(b))
+}
+
+impl Pattern {
+    /// Parse a pattern, where a leading `^` anchors the match at the start position.
+    pub fn parse(pattern: &[u8]) -> Result<Pattern, PatternError> {
+        match pattern.split_first() {
+            Some((b'^', rest)) => Ok(Pattern {
+                anchored: true,
+                ..Self::parse_items(rest)?
+            }),
+            _ => Self::parse_items(pattern),
+        }
+    }
+
+    /// Parse a pattern where a leading `^` has no special meaning, as in `string.gmatch`.
+    pub fn parse_unanchored(pattern: &[u8]) -> Result<Pattern, PatternError> {
+        Self::parse_items(pattern)
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    /// Try to match the pattern starting exactly at byte index `start` of `subject`.
+    ///
+    /// The number of steps taken by the matcher is added to `steps`, which callers use to charge
+    /// fuel for expensive matches.
+    pub fn match_at(
+        &self,
+        subject: &[u8],
+        start: usize,
+        steps: &mut usize,
+    ) -> Result<Option<Match>, PatternError> {
+        let mut matcher = Matcher {
+            subject,
+            items: &self.items,
+            captures: Vec::new(),
+            depth: 0,
+            steps: 0,
+        };
+        let end = matcher.do_match(start, 0);
+        *steps += matcher.steps;
+
+        let Some(end) = end? else {
+            return Ok(None);
+        };
+        let captures = matcher
+            .captures
+            .iter()
+            .map(|&(start, len)| match len {
+                CaptureLen::Position => Capture::Position(start),
+                CaptureLen::Len(len) => Capture::Range(start, start + len),
+                CaptureLen::Unfinished => unreachable!("unfinished captures are a parse error"),
+            })
+            .collect();
+        Ok(Some(Match {
+            start,
+            end,
+            captures,
+        }))
+    }
+
+    fn parse_items(pattern: &[u8]) -> Result<Pattern, PatternError> {
+        let mut items = Vec::new();
+        let mut captures = 0;
+        let mut open_captures = Vec::new();
+
+        let mut i = 0;
+        while i < pattern.len() {
+            match (pattern[i], pattern.get(i + 1).copied()) {
+                (b'(', next) => {
+                    if captures == MAX_CAPTURES {
+                        return Err(PatternError::TooManyCaptures);
+                    }
+                    if next == Some(b')') {
+                        items.push(Item::OpenPosition);
+                        i += 2;
+                    } else {
+                        open_captures.push(captures);
+                        items.push(Item::OpenCapture);
+                        i += 1;
+                    }
+                    captures += 1;
+                }
+                (b')', _) => {
+                    open_captures.pop().ok_or(PatternError::InvalidCapture)?;
+                    items.push(Item::CloseCapture);
+                    i += 1;
+                }
+                (b'$', None) => {
+                    items.push(Item::EndAnchor);
+                    i += 1;
+                }
+                (b'%', Some(b'b')) => {
+                    let (Some(&open), Some(&close)) = (pattern.get(i + 2), pattern.get(i + 3))
+                    else {
+                        return Err(PatternError::MissingBalanceArguments);
+                    };
+                    items.push(Item::Balance(open, close));
+                    i += 4;
+                }
+                (b'%', Some(b'f')) => {
+                    i += 2;
+                    if pattern.get(i) != Some(&b'[') {
+                        return Err(PatternError::MissingFrontierSet);
+                    }
+                    let (set, end) = parse_set(pattern, i + 1)?;
+                    items.push(Item::Frontier(set));
+                    i = end;
+                }
+                (b'%', Some(d @ b'0'..=b'9')) => {
+                    let index = (d - b'0') as usize;
+                    if index == 0 || index > captures || open_captures.contains(&(index - 1)) {
+                        return Err(PatternError::InvalidCaptureIndex(d - b'0'));
+                    }
+                    items.push(Item::BackReference(index - 1));
+                    i += 2;
+                }
+                _ => {
+                    let (class, end) = parse_class(pattern, i)?;
+                    i = end;
+                    let repeat = match pattern.get(i) {
+                        Some(b'?') => Repeat::ZeroOrOne,
+                        Some(b'*') => Repeat::ZeroOrMore,
+                        Some(b'+') => Repeat::OneOrMore,
+                        Some(b'-') => Repeat::Lazy,
+                        _ => Repeat::One,
+                    };
+                    if repeat != Repeat::One {
+                        i += 1;
+                    }
+                    items.push(Item::Single(class, repeat));
+                }
+            }
+        }
+
+        if !open_captures.is_empty() {
+            return Err(PatternError::UnfinishedCapture);
+        }
+
+        Ok(Pattern {
+            anchored: false,
+            items,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Single(Class, Repeat),
+    OpenCapture,
+    OpenPosition,
+    CloseCapture,
+    EndAnchor,
+    Balance(u8, u8),
+    Frontier(Class),
+    BackReference(usize),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Repeat {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+    Lazy,
+}
+
+#[derive(Debug, Clone)]
+enum Class {
+    Any,
+    Byte(u8),
+    Named(NamedClass),
+    Set { negated: bool, items: Vec<SetItem> },
+}
+
+#[derive(Debug, Clone)]
+enum SetItem {
+    Byte(u8),
+    Range(u8, u8),
+    Named(NamedClass),
+}
+
+/// A character class escape like `%a`, or its complement like `%A`.
+#[derive(Debug, Copy, Clone)]
+struct NamedClass {
+    class: u8,
+    negated: bool,
+}
+
+impl NamedClass {
+    fn new(c: u8) -> Option<NamedClass> {
+        let class = c.to_ascii_lowercase();
+        b"acdglpsuwx".contains(&class).then_some(NamedClass {
+            class,
+            negated: c.is_ascii_uppercase(),
+        })
+    }
+
+    fn matches(self, b: u8) -> bool {
+        let m = match self.class {
+            b'a' => b.is_ascii_alphabetic(),
+            b'c' => b.is_ascii_control(),
+            b'd' => b.is_ascii_digit(),
+            b'g' => b.is_ascii_graphic(),
+            b'l' => b.is_ascii_lowercase(),
+            b'p' => b.is_ascii_punctuation(),
+            // Matches C `isspace`, which unlike `u8::is_ascii_whitespace` includes vertical tab.
+            b's' => matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'),
+            b'u' => b.is_ascii_uppercase(),
+            b'w' => b.is_ascii_alphanumeric(),
+            b'x' => b.is_ascii_hexdigit(),
+            _ => unreachable!(),
+        };
+        m != self.negated
+    }
+}
+
+impl Class {
+    fn matches(&self, b: u8) -> bool {
+        match self {
+            Class::Any => true,
+            &Class::Byte(c) => b == c,
+            &Class::Named(named) => named.matches(b),
+            Class::Set { negated, items } => {
+                let m = items.iter().any(|item| match *item {
+                    SetItem::Byte(c) => b == c,
+                    SetItem::Range(low, high) => (low..=high).contains(&b),
+                    SetItem::Named(named) => named.matches(b),
+                });
+                m != *negated
+            }
+        }
+    }
+}
+
+// Parses a single character class starting at `i`, returning the class and the index after it.
+fn parse_class(pattern: &[u8], i: usize) -> Result<(Class, usize), PatternError> {
+    match pattern[i] {
+        b'.' => Ok((Class::Any, i + 1)),
+        b'%' => {
+            let &c = pattern.get(i + 1).ok_or(PatternError::EndsWithEscape)?;
+            let class = match NamedClass::new(c) {
+                Some(named) => Class::Named(named),
+                None => Class::Byte(c),
+            };
+            Ok((class, i + 2))
+        }
+        b'[' => parse_set(pattern, i + 1),
+        c => Ok((Class::Byte(c), i + 1)),
+    }
+}
+
+// Parses the body of a set starting just after its opening `[`, returning the set and the index
+// after its closing `]`.
+fn parse_set(pattern: &[u8], start: usize) -> Result<(Class, usize), PatternError> {
+    let mut i = start;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+    let body = i;
+
+    // The first character of the body is never the closing `]`, so `[]]` is a set containing `]`.
+    let end = loop {
+        let &c = pattern.get(i).ok_or(PatternError::MissingBracket)?;
+        i += 1;
+        if c == b'%' {
+            i += 1;
+        }
+        match pattern.get(i) {
+            Some(b']') => break i,
+            Some(_) => {}
+            None => return Err(PatternError::MissingBracket),
+        }
+    };
+
+    let mut items = Vec::new();
+    let mut i = body;
+    while i < end {
+        if pattern[i] == b'%' {
+            let c = pattern[i + 1];
+            items.push(match NamedClass::new(c) {
+                Some(named) => SetItem::Named(named),
+                None => SetItem::Byte(c),
+            });
+            i += 2;
+        } else if i + 2 < end && pattern[i + 1] == b'-' {
+            items.push(SetItem::Range(pattern[i], pattern[i + 2]));
+            i += 3;
+        } else {
+            items.push(SetItem::Byte(pattern[i]));
+            i += 1;
+        }
+    }
+
+    Ok((Class::Set { negated, items }, end + 1))
+}
+
+#[derive(Debug, Copy, Clone)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+struct Matcher<'a> {
+    subject: &'a [u8],
+    items: &'a [Item],
+    captures: Vec<(usize, CaptureLen)>,
+    depth: usize,
+    steps: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn do_match(&mut self, s: usize, item: usize) -> Result<Option<usize>, PatternError> {
+        if self.depth == MAX_MATCH_DEPTH {
+            return Err(PatternError::TooComplex);
+        }
+        self.depth += 1;
+        let res = self.match_items(s, item);
+        self.depth -= 1;
+        res
+    }
+
+    fn match_items(
+        &mut self,
+        mut s: usize,
+        mut item: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        let subject = self.subject;
+        loop {
+            self.steps += 1;
+            let Some(current) = self.items.get(item) else {
+                return Ok(Some(s));
+            };
+
+            match current {
+                Item::OpenCapture | Item::OpenPosition => {
+                    let len = match current {
+                        Item::OpenPosition => CaptureLen::Position,
+                        _ => CaptureLen::Unfinished,
+                    };
+                    self.captures.push((s, len));
+                    let res = self.do_match(s, item + 1)?;
+                    if res.is_none() {
+                        self.captures.pop();
+                    }
+                    return Ok(res);
+                }
+                Item::CloseCapture => {
+                    let index = self
+                        .captures
+                        .iter()
+                        .rposition(|(_, len)| matches!(len, CaptureLen::Unfinished))
+                        .expect("unbalanced captures are a parse error");
+                    self.captures[index].1 = CaptureLen::Len(s - self.captures[index].0);
+                    let res = self.do_match(s, item + 1)?;
+                    if res.is_none() {
+                        self.captures[index].1 = CaptureLen::Unfinished;
+                    }
+                    return Ok(res);
+                }
+                Item::EndAnchor => {
+                    return Ok((s == subject.len()).then_some(s));
+                }
+                &Item::Balance(open, close) => {
+                    if subject.get(s) != Some(&open) {
+                        return Ok(None);
+                    }
+                    let mut depth = 1;
+                    let mut i = s + 1;
+                    loop {
+                        let Some(&c) = subject.get(i) else {
+                            return Ok(None);
+                        };
+                        i += 1;
+                        if c == close {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        } else if c == open {
+                            depth += 1;
+                        }
+                    }
+                    self.steps += i - s;
+                    s = i;
+                    item += 1;
+                }
+                Item::Frontier(set) => {
+                    let prev = if s == 0 { 0 } else { subject[s - 1] };
+                    let next = subject.get(s).copied().unwrap_or(0);
+                    if set.matches(prev) || !set.matches(next) {
+                        return Ok(None);
+                    }
+                    item += 1;
+                }
+                &Item::BackReference(index) => {
+                    let (start, CaptureLen::Len(len)) = self.captures[index] else {
+                        return Ok(None);
+                    };
+                    if !subject[s..].starts_with(&subject[start..start + len]) {
+                        return Ok(None);
+                    }
+                    s += len;
+                    item += 1;
+                }
+                Item::Single(class, repeat) => {
+                    let matched = subject.get(s).is_some_and(|&b| class.matches(b));
+                    match repeat {
+                        Repeat::One => {
+                            if !matched {
+                                return Ok(None);
+                            }
+                            s += 1;
+                            item += 1;
+                        }
+                        Repeat::ZeroOrOne => {
+                            if matched {
+                                if let Some(end) = self.do_match(s + 1, item + 1)? {
+                                    return Ok(Some(end));
+                                }
+                            }
+                            item += 1;
+                        }
+                        Repeat::ZeroOrMore => return self.max_expand(s, class, item),
+                        Repeat::OneOrMore => {
+                            return if matched {
+                                self.max_expand(s + 1, class, item)
+                            } else {
+                                Ok(None)
+                            };
+                        }
+                        Repeat::Lazy => return self.min_expand(s, class, item),
+                    }
+                }
+            }
+        }
+    }
+
+    // Matches as many repetitions of `class` as possible, then backtracks until the rest of the
+    // pattern after `item` matches.
+    fn max_expand(
+        &mut self,
+        s: usize,
+        class: &Class,
+        item: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        let count = self.subject[s..]
+            .iter()
+            .take_while(|&&b| class.matches(b))
+            .count();
+        self.steps += count;
+        for i in (0..=count).rev() {
+            if let Some(end) = self.do_match(s + i, item + 1)? {
+                return Ok(Some(end));
+            }
+        }
+        Ok(None)
+    }
+
+    // Matches as few repetitions of `class` as possible such that the rest of the pattern after
+    // `item` matches.
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        class: &Class,
+        item: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if let Some(end) = self.do_match(s, item + 1)? {
+                return Ok(Some(end));
+            }
+            if self.subject.get(s).is_some_and(|&b| class.matches(b)) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+}
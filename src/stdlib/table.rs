@@ -2,8 +2,8 @@ use gc_arena::Collect;
 
 use crate::meta_ops::{self, MetaResult};
 use crate::{
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, Sequence,
-    SequencePoll, Stack, Table, Value,
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Fuel, Function, IntoValue,
+    Sequence, SequencePoll, Stack, String, Table, Value,
 };
 
 pub fn load_table<'gc>(ctx: Context<'gc>) {
@@ -58,9 +58,208 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
         )
         .unwrap();
 
+    fn insert<'gc>(
+        ctx: Context<'gc>,
+        fuel: &mut Fuel,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<(), Error<'gc>> {
+        let table: Table = stack.from_front(ctx)?;
+        let end = table.length().wrapping_add(1);
+        match stack.len() {
+            1 => {
+                table.set(ctx, end, stack.get(0))?;
+            }
+            2 => {
+                let (pos, value): (i64, Value) = stack.consume(ctx)?;
+                // Checks `1 <= pos <= end` with a single comparison, like PUC-Rio Lua.
+                if (pos as u64).wrapping_sub(1) >= end as u64 {
+                    return Err("bad argument #2 to 'insert' (position out of bounds)"
+                        .into_value(ctx)
+                        .into());
+                }
+                for i in (pos + 1..=end).rev() {
+                    table.set(ctx, i, table.get(ctx, i - 1))?;
+                }
+                table.set(ctx, pos, value)?;
+                fuel.consume(elems_fuel(end - pos));
+            }
+            _ => {
+                return Err("wrong number of arguments to 'insert'"
+                    .into_value(ctx)
+                    .into());
+            }
+        }
+        stack.clear();
+        Ok(())
+    }
+
+    table
+        .set(ctx, "insert", Callback::new_intrinsic(&ctx, insert))
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "remove",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let (table, pos): (Table, Option<i64>) = stack.consume(ctx)?;
+                let size = table.length();
+                let mut pos = pos.unwrap_or(size);
+                // Removing at `size + 1` is allowed, and removing at `size` when the table is empty
+                // removes at 0.
+                if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+                    return Err("bad argument #2 to 'remove' (position out of bounds)"
+                        .into_value(ctx)
+                        .into());
+                }
+                let removed = table.get(ctx, pos);
+                exec.fuel().consume(elems_fuel(size - pos));
+                while pos < size {
+                    table.set(ctx, pos, table.get(ctx, pos + 1))?;
+                    pos += 1;
+                }
+                table.set(ctx, pos, Value::Nil)?;
+                stack.replace(ctx, removed);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "move",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (from, first, last, target, to): (Table, i64, i64, i64, Option<Table>) =
+                    stack.consume(ctx)?;
+                let to = to.unwrap_or(from);
+                if last < first {
+                    stack.replace(ctx, to);
+                    return Ok(CallbackReturn::Return);
+                }
+                if first <= 0 && last >= i64::MAX + first {
+                    return Err("bad argument #3 to 'move' (too many elements to move)"
+                        .into_value(ctx)
+                        .into());
+                }
+                let count = last - first;
+                if target > i64::MAX - count {
+                    return Err("bad argument #4 to 'move' (destination wrap around)"
+                        .into_value(ctx)
+                        .into());
+                }
+                // Copy backwards only if the ranges overlap with the destination after the source.
+                let backward = target > first && target <= last && from == to;
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    Move {
+                        from,
+                        to,
+                        first,
+                        target,
+                        count,
+                        offset: 0,
+                        backward,
+                    },
+                )))
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "concat",
+            Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+                let (table, sep, i, j): (Table, Option<String>, Option<i64>, Option<i64>) =
+                    stack.consume(ctx)?;
+                let sep = sep.map(|s| s.as_bytes()).unwrap_or(b"");
+                let i = i.unwrap_or(1);
+                let j = j.unwrap_or_else(|| table.length());
+
+                let mut bytes = Vec::new();
+                if i <= j {
+                    let mut index = i;
+                    loop {
+                        let value = table.get(ctx, index);
+                        if !matches!(
+                            value,
+                            Value::String(_) | Value::Integer(_) | Value::Number(_)
+                        ) {
+                            return Err(format!(
+                                "invalid value (at index {}) in table for 'concat'",
+                                index
+                            )
+                            .into_value(ctx)
+                            .into());
+                        }
+                        value.write(&mut bytes).unwrap();
+                        if index == j {
+                            break;
+                        }
+                        bytes.extend_from_slice(sep);
+                        index += 1;
+                    }
+                    exec.fuel().consume(elems_fuel(j - i));
+                }
+                stack.replace(ctx, ctx.intern(&bytes));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    table
+        .set(
+            ctx,
+            "sort",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (table, comp): (Table, Option<Function>) = stack.consume(ctx)?;
+                let length = table.length();
+                if length <= 1 {
+                    return Ok(CallbackReturn::Return);
+                }
+                if length >= i32::MAX as i64 {
+                    return Err("bad argument #1 to 'sort' (array too big)"
+                        .into_value(ctx)
+                        .into());
+                }
+
+                let items: Vec<Value> = (1..=length).map(|i| table.get(ctx, i)).collect();
+                let length = items.len();
+                Ok(CallbackReturn::Sequence(BoxSequence::new(
+                    &ctx,
+                    Sort {
+                        table,
+                        comp,
+                        buffer: items.clone(),
+                        items,
+                        phase: SortPhase::Merge {
+                            width: 1,
+                            left: 0,
+                            i: 0,
+                            j: 1.min(length),
+                            k: 0,
+                        },
+                        awaiting: false,
+                    },
+                )))
+            }),
+        )
+        .unwrap();
+
     ctx.set_global("table", table).unwrap();
 }
 
+// `insert`, `remove`, `move` and `concat` access elements directly, without metamethods, and
+// consume one fuel for every `RAW_ELEMS_PER_FUEL` elements accessed.
+const RAW_ELEMS_PER_FUEL: usize = 8;
+
+fn elems_fuel(count: i64) -> i32 {
+    (count.max(0) as u64 / RAW_ELEMS_PER_FUEL as u64)
+        .try_into()
+        .unwrap_or(i32::MAX)
+}
+
 const PACK_ELEMS_PER_FUEL: usize = 8;
 const PACK_MIN_BATCH_SIZE: usize = 4096;
 
@@ -97,11 +296,7 @@ impl<'gc> Sequence<'gc> for Pack<'gc> {
             if let Some(call) =
                 meta_ops::new_index(ctx, table, "n".into_value(ctx), (length as i64).into())?
             {
-                stack.extend(call.args);
-                return Ok(SequencePoll::Call {
-                    function: call.function,
-                    bottom: length,
-                });
+                return Ok(call.into_sequence_poll(&mut stack));
             }
         }
 
@@ -139,11 +334,7 @@ impl<'gc> Sequence<'gc> for Pack<'gc> {
                 if let Some(call) =
                     meta_ops::new_index(ctx, table, (*index as i64 + 1).into(), stack[*index])?
                 {
-                    stack.extend(call.args);
-                    return Ok(SequencePoll::Call {
-                        function: call.function,
-                        bottom: length,
-                    });
+                    return Ok(call.into_sequence_poll(&mut stack));
                 }
                 *index += 1;
             }
@@ -209,11 +400,7 @@ impl<'gc> Sequence<'gc> for Unpack<'gc> {
             match meta_ops::len(ctx, table)? {
                 MetaResult::Value(v) => stack.push_back(v),
                 MetaResult::Call(call) => {
-                    stack.extend(call.args);
-                    return Ok(SequencePoll::Call {
-                        function: call.function,
-                        bottom: 0,
-                    });
+                    return Ok(call.into_sequence_poll(&mut stack));
                 }
             }
         }
@@ -281,11 +468,7 @@ impl<'gc> Sequence<'gc> for Unpack<'gc> {
                     }
                     MetaResult::Call(call) => {
                         *callback_return = true;
-                        stack.extend(call.args);
-                        return Ok(SequencePoll::Call {
-                            function: call.function,
-                            bottom: *index,
-                        });
+                        return Ok(call.into_sequence_poll(&mut stack));
                     }
                 }
                 *index += 1;
@@ -302,3 +485,230 @@ impl<'gc> Sequence<'gc> for Unpack<'gc> {
         Ok(SequencePoll::Return)
     }
 }
+
+// Copies `from[first..=first + count]` to `to[target..=target + count]`, which can be suspended
+// between elements since the range is not limited by the size of either table.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Move<'gc> {
+    from: Table<'gc>,
+    to: Table<'gc>,
+    first: i64,
+    target: i64,
+    count: i64,
+    // The number of elements copied so far.
+    offset: i64,
+    backward: bool,
+}
+
+impl<'gc> Sequence<'gc> for Move<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let fuel = exec.fuel();
+        // `move` checks that `count < i64::MAX`, so `offset` can not overflow.
+        while self.offset <= self.count {
+            let batch_end = self
+                .count
+                .min(self.offset.saturating_add(RAW_ELEMS_PER_FUEL as i64 - 1));
+            while self.offset <= batch_end {
+                let i = if self.backward {
+                    self.count - self.offset
+                } else {
+                    self.offset
+                };
+                self.to
+                    .set(ctx, self.target + i, self.from.get(ctx, self.first + i))?;
+                self.offset += 1;
+            }
+            fuel.consume(1);
+
+            if self.offset <= self.count && !fuel.should_continue() {
+                return Ok(SequencePoll::Pending);
+            }
+        }
+        stack.replace(ctx, self.to);
+        Ok(SequencePoll::Return)
+    }
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+enum SortPhase {
+    // Merging the sorted runs `left..left + width` and `left + width..left + 2 * width` of `items`
+    // into `buffer`. `i` and `j` are the next elements of each run, and `k` is the next position
+    // in `buffer`.
+    Merge {
+        width: usize,
+        left: usize,
+        i: usize,
+        j: usize,
+        k: usize,
+    },
+    // Checking that no element is less than the element before it, which catches invalid order
+    // functions.
+    Verify {
+        index: usize,
+    },
+}
+
+// A bottom-up merge sort which can be suspended at every comparison, so the order function can
+// be any Lua function, including one which yields.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Sort<'gc> {
+    table: Table<'gc>,
+    comp: Option<Function<'gc>>,
+    items: Vec<Value<'gc>>,
+    buffer: Vec<Value<'gc>>,
+    phase: SortPhase,
+    // True if the result of a comparison is waiting at the bottom of the stack.
+    awaiting: bool,
+}
+
+impl<'gc> Sort<'gc> {
+    // Returns the pair of elements which must be compared next with `a < b`, finishing any merge
+    // steps which need no comparison. Returns `None` once sorting and verification are done.
+    fn next_comparison(&mut self) -> Option<(Value<'gc>, Value<'gc>)> {
+        let length = self.items.len();
+        loop {
+            match self.phase {
+                SortPhase::Merge {
+                    width,
+                    left,
+                    ref mut i,
+                    ref mut j,
+                    ref mut k,
+                } => {
+                    let mid = (left + width).min(length);
+                    let right = (left + 2 * width).min(length);
+                    if *i < mid && *j < right {
+                        // Take from the right run only if it is strictly less, to keep the sort
+                        // stable.
+                        return Some((self.items[*j], self.items[*i]));
+                    } else if *i < mid {
+                        self.buffer[*k] = self.items[*i];
+                        *i += 1;
+                        *k += 1;
+                    } else if *j < right {
+                        self.buffer[*k] = self.items[*j];
+                        *j += 1;
+                        *k += 1;
+                    } else if right < length {
+                        let left = right;
+                        self.phase = SortPhase::Merge {
+                            width,
+                            left,
+                            i: left,
+                            j: (left + width).min(length),
+                            k: left,
+                        };
+                    } else {
+                        std::mem::swap(&mut self.items, &mut self.buffer);
+                        let width = width * 2;
+                        self.phase = if width < length {
+                            SortPhase::Merge {
+                                width,
+                                left: 0,
+                                i: 0,
+                                j: width,
+                                k: 0,
+                            }
+                        } else {
+                            SortPhase::Verify { index: 1 }
+                        };
+                    }
+                }
+                SortPhase::Verify { index } => {
+                    return (index < length).then(|| (self.items[index], self.items[index - 1]));
+                }
+            }
+        }
+    }
+
+    // Applies the result of the comparison returned by `next_comparison`.
+    fn apply_comparison(&mut self, less: bool) -> Result<(), &'static str> {
+        match self.phase {
+            SortPhase::Merge {
+                ref mut i,
+                ref mut j,
+                ref mut k,
+                ..
+            } => {
+                if less {
+                    self.buffer[*k] = self.items[*j];
+                    *j += 1;
+                } else {
+                    self.buffer[*k] = self.items[*i];
+                    *i += 1;
+                }
+                *k += 1;
+                Ok(())
+            }
+            SortPhase::Verify { ref mut index } => {
+                if less {
+                    return Err("invalid order function for sorting");
+                }
+                *index += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'gc> Sequence<'gc> for Sort<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        mut exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let fuel = exec.fuel();
+        loop {
+            if self.awaiting {
+                self.awaiting = false;
+                let less = stack.get(0).to_bool();
+                stack.clear();
+                self.apply_comparison(less).map_err(|e| e.into_value(ctx))?;
+            }
+
+            if !fuel.should_continue() {
+                return Ok(SequencePoll::Pending);
+            }
+
+            let Some((a, b)) = self.next_comparison() else {
+                break;
+            };
+            fuel.consume(1);
+
+            match self.comp {
+                Some(comp) => {
+                    self.awaiting = true;
+                    stack.extend([a, b]);
+                    return Ok(SequencePoll::Call {
+                        bottom: 0,
+                        function: comp,
+                    });
+                }
+                None => match meta_ops::less_than(ctx, a, b)? {
+                    MetaResult::Value(v) => {
+                        self.apply_comparison(v.to_bool())
+                            .map_err(|e| e.into_value(ctx))?;
+                    }
+                    MetaResult::Call(call) => {
+                        self.awaiting = true;
+                        return Ok(call.into_sequence_poll(&mut stack));
+                    }
+                },
+            }
+        }
+
+        for (i, &value) in self.items.iter().enumerate() {
+            self.table.set(ctx, i as i64 + 1, value)?;
+        }
+        Ok(SequencePoll::Return)
+    }
+}
@@ -0,0 +1,329 @@
+use crate::{Callback, CallbackReturn, Context, Error, IntoValue, Stack, String, Table, Value};
+
+// The largest code point accepted by any function, even in lax mode.
+const MAX_UTF: i64 = 0x7FFFFFFF;
+
+// Functions which decode strings consume one fuel for every `UTF8_BYTES_PER_FUEL` bytes decoded.
+const UTF8_BYTES_PER_FUEL: usize = 64;
+
+pub fn load_utf8<'gc>(ctx: Context<'gc>) {
+    let utf8 = Table::new(&ctx);
+
+    utf8.set(
+        ctx,
+        "charpattern",
+        ctx.intern_static(b"[\0-\x7F\xC2-\xFD][\x80-\xBF]*"),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "char",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let mut bytes = Vec::new();
+            for i in 0..stack.len() {
+                let value = stack.get(i);
+                let code = match value.to_integer() {
+                    Some(code) => code,
+                    None if value.to_number().is_some() => {
+                        return Err(bad_argument(
+                            ctx,
+                            "char",
+                            i + 1,
+                            "number has no integer representation",
+                        ));
+                    }
+                    None => {
+                        return Err(bad_argument(
+                            ctx,
+                            "char",
+                            i + 1,
+                            &format!("number expected, got {}", value.type_name()),
+                        ));
+                    }
+                };
+                if !(0..=MAX_UTF).contains(&code) {
+                    return Err(bad_argument(ctx, "char", i + 1, "value out of range"));
+                }
+                encode(code as u32, &mut bytes);
+            }
+            stack.replace(ctx, ctx.intern(&bytes));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "codepoint",
+        Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let (s, i, j, lax) = stack.consume::<(String, Option<i64>, Option<i64>, Value)>(ctx)?;
+            let s = s.as_bytes();
+            let posi = u_posrelat(i.unwrap_or(1), s.len());
+            let pose = u_posrelat(j.unwrap_or(posi), s.len());
+            if posi < 1 {
+                return Err(bad_argument(ctx, "codepoint", 2, "out of bounds"));
+            }
+            if pose > s.len() as i64 {
+                return Err(bad_argument(ctx, "codepoint", 3, "out of bounds"));
+            }
+            if posi > pose {
+                return Ok(CallbackReturn::Return);
+            }
+
+            let mut pos = posi as usize - 1;
+            while pos < pose as usize {
+                let (code, len) = decode(&s[pos..], lax.to_bool())
+                    .ok_or_else(|| "invalid UTF-8 code".into_value(ctx))?;
+                stack.push_back(Value::Integer(code.into()));
+                pos += len;
+            }
+            exec.fuel().consume(
+                ((pos + 1 - posi as usize) / UTF8_BYTES_PER_FUEL)
+                    .try_into()
+                    .unwrap_or(i32::MAX),
+            );
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "len",
+        Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let (s, i, j, lax) = stack.consume::<(String, Option<i64>, Option<i64>, Value)>(ctx)?;
+            let s = s.as_bytes();
+            let posi = u_posrelat(i.unwrap_or(1), s.len());
+            let posj = u_posrelat(j.unwrap_or(-1), s.len());
+            if posi < 1 || posi - 1 > s.len() as i64 {
+                return Err(bad_argument(
+                    ctx,
+                    "len",
+                    2,
+                    "initial position out of bounds",
+                ));
+            }
+            if posj > s.len() as i64 {
+                return Err(bad_argument(ctx, "len", 3, "final position out of bounds"));
+            }
+
+            let start = posi as usize - 1;
+            let mut pos = start;
+            let mut n: i64 = 0;
+            while (pos as i64) < posj {
+                match decode(&s[pos..], lax.to_bool()) {
+                    Some((_, len)) => pos += len,
+                    None => {
+                        stack.replace(ctx, (Value::Nil, pos as i64 + 1));
+                        return Ok(CallbackReturn::Return);
+                    }
+                }
+                n += 1;
+            }
+            exec.fuel().consume(
+                ((pos - start) / UTF8_BYTES_PER_FUEL)
+                    .try_into()
+                    .unwrap_or(i32::MAX),
+            );
+            stack.replace(ctx, n);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    utf8.set(
+        ctx,
+        "offset",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (s, mut n, i) = stack.consume::<(String, i64, Option<i64>)>(ctx)?;
+            let s = s.as_bytes();
+            let len = s.len() as i64;
+            let default_i = if n >= 0 { 1 } else { len + 1 };
+            let mut posi = u_posrelat(i.unwrap_or(default_i), s.len()) - 1;
+            if !(0..=len).contains(&posi) {
+                return Err(bad_argument(ctx, "offset", 3, "position out of bounds"));
+            }
+
+            // The byte at `len` is treated as a terminator, which is never a continuation byte.
+            let is_cont = |pos: i64| s.get(pos as usize).map_or(false, |&b| is_continuation(b));
+            if n == 0 {
+                // Find the beginning of the current byte sequence.
+                while posi > 0 && is_cont(posi) {
+                    posi -= 1;
+                }
+            } else {
+                if is_cont(posi) {
+                    return Err("initial position is a continuation byte"
+                        .into_value(ctx)
+                        .into());
+                }
+                if n < 0 {
+                    while n < 0 && posi > 0 {
+                        posi -= 1;
+                        while posi > 0 && is_cont(posi) {
+                            posi -= 1;
+                        }
+                        n += 1;
+                    }
+                } else {
+                    // Do not move for the first character.
+                    n -= 1;
+                    while n > 0 && posi < len {
+                        posi += 1;
+                        while is_cont(posi) {
+                            posi += 1;
+                        }
+                        n -= 1;
+                    }
+                }
+            }
+
+            if n == 0 {
+                stack.replace(ctx, posi + 1);
+            } else {
+                stack.replace(ctx, Value::Nil);
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    let iter_strict = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        iterate_codes(ctx, &mut stack, false)
+    });
+    let iter_lax = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        iterate_codes(ctx, &mut stack, true)
+    });
+
+    utf8.set(
+        ctx,
+        "codes",
+        Callback::from_fn_with(
+            &ctx,
+            (iter_strict, iter_lax),
+            |&(iter_strict, iter_lax), ctx, _, mut stack| {
+                let (s, lax) = stack.consume::<(String, Value)>(ctx)?;
+                if s.as_bytes().first().copied().map_or(false, is_continuation) {
+                    return Err(bad_argument(ctx, "codes", 1, "invalid UTF-8 code"));
+                }
+                let iter = if lax.to_bool() { iter_lax } else { iter_strict };
+                stack.replace(ctx, (iter, s, 0));
+                Ok(CallbackReturn::Return)
+            },
+        ),
+    )
+    .unwrap();
+
+    ctx.set_global("utf8", utf8).unwrap();
+}
+
+// The iterator function returned by `utf8.codes`, called with the string and the position of the
+// previous character.
+fn iterate_codes<'gc>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    lax: bool,
+) -> Result<CallbackReturn<'gc>, Error<'gc>> {
+    let (s, n) = stack.consume::<(String, i64)>(ctx)?;
+    let s = s.as_bytes();
+    let mut n = n.max(0) as usize;
+    // Skip the continuation bytes of the previous character.
+    while n < s.len() && is_continuation(s[n]) {
+        n += 1;
+    }
+    if n < s.len() {
+        match decode(&s[n..], lax) {
+            Some((code, len)) if !s.get(n + len).copied().map_or(false, is_continuation) => {
+                stack.replace(ctx, (n as i64 + 1, i64::from(code)));
+            }
+            _ => return Err("invalid UTF-8 code".into_value(ctx).into()),
+        }
+    }
+    Ok(CallbackReturn::Return)
+}
+
+fn bad_argument<'gc>(ctx: Context<'gc>, function: &str, arg: usize, message: &str) -> Error<'gc> {
+    format!("bad argument #{} to '{}' ({})", arg, function, message)
+        .into_value(ctx)
+        .into()
+}
+
+// Converts a possibly negative position into a position counting from the start of the string.
+fn u_posrelat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+// Decodes the byte sequence at the start of `s`, returning the code point and the length of the
+// sequence.
+//
+// Sequences of up to six bytes, encoding values up to `MAX_UTF`, are accepted. Unless `lax` is
+// set, the code point must also be at most 0x10FFFF and not a surrogate. Overlong encodings are
+// always rejected.
+fn decode(s: &[u8], lax: bool) -> Option<(u32, usize)> {
+    // The smallest value which needs a sequence of each length, to detect overlong encodings.
+    const LIMITS: [u32; 6] = [u32::MAX, 0x80, 0x800, 0x10000, 0x200000, 0x4000000];
+
+    let mut c = *s.first()? as u32;
+    if c < 0x80 {
+        return Some((c, 1));
+    }
+
+    let mut res: u32 = 0;
+    let mut count = 0;
+    while c & 0x40 != 0 {
+        count += 1;
+        if count > 5 {
+            return None;
+        }
+        let cc = *s.get(count)? as u32;
+        if cc & 0xC0 != 0x80 {
+            return None;
+        }
+        res = (res << 6) | (cc & 0x3F);
+        c <<= 1;
+    }
+    res |= (c & 0x7F) << (count * 5);
+    if res as i64 > MAX_UTF || res < LIMITS[count] {
+        return None;
+    }
+    if !lax && (res > 0x10FFFF || (0xD800..=0xDFFF).contains(&res)) {
+        return None;
+    }
+    Some((res, count + 1))
+}
+
+// Appends the UTF-8 encoding of `x`, using sequences of up to six bytes for values past the
+// Unicode range.
+fn encode(mut x: u32, out: &mut Vec<u8>) {
+    if x < 0x80 {
+        out.push(x as u8);
+        return;
+    }
+
+    let mut buf = [0u8; 6];
+    let mut n = 0;
+    // The largest value which fits in the first byte.
+    let mut mfb: u32 = 0x3F;
+    loop {
+        buf[buf.len() - 1 - n] = 0x80 | (x & 0x3F) as u8;
+        n += 1;
+        x >>= 6;
+        mfb >>= 1;
+        if x <= mfb {
+            break;
+        }
+    }
+    buf[buf.len() - 1 - n] = ((!mfb << 1) | x) as u8;
+    out.extend_from_slice(&buf[buf.len() - 1 - n..]);
+}
@@ -1,6 +1,7 @@
 use std::{
     alloc,
-    borrow::Cow,
+    borrow::{Borrow, Cow},
+    cmp::Ordering,
     fmt,
     hash::{BuildHasherDefault, Hash, Hasher},
     io::Write,
@@ -17,7 +18,7 @@ use gc_arena::{
 use hashbrown::{hash_map, raw::RawTable, HashMap};
 use thiserror::Error;
 
-use crate::{Context, Value};
+use crate::{value::NumberDisplay, Context, Value};
 
 // Represents `String` as either a pointer to an external / owned slice pointer or a size prefixed
 // inline array.
@@ -194,7 +195,7 @@ impl<'gc> String<'gc> {
                 Value::Nil => write!(&mut bytes, "nil").unwrap(),
                 Value::Boolean(b) => write!(&mut bytes, "{}", b).unwrap(),
                 Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                Value::Number(n) => write!(&mut bytes, "{}", NumberDisplay(*n)).unwrap(),
                 Value::String(s) => bytes.extend(s.as_bytes()),
                 Value::Table(_) => return Err(BadConcatType { bad_type: "table" }),
                 Value::Function(_) => {
@@ -219,10 +220,17 @@ impl<'gc> String<'gc> {
         self.as_bytes().len().try_into().unwrap()
     }
 
-    pub fn to_str(self) -> Result<&'gc str, Utf8Error> {
+    /// Returns the contents of this string as a `str`, or an error if it is not valid UTF-8.
+    ///
+    /// Lua strings are arbitrary bytes, so this must always be checked.
+    pub fn as_str(self) -> Result<&'gc str, Utf8Error> {
         str::from_utf8(self.as_bytes())
     }
 
+    pub fn to_str(self) -> Result<&'gc str, Utf8Error> {
+        self.as_str()
+    }
+
     pub fn to_str_lossy(self) -> Cow<'gc, str> {
         StdString::from_utf8_lossy(self.as_bytes())
     }
@@ -242,6 +250,13 @@ impl<'gc> AsRef<[u8]> for String<'gc> {
     }
 }
 
+impl<'gc> Borrow<[u8]> for String<'gc> {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+// Strings can be compared against anything that is bytes, without having to intern it first.
 impl<'gc, T> PartialEq<T> for String<'gc>
 where
     T: ?Sized + AsRef<[u8]>,
@@ -251,11 +266,49 @@ where
     }
 }
 
+impl<'gc> PartialEq<String<'gc>> for str {
+    fn eq(&self, other: &String<'gc>) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'gc, 'a> PartialEq<String<'gc>> for &'a str {
+    fn eq(&self, other: &String<'gc>) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'gc> PartialEq<String<'gc>> for [u8] {
+    fn eq(&self, other: &String<'gc>) -> bool {
+        self == other.as_bytes()
+    }
+}
+
+impl<'gc, 'a> PartialEq<String<'gc>> for &'a [u8] {
+    fn eq(&self, other: &String<'gc>) -> bool {
+        *self == other.as_bytes()
+    }
+}
+
 impl<'gc> Eq for String<'gc> {}
 
+impl<'gc> PartialOrd for String<'gc> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'gc> Ord for String<'gc> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+// Hashes the contents of the string, the same as `[u8]` does, so that maps keyed by `String` can be
+// queried by `&[u8]` through `Borrow`. Use `String::stored_hash` to avoid re-hashing the contents.
 impl<'gc> Hash for String<'gc> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_u64(self.stored_hash())
+        self.as_bytes().hash(state)
     }
 }
 
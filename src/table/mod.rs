@@ -1,7 +1,11 @@
 mod raw;
+mod snapshot;
 mod table;
 
+pub(crate) use self::table::ObservedTables;
+
 pub use self::{
     raw::{InvalidTableKey, NextValue, RawTable},
-    table::{Table, TableInner, TableState},
+    snapshot::{SnapshotValue, TableSnapshot},
+    table::{FieldError, Table, TableInner, TableState, WeakMode},
 };
@@ -1,17 +1,23 @@
-use std::{fmt, hash::Hash, i64, mem};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    i64, mem,
+};
 
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
+use gc_arena::{allocator_api::MetricsAlloc, Collect, Collection, Finalization, Gc, Mutation};
 use hashbrown::{hash_map, HashMap};
 use thiserror::Error;
 
-use crate::{Callback, Closure, Function, String, Table, Thread, UserData, Value};
+use crate::{
+    constant::f64_to_i64, Callback, Closure, Function, String, Table, Thread, UserData, Value,
+};
 
 #[derive(Debug, Copy, Clone, Error)]
 pub enum InvalidTableKey {
-    #[error("table key is NaN")]
+    #[error("table index is NaN")]
     IsNaN,
-    #[error("table key is Nil")]
+    #[error("table index is nil")]
     IsNil,
 }
 
@@ -386,6 +392,79 @@ impl<'gc> RawTable<'gc> {
         NextValue::NotFound
     }
 
+    /// Trace the contents of this table, skipping any GC objects held in weak keys or weak values.
+    ///
+    /// Strings are never considered weak and are always traced, matching PUC-Rio Lua.
+    ///
+    /// Any table traced this way *must* later have `RawTable::clear_dead` called on it during
+    /// finalization with the same weak settings, otherwise it may be left holding dangling
+    /// pointers.
+    pub(crate) fn trace_weak(&self, cc: &Collection, weak_keys: bool, weak_values: bool) {
+        for &value in &self.array {
+            if !(weak_values && is_weak_value(value)) {
+                value.trace(cc);
+            }
+        }
+
+        for (key, value) in &self.map {
+            if let Key::Live(key) = key {
+                if !(weak_keys && is_weak_value(key.to_value())) {
+                    key.trace(cc);
+                }
+            }
+            if !(weak_values && is_weak_value(*value)) {
+                value.trace(cc);
+            }
+        }
+    }
+
+    /// Remove every entry whose weak key or weak value is about to be collected.
+    ///
+    /// Removed entries are treated exactly as if they were set to Nil.
+    pub(crate) fn clear_dead(
+        &mut self,
+        fc: &Finalization<'gc>,
+        weak_keys: bool,
+        weak_values: bool,
+    ) {
+        if weak_values {
+            for value in self.array.iter_mut() {
+                if is_dead_value(fc, *value) {
+                    *value = Value::Nil;
+                }
+            }
+        }
+
+        for (key, value) in self.map.iter_mut() {
+            let dead_key = weak_keys
+                && key
+                    .live_key()
+                    .is_some_and(|k| is_dead_value(fc, k.to_value()));
+            if dead_key || (weak_values && is_dead_value(fc, *value)) {
+                *value = Value::Nil;
+            }
+        }
+
+        // Keys cannot be changed through `HashMap::iter_mut`, so kill the keys of every removed
+        // entry in a second pass.
+        unsafe {
+            for bucket in self.map.raw_table_mut().iter() {
+                let (key, value) = bucket.as_mut();
+                if value.is_nil() {
+                    if let Some(dead) = key.kill() {
+                        *key = dead;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The approximate number of bytes allocated for the array and map parts of this table.
+    pub fn allocated_bytes(&self) -> usize {
+        self.array.capacity() * mem::size_of::<Value<'gc>>()
+            + self.map.capacity() * mem::size_of::<(Key<'gc>, Value<'gc>)>()
+    }
+
     pub fn reserve_array(&mut self, additional: usize) {
         self.array.reserve(additional);
     }
@@ -404,7 +483,7 @@ impl<'gc> RawTable<'gc> {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Collect)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Collect)]
 #[collect(no_drop)]
 enum CanonicalKey<'gc> {
     Boolean(bool),
@@ -418,6 +497,25 @@ enum CanonicalKey<'gc> {
     UserData(UserData<'gc>),
 }
 
+// Strings hash by their stored hash rather than by re-hashing their contents, which is what the
+// `Hash` impl for `String` must do to agree with `Borrow<[u8]>`.
+impl<'gc> Hash for CanonicalKey<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            CanonicalKey::Boolean(b) => b.hash(state),
+            CanonicalKey::Integer(i) => i.hash(state),
+            CanonicalKey::Number(n) => n.hash(state),
+            CanonicalKey::String(s) => state.write_u64(s.stored_hash()),
+            CanonicalKey::Table(t) => t.hash(state),
+            CanonicalKey::Closure(c) => c.hash(state),
+            CanonicalKey::Callback(c) => c.hash(state),
+            CanonicalKey::Thread(t) => t.hash(state),
+            CanonicalKey::UserData(u) => u.hash(state),
+        }
+    }
+}
+
 impl<'gc> CanonicalKey<'gc> {
     fn new(value: Value<'gc>) -> Result<Self, InvalidTableKey> {
         Ok(match value {
@@ -527,14 +625,24 @@ impl<'gc> Key<'gc> {
     }
 }
 
-// Returns the closest i64 to a given f64 such that casting the i64 back to an f64 results in an
-// equal value, if such an integer exists.
-fn f64_to_i64(n: f64) -> Option<i64> {
-    let i = n as i64;
-    if i as f64 == n {
-        Some(i)
-    } else {
-        None
+// Returns true for values which are held weakly in weak tables. Strings are values rather than
+// objects in Lua and are never removed from weak tables.
+fn is_weak_value<'gc>(value: Value<'gc>) -> bool {
+    matches!(
+        value,
+        Value::Table(_) | Value::Function(_) | Value::Thread(_) | Value::UserData(_)
+    )
+}
+
+// Returns true if the given value is a GC object that is about to be collected.
+fn is_dead_value<'gc>(fc: &Finalization<'gc>, value: Value<'gc>) -> bool {
+    match value {
+        Value::Table(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::Function(Function::Closure(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Function(Function::Callback(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Thread(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::UserData(u) => Gc::is_dead(fc, u.into_inner()),
+        _ => false,
     }
 }
 
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use gc_arena::Gc;
+
+use crate::{identity::ObjectId, Context, Value};
+
+use super::Table;
+
+/// An immutable copy of the contents of a [`Table`], made with [`Table::snapshot`].
+///
+/// A snapshot owns all of its data and holds no references into the `Lua` instance it was taken
+/// from, so it can be kept, sent to other threads and read while scripts keep running. It is
+/// cheap to clone, and a table which appears several times within a snapshot is only copied once
+/// and shared.
+#[derive(Clone)]
+pub struct TableSnapshot(Arc<SnapshotState>);
+
+struct SnapshotState {
+    id: Option<ObjectId>,
+    entries: Vec<(SnapshotValue, SnapshotValue)>,
+    // Indexes entries by every key which is not a reference type.
+    index: HashMap<IndexKey, usize>,
+}
+
+/// A value copied into a [`TableSnapshot`].
+///
+/// Functions, threads and userdata can't be copied, so only their identity is kept, which is
+/// `None` if identities are hidden by the [`IdentityPolicy`](crate::IdentityPolicy).
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Arc<[u8]>),
+    Table(TableSnapshot),
+    /// A table which contains itself, either directly or through other tables. It is not copied
+    /// again, and refers to the enclosing table with the same identity.
+    Cycle(Option<ObjectId>),
+    Function(Option<ObjectId>),
+    Thread(Option<ObjectId>),
+    UserData(Option<ObjectId>),
+}
+
+static NIL: SnapshotValue = SnapshotValue::Nil;
+
+impl<'gc> Table<'gc> {
+    /// Copy the contents of this table and every table reachable from it into a
+    /// [`TableSnapshot`].
+    ///
+    /// Like [`Table::get`], every access is raw, and metatables are not copied.
+    pub fn snapshot(self, ctx: Context<'gc>) -> TableSnapshot {
+        Snapshotter {
+            ctx,
+            done: HashMap::new(),
+            in_progress: Vec::new(),
+        }
+        .table(self)
+    }
+}
+
+impl TableSnapshot {
+    /// The identity of the table this snapshot was taken from, see `Context::object_id`.
+    pub fn id(&self) -> Option<ObjectId> {
+        self.0.id
+    }
+
+    /// Get the value stored under the given key, or `Nil` if there is none.
+    ///
+    /// Keys which are tables or other reference types can't be looked up, but are still returned
+    /// by [`TableSnapshot::iter`].
+    pub fn get(&self, key: impl Into<SnapshotValue>) -> &SnapshotValue {
+        IndexKey::new(&key.into())
+            .and_then(|key| self.0.index.get(&key))
+            .map(|&i| &self.0.entries[i].1)
+            .unwrap_or(&NIL)
+    }
+
+    /// Returns the length of the sequence starting at `1`, which is a border of the table as
+    /// defined by [`Table::length`].
+    pub fn length(&self) -> i64 {
+        let mut length = 0;
+        while !matches!(self.get(length + 1), SnapshotValue::Nil) {
+            length += 1;
+        }
+        length
+    }
+
+    /// The number of entries in the table.
+    pub fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.entries.is_empty()
+    }
+
+    /// Iterate over the key-value pairs of the table, in the order they were found in the table.
+    pub fn iter(&self) -> impl Iterator<Item = (&SnapshotValue, &SnapshotValue)> {
+        self.0.entries.iter().map(|(k, v)| (k, v))
+    }
+}
+
+impl PartialEq for TableSnapshot {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.entries == other.0.entries
+    }
+}
+
+impl fmt::Debug for TableSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl From<bool> for SnapshotValue {
+    fn from(b: bool) -> Self {
+        SnapshotValue::Boolean(b)
+    }
+}
+
+impl From<i64> for SnapshotValue {
+    fn from(i: i64) -> Self {
+        SnapshotValue::Integer(i)
+    }
+}
+
+impl From<f64> for SnapshotValue {
+    fn from(n: f64) -> Self {
+        SnapshotValue::Number(n)
+    }
+}
+
+impl From<&str> for SnapshotValue {
+    fn from(s: &str) -> Self {
+        SnapshotValue::String(s.as_bytes().into())
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum IndexKey {
+    Boolean(bool),
+    Integer(i64),
+    Number(u64),
+    String(Arc<[u8]>),
+}
+
+impl IndexKey {
+    fn new(key: &SnapshotValue) -> Option<Self> {
+        Some(match key {
+            &SnapshotValue::Boolean(b) => IndexKey::Boolean(b),
+            &SnapshotValue::Integer(i) => IndexKey::Integer(i),
+            // Tables store floats with an integer value as integers, so they are looked up the
+            // same way.
+            &SnapshotValue::Number(n) => match Value::Number(n).to_integer() {
+                Some(i) => IndexKey::Integer(i),
+                None => IndexKey::Number(n.to_bits()),
+            },
+            SnapshotValue::String(s) => IndexKey::String(s.clone()),
+            _ => return None,
+        })
+    }
+}
+
+struct Snapshotter<'gc> {
+    ctx: Context<'gc>,
+    // Tables which have been copied, so that they are shared rather than copied again.
+    done: HashMap<*const (), TableSnapshot>,
+    // Tables which are still being copied, any reference to these is a cycle.
+    in_progress: Vec<*const ()>,
+}
+
+impl<'gc> Snapshotter<'gc> {
+    fn table(&mut self, table: Table<'gc>) -> TableSnapshot {
+        let ptr = Gc::as_ptr(table.into_inner()) as *const ();
+        self.in_progress.push(ptr);
+
+        let mut entries = Vec::new();
+        let mut index = HashMap::new();
+        for (key, value) in table {
+            let key = self.value(key);
+            if let Some(key) = IndexKey::new(&key) {
+                index.insert(key, entries.len());
+            }
+            entries.push((key, self.value(value)));
+        }
+
+        self.in_progress.pop();
+        let snapshot = TableSnapshot(Arc::new(SnapshotState {
+            id: self.ctx.object_id(table.into()),
+            entries,
+            index,
+        }));
+        self.done.insert(ptr, snapshot.clone());
+        snapshot
+    }
+
+    fn value(&mut self, value: Value<'gc>) -> SnapshotValue {
+        match value {
+            Value::Nil => SnapshotValue::Nil,
+            Value::Boolean(b) => SnapshotValue::Boolean(b),
+            Value::Integer(i) => SnapshotValue::Integer(i),
+            Value::Number(n) => SnapshotValue::Number(n),
+            Value::String(s) => SnapshotValue::String(s.as_bytes().into()),
+            Value::Table(t) => {
+                let ptr = Gc::as_ptr(t.into_inner()) as *const ();
+                if let Some(snapshot) = self.done.get(&ptr) {
+                    SnapshotValue::Table(snapshot.clone())
+                } else if self.in_progress.contains(&ptr) {
+                    SnapshotValue::Cycle(self.ctx.object_id(value))
+                } else {
+                    SnapshotValue::Table(self.table(t))
+                }
+            }
+            Value::Function(_) => SnapshotValue::Function(self.ctx.object_id(value)),
+            Value::Thread(_) => SnapshotValue::Thread(self.ctx.object_id(value)),
+            Value::UserData(_) => SnapshotValue::UserData(self.ctx.object_id(value)),
+        }
+    }
+}
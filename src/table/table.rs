@@ -1,11 +1,18 @@
 use std::{
+    fmt,
     hash::{Hash, Hasher},
-    i64, mem,
+    i64, iter, mem,
+    rc::Rc,
+    string::String as StdString,
 };
 
-use gc_arena::{lock::RefLock, Collect, Gc, Mutation};
+use gc_arena::{lock::RefLock, Collect, Collection, Gc, GcWeak, Mutation, Rootable};
+use thiserror::Error;
 
-use crate::{Context, IntoValue, Value};
+use crate::{
+    sanitizer::{self, ArenaTag},
+    Context, FromMultiValue, FromValue, IntoValue, MetaMethod, Singleton, TypeError, Value,
+};
 
 use super::raw::{InvalidTableKey, NextValue, RawTable};
 
@@ -44,6 +51,12 @@ impl<'gc> Table<'gc> {
             RefLock::new(TableState {
                 raw_table,
                 metatable,
+                weak_mode: WeakMode::default(),
+                has_finalizer: false,
+                observer: None,
+                #[cfg(feature = "dirty-tracking")]
+                dirty: None,
+                arena: ArenaTag::new(mc),
             }),
         ))
     }
@@ -56,10 +69,18 @@ impl<'gc> Table<'gc> {
         self.0
     }
 
+    /// Get the value stored in the table under the given key.
+    ///
+    /// This is a raw access which never calls the `__index` metamethod, like the `rawget` builtin.
+    /// Use [`meta_ops::index`](crate::meta_ops::index) to respect metamethods.
     pub fn get<K: IntoValue<'gc>>(self, ctx: Context<'gc>, key: K) -> Value<'gc> {
         self.get_value(key.into_value(ctx))
     }
 
+    /// Store a value in the table under the given key, returning the previous value.
+    ///
+    /// This is a raw access which never calls the `__newindex` metamethod, like the `rawset`
+    /// builtin. Use [`meta_ops::new_index`](crate::meta_ops::new_index) to respect metamethods.
     pub fn set<K: IntoValue<'gc>, V: IntoValue<'gc>>(
         self,
         ctx: Context<'gc>,
@@ -69,17 +90,126 @@ impl<'gc> Table<'gc> {
         self.set_value(&ctx, key.into_value(ctx), value.into_value(ctx))
     }
 
+    /// Get the value stored in the table under the given key, without calling the `__index`
+    /// metamethod.
+    ///
+    /// This is the same as [`Table::get`], for code which wants to make it clear that it skips
+    /// metamethods on purpose.
+    pub fn get_raw<K: IntoValue<'gc>>(self, ctx: Context<'gc>, key: K) -> Value<'gc> {
+        self.get_value(key.into_value(ctx))
+    }
+
+    /// Store a value in the table under the given key without calling the `__newindex`
+    /// metamethod, returning the previous value.
+    ///
+    /// This is the same as [`Table::set`], for code which wants to make it clear that it skips
+    /// metamethods on purpose.
+    pub fn set_raw<K: IntoValue<'gc>, V: IntoValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        key: K,
+        value: V,
+    ) -> Result<Value<'gc>, InvalidTableKey> {
+        self.set_value(&ctx, key.into_value(ctx), value.into_value(ctx))
+    }
+
+    /// A version of [`Table::get`] which converts the value to the requested type.
+    ///
+    /// A missing key is `Nil`, so use an `Option` for values which may not be present.
+    pub fn get_as<K: IntoValue<'gc>, V: FromValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        key: K,
+    ) -> Result<V, TypeError> {
+        V::from_value(ctx, self.get(ctx, key))
+    }
+
+    /// Get a value from nested tables by a path of string keys separated by `.`, so
+    /// `table.get_path(ctx, "a.b.c")` is the same as `table.a.b.c` in Lua.
+    ///
+    /// Like [`Table::get`], every access is raw. If any table along the path is missing, the
+    /// result is converted from `Nil`, and if a value along the path is present but not a table,
+    /// a `TypeError` is returned.
+    pub fn get_path<V: FromValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        path: &str,
+    ) -> Result<V, TypeError> {
+        let mut value = Value::Table(self);
+        for key in path.split('.') {
+            value = match value {
+                Value::Table(table) => table.get(ctx, key),
+                Value::Nil => break,
+                _ => {
+                    return Err(TypeError {
+                        expected: "table",
+                        found: value.type_name(),
+                    })
+                }
+            };
+        }
+        V::from_value(ctx, value)
+    }
+
+    /// Get several string keys at once, converting their values into a tuple, as in
+    /// `options.get_fields::<(f64, f64, Option<String>)>(ctx, ["x", "y", "label"])`.
+    ///
+    /// Like [`Table::get`], every access is raw. If a value can not be converted, the returned
+    /// error names the field it came from.
+    pub fn get_fields<'a, V: FromMultiValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Result<V, FieldError> {
+        let fields: Vec<(&str, Value<'gc>)> = keys
+            .into_iter()
+            .map(|key| (key, self.get(ctx, key)))
+            .collect();
+
+        // Tuples convert their elements in order, so the field that failed is the last one taken.
+        let mut taken = 0;
+        let result = V::from_multi_value(
+            ctx,
+            iter::from_fn(|| {
+                taken += 1;
+                fields.get(taken - 1).map(|&(_, value)| value)
+            }),
+        );
+        result.map_err(|err| FieldError {
+            field: match fields.get(taken.saturating_sub(1)) {
+                Some(&(key, _)) => key.to_owned(),
+                None => format!("#{}", taken),
+            },
+            expected: err.expected,
+            found: err.found,
+        })
+    }
+
+    /// A version of [`Table::get`] which takes an already converted key.
     pub fn get_value(self, key: Value<'gc>) -> Value<'gc> {
         self.0.borrow().raw_table.get(key)
     }
 
+    /// A version of [`Table::set`] which takes an already converted key and value.
     pub fn set_value(
         self,
         mc: &Mutation<'gc>,
         key: Value<'gc>,
         value: Value<'gc>,
     ) -> Result<Value<'gc>, InvalidTableKey> {
-        self.0.borrow_mut(&mc).raw_table.set(key, value)
+        sanitizer::check_value(mc, key, "a table key");
+        sanitizer::check_value(mc, value, "a table");
+        let mut state = self.0.borrow_mut(&mc);
+        let old = state.raw_table.set(key, value)?;
+        if let Some(observer) = &mut state.observer {
+            observer.pending.push((key, value));
+        }
+        #[cfg(feature = "dirty-tracking")]
+        if let Some(dirty) = &mut state.dirty {
+            // The key was just accepted by the table itself, so it is always valid.
+            dirty.set(key, Value::Boolean(true)).unwrap();
+        }
+        Ok(old)
     }
 
     /// Returns a 'border' for this table.
@@ -89,6 +219,9 @@ impl<'gc> Table<'gc> {
     ///
     /// If a table has exactly one border, it is called a 'sequence', and this border is the table's
     /// length.
+    ///
+    /// This never calls the `__len` metamethod, like the `rawlen` builtin. Use
+    /// [`meta_ops::len`](crate::meta_ops::len) to respect metamethods.
     pub fn length(self) -> i64 {
         self.0.borrow().raw_table.length()
     }
@@ -118,13 +251,168 @@ impl<'gc> Table<'gc> {
         self.0.borrow().metatable
     }
 
+    /// Sets the metatable for this table, returning the previous one.
+    ///
+    /// No fields of the new metatable are read, so this never changes whether the table is weak or
+    /// marked for finalization. Use [`Table::apply_metatable`] for the behavior of `setmetatable`.
     pub fn set_metatable(
         self,
         mc: &Mutation<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Option<Table<'gc>> {
+        if let Some(mt) = metatable {
+            sanitizer::check_value(mc, mt.into(), "a table metatable");
+        }
+
         mem::replace(&mut self.0.borrow_mut(mc).metatable, metatable)
     }
+
+    /// Sets the metatable for this table like `setmetatable` does, returning the previous one.
+    ///
+    /// The `__mode` field of the new metatable is read once here to decide whether the keys and /
+    /// or values of this table are weak, later changes to `__mode` have no effect until the
+    /// metatable is set again.
+    ///
+    /// Similarly, the table is only marked for finalization if the new metatable has a `__gc`
+    /// field at the time it is set. A table is only ever finalized once per time it is marked.
+    pub fn apply_metatable(
+        self,
+        ctx: Context<'gc>,
+        metatable: Option<Table<'gc>>,
+    ) -> Option<Table<'gc>> {
+        if let Some(mt) = metatable {
+            sanitizer::check_value(&ctx, mt.into(), "a table metatable");
+        }
+
+        let (weak_mode, has_finalizer) = match metatable {
+            Some(mt) => (
+                WeakMode::from_mode(mt.get(ctx, "__mode")),
+                !mt.get(ctx, MetaMethod::Gc).is_nil(),
+            ),
+            None => (WeakMode::default(), false),
+        };
+
+        let mut state = self.0.borrow_mut(&ctx);
+        if weak_mode.is_weak() && !state.weak_mode.is_weak() {
+            ctx.finalizers().register_weak_table(&ctx, self.0);
+        }
+        state.weak_mode = weak_mode;
+        if has_finalizer && !state.has_finalizer {
+            ctx.finalizers().register_table(&ctx, self.0);
+            state.has_finalizer = true;
+        }
+        mem::replace(&mut state.metatable, metatable)
+    }
+
+    /// Returns which parts of this table's entries are held weakly.
+    pub fn weak_mode(self) -> WeakMode {
+        self.0.borrow().weak_mode
+    }
+
+    /// Set a function to be notified of every change made to this table through
+    /// [`Table::set_value`], which includes all raw and metamethod-respecting writes from Lua.
+    ///
+    /// The observer is not called immediately. Changes are queued with the key and the new value
+    /// (`Nil` for a deleted key) in the order they were made, and are delivered together by
+    /// [`Context::flush_table_changes`], which happens whenever [`Executor::step`] returns. This
+    /// lets the observer run arbitrary Rust code without interrupting the script, and changes the
+    /// observer itself makes are only delivered at the next flush.
+    ///
+    /// Replaces any previous observer, but keeps changes which have not been delivered yet.
+    ///
+    /// [`Executor::step`]: crate::Executor::step
+    pub fn set_observer(
+        self,
+        ctx: Context<'gc>,
+        observer: impl for<'a> Fn(Context<'a>, Table<'a>, Value<'a>, Value<'a>) + 'static,
+    ) {
+        let callback = Rc::new(observer);
+        let mut state = self.0.borrow_mut(&ctx);
+        match &mut state.observer {
+            Some(existing) => existing.callback = callback,
+            None => {
+                state.observer = Some(Observer {
+                    callback,
+                    pending: Vec::new(),
+                });
+                drop(state);
+                ctx.singleton::<Rootable![ObservedTables<'_>]>()
+                    .register(&ctx, self.0);
+            }
+        }
+    }
+
+    /// Remove the observer of this table, discarding any changes which have not been delivered.
+    pub fn clear_observer(self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).observer = None;
+    }
+
+    /// Returns true if this table has an observer set with [`Table::set_observer`].
+    pub fn is_observed(self) -> bool {
+        self.0.borrow().observer.is_some()
+    }
+
+    /// Start or stop recording which keys of this table are written to, for sending only the
+    /// changed parts of a table when synchronizing state.
+    ///
+    /// While enabled, every key written through [`Table::set_value`] is marked dirty, whether it
+    /// was added, changed or deleted, until it is returned by [`Table::drain_dirty`]. Disabling
+    /// tracking forgets every dirty key.
+    #[cfg(feature = "dirty-tracking")]
+    pub fn set_dirty_tracking(self, mc: &Mutation<'gc>, enabled: bool) {
+        let mut state = self.0.borrow_mut(mc);
+        if enabled != state.dirty.is_some() {
+            state.dirty = enabled.then(|| RawTable::new(mc));
+        }
+    }
+
+    /// Returns true if dirty keys are being recorded, see [`Table::set_dirty_tracking`].
+    #[cfg(feature = "dirty-tracking")]
+    pub fn is_dirty_tracking(self) -> bool {
+        self.0.borrow().dirty.is_some()
+    }
+
+    /// Returns every key written to since dirty tracking was enabled or since the last call to
+    /// `drain_dirty`, each only once and in no particular order, and marks them all clean.
+    ///
+    /// The current value of each key can be read with [`Table::get_value`], a `Nil` value means
+    /// that the key was deleted. Returns nothing if dirty tracking is disabled.
+    #[cfg(feature = "dirty-tracking")]
+    pub fn drain_dirty(self, mc: &Mutation<'gc>) -> Vec<Value<'gc>> {
+        let mut state = self.0.borrow_mut(mc);
+        let Some(dirty) = &mut state.dirty else {
+            return Vec::new();
+        };
+        let dirty = mem::replace(dirty, RawTable::new(mc));
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+        while let NextValue::Found { key: next, .. } = dirty.next(key) {
+            keys.push(next);
+            key = next;
+        }
+        keys
+    }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn arena_tag(self) -> ArenaTag {
+        // A table which is being mutated is skipped rather than panicking on the borrow.
+        self.0
+            .try_borrow()
+            .map(|state| state.arena)
+            .unwrap_or_default()
+    }
+}
+
+/// An error converting one of the fields read by [`Table::get_fields`].
+///
+/// A missing field is found as `nil`.
+#[derive(Debug, Clone, Error)]
+#[error("bad field '{field}' (expected {expected}, found {found})")]
+pub struct FieldError {
+    pub field: StdString,
+    pub expected: &'static str,
+    pub found: &'static str,
 }
 
 #[derive(Copy, Clone, Collect)]
@@ -166,9 +454,143 @@ impl<'gc> IntoIterator for Table<'gc> {
     }
 }
 
-#[derive(Debug, Collect)]
-#[collect(no_drop)]
+#[derive(Debug)]
 pub struct TableState<'gc> {
     pub raw_table: RawTable<'gc>,
     pub metatable: Option<Table<'gc>>,
+    // Only ever set through `Table::apply_metatable`, which makes sure that the table is registered
+    // to have its dead entries cleared.
+    weak_mode: WeakMode,
+    // True if this table is registered with `Finalizers` and has not yet been finalized.
+    pub(crate) has_finalizer: bool,
+    // Only ever set through `Table::set_observer`, which registers the table with
+    // `ObservedTables`.
+    observer: Option<Observer<'gc>>,
+    // The keys written since the last `Table::drain_dirty`, if dirty tracking is enabled.
+    #[cfg(feature = "dirty-tracking")]
+    dirty: Option<RawTable<'gc>>,
+    arena: ArenaTag,
+}
+
+unsafe impl<'gc> Collect for TableState<'gc> {
+    fn trace(&self, cc: &Collection) {
+        self.raw_table
+            .trace_weak(cc, self.weak_mode.keys, self.weak_mode.values);
+        self.metatable.trace(cc);
+        if let Some(observer) = &self.observer {
+            // Undelivered changes keep their keys and values alive even in weak tables.
+            observer.pending.trace(cc);
+        }
+        #[cfg(feature = "dirty-tracking")]
+        self.dirty.trace(cc);
+    }
+}
+
+type ObserverCallback = Rc<dyn for<'a> Fn(Context<'a>, Table<'a>, Value<'a>, Value<'a>)>;
+
+struct Observer<'gc> {
+    callback: ObserverCallback,
+    pending: Vec<(Value<'gc>, Value<'gc>)>,
+}
+
+impl<'gc> fmt::Debug for Observer<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observer")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Every table which has had an observer set, held weakly so that observing a table does not keep
+/// it alive.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub(crate) struct ObservedTables<'gc>(Gc<'gc, RefLock<Vec<GcWeak<'gc, TableInner<'gc>>>>>);
+
+impl<'gc> Singleton<'gc> for ObservedTables<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        ObservedTables(Gc::new(&ctx, RefLock::default()))
+    }
+}
+
+impl<'gc> ObservedTables<'gc> {
+    fn register(self, mc: &Mutation<'gc>, table: Gc<'gc, TableInner<'gc>>) {
+        let mut tables = self.0.borrow_mut(mc);
+        let registered = tables
+            .iter()
+            .any(|t| t.upgrade(mc).is_some_and(|t| Gc::ptr_eq(t, table)));
+        if !registered {
+            tables.push(Gc::downgrade(table));
+        }
+    }
+
+    /// Deliver every queued change to the observer of its table.
+    pub(crate) fn flush(self, ctx: Context<'gc>) {
+        if self.0.borrow().is_empty() {
+            return;
+        }
+
+        // Collected tables and tables whose observer was cleared are forgotten.
+        let mut tables = Vec::new();
+        self.0
+            .borrow_mut(&ctx)
+            .retain(|weak| match weak.upgrade(&ctx) {
+                Some(table) if table.borrow().observer.is_some() => {
+                    tables.push(table);
+                    true
+                }
+                _ => false,
+            });
+
+        for table in tables {
+            let (callback, pending) = match &mut table.borrow_mut(&ctx).observer {
+                Some(observer) if !observer.pending.is_empty() => {
+                    (observer.callback.clone(), mem::take(&mut observer.pending))
+                }
+                _ => continue,
+            };
+            for (key, value) in pending {
+                callback(ctx, Table(table), key, value);
+            }
+        }
+    }
+}
+
+impl<'gc> TableState<'gc> {
+    pub fn weak_mode(&self) -> WeakMode {
+        self.weak_mode
+    }
+}
+
+/// Which parts of a table's entries are weak, as set by the `__mode` metatable field.
+///
+/// Entries whose weak key or weak value is collected are removed from the table. Only tables,
+/// functions, threads and userdata are ever considered weak, strings and other values are never
+/// removed.
+///
+/// Weak keys are not ephemerons: the value of an entry is always traced while its key is alive,
+/// even if the value itself refers to the key. Such an entry keeps its key alive and is never
+/// removed, unlike in PUC-Rio Lua.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct WeakMode {
+    pub keys: bool,
+    pub values: bool,
+}
+
+impl WeakMode {
+    /// Interprets the value of a `__mode` field, which may contain 'k' for weak keys and / or 'v'
+    /// for weak values.
+    pub fn from_mode<'gc>(mode: Value<'gc>) -> Self {
+        match mode {
+            Value::String(s) => WeakMode {
+                keys: s.as_bytes().contains(&b'k'),
+                values: s.as_bytes().contains(&b'v'),
+            },
+            _ => WeakMode::default(),
+        }
+    }
+
+    pub fn is_weak(self) -> bool {
+        self.keys || self.values
+    }
 }
@@ -1,18 +1,23 @@
 use std::hash::{Hash, Hasher};
 
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation, Rootable};
 use thiserror::Error;
 
 use crate::{
+    closure::display_chunk_name,
     compiler::{FunctionRef, LineNumber},
-    BadThreadMode, CallbackReturn, Context, Error, FromMultiValue, Fuel, Function, IntoMultiValue,
-    SequencePoll, Stack, String, Thread, ThreadMode, Variadic,
+    stack::StackLimitsSetting,
+    usage::UsageTracker,
+    BadThreadMode, CallbackReturn, Closure, Context, Error, FromMultiValue, Fuel, Function,
+    IntoMultiValue, SequencePoll, Stack, String, Thread, ThreadMode, Variadic,
 };
 
 use super::{
-    thread::{Frame, LuaFrame, ThreadState},
+    thread::{opcode_line_number, Frame, LuaFrame, ThreadState},
+    traceback::{Traceback, TracebackEntry, TracebackFrame},
     vm::run_vm,
+    VMError,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -44,6 +49,17 @@ pub struct ExecutorState<'gc> {
     thread_stack: vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
 }
 
+impl<'gc> ExecutorState<'gc> {
+    // Resets and removes every thread above the main thread, topmost first, so that nothing they
+    // were running is left half finished.
+    fn reset_upper_threads(&mut self, mc: &Mutation<'gc>) {
+        while self.thread_stack.len() > 1 {
+            let thread = self.thread_stack.pop().unwrap();
+            let _ = thread.reset(mc);
+        }
+    }
+}
+
 pub type ExecutorInner<'gc> = RefLock<ExecutorState<'gc>>;
 
 /// The entry-point for the Lua VM.
@@ -118,6 +134,36 @@ impl<'gc> Executor<'gc> {
         Self::run(&ctx, thread)
     }
 
+    /// Returns every thread on the thread stack of this executor, starting with the main thread.
+    ///
+    /// Each thread was resumed by the thread before it and is in `ThreadMode::Waiting` until it
+    /// finishes, except for the last thread which is the one being run.
+    ///
+    /// Returns nothing if the executor is currently running, use `Execution::threads` instead.
+    pub fn threads(self) -> Vec<Thread<'gc>> {
+        match self.0.try_borrow() {
+            Ok(state) => state.thread_stack.to_vec(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the call stack of every thread on the thread stack of this executor, starting with
+    /// the innermost call of the last thread.
+    ///
+    /// Returns an empty traceback if the executor is currently running, use
+    /// `Execution::traceback` instead.
+    pub fn traceback(self) -> Traceback<'gc> {
+        let mut traceback = Traceback::default();
+        if let Ok(state) = self.0.try_borrow() {
+            for &thread in state.thread_stack.iter().rev() {
+                if let Ok(thread_state) = thread.into_inner().try_borrow() {
+                    traceback.push_frames(thread, &thread_state.frames);
+                }
+            }
+        }
+        traceback
+    }
+
     pub fn mode(self) -> ExecutorMode {
         if let Ok(state) = self.0.try_borrow() {
             if state.thread_stack.len() > 1 {
@@ -145,10 +191,15 @@ impl<'gc> Executor<'gc> {
     /// Returns `false` if the method has exhausted its fuel, but there is more work to
     /// do, and returns `true` if no more progress can be made. If `true` is returned, then
     /// `Executor::mode()` will no longer be `ExecutorMode::Normal`.
+    ///
+    /// Before returning, changes to observed tables are delivered with
+    /// `Context::flush_table_changes`.
     pub fn step(self, ctx: Context<'gc>, fuel: &mut Fuel) -> bool {
+        let usage = ctx.singleton::<Rootable![UsageTracker]>();
+        let limits = ctx.singleton::<Rootable![StackLimitsSetting]>().0.get();
         let mut state = self.0.borrow_mut(&ctx);
 
-        loop {
+        let finished = loop {
             let mut top_thread = state.thread_stack.last().copied().unwrap();
             let mut res_thread = None;
             match top_thread.mode() {
@@ -226,13 +277,14 @@ impl<'gc> Executor<'gc> {
                         {
                             top_state.frames.push(Frame::Error(err.into()));
                         } else {
-                            top_state.frames.push(Frame::Yielded);
+                            top_state.frames.push(Frame::Yielded { results: None });
                             thread_stack.pop();
                             thread_stack.push(to_thread);
                         }
                     } else {
-                        top_state.frames.push(Frame::Yielded);
-                        top_state.frames.push(Frame::Result { bottom });
+                        top_state.frames.push(Frame::Yielded {
+                            results: Some(bottom),
+                        });
                     }
                 }
 
@@ -259,8 +311,9 @@ impl<'gc> Executor<'gc> {
 
                 match top_state.frames.pop() {
                     Some(Frame::Callback { bottom, callback }) => {
+                        let fuel_before = fuel.remaining();
                         fuel.consume(Self::FUEL_PER_CALLBACK);
-                        match callback.call(
+                        let ret = callback.call(
                             ctx,
                             Execution {
                                 executor: self,
@@ -268,8 +321,19 @@ impl<'gc> Executor<'gc> {
                                 threads: &state.thread_stack,
                                 upper_frames: &top_state.frames,
                             },
-                            Stack::new(&mut top_state.stack, bottom),
-                        ) {
+                            Stack::new(&mut top_state.stack, bottom).with_limits(limits),
+                        );
+                        // Callbacks are charged to the Lua function which called them.
+                        if let Some(&Frame::Lua { closure, .. }) = top_state.frames.last() {
+                            charge_usage(usage, closure, fuel_before, fuel);
+                        }
+                        let ret = ret.and_then(|ret| {
+                            let total = top_state.stack.len();
+                            let returning = matches!(ret, CallbackReturn::Return);
+                            limits.check(total - bottom, total, returning)?;
+                            Ok(ret)
+                        });
+                        match ret {
                             Ok(CallbackReturn::Return) => {
                                 top_state.return_to(bottom);
                             }
@@ -335,11 +399,18 @@ impl<'gc> Executor<'gc> {
                             threads: &state.thread_stack,
                             upper_frames: &top_state.frames,
                         };
+                        let stack = Stack::new(&mut top_state.stack, bottom).with_limits(limits);
                         let poll = if let Some(err) = pending_error {
-                            sequence.error(ctx, exec, err, Stack::new(&mut top_state.stack, bottom))
+                            sequence.error(ctx, exec, err, stack)
                         } else {
-                            sequence.poll(ctx, exec, Stack::new(&mut top_state.stack, bottom))
+                            sequence.poll(ctx, exec, stack)
                         };
+                        let poll = poll.and_then(|poll| {
+                            let total = top_state.stack.len();
+                            let returning = matches!(poll, SequencePoll::Return);
+                            limits.check(total - bottom, total, returning)?;
+                            Ok(poll)
+                        });
 
                         match poll {
                             Ok(SequencePoll::Pending) => {
@@ -418,20 +489,37 @@ impl<'gc> Executor<'gc> {
                             }
                         }
                     }
-                    Some(frame @ Frame::Lua { .. }) => {
+                    Some(frame @ Frame::Lua { closure, .. }) => {
                         top_state.frames.push(frame);
+                        let fuel_before = fuel.remaining();
+
+                        let max_instructions = match &top_state.hook {
+                            Some(hook) => Self::VM_GRANULARITY.min(hook.remaining()),
+                            None => Self::VM_GRANULARITY,
+                        };
 
                         let lua_frame = LuaFrame {
                             state: top_state,
                             thread: top_thread,
                             fuel,
+                            limits,
                         };
-                        match run_vm(ctx, lua_frame, Self::VM_GRANULARITY) {
+                        let ret = run_vm(ctx, lua_frame, max_instructions);
+                        if let Ok(instructions_run) = ret {
+                            fuel.consume(instructions_run.try_into().unwrap());
+                        }
+                        charge_usage(usage, closure, fuel_before, fuel);
+                        match ret {
                             Err(err) => {
-                                top_state.frames.push(Frame::Error(err.into()));
+                                let err = locate_error(&top_state.frames, err);
+                                top_state.frames.push(Frame::Error(err));
                             }
                             Ok(instructions_run) => {
-                                fuel.consume(instructions_run.try_into().unwrap());
+                                if let Err(err) =
+                                    top_state.run_hook(ctx, top_thread, fuel, instructions_run)
+                                {
+                                    top_state.frames.push(Frame::Error(err));
+                                }
                             }
                         }
                     }
@@ -441,10 +529,17 @@ impl<'gc> Executor<'gc> {
                             .pop()
                             .expect("normal thread must have frame above error")
                         {
-                            Frame::Lua { bottom, .. } => {
-                                top_state.close_upvalues(&ctx, bottom);
-                                top_state.stack.truncate(bottom);
-                                top_state.frames.push(Frame::Error(err));
+                            frame @ Frame::Lua { bottom, .. } => {
+                                // Any to-be-closed variables must be closed before the frame can be
+                                // unwound, which may require calling their metamethods in new
+                                // frames.
+                                top_state.frames.push(frame);
+                                if let Some(err) = top_state.close_with_error(ctx, bottom, err) {
+                                    top_state.frames.pop();
+                                    top_state.close_upvalues(&ctx, bottom);
+                                    top_state.stack.truncate(bottom);
+                                    top_state.frames.push(Frame::Error(err));
+                                }
                             }
                             Frame::Sequence {
                                 bottom,
@@ -470,7 +565,12 @@ impl<'gc> Executor<'gc> {
             if !fuel.should_continue() {
                 break false;
             }
-        }
+        };
+
+        // Observers run once the executor is no longer borrowed, so they may use it freely.
+        drop(state);
+        ctx.flush_table_changes();
+        finished
     }
 
     pub fn take_result<T: FromMultiValue<'gc>>(
@@ -507,6 +607,45 @@ impl<'gc> Executor<'gc> {
         }
     }
 
+    /// A version of [`Executor::take_result`] which pushes the returned (or yielded) values onto
+    /// the back of the given stack, see [`Thread::take_result_into`].
+    pub fn take_result_into(
+        self,
+        ctx: Context<'gc>,
+        stack: &mut Stack<'gc, '_>,
+    ) -> Result<Result<(), Error<'gc>>, BadExecutorMode> {
+        let mode = self.mode();
+        if mode == ExecutorMode::Result {
+            let state = self.0.borrow();
+            Ok(state.thread_stack[0].take_result_into(ctx, stack).unwrap())
+        } else {
+            Err(BadExecutorMode {
+                found: mode,
+                expected: ExecutorMode::Result,
+            })
+        }
+    }
+
+    /// A version of [`Executor::resume`] which moves the resume arguments out of the given stack,
+    /// see [`Thread::resume_from`].
+    pub fn resume_from(
+        self,
+        ctx: Context<'gc>,
+        args: &mut Stack<'gc, '_>,
+    ) -> Result<(), BadExecutorMode> {
+        let mode = self.mode();
+        if mode == ExecutorMode::Suspended {
+            let state = self.0.borrow();
+            state.thread_stack[0].resume_from(ctx, args).unwrap();
+            Ok(())
+        } else {
+            Err(BadExecutorMode {
+                found: mode,
+                expected: ExecutorMode::Suspended,
+            })
+        }
+    }
+
     pub fn resume_err(self, mc: &Mutation<'gc>, error: Error<'gc>) -> Result<(), BadExecutorMode> {
         let mode = self.mode();
         if mode == ExecutorMode::Suspended {
@@ -523,16 +662,25 @@ impl<'gc> Executor<'gc> {
 
     /// Reset this `Executor` entirely, leaving it with a stopped main thread. Equivalent to
     /// creating a new executor with `Executor::new`.
+    ///
+    /// Every thread that was running in the executor is reset, see `Thread::reset`.
     pub fn stop(self, mc: &Mutation<'gc>) {
         let mut state = self.0.borrow_mut(mc);
-        state.thread_stack.truncate(1);
+        state.reset_upper_threads(mc);
         state.thread_stack[0].reset(mc).unwrap();
     }
 
     /// Reset this `Executor` entirely and begins running the given thread. Equivalent to
     /// creating a new executor with `Executor::run`.
+    ///
+    /// Every other thread that was running in the executor is reset, see `Thread::reset`.
     pub fn reset(self, mc: &Mutation<'gc>, thread: Thread<'gc>) {
         let mut state = self.0.borrow_mut(mc);
+        state.reset_upper_threads(mc);
+        let main = state.thread_stack[0];
+        if main != thread {
+            let _ = main.reset(mc);
+        }
         state.thread_stack.clear();
         state.thread_stack.push(thread);
     }
@@ -546,7 +694,7 @@ impl<'gc> Executor<'gc> {
         args: impl IntoMultiValue<'gc>,
     ) {
         let mut state = self.0.borrow_mut(&ctx);
-        state.thread_stack.truncate(1);
+        state.reset_upper_threads(&ctx);
         state.thread_stack[0].reset(&ctx).unwrap();
         state.thread_stack[0].start(ctx, function, args).unwrap();
     }
@@ -592,27 +740,63 @@ impl<'gc, 'a> Execution<'gc, 'a> {
         self.executor
     }
 
+    /// Every thread on the thread stack of the running executor, starting with the main thread and
+    /// ending with the current thread.
+    ///
+    /// Each thread was resumed by the thread before it, so this is the chain of
+    /// `ThreadMode::Waiting` threads leading to the current one.
+    pub fn threads(&self) -> &'a [Thread<'gc>] {
+        self.threads
+    }
+
+    /// Returns the call stack of the running executor, starting with the currently running
+    /// callback or sequence and continuing through every thread which resumed the current one.
+    pub fn traceback(&self) -> Traceback<'gc> {
+        let current = *self.threads.last().unwrap();
+        let mut traceback = Traceback::default();
+        traceback.entries.push(TracebackEntry {
+            thread: current,
+            frame: TracebackFrame::Native,
+        });
+        traceback.push_frames(current, self.upper_frames);
+        // Only the current thread is borrowed while running, every thread below it is waiting.
+        for &thread in self.threads.iter().rev().skip(1) {
+            if let Ok(state) = thread.into_inner().try_borrow() {
+                traceback.push_frames(thread, &state.frames);
+            }
+        }
+        traceback
+    }
+
     /// If the function we are returning to is Lua, returns information about the Lua frame we are
     /// returning to.
     pub fn upper_lua_frame(&self) -> Option<UpperLuaFrame<'gc>> {
-        let Some(Frame::Lua { closure, pc, .. }) = self.upper_frames.last() else {
+        self.lua_frame(1)
+    }
+
+    /// Returns information about the frame `level` calls below the current one in the current
+    /// thread, if it is a Lua frame.
+    ///
+    /// Level 1 is the function we are returning to, level 2 is the function that called it, and so
+    /// on, as with the level argument of the `error` builtin.
+    pub fn lua_frame(&self, level: usize) -> Option<UpperLuaFrame<'gc>> {
+        let index = self.upper_frames.len().checked_sub(level)?;
+        let Some(Frame::Lua { closure, pc, .. }) = self.upper_frames.get(index) else {
             return None;
         };
 
         let proto = closure.prototype();
         // The previously executed instruction for a callback should be the Call opcode.
         let call_opcode = *pc - 1;
+        let current_line = opcode_line_number(&proto, call_opcode);
+        let (source_file, source_line) = proto.source_location(current_line);
 
         Some(UpperLuaFrame {
             chunk_name: proto.chunk_name,
             current_function: proto.reference,
-            current_line: match proto
-                .opcode_line_numbers
-                .binary_search_by_key(&call_opcode, |(opi, _)| *opi)
-            {
-                Ok(i) => proto.opcode_line_numbers[i].1,
-                Err(i) => proto.opcode_line_numbers[i - 1].1,
-            },
+            current_line,
+            source_file,
+            source_line,
         })
     }
 }
@@ -626,4 +810,37 @@ pub struct UpperLuaFrame<'gc> {
     pub chunk_name: String<'gc>,
     pub current_function: FunctionRef<String<'gc>>,
     pub current_line: LineNumber,
+    /// The original source file of the current line, which is the same as `chunk_name` unless
+    /// the chunk was loaded with a source map.
+    pub source_file: String<'gc>,
+    pub source_line: LineNumber,
+}
+
+// Prefixes an error raised by the VM with the source position of the instruction that raised it,
+// like "chunk:42: attempt to ...". The original error can still be downcast from the result.
+fn locate_error<'gc>(frames: &[Frame<'gc>], err: VMError) -> Error<'gc> {
+    let Some(Frame::Lua { closure, pc, .. }) = frames.last() else {
+        return err.into();
+    };
+    let proto = closure.prototype();
+    let current_line = opcode_line_number(&proto, pc.saturating_sub(1));
+    let (source_file, source_line) = proto.source_location(current_line);
+    let location = format!(
+        "{}:{}: {}",
+        display_chunk_name(&source_file.to_str_lossy()),
+        source_line,
+        err
+    );
+    anyhow::Error::from(err).context(location).into()
+}
+
+// Charge the fuel consumed since `fuel_before` to the prototype of the given closure.
+fn charge_usage(usage: &UsageTracker, closure: Closure<'_>, fuel_before: i32, fuel: &Fuel) {
+    if !usage.is_enabled() {
+        return;
+    }
+
+    if let Ok(used) = u64::try_from(fuel_before.saturating_sub(fuel.remaining())) {
+        usage.charge(&closure.prototype(), used);
+    }
 }
@@ -1,5 +1,8 @@
 mod executor;
+mod pool;
+mod scope;
 mod thread;
+mod traceback;
 mod vm;
 
 use thiserror::Error;
@@ -12,7 +15,12 @@ pub use self::{
         BadExecutorMode, CurrentThread, Execution, Executor, ExecutorInner, ExecutorMode,
         UpperLuaFrame,
     },
-    thread::{BadThreadMode, OpenUpValue, Thread, ThreadInner, ThreadMode},
+    pool::ThreadPool,
+    scope::{TaskScope, TaskScopeClosed},
+    thread::{
+        BadThreadMode, HookInfo, InstructionHook, OpenUpValue, Thread, ThreadInner, ThreadMode,
+    },
+    traceback::{Traceback, TracebackEntry, TracebackFrame},
 };
 
 #[derive(Debug, Clone, Error)]
@@ -37,4 +45,8 @@ pub enum VMError {
     BadForLoop(&'static str, &'static str, &'static str),
     #[error("Invalid types in for loop; expected numbers, found {0} and {1}")]
     BadForLoopPrep(&'static str, &'static str),
+    #[error("'for' step is zero")]
+    ForLoopZeroStep,
+    #[error("to-be-closed variable got a non-closable {0} value")]
+    BadToBeClosed(&'static str),
 }
@@ -0,0 +1,86 @@
+use allocator_api2::vec;
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
+
+use crate::{BadThreadMode, Context, Thread};
+
+/// A pool of stopped threads available for re-use.
+///
+/// Creating a new `Thread` requires a new allocation for the thread itself and, as it runs, for
+/// its stack and frames. Scripts which create large numbers of short-lived coroutines can instead
+/// re-use threads that are known to be no longer in use, which keeps the already allocated stack
+/// space.
+///
+/// It is up to the user to ensure that a recycled thread is not referenced anywhere else, since
+/// any such reference will observe the thread being re-used for an unrelated function.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ThreadPool<'gc>(Gc<'gc, RefLock<ThreadPoolState<'gc>>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct ThreadPoolState<'gc> {
+    max_threads: usize,
+    threads: vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
+}
+
+impl<'gc> ThreadPool<'gc> {
+    const DEFAULT_MAX_THREADS: usize = 64;
+
+    pub fn new(mc: &Mutation<'gc>) -> Self {
+        Self(Gc::new(
+            mc,
+            RefLock::new(ThreadPoolState {
+                max_threads: Self::DEFAULT_MAX_THREADS,
+                threads: vec::Vec::new_in(MetricsAlloc::new(mc)),
+            }),
+        ))
+    }
+
+    /// Take a stopped thread from the pool, or create a new one if the pool is empty.
+    pub fn take(self, ctx: Context<'gc>) -> Thread<'gc> {
+        let pooled = self.0.borrow_mut(&ctx).threads.pop();
+        pooled.unwrap_or_else(|| Thread::new(ctx))
+    }
+
+    /// Reset the given thread and return it to the pool.
+    ///
+    /// Any installed instruction hook is removed from the thread. If the pool is already full, the
+    /// thread is reset but not stored.
+    ///
+    /// Fails if the thread is currently `Running`.
+    pub fn recycle(self, mc: &Mutation<'gc>, thread: Thread<'gc>) -> Result<(), BadThreadMode> {
+        thread.reset(mc)?;
+        thread.set_hook(mc, None)?;
+        let mut state = self.0.borrow_mut(mc);
+        if state.threads.len() < state.max_threads {
+            state.threads.push(thread);
+        }
+        Ok(())
+    }
+
+    /// The number of threads currently available in the pool.
+    pub fn len(self) -> usize {
+        self.0.borrow().threads.len()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0.borrow().threads.is_empty()
+    }
+
+    pub fn max_threads(self) -> usize {
+        self.0.borrow().max_threads
+    }
+
+    /// Set the maximum number of threads held by the pool, dropping any held threads past this
+    /// limit.
+    pub fn set_max_threads(self, mc: &Mutation<'gc>, max_threads: usize) {
+        let mut state = self.0.borrow_mut(mc);
+        state.max_threads = max_threads;
+        state.threads.truncate(max_threads);
+    }
+
+    /// Remove all threads from the pool.
+    pub fn clear(self, mc: &Mutation<'gc>) {
+        self.0.borrow_mut(mc).threads.clear();
+    }
+}
@@ -0,0 +1,186 @@
+use std::mem;
+
+use allocator_api2::vec;
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
+use thiserror::Error;
+
+use crate::{
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Function, Sequence,
+    SequencePoll, Stack, Thread, ThreadMode,
+};
+
+#[derive(Debug, Copy, Clone, Error)]
+#[error("task scope is closed")]
+pub struct TaskScopeClosed;
+
+/// A set of child tasks which cannot outlive the code that opened the scope.
+///
+/// Each task is a separate thread started with [`TaskScope::spawn`]. The sequence returned by
+/// [`TaskScope::join`] runs every task in turn on the executor that polls it, resuming each task
+/// until it yields or returns before moving on to the next one, so a task can yield to let its
+/// siblings run. The join finishes once every task has returned.
+///
+/// If any task raises an error, every remaining task is cancelled and the join fails with that
+/// error. The same happens if the thread running the join is reset before it finishes, such as
+/// when its `Executor` is stopped. Once a scope has been joined or cancelled it is closed, and no
+/// more tasks can be spawned into it, so no task started in the scope can be left running in the
+/// background.
+///
+/// From an async sequence, a scope can be awaited by calling the function returned by
+/// [`TaskScope::join_callback`]. A host can run the same function with an `Executor`.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct TaskScope<'gc>(Gc<'gc, RefLock<TaskScopeState<'gc>>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct TaskScopeState<'gc> {
+    tasks: vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
+    closed: bool,
+}
+
+impl<'gc> TaskScope<'gc> {
+    pub fn new(mc: &Mutation<'gc>) -> Self {
+        Self(Gc::new(
+            mc,
+            RefLock::new(TaskScopeState {
+                tasks: vec::Vec::new_in(MetricsAlloc::new(mc)),
+                closed: false,
+            }),
+        ))
+    }
+
+    /// Start a new task which calls `function` with no arguments, returning the task's thread.
+    ///
+    /// The task does not run until the scope is joined. Tasks may be spawned while the scope is
+    /// being joined, including by other tasks in the same scope.
+    pub fn spawn(
+        self,
+        ctx: Context<'gc>,
+        function: Function<'gc>,
+    ) -> Result<Thread<'gc>, TaskScopeClosed> {
+        let mut state = self.0.borrow_mut(&ctx);
+        if state.closed {
+            return Err(TaskScopeClosed);
+        }
+        let thread = ctx.new_thread();
+        thread.start_suspended(&ctx, function).unwrap();
+        state.tasks.push(thread);
+        Ok(thread)
+    }
+
+    /// Stop every unfinished task and close the scope.
+    ///
+    /// A task which is currently running, because it called this method itself, is removed from
+    /// the scope but is not stopped.
+    pub fn cancel(self, mc: &Mutation<'gc>) {
+        // Resetting a task cancels any join of this scope that the task is running, so the scope
+        // must not be borrowed while the tasks are reset.
+        let tasks = {
+            let mut state = self.0.borrow_mut(mc);
+            state.closed = true;
+            mem::replace(&mut state.tasks, vec::Vec::new_in(MetricsAlloc::new(mc)))
+        };
+        for task in tasks {
+            let _ = task.reset(mc);
+        }
+    }
+
+    /// The number of tasks which have not yet finished.
+    pub fn len(self) -> usize {
+        self.0
+            .borrow()
+            .tasks
+            .iter()
+            .filter(|t| t.mode() != ThreadMode::Stopped)
+            .count()
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_closed(self) -> bool {
+        self.0.borrow().closed
+    }
+
+    /// Returns a sequence which runs every task in the scope to completion and then closes it,
+    /// see the [type docs](TaskScope).
+    ///
+    /// Values yielded or returned by tasks are discarded, and the sequence returns no values.
+    pub fn join(self, mc: &Mutation<'gc>) -> BoxSequence<'gc> {
+        BoxSequence::new(
+            mc,
+            Join {
+                scope: self,
+                next: 0,
+            },
+        )
+    }
+
+    /// Returns a callback which joins the scope when called, see [`TaskScope::join`].
+    pub fn join_callback(self, mc: &Mutation<'gc>) -> Callback<'gc> {
+        Callback::from_fn_with(mc, self, |&scope, ctx, _, _| {
+            Ok(CallbackReturn::Sequence(scope.join(&ctx)))
+        })
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Join<'gc> {
+    scope: TaskScope<'gc>,
+    // The index of the task to resume next.
+    next: usize,
+}
+
+impl<'gc> Sequence<'gc> for Join<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.clear();
+
+        let scope = self.scope;
+        let mut state = scope.0.borrow_mut(&ctx);
+        let mut index = 0;
+        let mut next = self.next;
+        state.tasks.retain(|task| {
+            let finished = task.mode() == ThreadMode::Stopped;
+            if finished && index < self.next {
+                next -= 1;
+            }
+            index += 1;
+            !finished
+        });
+
+        if state.tasks.is_empty() {
+            state.closed = true;
+            return Ok(SequencePoll::Return);
+        }
+
+        if next >= state.tasks.len() {
+            next = 0;
+        }
+        let thread = state.tasks[next];
+        self.next = next + 1;
+        Ok(SequencePoll::Resume { bottom: 0, thread })
+    }
+
+    fn error(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.scope.cancel(&ctx);
+        Err(error)
+    }
+
+    fn cancel(&mut self, mc: &Mutation<'gc>) {
+        self.scope.cancel(mc);
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
     cell::RefMut,
+    fmt,
     hash::{Hash, Hasher},
+    mem,
 };
 
 use allocator_api2::vec;
@@ -10,11 +12,15 @@ use gc_arena::{
 use thiserror::Error;
 
 use crate::{
-    closure::{UpValue, UpValueState},
+    closure::{FunctionPrototype, UpValue, UpValueState},
+    compiler::{FunctionRef, LineNumber},
+    heap::PathStep,
     meta_ops,
+    sanitizer::{self, ArenaTag},
     types::{RegisterIndex, VarCount},
-    BoxSequence, Callback, Closure, Context, Error, FromMultiValue, Fuel, Function, IntoMultiValue,
-    String, Table, UserData, VMError, Value,
+    BoxSequence, Callback, Closure, Context, Error, Execution, FromMultiValue, Fuel, Function,
+    IntoMultiValue, MetaMethod, Sequence, SequencePoll, Stack, StackLimits, String, Table,
+    UserData, VMError, Value, Variadic,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -45,6 +51,73 @@ pub struct BadThreadMode {
     pub expected: Option<ThreadMode>,
 }
 
+/// Information about the currently executing Lua frame, passed to a thread's instruction hook.
+#[derive(Debug, Copy, Clone)]
+pub struct HookInfo<'gc> {
+    /// The thread that the hook is installed on.
+    pub thread: Thread<'gc>,
+    pub chunk_name: String<'gc>,
+    pub current_function: FunctionRef<String<'gc>>,
+    /// The line number of the most recently executed instruction.
+    pub current_line: LineNumber,
+    /// The original source file of the most recently executed instruction.
+    ///
+    /// This is the same as `chunk_name` unless the chunk was loaded with a source map.
+    pub source_file: String<'gc>,
+    /// The line of the most recently executed instruction within `source_file`.
+    pub source_line: LineNumber,
+    /// The index of the next instruction to be executed in the current function.
+    pub pc: usize,
+}
+
+/// A Rust function that is called periodically as a thread executes VM instructions.
+///
+/// Installed with [`Thread::set_hook`]. The hook is called once every `interval` VM instructions
+/// with information about the running Lua frame. If the interval elapses while the thread is not
+/// running Lua code, the hook is delayed until the thread next returns to a Lua frame. If the hook
+/// returns an error, the error is raised in the thread at the point where it was interrupted.
+pub struct InstructionHook {
+    interval: u32,
+    remaining: u32,
+    hook: Box<dyn for<'gc> FnMut(Context<'gc>, &mut Fuel, HookInfo<'gc>) -> Result<(), Error<'gc>>>,
+}
+
+impl fmt::Debug for InstructionHook {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("InstructionHook")
+            .field("interval", &self.interval)
+            .field("remaining", &self.remaining)
+            .finish_non_exhaustive()
+    }
+}
+
+impl InstructionHook {
+    /// Create a new hook that is called every `interval` VM instructions.
+    ///
+    /// An `interval` of zero is treated as an interval of one.
+    pub fn new<F>(interval: u32, hook: F) -> Self
+    where
+        F: 'static
+            + for<'gc> FnMut(Context<'gc>, &mut Fuel, HookInfo<'gc>) -> Result<(), Error<'gc>>,
+    {
+        let interval = interval.max(1);
+        Self {
+            interval,
+            remaining: interval,
+            hook: Box::new(hook),
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// The number of VM instructions that may run before the hook is next called.
+    pub(super) fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
 pub type ThreadInner<'gc> = RefLock<ThreadState<'gc>>;
 
 #[derive(Debug, Clone, Copy, Collect)]
@@ -73,6 +146,9 @@ impl<'gc> Thread<'gc> {
                 frames: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
                 stack: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
                 open_upvalues: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
+                to_be_closed: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
+                hook: None,
+                arena: ArenaTag::new(&ctx),
             }),
         );
         ctx.finalizers().register_thread(&ctx, p);
@@ -87,6 +163,14 @@ impl<'gc> Thread<'gc> {
         self.0
     }
 
+    #[cfg(debug_assertions)]
+    pub(crate) fn arena_tag(self) -> ArenaTag {
+        self.0
+            .try_borrow()
+            .map(|state| state.arena)
+            .unwrap_or_default()
+    }
+
     pub fn mode(self) -> ThreadMode {
         match self.0.try_borrow() {
             Ok(state) => state.mode(),
@@ -103,7 +187,11 @@ impl<'gc> Thread<'gc> {
     ) -> Result<(), BadThreadMode> {
         let mut state = self.check_mode(&ctx, ThreadMode::Stopped)?;
         assert!(state.stack.is_empty());
+        sanitizer::check_value(&ctx, function.into(), "a thread");
         state.stack.extend(args.into_multi_value(ctx));
+        for &arg in state.stack.iter() {
+            sanitizer::check_value(&ctx, arg, "a thread");
+        }
         state.push_call(0, function);
         Ok(())
     }
@@ -115,6 +203,7 @@ impl<'gc> Thread<'gc> {
         function: Function<'gc>,
     ) -> Result<(), BadThreadMode> {
         let mut state = self.check_mode(mc, ThreadMode::Stopped)?;
+        sanitizer::check_value(mc, function.into(), "a thread");
         state.frames.push(Frame::Start(function));
         Ok(())
     }
@@ -141,13 +230,16 @@ impl<'gc> Thread<'gc> {
 
         let bottom = state.stack.len();
         state.stack.extend(args.into_multi_value(ctx));
+        for &arg in &state.stack[bottom..] {
+            sanitizer::check_value(&ctx, arg, "a thread");
+        }
 
         match state.frames.pop().expect("no frame to resume") {
             Frame::Start(function) => {
                 assert!(bottom == 0 && state.open_upvalues.is_empty() && state.frames.is_empty());
                 state.push_call(0, function);
             }
-            Frame::Yielded => {
+            Frame::Yielded { results: None } => {
                 state.return_to(bottom);
             }
             _ => panic!("top frame not a suspended thread"),
@@ -155,12 +247,36 @@ impl<'gc> Thread<'gc> {
         Ok(())
     }
 
+    /// A version of [`Thread::resume`] which moves the resume arguments out of the given stack,
+    /// leaving it empty.
+    ///
+    /// The arguments are moved directly onto the stack of this thread, so resuming a thread with
+    /// values that are already on a stack never allocates.
+    pub fn resume_from(
+        self,
+        ctx: Context<'gc>,
+        args: &mut Stack<'gc, '_>,
+    ) -> Result<(), BadThreadMode> {
+        self.resume(ctx, Variadic(args.drain(..)))
+    }
+
+    /// A version of [`Thread::take_result`] which pushes the returned (or yielded) values onto the
+    /// back of the given stack rather than converting them.
+    pub fn take_result_into(
+        self,
+        ctx: Context<'gc>,
+        stack: &mut Stack<'gc, '_>,
+    ) -> Result<Result<(), Error<'gc>>, BadThreadMode> {
+        let mut state = self.check_mode(&ctx, ThreadMode::Result)?;
+        Ok(state.take_result().map(|vals| stack.extend(vals)))
+    }
+
     /// If the thread is in `Suspended` mode, cause an error wherever the thread was suspended.
     pub fn resume_err(self, mc: &Mutation<'gc>, error: Error<'gc>) -> Result<(), BadThreadMode> {
         let mut state = self.check_mode(mc, ThreadMode::Suspended)?;
         assert!(matches!(
             state.frames.pop(),
-            Some(Frame::Start(_) | Frame::Yielded)
+            Some(Frame::Start(_) | Frame::Yielded { results: None })
         ));
         state.frames.push(Frame::Error(error));
         Ok(())
@@ -168,6 +284,9 @@ impl<'gc> Thread<'gc> {
 
     /// If this thread is in any other mode than `Running`, reset the thread completely and restore
     /// it to the `Stopped` state.
+    ///
+    /// Every unfinished [`Sequence`](crate::Sequence) in the thread is cancelled with
+    /// `Sequence::cancel`.
     pub fn reset(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
         match self.0.try_borrow_mut(mc) {
             Ok(mut state) => {
@@ -181,6 +300,64 @@ impl<'gc> Thread<'gc> {
         }
     }
 
+    /// Install an [`InstructionHook`] on this thread, replacing any previously installed hook.
+    ///
+    /// The hook is called from within `Executor::step` as this thread runs Lua code. It is kept
+    /// across calls to `Thread::reset`.
+    ///
+    /// Fails if this thread is currently `Running`.
+    pub fn set_hook(
+        self,
+        mc: &Mutation<'gc>,
+        hook: Option<InstructionHook>,
+    ) -> Result<(), BadThreadMode> {
+        match self.0.try_borrow_mut(mc) {
+            Ok(mut state) => {
+                state.hook = hook;
+                Ok(())
+            }
+            Err(_) => Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            }),
+        }
+    }
+
+    /// Returns true if this thread has an installed [`InstructionHook`].
+    ///
+    /// A thread that is currently `Running` is reported as having no hook.
+    pub fn has_hook(self) -> bool {
+        match self.0.try_borrow() {
+            Ok(state) => state.hook.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Reset this thread completely and start a new function with the given arguments. Equivalent
+    /// to calling `Thread::reset` followed by `Thread::start`.
+    ///
+    /// Re-using a thread in this way keeps the thread's already allocated stack space.
+    pub fn restart(
+        self,
+        ctx: Context<'gc>,
+        function: Function<'gc>,
+        args: impl IntoMultiValue<'gc>,
+    ) -> Result<(), BadThreadMode> {
+        self.reset(&ctx)?;
+        self.start(ctx, function, args)
+    }
+
+    /// Reset this thread completely and start a new suspended function. Equivalent to calling
+    /// `Thread::reset` followed by `Thread::start_suspended`.
+    pub fn restart_suspended(
+        self,
+        mc: &Mutation<'gc>,
+        function: Function<'gc>,
+    ) -> Result<(), BadThreadMode> {
+        self.reset(mc)?;
+        self.start_suspended(mc, function)
+    }
+
     /// For each open upvalue pointing to this thread, if the upvalue itself is live, then resurrect
     /// the actual value that it is pointing to.
     ///
@@ -202,6 +379,33 @@ impl<'gc> Thread<'gc> {
         Ok(())
     }
 
+    /// Calls `visit` with every value directly referenced by this thread (the values on its stack
+    /// and the functions of its frames) and returns the approximate number of bytes allocated for
+    /// it.
+    ///
+    /// A thread which is currently running cannot be inspected, and references no values.
+    pub(crate) fn visit_heap(self, mut visit: impl FnMut(PathStep, Value<'gc>)) -> usize {
+        let mut bytes = mem::size_of::<ThreadInner<'gc>>();
+        if let Ok(state) = self.0.try_borrow() {
+            for (i, frame) in state.frames.iter().enumerate() {
+                match *frame {
+                    Frame::Lua { closure, .. } => visit(PathStep::Frame(i), closure.into()),
+                    Frame::Start(function) => visit(PathStep::Frame(i), function.into()),
+                    Frame::Callback { callback, .. } => visit(PathStep::Frame(i), callback.into()),
+                    _ => {}
+                }
+            }
+            for (i, &value) in state.stack.iter().enumerate() {
+                visit(PathStep::Stack(i), value);
+            }
+            bytes += state.frames.capacity() * mem::size_of::<Frame<'gc>>()
+                + state.stack.capacity() * mem::size_of::<Value<'gc>>()
+                + state.open_upvalues.capacity() * mem::size_of::<UpValue<'gc>>()
+                + state.to_be_closed.capacity() * mem::size_of::<usize>();
+        }
+        bytes
+    }
+
     fn check_mode(
         &self,
         mc: &Mutation<'gc>,
@@ -305,9 +509,11 @@ pub(super) enum Frame<'gc> {
         bottom: usize,
         callback: Callback<'gc>,
     },
-    /// Thread has yielded and is waiting resume. Must be the top frame of the stack or immediately
-    /// below a Result frame.
-    Yielded,
+    /// Thread has yielded and is waiting resume. Must be the top frame of the stack.
+    ///
+    /// Until they are taken, the yielded values are on the stack starting at `results`. Keeping
+    /// them in the same frame means that a yield and resume only ever push and pop this one frame.
+    Yielded { results: Option<usize> },
     /// We are waiting on an upper thread to finish. Must be the top frame of the stack.
     WaitThread,
     /// Results are waiting to be taken. Must be the top frame of the stack.
@@ -322,22 +528,31 @@ pub struct ThreadState<'gc> {
     pub(super) frames: vec::Vec<Frame<'gc>, MetricsAlloc<'gc>>,
     pub(super) stack: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     pub(super) open_upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    // Stack indexes of all to-be-closed variables in the active Lua frames, in ascending order.
+    pub(super) to_be_closed: vec::Vec<usize, MetricsAlloc<'gc>>,
+    #[collect(require_static)]
+    pub(super) hook: Option<InstructionHook>,
+    arena: ArenaTag,
 }
 
 impl<'gc> ThreadState<'gc> {
     pub(super) fn mode(&self) -> ThreadMode {
         match self.frames.last() {
             None => {
-                debug_assert!(self.stack.is_empty() && self.open_upvalues.is_empty());
+                debug_assert!(
+                    self.stack.is_empty()
+                        && self.open_upvalues.is_empty()
+                        && self.to_be_closed.is_empty()
+                );
                 ThreadMode::Stopped
             }
             Some(frame) => match frame {
                 Frame::Lua { .. } | Frame::Callback { .. } | Frame::Sequence { .. } => {
                     ThreadMode::Normal
                 }
-                Frame::Start(_) | Frame::Yielded => ThreadMode::Suspended,
+                Frame::Start(_) | Frame::Yielded { results: None } => ThreadMode::Suspended,
                 Frame::WaitThread => ThreadMode::Waiting,
-                Frame::Result { .. } => ThreadMode::Result,
+                Frame::Yielded { results: Some(_) } | Frame::Result { .. } => ThreadMode::Result,
                 Frame::Error(_) => {
                     if self.frames.len() == 1 {
                         ThreadMode::Result
@@ -357,6 +572,7 @@ impl<'gc> ThreadState<'gc> {
         match function {
             Function::Closure(closure) => {
                 let proto = closure.prototype();
+                proto.counters.record_invocation();
                 let fixed_params = proto.fixed_params as usize;
                 let stack_size = proto.stack_size as usize;
                 let given_params = self.stack.len() - bottom;
@@ -423,8 +639,11 @@ impl<'gc> ThreadState<'gc> {
                     Some(LuaReturn::Meta(meta_ret)) => {
                         let meta_val = self.stack.get(bottom).copied().unwrap_or_default();
                         self.stack.truncate(bottom);
-                        self.stack.resize(*base + *stack_size, Value::Nil);
-                        *is_variable = false;
+                        // Only `__close` calls may be made with a variable stack, and they expect
+                        // no return value.
+                        if !*is_variable {
+                            self.stack.resize(*base + *stack_size, Value::Nil);
+                        }
                         match meta_ret {
                             MetaReturn::None => {}
                             MetaReturn::Register(reg) => {
@@ -450,6 +669,12 @@ impl<'gc> ThreadState<'gc> {
     pub(super) fn take_result(
         &mut self,
     ) -> Result<impl Iterator<Item = Value<'gc>> + '_, Error<'gc>> {
+        // Taking yielded values leaves the thread suspended in the same frame.
+        if let Some(Frame::Yielded { results }) = self.frames.last_mut() {
+            let bottom = results.take().expect("no yielded values to take");
+            return Ok(self.stack.drain(bottom..));
+        }
+
         match self.frames.pop() {
             Some(Frame::Result { bottom }) => Ok(self.stack.drain(bottom..)),
             Some(Frame::Error(err)) => {
@@ -488,6 +713,86 @@ impl<'gc> ThreadState<'gc> {
         self.open_upvalues.truncate(start);
     }
 
+    /// Call the `__close` metamethod of the topmost to-be-closed variable at or above the stack
+    /// index `bottom` with the given error, which is being unwound through the current top frame.
+    ///
+    /// When the metamethod returns, the error will continue to be unwound (closing the next
+    /// variable, if any). If the metamethod errors, its error replaces the original one. Returns
+    /// the error back if there are no variables left to close.
+    pub(super) fn close_with_error(
+        &mut self,
+        ctx: Context<'gc>,
+        bottom: usize,
+        mut error: Error<'gc>,
+    ) -> Option<Error<'gc>> {
+        while let Some(&ind) = self.to_be_closed.last() {
+            if ind < bottom {
+                break;
+            }
+            self.to_be_closed.pop();
+
+            match meta_ops::close(ctx, self.stack[ind], error.to_value(ctx)) {
+                Ok(None) => {}
+                Ok(Some(call)) => {
+                    let top = self.stack.len();
+                    self.frames.push(Frame::Sequence {
+                        bottom: top,
+                        sequence: BoxSequence::new(&ctx, ResumeUnwind(error)),
+                        pending_error: None,
+                    });
+                    self.stack.extend(call.args);
+                    self.push_call(top, call.function);
+                    return None;
+                }
+                Err(err) => error = err.into(),
+            }
+        }
+
+        Some(error)
+    }
+
+    /// Count `instructions_run` VM instructions against the installed hook (if any), calling it if
+    /// its interval has elapsed.
+    pub(super) fn run_hook(
+        &mut self,
+        ctx: Context<'gc>,
+        thread: Thread<'gc>,
+        fuel: &mut Fuel,
+        instructions_run: u32,
+    ) -> Result<(), Error<'gc>> {
+        let Some(hook) = &mut self.hook else {
+            return Ok(());
+        };
+
+        hook.remaining = hook.remaining.saturating_sub(instructions_run);
+        if hook.remaining > 0 {
+            return Ok(());
+        }
+
+        // The instructions that were run may have pushed a non-Lua frame. Errors cannot be raised
+        // through callback frames that have not been called yet, so we wait for the next time a
+        // Lua frame is at the top of the stack.
+        let Some(Frame::Lua { closure, pc, .. }) = self.frames.last() else {
+            return Ok(());
+        };
+        hook.remaining = hook.interval;
+
+        let proto = closure.prototype();
+        let current_line = opcode_line_number(&proto, pc.saturating_sub(1));
+        let (source_file, source_line) = proto.source_location(current_line);
+        let info = HookInfo {
+            thread,
+            chunk_name: proto.chunk_name,
+            current_function: proto.reference,
+            current_line,
+            source_file,
+            source_line,
+            pc: *pc,
+        };
+
+        (hook.hook)(ctx, fuel, info)
+    }
+
     fn resurrect_live_upvalues(&self, fc: &Finalization<'gc>) {
         for &upval in &self.open_upvalues {
             if !Gc::is_dead(fc, UpValue::into_inner(upval)) {
@@ -514,17 +819,52 @@ impl<'gc> ThreadState<'gc> {
     }
 
     fn reset(&mut self, mc: &Mutation<'gc>) {
+        for frame in self.frames.iter_mut().rev() {
+            if let Frame::Sequence { sequence, .. } = frame {
+                sequence.cancel(mc);
+            }
+        }
         self.close_upvalues(mc, 0);
         assert!(self.open_upvalues.is_empty());
+        self.to_be_closed.clear();
         self.stack.clear();
         self.frames.clear();
     }
 }
 
+// Sits above a Lua frame while a `__close` metamethod is called during error unwinding, and resumes
+// unwinding the error once the metamethod returns.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct ResumeUnwind<'gc>(Error<'gc>);
+
+impl<'gc> Sequence<'gc> for ResumeUnwind<'gc> {
+    fn poll(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        Err(self.0.clone())
+    }
+}
+
+/// The result of attempting to call a function as an intrinsic.
+pub(super) enum IntrinsicCall {
+    /// The called function is not an intrinsic, and no action was taken.
+    NotIntrinsic,
+    /// The intrinsic was called and its results were placed as though the function had returned.
+    Returned,
+    /// The intrinsic was called and errored, and an error frame has been pushed.
+    Errored,
+}
+
 pub(super) struct LuaFrame<'gc, 'a> {
     pub(super) thread: Thread<'gc>,
     pub(super) state: &'a mut ThreadState<'gc>,
     pub(super) fuel: &'a mut Fuel,
+    // Checked after every intrinsic call, like the `Executor` checks callbacks.
+    pub(super) limits: StackLimits,
 }
 
 impl<'gc, 'a> LuaFrame<'gc, 'a> {
@@ -658,7 +998,7 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
                 start = inc;
                 table
                     .set_value(mc, inc.into(), self.state.stack[table_ind + 2 + i])
-                    .unwrap();
+                    .expect("integer keys are always valid table keys");
             } else {
                 break;
             }
@@ -719,6 +1059,84 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         Ok(())
     }
 
+    /// If the function at the given register is an intrinsic callback, call it directly without
+    /// pushing a new frame. On return, results will be placed starting at the function register,
+    /// exactly as with `LuaFrame::call_function`.
+    pub(super) fn call_intrinsic(
+        &mut self,
+        ctx: Context<'gc>,
+        func: RegisterIndex,
+        args: VarCount,
+        returns: VarCount,
+    ) -> Result<IntrinsicCall, VMError> {
+        let Some(Frame::Lua {
+            expected_return,
+            is_variable,
+            base,
+            ..
+        }) = self.state.frames.last_mut()
+        else {
+            panic!("top frame is not lua frame");
+        };
+
+        let function_index = *base + func.0 as usize;
+        let Value::Function(Function::Callback(callback)) = self.state.stack[function_index] else {
+            return Ok(IntrinsicCall::NotIntrinsic);
+        };
+        let Some(intrinsic) = callback.intrinsic() else {
+            return Ok(IntrinsicCall::NotIntrinsic);
+        };
+
+        if *is_variable != args.is_variable() {
+            return Err(VMError::ExpectedVariableStack(args.is_variable()));
+        }
+
+        self.fuel.consume(Self::FUEL_PER_CALL);
+
+        let arg_count = args
+            .to_constant()
+            .map(|c| c as usize)
+            .unwrap_or(self.state.stack.len() - function_index - 1);
+
+        *expected_return = Some(LuaReturn::Normal(returns));
+
+        self.fuel
+            .consume(count_fuel(Self::FUEL_PER_ITEM, arg_count));
+
+        self.state.stack.remove(function_index);
+        self.state.stack.truncate(function_index + arg_count);
+
+        let ret = intrinsic(
+            ctx,
+            self.fuel,
+            Stack::new(&mut self.state.stack, function_index).with_limits(self.limits),
+        )
+        .and_then(|()| {
+            let total = self.state.stack.len();
+            self.limits.check(total - function_index, total, true)?;
+            Ok(())
+        });
+        match ret {
+            Ok(()) => {
+                self.state.return_to(function_index);
+                Ok(IntrinsicCall::Returned)
+            }
+            Err(err) => {
+                // Restore the frame to a state that can be unwound, exactly as if the intrinsic
+                // had been called through a normal callback frame.
+                if let Some(Frame::Lua {
+                    expected_return, ..
+                }) = self.state.frames.last_mut()
+                {
+                    *expected_return = None;
+                }
+                self.state.stack.truncate(function_index);
+                self.state.frames.push(Frame::Error(err));
+                Ok(IntrinsicCall::Errored)
+            }
+        }
+    }
+
     /// Calls the function at the given index with a constant number of arguments without
     /// invalidating the function or its arguments. Returns are placed *after* the function and its
     /// aruments, and all registers past this are invalidated as normal.
@@ -834,6 +1252,11 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
 
         self.fuel.consume(Self::FUEL_PER_CALL);
 
+        debug_assert!(
+            self.state.to_be_closed.last().map_or(true, |&i| i < bottom),
+            "cannot tail call with pending to-be-closed variables"
+        );
+
         let function_index = base + func.0 as usize;
         let arg_count = args
             .to_constant()
@@ -858,6 +1281,72 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         Ok(())
     }
 
+    /// Mark the given register as a to-be-closed variable.
+    pub(super) fn mark_to_be_closed(
+        &mut self,
+        ctx: Context<'gc>,
+        reg: RegisterIndex,
+    ) -> Result<(), VMError> {
+        let Some(Frame::Lua { base, .. }) = self.state.frames.last() else {
+            panic!("top frame is not lua frame");
+        };
+
+        let ind = *base + reg.0 as usize;
+        let value = self.state.stack[ind];
+        if value.to_bool() {
+            let metatable = meta_ops::get_metatable(ctx, value);
+            if metatable.map_or(true, |mt| mt.get(ctx, MetaMethod::Close).is_nil()) {
+                return Err(VMError::BadToBeClosed(value.type_name()));
+            }
+        }
+
+        debug_assert!(self.state.to_be_closed.last().map_or(true, |&i| i < ind));
+        self.state.to_be_closed.push(ind);
+        Ok(())
+    }
+
+    /// If there are any to-be-closed variables at or above the given register, calls the `__close`
+    /// metamethod of the topmost one in a new frame and returns true.
+    ///
+    /// The PC is moved back so that the current instruction will be run again once the metamethod
+    /// returns, which closes each variable in turn before the instruction can complete.
+    pub(super) fn close_variables(
+        &mut self,
+        ctx: Context<'gc>,
+        bottom_register: RegisterIndex,
+    ) -> Result<bool, VMError> {
+        let Some(Frame::Lua {
+            base,
+            pc,
+            expected_return,
+            ..
+        }) = self.state.frames.last_mut()
+        else {
+            panic!("top frame is not lua frame");
+        };
+
+        let bottom = *base + bottom_register.0 as usize;
+        while let Some(&ind) = self.state.to_be_closed.last() {
+            if ind < bottom {
+                break;
+            }
+            self.state.to_be_closed.pop();
+
+            if let Some(call) = meta_ops::close(ctx, self.state.stack[ind], Value::Nil)? {
+                *pc -= 1;
+                *expected_return = Some(LuaReturn::Meta(MetaReturn::None));
+
+                self.fuel.consume(Self::FUEL_PER_CALL);
+                let top = self.state.stack.len();
+                self.state.stack.extend(call.args);
+                self.state.push_call(top, call.function);
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     /// Return to the upper frame with results starting at the given register index.
     pub(super) fn return_upper(
         self,
@@ -1003,6 +1492,17 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
     }
 }
 
+/// Find the line number of the opcode at the given index in a function prototype.
+pub(super) fn opcode_line_number(proto: &FunctionPrototype<'_>, opcode_index: usize) -> LineNumber {
+    match proto
+        .opcode_line_numbers
+        .binary_search_by_key(&opcode_index, |(opi, _)| *opi)
+    {
+        Ok(i) => proto.opcode_line_numbers[i].1,
+        Err(i) => proto.opcode_line_numbers[i.saturating_sub(1)].1,
+    }
+}
+
 fn count_fuel(per_item: i32, len: usize) -> i32 {
     i32::try_from(len)
         .unwrap_or(i32::MAX)
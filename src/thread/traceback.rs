@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::{
+    compiler::{FunctionRef, LineNumber},
+    String, Thread,
+};
+
+use super::thread::{opcode_line_number, Frame};
+
+/// A single frame in a [`Traceback`].
+#[derive(Debug, Copy, Clone)]
+pub enum TracebackFrame<'gc> {
+    /// A Lua function, stopped at the line it is currently executing.
+    Lua {
+        chunk_name: String<'gc>,
+        function: FunctionRef<String<'gc>>,
+        current_line: LineNumber,
+        /// The original source file of the current line, which is the same as `chunk_name` unless
+        /// the chunk was loaded with a source map.
+        source_file: String<'gc>,
+        source_line: LineNumber,
+    },
+    /// A Rust callback or sequence.
+    Native,
+}
+
+/// An entry in a [`Traceback`], which is a frame along with the thread it is running on.
+#[derive(Debug, Copy, Clone)]
+pub struct TracebackEntry<'gc> {
+    pub thread: Thread<'gc>,
+    pub frame: TracebackFrame<'gc>,
+}
+
+/// The logical call stack of an executor, which crosses coroutine boundaries.
+///
+/// Frames are ordered from the innermost call outwards. When a thread was resumed by another
+/// thread, the frames of the resumed thread are followed by the frames of the thread which resumed
+/// it, so the traceback of code running in a deeply nested coroutine goes all the way back to the
+/// main thread.
+///
+/// Displays like the tracebacks of PUC-Rio Lua, with a `[resume]` line wherever one thread was
+/// resumed by the next.
+#[derive(Debug, Clone, Default)]
+pub struct Traceback<'gc> {
+    pub entries: Vec<TracebackEntry<'gc>>,
+}
+
+impl<'gc> Traceback<'gc> {
+    // Adds the frames of a thread which is not currently running, innermost first.
+    pub(super) fn push_frames(&mut self, thread: Thread<'gc>, frames: &[Frame<'gc>]) {
+        for frame in frames.iter().rev() {
+            let frame = match *frame {
+                Frame::Lua { closure, pc, .. } => {
+                    let proto = closure.prototype();
+                    // Every frame below the top is stopped at the instruction which called the
+                    // frame above it.
+                    let current_line = opcode_line_number(&proto, pc.saturating_sub(1));
+                    let (source_file, source_line) = proto.source_location(current_line);
+                    TracebackFrame::Lua {
+                        chunk_name: proto.chunk_name,
+                        function: proto.reference,
+                        current_line,
+                        source_file,
+                        source_line,
+                    }
+                }
+                Frame::Callback { .. } | Frame::Sequence { .. } => TracebackFrame::Native,
+                _ => continue,
+            };
+            self.entries.push(TracebackEntry { thread, frame });
+        }
+    }
+}
+
+impl<'gc> fmt::Display for Traceback<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stack traceback:")?;
+        let mut last_thread = None;
+        for entry in &self.entries {
+            if last_thread.is_some_and(|t| t != entry.thread) {
+                write!(f, "\n\t[resume]")?;
+            }
+            last_thread = Some(entry.thread);
+
+            match entry.frame {
+                TracebackFrame::Lua {
+                    chunk_name,
+                    function,
+                    source_file,
+                    source_line,
+                    ..
+                } => {
+                    write!(f, "\n\t{}:{}: ", source_file, source_line)?;
+                    match function {
+                        FunctionRef::Named(name, _) => write!(f, "in function '{}'", name)?,
+                        FunctionRef::Expression(line) => {
+                            write!(f, "in function <{}:{}>", chunk_name, line)?
+                        }
+                        FunctionRef::Chunk => write!(f, "in main chunk")?,
+                    }
+                }
+                TracebackFrame::Native => write!(f, "\n\t[native]: in ?")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -2,11 +2,11 @@ use allocator_api2::vec;
 use gc_arena::allocator_api::MetricsAlloc;
 
 use crate::{
-    meta_ops::{self, MetaOperatorError, MetaResult},
+    meta_ops::{self, ConcatResult, MetaOperatorError, MetaResult},
     opcode::{Operation, RCIndex},
     raw_ops,
     table::RawTable,
-    thread::thread::MetaReturn,
+    thread::thread::{IntrinsicCall, MetaReturn},
     types::{RegisterIndex, UpValueDescriptor, VarCount},
     Closure, Constant, Context, Function, MetaMethod, String, Table, Value,
 };
@@ -21,6 +21,31 @@ pub(super) fn run_vm<'gc>(
     ctx: Context<'gc>,
     mut lua_frame: LuaFrame<'gc, '_>,
     max_instructions: u32,
+) -> Result<u32, VMError> {
+    // Registers of a trusted prototype can only be accessed unchecked while the stack covers all of
+    // them, which is not the case while a variable number of values is on top of the stack.
+    let prototype = lua_frame.closure().prototype();
+    #[cfg(feature = "tier-up")]
+    if !prototype.is_trusted() {
+        crate::FunctionPrototype::tier_up(prototype, ctx.tier_up_threshold());
+    }
+    if prototype.is_trusted()
+        && lua_frame.registers().stack_frame.len() >= prototype.stack_size as usize
+    {
+        run_vm_with::<true>(ctx, lua_frame, max_instructions)
+    } else {
+        run_vm_with::<false>(ctx, lua_frame, max_instructions)
+    }
+}
+
+// Runs the VM, skipping the bounds checks on register access if `TRUSTED` is true.
+//
+// This must only be called with `TRUSTED` set if the current prototype has been verified and the
+// stack frame is at least as long as the prototype's stack size.
+fn run_vm_with<'gc, const TRUSTED: bool>(
+    ctx: Context<'gc>,
+    mut lua_frame: LuaFrame<'gc, '_>,
+    max_instructions: u32,
 ) -> Result<u32, VMError> {
     if max_instructions == 0 {
         return Ok(0);
@@ -29,32 +54,62 @@ pub(super) fn run_vm<'gc>(
     let current_function = lua_frame.closure();
     let current_prototype = current_function.prototype();
     let current_upvalues = current_function.upvalues();
+    let stack_size = current_prototype.stack_size as usize;
     let mut registers = lua_frame.registers();
     let mut instructions_run = 0;
 
-    fn get_rc<'gc>(
-        stack_frame: &[Value<'gc>],
+    fn get_rc<'gc, const TRUSTED: bool>(
+        stack_frame: &mut [Value<'gc>],
         constants: &[Constant<String<'gc>>],
         rc: RCIndex,
     ) -> Value<'gc> {
         match rc {
-            RCIndex::Register(r) => stack_frame[r.0 as usize],
+            RCIndex::Register(r) => *register::<TRUSTED>(stack_frame, r.0 as usize),
             RCIndex::Constant(c) => constants[c.0 as usize].into(),
         }
     }
 
+    // Accesses the register at the given index of the current stack frame.
+    macro_rules! reg {
+        ($index:expr) => {
+            *register::<TRUSTED>(registers.stack_frame, $index)
+        };
+    }
+
+    // Reads a register or constant operand.
+    macro_rules! rc {
+        ($rc:expr) => {
+            get_rc::<TRUSTED>(registers.stack_frame, &current_prototype.constants, $rc)
+        };
+    }
+
+    // Must follow every time the registers are borrowed again, since the stack may have been
+    // resized. Stops running the trusted VM if the stack no longer covers every register, the
+    // next call to `run_vm` will continue with checked register access.
+    macro_rules! check_stack_frame {
+        () => {
+            if TRUSTED && registers.stack_frame.len() < stack_size {
+                instructions_run += 1;
+                break;
+            }
+        };
+    }
+
     loop {
         let op = current_prototype.opcodes[*registers.pc].decode();
         *registers.pc += 1;
 
         match op {
             Operation::Move { dest, source } => {
-                registers.stack_frame[dest.0 as usize] = registers.stack_frame[source.0 as usize];
+                reg!(dest.0 as usize) = reg!(source.0 as usize);
             }
 
             Operation::LoadConstant { dest, constant } => {
-                registers.stack_frame[dest.0 as usize] =
-                    current_prototype.constants[constant.0 as usize].into();
+                reg!(dest.0 as usize) = current_prototype.constants[constant.0 as usize].into();
+            }
+
+            Operation::LoadExt { dest, ext } => {
+                reg!(dest.0 as usize) = ctx.ext_opcodes().get(ext).map_or(Value::Nil, Value::from);
             }
 
             Operation::LoadBool {
@@ -62,7 +117,7 @@ pub(super) fn run_vm<'gc>(
                 value,
                 skip_next,
             } => {
-                registers.stack_frame[dest.0 as usize] = Value::Boolean(value);
+                reg!(dest.0 as usize) = Value::Boolean(value);
                 if skip_next {
                     *registers.pc += 1;
                 }
@@ -70,7 +125,7 @@ pub(super) fn run_vm<'gc>(
 
             Operation::LoadNil { dest, count } => {
                 for i in dest.0..dest.0 + count {
-                    registers.stack_frame[i as usize] = Value::Nil;
+                    reg!(i as usize) = Value::Nil;
                 }
             }
 
@@ -83,15 +138,15 @@ pub(super) fn run_vm<'gc>(
                 raw_table.reserve_array(array_size as usize);
                 raw_table.reserve_map(map_size as usize);
                 let table = Table::from_parts(&ctx, raw_table, None);
-                registers.stack_frame[dest.0 as usize] = Value::Table(table);
+                reg!(dest.0 as usize) = Value::Table(table);
             }
 
             Operation::GetTable { dest, table, key } => {
-                let table = registers.stack_frame[table.0 as usize];
-                let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
+                let table = reg!(table.0 as usize);
+                let key = rc!(key);
                 match meta_ops::index(ctx, table, key)? {
                     MetaResult::Value(v) => {
-                        registers.stack_frame[dest.0 as usize] = v;
+                        reg!(dest.0 as usize) = v;
                     }
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -106,9 +161,9 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetTable { table, key, value } => {
-                let table = registers.stack_frame[table.0 as usize];
-                let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                let value = get_rc(&registers.stack_frame, &current_prototype.constants, value);
+                let table = reg!(table.0 as usize);
+                let key = rc!(key);
+                let value = rc!(value);
                 if let Some(call) = meta_ops::new_index(ctx, table, key, value)? {
                     lua_frame.call_meta_function(
                         ctx,
@@ -122,10 +177,10 @@ pub(super) fn run_vm<'gc>(
 
             Operation::GetUpTable { dest, table, key } => {
                 let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
-                let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
+                let key = rc!(key);
                 match meta_ops::index(ctx, table, key)? {
                     MetaResult::Value(v) => {
-                        registers.stack_frame[dest.0 as usize] = v;
+                        reg!(dest.0 as usize) = v;
                     }
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -141,8 +196,8 @@ pub(super) fn run_vm<'gc>(
 
             Operation::SetUpTable { table, key, value } => {
                 let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
-                let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                let value = get_rc(&registers.stack_frame, &current_prototype.constants, value);
+                let key = rc!(key);
+                let value = rc!(value);
                 if let Some(call) = meta_ops::new_index(ctx, table, key, value)? {
                     lua_frame.call_meta_function(
                         ctx,
@@ -157,16 +212,26 @@ pub(super) fn run_vm<'gc>(
             Operation::SetList { base, count } => {
                 lua_frame.set_table_list(&ctx, base, count)?;
                 registers = lua_frame.registers();
+                check_stack_frame!();
             }
 
             Operation::Call {
                 func,
                 args,
                 returns,
-            } => {
-                lua_frame.call_function(ctx, func, args, returns)?;
-                break;
-            }
+            } => match lua_frame.call_intrinsic(ctx, func, args, returns)? {
+                IntrinsicCall::Returned => {
+                    registers = lua_frame.registers();
+                    check_stack_frame!();
+                }
+                IntrinsicCall::Errored => {
+                    break;
+                }
+                IntrinsicCall::NotIntrinsic => {
+                    lua_frame.call_function(ctx, func, args, returns)?;
+                    break;
+                }
+            },
 
             Operation::TailCall { func, args } => {
                 lua_frame.tail_call_function(ctx, func, args)?;
@@ -174,27 +239,46 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Return { start, count } => {
-                lua_frame.return_upper(&ctx, start, count)?;
+                if !lua_frame.close_variables(ctx, RegisterIndex(0))? {
+                    lua_frame.return_upper(&ctx, start, count)?;
+                }
                 break;
             }
 
             Operation::VarArgs { dest, count } => {
                 lua_frame.varargs(dest, count)?;
                 registers = lua_frame.registers();
+                check_stack_frame!();
             }
 
             Operation::Jump {
                 offset,
                 close_upvalues,
             } => {
-                *registers.pc = add_offset(*registers.pc, offset);
                 if let Some(r) = close_upvalues.to_u8() {
+                    if lua_frame.close_variables(ctx, RegisterIndex(r))? {
+                        break;
+                    }
+                    registers = lua_frame.registers();
                     registers.close_upvalues(&ctx, RegisterIndex(r));
                 }
+                if offset < 0 {
+                    current_prototype.counters.record_back_edge();
+                }
+                *registers.pc = add_offset(*registers.pc, offset);
+                if close_upvalues.is_some() {
+                    check_stack_frame!();
+                }
+            }
+
+            Operation::ToBeClosed { value } => {
+                lua_frame.mark_to_be_closed(ctx, value)?;
+                registers = lua_frame.registers();
+                check_stack_frame!();
             }
 
             Operation::Test { value, is_true } => {
-                let value = registers.stack_frame[value.0 as usize];
+                let value = reg!(value.0 as usize);
                 if value.to_bool() == is_true {
                     *registers.pc += 1;
                 }
@@ -205,11 +289,11 @@ pub(super) fn run_vm<'gc>(
                 value,
                 is_true,
             } => {
-                let value = registers.stack_frame[value.0 as usize];
+                let value = reg!(value.0 as usize);
                 if value.to_bool() == is_true {
                     *registers.pc += 1;
                 } else {
-                    registers.stack_frame[dest.0 as usize] = value;
+                    reg!(dest.0 as usize) = value;
                 }
             }
 
@@ -231,66 +315,68 @@ pub(super) fn run_vm<'gc>(
                 }
 
                 let closure = Closure::from_parts(&ctx, proto, upvalues);
-                registers.stack_frame[dest.0 as usize] =
-                    Value::Function(Function::Closure(closure));
+                reg!(dest.0 as usize) = Value::Function(Function::Closure(closure));
             }
 
             Operation::NumericForPrep { base, jump } => {
-                registers.stack_frame[base.0 as usize] = raw_subtract(
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 2],
-                )
-                .ok_or_else(|| {
-                    VMError::BadForLoopPrep(
-                        registers.stack_frame[base.0 as usize].type_name(),
-                        registers.stack_frame[base.0 as usize + 2].type_name(),
-                    )
-                })?;
-                *registers.pc = add_offset(*registers.pc, jump);
+                match (
+                    reg!(base.0 as usize),
+                    reg!(base.0 as usize + 1),
+                    reg!(base.0 as usize + 2),
+                ) {
+                    (Value::Integer(start), limit, Value::Integer(step)) => {
+                        if step == 0 {
+                            return Err(VMError::ForLoopZeroStep);
+                        }
+
+                        // Integer loops never compute an index past the limit, so they cannot
+                        // overflow. Instead, the limit register is replaced with the number of
+                        // iterations remaining after the first one, and the first iteration is
+                        // entered directly.
+                        match for_loop_count(start, limit, step).ok_or_else(|| {
+                            VMError::BadForLoop("integer", limit.type_name(), "integer")
+                        })? {
+                            Some(count) => {
+                                reg!(base.0 as usize + 1) = Value::Integer(count as i64);
+                                reg!(base.0 as usize + 3) = Value::Integer(start);
+                            }
+                            None => {
+                                // Skip the loop entirely, including the `NumericForLoop`
+                                // instruction.
+                                *registers.pc = add_offset(*registers.pc, jump) + 1;
+                            }
+                        }
+                    }
+                    (start, _, step) => {
+                        if step.to_number() == Some(0.0) {
+                            return Err(VMError::ForLoopZeroStep);
+                        }
+
+                        reg!(base.0 as usize) = raw_subtract(start, step).ok_or_else(|| {
+                            VMError::BadForLoopPrep(start.type_name(), step.type_name())
+                        })?;
+                        *registers.pc = add_offset(*registers.pc, jump);
+                    }
+                }
             }
 
             Operation::NumericForLoop { base, jump } => {
                 match (
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 1],
-                    registers.stack_frame[base.0 as usize + 2],
+                    reg!(base.0 as usize),
+                    reg!(base.0 as usize + 1),
+                    reg!(base.0 as usize + 2),
                 ) {
-                    (Value::Integer(index), Value::Integer(limit), Value::Integer(step)) => {
-                        let (index, overflow) = index.overflowing_add(step);
-                        registers.stack_frame[base.0 as usize] = Value::Integer(index);
-
-                        let past_end = overflow
-                            || if step < 0 {
-                                index < limit
-                            } else {
-                                index > limit
-                            };
-                        if !past_end {
+                    (Value::Integer(index), Value::Integer(count), Value::Integer(step)) => {
+                        // `NumericForPrep` has replaced the limit with the remaining iteration
+                        // count.
+                        let count = count as u64;
+                        if count > 0 {
+                            let index = index.wrapping_add(step);
+                            reg!(base.0 as usize) = Value::Integer(index);
+                            reg!(base.0 as usize + 1) = Value::Integer((count - 1) as i64);
+                            reg!(base.0 as usize + 3) = Value::Integer(index);
+                            current_prototype.counters.record_back_edge();
                             *registers.pc = add_offset(*registers.pc, jump);
-                            registers.stack_frame[base.0 as usize + 3] = Value::Integer(index);
-                        }
-                    }
-                    (Value::Integer(index), limit, Value::Integer(step)) => {
-                        if let Some(limit) = limit.to_number() {
-                            let (index, overflow) = index.overflowing_add(step);
-                            registers.stack_frame[base.0 as usize] = Value::Integer(index);
-
-                            let past_end = overflow
-                                || if step < 0 {
-                                    !(index as f64 >= limit)
-                                } else {
-                                    !(index as f64 <= limit)
-                                };
-                            if !past_end {
-                                *registers.pc = add_offset(*registers.pc, jump);
-                                registers.stack_frame[base.0 as usize + 3] = Value::Integer(index);
-                            }
-                        } else {
-                            return Err(VMError::BadForLoop(
-                                "integer",
-                                limit.type_name(),
-                                "integer",
-                            ));
                         }
                     }
                     (index, limit, step) => {
@@ -298,7 +384,7 @@ pub(super) fn run_vm<'gc>(
                             (index.to_number(), limit.to_number(), step.to_number())
                         {
                             let index = index + step;
-                            registers.stack_frame[base.0 as usize] = Value::Number(index);
+                            reg!(base.0 as usize) = Value::Number(index);
 
                             let past_end = if step < 0.0 {
                                 !(index >= limit)
@@ -306,8 +392,9 @@ pub(super) fn run_vm<'gc>(
                                 !(index <= limit)
                             };
                             if !past_end {
+                                current_prototype.counters.record_back_edge();
                                 *registers.pc = add_offset(*registers.pc, jump);
-                                registers.stack_frame[base.0 as usize + 3] = Value::Number(index);
+                                reg!(base.0 as usize + 3) = Value::Number(index);
                             }
                         } else {
                             return Err(VMError::BadForLoop(
@@ -326,20 +413,20 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::GenericForLoop { base, jump } => {
-                if registers.stack_frame[base.0 as usize + 1].to_bool() {
-                    registers.stack_frame[base.0 as usize] =
-                        registers.stack_frame[base.0 as usize + 1];
+                if reg!(base.0 as usize + 1).to_bool() {
+                    reg!(base.0 as usize) = reg!(base.0 as usize + 1);
+                    current_prototype.counters.record_back_edge();
                     *registers.pc = add_offset(*registers.pc, jump);
                 }
             }
 
             Operation::Method { base, table, key } => {
-                let table = registers.stack_frame[table.0 as usize];
-                let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                registers.stack_frame[base.0 as usize + 1] = table;
+                let table = reg!(table.0 as usize);
+                let key = rc!(key);
+                reg!(base.0 as usize + 1) = table;
                 match meta_ops::index(ctx, table, key)? {
                     MetaResult::Value(v) => {
-                        registers.stack_frame[base.0 as usize] = v;
+                        reg!(base.0 as usize) = v;
                     }
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -358,29 +445,41 @@ pub(super) fn run_vm<'gc>(
                 source,
                 count,
             } => {
-                registers.stack_frame[dest.0 as usize] = Value::String(String::concat(
-                    ctx,
-                    &registers.stack_frame[source.0 as usize..source.0 as usize + count as usize],
-                )?);
+                let values =
+                    &registers.stack_frame[source.0 as usize..source.0 as usize + count as usize];
+                match meta_ops::concat_many(ctx, values)? {
+                    ConcatResult::Value(v) => {
+                        reg!(dest.0 as usize) = v;
+                    }
+                    ConcatResult::Call { .. } => {
+                        // Let the concat callback handle every call to `__concat` until the
+                        // concatenation is finished.
+                        let values = values.to_vec();
+                        lua_frame.call_meta_function(
+                            ctx,
+                            meta_ops::concat_callback(ctx).into(),
+                            &values,
+                            MetaReturn::Register(dest),
+                        )?;
+                        break;
+                    }
+                }
             }
 
             Operation::GetUpValue { source, dest } => {
-                registers.stack_frame[dest.0 as usize] =
+                reg!(dest.0 as usize) =
                     registers.get_upvalue(&ctx, current_upvalues[source.0 as usize]);
             }
 
             Operation::SetUpValue { source, dest } => {
-                registers.set_upvalue(
-                    &ctx,
-                    current_upvalues[dest.0 as usize],
-                    registers.stack_frame[source.0 as usize],
-                );
+                let value = reg!(source.0 as usize);
+                registers.set_upvalue(&ctx, current_upvalues[dest.0 as usize], value);
             }
 
             Operation::Length { dest, source } => {
-                match meta_ops::len(ctx, registers.stack_frame[source.0 as usize])? {
+                match meta_ops::len(ctx, reg!(source.0 as usize))? {
                     MetaResult::Value(v) => {
-                        registers.stack_frame[dest.0 as usize] = v;
+                        reg!(dest.0 as usize) = v;
                     }
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -399,8 +498,8 @@ pub(super) fn run_vm<'gc>(
                 left,
                 right,
             } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::equal(ctx, left, right)? {
                     MetaResult::Value(v) => {
                         if v.to_bool() == skip_if {
@@ -424,10 +523,14 @@ pub(super) fn run_vm<'gc>(
                 left,
                 right,
             } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 if (raw_ops::less_than(left, right).ok_or_else(|| {
-                    MetaOperatorError::Binary(MetaMethod::Lt, left.type_name(), right.type_name())
+                    MetaOperatorError::Binary(
+                        MetaMethod::Lt,
+                        meta_ops::type_name(ctx, left),
+                        meta_ops::type_name(ctx, right),
+                    )
                 })?) == skip_if
                 {
                     *registers.pc += 1;
@@ -439,10 +542,14 @@ pub(super) fn run_vm<'gc>(
                 left,
                 right,
             } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 if (raw_ops::less_equal(left, right).ok_or_else(|| {
-                    MetaOperatorError::Binary(MetaMethod::Le, left.type_name(), right.type_name())
+                    MetaOperatorError::Binary(
+                        MetaMethod::Le,
+                        meta_ops::type_name(ctx, left),
+                        meta_ops::type_name(ctx, right),
+                    )
                 })?) == skip_if
                 {
                     *registers.pc += 1;
@@ -450,14 +557,14 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Not { dest, source } => {
-                let source = registers.stack_frame[source.0 as usize];
-                registers.stack_frame[dest.0 as usize] = source.not();
+                let source = reg!(source.0 as usize);
+                reg!(dest.0 as usize) = source.not();
             }
 
             Operation::Minus { dest, source } => {
-                let value = registers.stack_frame[source.0 as usize];
+                let value = reg!(source.0 as usize);
                 match meta_ops::negate(ctx, value)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -471,9 +578,9 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::BitNot { dest, source } => {
-                let value = registers.stack_frame[source.0 as usize];
+                let value = reg!(source.0 as usize);
                 match meta_ops::bitwise_not(ctx, value)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -487,10 +594,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Add { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::add(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -504,10 +611,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Sub { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::subtract(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -521,10 +628,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Mul { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::multiply(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -538,10 +645,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Div { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::float_divide(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -555,10 +662,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::IDiv { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::floor_divide(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -572,10 +679,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Mod { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::modulo(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -589,10 +696,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Pow { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::exponentiate(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -606,10 +713,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::BitAnd { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::bitwise_and(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -623,10 +730,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::BitOr { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::bitwise_or(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -640,10 +747,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::BitXor { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::bitwise_xor(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -657,10 +764,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::ShiftLeft { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::shift_left(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -674,10 +781,10 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::ShiftRight { dest, left, right } => {
-                let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
-                let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
+                let left = rc!(left);
+                let right = rc!(right);
                 match meta_ops::shift_right(ctx, left, right)? {
-                    MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
+                    MetaResult::Value(v) => reg!(dest.0 as usize) = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
                             ctx,
@@ -700,6 +807,23 @@ pub(super) fn run_vm<'gc>(
     Ok(instructions_run)
 }
 
+// Indexes a register, without a bounds check if `TRUSTED` is true.
+#[inline(always)]
+fn register<'a, 'gc, const TRUSTED: bool>(
+    stack_frame: &'a mut [Value<'gc>],
+    index: usize,
+) -> &'a mut Value<'gc> {
+    if TRUSTED {
+        debug_assert!(index < stack_frame.len());
+        // SAFETY: `run_vm_with` is only trusted when the prototype has passed the verifier, which
+        // checks that every register accessed here is below the stack size, and while the stack
+        // frame is at least as long as the stack size.
+        unsafe { stack_frame.get_unchecked_mut(index) }
+    } else {
+        &mut stack_frame[index]
+    }
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
@@ -710,6 +834,53 @@ fn add_offset(pc: usize, offset: i16) -> usize {
     }
 }
 
+// Returns the number of iterations remaining after the first for an integer numeric for loop, or
+// `None` if the loop body should not run at all. Float limits are clipped to the integer range, the
+// same as PUC-Rio Lua. The outer option is `None` if the limit is not a number at all.
+fn for_loop_count<'gc>(start: i64, limit: Value<'gc>, step: i64) -> Option<Option<u64>> {
+    let limit = match limit {
+        Value::Integer(limit) => limit,
+        limit => {
+            let limit = limit.to_number()?;
+            if limit.is_nan() {
+                return Some(None);
+            }
+
+            let limit = if step > 0 {
+                limit.floor()
+            } else {
+                limit.ceil()
+            };
+            if limit >= i64::MAX as f64 {
+                if step < 0 {
+                    return Some(None);
+                }
+                i64::MAX
+            } else if limit < i64::MIN as f64 {
+                if step > 0 {
+                    return Some(None);
+                }
+                i64::MIN
+            } else {
+                limit as i64
+            }
+        }
+    };
+
+    Some(if step > 0 {
+        if start > limit {
+            None
+        } else {
+            Some((limit as u64).wrapping_sub(start as u64) / step as u64)
+        }
+    } else if start < limit {
+        None
+    } else {
+        // Computes `-step` without overflowing on `i64::MIN`.
+        Some((start as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1))
+    })
+}
+
 fn raw_subtract<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.subtract(&rhs.to_constant()?)?.into())
 }
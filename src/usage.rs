@@ -0,0 +1,194 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+    string::String as StdString,
+};
+
+use ahash::AHashMap;
+use gc_arena::Collect;
+
+use crate::{compiler::FunctionRef, FunctionPrototype};
+
+/// A stable identifier for a Lua function.
+///
+/// Unlike the address of a `FunctionPrototype`, this is the same for every time a chunk is loaded
+/// (and across `Lua` instances), as long as the chunk name and source of the function's chunk are
+/// unchanged. Chunks loaded by a `PluginManager` use the plugin name as their chunk name, so the
+/// chunk name of a function identifies the plugin it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FunctionId {
+    pub chunk_name: StdString,
+    pub function: FunctionRef<StdString>,
+}
+
+impl FunctionId {
+    pub fn of(proto: &FunctionPrototype<'_>) -> Self {
+        Self {
+            chunk_name: proto.chunk_name.to_str_lossy().into_owned(),
+            function: proto
+                .reference
+                .map_strings(|s| s.to_str_lossy().into_owned()),
+        }
+    }
+}
+
+impl fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.chunk_name, self.function)
+    }
+}
+
+/// Fuel consumed while running a single Lua function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionUsage {
+    pub id: FunctionId,
+    /// Fuel consumed by VM instructions of this function, plus any fuel consumed by callbacks that
+    /// it called directly.
+    pub fuel: u64,
+}
+
+/// A report of how much fuel each Lua function has consumed, returned by `Lua::usage_report`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    /// Every function which has consumed fuel, ordered from the most to the least fuel consumed.
+    pub functions: Vec<FunctionUsage>,
+}
+
+impl UsageReport {
+    /// The total fuel consumed by every function in this report.
+    pub fn total(&self) -> u64 {
+        self.functions.iter().map(|f| f.fuel).sum()
+    }
+
+    /// Returns the fuel consumed by the function with the given identifier.
+    pub fn get(&self, id: &FunctionId) -> Option<u64> {
+        self.functions.iter().find(|f| f.id == *id).map(|f| f.fuel)
+    }
+
+    /// Returns the total fuel consumed by functions of each chunk, ordered from the most to the
+    /// least fuel consumed.
+    pub fn by_chunk(&self) -> Vec<(StdString, u64)> {
+        let mut chunks: Vec<(StdString, u64)> = Vec::new();
+        for usage in &self.functions {
+            match chunks.iter_mut().find(|(c, _)| *c == usage.id.chunk_name) {
+                Some((_, fuel)) => *fuel += usage.fuel,
+                None => chunks.push((usage.id.chunk_name.clone(), usage.fuel)),
+            }
+        }
+        chunks.sort_by(|a, b| b.1.cmp(&a.1));
+        chunks
+    }
+}
+
+/// The execution counters of a single Lua function, recorded in a [`Profile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub id: FunctionId,
+    /// See `ProtoCounters::invocations`.
+    pub invocations: u64,
+    /// See `ProtoCounters::back_edges`.
+    pub back_edges: u64,
+}
+
+/// The execution counters of the functions run during a profiling run, used to warm up the same
+/// functions when they are loaded again, see `Context::set_profile`.
+///
+/// A profile holds only plain data, so it can be kept by the host and used with a different `Lua`
+/// instance, such as on the next launch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Every recorded function, in the order they were first recorded.
+    pub functions: Vec<FunctionProfile>,
+}
+
+impl Profile {
+    /// Add the counters of a prototype and every prototype nested within it to this profile.
+    ///
+    /// Counters of a function which is already in the profile are added to the recorded ones.
+    /// Prototypes which have never run are skipped.
+    pub fn record(&mut self, proto: &FunctionPrototype<'_>) {
+        let counters = &proto.counters;
+        if counters.heat() > 0 {
+            match self.functions.iter_mut().find(|f| f.id == *proto.id()) {
+                Some(function) => {
+                    function.invocations =
+                        function.invocations.saturating_add(counters.invocations());
+                    function.back_edges = function.back_edges.saturating_add(counters.back_edges());
+                }
+                None => self.functions.push(FunctionProfile {
+                    id: proto.id().clone(),
+                    invocations: counters.invocations(),
+                    back_edges: counters.back_edges(),
+                }),
+            }
+        }
+        for proto in proto.prototypes.iter() {
+            self.record(proto);
+        }
+    }
+
+    /// Returns the recorded counters of the function with the given identifier.
+    pub fn get(&self, id: &FunctionId) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|f| f.id == *id)
+    }
+}
+
+/// Singleton holding the profile set with `Context::set_profile`.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct ProfileSetting(pub(crate) RefCell<Option<Rc<Profile>>>);
+
+/// Singleton which accumulates the fuel usage of every Lua function while usage tracking is
+/// enabled.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct UsageTracker {
+    enabled: Cell<bool>,
+    usage: RefCell<AHashMap<FunctionId, u64>>,
+}
+
+impl UsageTracker {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.set(enabled);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    /// Charge fuel consumed while running the given prototype. Does nothing if tracking is
+    /// disabled.
+    pub(crate) fn charge(&self, proto: &FunctionPrototype<'_>, fuel: u64) {
+        if !self.enabled.get() || fuel == 0 {
+            return;
+        }
+
+        let id = proto.id();
+        let mut usage = self.usage.borrow_mut();
+        match usage.get_mut(id) {
+            Some(total) => *total = total.saturating_add(fuel),
+            None => {
+                usage.insert(id.clone(), fuel);
+            }
+        }
+    }
+
+    pub(crate) fn report(&self) -> UsageReport {
+        let mut functions: Vec<_> = self
+            .usage
+            .borrow()
+            .iter()
+            .map(|(id, &fuel)| FunctionUsage {
+                id: id.clone(),
+                fuel,
+            })
+            .collect();
+        functions.sort_by(|a, b| b.fuel.cmp(&a.fuel));
+        UsageReport { functions }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.usage.borrow_mut().clear();
+    }
+}
@@ -8,7 +8,8 @@ use thiserror::Error;
 
 use crate::{
     any::{Any, AnyInner},
-    Table,
+    sanitizer::{self, ArenaTag},
+    Context, MetaMethod, Table,
 };
 
 #[derive(Debug, Copy, Clone, Error)]
@@ -19,6 +20,9 @@ pub struct BadUserDataType;
 #[collect(no_drop)]
 pub struct UserDataMeta<'gc> {
     pub metatable: Option<Table<'gc>>,
+    // True if this userdata is registered with `Finalizers` and has not yet been finalized.
+    pub(crate) has_finalizer: bool,
+    pub(crate) arena: ArenaTag,
 }
 
 pub type UserDataMetaState<'gc> = lock::Lock<UserDataMeta<'gc>>;
@@ -58,7 +62,12 @@ impl<'gc> UserData<'gc> {
         R: for<'a> Rootable<'a>,
         Root<'gc, R>: Sized + Collect,
     {
-        UserData(Any::new::<R>(mc, val))
+        let meta = UserDataMeta {
+            metatable: None,
+            has_finalizer: false,
+            arena: ArenaTag::new(mc),
+        };
+        UserData(Any::with_metadata::<R>(mc, lock::Lock::new(meta), val))
     }
 
     pub fn new_static<R: 'static>(mc: &Mutation<'gc>, val: R) -> Self {
@@ -114,15 +123,62 @@ impl<'gc> UserData<'gc> {
         self.0.metadata().get().metatable
     }
 
+    /// Sets the metatable for this userdata, returning the previous one.
+    ///
+    /// No fields of the new metatable are read, so this never marks the userdata for finalization.
+    /// Use [`UserData::apply_metatable`] for the behavior of `debug.setmetatable`.
     pub fn set_metatable(
         self,
         mc: &Mutation<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Option<Table<'gc>> {
+        if let Some(mt) = metatable {
+            sanitizer::check_value(mc, mt.into(), "a userdata metatable");
+        }
+
         let md = self.0.write_metadata(mc).unlock();
         let mut v = md.get();
         let old_metatable = mem::replace(&mut v.metatable, metatable);
         md.set(v);
         old_metatable
     }
+
+    /// Sets the metatable for this userdata like `debug.setmetatable` does, returning the previous
+    /// one.
+    ///
+    /// The userdata is marked for finalization if the new metatable has a `__gc` field at the time
+    /// it is set. A userdata is only ever finalized once per time it is marked.
+    pub fn apply_metatable(
+        self,
+        ctx: Context<'gc>,
+        metatable: Option<Table<'gc>>,
+    ) -> Option<Table<'gc>> {
+        if let Some(mt) = metatable {
+            sanitizer::check_value(&ctx, mt.into(), "a userdata metatable");
+        }
+
+        let has_finalizer = metatable.is_some_and(|mt| !mt.get(ctx, MetaMethod::Gc).is_nil());
+
+        let md = self.0.write_metadata(&ctx).unlock();
+        let mut v = md.get();
+        let old_metatable = mem::replace(&mut v.metatable, metatable);
+        if has_finalizer && !v.has_finalizer {
+            ctx.finalizers().register_userdata(&ctx, self.into_inner());
+            v.has_finalizer = true;
+        }
+        md.set(v);
+        old_metatable
+    }
+
+    pub(crate) fn set_has_finalizer(self, mc: &Mutation<'gc>, has_finalizer: bool) {
+        let md = self.0.write_metadata(mc).unlock();
+        let mut v = md.get();
+        v.has_finalizer = has_finalizer;
+        md.set(v);
+    }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn arena_tag(self) -> ArenaTag {
+        self.0.metadata().get().arena
+    }
 }
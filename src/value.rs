@@ -2,7 +2,9 @@ use std::{f64, fmt, i64, io, string::String as StdString};
 
 use gc_arena::{Collect, Gc};
 
-use crate::{Callback, Closure, Constant, Function, String, Table, Thread, UserData};
+use crate::{
+    Callback, Closure, Constant, Context, Function, String, Table, Thread, TypeError, UserData,
+};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -83,10 +85,10 @@ impl<'gc> Value<'gc> {
     }
 
     /// Interprets Numbers, Integers, and Strings as a String, if possible.
-    pub fn into_string(self, ctx: crate::Context<'gc>) -> Option<String<'gc>> {
+    pub fn into_string(self, ctx: Context<'gc>) -> Option<String<'gc>> {
         match self {
             Value::Integer(i) => Some(ctx.intern(i.to_string().as_bytes())),
-            Value::Number(n) => Some(ctx.intern(n.to_string().as_bytes())),
+            Value::Number(n) => Some(ctx.intern(NumberDisplay(n).to_string().as_bytes())),
             Value::String(s) => Some(s),
             _ => None,
         }
@@ -107,6 +109,142 @@ impl<'gc> Value<'gc> {
         }
     }
 
+    /// Compare two values for equality without calling the `__eq` metamethod, like the `rawequal`
+    /// builtin.
+    ///
+    /// Integers and numbers are compared by their mathematical value, and tables, functions,
+    /// threads and userdata are compared by identity.
+    pub fn raw_equal(self, other: Value<'gc>) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Integer(a), Value::Number(b)) => a as f64 == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Number(a), Value::Integer(b)) => b as f64 == a,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Thread(a), Value::Thread(b)) => a == b,
+            (Value::UserData(a), Value::UserData(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Returns the length of a string or table without calling the `__len` metamethod, like the
+    /// `rawlen` builtin.
+    ///
+    /// Returns `None` for any other type of value.
+    pub fn raw_len(self) -> Option<i64> {
+        match self {
+            Value::String(s) => Some(s.len()),
+            Value::Table(t) => Some(t.length()),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner boolean if this value is a Boolean.
+    pub fn as_bool(self) -> Result<bool, TypeError> {
+        match self {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(self.type_error("boolean")),
+        }
+    }
+
+    /// Returns the value as an Integer if it is an Integer or a Number with an exact integer
+    /// representation.
+    ///
+    /// Does not perform string coercion, see [`Value::coerce_int`].
+    pub fn as_int(self) -> Result<i64, TypeError> {
+        match self {
+            Value::Integer(_) | Value::Number(_) => self.to_integer().ok_or(TypeError {
+                expected: "integer",
+                found: "number with no integer representation",
+            }),
+            _ => Err(self.type_error("integer")),
+        }
+    }
+
+    /// Returns the value as a Number if it is a Number or an Integer.
+    ///
+    /// Does not perform string coercion, see [`Value::coerce_number`].
+    pub fn as_number(self) -> Result<f64, TypeError> {
+        match self {
+            Value::Integer(i) => Ok(i as f64),
+            Value::Number(n) => Ok(n),
+            _ => Err(self.type_error("number")),
+        }
+    }
+
+    /// Returns the inner string if this value is a String.
+    ///
+    /// Does not perform number coercion, see [`Value::coerce_string`].
+    pub fn as_string(self) -> Result<String<'gc>, TypeError> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(self.type_error("string")),
+        }
+    }
+
+    /// Returns the contents of this value if it is a String containing valid UTF-8.
+    pub fn as_str(self) -> Result<&'gc str, TypeError> {
+        self.as_string()?.to_str().map_err(|_| TypeError {
+            expected: "UTF-8 string",
+            found: "non-UTF-8 string",
+        })
+    }
+
+    pub fn as_table(self) -> Result<Table<'gc>, TypeError> {
+        match self {
+            Value::Table(t) => Ok(t),
+            _ => Err(self.type_error("table")),
+        }
+    }
+
+    pub fn as_function(self) -> Result<Function<'gc>, TypeError> {
+        match self {
+            Value::Function(f) => Ok(f),
+            _ => Err(self.type_error("function")),
+        }
+    }
+
+    pub fn as_thread(self) -> Result<Thread<'gc>, TypeError> {
+        match self {
+            Value::Thread(t) => Ok(t),
+            _ => Err(self.type_error("thread")),
+        }
+    }
+
+    pub fn as_userdata(self) -> Result<UserData<'gc>, TypeError> {
+        match self {
+            Value::UserData(u) => Ok(u),
+            _ => Err(self.type_error("userdata")),
+        }
+    }
+
+    /// Interprets Numbers, Integers, and Strings as an Integer, following the Lua coercion rules.
+    pub fn coerce_int(self) -> Result<i64, TypeError> {
+        self.to_integer().ok_or_else(|| self.type_error("integer"))
+    }
+
+    /// Interprets Numbers, Integers, and Strings as a Number, following the Lua coercion rules.
+    pub fn coerce_number(self) -> Result<f64, TypeError> {
+        self.to_number().ok_or_else(|| self.type_error("number"))
+    }
+
+    /// Interprets Numbers, Integers, and Strings as a String, following the Lua coercion rules.
+    pub fn coerce_string(self, ctx: Context<'gc>) -> Result<String<'gc>, TypeError> {
+        self.into_string(ctx)
+            .ok_or_else(|| self.type_error("string"))
+    }
+
+    fn type_error(self, expected: &'static str) -> TypeError {
+        TypeError {
+            expected,
+            found: self.type_name(),
+        }
+    }
+
     pub fn to_constant(self) -> Option<Constant<String<'gc>>> {
         match self {
             Value::Nil => Some(Constant::Nil),
@@ -127,7 +265,7 @@ impl<'gc> fmt::Display for ValueDisplay<'gc> {
             Value::Nil => write!(fmt, "nil"),
             Value::Boolean(b) => write!(fmt, "{}", b),
             Value::Integer(i) => write!(fmt, "{}", i),
-            Value::Number(f) => write!(fmt, "{}", f),
+            Value::Number(f) => write!(fmt, "{}", NumberDisplay(f)),
             Value::String(s) => write!(fmt, "{}", StdString::from_utf8_lossy(&s)),
             Value::Table(t) => write!(fmt, "<table {:p}>", Gc::as_ptr(t.into_inner())),
             Value::Function(Function::Closure(c)) => {
@@ -142,6 +280,75 @@ impl<'gc> fmt::Display for ValueDisplay<'gc> {
     }
 }
 
+/// Displays a float the same way as PUC-Rio Lua's `tostring`, which uses the C format `"%.14g"` and
+/// then appends `.0` to any result that would otherwise look like an integer.
+///
+/// The output never depends on the platform or the current C locale. Infinities are displayed as
+/// `inf` and `-inf`, and all NaN values are displayed as `nan` regardless of their sign bit (which
+/// is not consistent across platforms).
+#[derive(Debug, Copy, Clone)]
+pub struct NumberDisplay(pub f64);
+
+impl fmt::Display for NumberDisplay {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PRECISION: usize = 14;
+
+        let s = format_float_g(self.0, PRECISION, false);
+        fmt.write_str(&s)?;
+        if s.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+            fmt.write_str(".0")?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a float like the C format specifier `"%.{precision}g"` (or `"%#.{precision}g"` if
+/// `alternate` is true), independent of the C locale.
+pub fn format_float_g(n: f64, precision: usize, alternate: bool) -> StdString {
+    if n.is_nan() {
+        return "nan".to_owned();
+    } else if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+
+    let precision = precision.max(1);
+
+    // The exponent used to decide between the fixed and exponent styles is the exponent that the
+    // number would have when formatted in exponent style with the given precision (after
+    // rounding).
+    let exp_form = format!("{:.*e}", precision - 1, n);
+    let (mantissa, exp) = exp_form.split_at(exp_form.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+
+    let strip = |s: &str| -> StdString {
+        if alternate || !s.contains('.') {
+            s.to_owned()
+        } else {
+            s.trim_end_matches('0').trim_end_matches('.').to_owned()
+        }
+    };
+
+    if exp < -4 || exp >= precision as i32 {
+        let mut mantissa = strip(mantissa);
+        if alternate && !mantissa.contains('.') {
+            mantissa.push('.');
+        }
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exp < 0 { '-' } else { '+' },
+            exp.unsigned_abs()
+        )
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        let mut s = strip(&format!("{:.*}", decimals, n));
+        if alternate && decimals == 0 {
+            s.push('.');
+        }
+        s
+    }
+}
+
 impl<'gc> From<bool> for Value<'gc> {
     fn from(v: bool) -> Value<'gc> {
         Value::Boolean(v)
@@ -160,6 +367,30 @@ impl<'gc> From<f64> for Value<'gc> {
     }
 }
 
+// Unsigned integers wider than 63 bits have no `IntoValue` implementation, since values above
+// `i64::MAX` cannot be represented exactly. They must be converted with `Value::try_from`, which
+// fails with a `TypeError` instead.
+macro_rules! impl_try_from_wide_int {
+    ($($i:ty),* $(,)?) => {
+        $(
+            impl<'gc> TryFrom<$i> for Value<'gc> {
+                type Error = TypeError;
+
+                fn try_from(v: $i) -> Result<Value<'gc>, TypeError> {
+                    match i64::try_from(v) {
+                        Ok(i) => Ok(Value::Integer(i)),
+                        Err(_) => Err(TypeError {
+                            expected: "integer",
+                            found: concat!(stringify!($i), " out of range"),
+                        }),
+                    }
+                }
+            }
+        )*
+    };
+}
+impl_try_from_wide_int!(u64, usize);
+
 impl<'gc, S> From<Constant<S>> for Value<'gc>
 where
     String<'gc>: From<S>,
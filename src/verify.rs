@@ -0,0 +1,271 @@
+use thiserror::Error;
+
+use crate::{
+    opcode::{Operation, RCIndex},
+    types::{RegisterIndex, UpValueDescriptor, VarCount},
+    FunctionPrototype,
+};
+
+/// An index in a [`FunctionPrototype`] which is out of range, found by
+/// [`FunctionPrototype::trust`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum VerifyError {
+    #[error("opcode {pc} uses register {register} but the stack size is {stack_size}")]
+    Register {
+        pc: usize,
+        register: usize,
+        stack_size: usize,
+    },
+    #[error("opcode {pc} uses constant {index} but there are {count} constants")]
+    Constant {
+        pc: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {pc} uses upvalue {index} but there are {count} upvalues")]
+    UpValue {
+        pc: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {pc} uses prototype {index} but there are {count} prototypes")]
+    Prototype {
+        pc: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {pc} jumps outside of the function")]
+    Jump { pc: usize },
+    #[error("function has no opcodes")]
+    Empty,
+    #[error("opcode {pc} falls through the end of the function")]
+    FallsThrough { pc: usize },
+}
+
+/// Check that every index used by the opcodes of a single prototype is in range, not including
+/// the prototypes nested within it.
+///
+/// The VM runs the opcodes of a verified prototype without bounds checking the registers it uses
+/// directly, so every register which is accessed outside of a call must be within `stack_size`.
+pub(crate) fn verify_prototype(proto: &FunctionPrototype<'_>) -> Result<(), VerifyError> {
+    let stack_size = proto.stack_size as usize;
+
+    // Execution must never run past the last opcode, so it has to return or jump backwards.
+    match proto.opcodes.last() {
+        None => return Err(VerifyError::Empty),
+        Some(opcode) => match opcode.decode() {
+            Operation::Return { .. } | Operation::TailCall { .. } | Operation::Jump { .. } => {}
+            _ => {
+                return Err(VerifyError::FallsThrough {
+                    pc: proto.opcodes.len() - 1,
+                })
+            }
+        },
+    }
+
+    for (pc, opcode) in proto.opcodes.iter().enumerate() {
+        // Checks that the registers `start..start + count` are all in range.
+        let registers = |start: usize, count: usize| {
+            let end = start + count;
+            if end > stack_size {
+                Err(VerifyError::Register {
+                    pc,
+                    register: end - 1,
+                    stack_size,
+                })
+            } else {
+                Ok(())
+            }
+        };
+        let register = |r: RegisterIndex| registers(r.0 as usize, 1);
+        // Registers followed by a variable number of values are only accessed through checked
+        // calls, so they may start at the very top of the stack.
+        let var_registers = |start: usize, count: VarCount| {
+            registers(start, count.to_constant().map(usize::from).unwrap_or(0))
+        };
+        let constant = |index: usize| {
+            let count = proto.constants.len();
+            if index < count {
+                Ok(())
+            } else {
+                Err(VerifyError::Constant { pc, index, count })
+            }
+        };
+        let rc = |rc: RCIndex| match rc {
+            RCIndex::Register(r) => register(r),
+            RCIndex::Constant(c) => constant(c.0 as usize),
+        };
+        let upvalue = |index: usize| {
+            let count = proto.upvalues.len();
+            if index < count {
+                Ok(())
+            } else {
+                Err(VerifyError::UpValue { pc, index, count })
+            }
+        };
+        let jump = |offset: i16, extra: isize| {
+            // The program counter has already moved past the opcode when it jumps.
+            let target = pc as isize + 1 + offset as isize + extra;
+            if target >= 0 && target < proto.opcodes.len() as isize {
+                Ok(())
+            } else {
+                Err(VerifyError::Jump { pc })
+            }
+        };
+
+        match opcode.decode() {
+            Operation::Move { dest, source }
+            | Operation::Length { dest, source }
+            | Operation::Not { dest, source }
+            | Operation::Minus { dest, source }
+            | Operation::BitNot { dest, source } => {
+                register(dest)?;
+                register(source)?;
+            }
+            Operation::LoadConstant { dest, constant: c } => {
+                register(dest)?;
+                constant(c.0 as usize)?;
+            }
+            Operation::LoadExt { dest, .. } => {
+                register(dest)?;
+            }
+            Operation::LoadBool {
+                dest, skip_next, ..
+            } => {
+                register(dest)?;
+                if skip_next {
+                    jump(1, 0)?;
+                }
+            }
+            Operation::LoadNil { dest, count } => registers(dest.0 as usize, count as usize)?,
+            Operation::NewTable { dest, .. } => register(dest)?,
+            Operation::GetTable { dest, table, key } => {
+                register(dest)?;
+                register(table)?;
+                rc(key)?;
+            }
+            Operation::SetTable { table, key, value } => {
+                register(table)?;
+                rc(key)?;
+                rc(value)?;
+            }
+            Operation::GetUpTable { dest, table, key } => {
+                register(dest)?;
+                upvalue(table.0 as usize)?;
+                rc(key)?;
+            }
+            Operation::SetUpTable { table, key, value } => {
+                upvalue(table.0 as usize)?;
+                rc(key)?;
+                rc(value)?;
+            }
+            Operation::SetList { base, count } => {
+                registers(base.0 as usize, 2)?;
+                var_registers(base.0 as usize + 2, count)?;
+            }
+            Operation::Call { func, args, .. } => {
+                register(func)?;
+                var_registers(func.0 as usize + 1, args)?;
+            }
+            Operation::TailCall { func, args } => {
+                register(func)?;
+                var_registers(func.0 as usize + 1, args)?;
+            }
+            Operation::Return { start, count } => var_registers(start.0 as usize, count)?,
+            Operation::VarArgs { dest, count } => var_registers(dest.0 as usize, count)?,
+            Operation::Jump { offset, .. } => jump(offset, 0)?,
+            Operation::ToBeClosed { value } => register(value)?,
+            Operation::Test { value, .. } => {
+                register(value)?;
+                jump(1, 0)?;
+            }
+            Operation::TestSet { dest, value, .. } => {
+                register(dest)?;
+                register(value)?;
+                jump(1, 0)?;
+            }
+            Operation::Closure { dest, proto: p } => {
+                register(dest)?;
+                let count = proto.prototypes.len();
+                let child = proto
+                    .prototypes
+                    .get(p.0 as usize)
+                    .ok_or(VerifyError::Prototype {
+                        pc,
+                        index: p.0 as usize,
+                        count,
+                    })?;
+                for &desc in child.upvalues.iter() {
+                    match desc {
+                        UpValueDescriptor::Environment => {}
+                        UpValueDescriptor::ParentLocal(r) => register(r)?,
+                        UpValueDescriptor::Outer(u) => upvalue(u.0 as usize)?,
+                    }
+                }
+            }
+            Operation::NumericForPrep { base, jump: offset } => {
+                registers(base.0 as usize, 4)?;
+                jump(offset, 0)?;
+                jump(offset, 1)?;
+            }
+            Operation::NumericForLoop { base, jump: offset } => {
+                registers(base.0 as usize, 4)?;
+                jump(offset, 0)?;
+            }
+            Operation::GenericForCall { base, var_count } => {
+                registers(base.0 as usize, 3)?;
+                registers(base.0 as usize + 3, var_count as usize)?;
+            }
+            Operation::GenericForLoop { base, jump: offset } => {
+                registers(base.0 as usize, 2)?;
+                jump(offset, 0)?;
+            }
+            Operation::Method { base, table, key } => {
+                registers(base.0 as usize, 2)?;
+                register(table)?;
+                rc(key)?;
+            }
+            Operation::Concat {
+                dest,
+                source,
+                count,
+            } => {
+                register(dest)?;
+                registers(source.0 as usize, count as usize)?;
+            }
+            Operation::GetUpValue { dest, source } => {
+                register(dest)?;
+                upvalue(source.0 as usize)?;
+            }
+            Operation::SetUpValue { dest, source } => {
+                upvalue(dest.0 as usize)?;
+                register(source)?;
+            }
+            Operation::Eq { left, right, .. }
+            | Operation::Less { left, right, .. }
+            | Operation::LessEq { left, right, .. } => {
+                rc(left)?;
+                rc(right)?;
+                jump(1, 0)?;
+            }
+            Operation::Add { dest, left, right }
+            | Operation::Sub { dest, left, right }
+            | Operation::Mul { dest, left, right }
+            | Operation::Div { dest, left, right }
+            | Operation::IDiv { dest, left, right }
+            | Operation::Mod { dest, left, right }
+            | Operation::Pow { dest, left, right }
+            | Operation::BitAnd { dest, left, right }
+            | Operation::BitOr { dest, left, right }
+            | Operation::BitXor { dest, left, right }
+            | Operation::ShiftLeft { dest, left, right }
+            | Operation::ShiftRight { dest, left, right } => {
+                register(dest)?;
+                rc(left)?;
+                rc(right)?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::cell::Cell;
+
+use gc_arena::Collect;
+use thiserror::Error;
+
+use crate::{Callback, CallbackReturn, Context, Function, String, Table, Value};
+
+#[derive(Debug, Copy, Clone, Error)]
+#[error("unknown API version {0}")]
+pub struct UnknownApiVersion(pub i64);
+
+/// A set of versions of a host API table.
+///
+/// Hosts that expose an API table to scripts can register every version of it that they still
+/// support, and then pick which version each script sees with [`ApiVersions::install`]. This lets
+/// the API evolve without breaking scripts written against an older version.
+///
+/// Versions and individual functions can be marked as deprecated. Deprecation warnings are
+/// emitted through [`Context::warn`], the same as the `warn` builtin, once when a deprecated
+/// version is installed and once for the first call to each deprecated function.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ApiVersions<'gc> {
+    name: String<'gc>,
+    versions: Table<'gc>,
+    deprecated: Table<'gc>,
+}
+
+impl<'gc> ApiVersions<'gc> {
+    /// Create an empty set of versions for the API with the given name.
+    ///
+    /// The name is only used in deprecation warnings.
+    pub fn new(ctx: Context<'gc>, name: &str) -> Self {
+        Self {
+            name: ctx.intern(name.as_bytes()),
+            versions: Table::new(&ctx),
+            deprecated: Table::new(&ctx),
+        }
+    }
+
+    /// Register the table for an API version, replacing any table previously registered for the
+    /// same version.
+    pub fn add_version(self, ctx: Context<'gc>, version: i64, api: Table<'gc>) {
+        self.versions.set(ctx, version, api).unwrap();
+    }
+
+    /// Mark an API version as deprecated, with a message explaining what to use instead.
+    pub fn deprecate_version(
+        self,
+        ctx: Context<'gc>,
+        version: i64,
+        message: &str,
+    ) -> Result<(), UnknownApiVersion> {
+        self.get(version).ok_or(UnknownApiVersion(version))?;
+        self.deprecated
+            .set(ctx, version, ctx.intern(message.as_bytes()))
+            .unwrap();
+        Ok(())
+    }
+
+    /// Returns the table for an API version, if it has been registered.
+    pub fn get(self, version: i64) -> Option<Table<'gc>> {
+        match self.versions.get_value(version.into()) {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Returns the newest registered API version.
+    pub fn latest(self) -> Option<i64> {
+        self.versions
+            .iter()
+            .filter_map(|(k, _)| match k {
+                Value::Integer(i) => Some(i),
+                _ => None,
+            })
+            .max()
+    }
+
+    /// Set `env[name]` to the table for the given API version.
+    ///
+    /// If the version is deprecated, a warning is emitted.
+    pub fn install(
+        self,
+        ctx: Context<'gc>,
+        env: Table<'gc>,
+        name: &str,
+        version: i64,
+    ) -> Result<(), UnknownApiVersion> {
+        let api = self.get(version).ok_or(UnknownApiVersion(version))?;
+        if let Value::String(message) = self.deprecated.get_value(version.into()) {
+            ctx.warn(&format!(
+                "{} API version {} is deprecated: {}",
+                self.name.to_str_lossy(),
+                version,
+                message.to_str_lossy(),
+            ));
+        }
+        env.set(ctx, ctx.intern(name.as_bytes()), api).unwrap();
+        Ok(())
+    }
+
+    /// Wrap a function so that a deprecation warning is emitted the first time it is called.
+    ///
+    /// Use this to deprecate individual functions within an API version table.
+    pub fn deprecated_function(
+        ctx: Context<'gc>,
+        name: &str,
+        message: &str,
+        function: Function<'gc>,
+    ) -> Callback<'gc> {
+        let warning = format!("{name} is deprecated: {message}");
+        let warned = Cell::new(false);
+        Callback::from_fn_with(&ctx, function, move |&function, ctx, _, _| {
+            if !warned.replace(true) {
+                ctx.warn(&warning);
+            }
+            Ok(CallbackReturn::Call {
+                function,
+                then: None,
+            })
+        })
+    }
+}
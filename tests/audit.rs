@@ -0,0 +1,70 @@
+use piccolo::{types::UpValueDescriptor, Closure, Lua, StaticError};
+
+#[test]
+fn audit_globals() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local print = print
+                counter = 0
+
+                local function bump(n)
+                    counter = counter + n
+                    return function()
+                        return string.format("%d", counter)
+                    end
+                end
+
+                do
+                    local _ENV = { shadowed = true }
+                    hidden = shadowed
+                end
+
+                print(bump(1)())
+            "#[..],
+        )?;
+
+        let audit = closure.prototype().audit();
+        assert_eq!(
+            audit.global_reads.iter().collect::<Vec<_>>(),
+            ["counter", "print", "string"]
+        );
+        assert_eq!(audit.global_writes.iter().collect::<Vec<_>>(), ["counter"]);
+        assert_eq!(
+            audit.globals().into_iter().collect::<Vec<_>>(),
+            ["counter", "print", "string"]
+        );
+        assert!(!audit.dynamic_access);
+        assert_eq!(audit.upvalues, [UpValueDescriptor::Environment]);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn audit_dynamic_access() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        for source in [
+            &b"local name = 'os'; return _ENV[name]"[..],
+            &b"local env = _ENV; return env.os"[..],
+            &b"return function() _ENV = {} end"[..],
+            &b"return rawget(_ENV, 'os')"[..],
+        ] {
+            let closure = Closure::load(ctx, None, source)?;
+            assert!(closure.prototype().audit().dynamic_access);
+        }
+
+        let closure = Closure::load(ctx, None, &b"return _ENV.os, _ENV['io']"[..])?;
+        let audit = closure.prototype().audit();
+        assert!(!audit.dynamic_access);
+        assert_eq!(audit.global_reads.iter().collect::<Vec<_>>(), ["io", "os"]);
+
+        Ok(())
+    })
+}
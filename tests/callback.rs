@@ -1,7 +1,12 @@
-use gc_arena::Collect;
+use std::iter;
+
+use allocator_api2::vec;
+use gc_arena::{allocator_api::MetricsAlloc, Collect};
 use piccolo::{
-    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor, Function,
-    IntoValue, Lua, Sequence, SequencePoll, Stack, StaticError, String, Thread, Value,
+    sequence::{with_results, Adjust, Call, CollectTable},
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor, Fuel,
+    Function, IntoValue, Lua, Sequence, SequenceExt, SequencePoll, Stack, StackLimits, StaticError,
+    String, Thread, Value,
 };
 
 #[test]
@@ -36,6 +41,128 @@ fn callback() -> Result<(), StaticError> {
     Ok(())
 }
 
+#[test]
+fn intrinsic_callback() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        fn intrinsic<'gc>(
+            ctx: Context<'gc>,
+            _: &mut Fuel,
+            mut stack: Stack<'gc, '_>,
+        ) -> Result<(), Error<'gc>> {
+            let (a, b): (i64, i64) = stack.consume(ctx)?;
+            if b == 0 {
+                return Err("divide by zero".into_value(ctx).into());
+            }
+            stack.replace(ctx, (a / b, a % b));
+            Ok(())
+        }
+
+        let callback = Callback::new_intrinsic(&ctx, intrinsic);
+        assert!(callback.intrinsic().is_some());
+        ctx.set_global("intrinsic", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local a, b = intrinsic(7, 2)
+                assert(a == 3 and b == 1)
+                local t = { intrinsic(9, 4) }
+                assert(#t == 2 and t[1] == 2 and t[2] == 1)
+                assert(not pcall(intrinsic, 1, 0))
+                assert(not pcall(function() return intrinsic(1, 0) + 1 end))
+                local ok, q = pcall(intrinsic, 8, 2)
+                assert(ok and q == 4)
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn builtin_intrinsics() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        for name in ["type", "select", "rawequal"] {
+            let Value::Function(Function::Callback(callback)) = ctx.get_global(name) else {
+                panic!("{} is not a callback", name);
+            };
+            assert!(callback.intrinsic().is_some());
+        }
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                assert(type(1) == "number" and type(nil) == "nil" and type(type) == "function")
+                assert(not pcall(type))
+                assert(select("#") == 0 and select("#", nil, nil) == 2)
+                local t = {select(2, "a", "b", "c")}
+                assert(#t == 2 and t[1] == "b" and t[2] == "c")
+                assert(select(-1, "a", "b") == "b")
+                assert(not pcall(select, 0, "a"))
+                assert(rawequal(t, t) and not rawequal(t, {}))
+                assert(not rawequal(1, "1"))
+                local function last(...) return select(select("#", ...), ...) end
+                assert(last(1, 2, 3) == 3)
+            "##[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn stdlib_intrinsics() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        for (lib, name) in [("math", "floor"), ("string", "len"), ("table", "insert")] {
+            let Value::Table(lib) = ctx.get_global(lib) else {
+                panic!("{} is not loaded", lib);
+            };
+            let Value::Function(Function::Callback(callback)) = lib.get(ctx, name) else {
+                panic!("{} is not a callback", name);
+            };
+            assert!(callback.intrinsic().is_some());
+        }
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                local t = {}
+                table.insert(t, "b")
+                table.insert(t, 1, "a")
+                table.insert(t, "c")
+                assert(#t == 3 and t[1] == "a" and t[2] == "b" and t[3] == "c")
+                assert(select("#", table.insert(t, "d")) == 0)
+                assert(not pcall(table.insert, t, 7, "x"))
+                assert(not pcall(table.insert, t))
+                assert(math.floor(3.5) == 3 and string.len("abc") == 3)
+            "##[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
 #[test]
 fn tail_call_trivial_callback() -> Result<(), StaticError> {
     let mut lua = Lua::core();
@@ -302,3 +429,252 @@ fn resume_with_err() {
         },
     );
 }
+
+#[test]
+fn resume_from_stack() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local sum = 0
+                while true do
+                    local a, b = coroutine.yield(sum)
+                    sum = sum + a * b
+                end
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor);
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let mut values = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        let mut stack = Stack::new(&mut values, 0);
+
+        executor.take_result_into(ctx, &mut stack).unwrap().unwrap();
+        assert_eq!(stack.consume::<i64>(ctx).unwrap(), 0);
+        stack.reserve(2);
+        let capacity = stack.capacity();
+
+        let mut expected = 0;
+        for i in 1..=10 {
+            stack.push_back(Value::Integer(i));
+            stack.push_back(Value::Integer(2));
+            executor.resume_from(ctx, &mut stack).unwrap();
+            assert!(stack.is_empty());
+
+            assert!(executor.step(ctx, &mut Fuel::with(i32::MAX)));
+            executor.take_result_into(ctx, &mut stack).unwrap().unwrap();
+            expected += i * 2;
+            assert_eq!(stack.consume::<i64>(ctx).unwrap(), expected);
+            assert_eq!(stack.capacity(), capacity);
+        }
+
+        assert!(executor.take_result_into(ctx, &mut stack).is_err());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn sequence_combinators() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let chain = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let f: Function = stack.from_front(ctx)?;
+            let g: Function = stack.from_front(ctx)?;
+            let seq = Call(f)
+                .map(|ctx, mut stack| {
+                    let n: i64 = stack.consume(ctx)?;
+                    stack.replace(ctx, n + 1);
+                    Ok(())
+                })
+                .then_call(g)
+                .map_err(|ctx, err| {
+                    format!("chain failed: {}", err.to_value(ctx).display())
+                        .into_value(ctx)
+                        .into()
+                });
+            Ok(CallbackReturn::Sequence(seq.boxed(&ctx)))
+        });
+        ctx.set_global("chain", chain)?;
+
+        let dispatch = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let f: Function = stack.from_front(ctx)?;
+            let seq = Call(f).and_then(|ctx, mut stack| {
+                let next: Function = stack.from_front(ctx)?;
+                Ok(Call(next))
+            });
+            Ok(CallbackReturn::Sequence(seq.boxed(&ctx)))
+        });
+        ctx.set_global("dispatch", dispatch)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function double(x) return x * 2 end
+                local a, b = chain(double, function(y) return y, "done" end, 20)
+                assert(a == 41 and b == "done")
+
+                local ok, err = pcall(chain, function() error("boom", 0) end, print, 1)
+                assert(not ok and err == "chain failed: boom")
+
+                local function add(a, b) return a + b end
+                local co = coroutine.wrap(function()
+                    return dispatch(function(x)
+                        local y = coroutine.yield(x)
+                        return add, y, 1
+                    end, 41)
+                end)
+                assert(co() == 41)
+                assert(co(9) == 10)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn stack_limits() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        ctx.set_stack_limits(StackLimits {
+            max_returns: 16,
+            max_stack_size: 1024,
+        });
+
+        let spew = Callback::from_fn(&ctx, |_, _, mut stack| {
+            let count = stack.get(0).to_integer().unwrap();
+            stack.clear();
+            for i in 0..count {
+                stack.push_back(Value::Integer(i));
+            }
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("spew", spew)?;
+
+        let capacity = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            stack.clear();
+            let remaining = stack.remaining_capacity() as i64;
+            stack.replace(ctx, remaining);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("capacity", capacity)?;
+
+        // The limit is enforced as the stack grows, so these finish without running out of memory.
+        let flood = Callback::from_fn(&ctx, |_, _, mut stack| {
+            stack.resize(usize::MAX);
+            stack.clear();
+            stack.extend(iter::repeat(Value::Integer(0)));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("flood", flood)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(select('#', spew(16)) == 16)
+                local ok, err = pcall(spew, 17)
+                assert(not ok and string.find(tostring(err), "too many values"))
+                ok, err = pcall(spew, 2000)
+                assert(not ok)
+                assert(capacity() == 16)
+                ok, err = pcall(flood)
+                assert(not ok and string.find(tostring(err), "stack overflow"))
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn call_continuations() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let pack = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let count: Option<i64> = stack.from_front(ctx)?;
+            let function: Function = stack.from_front(ctx)?;
+            let then = match count {
+                Some(count) => CollectTable::first(count as usize),
+                None => CollectTable::all(),
+            };
+            Ok(CallbackReturn::Call {
+                function,
+                then: Some(BoxSequence::new(&ctx, then)),
+            })
+        });
+        ctx.set_global("pack", pack)?;
+
+        let two = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let function: Function = stack.from_front(ctx)?;
+            Ok(CallbackReturn::Call {
+                function,
+                then: Some(BoxSequence::new(&ctx, Adjust(2))),
+            })
+        });
+        ctx.set_global("two", two)?;
+
+        let sum = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let function: Function = stack.from_front(ctx)?;
+            Ok(CallbackReturn::Call {
+                function,
+                then: Some(with_results(&ctx, |_, _, (a, b): (i64, Option<i64>)| {
+                    Ok((a + b.unwrap_or(0), "summed"))
+                })),
+            })
+        });
+        ctx.set_global("sum", sum)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                local t = pack(nil, function(...) return ... end, 1, nil, 3)
+                assert(t.n == 3 and t[1] == 1 and t[2] == nil and t[3] == 3)
+                t = pack(2, function() return "a", "b", "c" end)
+                assert(t.n == 2 and t[1] == "a" and t[2] == "b" and t[3] == nil)
+                t = pack(2, function() end)
+                assert(t.n == 2 and t[1] == nil)
+
+                assert(select("#", two(function() return 1, 2, 3 end)) == 2)
+                local a, b = two(function() return 1 end)
+                assert(a == 1 and b == nil and select("#", two(function() end)) == 2)
+
+                local n, s = sum(function() return 40, 2 end)
+                assert(n == 42 and s == "summed")
+                assert(sum(function() return 1 end) == 1)
+                assert(not pcall(sum, function() return "x" end))
+            "##[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
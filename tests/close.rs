@@ -0,0 +1,62 @@
+use piccolo::{
+    meta_ops, Callback, CallbackReturn, Closure, Executor, Lua, MetaMethod, StaticError, Table,
+    Value,
+};
+
+#[test]
+fn const_assignment() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let compiles = |source: &str| Closure::load(ctx, None, source.as_bytes()).is_ok();
+
+        assert!(compiles("local a <const> = 1; return a + 1"));
+        assert!(compiles("local a <close> = nil"));
+        assert!(compiles("local a <const> = 1; do local a = 1; a = 2 end"));
+
+        assert!(!compiles("local a <const> = 1; a = 2"));
+        assert!(!compiles("local a <close> = nil; a = 2"));
+        assert!(!compiles(
+            "local a <const> = 1; return function() a = 2 end"
+        ));
+        assert!(!compiles("local a <close>, b <close> = nil, nil"));
+        assert!(!compiles("local a <unknown> = 1"));
+    });
+}
+
+#[test]
+fn type_metatable_close() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let mt = Table::new(&ctx);
+        mt.set(
+            ctx,
+            MetaMethod::Close,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let n: i64 = stack.consume(ctx)?;
+                ctx.set_global("closed", n)?;
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        meta_ops::set_metatable(ctx, Value::Integer(0), Some(mt));
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                do
+                    local n <close> = 7
+                end
+                assert(closed == 7)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    Ok(())
+}
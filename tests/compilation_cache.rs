@@ -0,0 +1,37 @@
+use piccolo::{CompilationCache, Executor, Lua, PrototypeError, StaticError};
+
+#[test]
+fn reuse_across_instances() -> Result<(), StaticError> {
+    let mut cache = CompilationCache::new();
+    let source = "local function double(x) return x * 2 end return double(...)";
+
+    for _ in 0..2 {
+        let mut lua = Lua::core();
+        for arg in [1, 2] {
+            let executor = lua.try_enter(|ctx| {
+                let closure = cache.load_with_env(ctx, Some("double"), source, ctx.globals())?;
+                Ok(ctx.stash(Executor::start(ctx, closure.into(), arg)))
+            })?;
+            assert_eq!(lua.execute::<i64>(&executor)?, arg * 2);
+        }
+    }
+    assert_eq!((cache.len(), cache.hits(), cache.misses()), (1, 3, 1));
+
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        cache.compile(ctx, "changed", "return 1").unwrap();
+        assert_eq!(cache.len(), 2);
+
+        for _ in 0..2 {
+            assert!(matches!(
+                cache.compile(ctx, "broken", "return +"),
+                Err(PrototypeError::Parser(_))
+            ));
+        }
+        assert_eq!((cache.len(), cache.misses()), (2, 4));
+    });
+
+    cache.clear();
+    assert!(cache.is_empty());
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use piccolo::{
+    compiler::{CompileErrorKind, FunctionRef, LineNumber},
+    FunctionPrototype, Lua, PrototypeError,
+};
+
+fn compile_error(source: &str) -> (CompileErrorKind, FunctionRef<String>, LineNumber) {
+    let mut lua = Lua::core();
+    lua.enter(
+        |ctx| match FunctionPrototype::compile(ctx, "compile_error.lua", source.as_bytes()) {
+            Err(PrototypeError::Compiler(err)) => (err.kind, err.function, err.line_number),
+            Err(err) => panic!("unexpected error {err}"),
+            Ok(_) => panic!("compilation should fail"),
+        },
+    )
+}
+
+#[test]
+fn too_many_locals() {
+    let locals = (0..300)
+        .map(|i| format!("local l{i} = {i}\n"))
+        .collect::<String>();
+    let source = format!("local x = 1\nlocal function f()\n{locals}end\n");
+    let (kind, function, line_number) = compile_error(&source);
+    assert!(matches!(kind, CompileErrorKind::Registers));
+    assert!(matches!(function, FunctionRef::Named(name, LineNumber(1)) if name == "f"));
+    assert!(line_number.0 > 1 && line_number.0 < 302);
+    assert!(kind.to_string().contains("too complex"));
+}
+
+#[test]
+fn too_many_arguments() {
+    let arguments = (0..300).map(|i| i.to_string()).collect::<Vec<_>>();
+    let source = format!("local x = 1\n\nprint({})\n", arguments.join(", "));
+    let (kind, function, line_number) = compile_error(&source);
+    assert!(matches!(kind, CompileErrorKind::Registers));
+    assert!(matches!(function, FunctionRef::Chunk));
+    assert_eq!(line_number, LineNumber(2));
+}
+
+#[test]
+fn register_limit() {
+    // Exactly filling the register file must not overflow.
+    let mut lua = Lua::core();
+    let arguments = (0..254).map(|i| i.to_string()).collect::<Vec<_>>();
+    let source = format!("local x = select('#', {})\nreturn x", arguments.join(", "));
+    lua.enter(|ctx| {
+        FunctionPrototype::compile(ctx, "register_limit.lua", source.as_bytes()).unwrap();
+    });
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "conformance")]
+
+use std::fs::read_dir;
+
+use piccolo::{
+    conformance::{Script, SCRIPTS},
+    Lua,
+};
+
+#[test]
+fn corpus_is_complete() {
+    let mut names = read_dir("./tests/scripts")
+        .expect("could not list dir contents")
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".lua"))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    assert_eq!(
+        SCRIPTS.iter().map(|s| s.name).collect::<Vec<_>>(),
+        names,
+        "`conformance::SCRIPTS` must list every script in tests/scripts"
+    );
+    assert_eq!(Script::find("closure.lua"), None);
+    assert!(Script::find("metaops.lua").is_some());
+}
+
+#[test]
+fn corpus_passes() {
+    for script in SCRIPTS {
+        if let Err(err) = script.run(&mut Lua::full()) {
+            panic!("{} failed: {:?}", script.name, err);
+        }
+    }
+}
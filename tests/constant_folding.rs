@@ -0,0 +1,37 @@
+use piccolo::{opcode::Operation, Closure, Executor, Lua, StaticError};
+
+#[test]
+fn constant_folding() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                if false then
+                    local f = function() end
+                    error("unreachable")
+                elseif 2 + 3 * 4 ~= 14 then
+                    error("unreachable")
+                end
+                return 2 + 3 * 4, #"literal", -(2 ^ 3)
+            "#[..],
+        )?;
+
+        // Everything is resolved at compile time, leaving only the constants to return.
+        let proto = closure.prototype();
+        assert!(proto.opcodes.iter().all(|op| matches!(
+            op.decode(),
+            Operation::LoadConstant { .. } | Operation::Return { .. }
+        )));
+        assert!(proto.prototypes.is_empty());
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (a, b, c) = lua.execute::<(i64, i64, f64)>(&executor)?;
+    assert_eq!((a, b, c), (14, 7, -8.0));
+
+    Ok(())
+}
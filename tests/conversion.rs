@@ -1,4 +1,9 @@
-use piccolo::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Lua, Table, Value};
+use std::{borrow::Cow, path::PathBuf, string::String as StdString};
+
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, FromMultiValue, FromValue, Fuel, IntoMultiValue,
+    IntoValue, Lua, LuaResult, OwnedValue, OwnedValueError, StaticError, Table, Value,
+};
 
 #[test]
 fn test_conversions() {
@@ -57,3 +62,202 @@ fn test_conversions() {
         assert_eq!((a, b, c), (2, false, "goodbye".to_owned()));
     });
 }
+
+#[test]
+fn test_value_accessors() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        assert!(Value::Table(table).as_table().unwrap() == table);
+        assert!(Value::Nil.as_table().is_err());
+
+        assert!(Value::Boolean(true).as_bool().unwrap());
+        assert!(Value::Integer(1).as_bool().is_err());
+
+        assert_eq!(Value::Integer(3).as_int().unwrap(), 3);
+        assert_eq!(Value::Number(3.0).as_int().unwrap(), 3);
+        assert!(Value::Number(3.5).as_int().is_err());
+        assert!("3".into_value(ctx).as_int().is_err());
+        assert_eq!("3".into_value(ctx).coerce_int().unwrap(), 3);
+        assert_eq!(" 0x10 ".into_value(ctx).coerce_int().unwrap(), 16);
+        assert!("3.5".into_value(ctx).coerce_int().is_err());
+
+        assert_eq!(Value::Integer(2).as_number().unwrap(), 2.0);
+        assert!("2.5".into_value(ctx).as_number().is_err());
+        assert_eq!("2.5".into_value(ctx).coerce_number().unwrap(), 2.5);
+
+        assert_eq!("hello".into_value(ctx).as_str().unwrap(), "hello");
+        assert!(Value::Integer(1).as_str().is_err());
+        assert!(Value::Integer(1).as_string().is_err());
+        assert!(Value::Integer(1).coerce_string(ctx).unwrap() == b"1");
+
+        let err = Value::Boolean(false).as_table().unwrap_err();
+        assert_eq!(err.expected, "table");
+        assert_eq!(err.found, "boolean");
+    });
+}
+
+#[test]
+fn test_extra_conversions() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert!(matches!(Value::try_from(5u64), Ok(Value::Integer(5))));
+        assert!(Value::try_from(u64::MAX).is_err());
+        assert!(matches!(Value::try_from(7usize), Ok(Value::Integer(7))));
+        assert!(u64::from_value(ctx, Value::Integer(-1)).is_err());
+        assert!(matches!((-7isize).into_value(ctx), Value::Integer(-7)));
+        assert_eq!(usize::from_value(ctx, Value::Integer(3)).unwrap(), 3);
+        assert!(usize::from_value(ctx, Value::Integer(-1)).is_err());
+
+        assert!(matches!(
+            Cow::Borrowed("borrowed").into_value(ctx),
+            Value::String(s) if s == b"borrowed"
+        ));
+        assert!(matches!(
+            Cow::<str>::Owned("owned".to_owned()).into_value(ctx),
+            Value::String(s) if s == b"owned"
+        ));
+
+        assert!(matches!('é'.into_value(ctx), Value::String(s) if s == "é".as_bytes()));
+        assert_eq!(char::from_value(ctx, "é".into_value(ctx)).unwrap(), 'é');
+        assert!(char::from_value(ctx, "ab".into_value(ctx)).is_err());
+        assert!(char::from_value(ctx, "".into_value(ctx)).is_err());
+
+        let path = PathBuf::from("some/dir/file.lua");
+        let value = path.as_path().into_value(ctx);
+        assert!(matches!(value, Value::String(s) if s == b"some/dir/file.lua"));
+        assert_eq!(PathBuf::from_value(ctx, value).unwrap(), path);
+
+        let d = Duration::from_millis(1500);
+        assert!(matches!(d.into_value(ctx), Value::Number(n) if n == 1.5));
+        assert_eq!(Duration::from_value(ctx, Value::Number(1.5)).unwrap(), d);
+        assert!(Duration::from_value(ctx, Value::Integer(-1)).is_err());
+
+        let t = UNIX_EPOCH + Duration::from_secs(86400);
+        assert!(matches!(t.into_value(ctx), Value::Number(n) if n == 86400.0));
+        assert_eq!(
+            SystemTime::from_value(ctx, Value::Integer(86400)).unwrap(),
+            t
+        );
+        let t = UNIX_EPOCH - Duration::from_secs(60);
+        assert!(matches!(t.into_value(ctx), Value::Number(n) if n == -60.0));
+        assert_eq!(SystemTime::from_value(ctx, Value::Integer(-60)).unwrap(), t);
+        assert!(SystemTime::from_value(ctx, Value::Number(f64::NAN)).is_err());
+
+        let arr = <[i64; 3]>::from_value(ctx, [1, 2, 3].into_value(ctx)).unwrap();
+        assert_eq!(arr, [1, 2, 3]);
+    });
+}
+
+#[test]
+fn test_lua_result() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let parse = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let s: StdString = stack.consume(ctx)?;
+            let result: LuaResult<i64, StdString> =
+                s.parse::<i64>().map_err(|e| e.to_string()).into();
+            stack.replace(ctx, result);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("parse", parse)?;
+
+        let v = LuaResult::<(i64, i64), &'static str>::ok((1, 2))
+            .into_multi_value(ctx)
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            v.as_slice(),
+            [Value::Integer(1), Value::Integer(2)]
+        ));
+
+        let v = LuaResult::<i64, &'static str>::err("bad")
+            .into_multi_value(ctx)
+            .collect::<Vec<_>>();
+        assert!(matches!(v.as_slice(), [Value::Nil, Value::String(s)] if s == "bad"));
+
+        let r = LuaResult::<Option<i64>, StdString>::from_multi_value(
+            ctx,
+            [Value::Nil, "error".into_value(ctx)].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(r.into_result(), Err("error".to_owned()));
+
+        let r =
+            LuaResult::<Option<i64>, StdString>::from_multi_value(ctx, [Value::Nil].into_iter())
+                .unwrap();
+        assert_eq!(r.into_result(), Ok(None));
+
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local a, err_a = parse("12")
+                assert(a == 12 and err_a == nil)
+                local b, err_b = parse("twelve")
+                assert(b == nil and type(err_b) == "string")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn test_owned_value() {
+    let mut lua = Lua::core();
+
+    let owned = lua.enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local shared = { 1, 2 }
+                return { "a", "b", nested = { shared, shared }, flag = true, ratio = 0.5 }
+            "#[..],
+        )
+        .unwrap();
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.step(ctx, &mut Fuel::with(i32::MAX));
+        let value = executor.take_result::<Value>(ctx).unwrap().unwrap();
+        OwnedValue::from_value(value).unwrap()
+    });
+
+    let OwnedValue::Map(entries) = &owned else {
+        panic!("expected a map");
+    };
+    assert_eq!(entries.len(), 5);
+    let shared = OwnedValue::Array(vec![1.into(), 2.into()]);
+    assert!(entries.contains(&(
+        "nested".into(),
+        OwnedValue::Array(vec![shared.clone(), shared])
+    )));
+    assert!(entries.contains(&(1.into(), "a".into())));
+    assert!(entries.contains(&("ratio".into(), 0.5.into())));
+
+    // Values move freely between threads and `Lua` instances.
+    let owned = std::thread::spawn(move || owned).join().unwrap();
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let table: Table = Table::from_value(ctx, owned.clone().into_value(ctx)).unwrap();
+        assert_eq!(table.get_as::<_, StdString>(ctx, 1).unwrap(), "a");
+        assert_eq!(table.length(), 2);
+        assert_eq!(OwnedValue::from_value(table.into()).unwrap(), owned);
+
+        table.set(ctx, "self", table).unwrap();
+        assert!(matches!(
+            OwnedValue::from_value(table.into()),
+            Err(OwnedValueError::Cycle)
+        ));
+        assert!(matches!(
+            OwnedValue::from_value(ctx.get_global("print")),
+            Err(OwnedValueError::Unsupported("function"))
+        ));
+        assert!(<OwnedValue as FromValue>::from_value(ctx, ctx.get_global("print")).is_err());
+    });
+}
@@ -0,0 +1,82 @@
+use piccolo::{
+    dump::{dump, undump},
+    types::{RegisterIndex, UpValueIndex},
+    Closure, Lua, StaticError,
+};
+
+#[test]
+fn local_and_upvalue_names() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local player = {}
+                local function update(dt)
+                    local speed = 2
+                    player.x = (player.x or 0) + speed * dt
+                end
+                for i = 1, 3 do
+                    update(i)
+                end
+                print(player.x)
+            "#[..],
+        )?;
+        let proto = closure.prototype();
+
+        let locals = proto
+            .local_variables
+            .iter()
+            .map(|l| l.name.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(locals, ["player", "update", "i"]);
+        for local in proto.local_variables.iter() {
+            assert!(local.start_pc <= local.end_pc);
+            assert!(local.end_pc <= proto.opcodes.len());
+        }
+
+        let player = &proto.local_variables[0];
+        assert_eq!(player.register, RegisterIndex(0));
+        assert_eq!(
+            proto.local_name(RegisterIndex(0), player.start_pc),
+            Some(player.name)
+        );
+        assert_eq!(proto.local_name(RegisterIndex(0), 0), None);
+
+        // The loop variable goes out of scope once the loop ends.
+        let i = &proto.local_variables[2];
+        assert!(i.end_pc < proto.opcodes.len());
+        assert_eq!(proto.local_name(i.register, i.end_pc), None);
+
+        assert_eq!(
+            proto.upvalue_name(UpValueIndex(0)).unwrap().as_bytes(),
+            b"_ENV"
+        );
+
+        let update = &proto.prototypes[0];
+        let locals = update
+            .local_variables
+            .iter()
+            .map(|l| l.name.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(locals, ["dt", "speed"]);
+        assert_eq!(
+            update.upvalue_name(UpValueIndex(0)).unwrap().as_bytes(),
+            b"player"
+        );
+
+        // Debug information survives a binary chunk round trip unless it is stripped.
+        let loaded = undump(ctx, &dump(&proto, false)).unwrap();
+        assert_eq!(loaded.local_variables.len(), 3);
+        assert_eq!(loaded.prototypes[0].upvalue_names.len(), 1);
+
+        let stripped = undump(ctx, &dump(&proto, true)).unwrap();
+        assert!(stripped.local_variables.is_empty());
+        assert!(stripped.upvalue_names.is_empty());
+        assert_eq!(stripped.upvalue_name(UpValueIndex(0)), None);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,92 @@
+use allocator_api2::vec;
+use gc_arena::allocator_api::MetricsAlloc;
+use piccolo::{
+    dump::UndumpError,
+    opcode::{OpCode, Operation},
+    types::Opt254,
+    Closure, Executor, FunctionPrototype, Lua, PrototypeError, StaticError, VerifyError,
+};
+
+const SOURCE: &str = r##"
+    local count = 0
+    local function add(n, ...)
+        count = count + n + select("#", ...)
+        return count
+    end
+    return add(1, "a") + add(2), tostring(1.5) .. "x"
+"##;
+
+#[test]
+fn binary_round_trip() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let proto = FunctionPrototype::compile(ctx, "round_trip", SOURCE.as_bytes())?;
+        let mut chunk = Vec::new();
+        proto.dump_to(&mut chunk, false).unwrap();
+        assert_eq!(chunk, proto.dump(false));
+
+        let closure = Closure::load_binary(ctx, &chunk, ctx.globals())?;
+        assert_eq!(closure.prototype().chunk_name.as_bytes(), b"round_trip");
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    let (sum, string) = lua.execute::<(i64, String)>(&executor)?;
+    assert_eq!((sum, string.as_str()), (6, "1.5x"));
+    Ok(())
+}
+
+#[test]
+fn corrupt_chunks() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let proto = FunctionPrototype::compile(ctx, "corrupt", SOURCE.as_bytes()).unwrap();
+        let chunk = proto.dump(true);
+
+        let mut bad_version = chunk.clone();
+        bad_version[piccolo::dump::SIGNATURE.len()] = 0xff;
+        assert!(matches!(
+            Closure::load_binary(ctx, &bad_version, ctx.globals()),
+            Err(PrototypeError::Undump(UndumpError::BadVersion(0xff)))
+        ));
+
+        for len in 0..chunk.len() {
+            assert!(Closure::load_binary(ctx, &chunk[..len], ctx.globals()).is_err());
+        }
+
+        // A corrupted chunk either fails to load or passes the verifier, and never panics.
+        for i in piccolo::dump::SIGNATURE.len() + 1..chunk.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupt = chunk.clone();
+                corrupt[i] ^= flip;
+                let _ = Closure::load_binary(ctx, &corrupt, ctx.globals());
+            }
+        }
+    });
+}
+
+#[test]
+fn unverifiable_chunks() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        // A jump from the final opcode with no offset lands just past the end of the function.
+        let mut proto = FunctionPrototype::compile(ctx, "unverifiable", SOURCE.as_bytes()).unwrap();
+        let last = proto.opcodes.len() - 1;
+        proto.opcodes[last] = OpCode::encode(Operation::Jump {
+            offset: 0,
+            close_upvalues: Opt254::none(),
+        });
+        assert!(matches!(
+            Closure::load_binary(ctx, &proto.dump(true), ctx.globals()),
+            Err(PrototypeError::Verify(VerifyError::Jump { pc })) if pc == last
+        ));
+
+        let mut proto = FunctionPrototype::compile(ctx, "unverifiable", &b""[..]).unwrap();
+        proto.opcodes = vec::Vec::new_in(MetricsAlloc::new(&ctx)).into_boxed_slice();
+        assert!(matches!(
+            Closure::load_binary(ctx, &proto.dump(true), ctx.globals()),
+            Err(PrototypeError::Verify(VerifyError::Empty))
+        ));
+    });
+}
@@ -1,6 +1,8 @@
 mod sizes;
 
-use piccolo::{error::LuaError, Callback, Closure, Error, Executor, Lua, StaticError, Value};
+use piccolo::{
+    error::LuaError, Callback, Closure, Error, Executor, Lua, StaticError, VMError, Value,
+};
 use thiserror::Error;
 
 #[test]
@@ -25,13 +27,47 @@ fn error_unwind() -> Result<(), StaticError> {
     lua.finish(&executor);
     lua.try_enter(|ctx| {
         match ctx.fetch(&executor).take_result::<()>(ctx)? {
-            Err(Error::Lua(LuaError(Value::String(s)))) => assert!(s == "test error"),
+            Err(Error::Lua(LuaError(Value::String(s)))) => {
+                assert!(s == "<anonymous>:3: test error")
+            }
             _ => panic!("wrong error returned"),
         }
         Ok(())
     })
 }
 
+#[test]
+fn runtime_error_position() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("@script.lua"),
+            &br#"
+                local t = nil
+                local function f()
+                    return t.field
+                end
+                f()
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    match lua.execute::<()>(&executor) {
+        Err(StaticError::Runtime(err)) => {
+            assert_eq!(
+                err.to_string(),
+                "script.lua:4: could not index into a nil value"
+            );
+            assert!(err.downcast::<VMError>().is_some());
+        }
+        _ => panic!("wrong error returned"),
+    }
+    Ok(())
+}
+
 #[test]
 fn error_tostring() -> Result<(), StaticError> {
     let mut lua = Lua::core();
@@ -0,0 +1,90 @@
+use piccolo::{
+    ext::{ExtOpcodeError, MAX_EXT_OPCODES},
+    Closure, Context, Error, Executor, Fuel, Lua, Stack, StaticError,
+};
+
+fn add<'gc>(ctx: Context<'gc>, _: &mut Fuel, mut stack: Stack<'gc, '_>) -> Result<(), Error<'gc>> {
+    let (a, b): (i64, i64) = stack.consume(ctx)?;
+    stack.replace(ctx, a + b);
+    Ok(())
+}
+
+fn pair<'gc>(ctx: Context<'gc>, _: &mut Fuel, mut stack: Stack<'gc, '_>) -> Result<(), Error<'gc>> {
+    let a: i64 = stack.consume(ctx)?;
+    stack.replace(ctx, (a, a * 2));
+    Ok(())
+}
+
+#[test]
+fn ext_opcodes() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let before = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return fast_add(1, 2)"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.try_enter(|ctx| {
+        assert_eq!(ctx.ext_opcodes().register(ctx, "fast_add", add)?, 0);
+        assert_eq!(ctx.ext_opcodes().register(ctx, "pair", pair)?, 1);
+        assert!(matches!(
+            ctx.ext_opcodes().register(ctx, "pair", pair),
+            Err(ExtOpcodeError::AlreadyRegistered(_))
+        ));
+        Ok(())
+    })?;
+
+    // Chunks compiled before registration still look up the global.
+    assert!(lua.execute::<i64>(&before).is_err());
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                assert(fast_add == nil)
+                assert(fast_add(40, 2) == 42)
+
+                local a, b = pair(3)
+                assert(a == 3 and b == 6)
+                local t = {pair(5)}
+                assert(#t == 2 and t[2] == 10)
+                assert(select("#", pair(1)) == 2)
+
+                local ok, err = pcall(function() return fast_add("x", 1) end)
+                assert(not ok)
+
+                do
+                    local fast_add = function() return "shadowed" end
+                    assert(fast_add(1, 2) == "shadowed")
+                end
+
+                local function tail(n) return fast_add(n, 1) end
+                assert(tail(9) == 10)
+
+                return fast_add(1, 1)
+            "##[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    assert_eq!(lua.execute::<i64>(&executor)?, 2);
+
+    Ok(())
+}
+
+#[test]
+fn ext_opcode_limit() {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        for i in 0..MAX_EXT_OPCODES {
+            ctx.ext_opcodes()
+                .register(ctx, &format!("ext{}", i), add)
+                .unwrap();
+        }
+        assert!(matches!(
+            ctx.ext_opcodes().register(ctx, "one_more", add),
+            Err(ExtOpcodeError::TooMany)
+        ));
+        assert_eq!(ctx.ext_opcodes().names().len(), MAX_EXT_OPCODES);
+    });
+}
@@ -0,0 +1,219 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{Closure, Executor, Lua, StaticError, UserData, Value, YieldPolicy};
+
+fn run(lua: &mut Lua, code: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, code.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn table_finalizer() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            finalized = {}
+            local mt = { __gc = function(t) finalized[#finalized + 1] = t.name end }
+            setmetatable({ name = "a" }, mt)
+            setmetatable({ name = "b" }, mt)
+            live = setmetatable({ name = "live" }, mt)
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    run(
+        &mut lua,
+        r#"
+            assert(#finalized == 2)
+            assert(finalized[1] ~= "live" and finalized[2] ~= "live")
+        "#,
+    )
+}
+
+#[test]
+fn finalize_once() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            count = 0
+            setmetatable({}, { __gc = function(t)
+                count = count + 1
+                resurrected = t
+            end })
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    run(
+        &mut lua,
+        r#"
+            assert(count == 1)
+            assert(type(resurrected) == "table")
+            resurrected = nil
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    run(
+        &mut lua,
+        r#"
+            assert(count == 1)
+        "#,
+    )
+}
+
+#[test]
+fn finalizer_set_late() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            count = 0
+            -- `__gc` must be present when the metatable is set.
+            local mt = {}
+            setmetatable({}, mt)
+            mt.__gc = function() count = count + 1 end
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    run(
+        &mut lua,
+        r#"
+            assert(count == 0)
+        "#,
+    )
+}
+
+#[test]
+fn finalizer_errors_ignored() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            count = 0
+            local mt = { __gc = function()
+                count = count + 1
+                error("finalizer error")
+            end }
+            setmetatable({}, mt)
+            setmetatable({}, mt)
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    run(
+        &mut lua,
+        r#"
+            assert(count == 2)
+        "#,
+    )
+}
+
+#[test]
+fn finalizer_fuel() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    lua.enter(|ctx| {
+        let warnings = warnings.clone();
+        ctx.set_warning_handler(move |msg| warnings.borrow_mut().push(msg.to_owned()));
+        ctx.set_finalizer_fuel(10_000);
+    });
+
+    run(
+        &mut lua,
+        r#"
+            count = 0
+            setmetatable({}, { __gc = function() while true do end end })
+            setmetatable({}, { __gc = function() count = count + 1 end })
+        "#,
+    )?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    assert_eq!(*warnings.borrow(), ["__gc metamethod ran out of fuel"]);
+    run(
+        &mut lua,
+        r#"
+            assert(count == 1)
+        "#,
+    )
+}
+
+#[test]
+fn finalizer_yield_policy() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let code = r#"
+        count = 0
+        setmetatable({}, { __gc = function()
+            coroutine.yield()
+            count = count + 1
+        end })
+    "#;
+
+    run(&mut lua, code)?;
+    lua.gc_collect();
+    lua.run_finalizers();
+    run(&mut lua, "assert(count == 0)")?;
+
+    lua.enter(|ctx| ctx.set_yield_policy(YieldPolicy::Resume));
+    run(&mut lua, code)?;
+    lua.gc_collect();
+    lua.run_finalizers();
+    run(&mut lua, "assert(count == 1)")
+}
+
+#[test]
+fn userdata_finalizer() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            finalized = nil
+            mt = { __gc = function(u) finalized = u end }
+        "#,
+    )?;
+
+    lua.try_enter(|ctx| {
+        let Value::Table(mt) = ctx.get_global("mt") else {
+            panic!("metatable missing");
+        };
+        let ud = UserData::new_static(&ctx, 42i64);
+        ud.apply_metatable(ctx, Some(mt));
+        Ok(())
+    })?;
+
+    lua.gc_collect();
+    lua.run_finalizers();
+
+    lua.try_enter(|ctx| {
+        let Value::UserData(ud) = ctx.get_global("finalized") else {
+            panic!("userdata was not finalized");
+        };
+        assert_eq!(*ud.downcast_static::<i64>().unwrap(), 42);
+        Ok(())
+    })
+}
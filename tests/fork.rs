@@ -0,0 +1,105 @@
+use piccolo::{Closure, Executor, Lua, StaticError, Value};
+
+#[test]
+fn fork_overlay() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        ctx.set_global("existing", 1)?;
+        Ok(())
+    })?;
+
+    let (executor, overlay) = lua.try_enter(|ctx| {
+        let overlay = ctx.fork();
+        let closure = Closure::load_with_env(
+            ctx,
+            None,
+            &br#"
+                assert(existing == 1)
+                assert(type(tostring) == "function")
+                existing = 2
+                created = "new"
+                assert(existing == 2 and created == "new")
+            "#[..],
+            overlay,
+        )?;
+        Ok((
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+            ctx.stash(overlay),
+        ))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        // The real globals are untouched.
+        assert!(matches!(ctx.get_global("existing"), Value::Integer(1)));
+        assert!(ctx.get_global("created").is_nil());
+
+        // The overlay captured everything that was assigned.
+        let overlay = ctx.fetch(&overlay);
+        assert!(matches!(overlay.get(ctx, "existing"), Value::Integer(2)));
+        assert!(matches!(overlay.get(ctx, "created"), Value::String(s) if s == "new"));
+
+        ctx.commit_fork(overlay);
+        assert!(matches!(ctx.get_global("existing"), Value::Integer(2)));
+        assert!(matches!(ctx.get_global("created"), Value::String(s) if s == "new"));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn shadow_globals_capture() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (executor, shadow) = lua.try_enter(|ctx| {
+        let shadow = ctx.shadow_globals();
+        let closure = Closure::load_with_env(
+            ctx,
+            None,
+            &br#"
+                function plugin_init() return "init" end
+                local hidden = 1
+                _G.via_g = 2
+                rawset(_G, "via_rawset", 3)
+                load("escaped = 4")()
+                assert(not pcall(setmetatable, _G, nil))
+                assert(getmetatable(_G) == false)
+                assert(plugin_init() == "init")
+                assert(type(tostring) == "function")
+            "#[..],
+            shadow,
+        )?;
+        Ok((
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+            ctx.stash(shadow),
+        ))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        for name in ["plugin_init", "via_g", "via_rawset"] {
+            assert!(ctx.get_global(name).is_nil());
+        }
+        // Chunks loaded without an environment use the real globals.
+        assert!(matches!(ctx.get_global("escaped"), Value::Integer(4)));
+
+        let captured = ctx.captured_globals(ctx.fetch(&shadow));
+        assert!(matches!(
+            captured.get(ctx, "plugin_init"),
+            Value::Function(_)
+        ));
+        assert!(matches!(captured.get(ctx, "via_g"), Value::Integer(2)));
+        assert!(matches!(captured.get(ctx, "via_rawset"), Value::Integer(3)));
+
+        let mut count = 0;
+        for _ in captured {
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    });
+
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use piccolo::{Callback, CallbackReturn, Executor, Function, Lua, StaticError, Variadic};
+use std::rc::Rc;
+
+use piccolo::{
+    CallError, Callback, CallbackReturn, Closure, Context, Error, Executor, FromValue, Fuel,
+    Function, Lua, StaticError, Value, Variadic, YieldPolicy,
+};
 
 #[test]
 fn function_compose_bind() -> Result<(), StaticError> {
@@ -42,3 +47,121 @@ fn function_compose_bind() -> Result<(), StaticError> {
     assert_eq!(lua.execute::<i64>(&executor)?, 33);
     Ok(())
 }
+
+#[test]
+fn function_call_with_fuel() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return function(a, b)
+                    local co = coroutine.create(function(x) coroutine.yield(x * 2) end)
+                    local _, doubled = coroutine.resume(co, a)
+                    return doubled + b, "done"
+                end,
+                function() coroutine.yield(1) end,
+                function() while true do end end,
+                function() error("boom", 0) end
+            "#[..],
+        )?;
+        let values = Function::from(closure)
+            .call_with_fuel(ctx, (), &mut Fuel::with(1000))
+            .unwrap();
+        let [add, yields, spins, errors]: [Function; 4] = values
+            .into_iter()
+            .map(|v| Function::from_value(ctx, v).unwrap())
+            .collect::<Vec<_>>()
+            .try_into()
+            .unwrap();
+
+        let mut fuel = Fuel::with(1000);
+        let results = add.call_with_fuel(ctx, (20, 2), &mut fuel).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0], Value::Integer(42)));
+        assert_eq!(results[1].display().to_string(), "done");
+        assert!(fuel.remaining() < 1000);
+
+        assert!(matches!(
+            yields.call_with_fuel(ctx, (), &mut Fuel::with(1000)),
+            Err(CallError::Yielded)
+        ));
+        assert!(matches!(
+            spins.call_with_fuel(ctx, (), &mut Fuel::with(1000)),
+            Err(CallError::OutOfFuel)
+        ));
+        match errors.call_with_fuel(ctx, (), &mut Fuel::with(1000)) {
+            Err(CallError::Error(err)) => assert_eq!(err.to_string(), "lua error: boom"),
+            _ => panic!("expected an error"),
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn function_yield_policy() -> Result<(), StaticError> {
+    fn double<'gc>(_: Context<'gc>, values: &[Value<'gc>]) -> Result<Vec<Value<'gc>>, Error<'gc>> {
+        match values {
+            [Value::Integer(i)] => Ok(vec![Value::Integer(i * 2)]),
+            _ => Err(Value::Integer(-1).into()),
+        }
+    }
+
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return function(n)
+                    local a = coroutine.yield(n)
+                    local b = coroutine.yield(n + 1)
+                    return a, b
+                end
+            "#[..],
+        )?;
+        let yields = Function::from_value(
+            ctx,
+            Function::from(closure)
+                .call_with_fuel(ctx, (), &mut Fuel::with(1000))
+                .unwrap()[0],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            yields.call_with_fuel(ctx, 1, &mut Fuel::with(1000)),
+            Err(CallError::Yielded)
+        ));
+
+        ctx.set_yield_policy(YieldPolicy::Resume);
+        let results = yields
+            .call_with_fuel(ctx, 1, &mut Fuel::with(1000))
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|v| v.is_nil()));
+
+        ctx.set_yield_policy(YieldPolicy::Hook(Rc::new(double)));
+        let results = yields
+            .call_with_fuel(ctx, 1, &mut Fuel::with(1000))
+            .unwrap();
+        assert!(matches!(
+            results[..],
+            [Value::Integer(2), Value::Integer(4)]
+        ));
+
+        match yields.call_with_fuel(ctx, "one", &mut Fuel::with(1000)) {
+            Err(CallError::Error(err)) => {
+                assert!(matches!(err.to_value(ctx), Value::Integer(-1)))
+            }
+            _ => panic!("expected an error"),
+        }
+        Ok(())
+    })?;
+
+    Ok(())
+}
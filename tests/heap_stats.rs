@@ -0,0 +1,66 @@
+use piccolo::{Closure, Executor, HeapStats, Lua, StaticError, Table, UserData, Value};
+
+fn run(lua: &mut Lua, source: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn growing_registry() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let before = lua.heap_stats();
+    assert!(before.tables.count > 0);
+    assert!(before.strings.count > 0);
+
+    run(
+        &mut lua,
+        r#"
+            registry = {}
+            for i = 1, 100 do
+                registry[i] = { name = "entry" .. i }
+            end
+        "#,
+    )?;
+
+    let after = lua.heap_stats();
+    assert_eq!(after.tables.count, before.tables.count + 101);
+    assert!(after.tables.bytes > before.tables.bytes);
+    assert!(after.strings.count >= before.strings.count + 100);
+    assert!(after.total().bytes > before.total().bytes);
+
+    run(&mut lua, "registry = nil")?;
+    assert_eq!(lua.heap_stats().tables.count, before.tables.count);
+
+    Ok(())
+}
+
+#[test]
+fn reachable_from_roots() {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        let shared = Table::new(&ctx);
+        let root = Table::new(&ctx);
+        root.set(ctx, 1, shared).unwrap();
+        root.set(ctx, 2, shared).unwrap();
+        root.set(ctx, "self", root).unwrap();
+
+        let userdata = UserData::new_static(&ctx, 5u32);
+        userdata.set_metatable(&ctx, Some(shared));
+        root.set(ctx, 3, userdata).unwrap();
+
+        let closure = Closure::load(ctx, None, &b"local t = {...} return t"[..]).unwrap();
+        root.set(ctx, 4, closure).unwrap();
+        root.set(ctx, 5, ctx.new_thread()).unwrap();
+
+        let stats = HeapStats::reachable_from([Value::Table(root)]);
+        // The closure's `_ENV` upvalue is the globals table.
+        assert_eq!(stats.tables.count, 3);
+        assert_eq!(stats.userdata.count, 1);
+        assert_eq!(stats.closures.count, 1);
+        assert_eq!(stats.threads.count, 1);
+        assert_eq!(stats.strings.count, 1);
+    });
+}
@@ -0,0 +1,78 @@
+use std::{cell::Cell, rc::Rc};
+
+use piccolo::{Closure, Executor, InstructionHook, IntoValue, Lua, StaticError, Thread};
+
+#[test]
+fn hook_called_periodically() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let count = Rc::new(Cell::new(0));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local i = 0
+                while i < 1000 do
+                    i = i + 1
+                end
+            "#[..],
+        )?;
+
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ()).unwrap();
+        let hook_count = count.clone();
+        thread
+            .set_hook(
+                &ctx,
+                Some(InstructionHook::new(100, move |_, _, _| {
+                    hook_count.set(hook_count.get() + 1);
+                    Ok(())
+                })),
+            )
+            .unwrap();
+        assert!(thread.has_hook());
+
+        Ok(ctx.stash(Executor::run(&ctx, thread)))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    assert!(count.get() >= 20);
+
+    Ok(())
+}
+
+#[test]
+fn hook_error_aborts() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                while true do end
+            "#[..],
+        )?;
+
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ()).unwrap();
+        thread
+            .set_hook(
+                &ctx,
+                Some(InstructionHook::new(1000, |ctx, _, info| {
+                    assert_eq!(info.current_line.0, 1);
+                    Err("watchdog".into_value(ctx).into())
+                })),
+            )
+            .unwrap();
+
+        Ok(ctx.stash(Executor::run(&ctx, thread)))
+    })?;
+
+    assert!(lua.execute::<()>(&executor).is_err());
+
+    Ok(())
+}
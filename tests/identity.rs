@@ -0,0 +1,80 @@
+use gc_arena::Gc;
+use piccolo::{Closure, Executor, IdentityPolicy, Lua, ObjectId, StaticError, Table, Value};
+
+fn run(lua: &mut Lua, source: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn opaque_identity() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| assert_eq!(ctx.identity_policy(), IdentityPolicy::Opaque));
+
+    run(
+        &mut lua,
+        r#"
+            local t = {}
+            local id = rawid(t)
+            assert(math.type(id) == "integer")
+            assert(rawid(t) == id)
+            assert(rawid({}) ~= id)
+            assert(rawid(print) ~= nil)
+            assert(rawid(coroutine.create(print)) ~= nil)
+            assert(rawid(1) == nil and rawid("s") == nil and rawid(nil) == nil)
+
+            assert(tostring(t) == tostring(t))
+            assert(tostring(t) ~= tostring({}))
+            assert(string.sub(tostring(t), 1, 9) == "<table 0x")
+            assert(string.sub(tostring(print), 1, 12) == "<function 0x")
+
+            local named = setmetatable({}, { __name = "Point" })
+            assert(string.sub(tostring(named), 1, 9) == "Point: 0x")
+        "#,
+    )?;
+
+    lua.enter(|ctx| {
+        let t = Table::new(&ctx);
+        let address = Gc::as_ptr(t.into_inner()) as usize as u64;
+        let id = ctx.object_id(Value::Table(t)).unwrap();
+        assert_ne!(id, ObjectId(address));
+        assert_eq!(ctx.object_id(Value::Table(t)), Some(id));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn address_identity() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        ctx.set_identity_policy(IdentityPolicy::Address);
+        let t = Table::new(&ctx);
+        let address = Gc::as_ptr(t.into_inner()) as usize as u64;
+        assert_eq!(ctx.object_id(Value::Table(t)), Some(ObjectId(address)));
+        assert_eq!(ObjectId(0xabc).to_string(), "0x0000000000000abc");
+    });
+}
+
+#[test]
+fn hidden_identity() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| ctx.set_identity_policy(IdentityPolicy::Hidden));
+
+    run(
+        &mut lua,
+        r#"
+            local t = {}
+            assert(rawid(t) == nil)
+            assert(tostring(t) == "<table>")
+            assert(tostring(print) == "<function>")
+            assert(tostring(setmetatable({}, { __name = "Point" })) == "Point")
+            assert(tostring(1) == "1" and tostring(true) == "true" and tostring(nil) == "nil")
+        "#,
+    )?;
+
+    Ok(())
+}
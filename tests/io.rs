@@ -0,0 +1,203 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use piccolo::{
+    stdlib::{FileStream, FileSystem, OpenMode},
+    Closure, Executor, Lua, StaticError,
+};
+
+type Files = Rc<RefCell<HashMap<String, Rc<RefCell<Vec<u8>>>>>>;
+
+#[derive(Default)]
+struct MemFileSystem {
+    files: Files,
+}
+
+impl FileSystem for MemFileSystem {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn FileStream>> {
+        let mut files = self.files.borrow_mut();
+        let data = match files.get(path) {
+            Some(data) => data.clone(),
+            None if mode.create => files.entry(path.to_owned()).or_default().clone(),
+            None => return Err(io::ErrorKind::NotFound.into()),
+        };
+        if mode.truncate {
+            data.borrow_mut().clear();
+        }
+        Ok(Box::new(MemFile { data, pos: 0, mode }))
+    }
+}
+
+struct MemFile {
+    data: Rc<RefCell<Vec<u8>>>,
+    pos: usize,
+    mode: OpenMode,
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.mode.read {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let data = self.data.borrow();
+        let start = self.pos.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        self.pos = start + len;
+        Ok(len)
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.mode.write {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut data = self.data.borrow_mut();
+        if self.mode.append {
+            self.pos = data.len();
+        }
+        let end = self.pos + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+            SeekFrom::End(n) => self.data.borrow().len() as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        self.pos = pos as usize;
+        Ok(pos as u64)
+    }
+}
+
+#[test]
+fn virtual_file_system() -> Result<(), StaticError> {
+    let file_system = MemFileSystem::default();
+    file_system.files.borrow_mut().insert(
+        "data.txt".to_owned(),
+        Rc::new(RefCell::new(
+            b"first line\nsecond line\n12 0x1F -3.5e2 nope".to_vec(),
+        )),
+    );
+    let files = file_system.files.clone();
+
+    let mut lua = Lua::core();
+    lua.load_io();
+    lua.enter(|ctx| ctx.set_file_system(file_system));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local f = assert(io.open("data.txt"))
+                assert(io.type(f) == "file")
+                assert(tostring(f):sub(1, 6) == "file (")
+                assert(f:read() == "first line")
+                assert(f:read("L") == "second line\n")
+                local a, b, c, d = f:read("n", "*n", "n", "n")
+                assert(a == 12 and math.type(a) == "integer")
+                assert(b == 31 and c == -350.0 and d == nil)
+                assert(f:read(0) == "")
+                assert(f:read("a") == "nope")
+                assert(f:read(0) == nil and f:read() == nil and f:read("a") == "")
+                assert(f:seek("set", 6) == 6)
+                assert(f:read(4) == "line")
+                assert(f:seek() == 10)
+                assert(f:seek("end") == 42)
+                assert(f:close() == true)
+                assert(io.type(f) == "closed file")
+                assert(tostring(f) == "file (closed)")
+                assert(not pcall(f.read, f))
+                assert(io.type(42) == nil)
+
+                local nf, err = io.open("missing.txt")
+                assert(nf == nil and err:sub(1, 13) == "missing.txt: ")
+                assert(not pcall(io.open, "data.txt", "rw"))
+                assert(not pcall(io.lines, "missing.txt"))
+
+                local out = assert(io.open("out.txt", "w"))
+                assert(out:write("a", 1, " ", 2.5, "\n") == out)
+                assert(not pcall(out.write, out, {}))
+                assert(not out:read())
+                out:write("b\n", "c")
+                io.close(out)
+
+                local lines = {}
+                for l in io.lines("out.txt") do
+                    lines[#lines + 1] = l
+                end
+                assert(#lines == 3 and lines[1] == "a1 2.5" and lines[2] == "b" and lines[3] == "c")
+
+                local app = assert(io.open("out.txt", "a+"))
+                app:write("d")
+                assert(app:seek("set") == 0)
+                assert(app:read("a") == "a1 2.5\nb\ncd")
+                app:close()
+
+                local rw = assert(io.open("out.txt", "r+"))
+                assert(rw:read(2) == "a1")
+                rw:write("!")
+                rw:seek("set")
+                assert(rw:read() == "a1!2.5")
+                local chunks = {}
+                for x, y in rw:lines(1, 1) do
+                    chunks[#chunks + 1] = x .. y
+                end
+                assert(#chunks == 2 and chunks[1] == "b\n" and chunks[2] == "cd")
+                assert(io.type(rw) == "file")
+                rw:close()
+
+                do
+                    local scoped <close> = assert(io.open("data.txt"))
+                    f = scoped
+                end
+                assert(io.type(f) == "closed file")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    assert_eq!(&*files.borrow()["out.txt"].borrow(), b"a1!2.5\nb\ncd");
+    Ok(())
+}
+
+#[test]
+fn no_file_system() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.load_io();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local f, err = io.open("anything.txt")
+                assert(f == nil and err == "anything.txt: no file system available")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
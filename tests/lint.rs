@@ -0,0 +1,58 @@
+use piccolo::{
+    compiler::{LineNumber, LintKind},
+    FunctionPrototype, Lua, StaticError,
+};
+
+#[test]
+fn lints() -> Result<(), StaticError> {
+    // Line numbers are 0-indexed, and the chunk starts with an empty line.
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let (_, lints) = FunctionPrototype::compile_with_lints(
+            ctx,
+            "lints.lua",
+            &br#"
+                local unused = 1
+                local _ignored = 2
+                local count = 0
+                local function update(dt)
+                    local count = count + dt
+                    total = count
+                    return #{ 1, nil, 3 }, #{ 1, 2, 3 }, #{ x = 1 }
+                end
+                for i = 1, 3 do
+                    update(1)
+                end
+                top_level = true
+            "#[..],
+        )?;
+
+        let lints = lints
+            .iter()
+            .map(|lint| {
+                let kind = match &lint.kind {
+                    LintKind::UnusedLocal(n) => format!("unused {}", n.to_str_lossy()),
+                    LintKind::ShadowedLocal(n) => format!("shadowed {}", n.to_str_lossy()),
+                    LintKind::GlobalAssignment(n) => format!("global {}", n.to_str_lossy()),
+                    LintKind::NonSequenceLength => "length".to_owned(),
+                };
+                (lint.line_number, kind)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lints,
+            [
+                (LineNumber(5), "shadowed count".to_owned()),
+                (LineNumber(6), "global total".to_owned()),
+                (LineNumber(7), "length".to_owned()),
+                (LineNumber(7), "length".to_owned()),
+                (LineNumber(9), "unused i".to_owned()),
+                (LineNumber(1), "unused unused".to_owned()),
+            ]
+        );
+
+        Ok(())
+    })
+}
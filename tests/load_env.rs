@@ -0,0 +1,53 @@
+use piccolo::{Closure, Executor, Lua, StaticError, Table, Value};
+
+#[test]
+fn isolated_environments() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (first, second, first_env, second_env) = lua.try_enter(|ctx| {
+        let script = &br#"
+            local function set(v)
+                value = v
+            end
+            set(name)
+            assert(print == nil)
+            return _ENV
+        "#[..];
+
+        let first_env = Table::new(&ctx);
+        first_env.set(ctx, "name", "first")?;
+        let second_env = Table::new(&ctx);
+        second_env.set(ctx, "name", "second")?;
+
+        let first = Closure::load_with_env(ctx, Some("first"), script, first_env)?;
+        let second = Closure::load_with_env(ctx, Some("second"), script, second_env)?;
+
+        Ok((
+            ctx.stash(Executor::start(ctx, first.into(), ())),
+            ctx.stash(Executor::start(ctx, second.into(), ())),
+            ctx.stash(first_env),
+            ctx.stash(second_env),
+        ))
+    })?;
+
+    lua.finish(&first);
+    lua.execute::<()>(&second)?;
+
+    lua.enter(|ctx| {
+        let first_env = ctx.fetch(&first_env);
+        let second_env = ctx.fetch(&second_env);
+
+        // Each script only ever sees the table it was loaded with, including in nested functions.
+        assert!(matches!(first_env.get(ctx, "value"), Value::String(s) if s == "first"));
+        assert!(matches!(second_env.get(ctx, "value"), Value::String(s) if s == "second"));
+        assert!(ctx.get_global("value").is_nil());
+
+        let first = ctx.fetch(&first);
+        assert!(matches!(
+            first.take_result::<Table>(ctx).unwrap(),
+            Ok(env) if env == first_env
+        ));
+    });
+
+    Ok(())
+}
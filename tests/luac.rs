@@ -0,0 +1,233 @@
+use piccolo::{luac::LuacError, Closure, Executor, Lua, PrototypeError, StaticError};
+
+// Lua 5.4 opcode numbers used by the chunks below.
+const MOVE: u32 = 0;
+const LOADI: u32 = 1;
+const LOADK: u32 = 3;
+const GETTABUP: u32 = 11;
+const GETTABLE: u32 = 12;
+const NEWTABLE: u32 = 19;
+const ADD: u32 = 34;
+const MMBIN: u32 = 46;
+const LEN: u32 = 52;
+const JMP: u32 = 56;
+const EQI: u32 = 61;
+const CALL: u32 = 68;
+const RETURN: u32 = 70;
+const FORLOOP: u32 = 73;
+const FORPREP: u32 = 74;
+const TFORPREP: u32 = 75;
+const TFORCALL: u32 = 76;
+const TFORLOOP: u32 = 77;
+const SETLIST: u32 = 78;
+const VARARG: u32 = 80;
+const VARARGPREP: u32 = 81;
+const EXTRAARG: u32 = 82;
+
+fn abc(op: u32, a: u32, b: u32, c: u32) -> u32 {
+    op | a << 7 | b << 16 | c << 24
+}
+
+fn abx(op: u32, a: u32, bx: u32) -> u32 {
+    op | a << 7 | bx << 15
+}
+
+fn asbx(op: u32, a: u32, sbx: i32) -> u32 {
+    abx(op, a, (sbx + 65535) as u32)
+}
+
+fn sj(op: u32, sj: i32) -> u32 {
+    op | ((sj + 16777215) as u32) << 7
+}
+
+fn size(out: &mut Vec<u8>, mut size: usize) {
+    let mut groups = vec![(size & 0x7f) as u8 | 0x80];
+    size >>= 7;
+    while size != 0 {
+        groups.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    size(out, s.len() + 1);
+    out.extend(s.as_bytes());
+}
+
+// Assembles a main chunk with no nested functions, as `luac` would write it.
+fn chunk(max_stack_size: u8, code: &[u32], strings: &[&str], lines: bool) -> Vec<u8> {
+    let mut out = b"\x1bLua\x54\x00\x19\x93\r\n\x1a\n\x04\x08\x08".to_vec();
+    out.extend(0x5678i64.to_le_bytes());
+    out.extend(370.5f64.to_le_bytes());
+    out.push(1);
+
+    string(&mut out, "=luac");
+    size(&mut out, 0);
+    size(&mut out, 0);
+    out.extend([0, 1, max_stack_size]);
+    size(&mut out, code.len());
+    for i in code {
+        out.extend(i.to_le_bytes());
+    }
+    size(&mut out, strings.len());
+    for s in strings {
+        out.push(4);
+        string(&mut out, s);
+    }
+    // The main chunk has a single `_ENV` upvalue.
+    size(&mut out, 1);
+    out.extend([1, 0, 0]);
+    size(&mut out, 0);
+
+    if lines {
+        size(&mut out, code.len());
+        out.extend(code.iter().map(|_| 1));
+    } else {
+        size(&mut out, 0);
+    }
+    size(&mut out, 0);
+    size(&mut out, 0);
+    size(&mut out, 0);
+    out
+}
+
+// local t = {10, 20, 30}
+// local sum = 0
+// for i = 1, #t do
+//     sum = sum + t[i]
+// end
+// if sum == 60 then
+//     return sum, "ok"
+// end
+// return sum, "bad"
+fn numeric_for() -> Vec<u8> {
+    chunk(
+        7,
+        &[
+            abc(VARARGPREP, 0, 0, 0),
+            abc(NEWTABLE, 0, 0, 3),
+            abx(EXTRAARG, 0, 0),
+            asbx(LOADI, 1, 10),
+            asbx(LOADI, 2, 20),
+            asbx(LOADI, 3, 30),
+            abc(SETLIST, 0, 3, 0),
+            asbx(LOADI, 1, 0),
+            asbx(LOADI, 2, 1),
+            abc(LEN, 3, 0, 0),
+            asbx(LOADI, 4, 1),
+            abx(FORPREP, 2, 3),
+            abc(GETTABLE, 6, 0, 5),
+            abc(ADD, 1, 1, 6),
+            abc(MMBIN, 1, 6, 6),
+            abx(FORLOOP, 2, 4),
+            abc(EQI, 1, 60 + 127, 0),
+            sj(JMP, 3),
+            abc(MOVE, 2, 1, 0),
+            abx(LOADK, 3, 0),
+            abc(RETURN, 2, 3, 0),
+            abc(MOVE, 2, 1, 0),
+            abx(LOADK, 3, 1),
+            abc(RETURN, 2, 3, 0),
+            abc(RETURN, 2, 1, 1),
+        ],
+        &["ok", "bad"],
+        true,
+    )
+}
+
+// local t = {...}
+// local n = 0
+// for _, v in ipairs(t) do
+//     n = n + v
+// end
+// return n, #t
+fn generic_for() -> Vec<u8> {
+    chunk(
+        9,
+        &[
+            abc(VARARGPREP, 0, 0, 0),
+            abc(NEWTABLE, 0, 0, 0),
+            abx(EXTRAARG, 0, 0),
+            abc(VARARG, 1, 0, 0),
+            abc(SETLIST, 0, 0, 0),
+            asbx(LOADI, 1, 0),
+            abc(GETTABUP, 2, 0, 0),
+            abc(MOVE, 3, 0, 0),
+            abc(CALL, 2, 2, 5),
+            abx(TFORPREP, 2, 2),
+            abc(ADD, 1, 1, 7),
+            abc(MMBIN, 1, 7, 6),
+            abc(TFORCALL, 2, 0, 2),
+            abx(TFORLOOP, 2, 4),
+            abc(MOVE, 2, 1, 0),
+            abc(LEN, 3, 0, 0),
+            abc(RETURN, 2, 3, 1),
+            abc(RETURN, 2, 1, 1),
+        ],
+        &["ipairs"],
+        false,
+    )
+}
+
+#[test]
+fn numeric_for_loop() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load_binary(ctx, &numeric_for(), ctx.globals())?;
+        assert_eq!(closure.prototype().chunk_name.as_bytes(), b"=luac");
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    let (sum, result) = lua.execute::<(i64, String)>(&executor)?;
+    assert_eq!((sum, result.as_str()), (60, "ok"));
+    Ok(())
+}
+
+#[test]
+fn generic_for_loop() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load_binary(ctx, &generic_for(), ctx.globals())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), (1, 2, 3, 4))))
+    })?;
+    assert_eq!(lua.execute::<(i64, i64)>(&executor)?, (10, 4));
+    Ok(())
+}
+
+#[test]
+fn bad_chunks() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let chunk = generic_for();
+
+        let mut bad_version = chunk.clone();
+        bad_version[4] = 0x53;
+        assert!(matches!(
+            Closure::load_binary(ctx, &bad_version, ctx.globals()),
+            Err(PrototypeError::Luac(LuacError::BadVersion(0x53)))
+        ));
+
+        let mut big_endian = chunk.clone();
+        big_endian[15..23].reverse();
+        assert!(matches!(
+            Closure::load_binary(ctx, &big_endian, ctx.globals()),
+            Err(PrototypeError::Luac(LuacError::BadFormat))
+        ));
+
+        for len in 0..chunk.len() {
+            assert!(Closure::load_binary(ctx, &chunk[..len], ctx.globals()).is_err());
+        }
+
+        // A corrupted chunk either fails to load or passes the verifier, and never panics.
+        for i in 5..chunk.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut corrupt = chunk.clone();
+                corrupt[i] ^= flip;
+                let _ = Closure::load_binary(ctx, &corrupt, ctx.globals());
+            }
+        }
+    });
+}
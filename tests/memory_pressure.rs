@@ -0,0 +1,134 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{Closure, Executor, Lua, MemoryPressureEvent, StaticError};
+
+#[test]
+fn memory_pressure_handler() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    let base = lua.total_memory();
+    let low = base + 1024 * 1024;
+    let high = base + 4 * 1024 * 1024;
+    lua.set_memory_thresholds(vec![high, low]);
+
+    let events = Rc::new(RefCell::new(Vec::<MemoryPressureEvent>::new()));
+    let handler_events = events.clone();
+    lua.set_memory_pressure_handler(move |lua, event| {
+        handler_events.borrow_mut().push(event);
+        lua.gc_collect();
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local t = {}
+                for i = 1, 100000 do
+                    t[i] = { i }
+                end
+                return #t
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    assert_eq!(lua.execute::<i64>(&executor)?, 100000);
+
+    let events = events.borrow();
+    assert!(!events.is_empty());
+    assert_eq!(events[0].threshold, low);
+    assert_eq!(events[0].level, 0);
+    assert!(events[0].total_memory >= low);
+    assert!(events.iter().any(|e| e.threshold == high && e.level == 1));
+
+    Ok(())
+}
+
+#[test]
+fn no_event_below_threshold() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let fired = Rc::new(RefCell::new(false));
+    let handler_fired = fired.clone();
+    lua.set_memory_thresholds(vec![usize::MAX]);
+    lua.set_memory_pressure_handler(move |_, _| *handler_fired.borrow_mut() = true);
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &b"local t = {} for i = 1, 100 do t[i] = i end"[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    assert!(!*fired.borrow());
+
+    Ok(())
+}
+
+#[test]
+fn handler_cleared_while_running() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    let base = lua.total_memory();
+    lua.set_memory_thresholds(vec![base + 1024 * 1024, base + 4 * 1024 * 1024]);
+
+    let calls = Rc::new(RefCell::new(0));
+    let handler_calls = calls.clone();
+    lua.set_memory_pressure_handler(move |lua, _| {
+        *handler_calls.borrow_mut() += 1;
+        lua.clear_memory_pressure_handler();
+    });
+
+    for i in 0..2 {
+        lua.enter(|ctx| {
+            let big = ctx.intern(&vec![i; 2 * 1024 * 1024]);
+            ctx.set_global(i, big).unwrap();
+        });
+    }
+
+    assert_eq!(*calls.borrow(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn usage_rechecked_after_handler() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.gc_collect();
+
+    let base = lua.total_memory();
+    let low = base + 1024 * 1024;
+    let high = base + 4 * 1024 * 1024;
+    lua.set_memory_thresholds(vec![low, high]);
+
+    let events = Rc::new(RefCell::new(Vec::<MemoryPressureEvent>::new()));
+    let handler_events = events.clone();
+    lua.set_memory_pressure_handler(move |lua, event| {
+        handler_events.borrow_mut().push(event);
+        if event.level == 0 {
+            // Rises past the next threshold while the handler is running.
+            lua.enter(|ctx| {
+                let big = ctx.intern(&vec![1; 4 * 1024 * 1024]);
+                ctx.set_global("big", big).unwrap();
+            });
+        }
+    });
+
+    lua.enter(|ctx| {
+        let small = ctx.intern(&vec![0; 2 * 1024 * 1024]);
+        ctx.set_global("small", small).unwrap();
+    });
+
+    let events = events.borrow();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].threshold, low);
+    assert_eq!(events[1].threshold, high);
+    assert_eq!(events[1].level, 1);
+
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use piccolo::{
+    meta_ops::{self, ConcatResult, MetaResult},
+    Callback, CallbackReturn, Closure, Executor, Function, IntoValue, Lua, MetaMethod, StaticError,
+    Table, UserData, Value,
+};
+
+#[test]
+fn compare_primitives() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let lt = |a: Value, b: Value| match meta_ops::less_than(ctx, a, b).unwrap() {
+            MetaResult::Value(Value::Boolean(b)) => b,
+            _ => panic!("expected a boolean result"),
+        };
+        let le = |a: Value, b: Value| match meta_ops::less_equal(ctx, a, b).unwrap() {
+            MetaResult::Value(Value::Boolean(b)) => b,
+            _ => panic!("expected a boolean result"),
+        };
+
+        assert!(lt(Value::Integer(1), Value::Integer(2)));
+        assert!(!lt(Value::Integer(2), Value::Integer(2)));
+        assert!(le(Value::Integer(2), Value::Integer(2)));
+        assert!(lt(Value::Integer(1), Value::Number(1.5)));
+        assert!(le(Value::Number(1.0), Value::Integer(1)));
+        assert!(!lt(Value::Number(f64::NAN), Value::Integer(1)));
+        assert!(lt(ctx.intern(b"abc").into(), ctx.intern(b"abd").into()));
+        assert!(le(ctx.intern(b"abc").into(), ctx.intern(b"abc").into()));
+
+        assert!(meta_ops::less_than(ctx, ctx.intern(b"1").into(), Value::Integer(2)).is_err());
+        assert!(meta_ops::less_equal(ctx, Value::Nil, Value::Nil).is_err());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn compare_userdata() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let mt = Table::new(&ctx);
+        mt.set(
+            ctx,
+            "__lt",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (a, b): (UserData, UserData) = stack.consume(ctx)?;
+                let a = *a.downcast_static::<i64>().unwrap();
+                let b = *b.downcast_static::<i64>().unwrap();
+                stack.push_back(Value::Boolean(a < b));
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+
+        let a = UserData::new_static(&ctx, 1i64);
+        a.set_metatable(&ctx, Some(mt));
+        let b = UserData::new_static(&ctx, 2i64);
+        b.set_metatable(&ctx, Some(mt));
+
+        ctx.set_global("a", a)?;
+        ctx.set_global("b", b)?;
+
+        // Comparing userdata with no `__le` metamethod is an error.
+        assert!(meta_ops::less_equal(ctx, a.into(), b.into()).is_err());
+
+        let compare = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (a, b): (Value, Value) = stack.consume(ctx)?;
+            match meta_ops::less_than(ctx, a, b)? {
+                MetaResult::Value(v) => {
+                    stack.push_back(v);
+                    Ok(CallbackReturn::Return)
+                }
+                MetaResult::Call(call) => {
+                    stack.extend(call.args);
+                    Ok(CallbackReturn::Call {
+                        function: call.function,
+                        then: None,
+                    })
+                }
+            }
+        });
+        ctx.set_global("compare", compare)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return compare(a, b), compare(b, a), compare(1, 2)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert_eq!(
+        lua.execute::<(bool, bool, bool)>(&executor)?,
+        (true, false, true)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn index_chain_limit() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        assert_eq!(
+            meta_ops::meta_chain_limit(ctx),
+            meta_ops::DEFAULT_META_CHAIN_LIMIT
+        );
+        meta_ops::set_meta_chain_limit(ctx, 10);
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function chain(len)
+                    local top = { value = 1 }
+                    for i = 1, len do
+                        top = setmetatable({}, { __index = top })
+                    end
+                    return top
+                end
+
+                assert(chain(5).value == 1)
+                local ok, err = pcall(function() return chain(20).value end)
+                assert(not ok and tostring(err) == "'__index' chain too long; possible loop")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn index_chain_resolved_directly() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let func = Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return));
+
+        let last_mt = Table::new(&ctx);
+        last_mt.set(ctx, MetaMethod::Index, func).unwrap();
+        let last = Table::new(&ctx);
+        last.set_metatable(&ctx, Some(last_mt));
+
+        let mut top = last;
+        for _ in 0..100 {
+            let mt = Table::new(&ctx);
+            mt.set(ctx, MetaMethod::Index, top).unwrap();
+            top = Table::new(&ctx);
+            top.set_metatable(&ctx, Some(mt));
+        }
+
+        // The whole chain of `__index` tables is followed in one step, and the only call is to the
+        // final `__index` function with the last table in the chain.
+        let key = "key".into_value(ctx);
+        match meta_ops::index(ctx, top.into(), key).unwrap() {
+            MetaResult::Call(call) => {
+                assert!(matches!(call.function, Function::Callback(c) if c == func));
+                assert!(matches!(call.args[0], Value::Table(t) if t == last));
+            }
+            MetaResult::Value(_) => panic!("expected a call"),
+        }
+    });
+}
+
+#[test]
+fn concat_many_remaining() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mt = Table::new(&ctx);
+        let concat = Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return));
+        mt.set(ctx, MetaMethod::Concat, concat).unwrap();
+        let t = Table::new(&ctx);
+        t.set_metatable(&ctx, Some(mt));
+
+        let values = [
+            ctx.intern(b"a").into(),
+            Value::Integer(1),
+            t.into(),
+            ctx.intern(b"b").into(),
+            Value::Number(2.5),
+        ];
+
+        match meta_ops::concat_many(ctx, &values).unwrap() {
+            ConcatResult::Call { call, remaining } => {
+                assert_eq!(remaining, 2);
+                assert_eq!(call.function, concat.into());
+                assert!(matches!(call.args[0], Value::Table(a) if a == t));
+                assert!(matches!(call.args[1], Value::String(s) if s == b"b2.5"));
+            }
+            ConcatResult::Value(_) => panic!("expected a metamethod call"),
+        }
+
+        match meta_ops::concat_many(ctx, &values[..2]).unwrap() {
+            ConcatResult::Value(v) => assert!(matches!(v, Value::String(s) if s == b"a1")),
+            ConcatResult::Call { .. } => panic!("expected a value"),
+        }
+
+        match meta_ops::concat_many(ctx, &[]).unwrap() {
+            ConcatResult::Value(v) => assert!(matches!(v, Value::String(s) if s.is_empty())),
+            ConcatResult::Call { .. } => panic!("expected a value"),
+        }
+
+        assert!(meta_ops::concat_many(ctx, &[Value::Nil, Value::Integer(1)]).is_err());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn custom_metamethods() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        assert!(meta_ops::register_custom(ctx, "serialize").is_err());
+        assert!(meta_ops::register_custom(ctx, "__index").is_err());
+        assert!(meta_ops::register_custom(ctx, "__name").is_err());
+        meta_ops::register_custom(ctx, "__serialize")?;
+        assert!(meta_ops::is_custom(ctx, "__serialize"));
+        assert!(!meta_ops::is_custom(ctx, "__clone"));
+
+        ctx.set_global(
+            "serialize",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.consume(ctx)?;
+                match meta_ops::custom(ctx, v, "__serialize")? {
+                    Some(m) => {
+                        stack.push_back(v);
+                        Ok(CallbackReturn::Call {
+                            function: meta_ops::call(ctx, m)?,
+                            then: None,
+                        })
+                    }
+                    None => {
+                        stack.replace(ctx, "<opaque>");
+                        Ok(CallbackReturn::Return)
+                    }
+                }
+            }),
+        )?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local point = setmetatable({ x = 1, y = 2 }, {
+                    __serialize = function(p) return p.x .. "," .. p.y end,
+                })
+                assert(serialize(point) == "1,2")
+                assert(serialize({}) == "<opaque>")
+                assert(serialize(1) == "<opaque>")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        assert!(meta_ops::custom(ctx, Value::Nil, "__clone").is_err());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn type_metatables() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let mt = Table::new(&ctx);
+        mt.set(
+            ctx,
+            MetaMethod::Index,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (n, key): (i64, piccolo::String) = stack.consume(ctx)?;
+                if key == b"double" {
+                    stack.replace(ctx, n * 2);
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        meta_ops::set_metatable(ctx, Value::Integer(0), Some(mt));
+        assert!(meta_ops::get_metatable(ctx, Value::Number(1.5)).is_some_and(|t| t == mt));
+        assert!(meta_ops::get_metatable(ctx, Value::Boolean(true)).is_none());
+
+        let bmt = Table::new(&ctx);
+        bmt.set(
+            ctx,
+            MetaMethod::Add,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (a, b): (bool, bool) = stack.consume(ctx)?;
+                stack.replace(ctx, a || b);
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        meta_ops::set_metatable(ctx, Value::Boolean(false), Some(bmt));
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local n = 21
+                assert(n.double == 42)
+                local t, f = true, false
+                assert((t + f) == true)
+                assert((f + f) == false)
+                assert(getmetatable(true) ~= nil)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        meta_ops::set_metatable(ctx, Value::Boolean(true), None);
+        assert!(meta_ops::get_metatable(ctx, Value::Boolean(false)).is_none());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn meta_result_callback_return() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let get = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (table, key): (Value, Value) = stack.consume(ctx)?;
+            Ok(meta_ops::index(ctx, table, key)?.into_callback_return(ctx, &mut stack))
+        });
+        ctx.set_global("get", get)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                local t = setmetatable({ a = 1 }, {
+                    __index = function(_, k) return k .. "!" end,
+                })
+                assert(get(t, "a") == 1)
+                assert(get(t, "b") == "b!")
+                assert(select("#", get(t, "a")) == 1)
+            "##[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn string_coercion() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let run = |lua: &mut Lua, source: &'static str| -> Result<(), StaticError> {
+        let executor = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, source.as_bytes())?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        lua.execute::<()>(&executor)
+    };
+
+    run(
+        &mut lua,
+        r#"
+            local s = "10"
+            assert(not pcall(function() return s + 1 end))
+            assert(not pcall(function() return "10" + 1 end))
+            assert(not pcall(function() return -"10" end))
+        "#,
+    )?;
+
+    lua.enter(|ctx| {
+        assert!(!ctx.string_coercion());
+        ctx.set_string_coercion(true);
+    });
+
+    run(
+        &mut lua,
+        r#"
+            local s, f, x = "10", " 0.5 ", "x"
+            assert(s + 1 == 11 and math.type(s + 1) == "integer")
+            assert("10" + 1 == 11)
+            assert(f * 2 == 1.0)
+            assert(-s == -10)
+            assert(s // "3" == 3)
+            assert(s & 3 == 2)
+            assert(s .. 1 == "101")
+            assert(not pcall(function() return x + 1 end))
+            assert(not pcall(function() return s < 11 end))
+        "#,
+    )
+}
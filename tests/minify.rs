@@ -0,0 +1,103 @@
+use std::{
+    fs::{read_dir, File},
+    io::Read,
+};
+
+use piccolo::{compiler::minify, io, Closure, Executor, FunctionPrototype, Lua, StaticError};
+
+const SOURCE: &str = r##"
+    -- comments and whitespace are removed
+    local x <const> = 1
+    local function count(n, ...)
+        local total = select("#", ...) + n
+        for i = 1, 3 do total = total + i end
+        for k, v in pairs({a = 1, [2] = 2, 3}) do total = total + v end
+        return total
+    end
+
+    local t = {}
+    function t.f(self_) return self_ end
+    function t:m(a) return self, a end
+    local y = x
+    local x = x + 1
+    local s = "quote \" backslash \\ newline \n \0001 \127"
+    ;(print)(#s .. 1 .. 2.5, - -x, ~ ~x, 1 // 1, 0xffffffffffffffff, 1e999, 1.5e-7)
+
+    repeat local done = true until done
+    do goto skip end
+    ::skip::
+    return count(x, y, 1, 2), t:m(x), s, 2^-1 < 1 == true
+"##;
+
+fn compile(lua: &mut Lua, source: &[u8]) -> Result<Vec<(String, String)>, StaticError> {
+    fn dump(proto: &FunctionPrototype, out: &mut Vec<(String, String)>) {
+        for (i, op) in proto.opcodes.iter().enumerate() {
+            let line = proto
+                .opcode_line_numbers
+                .iter()
+                .rev()
+                .find(|&&(start, _)| start <= i)
+                .map(|(_, line)| line.to_string())
+                .unwrap_or_default();
+            out.push((format!("{:?}", op.decode()), line));
+        }
+        for nested in proto.prototypes.iter() {
+            dump(nested, out);
+        }
+    }
+
+    lua.try_enter(|ctx| {
+        let proto = FunctionPrototype::compile(ctx, "minify", source)?;
+        let mut out = Vec::new();
+        dump(&proto, &mut out);
+        Ok(out)
+    })
+}
+
+#[test]
+fn minified_compiles_identically() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let original = compile(&mut lua, SOURCE.as_bytes())?;
+
+    for rename_locals in [false, true] {
+        let minified = minify(SOURCE.as_bytes(), rename_locals).unwrap();
+        assert!(minified.len() < SOURCE.len());
+        assert_eq!(
+            minified.iter().filter(|&&c| c == b'\n').count(),
+            SOURCE.lines().count() - 1,
+        );
+        assert_eq!(compile(&mut lua, &minified)?, original);
+    }
+
+    let renamed = String::from_utf8(minify(SOURCE.as_bytes(), true).unwrap()).unwrap();
+    assert!(!renamed.contains("total"));
+    assert!(renamed.contains("select") && renamed.contains("self") && renamed.contains("::skip::"));
+
+    Ok(())
+}
+
+#[test]
+fn minified_scripts() -> Result<(), StaticError> {
+    for entry in read_dir("./tests/scripts").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "lua") {
+            let mut source = Vec::new();
+            io::buffered_read(File::open(&path).unwrap())
+                .unwrap()
+                .read_to_end(&mut source)
+                .unwrap();
+            let minified = minify(&source[..], true).unwrap();
+
+            let mut lua = Lua::full();
+            let exec = lua.try_enter(|ctx| {
+                ctx.set_string_coercion(true);
+                let closure =
+                    Closure::load(ctx, Some(path.to_string_lossy().as_ref()), &minified[..])?;
+                Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+            })?;
+            lua.execute::<()>(&exec)?;
+        }
+    }
+
+    Ok(())
+}
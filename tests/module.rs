@@ -0,0 +1,77 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{CallbackReturn, Closure, Executor, IntoValue, Lua, ModuleBuilder, StaticError};
+
+#[test]
+fn module_middleware() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    lua.try_enter(|ctx| {
+        let log = log.clone();
+        let module = ModuleBuilder::new(ctx)
+            .value("version", 1)
+            .function("unwrapped", |ctx, _, mut stack| {
+                stack.replace(ctx, 0);
+                Ok(CallbackReturn::Return)
+            })
+            .with_middleware(move |ctx, name, callback| {
+                let log = log.clone();
+                let name = name.to_owned();
+                ModuleBuilder::wrap(ctx, callback, move |_, stack| {
+                    log.borrow_mut().push((name.clone(), stack.len()));
+                    Ok(())
+                })
+            })
+            .with_middleware(|ctx, name, callback| {
+                if name == "secret" {
+                    ModuleBuilder::wrap(ctx, callback, |ctx, _| {
+                        Err("permission denied".into_value(ctx).into())
+                    })
+                } else {
+                    callback
+                }
+            })
+            .function("add", |ctx, _, mut stack| {
+                let (a, b): (i64, i64) = stack.consume(ctx)?;
+                stack.replace(ctx, a + b);
+                Ok(CallbackReturn::Return)
+            })
+            .function("secret", |ctx, _, mut stack| {
+                stack.replace(ctx, "hidden");
+                Ok(CallbackReturn::Return)
+            })
+            .build();
+        ctx.set_global("module", module)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(module.version == 1)
+                assert(module.unwrapped() == 0)
+                assert(module.add(1, 2) == 3)
+                assert(module.add(3, 4) == 7)
+                local ok, err = pcall(module.secret)
+                assert(not ok and err == "permission denied")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    // The permission check is the outermost middleware, so the denied call is never logged, and
+    // callbacks added before the middleware are never wrapped.
+    assert_eq!(
+        *log.borrow(),
+        vec![("add".to_owned(), 2), ("add".to_owned(), 2)]
+    );
+
+    Ok(())
+}
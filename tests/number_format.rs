@@ -0,0 +1,122 @@
+use piccolo::{
+    value::{format_float_g, NumberDisplay},
+    Closure, Executor, Lua, StaticError, Value,
+};
+
+#[test]
+fn tostring_matrix() {
+    let cases: &[(f64, &str)] = &[
+        (0.0, "0.0"),
+        (-0.0, "-0.0"),
+        (1.0, "1.0"),
+        (-1.0, "-1.0"),
+        (0.5, "0.5"),
+        (0.1, "0.1"),
+        (0.1 + 0.2, "0.3"),
+        (1.0 / 3.0, "0.33333333333333"),
+        (-2.0 / 3.0, "-0.66666666666667"),
+        (100.0, "100.0"),
+        (1e13, "10000000000000.0"),
+        (1e14, "1e+14"),
+        (1e15, "1e+15"),
+        (123456789012345.0, "1.2345678901234e+14"),
+        (1e100, "1e+100"),
+        (1.5e300, "1.5e+300"),
+        (1e-4, "0.0001"),
+        (1e-5, "1e-05"),
+        (1.25e-7, "1.25e-07"),
+        (5e-324, "4.9406564584125e-324"),
+        (f64::MAX, "1.7976931348623e+308"),
+        (f64::INFINITY, "inf"),
+        (f64::NEG_INFINITY, "-inf"),
+        (f64::NAN, "nan"),
+        (-f64::NAN, "nan"),
+        (9007199254740993.0, "9.007199254741e+15"),
+        (2.5, "2.5"),
+        (1234.5678, "1234.5678"),
+    ];
+
+    for &(n, expected) in cases {
+        assert_eq!(NumberDisplay(n).to_string(), expected, "formatting {n:?}");
+    }
+}
+
+#[test]
+fn format_g_matrix() {
+    let cases: &[(f64, usize, bool, &str)] = &[
+        (0.0, 6, false, "0"),
+        (1.0, 6, false, "1"),
+        (1.0, 6, true, "1.00000"),
+        (100000.0, 6, false, "100000"),
+        (1000000.0, 6, false, "1e+06"),
+        (1000000.0, 6, true, "1.00000e+06"),
+        (0.0001, 6, false, "0.0001"),
+        (0.00001, 6, false, "1e-05"),
+        (123.456, 2, false, "1.2e+02"),
+        (123.456, 3, false, "123"),
+        (123.456, 3, true, "123."),
+        (0.5, 0, false, "0.5"),
+        (2.5, 1, false, "2"),
+        (3.5, 1, false, "4"),
+        (1e5, 1, true, "1.e+05"),
+    ];
+
+    for &(n, precision, alternate, expected) in cases {
+        assert_eq!(
+            format_float_g(n, precision, alternate),
+            expected,
+            "formatting {n:?} with precision {precision}"
+        );
+    }
+}
+
+#[test]
+fn parse_matrix() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let cases: &[(&str, Option<f64>)] = &[
+        ("1", Some(1.0)),
+        ("1.5", Some(1.5)),
+        ("  1.5  ", Some(1.5)),
+        ("-1.5", Some(-1.5)),
+        ("+1.5", Some(1.5)),
+        (".5", Some(0.5)),
+        ("5.", Some(5.0)),
+        ("1e3", Some(1000.0)),
+        ("1E-3", Some(0.001)),
+        ("0x10", Some(16.0)),
+        ("0x.8", Some(0.5)),
+        ("0x1p4", Some(16.0)),
+        ("1,5", None),
+        ("inf", None),
+        ("-inf", None),
+        ("infinity", None),
+        ("nan", None),
+        ("NaN", None),
+        ("1e", None),
+        ("e1", None),
+        ("", None),
+    ];
+
+    for &(s, expected) in cases {
+        let result = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, &b"return tonumber(...)"[..])?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), s)))
+        })?;
+        let result = lua.execute::<Option<f64>>(&result)?;
+        assert_eq!(result, expected, "parsing {s:?}");
+    }
+
+    lua.enter(|ctx| {
+        assert_eq!(
+            Value::Number(0.25).into_string(ctx).unwrap().as_bytes(),
+            b"0.25"
+        );
+        assert_eq!(
+            Value::Number(-3.0).into_string(ctx).unwrap().as_bytes(),
+            b"-3.0"
+        );
+    });
+
+    Ok(())
+}
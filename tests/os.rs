@@ -0,0 +1,68 @@
+use piccolo::{stdlib::Clock, Closure, Executor, Lua, StaticError};
+
+struct FixedClock;
+
+impl Clock for FixedClock {
+    fn time(&self) -> i64 {
+        1700000000
+    }
+
+    fn clock(&self) -> f64 {
+        1.5
+    }
+
+    fn utc_offset(&self, _time: i64) -> i64 {
+        3600
+    }
+}
+
+#[test]
+fn virtual_clock() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.load_os();
+    lua.enter(|ctx| ctx.set_clock(FixedClock));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(os.time() == 1700000000)
+                assert(os.clock() == 1.5)
+                assert(os.difftime(10, 4) == 6.0)
+
+                assert(os.date("!%Y-%m-%d %H:%M:%S") == "2023-11-14 22:13:20")
+                assert(os.date("!%c") == "Tue Nov 14 22:13:20 2023")
+                assert(os.date("!%j %u %w %U %W %V %G %g") == "318 2 2 46 46 46 2023 23")
+                assert(os.date("!%a %A %b %B %p %I %e %D %%")
+                    == "Tue Tuesday Nov November PM 10 14 11/14/23 %")
+                assert(os.date("!%Ey %Od %Z") == "23 14 UTC")
+                assert(os.date("%H:%M %z") == "23:13 +0100")
+
+                assert(os.date("!%Y-%m-%d", 0) == "1970-01-01")
+                assert(os.date("!%Y-%m-%d %H", -1) == "1969-12-31 23")
+                assert(os.date("!%Y-%m-%d", 951782400) == "2000-02-29")
+                assert(os.date("!%G-W%V", 1609459200) == "2020-W53")
+
+                local t = os.date("*t")
+                assert(t.year == 2023 and t.month == 11 and t.day == 14)
+                assert(t.hour == 23 and t.min == 13 and t.sec == 20)
+                assert(t.wday == 3 and t.yday == 318 and t.isdst == false)
+                assert(os.time(t) == 1700000000)
+                local fields = { year = 2023, month = 11, day = 14, hour = 23, min = 13, sec = 20 }
+                assert(os.time(fields) == 1700000000)
+
+                local n = { year = 2023, month = 13, day = 32, hour = 0 }
+                os.time(n)
+                assert(n.year == 2024 and n.month == 2 and n.day == 1 and n.hour == 0)
+
+                assert(not pcall(os.time, { year = 2023 }))
+                assert(not pcall(os.time, { year = 2023, month = 1, day = 1.5 }))
+                assert(not pcall(os.date, "%Q"))
+                assert(not pcall(os.date, "%E"))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
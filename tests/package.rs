@@ -0,0 +1,114 @@
+use std::{collections::HashMap, io};
+
+use piccolo::{
+    stdlib::{ModuleResolver, ModuleSource},
+    Closure, Executor, Lua, StaticError,
+};
+
+#[derive(Default)]
+struct MapResolver {
+    modules: HashMap<String, String>,
+}
+
+impl ModuleResolver for MapResolver {
+    fn resolve(&self, name: &str) -> io::Result<Option<ModuleSource>> {
+        if name == "broken.io" {
+            return Err(io::Error::other("disk on fire"));
+        }
+        Ok(self.modules.get(name).map(|source| ModuleSource {
+            path: format!("{}.lua", name.replace('.', "/")),
+            source: source.clone().into_bytes(),
+        }))
+    }
+}
+
+#[test]
+fn require_modules() -> Result<(), StaticError> {
+    let mut resolver = MapResolver::default();
+    resolver.modules.insert(
+        "counter".to_owned(),
+        r#"
+            local name, path = ...
+            loads = (loads or 0) + 1
+            return { name = name, path = path, value = require("util.double")(21) }
+        "#
+        .to_owned(),
+    );
+    resolver.modules.insert(
+        "util.double".to_owned(),
+        "return function(x) return x * 2 end".to_owned(),
+    );
+    resolver
+        .modules
+        .insert("nothing".to_owned(), "done = true".to_owned());
+    resolver
+        .modules
+        .insert("syntax".to_owned(), "return +".to_owned());
+
+    let mut lua = Lua::full();
+    lua.enter(|ctx| ctx.set_module_resolver(resolver));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(package.loaded.string == string and package.loaded._G == _G)
+                assert(require("math") == math)
+
+                local counter, path = require("counter")
+                assert(counter.name == "counter" and counter.path == "counter.lua")
+                assert(path == "counter.lua" and counter.value == 42)
+                assert(require("counter") == counter and loads == 1)
+                assert(package.loaded["util.double"](2) == 4)
+
+                assert(require("nothing") == true and done)
+
+                package.preload.virtual = function(name, extra)
+                    assert(extra == ":preload:")
+                    return name .. "!"
+                end
+                assert(require("virtual") == "virtual!")
+
+                local ok, err = pcall(require, "missing")
+                assert(not ok and err:find("module 'missing' not found:", 1, true))
+                assert(err:find("no field package.preload['missing']", 1, true))
+                assert(err:find("no module 'missing' in the resolver", 1, true))
+
+                ok, err = pcall(require, "syntax")
+                assert(not ok)
+                assert(err:find("error loading module 'syntax' from 'syntax.lua'", 1, true))
+
+                ok, err = pcall(require, "broken.io")
+                assert(not ok and err:find("disk on fire", 1, true))
+
+                table.insert(package.searchers, 1, function(name)
+                    if name == "custom" then
+                        return function() return "from searcher" end
+                    end
+                end)
+                assert(require("custom") == "from searcher")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn no_module_resolver() -> Result<(), StaticError> {
+    let mut lua = Lua::full();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local ok, err = pcall(require, "anything")
+                assert(not ok and err:find("no module resolver available", 1, true))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
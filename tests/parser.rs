@@ -0,0 +1,114 @@
+use piccolo::{
+    compiler::{
+        interning::BasicInterner,
+        parse_chunk, parse_chunk_with_options, parse_chunk_with_recovery,
+        parser::{BinaryOperator, HeadExpression, ParseErrorKind, Span, Statement},
+        CompilerOptions, LineNumber,
+    },
+    Closure, Executor, Lua, StaticError,
+};
+
+fn span(start: u64, end: u64) -> Span {
+    Span {
+        start: LineNumber(start),
+        end: LineNumber(end),
+    }
+}
+
+#[test]
+fn statement_and_expression_spans() {
+    let chunk = parse_chunk(
+        &br#"
+            local t = {
+                1,
+                2,
+            }
+            if t then print(t) end
+            return 1 +
+                2 * 3
+        "#[..],
+        BasicInterner::default(),
+    )
+    .unwrap();
+
+    let block = &chunk.block;
+    assert_eq!(block.statements[0].span, span(1, 4));
+    assert_eq!(block.statements[1].span, span(5, 5));
+
+    let Statement::LocalStatement(local) = &block.statements[0].inner else {
+        panic!("expected a local statement");
+    };
+    assert_eq!(local.values[0].span, span(1, 4));
+
+    let return_statement = block.return_statement.as_ref().unwrap();
+    assert_eq!(return_statement.span, span(6, 7));
+    let sum = &return_statement.returns[0];
+    assert_eq!(sum.span, span(6, 7));
+    assert!(matches!(*sum.head, HeadExpression::Simple(_)));
+    assert_eq!(sum.tail.len(), 1);
+    assert_eq!(sum.tail[0].0, BinaryOperator::Add);
+    assert_eq!(sum.tail[0].1.span, span(7, 7));
+    assert_eq!(sum.tail[0].1.tail[0].0, BinaryOperator::Mul);
+}
+
+#[test]
+fn error_position() {
+    let err =
+        parse_chunk(&b"local x = 1\nlocal y = = 2"[..], BasicInterner::default()).unwrap_err();
+    assert_eq!(err.line_number, LineNumber(1));
+    assert_eq!(err.column, 10);
+    assert!(matches!(err.kind, ParseErrorKind::Unexpected { .. }));
+    assert!(err.to_string().contains("line 2, column 11"));
+}
+
+#[test]
+fn error_recovery() {
+    let errors = parse_chunk_with_recovery(
+        &br#"
+            local a = = 1
+            local function f()
+                return a +
+            end
+            print(a)
+            b c
+            end
+        "#[..],
+        BasicInterner::default(),
+    )
+    .unwrap_err();
+
+    let lines = errors.iter().map(|e| e.line_number.0).collect::<Vec<_>>();
+    assert_eq!(lines, [1, 4, 6, 7]);
+
+    assert!(parse_chunk_with_recovery(&b"local a = 1"[..], BasicInterner::default()).is_ok());
+}
+
+#[test]
+fn short_lambdas() -> Result<(), StaticError> {
+    const SOURCE: &str = r##"
+        local add = |a, b| a + b
+        local count = |...| select("#", ...)
+        local nothing = || nil
+        local curried = |a| |b| a .. b
+        assert(add(1, 2) == 3 and count(1, 2, 3) == 3 and nothing() == nil)
+        assert(curried("x")("y") == "xy")
+        assert((|x| x | 1)(2) == 3)
+        assert(load("return |x| x * 2")()(4) == 8)
+    "##;
+
+    // Standard Lua is parsed unless the extension is enabled.
+    assert!(parse_chunk(SOURCE.as_bytes(), BasicInterner::default()).is_err());
+    let options = CompilerOptions {
+        short_lambdas: true,
+    };
+    parse_chunk_with_options(SOURCE.as_bytes(), BasicInterner::default(), options).unwrap();
+
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        assert!(Closure::load(ctx, None, SOURCE.as_bytes()).is_err());
+        ctx.set_compiler_options(options);
+        let closure = Closure::load(ctx, None, SOURCE.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
@@ -0,0 +1,283 @@
+use piccolo::{Lua, PluginError, PluginManager, PluginQuota, Table, Value};
+
+#[test]
+fn plugin_lifecycle() -> Result<(), PluginError> {
+    let mut lua = Lua::core();
+    let mut plugins = PluginManager::new();
+
+    lua.try_enter(|ctx| {
+        ctx.set_global("host", Table::new(&ctx))?;
+        Ok(())
+    })?;
+
+    plugins.load(
+        &mut lua,
+        "counter",
+        &br#"
+            count = 0
+            function tick()
+                while true do
+                    count = count + 1
+                    coroutine.yield()
+                end
+            end
+            function fail()
+                error("plugin failure")
+            end
+            resource = setmetatable({}, { __gc = function() host.finalized = true end })
+        "#[..],
+    )?;
+    assert_eq!(plugins.plugins().collect::<Vec<_>>(), vec!["counter"]);
+    assert!(matches!(
+        plugins.load(&mut lua, "counter", &b""[..]),
+        Err(PluginError::AlreadyLoaded(_))
+    ));
+
+    // Globals defined by the plugin stay in its environment.
+    let env = plugins.env("counter").unwrap().clone();
+    lua.enter(|ctx| {
+        assert!(ctx.get_global("count").is_nil());
+        let captured = ctx.captured_globals(ctx.fetch(&env));
+        assert!(matches!(captured.get(ctx, "tick"), Value::Function(_)));
+    });
+
+    plugins.spawn(&mut lua, "counter", "tick")?;
+    plugins.spawn(&mut lua, "counter", "fail")?;
+    assert!(matches!(
+        plugins.spawn(&mut lua, "counter", "missing"),
+        Err(PluginError::NoSuchFunction { .. })
+    ));
+    assert_eq!(plugins.task_count("counter"), Some(2));
+
+    let errors = plugins.step(&mut lua, 1024);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "counter");
+    assert_eq!(plugins.task_count("counter"), Some(1));
+
+    // Disabling stops every task and prevents new ones.
+    plugins.disable(&mut lua, "counter")?;
+    assert_eq!(plugins.is_enabled("counter"), Some(false));
+    assert_eq!(plugins.task_count("counter"), Some(0));
+    assert!(matches!(
+        plugins.spawn(&mut lua, "counter", "tick"),
+        Err(PluginError::Disabled(_))
+    ));
+    plugins.enable("counter")?;
+    plugins.spawn(&mut lua, "counter", "tick")?;
+    plugins.step(&mut lua, 1024);
+
+    lua.enter(|ctx| {
+        let env = ctx.fetch(&env);
+        assert!(matches!(env.get(ctx, "count"), Value::Integer(2)));
+    });
+    drop(env);
+
+    plugins.unload(&mut lua, "counter")?;
+    assert_eq!(plugins.is_enabled("counter"), None);
+    assert!(matches!(
+        plugins.disable(&mut lua, "counter"),
+        Err(PluginError::NotLoaded(_))
+    ));
+
+    lua.enter(|ctx| {
+        let Value::Table(host) = ctx.get_global("host") else {
+            panic!("host table missing");
+        };
+        assert!(matches!(host.get(ctx, "finalized"), Value::Boolean(true)));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn plugin_load_error() {
+    let mut lua = Lua::core();
+    let mut plugins = PluginManager::new();
+
+    assert!(matches!(
+        plugins.load(&mut lua, "broken", &br#"error("broken")"#[..]),
+        Err(PluginError::Lua(_))
+    ));
+    assert_eq!(plugins.plugins().count(), 0);
+}
+
+#[test]
+fn plugin_quotas() -> Result<(), PluginError> {
+    let mut lua = Lua::core();
+    let mut plugins = PluginManager::new();
+
+    plugins.load(
+        &mut lua,
+        "greedy",
+        &br#"
+            spins = 0
+            function spin()
+                while true do
+                    spins = spins + 1
+                end
+            end
+        "#[..],
+    )?;
+    plugins.set_quota(
+        "greedy",
+        PluginQuota {
+            max_tasks: Some(2),
+            max_fuel_per_step: Some(1024),
+            max_timers: None,
+        },
+    )?;
+    assert_eq!(plugins.quota("greedy").unwrap().max_tasks, Some(2));
+
+    plugins.spawn(&mut lua, "greedy", "spin")?;
+    plugins.spawn(&mut lua, "greedy", "spin")?;
+    assert!(matches!(
+        plugins.spawn(&mut lua, "greedy", "spin"),
+        Err(PluginError::TaskQuotaExceeded { max_tasks: 2, .. })
+    ));
+
+    // The first task uses up the whole budget, so each step only runs one of the two tasks, and
+    // they take turns.
+    let spins = |lua: &mut Lua, plugins: &PluginManager| {
+        let env = plugins.env("greedy").unwrap().clone();
+        lua.enter(|ctx| match ctx.fetch(&env).get(ctx, "spins") {
+            Value::Integer(i) => i,
+            _ => panic!("spins is not an integer"),
+        })
+    };
+    plugins.step(&mut lua, 1024);
+    let after_one = spins(&mut lua, &plugins);
+    assert!(after_one > 0);
+    plugins.step(&mut lua, 1024);
+    let after_two = spins(&mut lua, &plugins);
+    assert!(after_two > after_one && after_two < after_one * 3);
+    assert_eq!(plugins.task_count("greedy"), Some(2));
+
+    Ok(())
+}
+
+#[test]
+fn plugin_timers() -> Result<(), PluginError> {
+    let mut lua = Lua::core();
+    let mut plugins = PluginManager::new();
+
+    plugins.load(
+        &mut lua,
+        "timed",
+        &br#"
+            fired = 0
+            function fire()
+                fired = fired + 1
+                coroutine.yield()
+            end
+        "#[..],
+    )?;
+    plugins.set_quota(
+        "timed",
+        PluginQuota {
+            max_tasks: Some(1),
+            max_timers: Some(2),
+            ..PluginQuota::default()
+        },
+    )?;
+
+    plugins.set_timer(&mut lua, "timed", "fire", 1)?;
+    plugins.set_timer(&mut lua, "timed", "fire", 0)?;
+    assert!(matches!(
+        plugins.set_timer(&mut lua, "timed", "fire", 0),
+        Err(PluginError::TimerQuotaExceeded { max_timers: 2, .. })
+    ));
+    assert!(matches!(
+        plugins.set_timer(&mut lua, "timed", "missing", 0),
+        Err(PluginError::NoSuchFunction { .. })
+    ));
+    assert_eq!(plugins.timer_count("timed"), Some(2));
+
+    let fired = |lua: &mut Lua, plugins: &PluginManager| {
+        let env = plugins.env("timed").unwrap().clone();
+        lua.enter(|ctx| match ctx.fetch(&env).get(ctx, "fired") {
+            Value::Integer(i) => i,
+            _ => panic!("fired is not an integer"),
+        })
+    };
+
+    // The second timer fires first, and the first one is held back by the task quota until the
+    // task started by the second one has finished.
+    plugins.step(&mut lua, 1024);
+    assert_eq!(fired(&mut lua, &plugins), 1);
+    assert_eq!(plugins.timer_count("timed"), Some(1));
+    plugins.step(&mut lua, 1024);
+    assert_eq!(fired(&mut lua, &plugins), 1);
+    assert_eq!(plugins.task_count("timed"), Some(0));
+    plugins.step(&mut lua, 1024);
+    assert_eq!(fired(&mut lua, &plugins), 2);
+    assert_eq!(plugins.timer_count("timed"), Some(0));
+
+    // Disabling a plugin cancels its timers.
+    plugins.set_timer(&mut lua, "timed", "fire", 0)?;
+    plugins.disable(&mut lua, "timed")?;
+    assert_eq!(plugins.timer_count("timed"), Some(0));
+    assert!(matches!(
+        plugins.set_timer(&mut lua, "timed", "fire", 0),
+        Err(PluginError::Disabled(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn plugin_shutdown() -> Result<(), PluginError> {
+    let mut lua = Lua::core();
+    let mut plugins = PluginManager::new();
+
+    lua.try_enter(|ctx| {
+        ctx.set_global("host", Table::new(&ctx))?;
+        Ok(())
+    })?;
+
+    plugins.load(
+        &mut lua,
+        "storage",
+        &br#"
+            function tick()
+                while true do coroutine.yield() end
+            end
+            function on_shutdown()
+                coroutine.yield()
+                host.flushed = true
+            end
+        "#[..],
+    )?;
+    plugins.load(
+        &mut lua,
+        "stuck",
+        &br#"
+            function on_shutdown()
+                while true do end
+            end
+        "#[..],
+    )?;
+    plugins.load(&mut lua, "quiet", &b""[..])?;
+
+    plugins.spawn(&mut lua, "storage", "tick")?;
+    plugins.step(&mut lua, 1024);
+    assert_eq!(plugins.task_count("storage"), Some(1));
+
+    let errors = plugins.shutdown(&mut lua, 4096);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "stuck");
+    assert!(matches!(errors[0].1, PluginError::ShutdownTimedOut(_)));
+
+    lua.enter(|ctx| {
+        let Value::Table(host) = ctx.get_global("host") else {
+            panic!("host table missing");
+        };
+        assert!(matches!(host.get(ctx, "flushed"), Value::Boolean(true)));
+    });
+
+    for name in ["storage", "stuck", "quiet"] {
+        assert_eq!(plugins.is_enabled(name), Some(false));
+        assert_eq!(plugins.task_count(name), Some(0));
+    }
+
+    Ok(())
+}
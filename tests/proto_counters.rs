@@ -0,0 +1,58 @@
+use piccolo::{compiler::FunctionRef, Closure, Executor, FunctionPrototype, Lua, StaticError};
+
+#[test]
+fn hottest_prototypes() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (closure, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function hot()
+                    for i = 1, 100 do end
+                end
+
+                local function cold()
+                end
+
+                for i = 1, 10 do
+                    hot()
+                end
+                cold()
+            "#[..],
+        )?;
+
+        Ok((
+            ctx.stash(closure),
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+        ))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        let proto = ctx.fetch(&closure).prototype();
+        assert_eq!(proto.counters.invocations(), 1);
+        assert_eq!(proto.counters.back_edges(), 9);
+
+        let hottest = FunctionPrototype::hottest(proto, 8);
+        assert_eq!(hottest.len(), 3);
+
+        assert!(matches!(hottest[0].reference, FunctionRef::Named(n, _) if n == "hot"));
+        assert_eq!(hottest[0].counters.invocations(), 10);
+        assert_eq!(hottest[0].counters.back_edges(), 990);
+
+        assert!(matches!(hottest[1].reference, FunctionRef::Chunk));
+
+        assert!(matches!(hottest[2].reference, FunctionRef::Named(n, _) if n == "cold"));
+        assert_eq!(hottest[2].counters.heat(), 1);
+
+        assert_eq!(FunctionPrototype::hottest(proto, 1).len(), 1);
+
+        proto.reset_counters();
+        assert!(FunctionPrototype::hottest(proto, 8).is_empty());
+    });
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use piccolo::{Closure, Executor, Lua, StaticError, Variadic};
+use rand::rngs::mock::StepRng;
+
+fn random_values(lua: &mut Lua) -> Result<Vec<i64>, StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &b"
+                local values = {}
+                for i = 1, 8 do
+                    values[i] = math.random(1000000)
+                end
+                return table.unpack(values)
+            "[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<Variadic<Vec<i64>>>(&executor)
+        .map(|v| v.into_iter().collect())
+}
+
+#[test]
+fn seeded_random_is_deterministic() -> Result<(), StaticError> {
+    let mut first = Lua::core();
+    first.enter(|ctx| ctx.seed_random(42));
+    let mut second = Lua::core();
+    second.enter(|ctx| ctx.seed_random(42));
+
+    let values = random_values(&mut first)?;
+    assert_eq!(values, random_values(&mut second)?);
+    assert_ne!(values, random_values(&mut first)?);
+
+    // Reseeding restarts the same sequence.
+    first.enter(|ctx| ctx.seed_random(42));
+    assert_eq!(values, random_values(&mut first)?);
+
+    Ok(())
+}
+
+#[test]
+fn replace_random_generator() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| ctx.set_random_generator(StepRng::new(0, 0)));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return math.random()"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    assert_eq!(lua.execute::<f64>(&executor)?, 0.0);
+
+    Ok(())
+}
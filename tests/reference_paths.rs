@@ -0,0 +1,95 @@
+use piccolo::{Closure, Executor, Fuel, Lua, PathStep, StaticError, Table, Value};
+
+fn run(lua: &mut Lua, source: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn paths_from_globals() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    run(
+        &mut lua,
+        r#"
+            leaked = {}
+            cache = { entries = { 1, 2, leaked } }
+            local held = leaked
+            function keep() return held end
+            weird = { ["not a name"] = setmetatable({}, leaked) }
+        "#,
+    )?;
+
+    let target = lua.enter(|ctx| ctx.stash(ctx.get_global("leaked")));
+    run(&mut lua, "leaked = nil")?;
+
+    let paths = lua.reference_paths(&target, &[], 10);
+    let mut rendered: Vec<_> = paths.iter().map(|p| p.to_string()).collect();
+    rendered.sort();
+    assert_eq!(
+        rendered,
+        [
+            "_G.cache.entries[3]",
+            "_G.keep.<upvalue 0>",
+            "_G.weird[\"not a name\"].<metatable>",
+        ]
+    );
+
+    let first = lua.reference_paths(&target, &[], 1);
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].root, "_G");
+
+    let cache = paths
+        .iter()
+        .find(|p| p.steps.first() == Some(&PathStep::Field("\"cache\"".to_owned())))
+        .unwrap();
+    assert_eq!(
+        cache.steps,
+        [
+            PathStep::Field("\"cache\"".to_owned()),
+            PathStep::Field("\"entries\"".to_owned()),
+            PathStep::Field("3".to_owned()),
+        ]
+    );
+
+    run(&mut lua, "cache = nil keep = nil weird = nil")?;
+    assert!(lua.reference_paths(&target, &[], 10).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn paths_from_executors() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    let (executor, target) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &b"
+                local t = ...
+                while true do end
+            "[..],
+        )?;
+        let target = Table::new(&ctx);
+        let executor = Executor::start(ctx, closure.into(), target);
+        Ok((ctx.stash(executor), ctx.stash(Value::Table(target))))
+    })?;
+
+    // Run the executor for a while, so that the table is a local on its stack.
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert!(!executor.step(ctx, &mut Fuel::with(100)));
+    });
+
+    assert!(lua.reference_paths(&target, &[], 10).is_empty());
+    let paths = lua.reference_paths(&target, &[&executor], 10);
+    assert!(!paths.is_empty());
+    for path in &paths {
+        assert_eq!(path.root, "executor 0");
+        assert!(matches!(path.steps[..], [PathStep::Stack(_)]));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+#![cfg(debug_assertions)]
+
+use std::mem;
+
+use piccolo::{Lua, Table};
+
+// Smuggles a table out of one `Lua` instance, which is only possible with unsafe code.
+fn foreign_table(lua: &mut Lua) -> Table<'static> {
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        // Keep the table alive for as long as the instance is.
+        ctx.globals().set(ctx, "foreign", table).unwrap();
+        unsafe { mem::transmute::<Table<'_>, Table<'static>>(table) }
+    })
+}
+
+#[test]
+#[should_panic(expected = "a table from a different `Lua` instance was stored in a table")]
+fn foreign_table_value() {
+    let mut other = Lua::core();
+    let foreign = foreign_table(&mut other);
+
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let foreign = unsafe { mem::transmute::<Table<'static>, Table<'_>>(foreign) };
+        ctx.globals().set(ctx, "leaked", foreign).unwrap();
+    });
+}
+
+#[test]
+#[should_panic(
+    expected = "a table from a different `Lua` instance was stored in a table metatable"
+)]
+fn foreign_metatable() {
+    let mut other = Lua::core();
+    let foreign = foreign_table(&mut other);
+
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let foreign = unsafe { mem::transmute::<Table<'static>, Table<'_>>(foreign) };
+        Table::new(&ctx).set_metatable(&ctx, Some(foreign));
+    });
+}
+
+#[test]
+fn same_instance() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, table, table).unwrap();
+        table.set_metatable(&ctx, Some(table));
+        ctx.globals().set(ctx, "table", table).unwrap();
+    });
+}
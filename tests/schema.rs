@@ -0,0 +1,86 @@
+use piccolo::{
+    schema::{Field, FieldType},
+    Closure, Executor, Fuel, Lua, Schema, Value,
+};
+
+fn schema() -> Schema {
+    Schema::new()
+        .field(
+            Field::new("width", FieldType::Integer)
+                .required()
+                .range(1.0..=4096.0),
+        )
+        .field(Field::new("height", FieldType::Integer).default(480))
+        .field(Field::new("title", FieldType::String))
+        .field(Field::new(
+            "window",
+            FieldType::Record(
+                Schema::new().field(
+                    Field::new("size", FieldType::Number)
+                        .required()
+                        .range(0.0..=1.0),
+                ),
+            ),
+        ))
+        .field(Field::new(
+            "tags",
+            FieldType::List(Box::new(FieldType::String)),
+        ))
+}
+
+#[test]
+fn test_schema() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return {
+                    width = 0,
+                    title = {},
+                    colour = "red",
+                    window = { size = "big" },
+                    tags = { "a", 2 },
+                }
+            "#[..],
+        )
+        .unwrap();
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.step(ctx, &mut Fuel::with(i32::MAX));
+        let options = executor.take_result::<Value>(ctx).unwrap().unwrap();
+
+        let schema = schema();
+        let err = schema.validate(ctx, "opts", options).unwrap_err();
+        assert_eq!(err.issues.len(), 5);
+        assert_eq!(
+            err.to_string(),
+            "invalid options: opts.width: 0 is out of range 1..=4096; \
+             opts.title: expected string, got table; \
+             opts.window.size: expected number, got string; \
+             opts.tags[2]: expected string, got number; \
+             opts.colour: unknown field"
+        );
+
+        let Value::Table(options) = options else {
+            panic!("expected a table");
+        };
+        assert!(matches!(options.get(ctx, "height"), Value::Integer(480)));
+
+        assert!(schema.validate(ctx, "opts", Value::Nil).is_err());
+        assert_eq!(
+            schema
+                .validate(ctx, "opts", Value::Integer(5))
+                .unwrap_err()
+                .to_string(),
+            "invalid options: opts: expected table, got number"
+        );
+
+        let lenient = Schema::new()
+            .field(Field::new("height", FieldType::Integer).default(480))
+            .allow_unknown();
+        let table = lenient.validate(ctx, "opts", Value::Nil).unwrap();
+        assert!(matches!(table.get(ctx, "height"), Value::Integer(480)));
+    });
+}
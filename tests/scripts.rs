@@ -5,12 +5,15 @@ use std::{
     io::{stdout, Read, Write},
 };
 
-use piccolo::{io, Closure, Executor, Lua, StaticError};
+use piccolo::{io, Closure, Executor, Lua, OpCodeChecks, StaticError};
 
-fn run_lua_code(name: &str, code: impl Read) -> Result<(), StaticError> {
+fn run_lua_code(name: &str, code: impl Read, checks: OpCodeChecks) -> Result<(), StaticError> {
     let mut lua = Lua::full();
 
     let exec = lua.try_enter(|ctx| {
+        ctx.set_opcode_checks(checks);
+        // The scripts rely on PUC-Rio Lua's coercion of strings in arithmetic.
+        ctx.set_string_coercion(true);
         let closure = Closure::load(ctx, Some(name), code)?;
         Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
     })?;
@@ -20,7 +23,7 @@ fn run_lua_code(name: &str, code: impl Read) -> Result<(), StaticError> {
     Ok(())
 }
 
-fn run_tests(dir: &str) -> bool {
+fn run_tests(dir: &str, checks: OpCodeChecks) -> bool {
     let _ = writeln!(stdout(), "running all test scripts in {dir:?}");
 
     let mut file_failed = false;
@@ -30,7 +33,7 @@ fn run_tests(dir: &str) -> bool {
         if let Some(ext) = path.extension() {
             if ext == "lua" {
                 let _ = writeln!(stdout(), "running {:?}", path);
-                if let Err(err) = run_lua_code(path.to_string_lossy().as_ref(), file) {
+                if let Err(err) = run_lua_code(path.to_string_lossy().as_ref(), file, checks) {
                     let _ = writeln!(stdout(), "error encountered running: {:?}", err);
                     file_failed = true;
                 }
@@ -46,11 +49,11 @@ fn run_tests(dir: &str) -> bool {
 fn test_scripts() {
     let mut file_failed = false;
 
-    file_failed |= run_tests("./tests/scripts");
+    file_failed |= run_tests("./tests/scripts", OpCodeChecks::Checked);
 
     let _ = writeln!(stdout(), "Running non-required tests");
 
-    let non_required_failed = run_tests("./tests/scripts-wishlist");
+    let non_required_failed = run_tests("./tests/scripts-wishlist", OpCodeChecks::Checked);
 
     if non_required_failed {
         let _ = writeln!(stdout(), "one or more non-required tests failed");
@@ -60,3 +63,10 @@ fn test_scripts() {
         panic!("one or more errors occurred");
     }
 }
+
+#[test]
+fn test_scripts_trusted() {
+    if run_tests("./tests/scripts", OpCodeChecks::Trusted) {
+        panic!("one or more errors occurred");
+    }
+}
@@ -26,3 +26,24 @@ do
     local i, j = -16, 3
     assert(i // j == math.floor(i / j))
 end
+
+do
+    -- Integer arithmetic wraps around on overflow
+    assert(math.maxinteger + 1 == math.mininteger)
+    assert(math.mininteger - 1 == math.maxinteger)
+    assert(math.maxinteger * 2 == -2)
+    assert(-math.mininteger == math.mininteger)
+    assert(math.mininteger // -1 == math.mininteger)
+    assert(math.mininteger % -1 == 0)
+    assert(math.abs(math.mininteger) == math.mininteger)
+
+    local max, one = math.maxinteger, 1
+    assert(max + one == math.mininteger)
+
+    assert(math.wrapadd(math.maxinteger, 1) == math.mininteger)
+    assert(math.wrapsub(math.mininteger, 1) == math.maxinteger)
+    assert(math.wrapmul(math.maxinteger, 2) == -2)
+    assert(math.wrapadd(1, 2) == 3)
+    assert(math.type(math.wrapadd(1.0, 2)) == "integer")
+    assert(not pcall(math.wrapadd, 1.5, 2))
+end
@@ -0,0 +1,132 @@
+local function closer(log, name)
+    return setmetatable({}, {
+        __close = function(self, err)
+            log[#log + 1] = name
+            if err ~= nil then
+                log[#log + 1] = err
+            end
+        end
+    })
+end
+
+do
+    -- Variables are closed in reverse order when their block ends
+    local log = {}
+    do
+        local a <close> = closer(log, "a")
+        local b <close> = closer(log, "b")
+        local c <const> = 3
+        assert(#log == 0)
+    end
+    assert(#log == 2 and log[1] == "b" and log[2] == "a")
+end
+
+do
+    -- nil and false are allowed and ignored
+    local log = {}
+    do
+        local a <close> = nil
+        local b <close> = false
+        local c <close> = closer(log, "c")
+    end
+    assert(#log == 1 and log[1] == "c")
+end
+
+do
+    -- Variables are closed on return, after the return values are computed
+    local log = {}
+    local function f()
+        local a <close> = closer(log, "a")
+        return #log, "ret"
+    end
+    local n, r = f()
+    assert(n == 0 and r == "ret")
+    assert(#log == 1 and log[1] == "a")
+
+    -- Returning a call is not a tail call when there are variables to close
+    local function g(...)
+        return ...
+    end
+    local function h()
+        local a <close> = closer(log, "h")
+        return g(1, 2, 3)
+    end
+    local x, y, z = h()
+    assert(x == 1 and y == 2 and z == 3)
+    assert(log[2] == "h")
+end
+
+do
+    -- Variables are closed on break and goto
+    local log = {}
+    for i = 1, 3 do
+        local a <close> = closer(log, i)
+        if i == 2 then
+            break
+        end
+    end
+    assert(#log == 2 and log[1] == 1 and log[2] == 2)
+
+    log = {}
+    local i = 0
+    ::again::
+    do
+        local a <close> = closer(log, i)
+        i = i + 1
+        if i < 3 then
+            goto again
+        end
+    end
+    assert(#log == 3 and log[1] == 0 and log[2] == 1 and log[3] == 2)
+end
+
+do
+    -- Variables are closed with the error during unwinding
+    local log = {}
+    local ok, err = pcall(function()
+        local a <close> = closer(log, "a")
+        local b <close> = closer(log, "b")
+        error("oops", 0)
+    end)
+    assert(not ok and err == "oops")
+    assert(#log == 4)
+    assert(log[1] == "b" and log[2] == "oops" and log[3] == "a" and log[4] == "oops")
+end
+
+do
+    -- An error in a `__close` metamethod replaces the original error
+    local log = {}
+    local ok, err = pcall(function()
+        local a <close> = closer(log, "a")
+        local b <close> = setmetatable({}, { __close = function() error("close error", 0) end })
+        error("original", 0)
+    end)
+    assert(not ok and err == "close error")
+    assert(log[1] == "a" and log[2] == "close error")
+end
+
+do
+    -- Values without a `__close` metamethod are rejected
+    assert(not pcall(function()
+        local a <close> = {}
+    end))
+    assert(not pcall(function()
+        local a <close> = 1
+    end))
+end
+
+do
+    -- `__close` metamethods can yield
+    local co = coroutine.create(function()
+        local a <close> = setmetatable({}, {
+            __close = function()
+                coroutine.yield("closing")
+            end
+        })
+        return "done"
+    end)
+    local ok, r = coroutine.resume(co)
+    assert(ok and r == "closing")
+    ok, r = coroutine.resume(co)
+    assert(ok and r == "done")
+end
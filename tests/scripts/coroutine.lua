@@ -31,7 +31,7 @@ end
 do
     local function test_coroutine()
         coroutine.yield(1)
-        error('test error')
+        error('test error', 0)
     end
 
     local co = coroutine.create(test_coroutine)
@@ -103,3 +103,37 @@ do
         coroutine.yieldto(co)
     end) == false)
 end
+
+do
+    local outer
+    local inner = coroutine.create(function()
+        coroutine.yield(coroutine.status(outer), coroutine.isyieldable())
+    end)
+    outer = coroutine.create(function()
+        return coroutine.resume(inner)
+    end)
+
+    local e1, e2, status, yieldable = coroutine.resume(outer)
+    assert(e1 == true and e2 == true and status == "normal" and yieldable == true)
+    assert(coroutine.status(outer) == "dead" and coroutine.status(inner) == "suspended")
+
+    assert(coroutine.isyieldable() == false)
+    assert(coroutine.isyieldable(inner) == true)
+    local main, is_main = coroutine.running()
+    assert(is_main == true and coroutine.status(main) == "running")
+    assert(coroutine.isyieldable(main) == false)
+end
+
+do
+    local gen = coroutine.wrap(function(a)
+        local b = coroutine.yield(a + 1)
+        coroutine.yield(b * 2)
+        error("done", 0)
+    end)
+
+    assert(gen(1) == 2)
+    assert(gen(5) == 10)
+    local ok, err = pcall(gen)
+    assert(not ok and err == "done")
+    assert(not pcall(gen))
+end
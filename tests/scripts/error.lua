@@ -0,0 +1,46 @@
+do
+    local a, b, c = assert(1, "message", 3)
+    assert(a == 1 and b == "message" and c == 3)
+
+    local ok, err = pcall(assert, false)
+    assert(not ok and err == "assertion failed!")
+    ok, err = pcall(assert, nil, "custom")
+    assert(not ok and err == "custom")
+    local t = {}
+    ok, err = pcall(assert, false, t)
+    assert(not ok and err == t)
+    ok, err = pcall(assert)
+    assert(not ok and err == "bad argument #1 to 'assert' (value expected)")
+end
+
+do
+    local function fail(level)
+        error("failed", level)
+    end
+    local function call_fail(level)
+        fail(level)
+    end
+
+    -- Level 1 is the position of the call to `error`, level 2 is the function which called it.
+    local ok, err = pcall(call_fail)
+    assert(not ok and err:match("^.*error%.lua:18: failed$"))
+    ok, err = pcall(call_fail, 1)
+    assert(not ok and err:match("^.*error%.lua:18: failed$"))
+    ok, err = pcall(call_fail, 2)
+    assert(not ok and err:match("^.*error%.lua:21: failed$"))
+    ok, err = pcall(call_fail, 0)
+    assert(not ok and err == "failed")
+
+    -- Levels which are not Lua functions add no position.
+    ok, err = pcall(error, "direct")
+    assert(not ok and err == "direct")
+    ok, err = pcall(call_fail, 10)
+    assert(not ok and err == "failed")
+
+    -- Only string messages get a position.
+    local t = {}
+    ok, err = pcall(error, t)
+    assert(not ok and err == t)
+    ok, err = pcall(function() error(t, 1) end)
+    assert(not ok and err == t)
+end
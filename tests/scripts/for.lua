@@ -120,6 +120,40 @@ function test_mixed_floats()
     return true
 end
 
+function test_underflow()
+    -- the loop must not stop early when `initial - step` would underflow
+    local iters = 0
+    for i = math.mininteger, math.mininteger + 2 do
+        iters = iters + 1
+        assert(i < 0)
+    end
+    assert(iters == 3)
+
+    iters = 0
+    for i = math.mininteger + 32, math.mininteger, -1 do
+        iters = iters + 1
+        assert(iters < 50)
+    end
+    assert(iters == 33)
+
+    iters = 0
+    for i = math.maxinteger, math.mininteger, math.mininteger do
+        iters = iters + 1
+    end
+    assert(iters == 2)
+
+    iters = 0
+    for i = 1, 0 do
+        iters = iters + 1
+    end
+    assert(iters == 0)
+
+    assert(not pcall(function() for i = 1, 10, 0 do end end))
+    assert(not pcall(function() for i = 1.0, 10, 0.0 do end end))
+
+    return true
+end
+
 assert(
     test_generic() and
     test_numeric() and
@@ -127,5 +161,6 @@ assert(
     test_generic_closure() and
     test_break_scope() and
     test_mixed_floats() and
-    test_overflow()
+    test_overflow() and
+    test_underflow()
 )
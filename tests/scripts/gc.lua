@@ -0,0 +1,28 @@
+do
+    assert(collectgarbage("isrunning"))
+    assert(collectgarbage("stop") == 0)
+    assert(not collectgarbage("isrunning"))
+    assert(collectgarbage("restart") == 0)
+    assert(collectgarbage("isrunning"))
+end
+
+do
+    local garbage = {}
+    for i = 1, 10000 do
+        garbage[i] = { i }
+    end
+    local before = collectgarbage("count")
+    garbage = nil
+    assert(collectgarbage() == 0)
+    assert(collectgarbage("count") < before)
+    assert(collectgarbage("collect") == 0)
+    assert(collectgarbage("step") == true)
+end
+
+do
+    assert(collectgarbage("incremental", 200, 100, 13) == "incremental")
+    assert(collectgarbage("incremental") == "incremental")
+
+    local ok, err = pcall(collectgarbage, "bogus")
+    assert(not ok and err == "bad argument #1 to 'collectgarbage' (invalid option 'bogus')")
+end
@@ -30,7 +30,83 @@ function test2()
     goto start
 end
 
+function test3()
+    -- `goto continue` skips the rest of a loop body, and each iteration still gets fresh locals
+    local closures = {}
+    for i = 1, 6 do
+        local captured = i * 10
+        if i % 2 == 0 then
+            goto continue
+        end
+        closures[#closures + 1] = function() return captured end
+        ::continue::
+    end
+    return #closures == 3 and closures[1]() == 10 and closures[2]() == 30 and
+        closures[3]() == 50
+end
+
+function test4()
+    -- Backward jumps close upvalues, so every closure sees its own copy of `v`
+    local closures = {}
+    local i = 1
+    ::top::
+    local v = i
+    closures[i] = function() return v end
+    i = i + 1
+    if i <= 3 then
+        goto top
+    end
+    return closures[1]() == 1 and closures[2]() == 2 and closures[3]() == 3
+end
+
+function test5()
+    -- Breaking out of nested loops and continuing an outer loop
+    local found
+    for x = 1, 5 do
+        for y = 1, 5 do
+            if x * y == 12 then
+                found = x .. "," .. y
+                goto done
+            end
+            if y > x then
+                goto next_x
+            end
+        end
+        ::next_x::
+    end
+    ::done::
+    return found == "4,3"
+end
+
+function test6()
+    local i = 0
+    while true do
+        i = i + 1
+        if i < 5 then
+            goto continue
+        end
+        do break end
+        ::continue::
+    end
+    return i == 5
+end
+
+function test7()
+    -- Jumping into the scope of a local, to a label that is not visible, or defining a label
+    -- twice in the same block are all compile errors
+    return load("goto skip; local x = 1; ::skip:: print(x)") == nil and
+        load("do ::inner:: end goto inner") == nil and
+        load("local function f() goto outer end ::outer::") == nil and
+        load("::a:: ::a::") == nil and
+        load("do goto done; local x = 1 end ::done::") ~= nil
+end
+
 assert(
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4() and
+    test5() and
+    test6() and
+    test7()
 )
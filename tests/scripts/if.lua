@@ -29,7 +29,61 @@ function test2()
         test(true) == 2
 end
 
+function test3()
+    -- Branches with constant conditions are resolved when compiling, but still behave the same
+    local r = {}
+    if false then
+        r[#r + 1] = "dead"
+    elseif nil then
+        r[#r + 1] = "dead"
+    elseif 0 then
+        r[#r + 1] = "zero"
+    elseif true then
+        r[#r + 1] = "dead"
+    else
+        r[#r + 1] = "dead"
+    end
+
+    local x = 1
+    if #"abc" == 3 then
+        local x = 2
+        r[#r + 1] = x
+    end
+    r[#r + 1] = x
+
+    if not true then
+        r[#r + 1] = "dead"
+    else
+        r[#r + 1] = "else"
+    end
+
+    return #r == 4 and r[1] == "zero" and r[2] == 2 and r[3] == 1 and r[4] == "else"
+end
+
+function test4()
+    -- Breaks, gotos and closures in dead branches are dropped with them
+    local count = 0
+    for i = 1, 3 do
+        if false then
+            local f = function() return i end
+            break
+        end
+        if nil then
+            goto next
+        end
+        count = count + i
+        ::next::
+    end
+
+    -- Dead code is still checked for errors
+    return count == 6 and
+        load("if false then local x <const> = 1; x = 2 end") == nil and
+        load("if true then else ::a:: ::a:: end") == nil
+end
+
 assert(
     test1() and
-    test2()
+    test2() and
+    test3() and
+    test4()
 )
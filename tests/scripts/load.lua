@@ -0,0 +1,104 @@
+do
+    local f = assert(load("return 1 + 2"))
+    assert(f() == 3)
+
+    local g = assert(load("local a, b = ... return a * b"))
+    assert(g(6, 7) == 42)
+
+    local f, err = load("return +")
+    assert(f == nil and type(err) == "string")
+
+    local f, err = load("return +", "=chunk")
+    assert(f == nil and err:sub(1, 7) == "chunk: ")
+end
+
+do
+    local env = { x = 5 }
+    local f = assert(load("y = x * 2 return x", "env", "t", env))
+    assert(f() == 5)
+    assert(env.y == 10 and y == nil)
+
+    local f = assert(load("return x", "nil env", "t", nil))
+    assert(not pcall(f))
+
+    x_global = "global"
+    local f = assert(load("return x_global"))
+    assert(f() == "global")
+    x_global = nil
+end
+
+do
+    local f, err = load("\27Lua", "binary", "t")
+    assert(f == nil and err == "attempt to load a binary chunk (mode is 't')")
+
+    local f, err = load("return 1", "text", "b")
+    assert(f == nil and err == "attempt to load a text chunk (mode is 'b')")
+
+    local f, err = load("\27Lua")
+    assert(f == nil)
+end
+
+do
+    local parts = { "return ", "'he", "llo'", "" }
+    local i = 0
+    local f = assert(load(function()
+        i = i + 1
+        return parts[i]
+    end))
+    assert(f() == "hello" and i == 4)
+
+    local pieces = { "local t = {} ", "for i = 1, 3 do t[i] = i end ", "return #t" }
+    local i = 0
+    local f = assert(load(function()
+        i = i + 1
+        return pieces[i]
+    end))
+    assert(f() == 3)
+
+    local f, err = load(function() return {} end)
+    assert(f == nil and err == "reader function must return a string")
+    local f, err = load(function() error("reader failed", 0) end)
+    assert(f == nil and err == "reader failed")
+
+    local co = coroutine.wrap(function()
+        return load(function()
+            return coroutine.yield()
+        end)
+    end)
+    co()
+    co("return 'yielded'")
+    local f = co(nil)
+    assert(f() == "yielded")
+
+    assert(not pcall(load, 42))
+end
+
+do
+    local function add(a, b) return a + b end
+    assert(load(string.dump(add))(2, 3) == 5)
+    assert(load(string.dump(add, true))(2, 3) == 5)
+
+    local source = "local t = {} for i = 1, 3 do " ..
+        "t[i] = function() return i * 1.5, 'x' .. i end end return t"
+    local t = load(string.dump(load(source)))()
+    local a, b = t[2]()
+    assert(a == 3.0 and b == "x2")
+
+    -- The first upvalue of a loaded function is the environment, and the rest are nil.
+    local x, y = 1, 2
+    local function upvalues() return x, y end
+    local first, second = load(string.dump(upvalues))()
+    assert(first == _G and second == nil)
+    local env = {}
+    assert(load(string.dump(upvalues), "upvalues", "b", env)() == env)
+
+    local ok, err = pcall(string.dump, print)
+    assert(not ok and err == "unable to dump given function")
+
+    local f, err = load(string.dump(add), "add", "t")
+    assert(f == nil and err == "attempt to load a binary chunk (mode is 't')")
+    f, err = load("\27Lua")
+    assert(f == nil and err == "(load): not a piccolo binary chunk")
+    f, err = load(string.dump(add):sub(1, 20))
+    assert(f == nil and err == "(load): truncated binary chunk")
+end
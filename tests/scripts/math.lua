@@ -339,3 +339,22 @@ do
     assert(is_err(function() return "" + 2 end))
     assert(" 0x0 " + 2 == 2)
 end
+
+do
+    local int, frac = math.modf(3.5)
+    assert(int == 3 and frac == 0.5 and math.type(int) == "float")
+    int, frac = math.modf(-3.5)
+    assert(int == -3 and frac == -0.5)
+    int, frac = math.modf(math.huge)
+    assert(int == math.huge and frac == 0.0)
+    int, frac = math.modf(5)
+    assert(int == 5 and frac == 0.0 and math.type(int) == "float")
+
+    assert(math.floor(math.maxinteger) == math.maxinteger)
+    assert(math.ceil(math.mininteger + 1) == math.mininteger + 1)
+
+    math.randomseed(7)
+    local a, b = math.random(100), math.random(100)
+    math.randomseed(7)
+    assert(math.random(100) == a and math.random(100) == b)
+end
@@ -65,3 +65,24 @@ do
     t.foo = 4
     assert(idx.foo == 4)
 end
+
+do
+    local t = {}
+    setmetatable(t, { __index = t, __newindex = t })
+
+    local ok, err = pcall(function() return t.a end)
+    assert(not ok and tostring(err):match("^.*metaindex%.lua:73: '__index' chain too long; possible loop$"))
+
+    ok, err = pcall(function() t.a = 1 end)
+    assert(not ok and tostring(err):match("^.*metaindex%.lua:76: '__newindex' chain too long; possible loop$"))
+
+    -- Long but finite chains still work
+    local base = { value = 1 }
+    local top = base
+    for i = 1, 1000 do
+        top = setmetatable({}, { __index = top, __newindex = top })
+    end
+    assert(top.value == 1)
+    top.other = 2
+    assert(base.other == 2 and rawget(top, "other") == nil)
+end
@@ -126,13 +126,13 @@ do
     cursed_mt["__len"] = function(val) return setmetatable({ "len", val }, cursed_mt) end
     cursed_mt["__index"] = function(a, b) return setmetatable({ "index", a, b }, cursed_mt) end
     cursed_mt["__call"] = function(this, ...) return setmetatable({ "call", this, ... }, cursed_mt) end
+    cursed_mt["__concat"] = function(a, b) return setmetatable({ "concat", a, b }, cursed_mt) end
 
     -- Not tested here:
     -- cursed_mt["__newindex"] = function(a, b) end
     -- cursed_mt["__eq"] = function(a, b) return false end
     -- cursed_mt["__lt"] = function(a, b) return false end
     -- cursed_mt["__le"] = function(a, b) return false end
-    -- cursed_mt["__concat"] = function(a, b) return setmetatable({ "concat", a, b }, cursed_mt) end
 
     local function curse(val)
         return setmetatable(val, cursed_mt)
@@ -162,6 +162,37 @@ do
     assert(cmp_array_recurse(a.b, { "index", { "a" }, "b" }))
     assert(cmp_array_recurse(a(), { "call", { "a" } }))
     assert(cmp_array_recurse(a(1, 2, 3), { "call", { "a" }, 1, 2, 3 }))
+    assert(cmp_array_recurse(a .. b, { "concat", { "a" }, { "b" } }))
+    assert(cmp_array_recurse(a .. "x" .. b, { "concat", { "a" }, { "concat", "x", { "b" } } }))
 
 end
 
+
+do
+    local mt = {}
+    mt.__concat = function(a, b)
+        local av = type(a) == "table" and a.v or a
+        local bv = type(b) == "table" and b.v or b
+        return setmetatable({ v = "(" .. av .. "|" .. bv .. ")" }, mt)
+    end
+    local function wrap(v)
+        return setmetatable({ v = v }, mt)
+    end
+
+    -- As in PUC-Rio Lua, operands are joined from the right, so only the strings and numbers to
+    -- the right of a metamethod call are joined before it. Each value to the left of the call is
+    -- then joined with its result through another call.
+    assert(("a" .. 1 .. wrap("b") .. "c" .. 2).v == "(a|(1|(b|c2)))")
+    assert((wrap("a") .. wrap("b") .. wrap("c")).v == "(a|(b|c))")
+    assert(("a" .. "b" .. wrap("c")).v == "(a|(b|c))")
+
+    -- A metamethod may return a plain string, which is then joined normally.
+    local s = setmetatable({}, { __concat = function(a, b) return "s" end })
+    assert("a" .. "b" .. s .. "c" .. "d" == "abs")
+    assert(s .. "c" == "s")
+
+    local ok, err = pcall(function() return "a" .. {} .. "b" end)
+    assert(not ok and tostring(err):match("^.*metaops%.lua:192: could not concatenate values of type table and string$"))
+    ok = pcall(function() return "a" .. nil end)
+    assert(not ok)
+end
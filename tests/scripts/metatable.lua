@@ -0,0 +1,46 @@
+do
+    local mt = {}
+    local t = setmetatable({}, mt)
+    assert(getmetatable(t) == mt)
+    assert(getmetatable({}) == nil)
+
+    -- Metatables can be replaced and removed while unprotected
+    local mt2 = {}
+    assert(setmetatable(t, mt2) == t)
+    assert(getmetatable(t) == mt2)
+    setmetatable(t, nil)
+    assert(getmetatable(t) == nil)
+end
+
+do
+    local mt = { __metatable = "locked" }
+    local t = setmetatable({}, mt)
+    assert(getmetatable(t) == "locked")
+    assert(not pcall(setmetatable, t, {}))
+    assert(not pcall(setmetatable, t, nil))
+    assert(getmetatable(t) == "locked")
+
+    -- Any non-nil value protects the metatable
+    local t2 = setmetatable({}, { __metatable = false })
+    assert(getmetatable(t2) == false)
+    assert(not pcall(setmetatable, t2, {}))
+end
+
+do
+    -- `__name` is used by `tostring` and in error messages
+    local t = setmetatable({}, { __name = "Foo" })
+    assert(string.sub(tostring(t), 1, 5) == "Foo: ")
+
+    local ok, err = pcall(function() return t + 1 end)
+    assert(not ok and tostring(err):match("^.*metatable%.lua:34: could not add values of type Foo and number$"))
+
+    ok, err = pcall(function() return -t end)
+    assert(not ok and tostring(err):match("^.*metatable%.lua:37: could not negate a Foo value$"))
+
+    ok, err = pcall(function() t() end)
+    assert(not ok and tostring(err):match("^.*metatable%.lua:40: could not call a Foo value$"))
+
+    -- Non-string names are ignored
+    local u = setmetatable({}, { __name = 1 })
+    assert(string.sub(tostring(u), 1, 7) == "<table ")
+end
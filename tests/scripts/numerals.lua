@@ -0,0 +1,30 @@
+do
+    -- Hex integers wrap around instead of overflowing
+    assert(0x7fffffffffffffff == math.maxinteger)
+    assert(0xffffffffffffffff == -1)
+    assert(0x10000000000000000 == 0)
+    assert(math.type(0xffffffffffffffff) == "integer")
+    assert(tonumber("0xffffffffffffffff") == -1)
+    assert(tonumber("-0x1") == -1)
+end
+
+do
+    -- Decimal integers which do not fit become floats
+    assert(math.type(9223372036854775807) == "integer")
+    assert(math.type(9223372036854775808) == "float")
+    assert(9223372036854775808 == 2^63)
+    assert(math.type(tonumber("9223372036854775808")) == "float")
+end
+
+do
+    -- Hex floats are correctly rounded
+    assert(0x1p-1074 > 0 and 0x1p-1074 / 2 == 0)
+    assert(0x1p-1075 == 0)
+    assert(0x1.fffffffffffffp1023 < math.huge and 0x1.fffffffffffffp1023 * 2 == math.huge)
+    assert(0x1.fffffffffffff8p1023 == math.huge)
+    assert(0x1.00000000000008p0 == 1.0)
+    assert(0x1.00000000000018p0 == 1 + 2^-51)
+    assert(0xA.8p0 == 10.5)
+    assert(0x.1p4 == 1.0)
+    assert(tonumber("0x1p-2") == 0.25)
+end
@@ -142,6 +142,30 @@ function test17()
         "0x10" + "4" == 20
 end
 
+function test18()
+    -- Short-circuit operators with a constant left side are resolved when compiling
+    local function three()
+        return 1, 2, 3
+    end
+    local called = false
+    local function call()
+        called = true
+    end
+
+    return (false and call()) == false and
+        (nil or 4) == 4 and
+        (1 or call()) == 1 and
+        not called and
+        select("#", true and three()) == 1 and
+        select("#", false or three()) == 1 and
+        #"literal" == 7 and
+        2 + 3 * 4 == 14 and
+        (1 + 2) * 3 == 9 and
+        -(2 ^ 3) == -8 and
+        ("abc"):upper() == "ABC" and
+        ("abc").len == string.len
+end
+
 assert(
     test1() and
     test2() and
@@ -159,5 +183,6 @@ assert(
     test14() and
     test15() and
     test16() and
-    test17()
+    test17() and
+    test18()
 )
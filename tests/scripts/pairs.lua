@@ -68,3 +68,60 @@ do
   local a, b = inext(t, math.maxinteger)
   assert(a == -9223372036854775808 and b == 4)
 end
+
+do
+  assert(select(1, pairs({})) == next)
+
+  local t = setmetatable({}, {
+    __pairs = function(self)
+      local i = 0
+      return function(_, k)
+        i = i + 1
+        if i <= 3 then
+          return i, i * 10
+        end
+      end, self, nil
+    end
+  })
+
+  local sum = 0
+  for k, v in pairs(t) do
+    sum = sum + k + v
+  end
+  assert(sum == 66)
+
+  local ok, err = pcall(pairs, nil)
+  assert(not ok and tostring(err) == "could not get pairs of a nil value")
+end
+
+do
+  local iter, state, control = ipairs({})
+  assert(select("#", ipairs({})) == 3 and state ~= nil and control == 0)
+  assert(select("#", pairs({})) == 3)
+  assert(not pcall(ipairs))
+  assert(not pcall(pairs))
+
+  -- Only the first three results of `__pairs` are used.
+  local t = setmetatable({}, { __pairs = function(self)
+    return 1, 2, 3, 4
+  end })
+  local a, b, c, d = pairs(t)
+  assert(a == 1 and b == 2 and c == 3 and d == nil)
+  assert(select("#", pairs(t)) == 3)
+
+  -- `ipairs` stops at the first nil, even if there are later entries.
+  local count = 0
+  for i, v in ipairs({ 1, 2, nil, 4 }) do
+    count = count + 1
+  end
+  assert(count == 2)
+
+  -- Existing fields may be cleared during traversal.
+  local fields = { a = 1, b = 2, c = 3, d = 4, 5, 6, 7 }
+  local seen = 0
+  for k in pairs(fields) do
+    fields[k] = nil
+    seen = seen + 1
+  end
+  assert(seen == 7 and next(fields) == nil)
+end
@@ -1,6 +1,6 @@
 do
     local function error_func(e)
-        error(e)
+        error(e, 0)
     end
     local function good_func()
         return "good"
@@ -0,0 +1,34 @@
+do
+    local log = {}
+    local t = setmetatable({}, {
+        __index = function(_, k) log[#log + 1] = "index " .. k; return "meta" end,
+        __newindex = function(_, k) log[#log + 1] = "newindex " .. k end,
+        __len = function() return 42 end,
+        __eq = function() return true end,
+    })
+
+    assert(rawget(t, "a") == nil)
+    assert(rawset(t, "a", 1) == t)
+    assert(rawget(t, "a") == 1)
+    assert(#log == 0)
+
+    assert(t.b == "meta")
+    t.c = 1
+    assert(#log == 2)
+
+    assert(#t == 42)
+    assert(rawlen(t) == 0)
+    rawset(t, 1, "x")
+    assert(rawlen(t) == 1)
+    assert(rawlen("abc") == 3)
+    assert(not pcall(rawlen, 1))
+
+    local u = setmetatable({}, getmetatable(t))
+    assert(t == u)
+    assert(not rawequal(t, u))
+    assert(rawequal(t, t))
+    assert(rawequal(1, 1.0))
+    assert(rawequal("a", "a"))
+    assert(not rawequal(1, "1"))
+    assert(rawequal(nil, nil))
+end
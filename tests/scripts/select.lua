@@ -32,3 +32,16 @@ do
     end)
     assert(last_element and before_last_element and not too_far)
 end
+
+do
+    assert(select("#") == 0)
+    assert(select("#", nil, nil) == 2)
+    assert(select(2, "a", "b", "c") == "b")
+    assert(select(-4, 1, 2, 3, 4) == 1)
+    assert(select(5, 1, 2, 3, 4) == nil)
+
+    local ok, err = pcall(select, -5, 1, 2, 3, 4)
+    assert(not ok and err == "bad argument #1 to 'select' (index out of range)")
+    ok, err = pcall(select, {})
+    assert(not ok and err == "bad argument #1 to 'select' (number expected, got table)")
+end
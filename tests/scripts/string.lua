@@ -96,3 +96,272 @@ do
     assert(string.upper(80) == "80")
     assert(string.upper(3.14) == "3.14")
 end
+
+do
+    local s = "Hello"
+    assert(s:upper() == "HELLO")
+    assert(("x"):upper() == "X")
+    assert(s:sub(2, 3) == "el")
+    assert(s:len() == 5)
+    assert(getmetatable("").__index == string)
+    assert(("abc").missing == nil)
+end
+
+do
+    assert(string.find("hello world", "wor") == 7)
+    local s, e = string.find("hello world", "o", 6)
+    assert(s == 8 and e == 8)
+    s, e = string.find("a.b", ".", 1, true)
+    assert(s == 2 and e == 2)
+    s, e = string.find("abc", "")
+    assert(s == 1 and e == 0)
+    assert(string.find("abc", "", 10) == nil)
+    assert(string.find("abc", "x") == nil)
+    local a, b, c, d = string.find("key = value", "(%w+)%s*=%s*(%w+)")
+    assert(a == 1 and b == 11 and c == "key" and d == "value")
+    assert(string.find("abc", "b", -1) == nil)
+    assert(string.find("abc", "^b") == nil)
+    assert(string.find("abc", "^a") == 1)
+
+    assert(string.match("hello 123 world", "%d+") == "123")
+    assert(string.match("2024-01-15", "(%d+)-(%d+)-(%d+)") == "2024")
+    local y, m, d = string.match("2024-01-15", "(%d+)-(%d+)-(%d+)")
+    assert(y == "2024" and m == "01" and d == "15")
+    assert(string.match("  trim  ", "^%s*(.-)%s*$") == "trim")
+    assert(string.match("[[x]]", "%[(.*)%]") == "[x]")
+    assert(string.match("f(a(b)c)d", "%b()") == "(a(b)c)")
+    assert(string.match("THE (quick) fox", "%f[%a]%a+") == "THE")
+    assert(string.match("hello", "()ll()") == 3)
+    assert(select(2, string.match("hello", "()ll()")) == 5)
+    assert(string.match("abcabc", "(abc)%1") == "abc")
+    assert(string.match("x = 'y'", "(['\"])(.-)%1") == "'")
+    assert(string.match("aaa", "a-b") == nil)
+    assert(string.match("aaab", "a-b") == "aaab")
+    assert(string.match("ab", "a?b") == "ab")
+    assert(string.match("b", "a?b") == "b")
+    assert(string.match("end$", "d%$$") == "d$")
+    assert(string.match("a]b", "[]]") == "]")
+    assert(string.match("a-z", "[a%-]+") == "a-")
+    assert(string.match("Hello", "[^%l]") == "H")
+    assert(string.match("\v", "%s") == "\v")
+
+    assert(is_err(function() return string.match("a", "%") end))
+    assert(is_err(function() return string.match("a", "[a") end))
+    assert(is_err(function() return string.match("a", "(a") end))
+    assert(is_err(function() return string.match("a", "a)") end))
+    assert(is_err(function() return string.match("a", "%1") end))
+    assert(is_err(function() return string.match("a", "%b") end))
+    assert(is_err(function() return string.match("a", "%fa") end))
+end
+
+do
+    local words = {}
+    for w in string.gmatch("one two  three", "%a+") do
+        words[#words + 1] = w
+    end
+    assert(#words == 3 and words[1] == "one" and words[3] == "three")
+
+    local t = {}
+    for k, v in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do
+        t[k] = v
+    end
+    assert(t.a == "1" and t.b == "2")
+
+    local count = 0
+    for _ in string.gmatch("abc", "") do
+        count = count + 1
+    end
+    assert(count == 4)
+
+    for w in string.gmatch("^a", "^a") do
+        assert(w == "^a")
+    end
+end
+
+do
+    assert(string.gsub("hello world", "o", "0") == "hell0 w0rld")
+    assert(select(2, string.gsub("hello world", "o", "0")) == 2)
+    assert(string.gsub("hello world", "o", "0", 1) == "hell0 world")
+    assert(string.gsub("hello", "", "-") == "-h-e-l-l-o-")
+    assert(string.gsub("abc", "%w", "%0%0") == "aabbcc")
+    assert(string.gsub("hello world", "(%w+) (%w+)", "%2 %1") == "world hello")
+    assert(string.gsub("abc", "b", "%%") == "a%c")
+    assert(string.gsub("abc", "^a", "x") == "xbc")
+    assert(string.gsub("aaa", "^a", "x") == "xaa")
+    assert(string.gsub("abc", "b", "%1") == "abc")
+    assert(is_err(function() return string.gsub("abc", "b", "%2") end))
+    assert(is_err(function() return string.gsub("abc", "b", "%x") end))
+
+    assert(string.gsub("$name is $age", "%$(%w+)", { name = "bob", age = 42 }) == "bob is 42")
+    assert(string.gsub("$name $missing", "%$(%w+)", { name = "bob" }) == "bob $missing")
+    local mt = setmetatable({}, { __index = function(_, k) return k:upper() end })
+    assert(string.gsub("a b", "%a", mt) == "A B")
+
+    assert(string.gsub("1 2 3", "%d", function(d) return tonumber(d) * 2 end) == "2 4 6")
+    assert(string.gsub("a b", "%a", function() return nil end) == "a b")
+    assert(string.gsub("a b", "%a", function() return false end) == "a b")
+    assert(is_err(function() return string.gsub("a", "a", function() return {} end) end))
+    assert(is_err(function() return string.gsub("a", "a", true) end))
+
+    local co = coroutine.wrap(function()
+        return string.gsub("abc", "%a", function(c)
+            return coroutine.yield(c)
+        end)
+    end)
+    assert(co() == "a")
+    assert(co("x") == "b")
+    assert(co("y") == "c")
+    assert(co("z") == "xyz")
+end
+
+do
+    assert(string.format("%d %i", 42, -7) == "42 -7")
+    assert(string.format("%5d|%-5d|%05d", 42, 42, 42) == "   42|42   |00042")
+    assert(string.format("%+d % d %.3d", 5, 5, 5) == "+5  5 005")
+    assert(string.format("%d", 3.0) == "3")
+    assert(string.format("%d", "10") == "10")
+    assert(is_err(function() return string.format("%d", 3.5) end))
+    assert(is_err(function() return string.format("%d", "x") end))
+    assert(is_err(function() return string.format("%d") end))
+
+    assert(string.format("%x %X %#x %o %#o", 255, 255, 255, 8, 8) == "ff FF 0xff 10 010")
+    assert(string.format("%x", -1) == "ffffffffffffffff")
+    assert(string.format("%u", -1) == "18446744073709551615")
+    assert(string.format("%c%c%c", 76, 117, 97) == "Lua")
+
+    assert(string.format("%g %g %g", 1, 0.1, 1e20) == "1 0.1 1e+20")
+    assert(string.format("%.3f %e", 3.14159, 12345.678) == "3.142 1.234568e+04")
+    assert(string.format("%10.2f|%-10.2f|", 1.5, -1.5) == "      1.50|-1.50     |")
+    assert(string.format("%G %E", 1e-10, 1.5) == "1E-10 1.500000E+00")
+    assert(string.format("%f %f", 1 / 0, -1 / 0) == "inf -inf")
+    assert(string.format("%05.1f", 2.3) == "002.3")
+    assert(string.format("%a %a %.1a", 1.0, 0.5, 1.0) == "0x1p+0 0x1p-1 0x1.0p+0")
+
+    assert(string.format("%s %s %s %s", "a", 1, 1.5, true) == "a 1 1.5 true")
+    assert(string.format("%s", nil) == "nil")
+    assert(string.format("%.2s|%5s|%-5s|", "abc", "ab", "ab") == "ab|   ab|ab   |")
+    assert(string.format("100%%") == "100%")
+    assert(string.format("%s", setmetatable({}, { __tostring = function() return "obj" end })) == "obj")
+    assert(is_err(function()
+        return string.format("%s", setmetatable({}, { __tostring = function() return {} end }))
+    end))
+
+    local co = coroutine.wrap(function()
+        local t = setmetatable({}, { __tostring = function() return coroutine.yield() end })
+        return string.format("<%s|%s>", t, t)
+    end)
+    co()
+    co("a")
+    assert(co("b") == "<a|b>")
+
+    assert(string.format("%q", 'a "quoted"\\ string\n') == '"a \\"quoted\\"\\\\ string\\\n"')
+    assert(string.format("%q", "\0\1\0012\r") == '"\\0\\1\\0012\\r"')
+    assert(string.format("%q", 42) == "42")
+    assert(string.format("%q", math.mininteger) == "0x8000000000000000")
+    assert(string.format("%q", 1.0) == "0x1p+0")
+    assert(string.format("%q", -0.5) == "-0x1p-1")
+    assert(tonumber(string.format("%q", 0.1)) == 0.1)
+    assert(string.format("%q", 1 / 0) == "1e9999")
+    assert(string.format("%q", 0 / 0) == "(0/0)")
+    assert(string.format("%q %q", nil, false) == "nil false")
+    assert(is_err(function() return string.format("%q", {}) end))
+    assert(is_err(function() return string.format("%5q", "a") end))
+
+    assert(is_err(function() return string.format("%y", 1) end))
+    assert(is_err(function() return string.format("%", 1) end))
+    assert(is_err(function() return string.format("%100d", 1) end))
+    assert(is_err(function() return string.format("%#d", 1) end))
+    assert(is_err(function() return string.format("%.3c", 1) end))
+    assert(("%d-%d"):format(1, 2) == "1-2")
+end
+
+do
+    assert(string.pack("<i4", 1) == "\1\0\0\0")
+    assert(string.pack(">i4", 1) == "\0\0\0\1")
+    assert(string.pack("<h", -2) == "\254\255")
+    assert(string.pack("B", 255) == "\255")
+    assert(is_err(function() return string.pack("B", 256) end))
+    assert(is_err(function() return string.pack("b", 128) end))
+    assert(is_err(function() return string.pack("i17", 1) end))
+    assert(is_err(function() return string.pack("i", 1.5) end))
+    assert(string.pack("<i16", -1) == "\255\255\255\255\255\255\255\255\255\255\255\255\255\255\255\255")
+
+    assert(string.unpack("<i4", "\1\0\0\0") == 1)
+    assert(select(2, string.unpack("<i4", "\1\0\0\0")) == 5)
+    assert(string.unpack("<h", "\254\255") == -2)
+    assert(string.unpack("<H", "\254\255") == 65534)
+    assert(string.unpack("<i16", string.pack("<i16", -3)) == -3)
+    assert(is_err(function() return string.unpack("<i9", "\0\0\0\0\0\0\0\0\1") end))
+
+    local packed = string.pack("<dfn", 1.5, 0.25, -2.0)
+    assert(#packed == 20)
+    local d, f, n = string.unpack("<dfn", packed)
+    assert(d == 1.5 and f == 0.25 and n == -2.0)
+
+    assert(string.pack("z", "abc") == "abc\0")
+    assert(is_err(function() return string.pack("z", "a\0b") end))
+    assert(string.unpack("z", "abc\0def") == "abc")
+    assert(is_err(function() return string.unpack("z", "abc") end))
+
+    assert(string.pack("s1", "hi") == "\2hi")
+    assert(string.unpack("s1", "\2hi") == "hi")
+    local long = "x"
+    for _ = 1, 8 do long = long .. long end
+    assert(#long == 256)
+    assert(is_err(function() return string.pack("s1", long) end))
+    assert(is_err(function() return string.unpack("s1", "\5hi") end))
+
+    assert(string.pack("c5", "ab") == "ab\0\0\0")
+    assert(string.unpack("c2", "abc") == "ab")
+    assert(is_err(function() return string.pack("c1", "ab") end))
+    assert(is_err(function() return string.pack("c", "a") end))
+
+    assert(string.pack("!<b i4", 1, 2) == "\1\0\0\0\2\0\0\0")
+    assert(string.pack("<b i4", 1, 2) == "\1\2\0\0\0")
+    assert(string.pack("!<b Xi4 b", 1, 2) == "\1\0\0\0\2")
+    assert(string.pack("<bxb", 1, 2) == "\1\0\2")
+    assert(is_err(function() return string.pack("!<b X", 1) end))
+    assert(is_err(function() return string.pack("!3<b i4", 1, 2) end))
+
+    assert(string.packsize("i4i8") == 12)
+    assert(string.packsize("!i1i8") == 16)
+    assert(string.packsize("c10") == 10)
+    assert(is_err(function() return string.packsize("s") end))
+    assert(is_err(function() return string.packsize("z") end))
+
+    local a, b, pos = string.unpack("<bb", "\1\2\3\4", 3)
+    assert(a == 3 and b == 4 and pos == 5)
+    assert(string.unpack("<b", "\1\2\3\4", -1) == 4)
+    assert(is_err(function() return string.unpack("<b", "\1", 3) end))
+    assert(is_err(function() return string.unpack("<i4", "\1\2") end))
+    assert(is_err(function() return string.pack("y") end))
+end
+
+do
+    assert(string.byte("abc") == 97)
+    assert(string.byte("abc", 2) == 98)
+    assert(string.byte("abc", -1) == 99)
+    assert(select("#", string.byte("abc", 1, -1)) == 3)
+    local a, b, c = string.byte("abc", 1, 3)
+    assert(a == 97 and b == 98 and c == 99)
+    assert(select("#", string.byte("abc", 4)) == 0)
+    assert(select("#", string.byte("abc", 3, 2)) == 0)
+    assert(select("#", string.byte("", 1)) == 0)
+    assert(("abc"):byte(-2, -1) == 98)
+
+    assert(string.char() == "")
+    assert(string.char(97, 98, 99) == "abc")
+    assert(string.char(0, 255) == "\0\255")
+    assert(is_err(function() return string.char(256) end))
+    assert(is_err(function() return string.char(-1) end))
+    assert(string.char(string.byte("hello", 1, -1)) == "hello")
+
+    assert(string.rep("ab", 3) == "ababab")
+    assert(string.rep("ab", 3, ",") == "ab,ab,ab")
+    assert(string.rep("ab", 1, ",") == "ab")
+    assert(string.rep("ab", 0) == "")
+    assert(string.rep("ab", -1, ",") == "")
+    assert(string.rep("", 5, "x") == "xxxx")
+    assert(("x"):rep(4) == "xxxx")
+    assert(is_err(function() return string.rep("x", math.maxinteger) end))
+end
@@ -137,3 +137,169 @@ do
     end
     assert(t2[1] == nil and t2[4] == nil and t2[7] == nil and t.a == nil and t.b == nil)
 end
+
+do
+    local t = {}
+    t[1.0] = "a"
+    t[2^53] = "b"
+    assert(t[1] == "a" and t[2^53 | 0] == "b")
+    assert(math.type(next(t)) == "integer")
+
+    t[1.5] = "c"
+    assert(t[1.5] == "c" and t[1] == "a")
+
+    assert(not pcall(function() t[0/0] = 1 end))
+    assert(not pcall(function() t[nil] = 1 end))
+    assert(not pcall(rawset, t, 0/0, 1))
+    assert(t[0/0] == nil and t[nil] == nil)
+end
+
+do
+    local t = {}
+    local function check(msg, f, ...)
+        local ok, err = pcall(f, ...)
+        -- Errors raised by the VM are prefixed with their position, errors from `rawset` are not.
+        assert(not ok and (tostring(err):gsub("^[^:]+:%d+: ", "")) == msg)
+    end
+
+    check("table index is nil", function() t[nil] = 1 end)
+    check("table index is nil", function() t[nil] = nil end)
+    check("table index is NaN", function() t[0/0] = 1 end)
+    check("table index is nil", function() local k; local u = {[k] = 1} end)
+    check("table index is NaN", function() local u = {[0/0] = 1} end)
+    check("table index is nil", rawset, t, nil, 1)
+    check("table index is NaN", rawset, t, 0/0, 1)
+
+    -- __newindex is still called for invalid keys
+    local seen = false
+    local m = setmetatable({}, {__newindex = function(_, k) seen = k == nil end})
+    m[nil] = 1
+    assert(seen)
+end
+
+do
+    local function is_sorted(t, comp)
+        comp = comp or function(a, b) return a < b end
+        for i = 2, #t do
+            if comp(t[i], t[i - 1]) then
+                return false
+            end
+        end
+        return true
+    end
+
+    local t = { 5, 3, 8, 1, 9, 2, 7, 4, 6, 0 }
+    table.sort(t)
+    for i = 1, 10 do
+        assert(t[i] == i - 1)
+    end
+
+    local s = { "pear", "apple", "fig", "banana" }
+    table.sort(s)
+    assert(s[1] == "apple" and s[2] == "banana" and s[3] == "fig" and s[4] == "pear")
+
+    local d = { 1, 5, 2, 4, 3 }
+    table.sort(d, function(a, b) return a > b end)
+    assert(d[1] == 5 and d[2] == 4 and d[3] == 3 and d[4] == 2 and d[5] == 1)
+
+    local big = {}
+    for i = 1, 1000 do
+        big[i] = (i * 7919) % 1009
+    end
+    table.sort(big)
+    assert(#big == 1000 and is_sorted(big))
+
+    local empty = {}
+    table.sort(empty)
+    assert(#empty == 0)
+    local one = { 1 }
+    table.sort(one)
+    assert(one[1] == 1)
+
+    -- The sort is stable.
+    local records = {}
+    for i = 1, 20 do
+        records[i] = { key = i % 3, order = i }
+    end
+    table.sort(records, function(a, b) return a.key < b.key end)
+    for i = 2, 20 do
+        local a, b = records[i - 1], records[i]
+        assert(a.key < b.key or (a.key == b.key and a.order < b.order))
+    end
+
+    -- Elements with a `__lt` metamethod.
+    local mt = { __lt = function(a, b) return a.v < b.v end }
+    local objs = {}
+    for i, v in ipairs({ 3, 1, 2 }) do
+        objs[i] = setmetatable({ v = v }, mt)
+    end
+    table.sort(objs)
+    assert(objs[1].v == 1 and objs[2].v == 2 and objs[3].v == 3)
+
+    -- The order function may yield.
+    local co = coroutine.wrap(function()
+        local y = { 3, 1, 2 }
+        table.sort(y, function(a, b)
+            coroutine.yield()
+            return a < b
+        end)
+        return y
+    end)
+    local result = co()
+    local yields = 0
+    while result == nil do
+        yields = yields + 1
+        result = co()
+    end
+    assert(yields > 0)
+    assert(result[1] == 1 and result[2] == 2 and result[3] == 3)
+
+    assert(pcall(table.sort, { 1, 2, 3 }, function(a, b) return true end) == false)
+    assert(pcall(table.sort, { 3, 1, 2 }, function(a, b) return a <= b end) == true)
+    assert(pcall(table.sort, { 1, 1, 2 }, function(a, b) return a <= b end) == false)
+    assert(pcall(table.sort, { 1, "x" }) == false)
+    assert(pcall(table.sort, { 1, 2 }, 3) == false)
+    assert(pcall(table.sort, { 3, 2, 1 }, function(a, b) error("fail") end) == false)
+end
+
+do
+    local t = { 1, 2, 3 }
+    table.insert(t, 4)
+    table.insert(t, 1, 0)
+    table.insert(t, 3, 1.5)
+    assert(#t == 6)
+    assert(t[1] == 0 and t[2] == 1 and t[3] == 1.5 and t[4] == 2 and t[6] == 4)
+    assert(pcall(table.insert, t, 8, 0) == false)
+    assert(pcall(table.insert, t, 0, 0) == false)
+    assert(pcall(table.insert, t, 1, 2, 3) == false)
+    assert(pcall(table.insert, t) == false)
+
+    assert(table.remove(t) == 4)
+    assert(table.remove(t, 1) == 0)
+    assert(table.remove(t, 2) == 1.5)
+    assert(#t == 3 and t[1] == 1 and t[2] == 2 and t[3] == 3)
+    assert(table.remove(t, #t + 1) == nil)
+    assert(pcall(table.remove, t, 5) == false)
+    assert(table.remove({}) == nil)
+
+    local m = table.move({ 1, 2, 3, 4, 5 }, 1, 3, 3)
+    assert(m[1] == 1 and m[2] == 2 and m[3] == 1 and m[4] == 2 and m[5] == 3)
+    m = table.move({ 1, 2, 3, 4, 5 }, 3, 5, 1)
+    assert(m[1] == 3 and m[2] == 4 and m[3] == 5 and m[4] == 4)
+    local dest = {}
+    assert(table.move({ "a", "b" }, 1, 2, 2, dest) == dest)
+    assert(dest[1] == nil and dest[2] == "a" and dest[3] == "b")
+    assert(table.move({}, 1, 0, 1) ~= nil)
+    assert(pcall(table.move, {}, 1, math.maxinteger, 2) == false)
+    assert(pcall(table.move, {}, -1, math.maxinteger, 1) == false)
+
+    assert(table.concat({}) == "")
+    assert(table.concat({ 1, 2, 3 }) == "123")
+    assert(table.concat({ "a", "b", "c" }, ", ") == "a, b, c")
+    assert(table.concat({ "a", "b", "c" }, "-", 2) == "b-c")
+    assert(table.concat({ "a", "b", "c" }, "-", 2, 2) == "b")
+    assert(table.concat({ "a", "b", "c" }, "-", 3, 2) == "")
+    assert(table.concat({ 1.5, "x" }) == "1.5x")
+    assert(pcall(table.concat, { 1, {}, 3 }) == false)
+    assert(pcall(table.concat, { 1, 2 }, "", 1, 3) == false)
+end
@@ -0,0 +1,108 @@
+local function is_err(f)
+    return pcall(f) == false
+end
+
+do
+    assert(utf8.char() == "")
+    assert(utf8.char(72, 105) == "Hi")
+    assert(utf8.char(0xE9) == "\xC3\xA9")
+    assert(utf8.char(0x20AC) == "\xE2\x82\xAC")
+    assert(utf8.char(0x1F600) == "\xF0\x9F\x98\x80")
+    assert(utf8.char(0x7FFFFFFF) == "\xFD\xBF\xBF\xBF\xBF\xBF")
+    assert(is_err(function() return utf8.char(-1) end))
+    assert(is_err(function() return utf8.char(0x80000000) end))
+    assert(is_err(function() return utf8.char(1.5) end))
+    assert(is_err(function() return utf8.char({}) end))
+end
+
+do
+    assert(utf8.charpattern == "[\0-\x7F\xC2-\xFD][\x80-\xBF]*")
+    local chars = {}
+    for c in string.gmatch("h\xC3\xA9\xE2\x82\xAC", utf8.charpattern) do
+        chars[#chars + 1] = c
+    end
+    assert(#chars == 3 and chars[2] == "\xC3\xA9" and chars[3] == "\xE2\x82\xAC")
+end
+
+do
+    local s = "h\xC3\xA9\xE2\x82\xAC!"
+    assert(utf8.codepoint(s) == 104)
+    assert(utf8.codepoint(s, 2) == 0xE9)
+    local a, b, c, d = utf8.codepoint(s, 1, -1)
+    assert(a == 104 and b == 0xE9 and c == 0x20AC and d == 33)
+    assert(select("#", utf8.codepoint(s, 3, 2)) == 0)
+    assert(is_err(function() return utf8.codepoint(s, 3) end))
+    assert(is_err(function() return utf8.codepoint(s, 0) end))
+    assert(is_err(function() return utf8.codepoint(s, 1, 100) end))
+    assert(is_err(function() return utf8.codepoint("\xED\xA0\x80") end))
+    assert(utf8.codepoint("\xED\xA0\x80", 1, 1, true) == 0xD800)
+    assert(is_err(function() return utf8.codepoint("\xC0\x80") end))
+    assert(is_err(function() return utf8.codepoint("\xC0\x80", 1, 1, true) end))
+end
+
+do
+    local s = "h\xC3\xA9\xE2\x82\xAC!"
+    assert(utf8.len(s) == 4)
+    assert(utf8.len("") == 0)
+    assert(utf8.len(s, 2) == 3)
+    assert(utf8.len(s, -1) == 1)
+    assert(utf8.len(s, 1, 2) == 2)
+    assert(utf8.len(s, #s + 1) == 0)
+    local n, pos = utf8.len("ab\xFFcd")
+    assert(n == nil and pos == 3)
+    n, pos = utf8.len(s, 3)
+    assert(n == nil and pos == 3)
+    assert(is_err(function() return utf8.len(s, #s + 2) end))
+    assert(is_err(function() return utf8.len(s, 1, #s + 1) end))
+    assert(utf8.len("\xF4\x90\x80\x80") == nil)
+    assert(utf8.len("\xF4\x90\x80\x80", 1, -1, true) == 1)
+end
+
+do
+    local s = "h\xC3\xA9\xE2\x82\xAC!"
+    assert(utf8.offset(s, 1) == 1)
+    assert(utf8.offset(s, 2) == 2)
+    assert(utf8.offset(s, 3) == 4)
+    assert(utf8.offset(s, 4) == 7)
+    assert(utf8.offset(s, 5) == 8)
+    assert(utf8.offset(s, 6) == nil)
+    assert(utf8.offset(s, -1) == 7)
+    assert(utf8.offset(s, -2) == 4)
+    assert(utf8.offset(s, -4) == 1)
+    assert(utf8.offset(s, -5) == nil)
+    assert(utf8.offset(s, 0, 3) == 2)
+    assert(utf8.offset(s, 0, 6) == 4)
+    assert(utf8.offset(s, 2, 2) == 4)
+    assert(is_err(function() return utf8.offset(s, 1, 3) end))
+    assert(is_err(function() return utf8.offset(s, 1, #s + 2) end))
+end
+
+do
+    local positions, codes = {}, {}
+    for p, c in utf8.codes("h\xC3\xA9\xE2\x82\xAC!") do
+        positions[#positions + 1] = p
+        codes[#codes + 1] = c
+    end
+    assert(#codes == 4)
+    assert(positions[1] == 1 and positions[2] == 2 and positions[3] == 4 and positions[4] == 7)
+    assert(codes[1] == 104 and codes[2] == 0xE9 and codes[3] == 0x20AC and codes[4] == 33)
+
+    local count = 0
+    for _ in utf8.codes("") do
+        count = count + 1
+    end
+    assert(count == 0)
+
+    assert(is_err(function()
+        for _ in utf8.codes("a\xFFb") do end
+    end))
+    assert(is_err(function()
+        for _ in utf8.codes("\x80") do end
+    end))
+    assert(is_err(function()
+        for _ in utf8.codes("\xED\xA0\x80") do end
+    end))
+    for _, c in utf8.codes("\xED\xA0\x80", true) do
+        assert(c == 0xD800)
+    end
+end
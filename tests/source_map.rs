@@ -0,0 +1,56 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    compiler::LineNumber, Callback, CallbackReturn, Closure, Executor, Lua, SourceMap, StaticError,
+};
+
+#[test]
+fn source_map_locations() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let locations = Rc::new(RefCell::new(Vec::new()));
+
+    let executor = lua.try_enter(|ctx| {
+        let recorded = locations.clone();
+        let location = Callback::from_fn(&ctx, move |_, exec, _| {
+            let frame = exec.upper_lua_frame().unwrap();
+            recorded.borrow_mut().push((
+                frame.chunk_name.to_str_lossy().into_owned(),
+                frame.current_line.0,
+                frame.source_file.to_str_lossy().into_owned(),
+                frame.source_line.0,
+            ));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("location", location)?;
+
+        // Line 0 of the bundle is not covered by the map, lines 1 and 2 come from "a.lua", and
+        // lines 3 onwards come from "b.lua".
+        let mut source_map = SourceMap::new();
+        source_map.add_segment(LineNumber(3), ctx.intern(b"b.lua"), LineNumber(10));
+        source_map.add_segment(LineNumber(1), ctx.intern(b"a.lua"), LineNumber(0));
+
+        let closure = Closure::load_with_source_map(
+            ctx,
+            Some("bundle.lua"),
+            &b"location()\nlocal function f()\n  location()\nend\nf()\nlocation()\n"[..],
+            ctx.globals(),
+            source_map,
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    let bundle = "bundle.lua".to_owned();
+    assert_eq!(
+        *locations.borrow(),
+        vec![
+            (bundle.clone(), 0, bundle.clone(), 0),
+            (bundle.clone(), 2, "a.lua".to_owned(), 1),
+            (bundle.clone(), 5, "b.lua".to_owned(), 12),
+        ]
+    );
+
+    Ok(())
+}
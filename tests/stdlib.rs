@@ -0,0 +1,63 @@
+use piccolo::{stdlib::StdLib, Closure, Executor, Lua, StaticError};
+
+fn run(lua: &mut Lua, code: &'static str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, code.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn selected_libraries() -> Result<(), StaticError> {
+    let mut lua = Lua::with_stdlib(StdLib::BASE | StdLib::STRING | StdLib::TABLE | StdLib::MATH);
+    run(
+        &mut lua,
+        r#"
+            assert(type(string) == "table" and type(table) == "table" and type(math) == "table")
+            assert(coroutine == nil and utf8 == nil)
+            assert(io == nil and os == nil and package == nil and require == nil)
+        "#,
+    )?;
+
+    let mut lua = Lua::with_stdlib(StdLib::NONE);
+    lua.enter(|ctx| {
+        assert!(ctx.get_global("print").is_nil());
+        assert!(!ctx.binary_chunks());
+    });
+
+    let mut lua = Lua::with_stdlib(StdLib::ALL);
+    run(
+        &mut lua,
+        r#"
+            assert(type(io) == "table" and type(os) == "table")
+            assert(package.loaded.string == string and package.loaded.os == os)
+        "#,
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn binary_chunks() -> Result<(), StaticError> {
+    let mut lua = Lua::with_stdlib(StdLib::CORE);
+    run(
+        &mut lua,
+        r#"
+            local f = load(string.dump(function() return 7 end))
+            assert(f() == 7)
+        "#,
+    )?;
+
+    let mut lua = Lua::with_stdlib(StdLib::CORE - StdLib::BINARY_CHUNKS);
+    run(
+        &mut lua,
+        r#"
+            local f, err = load(string.dump(function() return 7 end))
+            assert(f == nil and err:find("binary chunks are disabled"))
+            assert(load("return 7")() == 7)
+        "#,
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use piccolo::{Callback, CallbackReturn, Closure, Executor, Lua, StaticError, Table};
+
+#[test]
+fn strict_globals() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let env = ctx.fork();
+        ctx.enable_strict_globals(env);
+
+        let closure = Closure::load_with_env(
+            ctx,
+            None,
+            &br#"
+                -- Globals from the real globals table are still visible
+                assert(type(print) == "function")
+
+                local ok, err = pcall(function() return undefined_name end)
+                assert(not ok and err == "variable 'undefined_name' is not declared")
+
+                -- Defining a global makes it readable
+                defined = false
+                assert(defined == false)
+                assert(rawget(_ENV, "other") == nil)
+            "#[..],
+            env,
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    // Only the environment made strict is affected.
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"assert(undefined_name == nil)"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    // An existing `__index` function is called first.
+    let executor = lua.try_enter(|ctx| {
+        let env = Table::new(&ctx);
+        let metatable = Table::new(&ctx);
+        metatable.set(
+            ctx,
+            "__index",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (_, key): (Table, piccolo::String) = stack.consume(ctx)?;
+                if key == "magic" {
+                    stack.push_back(42.into());
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        env.set_metatable(&ctx, Some(metatable));
+        env.set(ctx, "assert", ctx.get_global("assert"))?;
+        env.set(ctx, "pcall", ctx.get_global("pcall"))?;
+        ctx.enable_strict_globals(env);
+
+        let closure = Closure::load_with_env(
+            ctx,
+            None,
+            &br#"
+                assert(magic == 42)
+                local ok, err = pcall(function() return mystery end)
+                return not ok and err == "variable 'mystery' is not declared"
+            "#[..],
+            env,
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    assert!(lua.execute::<bool>(&executor)?);
+
+    Ok(())
+}
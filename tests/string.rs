@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use piccolo::Lua;
+
+#[test]
+fn compare_without_interning() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let hello = ctx.intern(b"hello");
+        assert_eq!(hello, "hello");
+        assert_eq!(hello, b"hello"[..]);
+        assert!("hello" == hello);
+        assert!(&b"hello"[..] == hello);
+        assert_ne!(hello, "world");
+
+        assert_eq!(hello.as_str().unwrap(), "hello");
+        let invalid = ctx.intern(b"\xffabc");
+        assert!(invalid.as_str().is_err());
+        assert_eq!(invalid.to_str_lossy(), "\u{fffd}abc");
+
+        assert!(ctx.intern(b"abc") < ctx.intern(b"abd"));
+        assert!(ctx.intern(b"ab") < ctx.intern(b"abc"));
+    });
+}
+
+#[test]
+fn borrow_as_bytes() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let mut map = HashMap::new();
+        map.insert(ctx.intern(b"one"), 1);
+        map.insert(ctx.intern(b"two"), 2);
+
+        assert_eq!(map.get(&b"one"[..]), Some(&1));
+        assert_eq!(map.get(&b"two"[..]), Some(&2));
+        assert_eq!(map.get(&b"three"[..]), None);
+    });
+}
@@ -0,0 +1,49 @@
+use std::convert::Infallible;
+
+use piccolo::{stdlib::StringCache, Lua};
+
+#[test]
+fn string_cache() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let cache = StringCache::<usize>::new(&ctx, 2);
+
+        let a = ctx.intern(b"a");
+        let bb = ctx.intern(b"bb");
+        let ccc = ctx.intern(b"ccc");
+
+        let mut parses = 0;
+        let mut parse = |s: &[u8]| -> Result<usize, Infallible> {
+            parses += 1;
+            Ok(s.len())
+        };
+
+        assert_eq!(
+            *cache.get_or_try_insert_with(&ctx, a, &mut parse).unwrap(),
+            1
+        );
+        assert_eq!(
+            *cache.get_or_try_insert_with(&ctx, a, &mut parse).unwrap(),
+            1
+        );
+        assert_eq!(
+            *cache.get_or_try_insert_with(&ctx, bb, &mut parse).unwrap(),
+            2
+        );
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(
+            *cache.get_or_try_insert_with(&ctx, ccc, &mut parse).unwrap(),
+            3
+        );
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(a).is_none());
+        assert_eq!(*cache.get(ccc).unwrap(), 3);
+
+        assert_eq!(parses, 3);
+
+        cache.clear(&ctx);
+        assert!(cache.is_empty());
+    });
+}
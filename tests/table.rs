@@ -1,6 +1,8 @@
-use std::cmp::Ordering;
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
 
-use piccolo::{Lua, Table, Value};
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, Fuel, Lua, SnapshotValue, String, Table, Value,
+};
 
 #[test]
 fn test_table_iter() {
@@ -45,3 +47,284 @@ fn test_table_iter() {
         assert!(table.get(ctx, "3").is_nil());
     });
 }
+
+#[test]
+fn test_float_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+
+        // Floats with an exact integer value are normalized to integer keys, in both the array
+        // and map parts of the table.
+        table.set(ctx, 1.0, "one").unwrap();
+        table.set(ctx, 1000.0, "thousand").unwrap();
+        table.set(ctx, -0.0, "zero").unwrap();
+        assert!(matches!(table.get(ctx, 1), Value::String(s) if s == "one"));
+        assert!(matches!(table.get(ctx, 1000), Value::String(s) if s == "thousand"));
+        assert!(matches!(table.get(ctx, 0), Value::String(s) if s == "zero"));
+        assert!(matches!(table.get(ctx, 0.0), Value::String(s) if s == "zero"));
+
+        for (k, _) in table.iter() {
+            assert!(matches!(k, Value::Integer(_)));
+        }
+
+        // Non-integral floats and floats outside of the integer range remain floats.
+        table.set(ctx, 1.5, "one and a half").unwrap();
+        table.set(ctx, 9223372036854775808.0, "2^63").unwrap();
+        assert!(matches!(table.get(ctx, 1.5), Value::String(s) if s == "one and a half"));
+        assert!(table.get(ctx, i64::MAX).is_nil());
+        assert!(matches!(table.get(ctx, 9223372036854775808.0), Value::String(s) if s == "2^63"));
+
+        table.set(ctx, i64::MIN, "min").unwrap();
+        assert!(matches!(table.get(ctx, -9223372036854775808.0), Value::String(s) if s == "min"));
+
+        // NaN and nil keys cannot be set, but can be read.
+        assert!(table.set(ctx, f64::NAN, 1).is_err());
+        assert!(table.set(ctx, Value::Nil, 1).is_err());
+        assert!(table.get(ctx, f64::NAN).is_nil());
+        assert!(table.get(ctx, Value::Nil).is_nil());
+    });
+}
+
+#[test]
+fn test_raw_access() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let fallback = Table::new(&ctx);
+        fallback.set(ctx, "a", 1).unwrap();
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, "__index", fallback).unwrap();
+        metatable.set(ctx, "__newindex", fallback).unwrap();
+
+        let table = Table::new(&ctx);
+        table.set_metatable(&ctx, Some(metatable));
+
+        assert!(table.get_raw(ctx, "a").is_nil());
+        assert!(table.set_raw(ctx, "b", 2).unwrap().is_nil());
+        assert!(matches!(table.get_raw(ctx, "b"), Value::Integer(2)));
+        assert!(fallback.get_raw(ctx, "b").is_nil());
+        assert!(table.set_raw(ctx, Value::Nil, 3).is_err());
+    });
+}
+
+#[test]
+fn test_typed_access() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let config = Table::new(&ctx);
+        let server = Table::new(&ctx);
+        server.set(ctx, "host", "localhost").unwrap();
+        server.set(ctx, "port", 8080).unwrap();
+        config.set(ctx, "server", server).unwrap();
+        config.set(ctx, "name", "test").unwrap();
+
+        assert_eq!(server.get_as::<_, i64>(ctx, "port").unwrap(), 8080);
+        assert_eq!(
+            server.get_as::<_, Option<i64>>(ctx, "missing").unwrap(),
+            None
+        );
+        assert!(server.get_as::<_, i64>(ctx, "host").is_err());
+
+        assert_eq!(
+            config.get_path::<String>(ctx, "server.host").unwrap(),
+            "localhost"
+        );
+        assert_eq!(config.get_path::<i64>(ctx, "server.port").unwrap(), 8080);
+        assert_eq!(
+            config
+                .get_path::<Option<i64>>(ctx, "server.missing.port")
+                .unwrap(),
+            None
+        );
+        assert!(config.get_path::<Option<i64>>(ctx, "name.port").is_err());
+        assert!(matches!(
+            config.get_path::<Value>(ctx, "server").unwrap(),
+            Value::Table(t) if t == server
+        ));
+    });
+}
+
+#[test]
+fn test_get_fields() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let options = Table::new(&ctx);
+        options.set(ctx, "x", 1.5).unwrap();
+        options.set(ctx, "y", 2).unwrap();
+        options.set(ctx, "label", "point").unwrap();
+
+        let (x, y, label) = options
+            .get_fields::<(f64, i64, String)>(ctx, ["x", "y", "label"])
+            .unwrap();
+        assert_eq!((x, y), (1.5, 2));
+        assert_eq!(label, "point");
+
+        let (x, missing) = options
+            .get_fields::<(f64, Option<i64>)>(ctx, ["x", "missing"])
+            .unwrap();
+        assert_eq!((x, missing), (1.5, None));
+
+        let err = options
+            .get_fields::<(f64, i64, i64)>(ctx, ["x", "y", "width"])
+            .unwrap_err();
+        assert_eq!(err.field, "width");
+        assert_eq!(err.found, "nil");
+
+        let err = options
+            .get_fields::<(f64, Table)>(ctx, ["x", "label"])
+            .unwrap_err();
+        assert_eq!(err.field, "label");
+        assert_eq!(
+            err.to_string(),
+            "bad field 'label' (expected Table, found string)"
+        );
+    });
+}
+
+#[test]
+fn test_observer() {
+    let mut lua = Lua::core();
+
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let executor = lua.enter(|ctx| {
+        let model = Table::new(&ctx);
+        let observed = changes.clone();
+        model.set_observer(ctx, move |_, _, key, value| {
+            observed
+                .borrow_mut()
+                .push(format!("{}={}", key.display(), value.display()));
+        });
+        ctx.set_global("model", model).unwrap();
+
+        // Changes are only delivered once the executor returns, never in the middle of a script.
+        let delivered = changes.clone();
+        let delivered = Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            stack.replace(ctx, delivered.borrow().len() as i64);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("delivered", delivered).unwrap();
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                model.x = 1
+                model.x = nil
+                model[1] = "a"
+                rawset(model, "y", true)
+                model.y = false
+                assert(delivered() == 0)
+            "#[..],
+        )
+        .unwrap();
+        ctx.stash(Executor::start(ctx, closure.into(), ()))
+    });
+
+    lua.execute::<()>(&executor).unwrap();
+    assert_eq!(
+        *changes.borrow(),
+        ["x=1", "x=nil", "1=a", "y=true", "y=false"]
+    );
+
+    changes.borrow_mut().clear();
+    lua.enter(|ctx| {
+        let model: Table = ctx.globals().get_as(ctx, "model").unwrap();
+        model.set(ctx, "z", 3).unwrap();
+        assert!(changes.borrow().is_empty());
+        ctx.flush_table_changes();
+        assert_eq!(*changes.borrow(), ["z=3"]);
+
+        model.clear_observer(&ctx);
+        assert!(!model.is_observed());
+        model.set(ctx, "z", 4).unwrap();
+        ctx.flush_table_changes();
+        assert_eq!(changes.borrow().len(), 1);
+    });
+}
+
+#[cfg(feature = "dirty-tracking")]
+#[test]
+fn test_dirty_tracking() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, "untracked", 1).unwrap();
+        assert!(table.drain_dirty(&ctx).is_empty());
+
+        table.set_dirty_tracking(&ctx, true);
+        assert!(table.is_dirty_tracking());
+        table.set(ctx, "a", 1).unwrap();
+        table.set(ctx, "a", 2).unwrap();
+        table.set(ctx, 1, true).unwrap();
+        table.set(ctx, 1.0, false).unwrap();
+        table.set(ctx, "untracked", Value::Nil).unwrap();
+
+        let mut dirty = table
+            .drain_dirty(&ctx)
+            .into_iter()
+            .map(|key| key.display().to_string())
+            .collect::<Vec<_>>();
+        dirty.sort();
+        assert_eq!(dirty, ["1", "a", "untracked"]);
+        assert!(table.drain_dirty(&ctx).is_empty());
+
+        table.set(ctx, "b", 1).unwrap();
+        table.set_dirty_tracking(&ctx, false);
+        assert!(table.drain_dirty(&ctx).is_empty());
+    });
+}
+
+#[test]
+fn test_snapshot() {
+    let mut lua = Lua::core();
+
+    let snapshot = lua.enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local shared = { 1, 2 }
+                local state = { name = "player", hp = 10.5, a = shared, b = shared, f = print }
+                state.self = state
+                state[1], state[2], state[true] = "x", "y", false
+                return state
+            "#[..],
+        )
+        .unwrap();
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.step(ctx, &mut Fuel::with(i32::MAX));
+        executor
+            .take_result::<Table>(ctx)
+            .unwrap()
+            .unwrap()
+            .snapshot(ctx)
+    });
+    drop(lua);
+
+    let snapshot = std::thread::spawn(move || snapshot).join().unwrap();
+    assert_eq!(snapshot.len(), 9);
+    assert_eq!(snapshot.length(), 2);
+    assert_eq!(snapshot.get("name"), &SnapshotValue::from("player"));
+    assert_eq!(snapshot.get("hp"), &SnapshotValue::Number(10.5));
+    assert_eq!(snapshot.get(2.0), &SnapshotValue::from("y"));
+    assert_eq!(snapshot.get(true), &SnapshotValue::Boolean(false));
+    assert_eq!(snapshot.get("missing"), &SnapshotValue::Nil);
+    assert!(matches!(
+        snapshot.get("f"),
+        SnapshotValue::Function(Some(_))
+    ));
+    assert_eq!(snapshot.get("self"), &SnapshotValue::Cycle(snapshot.id()));
+
+    let (SnapshotValue::Table(a), SnapshotValue::Table(b)) = (snapshot.get("a"), snapshot.get("b"))
+    else {
+        panic!("expected tables");
+    };
+    assert_eq!(a, b);
+    assert_eq!(a.length(), 2);
+    assert_eq!(a.get(1), &SnapshotValue::Integer(1));
+}
@@ -0,0 +1,156 @@
+use std::string::String as StdString;
+
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, Fuel, Function, Lua, StaticError, Table,
+    TaskScope, Thread, ThreadMode,
+};
+
+#[test]
+fn join_interleaves_tasks() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (log, status, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local log = {}
+                local function task(name)
+                    return function()
+                        for i = 1, 2 do
+                            log[#log + 1] = name .. i
+                            coroutine.yield()
+                        end
+                    end
+                end
+                return log, task("a"), task("b")
+            "#[..],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let mut fuel = Fuel::with(i32::MAX);
+        assert!(executor.step(ctx, &mut fuel));
+        let (log, a, b) = executor.take_result::<(Table, Function, Function)>(ctx)??;
+
+        let scope = TaskScope::new(&ctx);
+        scope.spawn(ctx, a)?;
+        scope.spawn(ctx, b)?;
+        assert_eq!(scope.len(), 2);
+
+        // Reports whether the scope is empty and closed, and whether spawning into it still works.
+        let status = Callback::from_fn_with(&ctx, scope, |&scope, ctx, _, mut stack| {
+            let spawned = scope.spawn(ctx, Function::Callback(scope.join_callback(&ctx)));
+            stack.replace(ctx, (scope.is_empty(), scope.is_closed(), spawned.is_ok()));
+            Ok(CallbackReturn::Return)
+        });
+
+        let join = Executor::start(ctx, scope.join_callback(&ctx).into(), ());
+        Ok((ctx.stash(log), ctx.stash(status), ctx.stash(join)))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.try_enter(|ctx| {
+        let log = ctx.fetch(&log);
+        let log = (1..=4)
+            .map(|i| log.get_as::<_, StdString>(ctx, i))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(log, ["a1", "b1", "a2", "b2"]);
+
+        let status = Function::Callback(ctx.fetch(&status));
+        let executor = Executor::start(ctx, status, ());
+        let mut fuel = Fuel::with(i32::MAX);
+        assert!(executor.step(ctx, &mut fuel));
+        let status = executor.take_result::<(bool, bool, bool)>(ctx)??;
+        assert_eq!(status, (true, true, false));
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn failing_task_cancels_siblings() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (sibling, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function forever()
+                    while true do coroutine.yield() end
+                end
+                local function fail()
+                    coroutine.yield()
+                    error("task failed")
+                end
+                return forever, fail
+            "#[..],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let mut fuel = Fuel::with(i32::MAX);
+        assert!(executor.step(ctx, &mut fuel));
+        let (forever, fail) = executor.take_result::<(Function, Function)>(ctx)??;
+
+        let scope = TaskScope::new(&ctx);
+        let sibling = scope.spawn(ctx, forever)?;
+        scope.spawn(ctx, fail)?;
+
+        let join = Executor::start(ctx, scope.join_callback(&ctx).into(), ());
+        Ok((ctx.stash(sibling), ctx.stash(join)))
+    })?;
+
+    let err = lua.execute::<()>(&executor).unwrap_err();
+    assert!(err.to_string().contains("task failed"));
+
+    lua.enter(|ctx| {
+        let sibling: Thread = ctx.fetch(&sibling);
+        assert_eq!(sibling.mode(), ThreadMode::Stopped);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn stopping_executor_cancels_tasks() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return function()
+                    while true do coroutine.yield() end
+                end
+            "#[..],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let mut fuel = Fuel::with(i32::MAX);
+        assert!(executor.step(ctx, &mut fuel));
+        let forever = executor.take_result::<Function>(ctx)??;
+
+        let scope = TaskScope::new(&ctx);
+        let tasks = [scope.spawn(ctx, forever)?, scope.spawn(ctx, forever)?];
+        ctx.set_global("join", scope.join_callback(&ctx))?;
+
+        // Joins from inside a coroutine, so the join is not running on the executor's main
+        // thread.
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                coroutine.wrap(function() join() end)()
+            "#[..],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let mut fuel = Fuel::with(1000);
+        assert!(!executor.step(ctx, &mut fuel));
+        assert!(tasks.iter().all(|t| t.mode() != ThreadMode::Stopped));
+
+        executor.stop(&ctx);
+        assert!(tasks.iter().all(|t| t.mode() == ThreadMode::Stopped));
+        assert!(scope.is_closed());
+        Ok(())
+    })
+}
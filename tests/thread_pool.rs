@@ -0,0 +1,37 @@
+use piccolo::{Closure, Executor, Lua, StaticError, ThreadMode};
+
+#[test]
+fn recycle_threads() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return 1 + 2"[..])?;
+
+        let thread = ctx.new_thread();
+        thread.start(ctx, closure.into(), ()).unwrap();
+        let executor = Executor::run(&ctx, thread);
+        let mut fuel = piccolo::Fuel::with(i32::MAX);
+        assert!(executor.step(ctx, &mut fuel));
+        assert_eq!(executor.take_result::<i64>(ctx)??, 3);
+
+        ctx.recycle_thread(thread)?;
+        assert_eq!(ctx.thread_pool().len(), 1);
+
+        let reused = ctx.new_thread();
+        assert_eq!(reused, thread);
+        assert!(ctx.thread_pool().is_empty());
+        assert_eq!(reused.mode(), ThreadMode::Stopped);
+
+        reused.restart_suspended(&ctx, closure.into())?;
+        assert_eq!(reused.mode(), ThreadMode::Suspended);
+
+        ctx.thread_pool().set_max_threads(&ctx, 0);
+        ctx.recycle_thread(reused)?;
+        assert!(ctx.thread_pool().is_empty());
+        assert_eq!(reused.mode(), ThreadMode::Stopped);
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, Fuel, Lua, StaticError, TracebackFrame,
+};
+
+#[test]
+fn traceback_across_resumes() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+            let traceback = exec.traceback();
+            assert_eq!(traceback.entries[0].thread, exec.current_thread().thread);
+            assert_eq!(traceback.entries.last().unwrap().thread, exec.threads()[0]);
+            stack.replace(ctx, (exec.threads().len() as i64, traceback.to_string()));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("capture", callback)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("traceback"),
+            &br#"
+                local function inner()
+                    local threads, traceback = capture()
+                    return threads, traceback
+                end
+                local co = coroutine.create(function()
+                    local nested = coroutine.create(function()
+                        threads, traceback = inner()
+                    end)
+                    coroutine.resume(nested)
+                end)
+                coroutine.resume(co)
+                return threads, traceback
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (threads, traceback) = lua.execute::<(i64, String)>(&executor)?;
+    assert_eq!(threads, 3);
+    assert!(traceback.starts_with("stack traceback:\n\t[native]: in ?"));
+    assert!(traceback.contains("\n\ttraceback:3: in function 'inner'"));
+    assert!(traceback.contains("\n\ttraceback:8: in function <traceback:7>"));
+    assert!(traceback.contains("\n\ttraceback:10: in function <traceback:6>"));
+    assert!(traceback.ends_with("\n\ttraceback:12: in main chunk"));
+    assert_eq!(traceback.matches("\n\t[resume]").count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn traceback_of_paused_executor() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("paused"),
+            &br#"
+                coroutine.resume(coroutine.create(function()
+                    while true do end
+                end))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert!(!executor.step(ctx, &mut Fuel::with(100)));
+
+        let threads = executor.threads();
+        assert_eq!(threads.len(), 2);
+
+        let traceback = executor.traceback();
+        assert_eq!(traceback.entries[0].thread, threads[1]);
+        assert!(matches!(
+            traceback.entries[0].frame,
+            TracebackFrame::Lua { current_line, .. } if current_line.to_string() == "3"
+        ));
+        assert_eq!(traceback.entries.last().unwrap().thread, threads[0]);
+        assert!(traceback.to_string().ends_with(": in main chunk"));
+    });
+
+    Ok(())
+}
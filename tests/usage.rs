@@ -0,0 +1,153 @@
+use piccolo::{
+    compiler::FunctionRef, Closure, Executor, Fuel, Function, FunctionId, Lua, Profile, StaticError,
+};
+
+fn run(lua: &mut Lua, name: &str, source: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some(name), source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn usage_by_function() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    // Nothing is tracked until tracking is enabled.
+    run(
+        &mut lua,
+        "ignored",
+        "local i = 0 while i < 10 do i = i + 1 end",
+    )?;
+    assert!(lua.usage_report().functions.is_empty());
+
+    lua.enter(|ctx| ctx.set_usage_tracking(true));
+
+    run(
+        &mut lua,
+        "alpha",
+        r#"
+            local function busy()
+                local t = {}
+                for i = 1, 1000 do
+                    t[i] = i
+                end
+            end
+            busy()
+        "#,
+    )?;
+    run(&mut lua, "beta", "local x = 1 + 1")?;
+
+    let report = lua.usage_report();
+    assert_eq!(report.functions.len(), 3);
+
+    let busy = &report.functions[0];
+    assert_eq!(busy.id.chunk_name, "alpha");
+    assert!(matches!(&busy.id.function, FunctionRef::Named(name, _) if name == "busy"));
+    assert!(busy.fuel >= 1000);
+
+    let beta = FunctionId {
+        chunk_name: "beta".to_owned(),
+        function: FunctionRef::Chunk,
+    };
+    assert!(report.get(&beta).unwrap() > 0);
+
+    let chunks = report.by_chunk();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].0, "alpha");
+    assert_eq!(chunks[1].0, "beta");
+    assert_eq!(chunks[0].1 + chunks[1].1, report.total());
+
+    lua.reset_usage();
+    assert!(lua.usage_report().functions.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn callbacks_charged_to_caller() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| ctx.set_usage_tracking(true));
+
+    run(
+        &mut lua,
+        "main",
+        r#"
+            local function caller()
+                for i = 1, 100 do
+                    tostring(i)
+                end
+            end
+            caller()
+        "#,
+    )?;
+
+    let report = lua.usage_report();
+    assert!(report.functions.iter().all(|f| f.id.chunk_name == "main"));
+    assert!(matches!(
+        report.functions[0].id.function,
+        FunctionRef::Named(ref name, _) if name == "caller"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn profile_warm_up() -> Result<(), StaticError> {
+    const SOURCE: &str = r#"
+        local function hot(n)
+            local total = 0
+            for i = 1, n do
+                total = total + i
+            end
+            return total
+        end
+        local function cold()
+            return 1
+        end
+        return hot, cold
+    "#;
+
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("warm"), SOURCE.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    let profile = lua.try_enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        executor.step(ctx, &mut Fuel::with(i32::MAX));
+        let (hot, _cold) = executor.take_result::<(Closure, Closure)>(ctx)??;
+        Function::Closure(hot)
+            .call_with_fuel(ctx, 2000, &mut Fuel::with(i32::MAX))
+            .unwrap();
+
+        let mut profile = Profile::default();
+        profile.record(&hot.prototype());
+        Ok(profile)
+    })?;
+    assert_eq!(profile.functions.len(), 1);
+    assert_eq!(profile.functions[0].invocations, 1);
+    assert!(profile.functions[0].back_edges >= 1999);
+    let heat = profile.functions[0].invocations + profile.functions[0].back_edges;
+
+    // The same chunk loaded into a new instance starts out as hot as it was when recorded.
+    let mut lua = Lua::core();
+    lua.try_enter(|ctx| {
+        ctx.set_profile(Some(profile.clone()));
+        let closure = Closure::load(ctx, Some("warm"), SOURCE.as_bytes())?;
+        let hot = closure.prototype().prototypes[0];
+        let cold = closure.prototype().prototypes[1];
+        assert_eq!(hot.counters.heat(), heat);
+        assert_eq!(cold.counters.heat(), 0);
+        #[cfg(feature = "tier-up")]
+        assert!(hot.is_trusted() && !cold.is_trusted());
+
+        // A chunk with a different name is a different set of functions.
+        let closure = Closure::load(ctx, Some("other"), SOURCE.as_bytes())?;
+        assert_eq!(closure.prototype().prototypes[0].counters.heat(), 0);
+        Ok(())
+    })?;
+
+    Ok(())
+}
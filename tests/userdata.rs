@@ -1,5 +1,9 @@
+use std::string::String as StdString;
+
 use gc_arena::{lock::Lock, Collect, Gc, Rootable};
-use piccolo::{Callback, CallbackReturn, Closure, Executor, Lua, StaticError, UserData, Value};
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, Lua, StaticError, Table, UserData, Value,
+};
 
 #[derive(Collect)]
 #[collect(no_drop)]
@@ -60,3 +64,115 @@ fn userdata() -> Result<(), StaticError> {
         Ok(())
     })
 }
+
+#[test]
+fn protected_metatable() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let mt = Table::new(&ctx);
+        mt.set(ctx, "__metatable", "protected")?;
+        let userdata = UserData::new_static(&ctx, 3i32);
+        userdata.set_metatable(&ctx, Some(mt));
+        ctx.set_global("userdata", userdata)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return getmetatable(userdata) == "protected"
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert!(lua.execute::<bool>(&executor)?);
+    Ok(())
+}
+
+#[test]
+fn userdata_name() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let mt = Table::new(&ctx);
+        mt.set(ctx, "__name", "Foo")?;
+        let userdata = UserData::new_static(&ctx, 3i32);
+        userdata.set_metatable(&ctx, Some(mt));
+        ctx.set_global("userdata", userdata)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local ok, err = pcall(function() return userdata.field end)
+                assert(not ok)
+                return tostring(userdata), tostring(err)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (display, err) = lua.execute::<(StdString, StdString)>(&executor)?;
+    assert!(display.starts_with("Foo: "));
+    assert_eq!(err, "<anonymous>:2: could not index into a Foo value");
+    Ok(())
+}
+
+#[test]
+fn userdata_pairs() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let iter = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (ud, index): (UserData, Option<i64>) = stack.consume(ctx)?;
+            let items = ud.downcast_static::<Vec<&'static str>>()?;
+            let index = index.unwrap_or(0);
+            if let Some(&item) = items.get(index as usize) {
+                stack.replace(ctx, (index + 1, item));
+            }
+            Ok(CallbackReturn::Return)
+        });
+        let mt = Table::new(&ctx);
+        mt.set(
+            ctx,
+            "__pairs",
+            Callback::from_fn_with(&ctx, iter, |&iter, ctx, _, mut stack| {
+                let ud: UserData = stack.consume(ctx)?;
+                stack.replace(ctx, (iter, ud, Value::Nil));
+                Ok(CallbackReturn::Return)
+            }),
+        )?;
+        let userdata = UserData::new_static(&ctx, vec!["a", "b", "c"]);
+        userdata.set_metatable(&ctx, Some(mt));
+        ctx.set_global("collection", userdata)?;
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local keys, values = 0, ""
+                for k, v in pairs(collection) do
+                    keys = keys + k
+                    values = values .. v
+                end
+                return keys, values
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (keys, values) = lua.execute::<(i64, StdString)>(&executor)?;
+    assert_eq!(keys, 6);
+    assert_eq!(values, "abc");
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use gc_arena::Gc;
+use piccolo::{
+    opcode::{OpCode, Operation},
+    types::{Opt254, RegisterIndex},
+    Closure, Executor, FunctionPrototype, Lua, OpCodeChecks, StaticError, VerifyError,
+};
+
+#[test]
+fn trusted_chunks() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (closure, executor) = lua.try_enter(|ctx| {
+        ctx.set_opcode_checks(OpCodeChecks::Trusted);
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function sum(...)
+                    local total = 0
+                    for _, v in ipairs({...}) do
+                        total = total + v
+                    end
+                    return total, select('#', ...)
+                end
+
+                local t = {}
+                for i = 1, 10 do
+                    t[i] = i * 2
+                end
+                local total, count = sum(table.unpack(t))
+                assert(total == 110 and count == 10)
+                assert(sum() == 0)
+            "#[..],
+        )?;
+
+        Ok((
+            ctx.stash(closure),
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+        ))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        let proto = ctx.fetch(&closure).prototype();
+        assert!(proto.is_trusted());
+        assert!(proto.prototypes.iter().all(|p| p.is_trusted()));
+    });
+
+    Ok(())
+}
+
+#[test]
+fn checked_by_default() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        assert_eq!(ctx.opcode_checks(), OpCodeChecks::Checked);
+        let closure = Closure::load(ctx, None, &b"local a, b = 1, 2"[..])?;
+        assert!(!closure.prototype().is_trusted());
+        Ok(())
+    })
+}
+
+#[test]
+fn reject_out_of_range_registers() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut proto =
+            FunctionPrototype::compile(ctx, "test", &b"local a, b = 1, 2; return b"[..]).unwrap();
+        proto.stack_size = 1;
+        let proto = Gc::new(&ctx, proto);
+
+        assert!(matches!(
+            FunctionPrototype::trust(proto),
+            Err(VerifyError::Register { stack_size: 1, .. })
+        ));
+        assert!(!proto.is_trusted());
+    });
+}
+
+#[test]
+fn reject_falling_through() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut proto = FunctionPrototype::compile(ctx, "test", &b"local a = 1"[..]).unwrap();
+        let last = proto.opcodes.len() - 1;
+        proto.opcodes[last] = OpCode::encode(Operation::LoadNil {
+            dest: RegisterIndex(0),
+            count: 1,
+        });
+        let proto = Gc::new(&ctx, proto);
+
+        assert!(matches!(
+            FunctionPrototype::trust(proto),
+            Err(VerifyError::FallsThrough { pc }) if pc == last
+        ));
+    });
+}
+
+#[test]
+fn reject_jump_to_end() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut proto = FunctionPrototype::compile(ctx, "test", &b"local a = 1"[..]).unwrap();
+        // A jump from the final opcode with no offset lands just past the end of the function.
+        let last = proto.opcodes.len() - 1;
+        proto.opcodes[last] = OpCode::encode(Operation::Jump {
+            offset: 0,
+            close_upvalues: Opt254::none(),
+        });
+        let proto = Gc::new(&ctx, proto);
+
+        assert!(matches!(
+            FunctionPrototype::trust(proto),
+            Err(VerifyError::Jump { pc }) if pc == last
+        ));
+    });
+}
+
+#[cfg(feature = "tier-up")]
+#[test]
+fn hot_prototypes_tier_up() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let (closure, executor) = lua.try_enter(|ctx| {
+        ctx.set_tier_up_threshold(100);
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function hot(n)
+                    local total = 0
+                    for i = 1, n do
+                        total = total + i
+                    end
+                    return total
+                end
+                local function cold()
+                    return 1
+                end
+
+                -- The loop makes `hot` hot, and it tiers up the next time it is entered.
+                assert(hot(200) == 20100)
+                assert(hot(200) == 20100)
+                assert(cold() == 1)
+            "#[..],
+        )?;
+
+        Ok((
+            ctx.stash(closure),
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+        ))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        let proto = ctx.fetch(&closure).prototype();
+        assert!(!proto.is_trusted());
+        assert!(proto.prototypes[0].is_trusted());
+        assert!(!proto.prototypes[1].is_trusted());
+    });
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{ApiVersions, Callback, CallbackReturn, Closure, Executor, Lua, StaticError, Table};
+
+#[test]
+fn api_versions() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+
+    let executor = lua.try_enter(|ctx| {
+        let warnings = warnings.clone();
+        ctx.set_warning_handler(move |message| warnings.borrow_mut().push(message.to_owned()));
+
+        let spawn = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            stack.replace(ctx, "spawned");
+            Ok(CallbackReturn::Return)
+        });
+
+        let v1 = Table::new(&ctx);
+        v1.set(
+            ctx,
+            "create",
+            ApiVersions::deprecated_function(ctx, "game.create", "use game.spawn", spawn.into()),
+        )?;
+        let v2 = Table::new(&ctx);
+        v2.set(ctx, "spawn", spawn)?;
+
+        let versions = ApiVersions::new(ctx, "game");
+        versions.add_version(ctx, 1, v1);
+        versions.add_version(ctx, 2, v2);
+        versions.deprecate_version(ctx, 1, "upgrade to version 2")?;
+        assert!(versions.deprecate_version(ctx, 3, "missing").is_err());
+        assert_eq!(versions.latest(), Some(2));
+
+        let old_env = ctx.fork();
+        versions.install(ctx, old_env, "game", 1)?;
+        let new_env = ctx.fork();
+        versions.install(ctx, new_env, "game", 2)?;
+        assert!(versions.install(ctx, new_env, "game", 3).is_err());
+
+        ctx.set_global("old_env", old_env)?;
+        ctx.set_global("new_env", new_env)?;
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(old_env.game.create() == "spawned")
+                assert(old_env.game.create() == "spawned")
+                assert(old_env.game.spawn == nil)
+                assert(new_env.game.spawn() == "spawned")
+                assert(new_env.game.create == nil)
+                warn("from ", "script")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+
+    assert_eq!(
+        *warnings.borrow(),
+        vec![
+            "game API version 1 is deprecated: upgrade to version 2".to_owned(),
+            "game.create is deprecated: use game.spawn".to_owned(),
+            "from script".to_owned(),
+        ]
+    );
+
+    Ok(())
+}
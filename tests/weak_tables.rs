@@ -0,0 +1,163 @@
+use piccolo::{Closure, Executor, Lua, StaticError};
+
+fn run(lua: &mut Lua, code: &str) -> Result<(), StaticError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, code.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn weak_values() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            live = {}
+            weak = setmetatable({}, { __mode = "v" })
+            weak[1] = {}
+            weak[2] = live
+            weak[3] = "string"
+            weak.dead = function() end
+            weak.live = live
+            weak.number = 4
+        "#,
+    )?;
+
+    lua.gc_collect();
+
+    run(
+        &mut lua,
+        r#"
+            assert(weak[1] == nil)
+            assert(weak[2] == live)
+            assert(weak[3] == "string")
+            assert(weak.dead == nil)
+            assert(weak.live == live)
+            assert(weak.number == 4)
+
+            local count = 0
+            for _ in pairs(weak) do
+                count = count + 1
+            end
+            assert(count == 4)
+        "#,
+    )
+}
+
+#[test]
+fn weak_keys() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            live = {}
+            weak = setmetatable({}, { __mode = "k" })
+            weak[{}] = 1
+            weak[live] = 2
+            weak["string"] = 3
+            weak[4] = {}
+        "#,
+    )?;
+
+    lua.gc_collect();
+
+    run(
+        &mut lua,
+        r#"
+            local count = 0
+            for k, v in pairs(weak) do
+                count = count + 1
+            end
+            assert(count == 3)
+            assert(weak[live] == 2)
+            assert(weak["string"] == 3)
+            assert(type(weak[4]) == "table")
+        "#,
+    )
+}
+
+#[test]
+fn weak_keys_and_values() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            live = {}
+            weak = setmetatable({}, { __mode = "kv" })
+            weak[live] = {}
+            weak[{}] = live
+            weak[1] = live
+        "#,
+    )?;
+
+    lua.gc_collect();
+
+    run(
+        &mut lua,
+        r#"
+            local count = 0
+            for k, v in pairs(weak) do
+                count = count + 1
+            end
+            assert(count == 1)
+            assert(weak[1] == live)
+        "#,
+    )
+}
+
+#[test]
+fn strong_after_mode_removed() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            weak = setmetatable({}, { __mode = "v" })
+            setmetatable(weak, nil)
+            weak[1] = {}
+        "#,
+    )?;
+
+    lua.gc_collect();
+
+    run(
+        &mut lua,
+        r#"
+            assert(type(weak[1]) == "table")
+        "#,
+    )
+}
+
+#[test]
+fn weak_keys_are_not_ephemerons() -> Result<(), StaticError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            weak = setmetatable({}, { __mode = "k" })
+            local key = {}
+            weak[key] = { key }
+        "#,
+    )?;
+
+    lua.gc_collect();
+
+    // The value refers to its own key, which keeps the entry alive even though nothing else does.
+    run(
+        &mut lua,
+        r#"
+            local count = 0
+            for k, v in pairs(weak) do
+                assert(v[1] == k)
+                count = count + 1
+            end
+            assert(count == 1)
+        "#,
+    )
+}